@@ -0,0 +1,124 @@
+//! Derives the audience of a CBS token from the address of a link
+//!
+//! A CBS token is put for an audience, which is the URI of the entity that the token grants
+//! access to. An [`AudienceResolver`] derives the audience from the address of a link the same way
+//! the Azure SDKs do: a relative address such as `"my-queue"` is appended to the endpoint of the
+//! namespace (eg. `"amqps://my-namespace.servicebus.windows.net/my-queue"`), and an absolute
+//! address is used as it is. An override hook can replace the derived audience for some or all
+//! addresses.
+//!
+//! [`CbsClient::attach_sender()`](crate::client::CbsClient::attach_sender) and
+//! [`CbsClient::attach_receiver()`](crate::client::CbsClient::attach_receiver) resolve the
+//! audience of the link and only attach the link once a valid token for that audience has been
+//! put.
+
+use std::{fmt, sync::Arc};
+
+type OverrideHook = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// Derives the audience of a CBS token from the address of a link
+#[derive(Clone)]
+pub struct AudienceResolver {
+    endpoint: String,
+    override_hook: Option<OverrideHook>,
+}
+
+impl fmt::Debug for AudienceResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AudienceResolver")
+            .field("endpoint", &self.endpoint)
+            .field("override_hook", &self.override_hook.is_some())
+            .finish()
+    }
+}
+
+impl AudienceResolver {
+    /// Creates a resolver that appends relative addresses to the endpoint, eg.
+    /// `"amqps://my-namespace.servicebus.windows.net"`
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            override_hook: None,
+        }
+    }
+
+    /// Sets a hook that is called with the address of every link. The audience it returns is
+    /// used instead of the derived one, and the audience is derived if it returns `None`
+    pub fn with_override<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.override_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// The endpoint that relative addresses are appended to
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Resolves the audience of a link address
+    pub fn resolve(&self, address: &str) -> String {
+        self.override_hook
+            .as_ref()
+            .and_then(|hook| hook(address))
+            .unwrap_or_else(|| derive_audience(&self.endpoint, address))
+    }
+}
+
+/// Derives the audience of a link address. An absolute address is returned without its query,
+/// and a relative address is appended to the endpoint
+pub fn derive_audience(endpoint: &str, address: &str) -> String {
+    let address = match address.find('?') {
+        Some(index) => &address[..index],
+        None => address,
+    };
+    if address.contains("://") {
+        return address.to_string();
+    }
+    format!(
+        "{}/{}",
+        endpoint.trim_end_matches('/'),
+        address.trim_start_matches('/')
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{derive_audience, AudienceResolver};
+
+    #[test]
+    fn relative_addresses_are_appended_to_the_endpoint() {
+        let endpoint = "amqps://ns.servicebus.windows.net/";
+        assert_eq!(
+            derive_audience(endpoint, "/queue"),
+            "amqps://ns.servicebus.windows.net/queue"
+        );
+        assert_eq!(
+            derive_audience(endpoint, "topic/Subscriptions/sub?timeout=1"),
+            "amqps://ns.servicebus.windows.net/topic/Subscriptions/sub"
+        );
+        assert_eq!(
+            derive_audience(endpoint, "amqps://other.servicebus.windows.net/queue"),
+            "amqps://other.servicebus.windows.net/queue"
+        );
+    }
+
+    #[test]
+    fn override_hook_takes_precedence() {
+        let resolver =
+            AudienceResolver::new("amqps://ns.servicebus.windows.net").with_override(|address| {
+                address
+                    .starts_with("$management")
+                    .then(|| "amqps://ns.servicebus.windows.net".to_string())
+            });
+        assert_eq!(
+            resolver.resolve("$management"),
+            "amqps://ns.servicebus.windows.net"
+        );
+        assert_eq!(
+            resolver.resolve("queue"),
+            "amqps://ns.servicebus.windows.net/queue"
+        );
+    }
+}
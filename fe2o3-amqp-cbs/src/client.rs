@@ -1,17 +1,29 @@
 //! Implements the CBS client
 
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use fe2o3_amqp::{link::DetachError, session::SessionHandle, types::definitions::Fields};
+use fe2o3_amqp::{
+    link::{DetachError, ReceiverAttachError, SenderAttachError},
+    session::SessionHandle,
+    types::{definitions::Fields, primitives::Timestamp},
+    Receiver, Sender,
+};
 use fe2o3_amqp_management::{
     client::{MgmtClient, MgmtClientBuilder},
     error::{AttachError, Error as MgmtError},
 };
 
 use crate::{
+    audience::AudienceResolver,
     constants::{CBS_NODE_ADDR, DEFAULT_CBS_CLIENT_NODE},
+    error::{AttachLinkError, RefreshTokenError},
     put_token::{PutTokenRequest, PutTokenResponse},
     token::CbsToken,
+    AsyncCbsTokenProvider, CbsTokenProvider,
 };
 
 /// CBS client
@@ -20,6 +32,10 @@ use crate::{
 #[derive(Debug)]
 pub struct CbsClient {
     mgmt_client: MgmtClient,
+    audience_resolver: Option<AudienceResolver>,
+
+    /// Expiration times of the tokens that have been put, keyed by their name
+    tokens: HashMap<String, Option<Timestamp>>,
 }
 
 impl CbsClient {
@@ -44,23 +60,180 @@ impl CbsClient {
         name: impl Into<Cow<'a, str>>,
         token: CbsToken<'a>,
     ) -> Result<(), MgmtError> {
+        let name = name.into();
         let entity_type = token.token_type;
+        let expires_at_utc = token.expires_at_utc;
         let req = PutTokenRequest::new(
-            name,
+            name.clone(),
             token.token_value,
-            token.expires_at_utc,
+            expires_at_utc.clone(),
             entity_type,
             None,
         );
         let _res: PutTokenResponse = self.mgmt_client.call(req).await?;
+        self.tokens.insert(name.into_owned(), expires_at_utc);
         Ok(())
     }
+
+    /// Get the audience of a link address
+    ///
+    /// The address is used as the audience if the client is built without an
+    /// [`AudienceResolver`].
+    pub fn resolve_audience(&self, address: &str) -> String {
+        match &self.audience_resolver {
+            Some(resolver) => resolver.resolve(address),
+            None => address.to_string(),
+        }
+    }
+
+    /// Whether a token that has not expired has been put for the audience
+    pub fn has_valid_token(&self, audience: &str) -> bool {
+        match self.tokens.get(audience) {
+            Some(Some(expires_at_utc)) => expires_at_utc.milliseconds() > now_milliseconds(),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Put a token for the audience of the link address unless a valid one has already been put,
+    /// and return the audience
+    pub async fn ensure_token<P>(
+        &mut self,
+        address: &str,
+        provider: &mut P,
+        container_id: impl AsRef<str>,
+        claims: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<String, RefreshTokenError<P::Error>>
+    where
+        P: CbsTokenProvider,
+    {
+        let audience = self.resolve_audience(address);
+        if !self.has_valid_token(&audience) {
+            self.refresh_token(audience.clone(), provider, container_id, &audience, claims)
+                .await?;
+        }
+        Ok(audience)
+    }
+
+    /// Put a token from the async provider for the audience of the link address unless a valid
+    /// one has already been put, and return the audience
+    pub async fn ensure_token_async<P>(
+        &mut self,
+        address: &str,
+        provider: &mut P,
+        container_id: impl AsRef<str>,
+        claims: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<String, RefreshTokenError<P::Error>>
+    where
+        P: AsyncCbsTokenProvider,
+    {
+        let audience = self.resolve_audience(address);
+        if !self.has_valid_token(&audience) {
+            self.refresh_token_async(audience.clone(), provider, container_id, &audience, claims)
+                .await?;
+        }
+        Ok(audience)
+    }
+
+    /// Attach a sender once a valid token for the audience of its address has been put
+    ///
+    /// See [`ensure_token_async`](#method.ensure_token_async)
+    pub async fn attach_sender<P, R>(
+        &mut self,
+        session: &mut SessionHandle<R>,
+        name: impl Into<String>,
+        address: impl Into<String>,
+        provider: &mut P,
+        container_id: impl AsRef<str>,
+        claims: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Sender, AttachLinkError<P::Error, SenderAttachError>>
+    where
+        P: AsyncCbsTokenProvider,
+    {
+        let address = address.into();
+        self.ensure_token_async(&address, provider, container_id, claims)
+            .await?;
+        Sender::attach(session, name, address)
+            .await
+            .map_err(AttachLinkError::Attach)
+    }
+
+    /// Attach a receiver once a valid token for the audience of its address has been put
+    ///
+    /// See [`ensure_token_async`](#method.ensure_token_async)
+    pub async fn attach_receiver<P, R>(
+        &mut self,
+        session: &mut SessionHandle<R>,
+        name: impl Into<String>,
+        address: impl Into<String>,
+        provider: &mut P,
+        container_id: impl AsRef<str>,
+        claims: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Receiver, AttachLinkError<P::Error, ReceiverAttachError>>
+    where
+        P: AsyncCbsTokenProvider,
+    {
+        let address = address.into();
+        self.ensure_token_async(&address, provider, container_id, claims)
+            .await?;
+        Receiver::attach(session, name, address)
+            .await
+            .map_err(AttachLinkError::Attach)
+    }
+
+    /// Get a fresh token from the provider and put it
+    ///
+    /// This is meant to be called by a renewal task before the previous token expires, so that a
+    /// rotated token is picked up without re-opening the connection. The expiration time of the
+    /// new token is returned so that the next renewal can be scheduled.
+    pub async fn refresh_token<'a, P>(
+        &mut self,
+        name: impl Into<Cow<'a, str>>,
+        provider: &'a mut P,
+        container_id: impl AsRef<str>,
+        resource_id: impl AsRef<str>,
+        claims: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Option<Timestamp>, RefreshTokenError<P::Error>>
+    where
+        P: CbsTokenProvider,
+    {
+        let token = provider
+            .get_token(container_id, resource_id, claims)
+            .map_err(RefreshTokenError::Provider)?;
+        let expires_at_utc = token.expires_at_utc.clone();
+        self.put_token(name, token).await?;
+        Ok(expires_at_utc)
+    }
+
+    /// Get a fresh token from the async provider and put it
+    ///
+    /// See [`refresh_token`](#method.refresh_token)
+    pub async fn refresh_token_async<'a, P>(
+        &mut self,
+        name: impl Into<Cow<'a, str>>,
+        provider: &'a mut P,
+        container_id: impl AsRef<str>,
+        resource_id: impl AsRef<str>,
+        claims: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Option<Timestamp>, RefreshTokenError<P::Error>>
+    where
+        P: AsyncCbsTokenProvider,
+    {
+        let token = provider
+            .get_token_async(container_id, resource_id, claims)
+            .await
+            .map_err(RefreshTokenError::Provider)?;
+        let expires_at_utc = token.expires_at_utc.clone();
+        self.put_token(name, token).await?;
+        Ok(expires_at_utc)
+    }
 }
 
 /// Builder for a CBS client
 #[derive(Debug)]
 pub struct CbsClientBuilder {
     inner: MgmtClientBuilder,
+    audience_resolver: Option<AudienceResolver>,
 }
 
 impl Default for CbsClientBuilder {
@@ -68,7 +241,10 @@ impl Default for CbsClientBuilder {
         let inner = MgmtClient::builder()
             .management_node_address(CBS_NODE_ADDR)
             .client_node_addr(DEFAULT_CBS_CLIENT_NODE);
-        Self { inner }
+        Self {
+            inner,
+            audience_resolver: None,
+        }
     }
 }
 
@@ -97,9 +273,26 @@ impl CbsClientBuilder {
         self
     }
 
+    /// Set the resolver that derives the audience of a token from the address of a link.
+    pub fn audience_resolver(mut self, audience_resolver: AudienceResolver) -> Self {
+        self.audience_resolver = Some(audience_resolver);
+        self
+    }
+
     /// Attach a management client to a session.
     pub async fn attach<R>(self, session: &mut SessionHandle<R>) -> Result<CbsClient, AttachError> {
         let mgmt_client = self.inner.attach(session).await?;
-        Ok(CbsClient { mgmt_client })
+        Ok(CbsClient {
+            mgmt_client,
+            audience_resolver: self.audience_resolver,
+            tokens: HashMap::new(),
+        })
     }
 }
+
+fn now_milliseconds() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or_default()
+}
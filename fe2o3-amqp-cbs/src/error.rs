@@ -0,0 +1,75 @@
+//! Error types of the CBS client
+
+use fe2o3_amqp_management::error::Error as MgmtError;
+
+/// Error refreshing a CBS token with a token provider
+#[derive(Debug)]
+pub enum RefreshTokenError<E> {
+    /// The token provider failed to provide a token
+    Provider(E),
+
+    /// The token was rejected or could not be put
+    PutToken(MgmtError),
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for RefreshTokenError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefreshTokenError::Provider(error) => write!(f, "Token provider error: {}", error),
+            RefreshTokenError::PutToken(error) => write!(f, "Put token error: {}", error),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for RefreshTokenError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RefreshTokenError::Provider(error) => Some(error),
+            RefreshTokenError::PutToken(error) => Some(error),
+        }
+    }
+}
+
+impl<E> From<MgmtError> for RefreshTokenError<E> {
+    fn from(error: MgmtError) -> Self {
+        RefreshTokenError::PutToken(error)
+    }
+}
+
+/// Error attaching a link with the token for the audience of its address
+#[derive(Debug)]
+pub enum AttachLinkError<E, A> {
+    /// The token for the audience could not be obtained or put
+    Token(RefreshTokenError<E>),
+
+    /// The link failed to attach
+    Attach(A),
+}
+
+impl<E: std::fmt::Display, A: std::fmt::Display> std::fmt::Display for AttachLinkError<E, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttachLinkError::Token(error) => write!(f, "{}", error),
+            AttachLinkError::Attach(error) => write!(f, "Attach error: {}", error),
+        }
+    }
+}
+
+impl<E, A> std::error::Error for AttachLinkError<E, A>
+where
+    E: std::error::Error + 'static,
+    A: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AttachLinkError::Token(error) => Some(error),
+            AttachLinkError::Attach(error) => Some(error),
+        }
+    }
+}
+
+impl<E, A> From<RefreshTokenError<E>> for AttachLinkError<E, A> {
+    fn from(error: RefreshTokenError<E>) -> Self {
+        AttachLinkError::Token(error)
+    }
+}
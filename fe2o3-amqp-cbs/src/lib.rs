@@ -9,8 +9,10 @@ use std::future::Future;
 
 use token::CbsToken;
 
+pub mod audience;
 pub mod client;
 pub mod constants;
+pub mod error;
 pub mod put_token;
 pub mod token;
 
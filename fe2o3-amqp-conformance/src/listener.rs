@@ -0,0 +1,185 @@
+//! A minimal in-memory broker that passes all the scenarios
+//!
+//! Every node address is a queue that is created on first use. The messages received on a node
+//! are accepted and queued, and are sent to the receivers attached to the same node in the order
+//! they arrived. A message whose send fails, eg. because the receiver detaches in the meantime,
+//! is put back at the front of the queue.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use fe2o3_amqp::{
+    acceptor::{
+        ConnectionAcceptor, LinkAcceptor, LinkEndpoint, ListenerConnectionHandle,
+        ListenerSessionHandle, SessionAcceptor,
+    },
+    types::{
+        messaging::{Body, Message},
+        primitives::Value,
+    },
+    Receiver, Sender,
+};
+use tokio::{net::TcpListener, sync::Notify};
+
+/// Container id of the reference listener
+pub const LISTENER_CONTAINER_ID: &str = "fe2o3-amqp-conformance-listener";
+
+type QueuedMessage = Message<Body<Value>>;
+
+#[derive(Debug, Default)]
+struct Queue {
+    messages: Mutex<VecDeque<QueuedMessage>>,
+    notify: Notify,
+}
+
+impl Queue {
+    fn push_back(&self, message: QueuedMessage) {
+        self.messages.lock().expect("Poisoned").push_back(message);
+        self.notify.notify_one();
+    }
+
+    fn push_front(&self, message: QueuedMessage) {
+        self.messages.lock().expect("Poisoned").push_front(message);
+        self.notify.notify_one();
+    }
+
+    async fn pop_front(&self) -> QueuedMessage {
+        loop {
+            // Registered before the queue is checked so that a push cannot be missed
+            let notified = self.notify.notified();
+            if let Some(message) = self.messages.lock().expect("Poisoned").pop_front() {
+                return message;
+            }
+            notified.await;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queues {
+    queues: Mutex<HashMap<String, Arc<Queue>>>,
+    next_dynamic: AtomicU64,
+}
+
+impl Queues {
+    fn get(&self, address: &str) -> Arc<Queue> {
+        self.queues
+            .lock()
+            .expect("Poisoned")
+            .entry(address.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+/// A minimal in-memory broker that passes all the scenarios. See the
+/// [module level documentation](self) for details
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use fe2o3_amqp_conformance::ReferenceListener;
+/// use tokio::net::TcpListener;
+///
+/// # async fn example() -> std::io::Result<()> {
+/// let tcp_listener = TcpListener::bind("127.0.0.1:5672").await?;
+/// ReferenceListener::new().serve(tcp_listener).await
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ReferenceListener {
+    queues: Arc<Queues>,
+}
+
+impl ReferenceListener {
+    /// Creates a listener without any queue
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accepts connections until accepting on the TCP listener fails. Each connection is served
+    /// on its own task, and a connection that fails to open is dropped
+    pub async fn serve(self, tcp_listener: TcpListener) -> io::Result<()> {
+        let connection_acceptor = ConnectionAcceptor::new(LISTENER_CONTAINER_ID);
+        loop {
+            let (stream, _addr) = tcp_listener.accept().await?;
+            if let Ok(connection) = connection_acceptor.accept(stream).await {
+                tokio::spawn(self.clone().serve_connection(connection));
+            }
+        }
+    }
+
+    async fn serve_connection(self, mut connection: ListenerConnectionHandle) {
+        let session_acceptor = SessionAcceptor::new();
+        while let Ok(session) = session_acceptor.accept(&mut connection).await {
+            tokio::spawn(self.clone().serve_session(session));
+        }
+        let _ = connection.on_close().await;
+    }
+
+    async fn serve_session(self, mut session: ListenerSessionHandle) {
+        let queues = self.queues.clone();
+        let link_acceptor = LinkAcceptor::builder()
+            .on_dynamic_source(move |mut source| {
+                let id = queues.next_dynamic.fetch_add(1, Ordering::Relaxed);
+                source.address = Some(format!("dynamic-{}", id));
+                Some(source)
+            })
+            .build();
+        while let Ok(link) = link_acceptor.accept(&mut session).await {
+            match link {
+                LinkEndpoint::Sender(sender) => {
+                    tokio::spawn(self.clone().serve_sender(sender));
+                }
+                LinkEndpoint::Receiver(receiver) => {
+                    tokio::spawn(self.clone().serve_receiver(receiver));
+                }
+            }
+        }
+        let _ = session.on_end().await;
+    }
+
+    /// Sends the queued messages to the remote receiver until the link is detached
+    async fn serve_sender(self, mut sender: Sender) {
+        let address = sender
+            .source()
+            .as_ref()
+            .and_then(|source| source.address.clone())
+            .unwrap_or_default();
+        let queue = self.queues.get(&address);
+        loop {
+            let message = tokio::select! {
+                message = queue.pop_front() => message,
+                _ = sender.on_detach() => break,
+            };
+            if let Err(_error) = sender.send(message.clone()).await {
+                queue.push_front(message);
+                break;
+            }
+        }
+        let _ = sender.close().await;
+    }
+
+    /// Queues the messages from the remote sender until the link is detached
+    async fn serve_receiver(self, mut receiver: Receiver) {
+        let address = receiver
+            .target()
+            .as_ref()
+            .and_then(|target| target.address.clone())
+            .unwrap_or_default();
+        let queue = self.queues.get(&address);
+        while let Ok(delivery) = receiver.recv::<Body<Value>>().await {
+            if receiver.accept(&delivery).await.is_err() {
+                break;
+            }
+            queue.push_back(delivery.into_message());
+        }
+        let _ = receiver.close().await;
+    }
+}
@@ -0,0 +1,147 @@
+//! Command line of the conformance scenarios. See the Readme for the usage
+
+use std::{process::ExitCode, time::Duration};
+
+use fe2o3_amqp_conformance::{Config, ReferenceListener, Scenario, Summary};
+use tokio::net::TcpListener;
+
+const USAGE: &str = "\
+Usage:
+    fe2o3-amqp-conformance run <url> [--address <address>] [--scenario <name>]...
+                                     [--timeout-secs <secs>] [--format <text|json>]
+    fe2o3-amqp-conformance listen [<addr>]
+    fe2o3-amqp-conformance list";
+
+const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:5672";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Debug)]
+enum Command {
+    Run {
+        config: Config,
+        scenarios: Vec<Scenario>,
+        format: Format,
+    },
+    Listen {
+        addr: String,
+    },
+    List,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    match args.next().as_deref() {
+        Some("run") => {
+            let url = args.next().ok_or("Missing the url of the peer")?;
+            let mut config = Config::new(url);
+            let mut scenarios = Vec::new();
+            let mut format = Format::Text;
+            while let Some(option) = args.next() {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing the value of {}", option))?;
+                match option.as_str() {
+                    "--address" => config = config.address(value),
+                    "--scenario" => scenarios.push(
+                        Scenario::from_name(&value)
+                            .ok_or_else(|| format!("Unknown scenario {}", value))?,
+                    ),
+                    "--timeout-secs" => {
+                        let secs = value
+                            .parse()
+                            .map_err(|_| format!("Invalid timeout {}", value))?;
+                        config = config.timeout(Duration::from_secs(secs));
+                    }
+                    "--format" => {
+                        format = match value.as_str() {
+                            "text" => Format::Text,
+                            "json" => Format::Json,
+                            _ => return Err(format!("Unknown format {}", value)),
+                        }
+                    }
+                    _ => return Err(format!("Unknown option {}", option)),
+                }
+            }
+            if scenarios.is_empty() {
+                scenarios = Scenario::ALL.to_vec();
+            }
+            Ok(Command::Run {
+                config,
+                scenarios,
+                format,
+            })
+        }
+        Some("listen") => Ok(Command::Listen {
+            addr: args
+                .next()
+                .unwrap_or_else(|| String::from(DEFAULT_LISTEN_ADDR)),
+        }),
+        Some("list") => Ok(Command::List),
+        Some(command) => Err(format!("Unknown command {}", command)),
+        None => Err(String::from("Missing the command")),
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let command = match parse_args(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match command {
+        Command::Run {
+            config,
+            scenarios,
+            format,
+        } => {
+            let mut reports = Vec::with_capacity(scenarios.len());
+            for scenario in scenarios {
+                let report = scenario.run(&config).await;
+                match format {
+                    Format::Text => println!("{}", report),
+                    Format::Json => println!("{}", report.to_json()),
+                }
+                reports.push(report);
+            }
+            let summary = Summary::from_reports(&reports);
+            match format {
+                Format::Text => println!("{}", summary),
+                Format::Json => println!("{}", summary.to_json()),
+            }
+            match summary.failed {
+                0 => ExitCode::SUCCESS,
+                _ => ExitCode::FAILURE,
+            }
+        }
+        Command::Listen { addr } => {
+            let result = match TcpListener::bind(&addr).await {
+                Ok(tcp_listener) => {
+                    eprintln!("Listening on {}", addr);
+                    ReferenceListener::new().serve(tcp_listener).await
+                }
+                Err(error) => Err(error),
+            };
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Command::List => {
+            for scenario in Scenario::ALL {
+                println!("{:<22}{}", scenario.name(), scenario.description());
+            }
+            ExitCode::SUCCESS
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Machine-readable results of the scenarios
+
+use std::fmt;
+
+use serde::Serialize;
+
+/// The outcome of a scenario
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "kebab-case")]
+pub enum Outcome {
+    /// The peer behaved as expected
+    Passed,
+
+    /// The peer did not behave as expected
+    Failed {
+        /// What went wrong
+        reason: String,
+    },
+}
+
+/// The result of running a scenario against the peer under test
+///
+/// The report is serialized as a flat JSON object, eg.
+/// `{"scenario":"open-close","outcome":"passed","duration_ms":4}`, and the failed scenarios carry
+/// an additional `reason`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScenarioReport {
+    /// The name of the scenario
+    pub scenario: &'static str,
+
+    /// Whether the scenario passed
+    #[serde(flatten)]
+    pub outcome: Outcome,
+
+    /// How long the scenario took
+    pub duration_ms: u64,
+}
+
+impl ScenarioReport {
+    /// Whether the scenario passed
+    pub fn is_passed(&self) -> bool {
+        matches!(self.outcome, Outcome::Passed)
+    }
+
+    /// The report as a single-line JSON object
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("A report is always serializable")
+    }
+}
+
+impl fmt::Display for ScenarioReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            Outcome::Passed => write!(f, "PASS {} ({} ms)", self.scenario, self.duration_ms),
+            Outcome::Failed { reason } => write!(
+                f,
+                "FAIL {} ({} ms): {}",
+                self.scenario, self.duration_ms, reason
+            ),
+        }
+    }
+}
+
+/// The number of scenarios that passed and failed in a run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    /// Number of scenarios that passed
+    pub passed: usize,
+
+    /// Number of scenarios that failed
+    pub failed: usize,
+}
+
+impl Summary {
+    /// Counts the outcomes of the reports
+    pub fn from_reports(reports: &[ScenarioReport]) -> Self {
+        let passed = reports.iter().filter(|report| report.is_passed()).count();
+        Self {
+            passed,
+            failed: reports.len() - passed,
+        }
+    }
+
+    /// The summary as a single-line JSON object
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("A summary is always serializable")
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} passed, {} failed", self.passed, self.failed)
+    }
+}
@@ -0,0 +1,377 @@
+//! Scripted exchanges with the peer under test
+
+use std::{
+    error::Error as StdError,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use fe2o3_amqp::{
+    types::{
+        definitions::SenderSettleMode,
+        messaging::{
+            AmqpValue, ApplicationProperties, Body, Data, Message, MessageId, Properties, Source,
+        },
+        primitives::{Binary, Value},
+    },
+    Connection, Receiver, Sender, Session,
+};
+use tokio::time::Instant;
+
+use crate::report::{Outcome, ScenarioReport};
+
+/// Default prefix of the node addresses used by the scenarios
+pub const DEFAULT_ADDRESS: &str = "conformance";
+
+/// Default time limit of a scenario
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Container id of the connections opened by the scenarios
+const CONTAINER_ID: &str = "fe2o3-amqp-conformance";
+
+/// Size of the body of the multi-frame transfer, which is larger than the default max frame size
+const MULTI_FRAME_BODY_SIZE: usize = 256 * 1024;
+
+type Error = Box<dyn StdError + Send + Sync>;
+
+/// Where and how the scenarios are run
+///
+/// | Field | Default Value |
+/// |-------|---------------|
+/// |`address`| [`DEFAULT_ADDRESS`] |
+/// |`timeout`| [`DEFAULT_TIMEOUT`] |
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The url of the peer under test
+    pub url: String,
+
+    /// Prefix of the node addresses. Every scenario that transfers messages uses its own node
+    /// named `<address>.<scenario>`
+    pub address: String,
+
+    /// Time limit of each scenario
+    pub timeout: Duration,
+}
+
+impl Config {
+    /// Creates a configuration with the default address and timeout
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            address: String::from(DEFAULT_ADDRESS),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Set the prefix of the node addresses
+    pub fn address(mut self, address: impl Into<String>) -> Self {
+        self.address = address.into();
+        self
+    }
+
+    /// Set the time limit of each scenario
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn node_address(&self, scenario: Scenario) -> String {
+        format!("{}.{}", self.address, scenario.name())
+    }
+}
+
+/// A scripted exchange with the peer under test
+///
+/// The scenarios that transfer messages consume the messages they send, so a run leaves no
+/// message behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scenario {
+    /// Opens and closes a connection
+    OpenClose,
+
+    /// Begins and ends a session
+    SessionBeginEnd,
+
+    /// Attaches and closes a sender and a receiver
+    LinkAttachDetach,
+
+    /// Sends a pre-settled message and receives it back
+    SettledTransfer,
+
+    /// Sends an unsettled message that must be accepted and receives it back
+    UnsettledTransfer,
+
+    /// Sends a message larger than the default max frame size and receives it back unchanged
+    MultiFrameTransfer,
+
+    /// Sends a message with properties and application properties and receives them back
+    /// unchanged
+    MessageProperties,
+
+    /// Attaches a receiver with a dynamic source, which must be given an address
+    DynamicReceiver,
+
+    /// Sends a Flow with `echo` set, which must be answered with a Flow
+    FlowEcho,
+}
+
+impl Scenario {
+    /// All the scenarios in the order they are run
+    pub const ALL: [Scenario; 9] = [
+        Scenario::OpenClose,
+        Scenario::SessionBeginEnd,
+        Scenario::LinkAttachDetach,
+        Scenario::SettledTransfer,
+        Scenario::UnsettledTransfer,
+        Scenario::MultiFrameTransfer,
+        Scenario::MessageProperties,
+        Scenario::DynamicReceiver,
+        Scenario::FlowEcho,
+    ];
+
+    /// The name of the scenario in the reports and on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scenario::OpenClose => "open-close",
+            Scenario::SessionBeginEnd => "session-begin-end",
+            Scenario::LinkAttachDetach => "link-attach-detach",
+            Scenario::SettledTransfer => "settled-transfer",
+            Scenario::UnsettledTransfer => "unsettled-transfer",
+            Scenario::MultiFrameTransfer => "multi-frame-transfer",
+            Scenario::MessageProperties => "message-properties",
+            Scenario::DynamicReceiver => "dynamic-receiver",
+            Scenario::FlowEcho => "flow-echo",
+        }
+    }
+
+    /// Finds a scenario by its name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|scenario| scenario.name() == name)
+    }
+
+    /// A one-line description of what the scenario checks
+    pub fn description(&self) -> &'static str {
+        match self {
+            Scenario::OpenClose => "Opens and closes a connection",
+            Scenario::SessionBeginEnd => "Begins and ends a session",
+            Scenario::LinkAttachDetach => "Attaches and closes a sender and a receiver",
+            Scenario::SettledTransfer => "Sends a pre-settled message and receives it back",
+            Scenario::UnsettledTransfer => {
+                "Sends an unsettled message that must be accepted and receives it back"
+            }
+            Scenario::MultiFrameTransfer => {
+                "Sends a message larger than the default max frame size and receives it back"
+            }
+            Scenario::MessageProperties => {
+                "Receives back the properties and application properties of a message unchanged"
+            }
+            Scenario::DynamicReceiver => "Attaches a receiver with a dynamic source",
+            Scenario::FlowEcho => "Sends a Flow with echo set, which must be answered",
+        }
+    }
+
+    /// Runs the scenario against the peer, failing it if it does not complete within the timeout
+    pub async fn run(self, config: &Config) -> ScenarioReport {
+        let started = Instant::now();
+        let outcome = match tokio::time::timeout(config.timeout, self.exchange(config)).await {
+            Ok(Ok(())) => Outcome::Passed,
+            Ok(Err(error)) => Outcome::Failed {
+                reason: error.to_string(),
+            },
+            Err(_) => Outcome::Failed {
+                reason: format!("Timed out after {:?}", config.timeout),
+            },
+        };
+        ScenarioReport {
+            scenario: self.name(),
+            outcome,
+            duration_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+        }
+    }
+
+    async fn exchange(self, config: &Config) -> Result<(), Error> {
+        let mut connection = Connection::open(CONTAINER_ID, &config.url[..]).await?;
+        if self == Scenario::OpenClose {
+            connection.close().await?;
+            return Ok(());
+        }
+
+        let mut session = Session::begin(&mut connection).await?;
+        let address = config.node_address(self);
+        match self {
+            Scenario::OpenClose | Scenario::SessionBeginEnd => {}
+            Scenario::LinkAttachDetach => {
+                let sender =
+                    Sender::attach(&mut session, "conformance-sender", &address[..]).await?;
+                let receiver =
+                    Receiver::attach(&mut session, "conformance-receiver", &address[..]).await?;
+                sender.close().await?;
+                receiver.close().await?;
+            }
+            Scenario::SettledTransfer | Scenario::UnsettledTransfer => {
+                let settle_mode = match self {
+                    Scenario::SettledTransfer => SenderSettleMode::Settled,
+                    _ => SenderSettleMode::Unsettled,
+                };
+                let mut sender = Sender::builder()
+                    .name("conformance-sender")
+                    .target(&address[..])
+                    .sender_settle_mode(settle_mode)
+                    .attach(&mut session)
+                    .await?;
+                let body = Body::Value(AmqpValue(Value::String(String::from(self.name()))));
+                let (message_id, message) = tagged_message(self, None, body.clone());
+                send_accepted(&mut sender, message).await?;
+                sender.close().await?;
+
+                let received = receive_back(&mut session, &address, &message_id).await?;
+                ensure(
+                    received.body == body,
+                    format!("Expecting the body {:?}, found {:?}", body, received.body),
+                )?;
+            }
+            Scenario::MultiFrameTransfer => {
+                let mut sender =
+                    Sender::attach(&mut session, "conformance-sender", &address[..]).await?;
+                let payload: Vec<u8> = (0..MULTI_FRAME_BODY_SIZE).map(|i| i as u8).collect();
+                let body = Body::Data(vec![Data(Binary::from(payload))].into());
+                let (message_id, message) = tagged_message(self, None, body.clone());
+                send_accepted(&mut sender, message).await?;
+                sender.close().await?;
+
+                let received = receive_back(&mut session, &address, &message_id).await?;
+                ensure(received.body == body, "The body of the message is changed")?;
+            }
+            Scenario::MessageProperties => {
+                let mut sender =
+                    Sender::attach(&mut session, "conformance-sender", &address[..]).await?;
+                let properties = Properties::builder()
+                    .subject(String::from("conformance"))
+                    .content_type("text/plain")
+                    .correlation_id(String::from("correlation-1"))
+                    .build();
+                let application_properties = ApplicationProperties::builder()
+                    .insert("x-string", "value")
+                    .insert("x-int", 42i32)
+                    .insert("x-bool", true)
+                    .build();
+                let body = Body::Value(AmqpValue(Value::String(String::from(self.name()))));
+                let (message_id, mut message) = tagged_message(self, Some(properties), body);
+                message.application_properties = Some(application_properties.clone());
+                let properties = message.properties.clone();
+                send_accepted(&mut sender, message).await?;
+                sender.close().await?;
+
+                let received = receive_back(&mut session, &address, &message_id).await?;
+                ensure(
+                    received.properties == properties,
+                    format!(
+                        "Expecting the properties {:?}, found {:?}",
+                        properties, received.properties
+                    ),
+                )?;
+                ensure(
+                    received.application_properties == Some(application_properties),
+                    format!(
+                        "The application properties are changed to {:?}",
+                        received.application_properties
+                    ),
+                )?;
+            }
+            Scenario::DynamicReceiver => {
+                let receiver = Receiver::builder()
+                    .name("conformance-dynamic-receiver")
+                    .source(Source::builder().dynamic(true).build())
+                    .attach(&mut session)
+                    .await?;
+                let address = receiver
+                    .source()
+                    .as_ref()
+                    .and_then(|source| source.address.clone());
+                ensure(
+                    address.is_some(),
+                    "The dynamic source is not given an address",
+                )?;
+                receiver.close().await?;
+            }
+            Scenario::FlowEcho => {
+                let mut receiver =
+                    Receiver::attach(&mut session, "conformance-receiver", &address[..]).await?;
+                receiver.request_flow_echo().await?;
+                receiver.close().await?;
+            }
+        }
+
+        session.end().await?;
+        connection.close().await?;
+        Ok(())
+    }
+}
+
+/// Runs the scenarios one after the other
+pub async fn run_scenarios(config: &Config, scenarios: &[Scenario]) -> Vec<ScenarioReport> {
+    let mut reports = Vec::with_capacity(scenarios.len());
+    for scenario in scenarios {
+        reports.push(scenario.run(config).await);
+    }
+    reports
+}
+
+/// Gives the message a unique message id, so that it is told apart from the messages left on the
+/// node by previous runs
+fn tagged_message(
+    scenario: Scenario,
+    properties: Option<Properties>,
+    body: Body<Value>,
+) -> (MessageId, Message<Body<Value>>) {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    let message_id = MessageId::from(format!("{}-{}", scenario.name(), nanos));
+    let mut properties = properties.unwrap_or_default();
+    properties.message_id = Some(message_id.clone());
+    let message = Message::builder().properties(properties).body(body).build();
+    (message_id, message)
+}
+
+/// Sends the message and checks that it is accepted. A pre-settled message is always accepted
+async fn send_accepted(sender: &mut Sender, message: Message<Body<Value>>) -> Result<(), Error> {
+    let outcome = sender.send(message).await?;
+    ensure(
+        outcome.is_accepted(),
+        format!("Expecting the message to be accepted, found {:?}", outcome),
+    )
+}
+
+/// Receives the messages on the node until the one with the message id arrives. The other
+/// messages are accepted so that they do not pile up
+async fn receive_back(
+    session: &mut fe2o3_amqp::session::SessionHandle<()>,
+    address: &str,
+    message_id: &MessageId,
+) -> Result<Message<Body<Value>>, Error> {
+    let mut receiver = Receiver::attach(session, "conformance-receiver", address).await?;
+    loop {
+        let delivery = receiver.recv::<Body<Value>>().await?;
+        receiver.accept(&delivery).await?;
+        let is_expected = delivery
+            .message()
+            .properties
+            .as_ref()
+            .and_then(|properties| properties.message_id.as_ref())
+            == Some(message_id);
+        if is_expected {
+            receiver.close().await?;
+            return Ok(delivery.into_message());
+        }
+    }
+}
+
+fn ensure(condition: bool, reason: impl Into<String>) -> Result<(), Error> {
+    match condition {
+        true => Ok(()),
+        false => Err(reason.into().into()),
+    }
+}
@@ -0,0 +1,50 @@
+//! Runs the scenarios against the reference listener
+
+use std::time::Duration;
+
+use fe2o3_amqp_conformance::{
+    run_scenarios, Config, Outcome, ReferenceListener, Scenario, Summary,
+};
+use tokio::net::TcpListener;
+
+async fn spawn_listener() -> String {
+    let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = tcp_listener.local_addr().unwrap();
+    tokio::spawn(ReferenceListener::new().serve(tcp_listener));
+    format!("amqp://{}", addr)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reference_listener_passes_all_scenarios() {
+    let url = spawn_listener().await;
+    let config = Config::new(url).timeout(Duration::from_secs(5));
+    let reports = run_scenarios(&config, &Scenario::ALL).await;
+
+    for report in &reports {
+        assert!(report.is_passed(), "{}", report);
+    }
+    assert_eq!(
+        Summary::from_reports(&reports),
+        Summary {
+            passed: Scenario::ALL.len(),
+            failed: 0
+        }
+    );
+}
+
+#[tokio::test]
+async fn unreachable_peer_fails_with_a_reason() {
+    // The port is released as soon as the listener is dropped
+    let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("amqp://{}", tcp_listener.local_addr().unwrap());
+    drop(tcp_listener);
+
+    let report = Scenario::OpenClose.run(&Config::new(url)).await;
+    assert!(matches!(report.outcome, Outcome::Failed { .. }));
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+    assert_eq!(json["scenario"], "open-close");
+    assert_eq!(json["outcome"], "failed");
+    assert!(json["reason"].is_string());
+    assert!(json["duration_ms"].is_u64());
+}
@@ -0,0 +1,431 @@
+//! Typed builders for the most common broker filters
+//!
+//! [`Selector`] builds the expression of a [`SelectorFilter`] (a JMS message selector) with the
+//! string literals and `LIKE` patterns escaped, and [`TopicBinding`] builds the pattern of a
+//! [`LegacyAmqpTopicBinding`]. Both convert into the described filter values that are accepted by
+//! [`SourceBuilder::add_to_filter`], and [`SourceFilterExt`] adds them under the conventional
+//! filter keys.
+//!
+//! # Example
+//!
+//! ```rust
+//! use fe2o3_amqp_ext::builder::{Selector, SourceFilterExt, TopicBinding};
+//! use fe2o3_amqp_types::messaging::Source;
+//!
+//! let selector = Selector::property("color")
+//!     .eq("O'Neil")
+//!     .and(Selector::property("weight").gt(2500));
+//! assert_eq!(selector.to_string(), "(color = 'O''Neil') AND (weight > 2500)");
+//!
+//! let binding = TopicBinding::new().word("stock").any_word().rest().build().unwrap();
+//! assert_eq!(binding.0, "stock.*.#");
+//!
+//! let source = Source::builder()
+//!     .address("q1")
+//!     .selector(selector)
+//!     .topic_binding(binding)
+//!     .build();
+//! assert_eq!(source.filter.unwrap().len(), 2);
+//! ```
+
+use std::fmt::{self, Display, Write};
+
+use fe2o3_amqp_types::{
+    messaging::SourceBuilder,
+    primitives::{Symbol, Value},
+};
+use serde_amqp::described::Described;
+
+use crate::filters::{LegacyAmqpTopicBinding, SelectorFilter};
+
+/// The filter key under which [`SourceFilterExt::selector`] adds a selector filter
+pub const SELECTOR_FILTER_KEY: &str = "jms-selector";
+
+/// The filter key under which [`SourceFilterExt::topic_binding`] adds a topic binding filter
+pub const TOPIC_BINDING_FILTER_KEY: &str = "topic-binding";
+
+/// The escape character used in the `LIKE` patterns built by [`Property::starts_with`],
+/// [`Property::ends_with`] and [`Property::contains`]
+const LIKE_ESCAPE: char = '\\';
+
+/// A literal on the right hand side of a selector comparison
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectorValue {
+    /// A string literal, which is quoted and escaped
+    String(String),
+
+    /// A boolean literal
+    Bool(bool),
+
+    /// An exact numeric literal
+    Long(i64),
+
+    /// An approximate numeric literal
+    Double(f64),
+}
+
+impl Display for SelectorValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectorValue::String(s) => write_string_literal(f, s),
+            SelectorValue::Bool(true) => f.write_str("TRUE"),
+            SelectorValue::Bool(false) => f.write_str("FALSE"),
+            SelectorValue::Long(n) => write!(f, "{}", n),
+            // `Debug` always keeps the decimal point or the exponent so that the literal is not
+            // read back as an exact numeric
+            SelectorValue::Double(n) => write!(f, "{:?}", n),
+        }
+    }
+}
+
+macro_rules! impl_from_for_selector_value {
+    ($variant:ident, $target:ty, $($t:ty),*) => {
+        $(
+            impl From<$t> for SelectorValue {
+                fn from(value: $t) -> Self {
+                    SelectorValue::$variant(<$target>::from(value))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_for_selector_value!(String, String, String, &str);
+impl_from_for_selector_value!(Bool, bool, bool);
+impl_from_for_selector_value!(Long, i64, i8, i16, i32, i64, u8, u16, u32);
+impl_from_for_selector_value!(Double, f64, f32, f64);
+
+fn write_string_literal(f: &mut impl Write, s: &str) -> fmt::Result {
+    f.write_char('\'')?;
+    for c in s.chars() {
+        if c == '\'' {
+            f.write_char('\'')?;
+        }
+        f.write_char(c)?;
+    }
+    f.write_char('\'')
+}
+
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '%' | '_') || c == LIKE_ESCAPE {
+            escaped.push(LIKE_ESCAPE);
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// A message property or header field on the left hand side of a selector comparison
+///
+/// The identifier is written as is, so it must be a valid selector identifier such as
+/// `JMSPriority` or an application property name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Property(String);
+
+impl Property {
+    fn compare(self, op: &str, value: impl Into<SelectorValue>) -> Selector {
+        Selector(format!("{} {} {}", self.0, op, value.into()))
+    }
+
+    /// `property = value`
+    pub fn eq(self, value: impl Into<SelectorValue>) -> Selector {
+        self.compare("=", value)
+    }
+
+    /// `property <> value`
+    pub fn ne(self, value: impl Into<SelectorValue>) -> Selector {
+        self.compare("<>", value)
+    }
+
+    /// `property > value`
+    pub fn gt(self, value: impl Into<SelectorValue>) -> Selector {
+        self.compare(">", value)
+    }
+
+    /// `property >= value`
+    pub fn ge(self, value: impl Into<SelectorValue>) -> Selector {
+        self.compare(">=", value)
+    }
+
+    /// `property < value`
+    pub fn lt(self, value: impl Into<SelectorValue>) -> Selector {
+        self.compare("<", value)
+    }
+
+    /// `property <= value`
+    pub fn le(self, value: impl Into<SelectorValue>) -> Selector {
+        self.compare("<=", value)
+    }
+
+    /// `property BETWEEN low AND high`
+    pub fn between(
+        self,
+        low: impl Into<SelectorValue>,
+        high: impl Into<SelectorValue>,
+    ) -> Selector {
+        Selector(format!(
+            "{} BETWEEN {} AND {}",
+            self.0,
+            low.into(),
+            high.into()
+        ))
+    }
+
+    /// `property IN ('a', 'b', ...)`
+    pub fn is_in<I, S>(self, values: I) -> Selector
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut expr = format!("{} IN (", self.0);
+        for (i, value) in values.into_iter().enumerate() {
+            if i > 0 {
+                expr.push_str(", ");
+            }
+            // Writing to a `String` never fails
+            let _ = write_string_literal(&mut expr, value.as_ref());
+        }
+        expr.push(')');
+        Selector(expr)
+    }
+
+    /// `property LIKE 'pattern'`
+    ///
+    /// The `%` and `_` wildcards in `pattern` are kept, only the quotes are escaped. Use
+    /// [`starts_with`](Self::starts_with), [`ends_with`](Self::ends_with) or
+    /// [`contains`](Self::contains) to match a literal text.
+    pub fn like(self, pattern: impl AsRef<str>) -> Selector {
+        let mut expr = format!("{} LIKE ", self.0);
+        let _ = write_string_literal(&mut expr, pattern.as_ref());
+        Selector(expr)
+    }
+
+    fn like_escaped(self, prefix: &str, text: &str, suffix: &str) -> Selector {
+        let pattern = format!("{}{}{}", prefix, escape_like(text), suffix);
+        let mut expr = format!("{} LIKE ", self.0);
+        let _ = write_string_literal(&mut expr, &pattern);
+        let _ = write!(expr, " ESCAPE '{}'", LIKE_ESCAPE);
+        Selector(expr)
+    }
+
+    /// Matches a string property that starts with the literal `text`
+    pub fn starts_with(self, text: impl AsRef<str>) -> Selector {
+        self.like_escaped("", text.as_ref(), "%")
+    }
+
+    /// Matches a string property that ends with the literal `text`
+    pub fn ends_with(self, text: impl AsRef<str>) -> Selector {
+        self.like_escaped("%", text.as_ref(), "")
+    }
+
+    /// Matches a string property that contains the literal `text`
+    pub fn contains(self, text: impl AsRef<str>) -> Selector {
+        self.like_escaped("%", text.as_ref(), "%")
+    }
+
+    /// `property IS NULL`
+    pub fn is_null(self) -> Selector {
+        Selector(format!("{} IS NULL", self.0))
+    }
+
+    /// `property IS NOT NULL`
+    pub fn is_not_null(self) -> Selector {
+        Selector(format!("{} IS NOT NULL", self.0))
+    }
+}
+
+/// A JMS message selector expression
+///
+/// Compound expressions are parenthesized so that the precedence is the one of the calls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector(String);
+
+impl Selector {
+    /// Starts a comparison on a message property or header field
+    pub fn property(name: impl Into<String>) -> Property {
+        Property(name.into())
+    }
+
+    /// Uses a hand-written selector expression as is
+    pub fn raw(expr: impl Into<String>) -> Self {
+        Self(expr.into())
+    }
+
+    /// `(self) AND (other)`
+    pub fn and(self, other: Selector) -> Self {
+        Self(format!("({}) AND ({})", self.0, other.0))
+    }
+
+    /// `(self) OR (other)`
+    pub fn or(self, other: Selector) -> Self {
+        Self(format!("({}) OR ({})", self.0, other.0))
+    }
+
+    /// `NOT (self)`
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Self {
+        Self(format!("NOT ({})", self.0))
+    }
+
+    /// The selector expression
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<Selector> for SelectorFilter {
+    fn from(value: Selector) -> Self {
+        SelectorFilter(value.0)
+    }
+}
+
+impl From<Selector> for Option<Described<Value>> {
+    fn from(value: Selector) -> Self {
+        SelectorFilter::from(value).into()
+    }
+}
+
+/// Error of a topic word that contains a separator or a wildcard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidTopicWord(pub String);
+
+impl Display for InvalidTopicWord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Topic word {:?} is empty or contains '.', '*' or '#'",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidTopicWord {}
+
+/// Builder of the pattern of a [`LegacyAmqpTopicBinding`]
+///
+/// The pattern is a list of dot separated words, where `*` matches exactly one word and `#`
+/// matches zero or more words.
+#[derive(Debug, Clone, Default)]
+pub struct TopicBinding {
+    words: Vec<String>,
+    invalid: Option<String>,
+}
+
+impl TopicBinding {
+    /// Creates an empty pattern
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a literal word
+    pub fn word(mut self, word: impl Into<String>) -> Self {
+        let word = word.into();
+        if self.invalid.is_none() && (word.is_empty() || word.contains(['.', '*', '#'])) {
+            self.invalid = Some(word.clone());
+        }
+        self.words.push(word);
+        self
+    }
+
+    /// Appends `*`, which matches exactly one word
+    pub fn any_word(mut self) -> Self {
+        self.words.push(String::from("*"));
+        self
+    }
+
+    /// Appends `#`, which matches zero or more words
+    pub fn rest(mut self) -> Self {
+        self.words.push(String::from("#"));
+        self
+    }
+
+    /// Builds the filter, or returns the first word that is not valid
+    pub fn build(self) -> Result<LegacyAmqpTopicBinding, InvalidTopicWord> {
+        match self.invalid {
+            Some(word) => Err(InvalidTopicWord(word)),
+            None => Ok(LegacyAmqpTopicBinding(self.words.join("."))),
+        }
+    }
+}
+
+/// Adds the typed filters to a [`SourceBuilder`]
+pub trait SourceFilterExt {
+    /// Adds a selector filter under the [`SELECTOR_FILTER_KEY`]
+    fn selector(self, selector: impl Into<SelectorFilter>) -> Self;
+
+    /// Adds a topic binding filter under the [`TOPIC_BINDING_FILTER_KEY`]
+    fn topic_binding(self, binding: LegacyAmqpTopicBinding) -> Self;
+}
+
+impl SourceFilterExt for SourceBuilder {
+    fn selector(self, selector: impl Into<SelectorFilter>) -> Self {
+        self.add_to_filter(Symbol::from(SELECTOR_FILTER_KEY), selector.into())
+    }
+
+    fn topic_binding(self, binding: LegacyAmqpTopicBinding) -> Self {
+        self.add_to_filter(Symbol::from(TOPIC_BINDING_FILTER_KEY), binding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::{
+        messaging::Source,
+        primitives::{Symbol, Value},
+    };
+    use serde_amqp::descriptor::Descriptor;
+
+    use super::{Selector, SourceFilterExt, TopicBinding, SELECTOR_FILTER_KEY};
+    use crate::filters::SelectorFilter;
+
+    #[test]
+    fn selector_literals_are_escaped() {
+        let selector = Selector::property("name")
+            .eq("it's")
+            .or(Selector::property("flag").eq(true).not())
+            .and(Selector::property("ratio").le(1.0))
+            .and(Selector::property("path").starts_with("50%_off\\"))
+            .and(Selector::property("region").is_in(["eu", "o'us"]));
+        assert_eq!(
+            selector.as_str(),
+            "((((name = 'it''s') OR (NOT (flag = TRUE))) AND (ratio <= 1.0)) \
+             AND (path LIKE '50\\%\\_off\\\\%' ESCAPE '\\')) AND (region IN ('eu', 'o''us'))"
+        );
+    }
+
+    #[test]
+    fn topic_binding_rejects_wildcards_in_words() {
+        let binding = TopicBinding::new().word("a").any_word().rest().build();
+        assert_eq!(binding.unwrap().0, "a.*.#");
+
+        let err = TopicBinding::new().word("a.b").build().unwrap_err();
+        assert_eq!(err.0, "a.b");
+        assert!(TopicBinding::new().word("").build().is_err());
+    }
+
+    #[test]
+    fn selector_is_added_as_described_filter() {
+        let source = Source::builder()
+            .selector(Selector::property("sn").eq(100))
+            .build();
+        let filter = source.filter.unwrap();
+        match filter.get(&Symbol::from(SELECTOR_FILTER_KEY)) {
+            Some(Value::Described(described)) => {
+                assert_eq!(
+                    described.descriptor,
+                    Descriptor::Code(SelectorFilter::descriptor_code())
+                );
+                assert_eq!(described.value, Value::String(String::from("sn = 100")));
+            }
+            other => panic!("unexpected filter {:?}", other),
+        }
+    }
+}
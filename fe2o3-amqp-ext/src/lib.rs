@@ -2,4 +2,5 @@
 
 //! Extensions to `fe2o3-amqp`
 
+pub mod builder;
 pub mod filters;
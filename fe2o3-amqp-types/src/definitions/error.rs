@@ -1,4 +1,7 @@
-use serde_amqp::macros::{DeserializeComposite, SerializeComposite};
+use serde_amqp::{
+    macros::{DeserializeComposite, SerializeComposite},
+    Value,
+};
 
 use super::{ErrorCondition, Fields};
 
@@ -48,6 +51,14 @@ impl Error {
             info: info.into(),
         }
     }
+
+    /// Get a reference to the value associated with the key in the `info` fields
+    ///
+    /// This is useful for reading broker specific information attached to the error (eg.
+    /// `com.microsoft:tracking-id`)
+    pub fn info_value(&self, key: &str) -> Option<&Value> {
+        self.info.as_ref().and_then(|info| info.get(key))
+    }
 }
 
 impl<T> From<T> for Error
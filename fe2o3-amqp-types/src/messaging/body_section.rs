@@ -66,17 +66,40 @@ pub(crate) mod __private {
 /// 9. [`Arc<T>`] where `T` implements `SerializableBody`
 /// 10. [`Rc<T>`] where `T` implements `SerializableBody`
 /// 11. [`Box<T>`] where `T` implements `SerializableBody`
-pub trait SerializableBody: Serialize + BodySection {}
+pub trait SerializableBody: Serialize + BodySection {
+    /// Whether there is no body section at all, in which case the body is left out when the
+    /// message is serialized
+    fn is_empty_body(&self) -> bool {
+        false
+    }
+}
 
-impl<T> SerializableBody for &T where T: SerializableBody {}
+impl<T> SerializableBody for &T
+where
+    T: SerializableBody,
+{
+    fn is_empty_body(&self) -> bool {
+        (**self).is_empty_body()
+    }
+}
 
-impl<T> SerializableBody for &mut T where T: SerializableBody {}
+impl<T> SerializableBody for &mut T
+where
+    T: SerializableBody,
+{
+    fn is_empty_body(&self) -> bool {
+        (**self).is_empty_body()
+    }
+}
 
 impl<T> SerializableBody for Box<T>
 where
     T: SerializableBody,
     Box<T>: Serialize,
 {
+    fn is_empty_body(&self) -> bool {
+        (**self).is_empty_body()
+    }
 }
 
 impl<T> SerializableBody for Rc<T>
@@ -84,6 +107,9 @@ where
     T: SerializableBody,
     Rc<T>: Serialize,
 {
+    fn is_empty_body(&self) -> bool {
+        (**self).is_empty_body()
+    }
 }
 
 impl<T> SerializableBody for Arc<T>
@@ -91,6 +117,9 @@ where
     T: SerializableBody,
     Arc<T>: Serialize,
 {
+    fn is_empty_body(&self) -> bool {
+        (**self).is_empty_body()
+    }
 }
 
 /// This trait defines how to interprerte a message when there is an emtpy body.
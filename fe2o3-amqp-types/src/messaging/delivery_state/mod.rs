@@ -2,6 +2,7 @@
 
 use serde_amqp::macros::{DeserializeComposite, SerializeComposite};
 use serde_amqp::primitives::{Boolean, Uint, Ulong};
+use serde_amqp::Value;
 
 use crate::definitions::{Error, Fields};
 
@@ -140,6 +141,55 @@ impl DeliveryState {
         }
     }
 
+    /// Returns a reference to the [`Received`] state, or `None` for any other state
+    pub fn as_received(&self) -> Option<&Received> {
+        match self {
+            Self::Received(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns a reference to the [`Accepted`] state, or `None` for any other state
+    pub fn as_accepted(&self) -> Option<&Accepted> {
+        match self {
+            Self::Accepted(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns a reference to the [`Rejected`] state, or `None` for any other state
+    pub fn as_rejected(&self) -> Option<&Rejected> {
+        match self {
+            Self::Rejected(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns a reference to the [`Released`] state, or `None` for any other state
+    pub fn as_released(&self) -> Option<&Released> {
+        match self {
+            Self::Released(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns a reference to the [`Modified`] state, or `None` for any other state
+    pub fn as_modified(&self) -> Option<&Modified> {
+        match self {
+            Self::Modified(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns a reference to the [`Declared`] state, or `None` for any other state
+    #[cfg(feature = "transaction")]
+    pub fn as_declared(&self) -> Option<&Declared> {
+        match self {
+            Self::Declared(value) => Some(value),
+            _ => None,
+        }
+    }
+
     /// Transforms the [`DeliveryState`] into a `Result<Received, E>`,
     /// mapping Received(received) to Ok(received) and other variants to Err(err).
     pub fn received_or<E>(self, err: E) -> Result<Received, E> {
@@ -317,6 +367,15 @@ impl Outcome {
         }
     }
 
+    /// Returns the error carried by a [`Rejected`] outcome. Returns `None` for any other
+    /// outcome
+    pub fn rejected_error(&self) -> Option<&Error> {
+        match self {
+            Self::Rejected(rejected) => rejected.error(),
+            _ => None,
+        }
+    }
+
     /// Returns true if the result is [`Released`].
     pub fn is_released(&self) -> bool {
         match self {
@@ -353,6 +412,47 @@ impl Outcome {
         }
     }
 
+    /// Returns a reference to the [`Accepted`] outcome, or `None` for any other outcome
+    pub fn as_accepted(&self) -> Option<&Accepted> {
+        match self {
+            Self::Accepted(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns a reference to the [`Rejected`] outcome, or `None` for any other outcome
+    pub fn as_rejected(&self) -> Option<&Rejected> {
+        match self {
+            Self::Rejected(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns a reference to the [`Released`] outcome, or `None` for any other outcome
+    pub fn as_released(&self) -> Option<&Released> {
+        match self {
+            Self::Released(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns a reference to the [`Modified`] outcome, or `None` for any other outcome
+    pub fn as_modified(&self) -> Option<&Modified> {
+        match self {
+            Self::Modified(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns a reference to the [`Declared`] outcome, or `None` for any other outcome
+    #[cfg(feature = "transaction")]
+    pub fn as_declared(&self) -> Option<&Declared> {
+        match self {
+            Self::Declared(value) => Some(value),
+            _ => None,
+        }
+    }
+
     /// Transforms the [`DeliveryState`] into a `Result<Accepted, E>`,
     /// mapping Accepted(accepted) to Ok(accepted) and other variants to Err(err).
     pub fn accepted_or<E>(self, err: E) -> Result<Accepted, E> {
@@ -477,6 +577,29 @@ impl From<Outcome> for DeliveryState {
     }
 }
 
+/// Converts a terminal [`DeliveryState`] into an [`Outcome`]. The non-terminal states are
+/// returned as the error
+impl TryFrom<DeliveryState> for Outcome {
+    type Error = DeliveryState;
+
+    fn try_from(value: DeliveryState) -> Result<Self, Self::Error> {
+        match value {
+            DeliveryState::Accepted(val) => Ok(Self::Accepted(val)),
+            DeliveryState::Rejected(val) => Ok(Self::Rejected(val)),
+            DeliveryState::Released(val) => Ok(Self::Released(val)),
+            DeliveryState::Modified(val) => Ok(Self::Modified(val)),
+
+            #[cfg(feature = "transaction")]
+            DeliveryState::Declared(val) => Ok(Self::Declared(val)),
+
+            DeliveryState::Received(_) => Err(value),
+
+            #[cfg(feature = "transaction")]
+            DeliveryState::TransactionalState(_) => Err(value),
+        }
+    }
+}
+
 /// 3.4.1 Received
 ///
 /// <type name="received" class="composite" source="list" provides="delivery-state">
@@ -550,6 +673,30 @@ pub struct Rejected {
     pub error: Option<Error>,
 }
 
+impl Rejected {
+    /// Creates a new [`Rejected`] outcome
+    pub fn new(error: impl Into<Option<Error>>) -> Self {
+        Self {
+            error: error.into(),
+        }
+    }
+
+    /// Get a reference to the error that describes the reason for the rejection
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Get a reference to the `info` fields of the error
+    pub fn info(&self) -> Option<&Fields> {
+        self.error.as_ref().and_then(|error| error.info.as_ref())
+    }
+
+    /// Get a reference to the value associated with the key in the `info` fields of the error
+    pub fn info_value(&self, key: &str) -> Option<&Value> {
+        self.error.as_ref().and_then(|error| error.info_value(key))
+    }
+}
+
 impl From<Rejected> for DeliveryState {
     fn from(value: Rejected) -> Self {
         Self::Rejected(value)
@@ -628,7 +775,7 @@ mod tests {
     //! Test serialization and deserialization
     use serde_amqp::{de::from_slice, format_code::EncodingCodes, from_reader, ser::to_vec};
 
-    use super::{Accepted, DeliveryState, Modified, Received, Rejected, Released};
+    use super::{Accepted, DeliveryState, Modified, Outcome, Received, Rejected, Released};
 
     /* ---------------------------- // test Accepted ---------------------------- */
     #[test]
@@ -847,4 +994,51 @@ mod tests {
         };
         assert!(smaller == larger);
     }
+
+    #[test]
+    fn test_rejected_info_is_preserved() {
+        use crate::definitions::{AmqpError, Error, Fields};
+        use serde_amqp::{primitives::Symbol, Value};
+
+        let mut info = Fields::new();
+        info.insert(
+            Symbol::from("com.microsoft:tracking-id"),
+            Value::String(String::from("abc-123")),
+        );
+        let error = Error::new(AmqpError::NotAllowed, None, info);
+        let rejected = Rejected::new(error);
+
+        let buf = to_vec(&rejected).unwrap();
+        let rejected: Rejected = from_slice(&buf).unwrap();
+        assert_eq!(
+            rejected.info_value("com.microsoft:tracking-id"),
+            Some(&Value::String(String::from("abc-123")))
+        );
+        assert!(rejected.info_value("unknown").is_none());
+
+        let outcome = Outcome::Rejected(rejected);
+        let error = outcome.rejected_error().unwrap();
+        assert_eq!(error.condition, AmqpError::NotAllowed.into());
+        assert_eq!(error.info.as_ref().unwrap().len(), 1);
+        assert!(Outcome::Accepted(Accepted {}).rejected_error().is_none());
+    }
+
+    #[test]
+    fn test_convert_between_delivery_state_and_outcome() {
+        let state = DeliveryState::from(Outcome::Rejected(Rejected::new(None)));
+        assert!(state.is_terminal());
+        assert!(state.as_rejected().is_some());
+        assert!(state.as_accepted().is_none());
+
+        let outcome = Outcome::try_from(state).unwrap();
+        assert!(outcome.as_rejected().is_some());
+        assert!(outcome.as_modified().is_none());
+
+        let received = DeliveryState::Received(Received {
+            section_number: 0,
+            section_offset: 0,
+        });
+        let state = Outcome::try_from(received).unwrap_err();
+        assert!(state.as_received().is_some());
+    }
 }
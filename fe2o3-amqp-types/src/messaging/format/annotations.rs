@@ -82,7 +82,7 @@ impl From<u64> for OwnedKey {
 
 impl From<String> for OwnedKey {
     fn from(value: String) -> Self {
-        Self::Symbol(Symbol(value))
+        Self::Symbol(Symbol::from(value))
     }
 }
 
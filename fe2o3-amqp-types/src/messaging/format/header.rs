@@ -14,6 +14,10 @@ use super::Priority;
 /// <type name="header" class="composite" source="list" provides="section">
 ///     <descriptor name="amqp:header:list" code="0x00000000:0x00000070"/>
 /// </type>
+///
+/// The fields that are omitted or null on the wire take the defaults of the spec, which are also
+/// the values of [`Header::default()`] and of a new [`Builder`]: `durable` is `false`, `priority`
+/// is `4`, `first-acquirer` is `false` and `delivery-count` is `0`. The `ttl` has no default.
 #[derive(
     Debug,
     Clone,
@@ -114,3 +118,78 @@ impl From<Builder> for Option<Header> {
         Some(builder.build())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_amqp::{from_slice, to_vec};
+
+    use super::{Header, Priority};
+
+    fn default_header() -> Header {
+        Header {
+            durable: false,
+            priority: Priority(4),
+            ttl: None,
+            first_acquirer: false,
+            delivery_count: 0,
+        }
+    }
+
+    #[test]
+    fn default_and_builder_follow_the_spec() {
+        assert_eq!(Header::default(), default_header());
+        assert_eq!(Header::builder().build(), default_header());
+
+        let header = Header::builder().durable(true).build();
+        assert_eq!(header.priority, Priority(4));
+        assert!(!header.first_acquirer);
+        assert_eq!(header.delivery_count, 0);
+    }
+
+    #[test]
+    fn omitted_and_null_fields_take_the_defaults() {
+        let cases: [&[u8]; 3] = [
+            // Empty list
+            &[0x00, 0x53, 0x70, 0x45],
+            // The trailing fields are omitted
+            &[0x00, 0x53, 0x70, 0xc0, 0x03, 0x02, 0x40, 0x40],
+            // All the fields are null
+            &[
+                0x00, 0x53, 0x70, 0xc0, 0x06, 0x05, 0x40, 0x40, 0x40, 0x40, 0x40,
+            ],
+        ];
+        for bytes in cases {
+            assert_eq!(from_slice::<Header>(bytes).unwrap(), default_header());
+        }
+
+        // durable = true, priority = 9, ttl = 1000
+        let bytes = [
+            0x00, 0x53, 0x70, 0xc0, 0x09, 0x03, 0x41, 0x50, 0x09, 0x70, 0x00, 0x00, 0x03, 0xe8,
+        ];
+        let header: Header = from_slice(&bytes).unwrap();
+        assert_eq!(
+            header,
+            Header {
+                durable: true,
+                priority: Priority(9),
+                ttl: Some(1000),
+                ..default_header()
+            }
+        );
+    }
+
+    #[test]
+    fn header_round_trips() {
+        let header = Header::builder()
+            .priority(0)
+            .first_acquirer(true)
+            .delivery_count(3)
+            .build();
+        let decoded: Header = from_slice(&to_vec(&header).unwrap()).unwrap();
+        assert_eq!(decoded, header);
+        assert_eq!(
+            to_vec(&Header::default()).unwrap(),
+            [0x00, 0x53, 0x70, 0x45]
+        );
+    }
+}
@@ -77,7 +77,14 @@ impl MapBuilder<String, SimpleValue, ApplicationProperties> {
 
 #[cfg(test)]
 mod tests {
-    use crate::messaging::{ApplicationProperties, MessageAnnotations};
+    use std::borrow::Cow;
+
+    use serde_bytes::ByteBuf;
+
+    use crate::{
+        messaging::{ApplicationProperties, MessageAnnotations},
+        primitives::{SimpleValue, Uuid},
+    };
 
     #[test]
     fn test_message_annotation_builder() {
@@ -96,4 +103,35 @@ mod tests {
             .build();
         println!("{:?}", application_props);
     }
+
+    #[test]
+    fn application_properties_builder_coerces_common_types() {
+        let name = String::from("name");
+        let application_props = ApplicationProperties::builder()
+            .insert("usize", 1usize)
+            .insert("isize", -1isize)
+            .insert("f64", 1.5)
+            .insert("string", &name)
+            .insert("cow", Cow::Borrowed("cow"))
+            .insert("bytes", &b"bytes"[..])
+            .insert("uuid", Uuid::from([1; 16]))
+            .insert("some", Some(2u8))
+            .insert("none", None::<i32>)
+            .build();
+
+        let expected = [
+            ("usize", SimpleValue::Ulong(1)),
+            ("isize", SimpleValue::Long(-1)),
+            ("f64", SimpleValue::Double(1.5.into())),
+            ("string", SimpleValue::String(name)),
+            ("cow", SimpleValue::String("cow".into())),
+            ("bytes", SimpleValue::Binary(ByteBuf::from(&b"bytes"[..]))),
+            ("uuid", SimpleValue::Uuid(Uuid::from([1; 16]))),
+            ("some", SimpleValue::Ubyte(2)),
+            ("none", SimpleValue::Null),
+        ];
+        for (key, value) in expected {
+            assert_eq!(application_props.get(key), Some(&value), "{}", key);
+        }
+    }
 }
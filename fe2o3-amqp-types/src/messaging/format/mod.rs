@@ -210,6 +210,38 @@ pub struct ApplicationProperties(pub OrderedMap<String, SimpleValue>);
 
 impl ApplicationProperties {
     /// Creates a builder for ApplicationProperties
+    ///
+    /// The values can be anything that converts into a [`SimpleValue`], which includes the
+    /// integers, floats, `bool`, `char`, strings, byte slices and vectors (as `binary`),
+    /// [`Timestamp`](crate::primitives::Timestamp), [`Uuid`](crate::primitives::Uuid) and
+    /// [`Symbol`](crate::primitives::Symbol). An `Option` is inserted as `null` if it is `None`.
+    ///
+    /// ```rust
+    /// use fe2o3_amqp_types::messaging::ApplicationProperties;
+    /// use fe2o3_amqp_types::primitives::{SimpleValue, Timestamp};
+    ///
+    /// let properties = ApplicationProperties::builder()
+    ///     .insert("count", 3u32)
+    ///     .insert("ratio", 0.5)
+    ///     .insert("urgent", true)
+    ///     .insert("region", "eu-west")
+    ///     .insert("created", Timestamp::from_milliseconds(0))
+    ///     .insert("checksum", vec![0xde, 0xad])
+    ///     .insert("reply-to", None::<String>)
+    ///     .build();
+    /// assert_eq!(properties.get("reply-to"), Some(&SimpleValue::Null));
+    /// ```
+    ///
+    /// The application properties are restricted to simple types by the spec, so compound values
+    /// like lists or maps are rejected at compile time
+    ///
+    /// ```rust,compile_fail
+    /// use fe2o3_amqp_types::messaging::ApplicationProperties;
+    ///
+    /// let properties = ApplicationProperties::builder()
+    ///     .insert("tags", vec![String::from("a"), String::from("b")])
+    ///     .build();
+    /// ```
     pub fn builder() -> MapBuilder<String, SimpleValue, Self> {
         MapBuilder::new()
     }
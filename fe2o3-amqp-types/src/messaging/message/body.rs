@@ -284,7 +284,14 @@ where
 
 impl<T> BodySection for Body<T> {}
 
-impl<T> SerializableBody for Body<T> where T: ser::Serialize {}
+impl<T> SerializableBody for Body<T>
+where
+    T: ser::Serialize,
+{
+    fn is_empty_body(&self) -> bool {
+        self.is_empty()
+    }
+}
 
 impl<'de, T> DeserializableBody<'de> for Body<T> where T: de::Deserialize<'de> {}
 
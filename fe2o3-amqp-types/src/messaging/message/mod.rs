@@ -7,11 +7,17 @@ use serde::{
     ser::SerializeStruct,
     Serialize,
 };
-use serde_amqp::__constants::{DESCRIBED_BASIC, DESCRIPTOR};
+use serde_amqp::{
+    __constants::{DESCRIBED_BASIC, DESCRIPTOR},
+    primitives::Uint,
+    DecodeLimits,
+};
+
+use crate::definitions::Milliseconds;
 
 use super::{
     AmqpSequence, AmqpValue, ApplicationProperties, Batch, Data, DeliveryAnnotations, Footer,
-    FromBody, Header, IntoBody, MessageAnnotations, Properties, SerializableBody,
+    FromBody, Header, IntoBody, MessageAnnotations, Priority, Properties, SerializableBody,
 };
 
 mod body;
@@ -40,6 +46,8 @@ pub trait DecodeIntoMessage: Sized {
     type DecodeError;
 
     /// Decode reader into [`Message<T>`]
+    ///
+    /// The default [`DecodeLimits`] on nesting depth and collection length are applied
     fn decode_into_message(reader: impl io::Read) -> Result<Message<Self>, Self::DecodeError>;
 }
 
@@ -50,7 +58,8 @@ where
     type DecodeError = serde_amqp::Error;
 
     fn decode_into_message(reader: impl io::Read) -> Result<Message<Self>, Self::DecodeError> {
-        let message: Deserializable<Message<T>> = serde_amqp::from_reader(reader)?;
+        let message: Deserializable<Message<T>> =
+            serde_amqp::from_reader_with_limits(reader, DecodeLimits::default())?;
         Ok(message.0)
     }
 }
@@ -177,6 +186,92 @@ impl<T> Message<T> {
             footer: self.footer,
         }
     }
+
+    /// Like [`map_body`](#method.map_body) but the body conversion may fail, in which case the
+    /// error is returned and the rest of the message is dropped
+    pub fn try_map_body<F, B, E>(self, op: F) -> Result<Message<B>, E>
+    where
+        F: FnOnce(T) -> Result<B, E>,
+    {
+        Ok(Message {
+            header: self.header,
+            delivery_annotations: self.delivery_annotations,
+            message_annotations: self.message_annotations,
+            properties: self.properties,
+            application_properties: self.application_properties,
+            body: (op)(self.body)?,
+            footer: self.footer,
+        })
+    }
+
+    /// Get a mutable reference to the header, inserting an empty header if absent
+    pub fn header_mut(&mut self) -> &mut Header {
+        self.header.get_or_insert_with(Default::default)
+    }
+
+    /// Get a mutable reference to the delivery annotations, inserting an empty section if absent
+    pub fn delivery_annotations_mut(&mut self) -> &mut DeliveryAnnotations {
+        self.delivery_annotations
+            .get_or_insert_with(Default::default)
+    }
+
+    /// Get a mutable reference to the message annotations, inserting an empty section if absent
+    pub fn message_annotations_mut(&mut self) -> &mut MessageAnnotations {
+        self.message_annotations
+            .get_or_insert_with(Default::default)
+    }
+
+    /// Get a mutable reference to the properties, inserting an empty section if absent
+    pub fn properties_mut(&mut self) -> &mut Properties {
+        self.properties.get_or_insert_with(Default::default)
+    }
+
+    /// Get a mutable reference to the application properties, inserting an empty section if
+    /// absent
+    pub fn application_properties_mut(&mut self) -> &mut ApplicationProperties {
+        self.application_properties
+            .get_or_insert_with(Default::default)
+    }
+
+    /// Get a mutable reference to the body
+    pub fn body_mut(&mut self) -> &mut T {
+        &mut self.body
+    }
+
+    /// Get a mutable reference to the footer, inserting an empty footer if absent
+    pub fn footer_mut(&mut self) -> &mut Footer {
+        self.footer.get_or_insert_with(Default::default)
+    }
+
+    /// The `durable` field of the header, or `false` if the message has no header
+    pub fn durable(&self) -> bool {
+        self.header.as_ref().map(|h| h.durable).unwrap_or(false)
+    }
+
+    /// The `priority` field of the header, or the default priority `4` if the message has no
+    /// header
+    pub fn priority(&self) -> Priority {
+        self.header.as_ref().map(|h| h.priority).unwrap_or_default()
+    }
+
+    /// The `ttl` field of the header. There is no default ttl, so `None` is returned if the
+    /// message has no header or the header has no ttl
+    pub fn ttl(&self) -> Option<Milliseconds> {
+        self.header.as_ref().and_then(|h| h.ttl)
+    }
+
+    /// The `first-acquirer` field of the header, or `false` if the message has no header
+    pub fn first_acquirer(&self) -> bool {
+        self.header
+            .as_ref()
+            .map(|h| h.first_acquirer)
+            .unwrap_or(false)
+    }
+
+    /// The `delivery-count` field of the header, or `0` if the message has no header
+    pub fn delivery_count(&self) -> Uint {
+        self.header.as_ref().map(|h| h.delivery_count).unwrap_or(0)
+    }
 }
 
 // impl<T> Serialize for Message<T>
@@ -204,7 +299,10 @@ where
         if let Some(application_properties) = &self.application_properties {
             state.serialize_field("application_properties", application_properties)?
         }
-        state.serialize_field("body", &self.body)?;
+        // A message without any body section is sent with only the other sections
+        if !self.body.is_empty_body() {
+            state.serialize_field("body", &self.body)?;
+        }
         if let Some(footer) = &self.footer {
             state.serialize_field("footer", footer)?;
         }
@@ -615,7 +713,7 @@ mod tests {
             __private::{Deserializable, Serializable},
         },
         AmqpSequence, AmqpValue, ApplicationProperties, Batch, Data, DeliveryAnnotations, Footer,
-        Header, MessageAnnotations, Properties,
+        Header, MessageAnnotations, Priority, Properties,
     };
 
     use super::Message;
@@ -627,6 +725,55 @@ mod tests {
     //     println!("Message<()>: {:?}", std::mem::size_of::<Message<()>>());
     // }
 
+    #[test]
+    fn test_header_getters_apply_the_defaults() {
+        let message = Message::builder().value(1_i32).build();
+        assert!(!message.durable());
+        assert_eq!(message.priority(), Priority(4));
+        assert_eq!(message.ttl(), None);
+        assert!(!message.first_acquirer());
+        assert_eq!(message.delivery_count(), 0);
+
+        let message = Message::builder()
+            .header(
+                Header::builder()
+                    .durable(true)
+                    .priority(9)
+                    .ttl(1000)
+                    .delivery_count(2)
+                    .build(),
+            )
+            .value(1_i32)
+            .build();
+        assert!(message.durable());
+        assert_eq!(message.priority(), Priority(9));
+        assert_eq!(message.ttl(), Some(1000));
+        assert_eq!(message.delivery_count(), 2);
+    }
+
+    #[test]
+    fn test_edit_sections_in_place() {
+        let mut message = Message::builder().value(1_i32).build();
+        message.header_mut().durable = true;
+        message.properties_mut().subject = Some(String::from("relayed"));
+        message
+            .application_properties_mut()
+            .insert(String::from("hop"), 1_u32.into());
+        *message.body_mut() = AmqpValue(2);
+        assert!(message.delivery_annotations.is_none());
+        assert_eq!(message.sections(), 4);
+
+        let message = message
+            .try_map_body(|body| u8::try_from(body.0).map(AmqpValue))
+            .unwrap();
+        assert!(message.header.unwrap().durable);
+        assert_eq!(
+            message.properties.unwrap().subject.as_deref(),
+            Some("relayed")
+        );
+        assert_eq!(message.body, AmqpValue(2_u8));
+    }
+
     #[test]
     fn test_convert_data_into_message() {
         let data = Data(Binary::from("hello AMQP"));
@@ -796,6 +943,25 @@ mod tests {
         assert_eq!(buf, expected);
     }
 
+    #[test]
+    fn test_empty_body_round_trip() {
+        let message: Message<Body<Value>> = Message::builder()
+            .properties(Properties::builder().message_id(1u64).build())
+            .body(Body::Empty)
+            .build();
+        let buf = to_vec(&Serializable(message)).unwrap();
+        // Only the properties section is encoded
+        assert_eq!(&buf[..3], &[0x0, 0x53, 0x73]);
+        assert!(!buf.windows(3).any(|w| w == [0x0, 0x53, 0x77]));
+
+        let decoded: Deserializable<Message<Body<Value>>> = from_slice(&buf).unwrap();
+        assert!(decoded.0.properties.is_some());
+        assert!(decoded.0.body.is_empty());
+
+        let decoded: Deserializable<Message<Option<String>>> = from_slice(&buf).unwrap();
+        assert_eq!(decoded.0.body, None);
+    }
+
     #[test]
     fn test_decode_message_using_reader() {
         let buf = &[
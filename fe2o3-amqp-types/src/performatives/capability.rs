@@ -0,0 +1,238 @@
+//! Well-known connection, session and link capabilities
+
+use serde::{de, ser};
+use serde_amqp::primitives::{Array, Symbol};
+
+use super::{Attach, Begin, Open};
+
+/// Symbol of [`Capability::AnonymousRelay`]
+pub const ANONYMOUS_RELAY: &str = "ANONYMOUS-RELAY";
+
+/// Symbol of [`Capability::SharedSubscriptions`]
+pub const SHARED_SUBSCRIPTIONS: &str = "shared-subs";
+
+/// Symbol of [`Capability::DelayedDelivery`]
+pub const DELAYED_DELIVERY: &str = "DELAYED_DELIVERY";
+
+/// Symbol of [`Capability::SoleConnectionForContainer`]
+pub const SOLE_CONNECTION_FOR_CONTAINER: &str = "sole-connection-for-container";
+
+/// Symbol of [`Capability::LinkPairV1`]
+pub const LINK_PAIR_V1: &str = "LINK_PAIR_V1";
+
+/// Symbol of [`Capability::Queue`]
+pub const QUEUE: &str = "queue";
+
+/// Symbol of [`Capability::Topic`]
+pub const TOPIC: &str = "topic";
+
+/// Well-known capabilities that are exchanged in the `offered-capabilities` and
+/// `desired-capabilities` fields of [`Open`], [`Begin`] and [`Attach`] (and in the
+/// `capabilities` field of source and target)
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Hash)]
+pub enum Capability {
+    /// `"ANONYMOUS-RELAY"`
+    ///
+    /// The container supports links with a null target address that route each message by its
+    /// `to` property
+    AnonymousRelay,
+
+    /// `"shared-subs"`
+    ///
+    /// The container supports subscriptions that are shared by multiple receivers
+    SharedSubscriptions,
+
+    /// `"DELAYED_DELIVERY"`
+    ///
+    /// The container supports scheduling the delivery of a message to a later time
+    DelayedDelivery,
+
+    /// `"sole-connection-for-container"`
+    ///
+    /// Only one connection is allowed between the two containers
+    SoleConnectionForContainer,
+
+    /// `"LINK_PAIR_V1"`
+    ///
+    /// The container supports pairing a sender and a receiver into a full-duplex channel
+    LinkPairV1,
+
+    /// `"queue"`
+    ///
+    /// The node is a queue
+    Queue,
+
+    /// `"topic"`
+    ///
+    /// The node is a topic
+    Topic,
+}
+
+impl Capability {
+    /// The symbol of the capability
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::AnonymousRelay => ANONYMOUS_RELAY,
+            Capability::SharedSubscriptions => SHARED_SUBSCRIPTIONS,
+            Capability::DelayedDelivery => DELAYED_DELIVERY,
+            Capability::SoleConnectionForContainer => SOLE_CONNECTION_FOR_CONTAINER,
+            Capability::LinkPairV1 => LINK_PAIR_V1,
+            Capability::Queue => QUEUE,
+            Capability::Topic => TOPIC,
+        }
+    }
+
+    /// Whether the capability is found in a list of capabilities
+    pub fn is_in(&self, capabilities: Option<&Array<Symbol>>) -> bool {
+        contains_capability(capabilities, self.as_str())
+    }
+}
+
+impl AsRef<str> for Capability {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&Capability> for Symbol {
+    fn from(value: &Capability) -> Self {
+        Symbol::from(value.as_str())
+    }
+}
+
+impl From<Capability> for Symbol {
+    fn from(value: Capability) -> Self {
+        Symbol::from(&value)
+    }
+}
+
+impl TryFrom<Symbol> for Capability {
+    type Error = Symbol;
+
+    fn try_from(value: Symbol) -> Result<Self, Self::Error> {
+        match value.as_str().try_into() {
+            Ok(val) => Ok(val),
+            Err(_) => Err(value),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Capability {
+    type Error = &'a str;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        let val = match value {
+            ANONYMOUS_RELAY => Self::AnonymousRelay,
+            SHARED_SUBSCRIPTIONS => Self::SharedSubscriptions,
+            DELAYED_DELIVERY => Self::DelayedDelivery,
+            SOLE_CONNECTION_FOR_CONTAINER => Self::SoleConnectionForContainer,
+            LINK_PAIR_V1 => Self::LinkPairV1,
+            QUEUE => Self::Queue,
+            TOPIC => Self::Topic,
+            _ => return Err(value),
+        };
+
+        Ok(val)
+    }
+}
+
+impl ser::Serialize for Capability {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let val = Symbol::from(self);
+        val.serialize(serializer)
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Capability {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Symbol::deserialize(deserializer)?
+            .try_into()
+            .map_err(|_| de::Error::custom("Invalid symbol value for Capability"))
+    }
+}
+
+fn contains_capability(capabilities: Option<&Array<Symbol>>, capability: &str) -> bool {
+    capabilities
+        .map(|capabilities| capabilities.0.iter().any(|c| c.as_str() == capability))
+        .unwrap_or(false)
+}
+
+macro_rules! impl_capability_queries {
+    ($($performative:ty),*) => {
+        $(
+            impl $performative {
+                /// Whether the capability is found in the `offered-capabilities`
+                pub fn offers_capability(&self, capability: impl AsRef<str>) -> bool {
+                    contains_capability(self.offered_capabilities.as_ref(), capability.as_ref())
+                }
+
+                /// Whether the capability is found in the `desired-capabilities`
+                pub fn desires_capability(&self, capability: impl AsRef<str>) -> bool {
+                    contains_capability(self.desired_capabilities.as_ref(), capability.as_ref())
+                }
+            }
+        )*
+    };
+}
+
+impl_capability_queries!(Open, Begin, Attach);
+
+#[cfg(test)]
+mod tests {
+    use serde_amqp::{from_slice, primitives::Symbol, to_vec};
+
+    use crate::performatives::Open;
+
+    use super::{Capability, ANONYMOUS_RELAY};
+
+    #[test]
+    fn test_capability_symbol_round_trip() {
+        let capabilities = [
+            Capability::AnonymousRelay,
+            Capability::SharedSubscriptions,
+            Capability::DelayedDelivery,
+            Capability::SoleConnectionForContainer,
+            Capability::LinkPairV1,
+            Capability::Queue,
+            Capability::Topic,
+        ];
+        for capability in capabilities {
+            let symbol = Symbol::from(capability);
+            assert_eq!(Capability::try_from(symbol), Ok(capability));
+
+            let buf = to_vec(&capability).unwrap();
+            let decoded: Capability = from_slice(&buf).unwrap();
+            assert_eq!(decoded, capability);
+        }
+        assert!(Capability::try_from("unknown").is_err());
+    }
+
+    #[test]
+    fn test_query_offered_and_desired_capabilities() {
+        let open = Open {
+            container_id: "test".into(),
+            hostname: None,
+            max_frame_size: Default::default(),
+            channel_max: Default::default(),
+            idle_time_out: None,
+            outgoing_locales: None,
+            incoming_locales: None,
+            offered_capabilities: Some(vec![Symbol::from(ANONYMOUS_RELAY)].into()),
+            desired_capabilities: Some(vec![Symbol::from("DELAYED_DELIVERY")].into()),
+            properties: None,
+        };
+
+        assert!(open.offers_capability(Capability::AnonymousRelay));
+        assert!(open.offers_capability("ANONYMOUS-RELAY"));
+        assert!(!open.offers_capability(Capability::DelayedDelivery));
+        assert!(open.desires_capability(Capability::DelayedDelivery));
+        assert!(!open.desires_capability(Capability::SharedSubscriptions));
+        assert!(Capability::AnonymousRelay.is_in(open.offered_capabilities.as_ref()));
+    }
+}
@@ -2,6 +2,7 @@
 
 mod attach;
 mod begin;
+mod capability;
 mod close;
 mod detach;
 mod disposition;
@@ -12,6 +13,7 @@ mod transfer;
 
 pub use attach::*;
 pub use begin::*;
+pub use capability::*;
 pub use close::*;
 pub use detach::*;
 pub use disposition::*;
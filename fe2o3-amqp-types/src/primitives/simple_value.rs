@@ -1,5 +1,7 @@
 //! Simple values. A subset of the primitive types.
 
+use std::borrow::Cow;
+
 use super::*;
 
 /// A subset of `Value`
@@ -405,6 +407,54 @@ impl From<&str> for SimpleValue {
     }
 }
 
+impl From<&String> for SimpleValue {
+    fn from(val: &String) -> Self {
+        Self::String(val.clone())
+    }
+}
+
+impl From<Cow<'_, str>> for SimpleValue {
+    fn from(val: Cow<'_, str>) -> Self {
+        Self::String(val.into_owned())
+    }
+}
+
+impl From<Vec<u8>> for SimpleValue {
+    fn from(val: Vec<u8>) -> Self {
+        Self::Binary(ByteBuf::from(val))
+    }
+}
+
+impl From<&[u8]> for SimpleValue {
+    fn from(val: &[u8]) -> Self {
+        Self::Binary(ByteBuf::from(val))
+    }
+}
+
+/// `usize` is encoded as a `ulong`
+impl From<usize> for SimpleValue {
+    fn from(val: usize) -> Self {
+        Self::Ulong(val as u64)
+    }
+}
+
+/// `isize` is encoded as a `long`
+impl From<isize> for SimpleValue {
+    fn from(val: isize) -> Self {
+        Self::Long(val as i64)
+    }
+}
+
+/// `None` is encoded as `null`
+impl<T> From<Option<T>> for SimpleValue
+where
+    T: Into<SimpleValue>,
+{
+    fn from(val: Option<T>) -> Self {
+        val.map(Into::into).unwrap_or(Self::Null)
+    }
+}
+
 macro_rules! impl_try_from_for_simple_value_variant {
     ($variant:ident, $variant_ty:ty) => {
         impl TryFrom<SimpleValue> for $variant_ty {
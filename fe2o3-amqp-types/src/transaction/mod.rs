@@ -2,11 +2,9 @@
 
 mod txn_capability;
 mod txn_error;
+mod txn_id;
 
-use serde_amqp::{
-    primitives::{Array, Binary},
-    DeserializeComposite, SerializeComposite,
-};
+use serde_amqp::{primitives::Array, DeserializeComposite, SerializeComposite};
 
 /// 4.5.1 Coordinator
 /// Target for communicating with a transaction coordinator.
@@ -148,10 +146,8 @@ pub struct Discharge {
     pub fail: Option<bool>,
 }
 
-/// 4.5.4 Transaction ID
-/// <type name="transaction-id" class="restricted" source="binary" provides="txn-id"/>
-/// A transaction-id can be up to 32 octets of binary data.
-pub type TransactionId = Binary;
+// 4.5.4 Transaction ID
+pub use txn_id::{InvalidTransactionId, TransactionId};
 
 /// 4.5.5 Declared
 /// <type name="declared" class="composite" source="list" provides="delivery-state, outcome">
@@ -0,0 +1,168 @@
+//! 4.5.4 Transaction ID
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use serde_amqp::{primitives::Binary, Value};
+
+/// 4.5.4 Transaction ID
+///
+/// <type name="transaction-id" class="restricted" source="binary" provides="txn-id"/>
+///
+/// A transaction-id can be up to 32 octets of binary data.
+///
+/// The same id is carried by the `declared` outcome, the `discharge` body, the
+/// `transactional-state` of the deliveries and, for transactional acquisition, the `txn-id` entry
+/// of the link flow properties.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TransactionId(pub Binary);
+
+impl TransactionId {
+    /// Maximum number of octets in a transaction-id
+    pub const MAX_LEN: usize = 32;
+
+    /// Creates a transaction-id after checking that it is at most [`Self::MAX_LEN`] octets long
+    pub fn new(bytes: impl Into<Vec<u8>>) -> Result<Self, InvalidTransactionId> {
+        let bytes = bytes.into();
+        match bytes.len() {
+            len if len > Self::MAX_LEN => Err(InvalidTransactionId { len }),
+            _ => Ok(Self(Binary::from(bytes))),
+        }
+    }
+
+    /// Whether the transaction-id is at most [`Self::MAX_LEN`] octets long
+    pub fn is_valid(&self) -> bool {
+        self.0.len() <= Self::MAX_LEN
+    }
+
+    /// Returns the octets of the transaction-id
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Consumes the transaction-id and returns the inner binary
+    pub fn into_inner(self) -> Binary {
+        self.0
+    }
+}
+
+impl AsRef<[u8]> for TransactionId {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Binary> for TransactionId {
+    fn from(value: Binary) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Vec<u8>> for TransactionId {
+    fn from(value: Vec<u8>) -> Self {
+        Self(Binary::from(value))
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for TransactionId {
+    fn from(value: [u8; N]) -> Self {
+        Self(Binary::from(value.to_vec()))
+    }
+}
+
+impl From<&[u8]> for TransactionId {
+    fn from(value: &[u8]) -> Self {
+        Self(Binary::from(value))
+    }
+}
+
+impl From<TransactionId> for Binary {
+    fn from(value: TransactionId) -> Self {
+        value.0
+    }
+}
+
+impl From<TransactionId> for Value {
+    fn from(value: TransactionId) -> Self {
+        Value::Binary(value.0)
+    }
+}
+
+impl TryFrom<Value> for TransactionId {
+    type Error = Value;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        if let Value::Binary(buf) = value {
+            Ok(TransactionId(buf))
+        } else {
+            Err(value)
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a Value> for TransactionId {
+    type Error = &'a Value;
+
+    fn try_from(value: &'a Value) -> Result<Self, Self::Error> {
+        if let Value::Binary(buf) = value {
+            Ok(TransactionId(buf.clone()))
+        } else {
+            Err(value)
+        }
+    }
+}
+
+/// Formats the transaction-id as lower case hex
+impl Display for TransactionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+/// A transaction-id that is longer than [`TransactionId::MAX_LEN`] octets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidTransactionId {
+    /// Length of the rejected transaction-id
+    pub len: usize,
+}
+
+impl Display for InvalidTransactionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "A transaction-id can be up to {} octets, found {}",
+            TransactionId::MAX_LEN,
+            self.len
+        )
+    }
+}
+
+impl std::error::Error for InvalidTransactionId {}
+
+#[cfg(test)]
+mod tests {
+    use serde_amqp::{from_slice, to_vec, Value};
+
+    use super::TransactionId;
+
+    #[test]
+    fn transaction_id_is_encoded_as_binary() {
+        let txn_id = TransactionId::from(vec![0x01, 0xab]);
+        let buf = to_vec(&txn_id).unwrap();
+        assert_eq!(buf, vec![0xa0, 0x02, 0x01, 0xab]);
+        let decoded: TransactionId = from_slice(&buf).unwrap();
+        assert_eq!(decoded, txn_id);
+
+        let value = Value::from(txn_id.clone());
+        assert_eq!(TransactionId::try_from(&value), Ok(txn_id.clone()));
+        assert_eq!(txn_id.to_string(), "01ab");
+    }
+
+    #[test]
+    fn transaction_id_longer_than_32_octets_is_invalid() {
+        assert!(TransactionId::new([0u8; 32]).is_ok());
+        let err = TransactionId::new([0u8; 33]).unwrap_err();
+        assert_eq!(err.len, 33);
+        assert!(!TransactionId::from([0u8; 33]).is_valid());
+    }
+}
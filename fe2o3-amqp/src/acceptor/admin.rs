@@ -0,0 +1,166 @@
+//! Administrative control over the sessions and links of an accepted connection
+
+use std::sync::Arc;
+
+use fe2o3_amqp_types::definitions;
+use parking_lot::Mutex;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{control::SessionControl, session::LinkInfo};
+
+use super::{error::AdminError, ListenerConnectionHandle};
+
+/// A snapshot of a session that is accepted on a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// The local channel of the session
+    pub channel: u16,
+
+    /// The channel that the remote peer uses for the session
+    pub remote_channel: u16,
+
+    /// The links that are attached on the session
+    pub links: Vec<LinkInfo>,
+}
+
+#[derive(Debug)]
+struct RegisteredSession {
+    channel: u16,
+    remote_channel: u16,
+    control: mpsc::Sender<SessionControl>,
+}
+
+/// Sessions that are accepted on a connection. Sessions whose event loop has stopped are removed
+/// lazily
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SessionRegistry {
+    sessions: Arc<Mutex<Vec<RegisteredSession>>>,
+}
+
+impl SessionRegistry {
+    pub(crate) fn register(
+        &self,
+        channel: u16,
+        remote_channel: u16,
+        control: mpsc::Sender<SessionControl>,
+    ) {
+        let mut sessions = self.sessions.lock();
+        sessions.retain(|session| !session.control.is_closed());
+        sessions.push(RegisteredSession {
+            channel,
+            remote_channel,
+            control,
+        });
+    }
+
+    /// The number of sessions whose event loop is running
+    pub(crate) fn len(&self) -> usize {
+        let mut sessions = self.sessions.lock();
+        sessions.retain(|session| !session.control.is_closed());
+        sessions.len()
+    }
+
+    fn open_sessions(&self) -> Vec<(u16, u16, mpsc::Sender<SessionControl>)> {
+        let mut sessions = self.sessions.lock();
+        sessions.retain(|session| !session.control.is_closed());
+        sessions
+            .iter()
+            .map(|session| {
+                (
+                    session.channel,
+                    session.remote_channel,
+                    session.control.clone(),
+                )
+            })
+            .collect()
+    }
+
+    /// The number of unsettled deliveries on the links of the open sessions
+    pub(crate) async fn unsettled(&self) -> usize {
+        let mut unsettled = 0;
+        for (_, _, control) in self.open_sessions() {
+            let (resp, result) = oneshot::channel();
+            if control.send(SessionControl::GetLinks(resp)).await.is_err() {
+                continue;
+            }
+            if let Ok(links) = result.await {
+                unsettled += links.iter().map(|link| link.unsettled).sum::<usize>();
+            }
+        }
+        unsettled
+    }
+
+    fn control(&self, channel: u16) -> Result<mpsc::Sender<SessionControl>, AdminError> {
+        self.open_sessions()
+            .into_iter()
+            .find(|(local, _, _)| *local == channel)
+            .map(|(_, _, control)| control)
+            .ok_or(AdminError::SessionNotFound)
+    }
+}
+
+impl ListenerConnectionHandle {
+    /// Get a snapshot of the open sessions that are accepted on this connection and the links
+    /// attached on them
+    ///
+    /// This can be called from outside the tasks that handle the sessions and links.
+    pub async fn clients(&self) -> impl Iterator<Item = SessionInfo> {
+        let mut clients = Vec::new();
+        for (channel, remote_channel, control) in self.sessions.open_sessions() {
+            let (resp, result) = oneshot::channel();
+            if control.send(SessionControl::GetLinks(resp)).await.is_err() {
+                continue;
+            }
+            if let Ok(links) = result.await {
+                clients.push(SessionInfo {
+                    channel,
+                    remote_channel,
+                    links,
+                });
+            }
+        }
+        clients.into_iter()
+    }
+
+    /// Ends the session on the local `channel` with an error
+    ///
+    /// The links attached on the session fail with a session error the next time they are used.
+    pub async fn kick_session(
+        &self,
+        channel: u16,
+        error: definitions::Error,
+    ) -> Result<(), AdminError> {
+        self.sessions
+            .control(channel)?
+            .send(SessionControl::End(Some(error)))
+            .await
+            .map_err(|_| AdminError::SessionNotFound)
+    }
+
+    /// Closes the link named `link_name` on the session on the local `channel` with an error
+    ///
+    /// A Detach carrying the error is sent to the remote peer right away. The local link endpoint
+    /// sees the link as closed by the remote peer the next time it is used.
+    pub async fn kick_link(
+        &self,
+        channel: u16,
+        link_name: impl Into<String>,
+        error: definitions::Error,
+    ) -> Result<(), AdminError> {
+        let (responder, result) = oneshot::channel();
+        self.sessions
+            .control(channel)?
+            .send(SessionControl::KickLink {
+                link_name: link_name.into(),
+                error,
+                responder,
+            })
+            .await
+            .map_err(|_| AdminError::SessionNotFound)?;
+        match result.await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(AdminError::LinkNotFound),
+            Err(_) => Err(AdminError::SessionNotFound),
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Authorization of the incoming links and deliveries of a listener
+//!
+//! An [`Authorizer`] is set on a [`LinkAcceptor`](super::LinkAcceptor) with
+//! [`Builder::authorizer()`](super::builder::Builder::authorizer). It is consulted on every
+//! incoming Attach with the address, the direction of the link and the identity that the remote
+//! peer authenticated with during the SASL negotiation. A denied link is attached and then
+//! immediately closed with the error of the denial, and accepting it fails with
+//! [`AcceptorAttachError::LocalSender`](super::error::AcceptorAttachError::LocalSender) or
+//! [`AcceptorAttachError::LocalReceiver`](super::error::AcceptorAttachError::LocalReceiver)
+//! holding an `Unauthorized` error.
+//!
+//! The authorizer is also consulted on every delivery that is received on a link that it allowed.
+//! A denied delivery is rejected with the error of the denial and is not returned by
+//! [`Receiver::recv()`](crate::link::Receiver::recv).
+//!
+//! # Example
+//!
+//! ```rust
+//! use fe2o3_amqp::acceptor::{AttachRequest, Authorization, Direction, LinkAcceptor};
+//!
+//! let link_acceptor = LinkAcceptor::builder()
+//!     .authorizer(|request: &AttachRequest<'_>| {
+//!         match (request.identity, request.direction) {
+//!             (Some("admin"), _) => Authorization::Allow,
+//!             (Some(_), Direction::Receive) => Authorization::Allow,
+//!             _ => Authorization::deny("Only the admin may send messages"),
+//!         }
+//!     })
+//!     .build();
+//! ```
+
+use std::sync::Arc;
+
+use fe2o3_amqp_types::{
+    definitions::{self, AmqpError, Role},
+    messaging::{ApplicationProperties, Properties, TargetArchetype},
+    performatives::Attach,
+};
+
+/// Direction of a link from the perspective of the remote peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The remote peer sends messages to the address
+    Send,
+
+    /// The remote peer receives messages from the address
+    Receive,
+}
+
+/// An incoming Attach to authorize
+#[derive(Debug, Clone, Copy)]
+pub struct AttachRequest<'a> {
+    /// The identity that the remote peer authenticated with, or `None` if the connection is not
+    /// authenticated or the SASL mechanism does not establish an identity
+    pub identity: Option<&'a str>,
+
+    /// Name of the link
+    pub link_name: &'a str,
+
+    /// Address of the target if the remote peer sends messages, or of the source if it receives
+    /// messages
+    pub address: Option<&'a str>,
+
+    /// Direction of the link
+    pub direction: Direction,
+}
+
+/// A delivery received from the remote peer to authorize
+#[derive(Debug, Clone, Copy)]
+pub struct TransferRequest<'a> {
+    /// The identity that the remote peer authenticated with
+    pub identity: Option<&'a str>,
+
+    /// Name of the link
+    pub link_name: &'a str,
+
+    /// Address of the target of the link
+    pub address: Option<&'a str>,
+
+    /// Properties of the message
+    pub properties: Option<&'a Properties>,
+
+    /// Application properties of the message
+    pub application_properties: Option<&'a ApplicationProperties>,
+}
+
+/// Decision of an [`Authorizer`]
+#[derive(Debug, Clone)]
+pub enum Authorization {
+    /// The operation is allowed
+    Allow,
+
+    /// The operation is denied and the error is sent to the remote peer
+    Deny(definitions::Error),
+}
+
+impl Authorization {
+    /// Denies the operation with an `amqp:unauthorized-access` error
+    pub fn deny(description: impl Into<String>) -> Self {
+        Self::Deny(definitions::Error::new(
+            AmqpError::UnauthorizedAccess,
+            description.into(),
+            None,
+        ))
+    }
+}
+
+/// Decides whether the remote peer may attach a link or send a delivery
+pub trait Authorizer: Send + Sync {
+    /// Decides whether the link may be attached
+    fn authorize_attach(&self, request: &AttachRequest<'_>) -> Authorization;
+
+    /// Decides whether a delivery that is received on an authorized link is accepted. All
+    /// deliveries are allowed by default
+    fn authorize_transfer(&self, _request: &TransferRequest<'_>) -> Authorization {
+        Authorization::Allow
+    }
+}
+
+impl<F> Authorizer for F
+where
+    F: Fn(&AttachRequest<'_>) -> Authorization + Send + Sync,
+{
+    fn authorize_attach(&self, request: &AttachRequest<'_>) -> Authorization {
+        (self)(request)
+    }
+}
+
+/// An [`Authorizer`] that is shared by the clones of a [`LinkAcceptor`](super::LinkAcceptor)
+#[derive(Clone)]
+pub struct SharedAuthorizer(Arc<dyn Authorizer>);
+
+impl std::fmt::Debug for SharedAuthorizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedAuthorizer").finish()
+    }
+}
+
+impl SharedAuthorizer {
+    /// Wraps the authorizer
+    pub fn new(authorizer: impl Authorizer + 'static) -> Self {
+        Self(Arc::new(authorizer))
+    }
+
+    /// Authorizes an incoming Attach. The error to close the link with is returned if the link is
+    /// denied
+    pub(crate) fn authorize_attach(
+        &self,
+        remote_attach: &Attach,
+        identity: Option<&str>,
+    ) -> Option<definitions::Error> {
+        // The role of the remote peer
+        let (direction, address) = match remote_attach.role {
+            Role::Sender => (Direction::Send, target_address(remote_attach)),
+            Role::Receiver => (Direction::Receive, source_address(remote_attach)),
+        };
+        let request = AttachRequest {
+            identity,
+            link_name: &remote_attach.name,
+            address,
+            direction,
+        };
+        match self.0.authorize_attach(&request) {
+            Authorization::Allow => None,
+            Authorization::Deny(error) => Some(error),
+        }
+    }
+
+    /// The authorization of the deliveries on a link whose remote peer is the sender
+    pub(crate) fn for_transfers(
+        &self,
+        remote_attach: &Attach,
+        identity: Option<&str>,
+    ) -> TransferAuthorization {
+        TransferAuthorization {
+            authorizer: self.clone(),
+            identity: identity.map(Into::into),
+            link_name: remote_attach.name.clone(),
+            address: target_address(remote_attach).map(Into::into),
+        }
+    }
+}
+
+fn source_address(attach: &Attach) -> Option<&str> {
+    attach.source.as_ref()?.address.as_deref()
+}
+
+fn target_address(attach: &Attach) -> Option<&str> {
+    match attach.target.as_deref()? {
+        TargetArchetype::Target(target) => target.address.as_deref(),
+        #[cfg(feature = "transaction")]
+        TargetArchetype::Coordinator(_) => None,
+    }
+}
+
+/// Authorizes the deliveries received on a link
+#[derive(Debug)]
+pub(crate) struct TransferAuthorization {
+    authorizer: SharedAuthorizer,
+    identity: Option<String>,
+    link_name: String,
+    address: Option<String>,
+}
+
+impl TransferAuthorization {
+    /// Returns the error to reject the delivery with if it is denied
+    pub fn authorize(
+        &self,
+        properties: Option<&Properties>,
+        application_properties: Option<&ApplicationProperties>,
+    ) -> Option<definitions::Error> {
+        let request = TransferRequest {
+            identity: self.identity.as_deref(),
+            link_name: &self.link_name,
+            address: self.address.as_deref(),
+            properties,
+            application_properties,
+        };
+        match self.authorizer.0.authorize_transfer(&request) {
+            Authorization::Allow => None,
+            Authorization::Deny(error) => Some(error),
+        }
+    }
+}
@@ -1,6 +1,6 @@
 //! Builder for acceptors
 
-use std::marker::PhantomData;
+use std::{marker::PhantomData, sync::Arc};
 
 use fe2o3_amqp_types::{
     definitions::{
@@ -8,19 +8,36 @@ use fe2o3_amqp_types::{
         SequenceNo, TransferNumber, MIN_MAX_FRAME_SIZE,
     },
     messaging::{Source, Target},
-    performatives::{ChannelMax, MaxFrameSize, Open},
-    primitives::{Array, Symbol, Ulong},
+    performatives::{Capability, ChannelMax, MaxFrameSize, Open},
+    primitives::{Array, Symbol, Ulong, Value},
 };
 
 use crate::{
-    connection::{DEFAULT_CHANNEL_MAX, DEFAULT_MAX_FRAME_SIZE, DEFAULT_OUTGOING_BUFFER_SIZE},
+    clock::{default_clock, Clock},
+    connection::sole_connection::{
+        SoleConnectionEnforcementPolicy, SOLE_CONNECTION_ENFORCEMENT_POLICY,
+    },
+    connection::{
+        QosClass, QosWeights, WriteBatching, DEFAULT_CHANNEL_MAX, DEFAULT_MAX_FRAME_SIZE,
+        DEFAULT_OUTGOING_BUFFER_SIZE,
+    },
+    session::{FairDispatch, FlowCoalescing, SharedTransferMiddleware, TransferMiddleware},
     util::{Initialized, Uninitialized},
+    watchdog::Watchdog,
 };
 
 use super::{
-    link::LinkAcceptor, local_receiver_link::LocalReceiverLinkAcceptor,
-    local_sender_link::LocalSenderLinkAcceptor, session::SessionAcceptor, ConnectionAcceptor,
-    SaslAcceptor, SupportedReceiverSettleModes, SupportedSenderSettleModes,
+    authorization::{Authorizer, SharedAuthorizer},
+    drain::ListenerDrain,
+    keep_alive::KeepAlivePolicy,
+    limits::SharedLimits,
+    link::{LinkAcceptor, LinkNameCollision},
+    local_receiver_link::LocalReceiverLinkAcceptor,
+    local_sender_link::LocalSenderLinkAcceptor,
+    session::SessionAcceptor,
+    settle_mode::SettleModePolicy,
+    ConnectionAcceptor, SaslAcceptor, SoleConnectionEnforcement, SupportedReceiverSettleModes,
+    SupportedSenderSettleModes,
 };
 
 cfg_transaction! {
@@ -74,6 +91,14 @@ impl Builder<ConnectionAcceptor<(), ()>, Uninitialized> {
             tls_acceptor: (),
             sasl_acceptor: (),
             buffer_size: DEFAULT_OUTGOING_BUFFER_SIZE,
+            sole_connection_enforcement: None,
+            keep_alive: None,
+            write_batching: WriteBatching::default(),
+            clock: default_clock(),
+            watchdog: None,
+            qos_weights: QosWeights::default(),
+            limits: None,
+            drain: None,
         };
 
         Self {
@@ -215,6 +240,14 @@ impl<M, Tls, Sasl> Builder<ConnectionAcceptor<Tls, Sasl>, M> {
             tls_acceptor,
             sasl_acceptor: self.inner.sasl_acceptor,
             buffer_size: self.inner.buffer_size,
+            sole_connection_enforcement: self.inner.sole_connection_enforcement,
+            keep_alive: self.inner.keep_alive,
+            write_batching: self.inner.write_batching,
+            clock: self.inner.clock,
+            watchdog: self.inner.watchdog,
+            qos_weights: self.inner.qos_weights,
+            limits: self.inner.limits,
+            drain: self.inner.drain,
         };
         Builder {
             inner,
@@ -232,6 +265,14 @@ impl<M, Tls, Sasl> Builder<ConnectionAcceptor<Tls, Sasl>, M> {
             tls_acceptor: self.inner.tls_acceptor,
             sasl_acceptor,
             buffer_size: self.inner.buffer_size,
+            sole_connection_enforcement: self.inner.sole_connection_enforcement,
+            keep_alive: self.inner.keep_alive,
+            write_batching: self.inner.write_batching,
+            clock: self.inner.clock,
+            watchdog: self.inner.watchdog,
+            qos_weights: self.inner.qos_weights,
+            limits: self.inner.limits,
+            drain: self.inner.drain,
         };
         Builder {
             inner,
@@ -244,6 +285,88 @@ impl<M, Tls, Sasl> Builder<ConnectionAcceptor<Tls, Sasl>, M> {
         self.inner.buffer_size = buffer_size;
         self
     }
+
+    /// Enforce the `sole-connection-for-container` capability with the policy. This offers the
+    /// capability and advertises the policy in the connection properties.
+    ///
+    /// Use [`sole_connection_enforcement_with`](Self::sole_connection_enforcement_with) to share
+    /// the enforcement among multiple acceptors
+    pub fn sole_connection_enforcement(self, policy: SoleConnectionEnforcementPolicy) -> Self {
+        self.sole_connection_enforcement_with(SoleConnectionEnforcement::new(policy))
+    }
+
+    /// Enforce the `sole-connection-for-container` capability with a (possibly shared)
+    /// [`SoleConnectionEnforcement`]. This offers the capability and advertises the policy in the
+    /// connection properties.
+    pub fn sole_connection_enforcement_with(
+        mut self,
+        enforcement: SoleConnectionEnforcement,
+    ) -> Self {
+        let local_open = &mut self.inner.local_open;
+        if !local_open.offers_capability(Capability::SoleConnectionForContainer) {
+            let capability = Symbol::from(Capability::SoleConnectionForContainer);
+            match &mut local_open.offered_capabilities {
+                Some(capabilities) => capabilities.0.push(capability),
+                None => local_open.offered_capabilities = Some(vec![capability].into()),
+            }
+        }
+        local_open
+            .properties
+            .get_or_insert_with(Fields::new)
+            .insert(
+                Symbol::from(SOLE_CONNECTION_ENFORCEMENT_POLICY),
+                Value::Uint(enforcement.policy().into()),
+            );
+        self.inner.sole_connection_enforcement = Some(enforcement);
+        self
+    }
+
+    /// Requests the clients to keep the connections alive and closes the connections that are
+    /// silent for longer than the policy allows. This overrides the `idle_time_out`
+    pub fn keep_alive(mut self, policy: KeepAlivePolicy) -> Self {
+        self.inner.local_open.idle_time_out = Some(policy.advertised_idle_time_out());
+        self.inner.keep_alive = Some(policy);
+        self
+    }
+
+    /// Batching of the outgoing frames of the accepted connections
+    pub fn write_batching(mut self, write_batching: WriteBatching) -> Self {
+        self.inner.write_batching = write_batching;
+        self
+    }
+
+    /// Clock that drives the idle timeout and the heartbeat of the accepted connections
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.inner.clock = Arc::new(clock);
+        self
+    }
+
+    /// Watchdog that catches the panics in the engines of the accepted connections and their
+    /// sessions
+    pub fn watchdog(mut self, watchdog: Watchdog) -> Self {
+        self.inner.watchdog = Some(watchdog);
+        self
+    }
+
+    /// Weights of the quality-of-service classes of the sessions on the accepted connections
+    pub fn qos_weights(mut self, qos_weights: QosWeights) -> Self {
+        self.inner.qos_weights = qos_weights;
+        self
+    }
+
+    /// Resource limits of the accepted connections and their sessions. The limits can be updated
+    /// at runtime through a clone of the [`SharedLimits`]
+    pub fn limits(mut self, limits: SharedLimits) -> Self {
+        self.inner.limits = Some(limits);
+        self
+    }
+
+    /// Tracks the accepted connections so that they can be drained through a clone of the
+    /// [`ListenerDrain`]. Connections are refused once the drain has started
+    pub fn drain(mut self, drain: ListenerDrain) -> Self {
+        self.inner.drain = Some(drain);
+        self
+    }
 }
 
 // =============================================================================
@@ -334,6 +457,36 @@ impl Builder<SessionAcceptor, Initialized> {
         self
     }
 
+    /// Merge outgoing link flow updates within a small time window into fewer flow frames.
+    /// Flow coalescing is disabled by default.
+    pub fn flow_coalescing(
+        mut self,
+        flow_coalescing: impl Into<Option<FlowCoalescing>>,
+    ) -> Self {
+        self.inner.0.flow_coalescing = flow_coalescing.into();
+        self
+    }
+
+    /// Queue incoming transfers in per-link inbound queues and dispatch them fairly among the
+    /// links. Fair dispatch is disabled by default.
+    pub fn fair_dispatch(mut self, fair_dispatch: impl Into<Option<FairDispatch>>) -> Self {
+        self.inner.0.fair_dispatch = fair_dispatch.into();
+        self
+    }
+
+    /// Quality-of-service class of the accepted sessions. Defaults to [`QosClass::Interactive`]
+    pub fn qos_class(mut self, qos_class: QosClass) -> Self {
+        self.inner.0.qos_class = qos_class;
+        self
+    }
+
+    /// Transform the payloads of all incoming and outgoing transfers on the session with the
+    /// middleware. See [`TransferMiddleware`] for details.
+    pub fn transfer_middleware(mut self, middleware: impl TransferMiddleware + 'static) -> Self {
+        self.inner.0.transfer_middleware = Some(SharedTransferMiddleware(Arc::new(middleware)));
+        self
+    }
+
     cfg_transaction! {
         /// Enable handling remotely initiated control link and transaction by setting the
         /// `control_link_acceptor` field
@@ -409,6 +562,13 @@ where
         self
     }
 
+    /// Negotiates the combination of settle modes with the given policy instead of the supported
+    /// and fallback modes of the sender and the receiver
+    pub fn settle_mode_policy(mut self, policy: impl Into<Option<SettleModePolicy>>) -> Self {
+        self.inner.shared.settle_mode_policy = policy.into();
+        self
+    }
+
     /// This MUST NOT be null if role is sender,
     /// and it is ignored if the role is receiver.
     /// See subsection 2.6.7.
@@ -459,6 +619,18 @@ where
         self
     }
 
+    /// How an incoming Attach with a link name that is already in use on the session is handled
+    pub fn link_name_collision(mut self, policy: LinkNameCollision) -> Self {
+        self.inner.shared.link_name_collision = policy;
+        self
+    }
+
+    /// Authorizes the incoming links and the deliveries received on them
+    pub fn authorizer(mut self, authorizer: impl Authorizer + 'static) -> Self {
+        self.inner.shared.authorizer = Some(SharedAuthorizer::new(authorizer));
+        self
+    }
+
     /// Set the target capabilities field
     pub fn target_capabilities(
         mut self,
@@ -4,8 +4,8 @@ use std::{io, marker::PhantomData, time::Duration};
 
 
 use fe2o3_amqp_types::{
-    definitions::{self},
-    performatives::{Begin, Close, End, Open},
+    definitions::{self, MIN_MAX_FRAME_SIZE},
+    performatives::{Begin, ChannelMax, Close, End, Open},
     sasl::{SaslCode, SaslOutcome},
     states::ConnectionState,
 };
@@ -18,22 +18,32 @@ use tokio_util::codec::{FramedRead, FramedWrite};
 
 use crate::{
     acceptor::sasl_acceptor::SaslServerFrame,
+    clock::SharedClock,
     connection::{
-        self, engine::ConnectionEngine, ConnectionHandle, OpenError, DEFAULT_CONTROL_CHAN_BUF,
+        self,
+        engine::{recv_remote_close, recv_remote_open, ConnectionEngine},
+        sole_connection, ConnectionHandle, OpenError, QosWeights, WriteBatching,
+        DEFAULT_CONTROL_CHAN_BUF,
     },
     endpoint::{self, IncomingChannel, OutgoingChannel},
     frames::{
-        amqp::{self, Frame},
+        amqp::{self, Frame, FrameBody},
         sasl,
     },
+    sasl_profile::SharedCredentials,
     session::frame::{SessionFrame, SessionFrameBody},
     transport::{protocol_header::ProtocolHeaderCodec, Transport},
     util::{Initialized, Uninitialized},
+    watchdog::Watchdog,
 };
 
 use super::{
     builder::Builder,
+    drain::{draining_error, ListenerDrain},
+    keep_alive::KeepAlivePolicy,
+    limits::{limit_exceeded_error, SharedLimits},
     sasl_acceptor::{SaslAcceptor, SaslAcceptorExt},
+    sole_connection::{Admission, SoleConnectionEnforcement},
     IncomingSession,
 };
 
@@ -45,6 +55,12 @@ impl ListenerConnectionHandle {
     pub async fn next_incoming_session(&mut self) -> Option<IncomingSession> {
         self.session_listener.recv().await
     }
+
+    /// The identity that the remote peer authenticated with during the SASL negotiation, or
+    /// `None` if the SASL mechanism does not establish an identity
+    pub fn authenticated_identity(&self) -> Option<&str> {
+        self.authenticated_identity.as_deref()
+    }
 }
 
 /// Acceptor for an incoming connection
@@ -76,6 +92,14 @@ impl ListenerConnectionHandle {
 /// |`offered_capabilities`| `None` |
 /// |`desired_capabilities`| `None` |
 /// |`Properties`| `None` |
+/// |`sole_connection_enforcement`| `None` |
+/// |`keep_alive`| `None` |
+/// |`write_batching`| [`WriteBatching::default()`] |
+/// |`clock`| [`TokioClock`](crate::clock::TokioClock) |
+/// |`watchdog`| `None` |
+/// |`qos_weights`| [`QosWeights::default()`] |
+/// |`limits`| `None` |
+/// |`drain`| `None` |
 ///
 /// # Customize configuration
 ///
@@ -149,6 +173,32 @@ pub struct ConnectionAcceptor<Tls, Sasl> {
 
     /// Buffer size for the underlying channel
     pub buffer_size: usize,
+
+    /// Enforcement of the `sole-connection-for-container` capability. The capability is not
+    /// enforced if this is `None`
+    pub sole_connection_enforcement: Option<SoleConnectionEnforcement>,
+
+    /// Keep-alive enforcement of the accepted connections. If this is `None`, the connections are
+    /// closed once they are silent for the `idle_time_out` in the `local_open`
+    pub keep_alive: Option<KeepAlivePolicy>,
+
+    /// Batching of the outgoing frames of the accepted connections
+    pub write_batching: WriteBatching,
+
+    /// Clock that drives the idle timeout and the heartbeat of the accepted connections
+    pub clock: SharedClock,
+
+    /// Watchdog of the engines of the accepted connections and their sessions
+    pub watchdog: Option<Watchdog>,
+
+    /// Weights of the quality-of-service classes of the sessions on the accepted connections
+    pub qos_weights: QosWeights,
+
+    /// Resource limits of the accepted connections and their sessions
+    pub limits: Option<SharedLimits>,
+
+    /// Drain of the listener that tracks the accepted connections
+    pub drain: Option<ListenerDrain>,
 }
 
 impl ConnectionAcceptor<(), ()> {
@@ -173,7 +223,7 @@ impl<Tls, Sasl> ConnectionAcceptor<Tls, Sasl> {
         }
     }
 
-    async fn negotiate_amqp_with_framed<Io>(
+    pub(crate) async fn negotiate_amqp_with_framed<Io>(
         &self,
         framed_write: FramedWrite<WriteHalf<Io>, ProtocolHeaderCodec>,
         framed_read: FramedRead<ReadHalf<Io>, ProtocolHeaderCodec>,
@@ -182,43 +232,191 @@ impl<Tls, Sasl> ConnectionAcceptor<Tls, Sasl> {
         Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
     {
         let mut local_state = ConnectionState::Start;
-        let idle_timeout = self
-            .local_open
-            .idle_time_out
-            .map(|millis| Duration::from_millis(millis as u64));
         let transport = Transport::negotiate_amqp_header(
             framed_write,
             framed_read,
             &mut local_state,
-            idle_timeout,
+            self.local_idle_timeout(),
         )
         .await?;
 
+        self.open_with_transport(transport, local_state, None).await
+    }
+
+    pub(crate) fn local_idle_timeout(&self) -> Option<Duration> {
+        match &self.keep_alive {
+            Some(policy) => Some(policy.threshold()),
+            None => self
+                .local_open
+                .idle_time_out
+                .map(|millis| Duration::from_millis(millis as u64)),
+        }
+    }
+
+    /// Accepts a connection over a stream whose protocol header exchange has already been
+    /// performed by an outer layer, eg. a TLS or WebSocket terminator or a proxy protocol handler
+    /// of an existing server framework. The outer layer is also responsible for the SASL
+    /// exchange, if any, so the TLS and SASL configurations of the acceptor are not used.
+    ///
+    /// The stream must be positioned right after the AMQP protocol header of the remote peer. If
+    /// the outer layer has already read the remote Open (eg. to route the connection by its
+    /// `hostname`), it is passed as `remote_open`. Otherwise the remote Open is read from the
+    /// stream.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// // The outer layer has exchanged the AMQP protocol headers
+    /// let connection = acceptor.accept_negotiated(stream, None).await.unwrap();
+    /// ```
+    pub async fn accept_negotiated<Io>(
+        &self,
+        stream: Io,
+        remote_open: Option<Open>,
+    ) -> Result<ListenerConnectionHandle, OpenError>
+    where
+        Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+    {
+        let transport = Transport::bind(stream, MIN_MAX_FRAME_SIZE, self.local_idle_timeout());
+        // The Open is always sent on channel 0
+        let remote_open = remote_open.map(|open| (IncomingChannel(0), open));
+        self.open_with_transport(transport, ConnectionState::HeaderExchange, remote_open)
+            .await
+    }
+
+    /// Opens the connection over a transport that has finished the AMQP header exchange. If
+    /// `remote_open` is `Some(_)`, the remote Open has already been received
+    pub(crate) async fn open_with_transport<Io>(
+        &self,
+        transport: Transport<Io, amqp::Frame>,
+        local_state: ConnectionState,
+        remote_open: Option<(IncomingChannel, Open)>,
+    ) -> Result<ListenerConnectionHandle, OpenError>
+    where
+        Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+    {
         let (control_tx, control_rx) = mpsc::channel(DEFAULT_CONTROL_CHAN_BUF);
-        let (outgoing_tx, outgoing_rx) = mpsc::channel(self.buffer_size);
+        let (outgoing_tx, outgoing_rx) =
+            connection::session_frame_channel(self.buffer_size, self.qos_weights);
         let (begin_tx, begin_rx) = mpsc::channel(self.buffer_size);
 
-        let connection = connection::Connection::new(local_state, self.local_open.clone());
+        let mut transport = transport;
+        transport.set_clock(self.clock.clone());
+        if self.drain.as_ref().is_some_and(|drain| drain.is_draining()) {
+            return Err(self
+                .refuse_connection(transport, draining_error(), OpenError::ListenerDraining)
+                .await);
+        }
+        let limits = match &self.limits {
+            Some(limits) => match limits.admit() {
+                Some(limits) => Some(limits),
+                None => {
+                    let error =
+                        limit_exceeded_error("The maximum number of connections is reached");
+                    return Err(self
+                        .refuse_connection(transport, error, OpenError::ConnectionLimitReached)
+                        .await);
+                }
+            },
+            None => None,
+        };
+        let remote_open = match &self.sole_connection_enforcement {
+            Some(enforcement) => {
+                // The container id is only known after the remote Open is received
+                let (channel, remote_open) = match remote_open {
+                    Some(remote_open) => remote_open,
+                    None => recv_remote_open(&mut transport).await?,
+                };
+                let desired = sole_connection::is_desired(&remote_open);
+                match enforcement.admit(&remote_open.container_id, desired, &control_tx) {
+                    Admission::Accept => Some((channel, remote_open)),
+                    Admission::Refuse => {
+                        let error = sole_connection::refuse_connection_error();
+                        return Err(self
+                            .refuse_connection(transport, error, OpenError::ContainerIdInUse)
+                            .await);
+                    }
+                }
+            }
+            None => remote_open,
+        };
+
+        let mut local_open = self.local_open.clone();
+        if let Some(limits) = &limits {
+            let channel_max = limits.current().cap_channel_max(local_open.channel_max.0);
+            local_open.channel_max = ChannelMax(channel_max);
+        }
+        let connection = connection::Connection::new(local_state, local_open);
         let listener_connection = ListenerConnection {
             connection,
             session_listener: begin_tx,
         };
 
-        let engine =
-            ConnectionEngine::open(transport, listener_connection, control_rx, outgoing_rx).await?;
+        let mut engine = ConnectionEngine::open_with_remote_open(
+            transport,
+            listener_connection,
+            control_rx,
+            outgoing_rx,
+            remote_open,
+        )
+        .await?;
+        engine.set_write_batching(self.write_batching);
+        engine.set_watchdog(self.watchdog.clone());
+        let remote_container_id = engine
+            .connection()
+            .connection
+            .remote_open
+            .as_ref()
+            .map(|open| open.container_id.clone());
+        let idle_monitor = engine.idle_monitor().clone();
         let (handle, outcome) = engine.spawn();
 
-        let connection_handle = ConnectionHandle {
+        let mut connection_handle = ConnectionHandle {
             is_closed: false,
             control: control_tx,
             handle,
-            outcome,
+            outcome: outcome.into(),
             outgoing: outgoing_tx,
             session_listener: begin_rx,
+            close_on_drop: false,
+            peer_addr: None,
+            credentials: SharedCredentials::default(),
+            watchdog: self.watchdog.clone(),
+            container_id: self.local_open.container_id.clone(),
+            incoming_sessions: None,
+            sessions: Default::default(),
+            limits,
+            authenticated_identity: None,
+            idle_monitor,
         };
+        if let Some(drain) = &self.drain {
+            let sessions = &connection_handle.sessions;
+            if !drain.admit(remote_container_id, &connection_handle.control, sessions) {
+                // The drain has started while the connection was being opened
+                let _ = connection_handle.close_with_error(draining_error()).await;
+                return Err(OpenError::ListenerDraining(draining_error()));
+            }
+        }
         Ok(connection_handle)
     }
 
+    /// Refuses a connection with the error. An Open that signals the failed establishment is sent
+    /// and immediately followed by a Close
+    async fn refuse_connection<Io>(
+        &self,
+        mut transport: Transport<Io, amqp::Frame>,
+        error: definitions::Error,
+        into_open_error: fn(definitions::Error) -> OpenError,
+    ) -> OpenError
+    where
+        Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+    {
+        match send_refusal(&mut transport, self.local_open.clone(), error.clone()).await {
+            Ok(()) => into_open_error(error),
+            Err(error) => error,
+        }
+    }
+
     async fn negotiate_amqp_with_stream<Io>(
         &self,
         stream: Io,
@@ -234,12 +432,38 @@ impl<Tls, Sasl> ConnectionAcceptor<Tls, Sasl> {
     }
 }
 
+/// Sends the `local_open`, marked as failed establishment, immediately followed by a Close with
+/// the error, and waits for the remote Close
+pub(crate) async fn send_refusal<Io>(
+    transport: &mut Transport<Io, amqp::Frame>,
+    mut local_open: Open,
+    error: definitions::Error,
+) -> Result<(), OpenError>
+where
+    Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+{
+    sole_connection::set_establishment_failed(&mut local_open);
+    let frames = [
+        Frame::new(0u16, FrameBody::Open(local_open)),
+        Frame::new(0u16, FrameBody::Close(Close { error: Some(error) })),
+    ];
+    for frame in frames {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(sending = ?frame);
+        #[cfg(feature = "log")]
+        log::trace!("sending = {:?}", frame);
+        transport.send(frame).await?;
+    }
+
+    recv_remote_close(transport).await.map(|_| ())
+}
+
 impl<Tls, Sasl> ConnectionAcceptor<Tls, Sasl>
 where
     Sasl: SaslAcceptor,
 {
     #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
-    async fn negotiate_sasl_with_framed<Io>(
+    pub(crate) async fn negotiate_sasl_with_framed<Io>(
         &self,
         framed_write: FramedWrite<WriteHalf<Io>, ProtocolHeaderCodec>,
         framed_read: FramedRead<ReadHalf<Io>, ProtocolHeaderCodec>,
@@ -306,8 +530,11 @@ where
         let (framed_write, framed_read) = transport.into_framed_codec();
         let framed_write = framed_write.map_encoder(|_| ProtocolHeaderCodec::new());
         let framed_read = framed_read.map_decoder(|_| ProtocolHeaderCodec::new());
-        self.negotiate_amqp_with_framed(framed_write, framed_read)
-            .await
+        let mut connection = self
+            .negotiate_amqp_with_framed(framed_write, framed_read)
+            .await?;
+        connection.authenticated_identity = sasl_acceptor.authenticated_identity();
+        Ok(connection)
     }
 
     async fn negotiate_sasl_with_stream<Io>(
@@ -511,11 +738,16 @@ impl endpoint::Connection for ListenerConnection {
     }
 
     #[inline]
-    async fn on_incoming_begin(
+    async fn on_incoming_begin<W>(
         &mut self,
         channel: IncomingChannel,
         begin: Begin,
-    ) -> Result<(), Self::Error> {
+        _writer: &mut W,
+    ) -> Result<(), Self::Error>
+    where
+        W: Sink<Frame> + Send + Unpin,
+        Self::Error: From<W::Error>,
+    {
         // This should remain mostly the same
         match self.connection.on_incoming_begin_inner(channel, &begin)? {
             Some(relay) => {
@@ -595,20 +827,6 @@ impl endpoint::Connection for ListenerConnection {
         outgoing_channel: OutgoingChannel,
         begin: Begin,
     ) -> Result<amqp::Frame, Self::Error> {
-        if let Some(remote_channel) = begin.remote_channel {
-            let relay = self
-                .connection
-                .session_by_outgoing_channel
-                .get(outgoing_channel.0 as usize)
-                .ok_or_else(|| {
-                    Self::Error::NotFound(Some(String::from("Outgoing channel is not found")))
-                })?;
-
-            self.connection
-                .session_by_incoming_channel
-                .insert(IncomingChannel(remote_channel), relay.clone());
-        }
-
         self.connection.on_outgoing_begin(outgoing_channel, begin)
     }
 
@@ -636,4 +854,9 @@ impl endpoint::Connection for ListenerConnection {
     ) -> Option<&mpsc::Sender<crate::session::frame::SessionIncomingItem>> {
         self.connection.session_tx_by_outgoing_channel(channel)
     }
+
+    #[inline]
+    fn is_refused_session(&self, channel: IncomingChannel) -> bool {
+        self.connection.is_refused_session(channel)
+    }
 }
@@ -0,0 +1,203 @@
+//! Graceful draining of a listener, eg. for rolling restarts
+//!
+//! A [`ListenerDrain`] is attached to a [`ConnectionAcceptor`](super::ConnectionAcceptor) with
+//! [`Builder::drain()`](super::builder::Builder::drain), and the same instance (or its clones) is
+//! kept by the application to drain the listener on shutdown. [`ListenerDrain::drain()`]
+//!
+//! 1. stops accepting connections. A connection that arrives afterwards is refused with
+//!    `amqp:connection:forced`
+//! 2. closes the idle connections (whose sessions have no unsettled delivery) with
+//!    `amqp:connection:forced` and the description
+//! 3. waits for the other connections to settle their deliveries and closes each of them once it
+//!    has no unsettled delivery left
+//! 4. force-closes the connections that still have unsettled deliveries when the timeout elapses
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let drain = ListenerDrain::new();
+//! let connection_acceptor = ConnectionAcceptor::builder()
+//!     .container_id("broker")
+//!     .drain(drain.clone())
+//!     .build();
+//!
+//! // On shutdown
+//! let report = drain
+//!     .drain(Duration::from_secs(30), "The broker is restarting")
+//!     .await;
+//! for connection in report.force_closed {
+//!     println!("{:?} had {} unsettled deliveries", connection.container_id, connection.unsettled);
+//! }
+//! ```
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use fe2o3_amqp_types::definitions::{self, ConnectionError};
+use parking_lot::Mutex;
+use tokio::{sync::mpsc, time::Instant};
+
+use crate::control::ConnectionControl;
+
+use super::admin::SessionRegistry;
+
+/// How often the unsettled deliveries of the active connections are checked while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A connection that still had unsettled deliveries when the timeout elapsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForceClosedConnection {
+    /// The container id of the remote peer
+    pub container_id: Option<String>,
+
+    /// The number of deliveries that were not settled
+    pub unsettled: usize,
+}
+
+/// The result of draining a listener
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DrainReport {
+    /// The number of connections that were idle and closed right away
+    pub idle_closed: usize,
+
+    /// The number of connections that settled their deliveries before the timeout and were closed
+    /// afterwards, or that were closed by the remote peer while draining
+    pub completed: usize,
+
+    /// The connections that were closed with unsettled deliveries
+    pub force_closed: Vec<ForceClosedConnection>,
+}
+
+#[derive(Debug, Clone)]
+struct TrackedConnection {
+    container_id: Option<String>,
+    control: mpsc::Sender<ConnectionControl>,
+    sessions: SessionRegistry,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    draining: AtomicBool,
+    connections: Mutex<Vec<TrackedConnection>>,
+}
+
+/// Keeps track of the connections accepted by a listener so that they can be drained
+///
+/// The drain is shared by all clones.
+#[derive(Debug, Clone, Default)]
+pub struct ListenerDrain {
+    inner: Arc<Inner>,
+}
+
+impl ListenerDrain {
+    /// Creates a drain without any connection
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the listener has stopped accepting connections
+    pub fn is_draining(&self) -> bool {
+        self.inner.draining.load(Ordering::Acquire)
+    }
+
+    /// The number of open connections that are tracked
+    pub fn connections(&self) -> usize {
+        let mut connections = self.inner.connections.lock();
+        connections.retain(|connection| !connection.control.is_closed());
+        connections.len()
+    }
+
+    /// Tracks a new connection. `false` is returned if the listener is draining
+    pub(crate) fn admit(
+        &self,
+        container_id: Option<String>,
+        control: &mpsc::Sender<ConnectionControl>,
+        sessions: &SessionRegistry,
+    ) -> bool {
+        let mut connections = self.inner.connections.lock();
+        // Checked under the lock so that a connection is either drained or refused
+        if self.is_draining() {
+            return false;
+        }
+        connections.retain(|connection| !connection.control.is_closed());
+        connections.push(TrackedConnection {
+            container_id,
+            control: control.clone(),
+            sessions: sessions.clone(),
+        });
+        true
+    }
+
+    /// Stops accepting connections and closes the open connections once they have no unsettled
+    /// delivery, waiting at most `timeout` before the remaining connections are force-closed
+    ///
+    /// All connections are closed with `amqp:connection:forced` and the `description`. This can
+    /// be called again, eg. to drain the connections that arrived in the meantime, but the
+    /// listener never resumes accepting connections.
+    pub async fn drain(&self, timeout: Duration, description: impl Into<String>) -> DrainReport {
+        let error =
+            definitions::Error::new(ConnectionError::ConnectionForced, description.into(), None);
+        let mut pending = {
+            let mut connections = self.inner.connections.lock();
+            self.inner.draining.store(true, Ordering::Release);
+            connections.retain(|connection| !connection.control.is_closed());
+            connections.clone()
+        };
+
+        let deadline = Instant::now() + timeout;
+        let mut report = DrainReport::default();
+        let mut is_first_check = true;
+        loop {
+            let mut active = Vec::new();
+            for connection in pending {
+                if connection.control.is_closed() {
+                    report.completed += 1;
+                    continue;
+                }
+                let unsettled = connection.sessions.unsettled().await;
+                if unsettled == 0 {
+                    let _ = connection
+                        .control
+                        .send(ConnectionControl::Close(Some(error.clone())))
+                        .await;
+                    match is_first_check {
+                        true => report.idle_closed += 1,
+                        false => report.completed += 1,
+                    }
+                } else if Instant::now() >= deadline {
+                    let _ = connection
+                        .control
+                        .send(ConnectionControl::Close(Some(error.clone())))
+                        .await;
+                    report.force_closed.push(ForceClosedConnection {
+                        container_id: connection.container_id,
+                        unsettled,
+                    });
+                } else {
+                    active.push(connection);
+                }
+            }
+
+            if active.is_empty() {
+                return report;
+            }
+            pending = active;
+            is_first_check = false;
+            tokio::time::sleep_until(deadline.min(Instant::now() + DRAIN_POLL_INTERVAL)).await;
+        }
+    }
+}
+
+/// The error that refuses a connection while the listener is draining
+pub(crate) fn draining_error() -> definitions::Error {
+    definitions::Error::new(
+        ConnectionError::ConnectionForced,
+        String::from("The listener is not accepting connections"),
+        None,
+    )
+}
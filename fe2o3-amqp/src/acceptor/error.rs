@@ -1,6 +1,6 @@
 //! Implements errors for the acceptors
 
-use crate::link::{ReceiverAttachError, SenderAttachError};
+use crate::link::{DispositionError, ReceiverAttachError, RecvError, SenderAttachError};
 
 /// Error accepting incoming attach
 #[derive(Debug, thiserror::Error)]
@@ -37,3 +37,32 @@ impl From<ReceiverAttachError> for AcceptorAttachError {
         }
     }
 }
+
+/// Error performing an administrative action on an accepted connection
+#[derive(Debug, thiserror::Error)]
+pub enum AdminError {
+    /// No open session is found on the channel
+    #[error("Session is not found")]
+    SessionNotFound,
+
+    /// No attached link is found with the name
+    #[error("Link is not found")]
+    LinkNotFound,
+}
+
+/// A pattern of a [`SubscriptionRouter`](super::SubscriptionRouter) is not valid
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("Invalid topic pattern {:?}", .0)]
+pub struct InvalidTopicPattern(pub String);
+
+/// Error that stops [`SubscriptionRouter::route()`](super::SubscriptionRouter::route)
+#[derive(Debug, thiserror::Error)]
+pub enum RouterError {
+    /// The receiver failed to receive a delivery
+    #[error(transparent)]
+    Recv(#[from] RecvError),
+
+    /// The outcome of a delivery cannot be sent
+    #[error(transparent)]
+    Disposition(#[from] DispositionError),
+}
@@ -0,0 +1,85 @@
+//! Keep-alive enforcement of the accepted connections
+
+use std::time::Duration;
+
+use fe2o3_amqp_types::definitions::Milliseconds;
+
+/// Requests the clients to keep the connection alive and closes the connections that fall silent
+///
+/// The `idle_time_out` is sent in the Open of the listener, which asks the client to send a frame
+/// (an empty frame if there is nothing else to send) at least that frequently. A client that does
+/// not send anything for `idle_time_out` plus the `grace_period` is considered gone, and the
+/// connection is closed with `amqp:resource-limit-exceeded`.
+///
+/// The default grace period is the same as the `idle_time_out`, so the connection is closed once
+/// it is silent for twice the advertised value, as suggested by the specification.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use fe2o3_amqp::acceptor::{ConnectionAcceptor, KeepAlivePolicy};
+///
+/// let connection_acceptor = ConnectionAcceptor::builder()
+///     .container_id("example-listener")
+///     .keep_alive(KeepAlivePolicy::new(Duration::from_secs(30)))
+///     .build();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAlivePolicy {
+    /// Idle time-out that is requested in the Open sent to the client
+    pub idle_time_out: Duration,
+
+    /// Additional time the client is given before the connection is closed
+    pub grace_period: Duration,
+}
+
+impl KeepAlivePolicy {
+    /// Requests the `idle_time_out` with a grace period of the same length
+    pub fn new(idle_time_out: Duration) -> Self {
+        Self {
+            idle_time_out,
+            grace_period: idle_time_out,
+        }
+    }
+
+    /// Sets the grace period
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Idle time-out in the Open sent to the client
+    pub(crate) fn advertised_idle_time_out(&self) -> Milliseconds {
+        self.idle_time_out
+            .as_millis()
+            .try_into()
+            .unwrap_or(Milliseconds::MAX)
+    }
+
+    /// How long the connection can be silent before it is closed
+    pub(crate) fn threshold(&self) -> Duration {
+        self.idle_time_out.saturating_add(self.grace_period)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::KeepAlivePolicy;
+
+    #[test]
+    fn threshold_adds_grace_period_to_idle_time_out() {
+        let policy = KeepAlivePolicy::new(Duration::from_secs(2));
+        assert_eq!(policy.advertised_idle_time_out(), 2000);
+        assert_eq!(policy.threshold(), Duration::from_secs(4));
+
+        let policy = policy.grace_period(Duration::from_millis(500));
+        assert_eq!(policy.threshold(), Duration::from_millis(2500));
+
+        let policy = KeepAlivePolicy::new(Duration::from_secs(u64::MAX));
+        assert_eq!(policy.advertised_idle_time_out(), u32::MAX);
+        assert_eq!(policy.threshold(), Duration::MAX);
+    }
+}
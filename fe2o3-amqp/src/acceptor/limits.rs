@@ -0,0 +1,308 @@
+//! Resource limits of a listener that can be changed at runtime
+//!
+//! A [`SharedLimits`] is attached to a [`ConnectionAcceptor`](super::ConnectionAcceptor) with
+//! [`Builder::limits()`](super::builder::Builder::limits), and the same instance (or its clones)
+//! is kept by the application to update the limits while the listener is running. The limits are
+//! read when a connection or a session is accepted:
+//!
+//! - `max_connections` is checked when a connection is accepted, and a connection is counted
+//!   until its handle is dropped
+//! - `max_sessions_per_connection` caps the `channel-max` of the Open of a new connection and is
+//!   checked when a session is accepted
+//! - `max_links_per_session`, `incoming_window` and `outgoing_window` are applied to the Begin of
+//!   a new session. The sessions that have already begun keep their `handle-max` and windows
+//!
+//! An update is always applied to the connections accepted afterwards. With
+//! [`ApplyTo::AllConnections`], the connections that are already open also use the new limits for
+//! the sessions they accept from then on, whereas with [`ApplyTo::NewConnections`] they keep the
+//! limits that were in effect when they were accepted.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let limits = SharedLimits::new(ListenerLimits {
+//!     max_connections: Some(1000),
+//!     ..Default::default()
+//! });
+//! let connection_acceptor = ConnectionAcceptor::builder()
+//!     .container_id("broker")
+//!     .limits(limits.clone())
+//!     .build();
+//!
+//! // Later, eg. on a configuration reload
+//! limits.update(ApplyTo::AllConnections, |limits| {
+//!     limits.max_sessions_per_connection = Some(16);
+//! });
+//! ```
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use fe2o3_amqp_types::definitions::{self, AmqpError, TransferNumber};
+use parking_lot::Mutex;
+use tokio::sync::watch;
+
+use crate::session::Builder as SessionBuilder;
+
+/// Resource limits of a listener. A limit that is `None` is not enforced
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListenerLimits {
+    /// Maximum number of open connections
+    pub max_connections: Option<usize>,
+
+    /// Maximum number of sessions on a connection
+    pub max_sessions_per_connection: Option<usize>,
+
+    /// Maximum number of links on a session. A session always allows at least one link, because
+    /// the `handle-max` of the Begin cannot express zero links
+    pub max_links_per_session: Option<usize>,
+
+    /// Incoming window of the accepted sessions, which overrides that of the
+    /// [`SessionAcceptor`](super::SessionAcceptor)
+    pub incoming_window: Option<TransferNumber>,
+
+    /// Outgoing window of the accepted sessions, which overrides that of the
+    /// [`SessionAcceptor`](super::SessionAcceptor)
+    pub outgoing_window: Option<TransferNumber>,
+}
+
+impl ListenerLimits {
+    /// Caps the `channel-max` of an Open by the maximum number of sessions
+    pub(crate) fn cap_channel_max(&self, channel_max: u16) -> u16 {
+        match self.max_sessions_per_connection {
+            Some(max) => {
+                let max_channel = u16::try_from(max.saturating_sub(1)).unwrap_or(u16::MAX);
+                channel_max.min(max_channel)
+            }
+            None => channel_max,
+        }
+    }
+
+    /// Applies the session limits to the builder of an accepted session
+    pub(crate) fn apply_to_session(&self, builder: &mut SessionBuilder) {
+        if let Some(max) = self.max_links_per_session {
+            let max_handle = u32::try_from(max.saturating_sub(1)).unwrap_or(u32::MAX);
+            builder.handle_max = builder.handle_max.0.min(max_handle).into();
+        }
+        if let Some(incoming_window) = self.incoming_window {
+            builder.incoming_window = incoming_window;
+        }
+        if let Some(outgoing_window) = self.outgoing_window {
+            builder.outgoing_window = outgoing_window;
+        }
+    }
+}
+
+/// Which connections an update of the limits is applied to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApplyTo {
+    /// Only the connections that are accepted after the update
+    NewConnections,
+
+    /// The connections that are accepted after the update and the connections that are already
+    /// open
+    AllConnections,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Incremented on every update
+    generation: u64,
+
+    /// The limits of the connections accepted from now on
+    current: ListenerLimits,
+
+    /// The last update that is applied to all connections and its generation
+    for_existing: Option<(u64, ListenerLimits)>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: Mutex<State>,
+    connections: AtomicUsize,
+    notifier: watch::Sender<ListenerLimits>,
+}
+
+/// Resource limits that are shared by the acceptors of a listener and can be updated at runtime
+///
+/// The limits are shared by all clones.
+#[derive(Debug, Clone)]
+pub struct SharedLimits {
+    inner: Arc<Inner>,
+}
+
+impl Default for SharedLimits {
+    fn default() -> Self {
+        Self::new(ListenerLimits::default())
+    }
+}
+
+impl SharedLimits {
+    /// Creates shared limits with the initial limits
+    pub fn new(limits: ListenerLimits) -> Self {
+        let (notifier, _) = watch::channel(limits.clone());
+        let state = State {
+            generation: 0,
+            current: limits,
+            for_existing: None,
+        };
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(state),
+                connections: AtomicUsize::new(0),
+                notifier,
+            }),
+        }
+    }
+
+    /// The limits of the connections that are accepted from now on
+    pub fn limits(&self) -> ListenerLimits {
+        self.inner.state.lock().current.clone()
+    }
+
+    /// Replaces the limits
+    pub fn set(&self, apply_to: ApplyTo, limits: ListenerLimits) {
+        self.update(apply_to, |current| *current = limits)
+    }
+
+    /// Modifies the limits in place
+    pub fn update(&self, apply_to: ApplyTo, f: impl FnOnce(&mut ListenerLimits)) {
+        let limits = {
+            let mut state = self.inner.state.lock();
+            f(&mut state.current);
+            state.generation += 1;
+            if apply_to == ApplyTo::AllConnections {
+                state.for_existing = Some((state.generation, state.current.clone()));
+            }
+            state.current.clone()
+        };
+        self.inner.notifier.send_replace(limits);
+    }
+
+    /// Subscribes to the updates of the limits. The receiver sees the limits of the connections
+    /// that are accepted from now on
+    pub fn subscribe(&self) -> watch::Receiver<ListenerLimits> {
+        self.inner.notifier.subscribe()
+    }
+
+    /// The number of open connections that are accepted with these limits
+    pub fn connections(&self) -> usize {
+        self.inner.connections.load(Ordering::Acquire)
+    }
+
+    /// Counts a new connection if `max_connections` is not reached
+    pub(crate) fn admit(&self) -> Option<ConnectionLimits> {
+        let state = self.inner.state.lock();
+        let max = state.current.max_connections.unwrap_or(usize::MAX);
+        self.inner
+            .connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < max).then_some(count + 1)
+            })
+            .ok()?;
+        Some(ConnectionLimits {
+            shared: self.clone(),
+            generation: state.generation,
+            snapshot: state.current.clone(),
+        })
+    }
+}
+
+/// The limits of an accepted connection. The connection is no longer counted once this is dropped
+#[derive(Debug)]
+pub(crate) struct ConnectionLimits {
+    shared: SharedLimits,
+    generation: u64,
+    snapshot: ListenerLimits,
+}
+
+impl ConnectionLimits {
+    /// The limits that are in effect for the connection
+    pub fn current(&self) -> ListenerLimits {
+        let state = self.shared.inner.state.lock();
+        match &state.for_existing {
+            Some((generation, limits)) if *generation > self.generation => limits.clone(),
+            _ => self.snapshot.clone(),
+        }
+    }
+}
+
+impl Drop for ConnectionLimits {
+    fn drop(&mut self) {
+        self.shared.inner.connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The error that refuses a connection or a session because a limit is reached
+pub(crate) fn limit_exceeded_error(description: &str) -> definitions::Error {
+    definitions::Error::new(
+        AmqpError::ResourceLimitExceeded,
+        description.to_string(),
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApplyTo, ListenerLimits, SharedLimits};
+
+    #[test]
+    fn connections_are_counted_up_to_the_limit() {
+        let limits = SharedLimits::new(ListenerLimits {
+            max_connections: Some(1),
+            ..Default::default()
+        });
+        let first = limits.admit().unwrap();
+        assert!(limits.admit().is_none());
+        assert_eq!(limits.connections(), 1);
+
+        drop(first);
+        assert_eq!(limits.connections(), 0);
+        assert!(limits.admit().is_some());
+    }
+
+    #[test]
+    fn existing_connections_only_see_updates_applied_to_them() {
+        let limits = SharedLimits::default();
+        let existing = limits.admit().unwrap();
+
+        limits.update(ApplyTo::NewConnections, |limits| {
+            limits.max_sessions_per_connection = Some(1)
+        });
+        assert_eq!(existing.current().max_sessions_per_connection, None);
+        let new = limits.admit().unwrap();
+        assert_eq!(new.current().max_sessions_per_connection, Some(1));
+
+        limits.update(ApplyTo::AllConnections, |limits| {
+            limits.max_sessions_per_connection = Some(2)
+        });
+        assert_eq!(existing.current().max_sessions_per_connection, Some(2));
+        assert_eq!(new.current().max_sessions_per_connection, Some(2));
+
+        // A later update that is only applied to new connections does not override it
+        limits.update(ApplyTo::NewConnections, |limits| {
+            limits.max_sessions_per_connection = Some(3)
+        });
+        assert_eq!(existing.current().max_sessions_per_connection, Some(2));
+        assert_eq!(*limits.subscribe().borrow(), limits.limits());
+    }
+
+    #[test]
+    fn channel_max_and_handle_max_are_capped() {
+        let limits = ListenerLimits {
+            max_sessions_per_connection: Some(4),
+            max_links_per_session: Some(0),
+            incoming_window: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(limits.cap_channel_max(u16::MAX), 3);
+        assert_eq!(limits.cap_channel_max(1), 1);
+
+        let mut builder = crate::Session::builder();
+        limits.apply_to_session(&mut builder);
+        assert_eq!(builder.handle_max.0, 0);
+        assert_eq!(builder.incoming_window, 10);
+    }
+}
@@ -4,20 +4,31 @@
 // #[derive(Debug)]
 // pub struct LinkListener {}
 
-use std::marker::PhantomData;
+use std::{future::Future, marker::PhantomData};
 
 use fe2o3_amqp_types::{
-    definitions::{Fields, ReceiverSettleMode, Role, SenderSettleMode},
+    definitions::{self, AmqpError, Fields, ReceiverSettleMode, Role, SenderSettleMode},
     messaging::{Source, Target},
     performatives::Attach,
     primitives::{Symbol, Ulong},
 };
 
-use crate::{connection::DEFAULT_OUTGOING_BUFFER_SIZE, session::SessionHandle, util::Initialized};
+use crate::{
+    connection::DEFAULT_OUTGOING_BUFFER_SIZE,
+    link::{Receiver, Sender},
+    session::SessionHandle,
+    util::Initialized,
+};
 
 use super::{
-    builder::Builder, error::AcceptorAttachError, local_receiver_link::LocalReceiverLinkAcceptor,
-    local_sender_link::LocalSenderLinkAcceptor, session::ListenerSessionHandle,
+    authorization::SharedAuthorizer,
+    builder::Builder,
+    error::AcceptorAttachError,
+    link_task::LinkTasks,
+    local_receiver_link::LocalReceiverLinkAcceptor,
+    local_sender_link::LocalSenderLinkAcceptor,
+    session::ListenerSessionHandle,
+    settle_mode::{SettleModePolicy, SettleModes},
     SupportedReceiverSettleModes, SupportedSenderSettleModes,
 };
 
@@ -31,6 +42,19 @@ pub enum LinkEndpoint {
     Receiver(crate::link::Receiver),
 }
 
+/// How an incoming Attach is handled if its link name is already used by an attached link on the
+/// same session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LinkNameCollision {
+    /// The incoming link is refused with an Attach that is immediately followed by a Detach
+    /// carrying `amqp:resource-locked`. The attached link is left untouched
+    #[default]
+    Reject,
+
+    /// The attached link is detached with `amqp:link:stolen` and the incoming link is accepted
+    Steal,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct SharedLinkAcceptorFields {
     /// The maximum message size supported by the link endpoint
@@ -67,6 +91,17 @@ pub(crate) struct SharedLinkAcceptorFields {
     /// If this field is None, an incoming attach whose desired receiver settle
     /// mode is not supported will then be rejected
     pub fallback_rcv_settle_mode: ReceiverSettleMode,
+
+    /// Negotiates the combination of settle modes. The supported and fallback modes above are
+    /// ignored if this is set
+    pub settle_mode_policy: Option<SettleModePolicy>,
+
+    /// How an incoming Attach with a link name that is already in use is handled
+    pub link_name_collision: LinkNameCollision,
+
+    /// Authorizes the incoming links and the deliveries received on them. Everything is allowed
+    /// if this is `None`
+    pub authorizer: Option<SharedAuthorizer>,
 }
 
 impl Default for SharedLinkAcceptorFields {
@@ -81,10 +116,46 @@ impl Default for SharedLinkAcceptorFields {
             fallback_snd_settle_mode: SenderSettleMode::default(),
             supported_rcv_settle_modes: SupportedReceiverSettleModes::default(),
             fallback_rcv_settle_mode: ReceiverSettleMode::default(),
+            settle_mode_policy: None,
+            link_name_collision: LinkNameCollision::default(),
+            authorizer: None,
         }
     }
 }
 
+impl SharedLinkAcceptorFields {
+    /// Returns the settle modes that are sent back in the echoed Attach, or `None` if the modes
+    /// requested by the remote peer are refused
+    pub(crate) fn negotiate_settle_modes(&self, remote_attach: &Attach) -> Option<SettleModes> {
+        if let Some(policy) = &self.settle_mode_policy {
+            return policy.negotiate(
+                &remote_attach.snd_settle_mode,
+                &remote_attach.rcv_settle_mode,
+            );
+        }
+
+        let snd_settle_mode = if self
+            .supported_snd_settle_modes
+            .supports(&remote_attach.snd_settle_mode)
+        {
+            remote_attach.snd_settle_mode.clone()
+        } else {
+            self.fallback_snd_settle_mode.clone()
+        };
+        // The receiver SHOULD respect the sender’s desired settlement mode if
+        // the sender initiates the attach exchange and the receiver supports the desired mode
+        let rcv_settle_mode = if self
+            .supported_rcv_settle_modes
+            .supports(&remote_attach.rcv_settle_mode)
+        {
+            remote_attach.rcv_settle_mode.clone()
+        } else {
+            self.fallback_rcv_settle_mode.clone()
+        };
+        Some((snd_settle_mode, rcv_settle_mode))
+    }
+}
+
 /// An acceptor for incoming links
 ///
 /// # Accepts incoming link with default configuration
@@ -105,6 +176,7 @@ impl Default for SharedLinkAcceptorFields {
 /// |`fallback_snd_settle_mode`| `None` |
 /// |`supported_rcv_settle_modes`|[`SupportedReceiverSettleModes::Both`]|
 /// |`fallback_rcv_settle_mode`| `None` |
+/// |`settle_mode_policy`| `None` |
 /// |`initial_delivery_count`| `0` |
 /// |`max_message_size`| `None` |
 /// |`offered_capabilities`| `None` |
@@ -112,6 +184,8 @@ impl Default for SharedLinkAcceptorFields {
 /// |`properties`| `None` |
 /// |`buffer_size`| [`u16::MAX`] |
 /// |`credit_mode`| [`CreditMode::Auto(DEFAULT_CREDIT)`] |
+/// |`link_name_collision`| [`LinkNameCollision::Reject`] |
+/// |`authorizer`| `None` |
 ///
 /// # Customize acceptor
 ///
@@ -221,4 +295,74 @@ where
             .ok_or(AcceptorAttachError::IllegalSessionState)?;
         self.accept_incoming_attach(remote_attach, session).await
     }
+
+    /// Accept the next incoming link on which the local peer is the sender, and spawn a task in
+    /// `tasks` that runs `handler` on it. The link returned by the handler is then closed
+    ///
+    /// An incoming link on which the local peer would be the receiver is closed with an
+    /// `amqp:not-allowed` error, and the next incoming link is waited for. This waits for a
+    /// running task to end if the concurrency limit of `tasks` is reached.
+    pub async fn accept_sender_with<F, Fut>(
+        &self,
+        session: &mut ListenerSessionHandle,
+        tasks: &mut LinkTasks,
+        handler: F,
+    ) -> Result<(), AcceptorAttachError>
+    where
+        F: FnOnce(Sender) -> Fut + Send + 'static,
+        Fut: Future<Output = Sender> + Send + 'static,
+    {
+        let permit = tasks.acquire_permit().await;
+        loop {
+            match self.accept(session).await? {
+                LinkEndpoint::Sender(sender) => {
+                    let name = sender.name().to_string();
+                    let task = async move { handler(sender).await.close().await };
+                    tasks.spawn(name, permit, task);
+                    return Ok(());
+                }
+                LinkEndpoint::Receiver(receiver) => {
+                    let error = not_allowed("Only links to receive messages from are accepted");
+                    tokio::spawn(receiver.close_with_error(error));
+                }
+            }
+        }
+    }
+
+    /// Accept the next incoming link on which the local peer is the receiver, and spawn a task in
+    /// `tasks` that runs `handler` on it. The link returned by the handler is then closed
+    ///
+    /// An incoming link on which the local peer would be the sender is closed with an
+    /// `amqp:not-allowed` error, and the next incoming link is waited for. This waits for a
+    /// running task to end if the concurrency limit of `tasks` is reached.
+    pub async fn accept_receiver_with<F, Fut>(
+        &self,
+        session: &mut ListenerSessionHandle,
+        tasks: &mut LinkTasks,
+        handler: F,
+    ) -> Result<(), AcceptorAttachError>
+    where
+        F: FnOnce(Receiver) -> Fut + Send + 'static,
+        Fut: Future<Output = Receiver> + Send + 'static,
+    {
+        let permit = tasks.acquire_permit().await;
+        loop {
+            match self.accept(session).await? {
+                LinkEndpoint::Receiver(receiver) => {
+                    let name = receiver.name().to_string();
+                    let task = async move { handler(receiver).await.close().await };
+                    tasks.spawn(name, permit, task);
+                    return Ok(());
+                }
+                LinkEndpoint::Sender(sender) => {
+                    let error = not_allowed("Only links to send messages to are accepted");
+                    tokio::spawn(sender.close_with_error(error));
+                }
+            }
+        }
+    }
+}
+
+fn not_allowed(description: &str) -> definitions::Error {
+    definitions::Error::new(AmqpError::NotAllowed, description.to_string(), None)
 }
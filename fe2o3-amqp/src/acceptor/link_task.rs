@@ -0,0 +1,172 @@
+//! Managed tasks of the links accepted by a [`LinkAcceptor`](super::LinkAcceptor)
+//!
+//! [`LinkAcceptor::accept_sender_with()`](super::LinkAcceptor::accept_sender_with) and
+//! [`LinkAcceptor::accept_receiver_with()`](super::LinkAcceptor::accept_receiver_with) accept a
+//! link and spawn a task that runs a handler on it. The handler returns the link once it is done
+//! with it, and the task then closes the link. The tasks are tracked by [`LinkTasks`], which also
+//! limits how many of them may run at the same time.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let link_acceptor = LinkAcceptor::new();
+//! let mut tasks = LinkTasks::with_max_concurrency(100);
+//! loop {
+//!     tokio::select! {
+//!         result = link_acceptor.accept_sender_with(&mut session, &mut tasks, |mut sender| async move {
+//!             sender.send("hello").await.unwrap();
+//!             sender
+//!         }) => result.unwrap(),
+//!         Some(report) = tasks.join_next() => println!("{:?}", report),
+//!     }
+//! }
+//! ```
+
+use std::{any::Any, future::Future, panic::AssertUnwindSafe, sync::Arc};
+
+use futures_util::FutureExt;
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+};
+
+use crate::link::DetachError;
+
+/// How the task of a link ended
+#[derive(Debug)]
+pub enum LinkTaskOutcome {
+    /// The handler returned and the link was closed
+    Closed,
+
+    /// The handler returned but closing the link failed
+    CloseFailed(DetachError),
+
+    /// The handler panicked with the message. The link is dropped, which closes it without an
+    /// error
+    Panicked(String),
+}
+
+/// Report of a link task that has ended
+#[derive(Debug)]
+pub struct LinkTaskReport {
+    /// Name of the link
+    pub name: String,
+
+    /// How the task ended
+    pub outcome: LinkTaskOutcome,
+}
+
+/// Tasks that run the handlers of accepted links
+///
+/// The tasks are aborted when [`LinkTasks`] is dropped.
+#[derive(Debug, Default)]
+pub struct LinkTasks {
+    tasks: JoinSet<LinkTaskReport>,
+    limit: Option<Arc<Semaphore>>,
+}
+
+impl LinkTasks {
+    /// Creates a set of tasks without a limit on the number of running tasks
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a set of tasks of which at most `max_concurrency` run at the same time. No link is
+    /// accepted while the limit is reached
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_concurrency` is zero
+    pub fn with_max_concurrency(max_concurrency: usize) -> Self {
+        assert!(
+            max_concurrency > 0,
+            "max_concurrency must be greater than zero"
+        );
+        Self {
+            tasks: JoinSet::new(),
+            limit: Some(Arc::new(Semaphore::new(max_concurrency))),
+        }
+    }
+
+    /// The number of tasks that have not been joined yet
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Whether there is no task to join
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Waits for the next task to end and returns its report. `None` is returned if there is no
+    /// task left
+    ///
+    /// This is cancel safe.
+    pub async fn join_next(&mut self) -> Option<LinkTaskReport> {
+        loop {
+            match self.tasks.join_next().await? {
+                Ok(report) => return Some(report),
+                // The panics of the handlers are caught in the task, so only an aborted task
+                // ends up here
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// Waits for all tasks to end and returns their reports in the order they ended
+    pub async fn join_all(&mut self) -> Vec<LinkTaskReport> {
+        let mut reports = Vec::with_capacity(self.len());
+        while let Some(report) = self.join_next().await {
+            reports.push(report);
+        }
+        reports
+    }
+
+    /// Aborts all tasks. The links of the aborted tasks are dropped
+    pub fn abort_all(&mut self) {
+        self.tasks.abort_all()
+    }
+
+    pub(crate) async fn acquire_permit(&self) -> Option<OwnedSemaphorePermit> {
+        match &self.limit {
+            // The semaphore is never closed
+            Some(limit) => limit.clone().acquire_owned().await.ok(),
+            None => None,
+        }
+    }
+
+    pub(crate) fn spawn<Fut>(
+        &mut self,
+        name: String,
+        permit: Option<OwnedSemaphorePermit>,
+        task: Fut,
+    ) where
+        Fut: Future<Output = Result<(), DetachError>> + Send + 'static,
+    {
+        self.tasks.spawn(async move {
+            let outcome = match AssertUnwindSafe(task).catch_unwind().await {
+                Ok(Ok(())) => LinkTaskOutcome::Closed,
+                Ok(Err(error)) => LinkTaskOutcome::CloseFailed(error),
+                Err(panic) => LinkTaskOutcome::Panicked(panic_message(panic)),
+            };
+            drop(permit);
+
+            #[cfg(feature = "tracing")]
+            tracing::debug!(link = %name, ?outcome, "link task ended");
+            #[cfg(feature = "log")]
+            log::debug!("link task ended link={}, outcome={:?}", name, outcome);
+
+            LinkTaskReport { name, outcome }
+        });
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => "Box<dyn Any>".to_string(),
+        },
+    }
+}
@@ -11,9 +11,10 @@ use fe2o3_amqp_types::{
     primitives::Symbol,
 };
 use parking_lot::RwLock;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
 use crate::{
+    clock::SharedClock,
     control::SessionControl,
     endpoint::{InputHandle, LinkAttach, LinkExt},
     link::{
@@ -92,6 +93,8 @@ where
             remote_attach,
             session.control.clone(),
             session.outgoing.clone(),
+            session.authenticated_identity.as_deref(),
+            session.clock.clone(),
         )
         .await
         .map(|inner| Receiver { inner })
@@ -107,9 +110,11 @@ where
     pub async fn accept_incoming_attach_inner(
         &self,
         shared: &SharedLinkAcceptorFields,
-        remote_attach: Attach,
+        mut remote_attach: Attach,
         control: mpsc::Sender<SessionControl>,
         outgoing: mpsc::Sender<LinkFrame>,
+        identity: Option<&str>,
+        clock: SharedClock,
     ) -> Result<ReceiverInner<ReceiverLink<T>>, ReceiverAttachError>
     where
         T: Into<TargetArchetype>
@@ -119,24 +124,25 @@ where
             + Send
             + Sync,
     {
-        let snd_settle_mode = if shared
-            .supported_snd_settle_modes
-            .supports(&remote_attach.snd_settle_mode)
-        {
-            remote_attach.snd_settle_mode.clone()
-        } else {
-            shared.fallback_snd_settle_mode.clone()
-        };
-        // The receiver SHOULD respect the sender’s desired settlement mode if
-        // the sender initiates the attach exchange and the receiver supports the desired mode
-        let rcv_settle_mode = if shared
-            .supported_rcv_settle_modes
-            .supports(&remote_attach.rcv_settle_mode)
-        {
-            remote_attach.rcv_settle_mode.clone()
-        } else {
-            shared.fallback_rcv_settle_mode.clone()
-        };
+        let denied = shared
+            .authorizer
+            .as_ref()
+            .and_then(|authorizer| authorizer.authorize_attach(&remote_attach, identity));
+        let transfer_authorization = shared
+            .authorizer
+            .as_ref()
+            .map(|authorizer| Box::new(authorizer.for_transfers(&remote_attach, identity)));
+        let negotiated = shared.negotiate_settle_modes(&remote_attach);
+        // A refused link is echoed with the requested modes before it is detached
+        let (snd_settle_mode, rcv_settle_mode) = negotiated.clone().unwrap_or_else(|| {
+            (
+                remote_attach.snd_settle_mode.clone(),
+                remote_attach.rcv_settle_mode.clone(),
+            )
+        });
+        // The link is set up with the negotiated modes
+        remote_attach.snd_settle_mode = snd_settle_mode.clone();
+        remote_attach.rcv_settle_mode = rcv_settle_mode.clone();
 
         // Create channels for Session-Link communication
         let (incoming_tx, mut incoming_rx) = mpsc::channel::<LinkIncomingItem>(shared.buffer_size);
@@ -156,6 +162,8 @@ where
 
         // Comparing unsettled should be taken care of in `on_incoming_attach`
         let unsettled = Arc::new(RwLock::new(None));
+        #[cfg(feature = "debug-deliveries")]
+        let delivery_traces = crate::link::delivery_trace::ArcDeliveryTraces::default();
         let link_handle = LinkRelay::Receiver {
             tx: incoming_tx,
             output_handle: (),
@@ -163,6 +171,8 @@ where
             unsettled: unsettled.clone(),
             receiver_settle_mode: rcv_settle_mode.clone(),
             more: false,
+            #[cfg(feature = "debug-deliveries")]
+            delivery_traces: delivery_traces.clone(),
         };
 
         // Allocate link in session
@@ -172,10 +182,16 @@ where
             remote_attach.name.clone(),
             link_handle,
             input_handle,
+            shared.link_name_collision,
         )
         .await?;
 
-        let mut err = None;
+        let mut err = match denied {
+            Some(error) => Some(ReceiverAttachError::Unauthorized(error)),
+            None => negotiated
+                .is_none()
+                .then_some(ReceiverAttachError::SettleModesNotSupported),
+        };
         // **the receiver is considered to hold the authoritative version of the target properties**,
         let local_target = remote_attach
             .target
@@ -204,6 +220,7 @@ where
         let mut link = ReceiverLink::<T> {
             role: PhantomData,
             local_state: LinkState::Unattached, // State change will be taken care of in `on_incoming_attach`
+            state_watch: watch::channel(LinkState::Unattached).0,
             name: remote_attach.name.clone(),
             output_handle: Some(output_handle),
             input_handle: None, // will be set in `on_incoming_attach`
@@ -212,12 +229,15 @@ where
             source: None,         // Will take value from incoming attach
             target: local_target, // Will take value from incoming attach
             max_message_size: shared.max_message_size.unwrap_or(0),
+            max_transfer_frame_size: None,
             offered_capabilities: shared.offered_capabilities.clone(),
             desired_capabilities: shared.desired_capabilities.clone(),
             flow_state: flow_state_consumer,
             unsettled,
             verify_incoming_source: self.verify_incoming_source,
             verify_incoming_target: self.verify_incoming_target,
+            #[cfg(feature = "debug-deliveries")]
+            delivery_traces,
         };
 
         // `on_incoming_attach` should always be evaluated
@@ -255,6 +275,32 @@ where
             outgoing,
             incoming: incoming_rx,
             incomplete_transfer: None,
+            detach_timeout: None,
+            rate_limiter: None,
+            deduplicator: None,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            decompress: false,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            decompressed_size_exceeded: false,
+            #[cfg(feature = "checksum")]
+            checksum: None,
+            #[cfg(feature = "checksum")]
+            checksum_mismatch: false,
+            message_formats: Default::default(),
+            empty_body_policy: Default::default(),
+            delivery_tag_violations: 0,
+            #[cfg(not(target_arch = "wasm32"))]
+            unsettled_arrivals: Default::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            spooling: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            disposition_batcher: None,
+            priority_ordering: false,
+            prefetched: Default::default(),
+            byte_window: None,
+            transfer_authorization,
+            events: Default::default(),
+            clock,
         };
 
         if let CreditMode::Auto(credit) = inner.credit_mode {
@@ -9,12 +9,12 @@ use fe2o3_amqp_types::{
     primitives::Symbol,
 };
 use parking_lot::RwLock;
-use tokio::sync::{mpsc, Notify};
+use tokio::sync::{mpsc, watch, Notify};
 
 use crate::{
     endpoint::{InputHandle, LinkAttach, LinkExt},
     link::{
-        sender::SenderInner,
+        sender::{AvailableMode, SenderInner},
         state::{LinkFlowState, LinkFlowStateInner, LinkState},
         LinkRelay, SenderAttachError, SenderLink,
     },
@@ -76,25 +76,23 @@ where
     pub async fn accept_incoming_attach<R>(
         &self,
         shared: &SharedLinkAcceptorFields,
-        remote_attach: Attach,
+        mut remote_attach: Attach,
         session: &mut SessionHandle<R>,
     ) -> Result<Sender, SenderAttachError> {
-        let snd_settle_mode = if shared
-            .supported_snd_settle_modes
-            .supports(&remote_attach.snd_settle_mode)
-        {
-            remote_attach.snd_settle_mode.clone()
-        } else {
-            shared.fallback_snd_settle_mode.clone()
-        };
-        let rcv_settle_mode = if shared
-            .supported_rcv_settle_modes
-            .supports(&remote_attach.rcv_settle_mode)
-        {
-            remote_attach.rcv_settle_mode.clone()
-        } else {
-            shared.fallback_rcv_settle_mode.clone()
-        };
+        let denied = shared.authorizer.as_ref().and_then(|authorizer| {
+            authorizer.authorize_attach(&remote_attach, session.authenticated_identity.as_deref())
+        });
+        let negotiated = shared.negotiate_settle_modes(&remote_attach);
+        // A refused link is echoed with the requested modes before it is detached
+        let (snd_settle_mode, rcv_settle_mode) = negotiated.clone().unwrap_or_else(|| {
+            (
+                remote_attach.snd_settle_mode.clone(),
+                remote_attach.rcv_settle_mode.clone(),
+            )
+        });
+        // The link is set up with the negotiated modes
+        remote_attach.snd_settle_mode = snd_settle_mode.clone();
+        remote_attach.rcv_settle_mode = rcv_settle_mode.clone();
 
         let (incoming_tx, mut incoming_rx) = mpsc::channel(shared.buffer_size);
 
@@ -112,12 +110,20 @@ where
         let flow_state_consumer = Consumer::new(notifier, flow_state);
 
         let unsettled = Arc::new(RwLock::new(None));
+        #[cfg(feature = "debug-deliveries")]
+        let delivery_traces = crate::link::delivery_trace::ArcDeliveryTraces::default();
+        #[cfg(not(target_arch = "wasm32"))]
+        let latency = crate::link::latency::ArcSettlementLatency::default();
         let link_handle = LinkRelay::Sender {
             tx: incoming_tx,
             output_handle: (),
             flow_state: flow_state_producer,
             unsettled: unsettled.clone(),
-            receiver_settle_mode: remote_attach.rcv_settle_mode.clone(),
+            receiver_settle_mode: rcv_settle_mode.clone(),
+            #[cfg(feature = "debug-deliveries")]
+            delivery_traces: delivery_traces.clone(),
+            #[cfg(not(target_arch = "wasm32"))]
+            latency: latency.clone(),
         };
 
         // Allocate link in session
@@ -127,6 +133,7 @@ where
             remote_attach.name.clone(),
             link_handle,
             input_handle,
+            shared.link_name_collision,
         )
         .await?;
 
@@ -148,6 +155,7 @@ where
         let mut link = SenderLink::<Target> {
             role: PhantomData,
             local_state: LinkState::Unattached, // will be set in `on_incoming_attach`
+            state_watch: watch::channel(LinkState::Unattached).0,
 
             name: remote_attach.name.clone(),
             output_handle: Some(output_handle),
@@ -157,17 +165,25 @@ where
             source: local_source,
             target: None, // Will take value from incoming attach
             max_message_size: shared.max_message_size.unwrap_or(0),
+            max_transfer_frame_size: None,
             offered_capabilities: shared.offered_capabilities.clone(),
             desired_capabilities: shared.desired_capabilities.clone(),
             flow_state: flow_state_consumer,
             unsettled,
             verify_incoming_source: self.verify_incoming_source,
             verify_incoming_target: self.verify_incoming_target,
+            #[cfg(feature = "debug-deliveries")]
+            delivery_traces,
         };
 
         let outgoing = session.outgoing.clone();
 
-        match link.on_incoming_attach(remote_attach) {
+        let result = match (link.on_incoming_attach(remote_attach), negotiated, denied) {
+            (Ok(_), _, Some(error)) => Err(SenderAttachError::Unauthorized(error)),
+            (Ok(_), None, None) => Err(SenderAttachError::SettleModesNotSupported),
+            (result, _, _) => result,
+        };
+        match result {
             Ok(_) => link.send_attach(&outgoing, &session.control, false).await?,
             Err(attach_error) => {
                 // Complete attach then detach should any error happen
@@ -197,6 +213,16 @@ where
             session: session.control.clone(),
             outgoing,
             incoming: incoming_rx,
+            available_mode: AvailableMode::default(),
+            detach_timeout: None,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            compression: None,
+            #[cfg(feature = "checksum")]
+            checksum: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            latency,
+            events: Default::default(),
+            clock: session.clock.clone(),
         };
         Ok(Sender { inner })
     }
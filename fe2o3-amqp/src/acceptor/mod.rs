@@ -1,13 +1,23 @@
 //! Acceptors for fine control over incoming connections, sessions, and links
 
+pub mod admin;
+pub mod authorization;
 pub mod builder;
 pub mod connection;
+pub mod drain;
 pub mod error;
+pub mod keep_alive;
+pub mod limits;
 pub mod link;
+pub mod link_task;
 pub mod local_receiver_link;
 pub mod local_sender_link;
 pub mod sasl_acceptor;
 pub mod session;
+pub mod settle_mode;
+pub mod sole_connection;
+pub mod subscription;
+pub mod virtual_host;
 
 cfg_scram! {
     pub mod scram;
@@ -18,10 +28,24 @@ use fe2o3_amqp_types::{
     performatives::Begin,
 };
 
+pub use self::admin::SessionInfo;
+pub use self::authorization::{
+    AttachRequest, Authorization, Authorizer, Direction, SharedAuthorizer, TransferRequest,
+};
 pub use self::connection::{ConnectionAcceptor, ListenerConnectionHandle};
-pub use self::link::{LinkAcceptor, LinkEndpoint};
-pub use self::sasl_acceptor::{SaslAcceptor, SaslAnonymousMechanism, SaslPlainMechanism};
+pub use self::drain::{DrainReport, ForceClosedConnection, ListenerDrain};
+pub use self::keep_alive::KeepAlivePolicy;
+pub use self::limits::{ApplyTo, ListenerLimits, SharedLimits};
+pub use self::link::{LinkAcceptor, LinkEndpoint, LinkNameCollision};
+pub use self::link_task::{LinkTaskOutcome, LinkTaskReport, LinkTasks};
+pub use self::sasl_acceptor::{
+    PlainCredentials, SaslAcceptor, SaslAnonymousMechanism, SaslPlainMechanism,
+};
 pub use self::session::{ListenerSessionHandle, SessionAcceptor};
+pub use self::settle_mode::SettleModePolicy;
+pub use self::sole_connection::SoleConnectionEnforcement;
+pub use self::subscription::{Subscription, SubscriptionRouter, TopicPattern};
+pub use self::virtual_host::VirtualHostAcceptor;
 
 /// A half established session that is initiated by the remote peer
 #[derive(Debug)]
@@ -29,6 +29,12 @@ pub trait SaslAcceptor: Clone {
 
     /// Respond to a SaslResponse frame
     fn on_response(&mut self, response: SaslResponse) -> SaslServerFrame;
+
+    /// The identity that the client authenticated as once the negotiation has succeeded. This
+    /// is `None` by default
+    fn authenticated_identity(&self) -> Option<String> {
+        None
+    }
 }
 
 /// Extension trait of SaslAcceptor
@@ -63,39 +69,107 @@ impl<T: SaslAcceptor> SaslAcceptorExt for T {}
 //     Plain,
 // }
 
-/// A naive acceptor for SASL PLAIN mechanism
-#[derive(Debug, Clone)]
+/// Credentials carried by the initial response of the SASL PLAIN mechanism
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlainCredentials<'a> {
+    /// The identity to act as, if the client asks to act as another identity than `authcid`
+    pub authzid: Option<&'a str>,
+
+    /// The authentication identity (username)
+    pub authcid: &'a str,
+
+    /// The password
+    pub passwd: &'a str,
+}
+
+type PlainValidator = dyn Fn(&PlainCredentials<'_>) -> bool + Send + Sync;
+
+/// An acceptor for SASL PLAIN mechanism
+///
+/// [`SaslPlainMechanism::new`] accepts a single username and password, and
+/// [`SaslPlainMechanism::with_validator`] accepts the credentials that are approved by a callback.
+#[derive(Clone)]
 pub struct SaslPlainMechanism {
-    username: Arc<String>,
-    password: Arc<String>,
+    validator: Arc<PlainValidator>,
+    identity: Option<String>,
+}
+
+impl std::fmt::Debug for SaslPlainMechanism {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SaslPlainMechanism").finish()
+    }
 }
 
 impl SaslPlainMechanism {
-    /// Creates a new PLAIN mechanism acceptor
+    /// Creates a new PLAIN mechanism acceptor that accepts a single username and password
+    ///
+    /// An authorization identity other than the username is rejected, as this acceptor cannot
+    /// decide whether the user may act as another identity.
     pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+        let username = username.into();
+        let password = password.into();
+        Self::with_validator(move |credentials| {
+            credentials.authcid == username
+                && credentials.passwd == password
+                && credentials.authzid.unwrap_or(&username) == username
+        })
+    }
+
+    /// Creates a new PLAIN mechanism acceptor that accepts the credentials for which `validator`
+    /// returns `true`
+    ///
+    /// The validator is responsible for checking that `authcid` is allowed to act as `authzid`
+    /// when an authorization identity is given.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use fe2o3_amqp::acceptor::SaslPlainMechanism;
+    ///
+    /// let mechanism = SaslPlainMechanism::with_validator(|credentials| {
+    ///     match credentials.authzid {
+    ///         // The proxy may act as any user
+    ///         Some(_) => credentials.authcid == "proxy" && credentials.passwd == "proxy-secret",
+    ///         None => credentials.authcid == "guest" && credentials.passwd == "guest",
+    ///     }
+    /// });
+    /// ```
+    pub fn with_validator<F>(validator: F) -> Self
+    where
+        F: Fn(&PlainCredentials<'_>) -> bool + Send + Sync + 'static,
+    {
         Self {
-            username: Arc::new(username.into()),
-            password: Arc::new(password.into()),
+            validator: Arc::new(validator),
+            identity: None,
         }
     }
 }
 
 impl SaslPlainMechanism {
-    fn validate_init(&self, init: SaslInit) -> Option<SaslCode> {
+    fn validate_init(&mut self, init: SaslInit) -> Option<SaslCode> {
         let response = init.initial_response?.into_vec();
 
         let mut split = response.split(|b| *b == 0u8);
-        let _authzid = split.next()?;
-        let authcid = split.next()?;
-        let passwd = split.next()?;
-        Some(self.validate_credential(authcid, passwd))
-    }
+        let authzid = std::str::from_utf8(split.next()?).ok()?;
+        let authcid = std::str::from_utf8(split.next()?).ok()?;
+        let passwd = std::str::from_utf8(split.next()?).ok()?;
+        if split.next().is_some() {
+            return None;
+        }
 
-    fn validate_credential(&self, authcid: &[u8], passwd: &[u8]) -> SaslCode {
-        if self.username.as_bytes() == authcid && self.password.as_bytes() == passwd {
-            SaslCode::Ok
-        } else {
-            SaslCode::Auth
+        let credentials = PlainCredentials {
+            authzid: (!authzid.is_empty()).then_some(authzid),
+            authcid,
+            passwd,
+        };
+        match (self.validator)(&credentials) {
+            true => {
+                // The client acts as the authorization identity if it asks for one
+                let identity = credentials.authzid.unwrap_or(credentials.authcid);
+                self.identity = Some(identity.to_string());
+                Some(SaslCode::Ok)
+            }
+            false => Some(SaslCode::Auth),
         }
     }
 }
@@ -122,6 +196,10 @@ impl SaslAcceptor for SaslPlainMechanism {
         };
         SaslServerFrame::Outcome(outcome)
     }
+
+    fn authenticated_identity(&self) -> Option<String> {
+        self.identity.clone()
+    }
 }
 
 /// A SASL Anonymous acceptor that is going to accept anything
@@ -1,16 +1,16 @@
 //! Session Listener
 
-
 use fe2o3_amqp_types::{
-    definitions::{self, ConnectionError},
+    definitions::{self, ConnectionError, DeliveryTag, Role},
+    messaging::{DeliveryState, Source, Target},
     performatives::{Attach, Begin, Detach, Disposition, End, Flow, Transfer},
     states::SessionState,
 };
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, watch};
 use tokio::task::JoinHandle;
 
 use crate::{
-    connection::AllocSessionError,
+    connection::{AllocSessionError, ConnectionHandle},
     control::{ConnectionControl, SessionControl},
     endpoint::{
         self, IncomingChannel, InputHandle, LinkFlow, OutgoingChannel, OutputHandle, Session,
@@ -19,23 +19,26 @@ use crate::{
     session::{
         self,
         engine::SessionEngine,
+        error::{AllocLinkError, BeginError, Error, SessionInnerError},
         frame::{SessionFrame, SessionIncomingItem, SessionOutgoingItem},
-        error::{AllocLinkError, BeginError, Error, SessionInnerError}, SessionHandle, 
-        DEFAULT_SESSION_CONTROL_BUFFER_SIZE,
+        FlowCoalescer, FlowCoalescing, LinkInfo, SendBlocking, SessionHandle,
+        SharedTransferMiddleware, TransferIds, DEFAULT_SESSION_CONTROL_BUFFER_SIZE,
     },
     util::Initialized,
     Payload,
 };
 
-use super::{builder::Builder, IncomingSession, ListenerConnectionHandle};
+use super::{
+    builder::Builder, error::AcceptorAttachError, limits::limit_exceeded_error, IncomingSession,
+    LinkAcceptor, LinkEndpoint, LinkNameCollision, ListenerConnectionHandle,
+};
 
 cfg_transaction! {
     use fe2o3_amqp_types::{messaging::Accepted, transaction::TransactionError};
-    
+
     use crate::transaction::{manager::TransactionManager, session::TxnSession, AllocTxnIdError};
 }
 
-
 /// An empty marker trait that acts as a constraint for session engine
 pub trait ListenerSessionEndpoint {}
 
@@ -55,6 +58,18 @@ impl ListenerSessionHandle {
     pub async fn next_incoming_attach(&mut self) -> Option<Attach> {
         self.link_listener.recv().await
     }
+
+    /// Waits for the next incoming link and accepts it with the [`LinkAcceptor`]
+    pub async fn accept_link<FS, FT>(
+        &mut self,
+        acceptor: &LinkAcceptor<FS, FT>,
+    ) -> Result<LinkEndpoint, AcceptorAttachError>
+    where
+        FS: Fn(Source) -> Option<Source>,
+        FT: Fn(Target) -> Option<Target>,
+    {
+        acceptor.accept(self).await
+    }
 }
 
 pub(crate) async fn allocate_incoming_link(
@@ -62,6 +77,7 @@ pub(crate) async fn allocate_incoming_link(
     link_name: String,
     link_relay: LinkRelay<()>,
     input_handle: InputHandle,
+    on_collision: LinkNameCollision,
 ) -> Result<OutputHandle, AllocLinkError> {
     let (responder, resp_rx) = oneshot::channel();
 
@@ -70,6 +86,7 @@ pub(crate) async fn allocate_incoming_link(
             link_name,
             link_relay,
             input_handle,
+            steal_existing: on_collision == LinkNameCollision::Steal,
             responder,
         })
         .await
@@ -159,15 +176,18 @@ impl SessionAcceptor {
             incoming: mpsc::Receiver<SessionFrame>,
             outgoing_link_frames: mpsc::Receiver<LinkFrame>,
         ) -> Result<(JoinHandle<()>, oneshot::Receiver<Result<(), Error>>), BeginError> {
-            let engine = SessionEngine::begin_listener_session(
+            let mut engine = SessionEngine::begin_listener_session(
                 connection.control.clone(),
                 listener_session,
                 session_control_rx,
                 incoming,
-                connection.outgoing.clone(),
+                connection.outgoing.get(self.0.qos_class).clone(),
                 outgoing_link_frames,
+                self.0.flow_coalescing,
             )
             .await?;
+            engine.set_watchdog(connection.watchdog.clone());
+            engine.set_clock(connection.idle_monitor.clock().clone());
             Ok(engine.spawn())
         }
     }
@@ -186,35 +206,44 @@ impl SessionAcceptor {
         ) -> Result<(JoinHandle<()>, oneshot::Receiver<Result<(), Error>>), BeginError> {
             match self.0.control_link_acceptor.clone() {
                 Some(control_link_acceptor) => {
-                    let txn_manager =
-                        TransactionManager::new(control_link_outgoing.clone(), control_link_acceptor);
+                    let txn_manager = TransactionManager::new(
+                        control_link_outgoing.clone(),
+                        control_link_acceptor,
+                        connection.idle_monitor.clock().clone(),
+                    );
                     let listener_session = TxnSession {
                         control: session_control_tx.clone(),
                         session: listener_session,
                         txn_manager,
                     };
-    
-                    let engine = SessionEngine::begin_listener_session(
+
+                    let mut engine = SessionEngine::begin_listener_session(
                         connection.control.clone(),
                         listener_session,
                         session_control_rx,
                         incoming,
-                        connection.outgoing.clone(),
+                        connection.outgoing.get(self.0.qos_class).clone(),
                         outgoing_link_frames,
+                        self.0.flow_coalescing,
                     )
                     .await?;
+                    engine.set_watchdog(connection.watchdog.clone());
+                    engine.set_clock(connection.idle_monitor.clock().clone());
                     Ok(engine.spawn())
                 }
                 None => {
-                    let engine = SessionEngine::begin_listener_session(
+                    let mut engine = SessionEngine::begin_listener_session(
                         connection.control.clone(),
                         listener_session,
                         session_control_rx,
                         incoming,
-                        connection.outgoing.clone(),
+                        connection.outgoing.get(self.0.qos_class).clone(),
                         outgoing_link_frames,
+                        self.0.flow_coalescing,
                     )
                     .await?;
+                    engine.set_watchdog(connection.watchdog.clone());
+                    engine.set_clock(connection.idle_monitor.clock().clone());
                     Ok(engine.spawn())
                 }
             }
@@ -222,12 +251,21 @@ impl SessionAcceptor {
     }
 
     /// Accept an incoming session
-    pub async fn accept_incoming_session(
+    pub async fn accept_incoming_session<R>(
         &self,
         incoming_session: IncomingSession,
-        connection: &mut ListenerConnectionHandle,
+        connection: &mut ConnectionHandle<R>,
     ) -> Result<ListenerSessionHandle, BeginError> {
-        let local_state = SessionState::Unmapped;
+        let limits = connection.limits.as_ref().map(|limits| limits.current());
+        let limit_reached = limits
+            .as_ref()
+            .and_then(|limits| limits.max_sessions_per_connection)
+            .map(|max| connection.sessions.len() >= max)
+            .unwrap_or(false);
+
+        let (state_watch, state) = watch::channel(SessionState::Unmapped);
+        let (send_blocking_watch, send_blocking) = watch::channel(SendBlocking::default());
+        let (transfer_ids_watch, transfer_ids) = watch::channel(TransferIds::default());
         let (session_control_tx, session_control_rx) =
             mpsc::channel::<SessionControl>(DEFAULT_SESSION_CONTROL_BUFFER_SIZE);
         let (incoming_tx, incoming_rx) = mpsc::channel(self.0.buffer_size);
@@ -257,10 +295,21 @@ impl SessionAcceptor {
                 }
             },
         };
-        let mut session = self.0.clone().into_session(outgoing_channel, local_state);
+        let mut builder = self.0.clone();
+        if let Some(limits) = &limits {
+            limits.apply_to_session(&mut builder);
+        }
+        let mut session = builder.into_session(
+            outgoing_channel,
+            state_watch,
+            send_blocking_watch,
+            transfer_ids_watch,
+        );
+        let remote_channel = incoming_session.channel;
+        let remote_begin = incoming_session.begin;
         session.on_incoming_begin(
             IncomingChannel(incoming_session.channel),
-            incoming_session.begin,
+            remote_begin.clone(),
         )?;
 
         let listener_session = ListenerSession {
@@ -280,14 +329,32 @@ impl SessionAcceptor {
             )
             .await?;
 
-        let handle = SessionHandle {
+        connection
+            .sessions
+            .register(outgoing_channel.0, remote_channel, session_control_tx.clone());
+
+        let mut handle = SessionHandle {
             is_ended: false,
             control: session_control_tx,
             engine_handle,
-            outcome,
+            outcome: outcome.into(),
             outgoing: outgoing_tx,
             link_listener: link_listener_rx,
+            remote_begin,
+            state,
+            send_blocking,
+            transfer_ids,
+            resumed_from: None,
+            authenticated_identity: connection.authenticated_identity.clone(),
+            clock: connection.idle_monitor.clock().clone(),
         };
+
+        if limit_reached {
+            // The session is begun so that the End can carry the error
+            let error = limit_exceeded_error("The maximum number of sessions is reached");
+            let _ = handle.end_with_error(error).await;
+            return Err(BeginError::SessionLimitReached);
+        }
         Ok(handle)
     }
 
@@ -318,6 +385,7 @@ where
         incoming: mpsc::Receiver<SessionIncomingItem>,
         outgoing: mpsc::Sender<SessionFrame>,
         outgoing_link_frames: mpsc::Receiver<LinkFrame>,
+        flow_coalescing: Option<FlowCoalescing>,
     ) -> Result<Self, BeginError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("Instantiating session engine");
@@ -330,6 +398,8 @@ where
             incoming,
             outgoing,
             outgoing_link_frames,
+            flow_coalescer: flow_coalescing.map(FlowCoalescer::new),
+            watchdog: None,
         };
 
         // send a begin
@@ -347,7 +417,6 @@ pub struct ListenerSession {
 
 impl endpoint::SessionExt for ListenerSession {}
 
-
 impl endpoint::Session for ListenerSession {
     type AllocError = <session::Session as endpoint::Session>::AllocError;
     type BeginError = <session::Session as endpoint::Session>::BeginError;
@@ -390,6 +459,10 @@ impl endpoint::Session for ListenerSession {
         self.session.deallocate_link(output_handle)
     }
 
+    fn abandon_link(&mut self, input_handle: InputHandle) {
+        self.session.abandon_link(input_handle)
+    }
+
     fn on_incoming_begin(
         &mut self,
         channel: IncomingChannel,
@@ -521,9 +594,62 @@ impl endpoint::Session for ListenerSession {
         self.session.on_outgoing_disposition(disposition)
     }
 
+    fn on_outgoing_settlement(
+        &mut self,
+        input_handle: InputHandle,
+        delivery_tag: DeliveryTag,
+        state: Option<DeliveryState>,
+    ) -> Option<SessionFrame> {
+        self.session
+            .on_outgoing_settlement(input_handle, delivery_tag, state)
+    }
+
     fn on_outgoing_detach(&mut self, detach: Detach) -> SessionFrame {
         self.session.on_outgoing_detach(detach)
     }
+
+    fn links(&self) -> Vec<LinkInfo> {
+        self.session.links()
+    }
+
+    async fn kick_link(
+        &mut self,
+        link_name: &str,
+        error: definitions::Error,
+    ) -> Option<SessionFrame> {
+        self.session.kick_link(link_name, error).await
+    }
+
+    fn on_kicked_link_frame(&mut self, frame: &LinkFrame) -> bool {
+        self.session.on_kicked_link_frame(frame)
+    }
+
+    fn reject_incoming_link(
+        &mut self,
+        link_name: String,
+        role: Role,
+        input_handle: InputHandle,
+        error: definitions::Error,
+    ) -> [SessionFrame; 2] {
+        self.session
+            .reject_incoming_link(link_name, role, input_handle, error)
+    }
+
+    fn has_inbound_backlog(&self) -> bool {
+        self.session.has_inbound_backlog()
+    }
+
+    async fn dispatch_inbound_backlog(&mut self) -> Vec<LinkFlow> {
+        self.session.dispatch_inbound_backlog().await
+    }
+
+    fn hold_outgoing_flow(&mut self, flow: LinkFlow) -> Option<LinkFlow> {
+        self.session.hold_outgoing_flow(flow)
+    }
+
+    fn transfer_middleware(&self) -> Option<SharedTransferMiddleware> {
+        self.session.transfer_middleware()
+    }
 }
 
 cfg_transaction! {
@@ -535,8 +661,8 @@ cfg_transaction! {
             Err(AllocTxnIdError::NotImplemented)
         }
     }
-    
-    
+
+
     impl endpoint::HandleDischarge for ListenerSession {
         async fn commit_transaction(
             &mut self,
@@ -545,7 +671,7 @@ cfg_transaction! {
             // FIXME: This should be impossible
             Ok(Err(TransactionError::UnknownId))
         }
-    
+
         fn rollback_transaction(
             &mut self,
             _txn_id: fe2o3_amqp_types::transaction::TransactionId,
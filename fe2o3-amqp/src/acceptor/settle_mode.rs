@@ -0,0 +1,188 @@
+//! Settle-mode negotiation of the accepted links
+
+use fe2o3_amqp_types::definitions::{ReceiverSettleMode, SenderSettleMode};
+
+/// A combination of sender settle mode and receiver settle mode
+pub type SettleModes = (SenderSettleMode, ReceiverSettleMode);
+
+/// Negotiates the settle modes of the incoming links
+///
+/// The policy lists the combinations of `snd-settle-mode` and `rcv-settle-mode` that the listener
+/// supports. The modes requested in the incoming Attach are kept if their combination is
+/// supported. Otherwise the downgrade rules are applied and the resulting modes are sent back in
+/// the echoed Attach. If the downgraded combination is not supported either, the link is refused
+/// with an Attach that is immediately followed by a Detach carrying `amqp:not-implemented`.
+///
+/// The downgrade rules are looked up in this order
+///
+/// 1. the rule for the requested combination, added with [`SettleModePolicy::downgrade`]
+/// 2. the rules for the requested sender and receiver settle modes, added with
+///    [`SettleModePolicy::downgrade_sender`] and [`SettleModePolicy::downgrade_receiver`]. A mode
+///    without a rule is kept
+///
+/// An acceptor without a policy keeps using the supported and fallback modes that are configured
+/// separately for the sender and the receiver.
+///
+/// # Example
+///
+/// ```rust
+/// use fe2o3_amqp::acceptor::{LinkAcceptor, SettleModePolicy};
+/// use fe2o3_amqp::types::definitions::{ReceiverSettleMode, SenderSettleMode};
+///
+/// // Only unsettled deliveries that are settled first by the receiver are supported
+/// let policy = SettleModePolicy::new()
+///     .support(SenderSettleMode::Unsettled, ReceiverSettleMode::First)
+///     .downgrade_sender(SenderSettleMode::Mixed, SenderSettleMode::Unsettled)
+///     .downgrade_receiver(ReceiverSettleMode::Second, ReceiverSettleMode::First);
+///
+/// let link_acceptor = LinkAcceptor::builder()
+///     .settle_mode_policy(policy)
+///     .build();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SettleModePolicy {
+    supported: Vec<SettleModes>,
+    downgrades: Vec<(SettleModes, SettleModes)>,
+    sender_downgrades: Vec<(SenderSettleMode, SenderSettleMode)>,
+    receiver_downgrades: Vec<(ReceiverSettleMode, ReceiverSettleMode)>,
+}
+
+impl SettleModePolicy {
+    /// Creates a policy that supports no combination
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a supported combination
+    pub fn support(
+        mut self,
+        snd_settle_mode: SenderSettleMode,
+        rcv_settle_mode: ReceiverSettleMode,
+    ) -> Self {
+        let modes = (snd_settle_mode, rcv_settle_mode);
+        if !self.supported.contains(&modes) {
+            self.supported.push(modes);
+        }
+        self
+    }
+
+    /// Downgrades the requested combination `from` to `to`. This replaces the rule that is
+    /// already set for `from`
+    pub fn downgrade(mut self, from: SettleModes, to: SettleModes) -> Self {
+        self.downgrades.retain(|(f, _)| *f != from);
+        self.downgrades.push((from, to));
+        self
+    }
+
+    /// Downgrades the requested sender settle mode `from` to `to` if the requested combination
+    /// has no rule of its own. This replaces the rule that is already set for `from`
+    pub fn downgrade_sender(mut self, from: SenderSettleMode, to: SenderSettleMode) -> Self {
+        self.sender_downgrades.retain(|(f, _)| *f != from);
+        self.sender_downgrades.push((from, to));
+        self
+    }
+
+    /// Downgrades the requested receiver settle mode `from` to `to` if the requested combination
+    /// has no rule of its own. This replaces the rule that is already set for `from`
+    pub fn downgrade_receiver(mut self, from: ReceiverSettleMode, to: ReceiverSettleMode) -> Self {
+        self.receiver_downgrades.retain(|(f, _)| *f != from);
+        self.receiver_downgrades.push((from, to));
+        self
+    }
+
+    /// Whether the combination is supported
+    pub fn supports(
+        &self,
+        snd_settle_mode: &SenderSettleMode,
+        rcv_settle_mode: &ReceiverSettleMode,
+    ) -> bool {
+        self.supported
+            .iter()
+            .any(|(snd, rcv)| snd == snd_settle_mode && rcv == rcv_settle_mode)
+    }
+
+    /// Returns the modes that are sent back for the requested modes, or `None` if the link is
+    /// refused
+    pub fn negotiate(
+        &self,
+        snd_settle_mode: &SenderSettleMode,
+        rcv_settle_mode: &ReceiverSettleMode,
+    ) -> Option<SettleModes> {
+        if self.supports(snd_settle_mode, rcv_settle_mode) {
+            return Some((snd_settle_mode.clone(), rcv_settle_mode.clone()));
+        }
+
+        let (snd, rcv) = self
+            .downgrades
+            .iter()
+            .find(|((snd, rcv), _)| snd == snd_settle_mode && rcv == rcv_settle_mode)
+            .map(|(_, to)| to.clone())
+            .unwrap_or_else(|| {
+                let snd = self
+                    .sender_downgrades
+                    .iter()
+                    .find(|(from, _)| from == snd_settle_mode)
+                    .map(|(_, to)| to)
+                    .unwrap_or(snd_settle_mode);
+                let rcv = self
+                    .receiver_downgrades
+                    .iter()
+                    .find(|(from, _)| from == rcv_settle_mode)
+                    .map(|(_, to)| to)
+                    .unwrap_or(rcv_settle_mode);
+                (snd.clone(), rcv.clone())
+            });
+        self.supports(&snd, &rcv).then_some((snd, rcv))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::definitions::{ReceiverSettleMode, SenderSettleMode};
+
+    use super::SettleModePolicy;
+
+    #[test]
+    fn unsupported_modes_are_downgraded_or_refused() {
+        let policy = SettleModePolicy::new()
+            .support(SenderSettleMode::Unsettled, ReceiverSettleMode::First)
+            .support(SenderSettleMode::Settled, ReceiverSettleMode::First)
+            .downgrade_sender(SenderSettleMode::Mixed, SenderSettleMode::Unsettled)
+            .downgrade_receiver(ReceiverSettleMode::Second, ReceiverSettleMode::First)
+            .downgrade(
+                (SenderSettleMode::Mixed, ReceiverSettleMode::Second),
+                (SenderSettleMode::Settled, ReceiverSettleMode::First),
+            );
+
+        // Supported combinations are kept
+        assert_eq!(
+            policy.negotiate(&SenderSettleMode::Settled, &ReceiverSettleMode::First),
+            Some((SenderSettleMode::Settled, ReceiverSettleMode::First))
+        );
+        // The rule of the combination takes precedence over the rules of each mode
+        assert_eq!(
+            policy.negotiate(&SenderSettleMode::Mixed, &ReceiverSettleMode::Second),
+            Some((SenderSettleMode::Settled, ReceiverSettleMode::First))
+        );
+        assert_eq!(
+            policy.negotiate(&SenderSettleMode::Mixed, &ReceiverSettleMode::First),
+            Some((SenderSettleMode::Unsettled, ReceiverSettleMode::First))
+        );
+        assert_eq!(
+            policy.negotiate(&SenderSettleMode::Unsettled, &ReceiverSettleMode::Second),
+            Some((SenderSettleMode::Unsettled, ReceiverSettleMode::First))
+        );
+
+        let policy = SettleModePolicy::new()
+            .support(SenderSettleMode::Unsettled, ReceiverSettleMode::Second)
+            .downgrade_sender(SenderSettleMode::Mixed, SenderSettleMode::Settled);
+        assert_eq!(
+            policy.negotiate(&SenderSettleMode::Mixed, &ReceiverSettleMode::Second),
+            None
+        );
+        assert_eq!(
+            policy.negotiate(&SenderSettleMode::Unsettled, &ReceiverSettleMode::First),
+            None
+        );
+    }
+}
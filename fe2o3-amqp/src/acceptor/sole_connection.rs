@@ -0,0 +1,106 @@
+//! Enforcement of the `sole-connection-for-container` capability on the listener side
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::mpsc;
+
+use crate::{
+    connection::sole_connection::{self, SoleConnectionEnforcementPolicy},
+    control::ConnectionControl,
+};
+
+/// Keeps track of the open connections by container id so that at most one connection is
+/// allowed for a container id that desires the `sole-connection-for-container` capability
+///
+/// The enforcement is shared by all clones, so the same instance (or its clones) should be used
+/// for every [`ConnectionAcceptor`](super::ConnectionAcceptor) that must enforce the uniqueness.
+///
+/// # Example
+///
+/// ```rust
+/// use fe2o3_amqp::acceptor::ConnectionAcceptor;
+/// use fe2o3_amqp::connection::sole_connection::SoleConnectionEnforcementPolicy;
+///
+/// let connection_acceptor = ConnectionAcceptor::builder()
+///     .container_id("example-listener")
+///     .sole_connection_enforcement(SoleConnectionEnforcementPolicy::CloseExisting)
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct SoleConnectionEnforcement {
+    policy: SoleConnectionEnforcementPolicy,
+    connections: Arc<Mutex<HashMap<String, mpsc::Sender<ConnectionControl>>>>,
+}
+
+/// The result of checking a new connection against the existing connections
+pub(crate) enum Admission {
+    Accept,
+    Refuse,
+}
+
+impl SoleConnectionEnforcement {
+    /// Creates a new [`SoleConnectionEnforcement`] without any connection
+    pub fn new(policy: SoleConnectionEnforcementPolicy) -> Self {
+        Self {
+            policy,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The policy that is applied when the container id is already in use
+    pub fn policy(&self) -> SoleConnectionEnforcementPolicy {
+        self.policy
+    }
+
+    /// Whether there is an open connection for the container id
+    pub fn contains(&self, container_id: &str) -> bool {
+        let connections = self.lock_connections();
+        connections
+            .get(container_id)
+            .map(|control| !control.is_closed())
+            .unwrap_or(false)
+    }
+
+    /// Registers a new connection. If the new connection desires the sole connection and there
+    /// is already an open connection for the container id, the new connection is either refused
+    /// or the existing connection is closed according to the policy.
+    pub(crate) fn admit(
+        &self,
+        container_id: &str,
+        desired: bool,
+        control: &mpsc::Sender<ConnectionControl>,
+    ) -> Admission {
+        let mut connections = self.lock_connections();
+        // Connections whose event loop has stopped are no longer relevant
+        connections.retain(|_, control| !control.is_closed());
+
+        if desired {
+            if let Some(existing) = connections.get(container_id) {
+                match self.policy {
+                    SoleConnectionEnforcementPolicy::RefuseConnection => return Admission::Refuse,
+                    SoleConnectionEnforcementPolicy::CloseExisting => {
+                        let error = sole_connection::close_existing_error();
+                        // The close is best effort. The existing connection is replaced even if
+                        // the control buffer is full
+                        let _ = existing.try_send(ConnectionControl::Close(Some(error)));
+                    }
+                }
+            }
+        }
+
+        connections.insert(container_id.to_string(), control.clone());
+        Admission::Accept
+    }
+
+    fn lock_connections(
+        &self,
+    ) -> std::sync::MutexGuard<'_, HashMap<String, mpsc::Sender<ConnectionControl>>> {
+        // The map is always left in a consistent state, so a poisoned lock can be recovered
+        self.connections
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -0,0 +1,364 @@
+//! Fan-out of the messages published to a topic-like node to the subscribed links
+//!
+//! [`SubscriptionRouter`] provides the core of a topic broker for embedded use. The local senders
+//! of the accepted links subscribe with a wildcard pattern (by default the source address that the
+//! remote receiver attached with) and the messages received by the local receivers are published
+//! with a routing key. Every message is forwarded to all subscriptions whose pattern matches the
+//! routing key.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let router = SubscriptionRouter::new();
+//! loop {
+//!     match session.accept_link(&link_acceptor).await.unwrap() {
+//!         // The remote receiver attached with a source address like "orders.*"
+//!         LinkEndpoint::Sender(sender) => {
+//!             let subscription = router.subscribe(sender).unwrap();
+//!             subscriptions.push(subscription);
+//!         }
+//!         LinkEndpoint::Receiver(mut receiver) => {
+//!             let router = router.clone();
+//!             tokio::spawn(async move { router.route(&mut receiver).await });
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use fe2o3_amqp_types::{
+    definitions::{self, AmqpError},
+    messaging::{annotations::AnnotationKey, Body, Message},
+    primitives::Value,
+};
+use parking_lot::RwLock;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::link::{Receiver, SendError, Sender};
+
+use super::error::{InvalidTopicPattern, RouterError};
+
+/// Key of the routing key in the message annotations
+pub const ROUTING_KEY: &str = "x-opt-routing-key";
+
+/// Default number of messages that are buffered for each subscription
+pub const DEFAULT_SUBSCRIPTION_BUFFER_SIZE: usize = 64;
+
+/// A wildcard pattern that is matched against routing keys
+///
+/// Both the pattern and the routing keys are made of words separated by `.`. In a pattern, the
+/// word `*` matches exactly one word and the word `#` matches zero or more words, so `orders.*`
+/// matches `orders.created` but not `orders` or `orders.eu.created`, while `logs.#` matches
+/// `logs`, `logs.error` and `logs.app.error`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TopicPattern {
+    pattern: String,
+}
+
+impl TopicPattern {
+    /// Parses a pattern. A pattern must not be empty or contain an empty word, and `*` and `#`
+    /// must make up a whole word
+    pub fn new(pattern: impl Into<String>) -> Result<Self, InvalidTopicPattern> {
+        let pattern = pattern.into();
+        let is_valid = pattern.split('.').all(|word| {
+            !word.is_empty() && (word == "*" || word == "#" || !word.contains(['*', '#']))
+        });
+        match is_valid {
+            true => Ok(Self { pattern }),
+            false => Err(InvalidTopicPattern(pattern)),
+        }
+    }
+
+    /// The pattern as a string
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Whether the routing key matches the pattern
+    pub fn matches(&self, routing_key: &str) -> bool {
+        let pattern: Vec<&str> = self.pattern.split('.').collect();
+        let words: Vec<&str> = routing_key.split('.').collect();
+        matches_words(&pattern, &words)
+    }
+}
+
+impl fmt::Display for TopicPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+fn matches_words(pattern: &[&str], words: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => words.is_empty(),
+        Some((&"#", rest)) => {
+            (0..=words.len()).any(|skipped| matches_words(rest, &words[skipped..]))
+        }
+        Some((first, rest)) => match words.split_first() {
+            Some((word, words)) => (*first == "*" || first == word) && matches_words(rest, words),
+            None => false,
+        },
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    id: u64,
+    pattern: TopicPattern,
+    messages: mpsc::Sender<Message<Body<Value>>>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    next_id: AtomicU64,
+    entries: RwLock<Vec<Entry>>,
+}
+
+impl Shared {
+    fn remove(&self, id: u64) {
+        self.entries.write().retain(|entry| entry.id != id);
+    }
+}
+
+/// Routes the published messages to the subscriptions with a matching [`TopicPattern`]
+///
+/// Each subscription owns a local [`Sender`] and a task that sends the forwarded messages in the
+/// order they were published. The messages waiting for a subscription are buffered up to
+/// `buffer_size`; publishing waits while the buffer of a matching subscription is full, so a slow
+/// subscriber slows down the publishers instead of losing messages.
+///
+/// The router is cheap to clone and all clones share the same subscriptions.
+#[derive(Debug, Clone)]
+pub struct SubscriptionRouter {
+    shared: Arc<Shared>,
+    buffer_size: usize,
+}
+
+impl Default for SubscriptionRouter {
+    fn default() -> Self {
+        Self {
+            shared: Default::default(),
+            buffer_size: DEFAULT_SUBSCRIPTION_BUFFER_SIZE,
+        }
+    }
+}
+
+impl SubscriptionRouter {
+    /// Creates a router without any subscription
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of messages that are buffered for each subscription
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buffer_size` is zero
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        assert!(buffer_size > 0, "buffer_size must be greater than zero");
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// The number of active subscriptions
+    pub fn subscription_count(&self) -> usize {
+        self.shared.entries.read().len()
+    }
+
+    /// Subscribes the sender with the source address that the remote receiver attached with as the
+    /// pattern
+    pub fn subscribe(&self, sender: Sender) -> Result<Subscription, InvalidTopicPattern> {
+        let address = sender
+            .source()
+            .as_ref()
+            .and_then(|source| source.address.clone())
+            .unwrap_or_default();
+        let pattern = TopicPattern::new(address)?;
+        Ok(self.subscribe_with_pattern(pattern, sender))
+    }
+
+    /// Subscribes the sender with the pattern
+    pub fn subscribe_with_pattern(&self, pattern: TopicPattern, sender: Sender) -> Subscription {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        let (messages, outgoing) = mpsc::channel(self.buffer_size);
+        self.shared.entries.write().push(Entry {
+            id,
+            pattern: pattern.clone(),
+            messages,
+        });
+        let task = tokio::spawn(forward(sender, outgoing));
+        Subscription {
+            id,
+            pattern,
+            shared: self.shared.clone(),
+            task: Some(task),
+        }
+    }
+
+    /// Forwards the message to all subscriptions that match the routing key and returns the number
+    /// of subscriptions it is forwarded to
+    pub async fn publish(&self, routing_key: &str, message: Message<Body<Value>>) -> usize {
+        let matching: Vec<_> = self
+            .shared
+            .entries
+            .read()
+            .iter()
+            .filter(|entry| entry.pattern.matches(routing_key))
+            .map(|entry| (entry.id, entry.messages.clone()))
+            .collect();
+
+        let mut count = 0;
+        for (id, messages) in matching {
+            match messages.send(message.clone()).await {
+                Ok(()) => count += 1,
+                // The task of the subscription has stopped
+                Err(_) => self.shared.remove(id),
+            }
+        }
+        count
+    }
+
+    /// Publishes the messages received by the receiver until the receiver fails
+    ///
+    /// The routing key of a message is taken from the [`ROUTING_KEY`] message annotation, then
+    /// from the `to` field of the properties and then from the target address of the receiver. A
+    /// message is accepted once it is forwarded, even if no subscription matches its routing key,
+    /// and is rejected with `amqp:not-found` if it has no routing key.
+    pub async fn route(&self, receiver: &mut Receiver) -> RouterError {
+        loop {
+            let delivery = match receiver.recv::<Body<Value>>().await {
+                Ok(delivery) => delivery,
+                Err(error) => return error.into(),
+            };
+            let result = match routing_key(delivery.message(), receiver) {
+                Some(routing_key) => {
+                    self.publish(&routing_key, delivery.message().clone()).await;
+                    receiver.accept(&delivery).await
+                }
+                None => {
+                    let error = definitions::Error::new(
+                        AmqpError::NotFound,
+                        Some(String::from("The message has no routing key")),
+                        None,
+                    );
+                    receiver.reject(&delivery, error).await
+                }
+            };
+            if let Err(error) = result {
+                return error.into();
+            }
+        }
+    }
+}
+
+fn routing_key<T>(message: &Message<T>, receiver: &Receiver) -> Option<String> {
+    let annotation = message
+        .message_annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(&ROUTING_KEY as &dyn AnnotationKey));
+    match annotation {
+        Some(Value::String(key)) => return Some(key.clone()),
+        Some(Value::Symbol(key)) => return Some(key.to_string()),
+        _ => {}
+    }
+    if let Some(to) = message.properties.as_ref().and_then(|p| p.to.clone()) {
+        return Some(to);
+    }
+    receiver
+        .target()
+        .as_ref()
+        .and_then(|target| target.address.clone())
+}
+
+async fn forward(
+    mut sender: Sender,
+    mut messages: mpsc::Receiver<Message<Body<Value>>>,
+) -> (Sender, Result<(), SendError>) {
+    while let Some(message) = messages.recv().await {
+        if let Err(error) = sender.send(message).await {
+            return (sender, Err(error));
+        }
+    }
+    (sender, Ok(()))
+}
+
+/// A subscription of a [`SubscriptionRouter`]
+///
+/// Dropping the subscription removes it from the router. The messages that are already buffered
+/// are still sent before the sender is dropped.
+#[derive(Debug)]
+pub struct Subscription {
+    id: u64,
+    pattern: TopicPattern,
+    shared: Arc<Shared>,
+    task: Option<JoinHandle<(Sender, Result<(), SendError>)>>,
+}
+
+impl Subscription {
+    /// The pattern of the subscription
+    pub fn pattern(&self) -> &TopicPattern {
+        &self.pattern
+    }
+
+    /// Whether the subscription has stopped forwarding messages, which happens when sending a
+    /// message fails
+    pub fn is_finished(&self) -> bool {
+        self.task.as_ref().map_or(true, |task| task.is_finished())
+    }
+
+    /// Removes the subscription from the router, waits for the buffered messages to be sent and
+    /// returns the sender, together with the error that stopped the forwarding if there is any
+    ///
+    /// # Panics
+    ///
+    /// Panics if the forwarding task panicked
+    pub async fn unsubscribe(mut self) -> (Sender, Result<(), SendError>) {
+        self.shared.remove(self.id);
+        let task = self.task.take().expect("The task is only taken once");
+        match task.await {
+            Ok(result) => result,
+            Err(error) => std::panic::resume_unwind(error.into_panic()),
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.shared.remove(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TopicPattern;
+
+    #[test]
+    fn wildcards_match_words() {
+        let pattern = TopicPattern::new("orders.*").unwrap();
+        assert!(pattern.matches("orders.created"));
+        assert!(!pattern.matches("orders"));
+        assert!(!pattern.matches("orders.eu.created"));
+
+        let pattern = TopicPattern::new("logs.#").unwrap();
+        assert!(pattern.matches("logs"));
+        assert!(pattern.matches("logs.error"));
+        assert!(pattern.matches("logs.app.error"));
+        assert!(!pattern.matches("metrics.error"));
+
+        let pattern = TopicPattern::new("#.error.*").unwrap();
+        assert!(pattern.matches("error.db"));
+        assert!(pattern.matches("app.eu.error.db"));
+        assert!(!pattern.matches("app.error"));
+
+        assert!(TopicPattern::new("").is_err());
+        assert!(TopicPattern::new("orders..created").is_err());
+        assert!(TopicPattern::new("orders.created*").is_err());
+    }
+}
@@ -0,0 +1,537 @@
+//! Routing of incoming connections to per virtual host connection acceptors
+
+use std::{collections::HashMap, io};
+
+use fe2o3_amqp_types::{
+    definitions::{self, AmqpError},
+    performatives::{ChannelMax, MaxFrameSize, Open},
+    primitives::{Array, Symbol},
+    sasl::{SaslCode, SaslMechanisms, SaslOutcome},
+    states::ConnectionState,
+};
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+use crate::{
+    acceptor::sasl_acceptor::SaslServerFrame,
+    connection::{
+        engine::recv_remote_open, OpenError, DEFAULT_CHANNEL_MAX, DEFAULT_MAX_FRAME_SIZE,
+    },
+    frames::sasl,
+    transport::{protocol_header::ProtocolHeaderCodec, Transport},
+};
+
+use super::{
+    connection::{send_refusal, ListenerConnectionHandle},
+    sasl_acceptor::SaslAcceptor,
+    ConnectionAcceptor,
+};
+
+/// Acceptor that allows one listener to host multiple logical AMQP containers (virtual hosts)
+///
+/// Each virtual host is served by its own [`ConnectionAcceptor`], which means that the
+/// container id, capabilities, resource limits (eg. `max_frame_size`, `channel_max`) and SASL
+/// acceptor can be different for each virtual host. An incoming connection is routed using the
+/// first hostname that becomes available during the connection negotiation:
+///
+/// 1. the TLS SNI (only with the `"rustls"` TLS acceptor),
+/// 2. the `hostname` field of the SASL init frame, if SASL is used,
+/// 3. the `hostname` field of the remote Open frame, if SASL is not used.
+///
+/// Hostnames are matched case-insensitively. A connection whose hostname doesn't match any
+/// virtual host is handed to the default acceptor if there is one. Otherwise, the connection is
+/// refused with an Open that is immediately followed by a Close with `amqp:not-found`, or with
+/// the `auth` SASL outcome if the hostname comes from the SASL init frame.
+///
+/// Because the SASL mechanisms must be sent before the SASL init frame is received, the
+/// mechanisms advertised to a connection that is not routed by SNI are the union of the
+/// mechanisms of all virtual hosts.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use tokio::net::TcpListener;
+/// use fe2o3_amqp::acceptor::{ConnectionAcceptor, VirtualHostAcceptor};
+///
+/// let acceptor = VirtualHostAcceptor::new()
+///     .virtual_host("tenant-a.example.com", ConnectionAcceptor::new("tenant-a"))
+///     .virtual_host(
+///         "tenant-b.example.com",
+///         ConnectionAcceptor::builder()
+///             .container_id("tenant-b")
+///             .max_frame_size(4096)
+///             .build(),
+///     );
+///
+/// let tcp_listener = TcpListener::bind("localhost:5672").await.unwrap();
+/// if let Ok((stream, addr)) = tcp_listener.accept().await {
+///     let connection = acceptor.accept(stream).await.unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct VirtualHostAcceptor<Tls, Sasl> {
+    /// TLS acceptor that handles TLS negotiation for all virtual hosts
+    pub tls_acceptor: Tls,
+
+    /// Connection acceptors by the (lower case) hostname of the virtual host
+    pub virtual_hosts: HashMap<String, ConnectionAcceptor<(), Sasl>>,
+
+    /// Connection acceptor for connections that don't match any virtual host
+    pub default_acceptor: Option<ConnectionAcceptor<(), Sasl>>,
+}
+
+impl<Sasl> Default for VirtualHostAcceptor<(), Sasl> {
+    fn default() -> Self {
+        Self {
+            tls_acceptor: (),
+            virtual_hosts: HashMap::new(),
+            default_acceptor: None,
+        }
+    }
+}
+
+impl<Sasl> VirtualHostAcceptor<(), Sasl> {
+    /// Creates a [`VirtualHostAcceptor`] without any virtual host
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<Tls, Sasl> VirtualHostAcceptor<Tls, Sasl> {
+    /// Adds a virtual host. The acceptor previously added for the same hostname is replaced
+    pub fn virtual_host(
+        mut self,
+        hostname: impl AsRef<str>,
+        acceptor: ConnectionAcceptor<(), Sasl>,
+    ) -> Self {
+        self.virtual_hosts
+            .insert(hostname.as_ref().to_ascii_lowercase(), acceptor);
+        self
+    }
+
+    /// Sets the acceptor for connections that don't match any virtual host
+    pub fn default_acceptor(mut self, acceptor: ConnectionAcceptor<(), Sasl>) -> Self {
+        self.default_acceptor = Some(acceptor);
+        self
+    }
+
+    /// Sets the TLS acceptor that is shared by all virtual hosts
+    pub fn tls_acceptor<T>(self, tls_acceptor: T) -> VirtualHostAcceptor<T, Sasl> {
+        VirtualHostAcceptor {
+            tls_acceptor,
+            virtual_hosts: self.virtual_hosts,
+            default_acceptor: self.default_acceptor,
+        }
+    }
+
+    /// Finds the acceptor for the hostname, falling back to the default acceptor
+    pub fn resolve(&self, hostname: Option<&str>) -> Option<&ConnectionAcceptor<(), Sasl>> {
+        hostname
+            .and_then(|hostname| self.find_virtual_host(hostname))
+            .or(self.default_acceptor.as_ref())
+    }
+
+    fn find_virtual_host(&self, hostname: &str) -> Option<&ConnectionAcceptor<(), Sasl>> {
+        self.virtual_hosts.get(&hostname.to_ascii_lowercase())
+    }
+
+    async fn negotiate_amqp_with_framed<Io>(
+        &self,
+        framed_write: FramedWrite<WriteHalf<Io>, ProtocolHeaderCodec>,
+        framed_read: FramedRead<ReadHalf<Io>, ProtocolHeaderCodec>,
+        acceptor: Option<&ConnectionAcceptor<(), Sasl>>,
+    ) -> Result<ListenerConnectionHandle, OpenError>
+    where
+        Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+    {
+        if let Some(acceptor) = acceptor {
+            return acceptor
+                .negotiate_amqp_with_framed(framed_write, framed_read)
+                .await;
+        }
+
+        // The virtual host is selected by the hostname in the remote Open, so the local Open
+        // can only be sent after the remote Open is received
+        let mut local_state = ConnectionState::Start;
+        let mut transport =
+            Transport::negotiate_amqp_header(framed_write, framed_read, &mut local_state, None)
+                .await?;
+        let (channel, remote_open) = recv_remote_open(&mut transport).await?;
+
+        let acceptor = match self.resolve(remote_open.hostname.as_deref()) {
+            Some(acceptor) => acceptor,
+            None => {
+                let hostname = remote_open.hostname;
+                let error = definitions::Error::new(
+                    AmqpError::NotFound,
+                    format!("Virtual host {:?} is not found", hostname),
+                    None,
+                );
+                return Err(
+                    match send_refusal(&mut transport, refusal_open(), error).await {
+                        Ok(()) => OpenError::VirtualHostNotFound(hostname),
+                        Err(error) => error,
+                    },
+                );
+            }
+        };
+        if let Some(idle_timeout) = acceptor.local_idle_timeout() {
+            transport.set_idle_timeout(idle_timeout);
+        }
+        acceptor
+            .open_with_transport(transport, local_state, Some((channel, remote_open)))
+            .await
+    }
+
+    async fn negotiate_amqp_with_stream<Io>(
+        &self,
+        stream: Io,
+        server_name: Option<&str>,
+    ) -> Result<ListenerConnectionHandle, OpenError>
+    where
+        Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let framed_write = FramedWrite::new(writer, ProtocolHeaderCodec::new());
+        let framed_read = FramedRead::new(reader, ProtocolHeaderCodec::new());
+        let acceptor = server_name.and_then(|hostname| self.find_virtual_host(hostname));
+        self.negotiate_amqp_with_framed(framed_write, framed_read, acceptor)
+            .await
+    }
+}
+
+impl<Tls, Sasl> VirtualHostAcceptor<Tls, Sasl>
+where
+    Sasl: SaslAcceptor,
+{
+    /// Collects the SASL mechanisms supported by any of the virtual hosts
+    fn sasl_mechanisms(&self) -> SaslMechanisms {
+        let mut mechanisms: Vec<Symbol> = Vec::new();
+        let acceptors = self
+            .virtual_hosts
+            .values()
+            .chain(self.default_acceptor.iter());
+        for acceptor in acceptors {
+            for mechanism in acceptor.sasl_acceptor.mechanisms().0 {
+                if !mechanisms.contains(&mechanism) {
+                    mechanisms.push(mechanism);
+                }
+            }
+        }
+
+        if mechanisms.is_empty() {
+            SaslMechanisms::default()
+        } else {
+            SaslMechanisms {
+                sasl_server_mechanisms: Array(mechanisms),
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn negotiate_sasl_with_framed<Io>(
+        &self,
+        framed_write: FramedWrite<WriteHalf<Io>, ProtocolHeaderCodec>,
+        framed_read: FramedRead<ReadHalf<Io>, ProtocolHeaderCodec>,
+        acceptor: Option<&ConnectionAcceptor<(), Sasl>>,
+    ) -> Result<ListenerConnectionHandle, OpenError>
+    where
+        Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+    {
+        if let Some(acceptor) = acceptor {
+            return acceptor
+                .negotiate_sasl_with_framed(framed_write, framed_read)
+                .await;
+        }
+
+        let mut transport = Transport::negotiate_sasl_header(framed_write, framed_read).await?;
+
+        // Send mechanisms
+        let frame = sasl::Frame::Mechanisms(self.sasl_mechanisms());
+        #[cfg(feature = "tracing")]
+        tracing::trace!(sending = ?frame);
+        #[cfg(feature = "log")]
+        log::trace!("sending = {:?}", frame);
+        transport.send(frame).await?;
+
+        // The virtual host is selected by the hostname in the SASL init
+        let init = match transport.next().await.ok_or_else(|| {
+            OpenError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Expecting SASL frames",
+            ))
+        })?? {
+            sasl::Frame::Init(init) => init,
+            _ => {
+                let outcome = SaslOutcome {
+                    code: SaslCode::Sys,
+                    additional_data: None,
+                };
+                transport.send(sasl::Frame::Outcome(outcome)).await?;
+                return Err(OpenError::SaslError {
+                    code: SaslCode::Sys,
+                    additional_data: None,
+                });
+            }
+        };
+        let acceptor = match self.resolve(init.hostname.as_deref()) {
+            Some(acceptor) => acceptor,
+            None => {
+                let outcome = SaslOutcome {
+                    code: SaslCode::Auth,
+                    additional_data: None,
+                };
+                transport.send(sasl::Frame::Outcome(outcome)).await?;
+                return Err(OpenError::SaslError {
+                    code: SaslCode::Auth,
+                    additional_data: None,
+                });
+            }
+        };
+
+        let mut sasl_acceptor = acceptor.sasl_acceptor.clone();
+        let mut server_frame = sasl_acceptor.on_init(init);
+        loop {
+            match server_frame {
+                SaslServerFrame::Challenge(challenge) => {
+                    let frame = sasl::Frame::Challenge(challenge);
+                    #[cfg(feature = "tracing")]
+                    tracing::trace!(sending = ?frame);
+                    #[cfg(feature = "log")]
+                    log::trace!("sending = {:?}", frame);
+                    transport.send(frame).await?;
+                }
+                SaslServerFrame::Outcome(outcome) => {
+                    let frame = sasl::Frame::Outcome(outcome);
+                    #[cfg(feature = "tracing")]
+                    tracing::trace!(sending = ?frame);
+                    #[cfg(feature = "log")]
+                    log::trace!("sending = {:?}", frame);
+                    transport.send(frame).await?;
+                    break;
+                }
+            }
+
+            server_frame = match transport.next().await.ok_or_else(|| {
+                OpenError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Expecting SASL frames",
+                ))
+            })?? {
+                sasl::Frame::Response(response) => sasl_acceptor.on_response(response),
+                _ => {
+                    let outcome = SaslOutcome {
+                        code: SaslCode::Sys,
+                        additional_data: None,
+                    };
+                    transport.send(sasl::Frame::Outcome(outcome)).await?;
+                    return Err(OpenError::SaslError {
+                        code: SaslCode::Sys,
+                        additional_data: None,
+                    });
+                }
+            };
+        }
+
+        let (framed_write, framed_read) = transport.into_framed_codec();
+        let framed_write = framed_write.map_encoder(|_| ProtocolHeaderCodec::new());
+        let framed_read = framed_read.map_decoder(|_| ProtocolHeaderCodec::new());
+        let mut connection = acceptor
+            .negotiate_amqp_with_framed(framed_write, framed_read)
+            .await?;
+        connection.authenticated_identity = sasl_acceptor.authenticated_identity();
+        Ok(connection)
+    }
+
+    async fn negotiate_sasl_with_stream<Io>(
+        &self,
+        stream: Io,
+        server_name: Option<&str>,
+    ) -> Result<ListenerConnectionHandle, OpenError>
+    where
+        Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let framed_write = FramedWrite::new(writer, ProtocolHeaderCodec::new());
+        let framed_read = FramedRead::new(reader, ProtocolHeaderCodec::new());
+        let acceptor = server_name.and_then(|hostname| self.find_virtual_host(hostname));
+        self.negotiate_sasl_with_framed(framed_write, framed_read, acceptor)
+            .await
+    }
+}
+
+// A macro is used instead of blanked impl with trait to avoid heap allocated future
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+macro_rules! connect_tls {
+    ($fn_ident:ident, $next_proto_header_handler:ident, $server_name:expr) => {
+        async fn $fn_ident<Io>(&self, mut stream: Io) -> Result<ListenerConnectionHandle, OpenError>
+        where
+            Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+        {
+            use crate::transport::protocol_header::ProtocolHeader;
+            use tokio::io::AsyncWriteExt;
+
+            let incoming_header = crate::transport::recv_tls_proto_header(&mut stream).await?;
+
+            let tls_header = ProtocolHeader::tls();
+            if tls_header != incoming_header {
+                let buf: [u8; 8] = tls_header.into();
+                stream.write_all(&buf).await?;
+                return Err(OpenError::ProtocolHeaderMismatch(incoming_header.into()));
+            }
+
+            // Send protocol header
+            let buf: [u8; 8] = tls_header.into();
+            stream.write_all(&buf).await?;
+
+            let tls_stream = self.tls_acceptor.accept(stream).await.map_err(|e| {
+                OpenError::Io(io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))
+            })?;
+
+            let server_name: Option<String> = $server_name(&tls_stream);
+            self.$next_proto_header_handler(tls_stream, server_name.as_deref())
+                .await
+        }
+    };
+}
+
+cfg_native_tls! {
+    // `native-tls` doesn't expose the SNI sent by the client
+    fn native_tls_server_name<Io>(_: &tokio_native_tls::TlsStream<Io>) -> Option<String> {
+        None
+    }
+
+    impl VirtualHostAcceptor<tokio_native_tls::TlsAcceptor, ()> {
+        connect_tls!(negotiate_tls_with_native_tls, negotiate_amqp_with_stream, native_tls_server_name);
+
+        /// Accepts an incoming connection
+        pub async fn accept<Io>(&self, stream: Io) -> Result<ListenerConnectionHandle, OpenError>
+        where
+            Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+        {
+            self.negotiate_tls_with_native_tls(stream).await
+        }
+    }
+
+    impl<Sasl> VirtualHostAcceptor<tokio_native_tls::TlsAcceptor, Sasl>
+    where
+        Sasl: SaslAcceptor,
+    {
+        connect_tls!(negotiate_tls_with_native_tls, negotiate_sasl_with_stream, native_tls_server_name);
+
+        /// Accepts an incoming connection
+        pub async fn accept<Io>(&self, stream: Io) -> Result<ListenerConnectionHandle, OpenError>
+        where
+            Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+        {
+            self.negotiate_tls_with_native_tls(stream).await
+        }
+    }
+}
+
+cfg_rustls! {
+    fn rustls_server_name<Io>(tls_stream: &tokio_rustls::server::TlsStream<Io>) -> Option<String> {
+        tls_stream.get_ref().1.server_name().map(ToOwned::to_owned)
+    }
+
+    impl VirtualHostAcceptor<tokio_rustls::TlsAcceptor, ()> {
+        connect_tls!(negotiate_tls_with_rustls, negotiate_amqp_with_stream, rustls_server_name);
+
+        /// Accepts an incoming connection
+        pub async fn accept<Io>(&self, stream: Io) -> Result<ListenerConnectionHandle, OpenError>
+        where
+            Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+        {
+            self.negotiate_tls_with_rustls(stream).await
+        }
+    }
+
+    impl<Sasl> VirtualHostAcceptor<tokio_rustls::TlsAcceptor, Sasl>
+    where
+        Sasl: SaslAcceptor,
+    {
+        connect_tls!(negotiate_tls_with_rustls, negotiate_sasl_with_stream, rustls_server_name);
+
+        /// Accepts an incoming connection
+        pub async fn accept<Io>(&self, stream: Io) -> Result<ListenerConnectionHandle, OpenError>
+        where
+            Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+        {
+            self.negotiate_tls_with_rustls(stream).await
+        }
+    }
+}
+
+impl VirtualHostAcceptor<(), ()> {
+    /// Accepts an incoming connection
+    pub async fn accept<Io>(&self, stream: Io) -> Result<ListenerConnectionHandle, OpenError>
+    where
+        Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+    {
+        self.negotiate_amqp_with_stream(stream, None).await
+    }
+}
+
+impl<Sasl> VirtualHostAcceptor<(), Sasl>
+where
+    Sasl: SaslAcceptor,
+{
+    /// Accepts an incoming connection
+    pub async fn accept<Io>(&self, stream: Io) -> Result<ListenerConnectionHandle, OpenError>
+    where
+        Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+    {
+        self.negotiate_sasl_with_stream(stream, None).await
+    }
+}
+
+/// Container id of the Open that refuses a connection that no virtual host serves
+///
+/// The connection is not routed to any container, but the container id is mandatory and must not
+/// be empty, which is otherwise rejected by the `"strict-validation"` feature before the refusal
+/// is sent.
+const REFUSAL_CONTAINER_ID: &str = "virtual-host-acceptor";
+
+/// The Open that is sent before refusing a connection that no virtual host serves
+fn refusal_open() -> Open {
+    Open {
+        container_id: String::from(REFUSAL_CONTAINER_ID),
+        hostname: None,
+        max_frame_size: MaxFrameSize(DEFAULT_MAX_FRAME_SIZE),
+        channel_max: ChannelMax(DEFAULT_CHANNEL_MAX),
+        idle_time_out: None,
+        outgoing_locales: None,
+        incoming_locales: None,
+        offered_capabilities: None,
+        desired_capabilities: None,
+        properties: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::acceptor::ConnectionAcceptor;
+
+    use super::VirtualHostAcceptor;
+
+    #[test]
+    fn resolve_virtual_host() {
+        let acceptor = VirtualHostAcceptor::<(), ()>::new()
+            .virtual_host("Tenant-A.example.com", ConnectionAcceptor::new("tenant-a"))
+            .virtual_host("tenant-b.example.com", ConnectionAcceptor::new("tenant-b"));
+
+        let resolved = acceptor.resolve(Some("tenant-a.EXAMPLE.com")).unwrap();
+        assert_eq!(resolved.local_open.container_id, "tenant-a");
+        let resolved = acceptor.resolve(Some("tenant-b.example.com")).unwrap();
+        assert_eq!(resolved.local_open.container_id, "tenant-b");
+        assert!(acceptor.resolve(Some("unknown.example.com")).is_none());
+        assert!(acceptor.resolve(None).is_none());
+
+        let acceptor = acceptor.default_acceptor(ConnectionAcceptor::new("default"));
+        let resolved = acceptor.resolve(Some("unknown.example.com")).unwrap();
+        assert_eq!(resolved.local_open.container_id, "default");
+        let resolved = acceptor.resolve(None).unwrap();
+        assert_eq!(resolved.local_open.container_id, "default");
+    }
+}
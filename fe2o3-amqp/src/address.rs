@@ -0,0 +1,277 @@
+//! Parsing and formatting of the common addressing conventions of the brokers
+//!
+//! The address of a node is only a string in AMQP 1.0, and each broker gives some structure to it.
+//! [`Address`] parses and formats the following conventions
+//!
+//! | Convention | Example | Variant |
+//! |------------|---------|---------|
+//! | Plain node address | `orders` | [`Address::Node`] |
+//! | Queue prefix (eg. ActiveMQ, Qpid JMS) | `queue://orders` | [`Address::Queue`] |
+//! | Topic prefix | `topic://prices` | [`Address::Topic`] |
+//! | Temporary queue prefix | `temp-queue://replies` | [`Address::TemporaryQueue`] |
+//! | Temporary topic prefix | `temp-topic://events` | [`Address::TemporaryTopic`] |
+//! | ActiveMQ Artemis fully qualified queue name | `orders::priority` | [`Address::FullyQualifiedQueue`] |
+//! | Azure Service Bus subscription | `prices/Subscriptions/audit` | [`Address::Subscription`] |
+//!
+//! An [`Address`] converts into the address string, so it can be used wherever an address, a
+//! [`Source`](crate::types::messaging::Source) or a [`Target`](crate::types::messaging::Target) is
+//! expected.
+//!
+//! # Example
+//!
+//! ```rust
+//! use fe2o3_amqp::address::Address;
+//! use fe2o3_amqp::types::messaging::Source;
+//!
+//! let address: Address = "prices/Subscriptions/audit".parse().unwrap();
+//! assert_eq!(address, Address::subscription("prices", "audit"));
+//!
+//! let source = Source::builder()
+//!     .address(Address::fully_qualified_queue("orders", "priority"))
+//!     .build();
+//! assert_eq!(source.address.as_deref(), Some("orders::priority"));
+//! ```
+
+use std::{fmt, str::FromStr};
+
+const QUEUE_PREFIX: &str = "queue://";
+const TOPIC_PREFIX: &str = "topic://";
+const TEMP_QUEUE_PREFIX: &str = "temp-queue://";
+const TEMP_TOPIC_PREFIX: &str = "temp-topic://";
+const FQQN_SEPARATOR: &str = "::";
+const SUBSCRIPTIONS_SEGMENT: &str = "/subscriptions/";
+
+/// The address cannot be parsed
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AddressError {
+    /// The address is empty
+    #[error("Address must not be empty")]
+    Empty,
+
+    /// A component of the address (eg. the queue of a fully qualified queue name) is empty
+    #[error("Address {:?} has an empty component", .0)]
+    EmptyComponent(String),
+}
+
+/// Address of a node following one of the common addressing conventions
+///
+/// See the [module documentation](self) for the supported conventions. The `Subscriptions`
+/// segment of a Service Bus subscription is matched case-insensitively, like Service Bus does, and
+/// is always formatted as `Subscriptions`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    /// Plain node address, eg. `orders`
+    Node(String),
+
+    /// Queue with the `queue://` prefix
+    Queue(String),
+
+    /// Topic with the `topic://` prefix
+    Topic(String),
+
+    /// Temporary queue with the `temp-queue://` prefix
+    TemporaryQueue(String),
+
+    /// Temporary topic with the `temp-topic://` prefix
+    TemporaryTopic(String),
+
+    /// ActiveMQ Artemis fully qualified queue name `address::queue`
+    FullyQualifiedQueue {
+        /// The address the queue is bound to
+        address: String,
+
+        /// The queue
+        queue: String,
+    },
+
+    /// Azure Service Bus subscription `topic/Subscriptions/subscription`
+    Subscription {
+        /// The topic
+        topic: String,
+
+        /// The subscription of the topic
+        subscription: String,
+    },
+}
+
+impl Address {
+    /// Parses an address
+    pub fn parse(address: &str) -> Result<Self, AddressError> {
+        if address.is_empty() {
+            return Err(AddressError::Empty);
+        }
+
+        let prefixed = if let Some(name) = address.strip_prefix(TEMP_QUEUE_PREFIX) {
+            Some((name, Address::TemporaryQueue as fn(String) -> Self))
+        } else if let Some(name) = address.strip_prefix(TEMP_TOPIC_PREFIX) {
+            Some((name, Address::TemporaryTopic as fn(String) -> Self))
+        } else if let Some(name) = address.strip_prefix(QUEUE_PREFIX) {
+            Some((name, Address::Queue as fn(String) -> Self))
+        } else {
+            address
+                .strip_prefix(TOPIC_PREFIX)
+                .map(|name| (name, Address::Topic as fn(String) -> Self))
+        };
+        if let Some((name, variant)) = prefixed {
+            return non_empty(address, [name]).map(|_| variant(name.to_string()));
+        }
+
+        if let Some((node, queue)) = address.split_once(FQQN_SEPARATOR) {
+            return non_empty(address, [node, queue])
+                .map(|_| Address::fully_qualified_queue(node, queue));
+        }
+
+        // Topics may contain '/' but subscriptions may not, so the last segment is looked up
+        if let Some(index) = address.to_ascii_lowercase().rfind(SUBSCRIPTIONS_SEGMENT) {
+            let topic = &address[..index];
+            let subscription = &address[index + SUBSCRIPTIONS_SEGMENT.len()..];
+            if !subscription.contains('/') {
+                return non_empty(address, [topic, subscription])
+                    .map(|_| Address::subscription(topic, subscription));
+            }
+        }
+
+        Ok(Address::Node(address.to_string()))
+    }
+
+    /// Plain node address
+    pub fn node(name: impl Into<String>) -> Self {
+        Address::Node(name.into())
+    }
+
+    /// Queue with the `queue://` prefix
+    pub fn queue(name: impl Into<String>) -> Self {
+        Address::Queue(name.into())
+    }
+
+    /// Topic with the `topic://` prefix
+    pub fn topic(name: impl Into<String>) -> Self {
+        Address::Topic(name.into())
+    }
+
+    /// ActiveMQ Artemis fully qualified queue name `address::queue`
+    pub fn fully_qualified_queue(address: impl Into<String>, queue: impl Into<String>) -> Self {
+        Address::FullyQualifiedQueue {
+            address: address.into(),
+            queue: queue.into(),
+        }
+    }
+
+    /// Azure Service Bus subscription `topic/Subscriptions/subscription`
+    pub fn subscription(topic: impl Into<String>, subscription: impl Into<String>) -> Self {
+        Address::Subscription {
+            topic: topic.into(),
+            subscription: subscription.into(),
+        }
+    }
+
+    /// Whether the address is a temporary queue or topic
+    pub fn is_temporary(&self) -> bool {
+        matches!(
+            self,
+            Address::TemporaryQueue(_) | Address::TemporaryTopic(_)
+        )
+    }
+}
+
+fn non_empty<const N: usize>(address: &str, components: [&str; N]) -> Result<(), AddressError> {
+    match components.iter().any(|component| component.is_empty()) {
+        true => Err(AddressError::EmptyComponent(address.to_string())),
+        false => Ok(()),
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Node(name) => f.write_str(name),
+            Address::Queue(name) => write!(f, "{}{}", QUEUE_PREFIX, name),
+            Address::Topic(name) => write!(f, "{}{}", TOPIC_PREFIX, name),
+            Address::TemporaryQueue(name) => write!(f, "{}{}", TEMP_QUEUE_PREFIX, name),
+            Address::TemporaryTopic(name) => write!(f, "{}{}", TEMP_TOPIC_PREFIX, name),
+            Address::FullyQualifiedQueue { address, queue } => {
+                write!(f, "{}{}{}", address, FQQN_SEPARATOR, queue)
+            }
+            Address::Subscription {
+                topic,
+                subscription,
+            } => write!(f, "{}/Subscriptions/{}", topic, subscription),
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl From<Address> for String {
+    fn from(address: Address) -> Self {
+        address.to_string()
+    }
+}
+
+impl From<&Address> for String {
+    fn from(address: &Address) -> Self {
+        address.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Address, AddressError};
+
+    #[test]
+    fn addresses_are_parsed_and_formatted_back() {
+        let cases = [
+            ("orders", Address::node("orders")),
+            ("queue://orders", Address::queue("orders")),
+            ("topic://prices", Address::topic("prices")),
+            (
+                "temp-queue://replies",
+                Address::TemporaryQueue("replies".into()),
+            ),
+            (
+                "temp-topic://events",
+                Address::TemporaryTopic("events".into()),
+            ),
+            (
+                "orders::priority",
+                Address::fully_qualified_queue("orders", "priority"),
+            ),
+            (
+                "a/b/Subscriptions/audit",
+                Address::subscription("a/b", "audit"),
+            ),
+            // Not a subscription, as subscriptions cannot contain '/'
+            (
+                "prices/Subscriptions/audit/x",
+                Address::node("prices/Subscriptions/audit/x"),
+            ),
+        ];
+        for (text, address) in cases {
+            assert_eq!(Address::parse(text), Ok(address.clone()));
+            assert_eq!(address.to_string(), text);
+        }
+
+        assert_eq!(
+            "prices/subscriptions/audit".parse::<Address>().unwrap(),
+            Address::subscription("prices", "audit")
+        );
+        assert!(Address::TemporaryQueue("replies".into()).is_temporary());
+    }
+
+    #[test]
+    fn addresses_with_empty_components_are_rejected() {
+        assert_eq!(Address::parse(""), Err(AddressError::Empty));
+        for text in ["queue://", "::queue", "orders::", "/Subscriptions/audit"] {
+            assert_eq!(
+                Address::parse(text),
+                Err(AddressError::EmptyComponent(text.to_string()))
+            );
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Injectable source of time for the timers of the connection
+//!
+//! The idle timeout and the heartbeat of a connection, as well as the per-attempt timeout and
+//! delay of the [`ConnectStrategy`](crate::connection::ConnectStrategy), are driven by a
+//! [`Clock`]. The sessions and links share the clock of their connection, which drives the flow
+//! coalescing, the detach timeout, the keep-alive, the deadline of a message, the rate limit, the
+//! deduplication window and the batching of the dispositions. The default [`TokioClock`] follows the tokio timer, so tests can already control
+//! time with `tokio::time::pause()` and `tokio::time::advance()`. A [`MockClock`] can be injected
+//! instead when the time should only move when the test says so, independently of the runtime.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let clock = MockClock::new();
+//! let connection = Connection::builder()
+//!     .container_id("connection-1")
+//!     .idle_time_out(1000)
+//!     .clock(clock.clone())
+//!     .open("amqp://localhost:5672")
+//!     .await
+//!     .unwrap();
+//!
+//! // Let the idle timeout elapse without waiting for it
+//! clock.advance(Duration::from_secs(1));
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+/// Future returned by [`Clock::sleep_until`]
+pub type ClockSleep = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// A [`Clock`] that can be shared by the connections, the acceptors and their timers
+pub type SharedClock = Arc<dyn Clock>;
+
+/// Source of the current time and of the timers
+pub trait Clock: fmt::Debug + Send + Sync + 'static {
+    /// Returns the current time of the clock
+    fn now(&self) -> Instant;
+
+    /// Returns a future that completes once the clock reaches `deadline`
+    fn sleep_until(&self, deadline: Instant) -> ClockSleep;
+
+    /// Returns a future that completes once `duration` has passed on the clock
+    fn sleep(&self, duration: Duration) -> ClockSleep {
+        self.sleep_until(self.now() + duration)
+    }
+}
+
+/// The default [`Clock`] that is backed by the tokio timer
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> ClockSleep {
+        Box::pin(tokio::time::sleep_until(deadline))
+    }
+}
+
+/// Returns the default [`SharedClock`]
+pub fn default_clock() -> SharedClock {
+    Arc::new(TokioClock)
+}
+
+/// Awaits `fut` until `duration` has passed on the clock. Returns `None` if the duration elapses
+/// first
+pub(crate) async fn timeout<F: Future>(
+    clock: &dyn Clock,
+    duration: Duration,
+    fut: F,
+) -> Option<F::Output> {
+    timeout_at(clock, clock.now() + duration, fut).await
+}
+
+/// Awaits `fut` until the clock reaches `deadline`. Returns `None` if the deadline elapses first
+pub(crate) async fn timeout_at<F: Future>(
+    clock: &dyn Clock,
+    deadline: Instant,
+    fut: F,
+) -> Option<F::Output> {
+    let sleep = clock.sleep_until(deadline);
+    tokio::select! {
+        output = fut => Some(output),
+        _ = sleep => None,
+    }
+}
+
+#[derive(Debug)]
+struct MockState {
+    now: Instant,
+    next_id: u64,
+    sleepers: HashMap<u64, (Instant, Waker)>,
+}
+
+/// A [`Clock`] that only moves forward with [`MockClock::advance`]
+///
+/// Cloning a `MockClock` gives another handle to the same clock.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    /// Creates a clock that starts at the current time of the tokio timer
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    /// Creates a clock that starts at `now`
+    pub fn starting_at(now: Instant) -> Self {
+        let state = MockState {
+            now,
+            next_id: 0,
+            sleepers: HashMap::new(),
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Moves the clock forward by `duration` and wakes up the sleeps that are due
+    pub fn advance(&self, duration: Duration) {
+        let wakers: Vec<Waker> = {
+            let mut state = self.lock();
+            state.now += duration;
+            let now = state.now;
+            let due: Vec<u64> = state
+                .sleepers
+                .iter()
+                .filter(|(_, (deadline, _))| *deadline <= now)
+                .map(|(id, _)| *id)
+                .collect();
+            due.into_iter()
+                .filter_map(|id| state.sleepers.remove(&id))
+                .map(|(_, waker)| waker)
+                .collect()
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// Number of sleeps that are waiting for the clock to move forward
+    pub fn pending_sleeps(&self) -> usize {
+        self.lock().sleepers.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        // The lock is never held across a call that could panic
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.lock().now
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> ClockSleep {
+        let id = {
+            let mut state = self.lock();
+            state.next_id += 1;
+            state.next_id
+        };
+        Box::pin(MockSleep {
+            clock: self.clone(),
+            id,
+            deadline,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct MockSleep {
+    clock: MockClock,
+    id: u64,
+    deadline: Instant,
+}
+
+impl Future for MockSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.clock.lock();
+        if state.now >= self.deadline {
+            state.sleepers.remove(&self.id);
+            Poll::Ready(())
+        } else {
+            state
+                .sleepers
+                .insert(self.id, (self.deadline, cx.waker().clone()));
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for MockSleep {
+    fn drop(&mut self) {
+        self.clock.lock().sleepers.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::FutureExt;
+
+    use super::{Clock, MockClock};
+
+    #[tokio::test]
+    async fn mock_sleep_completes_once_the_clock_is_advanced() {
+        let clock = MockClock::new();
+        let mut sleep = clock.sleep(Duration::from_secs(10));
+        assert!((&mut sleep).now_or_never().is_none());
+        assert_eq!(clock.pending_sleeps(), 1);
+
+        clock.advance(Duration::from_secs(9));
+        assert!((&mut sleep).now_or_never().is_none());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(sleep.now_or_never().is_some());
+        assert_eq!(clock.pending_sleeps(), 0);
+    }
+
+    #[tokio::test]
+    async fn dropped_mock_sleep_is_unregistered() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut sleep = clock.sleep(Duration::from_secs(1));
+        assert!((&mut sleep).now_or_never().is_none());
+        drop(sleep);
+        assert_eq!(clock.pending_sleeps(), 0);
+
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(clock.now() - start, Duration::from_millis(1500));
+    }
+}
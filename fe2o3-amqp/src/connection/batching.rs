@@ -0,0 +1,63 @@
+//! Batching of the outgoing frames
+
+use std::time::Duration;
+
+/// Default maximum number of frames that are written with a single flush
+pub const DEFAULT_MAX_BATCHED_FRAMES: usize = 32;
+
+/// Batching of the frames that are sent by the sessions of a connection
+///
+/// When the connection engine writes a frame, the frames that are already queued by the sessions
+/// (ie. flows, dispositions and small transfers) are encoded into the same write buffer, which is
+/// then flushed once instead of once per frame. No frame waits for a batch to fill up unless
+/// `flush_deadline` is set.
+///
+/// This is buffered coalescing, not vectored IO. Each frame is encoded (and therefore copied)
+/// into the contiguous write buffer of the transport, and a batch is written with the regular
+/// `write` calls of the flush rather than with one `write_vectored` over the separate frames.
+///
+/// # Default
+///
+/// | Field | Default Value |
+/// |-------|---------------|
+/// |`max_frames`| [`DEFAULT_MAX_BATCHED_FRAMES`] |
+/// |`flush_deadline`| `Duration::ZERO` |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteBatching {
+    /// Maximum number of frames that are written with a single flush. A value of `0` or `1`
+    /// flushes every frame on its own
+    pub max_frames: usize,
+
+    /// How long the engine waits for more frames before flushing a batch that is not full. The
+    /// batch is flushed as soon as no frame is queued if this is zero. The incoming frames and the
+    /// other events of the connection are still handled while a batch waits
+    pub flush_deadline: Duration,
+}
+
+impl Default for WriteBatching {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_BATCHED_FRAMES)
+    }
+}
+
+impl WriteBatching {
+    /// Batches at most `max_frames` frames that are already queued
+    pub fn new(max_frames: usize) -> Self {
+        Self {
+            max_frames,
+            flush_deadline: Duration::ZERO,
+        }
+    }
+
+    /// Flushes every frame on its own
+    pub fn disabled() -> Self {
+        Self::new(1)
+    }
+
+    /// Waits up to `flush_deadline` for more frames before flushing a batch that is not full.
+    /// This trades latency for fewer writes at high frame rates
+    pub fn flush_deadline(mut self, flush_deadline: Duration) -> Self {
+        self.flush_deadline = flush_deadline;
+        self
+    }
+}
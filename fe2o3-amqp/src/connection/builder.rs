@@ -4,7 +4,7 @@ use std::{io, marker::PhantomData, time::Duration};
 
 use fe2o3_amqp_types::{
     definitions::{Fields, IetfLanguageTag, Milliseconds, MIN_MAX_FRAME_SIZE},
-    performatives::{ChannelMax, MaxFrameSize, Open},
+    performatives::{Capability, ChannelMax, MaxFrameSize, Open},
     sasl::SaslCode,
 };
 use futures_util::{SinkExt, StreamExt};
@@ -18,23 +18,30 @@ use tokio_util::codec::{FramedRead, FramedWrite};
 cfg_not_wasm32! {
     use std::convert::TryInto;
     use url::Url;
-    use tokio::net::TcpStream;
+
+    use std::sync::Arc;
+
+    use crate::clock::{Clock, SharedClock};
+    use crate::transport::protocol_header::default_port;
+
+    use super::{connect, failover, HostHealth};
 }
 
 use crate::{
     connection::{Connection, ConnectionState},
     control::ConnectionControl,
     frames::sasl,
-    sasl_profile::{Negotiation, SaslProfile},
-    session::frame::SessionFrame,
+    sasl_profile::{Negotiation, SaslProfile, SharedCredentials},
     transport::Transport,
     transport::{error::NegotiationError, protocol_header::ProtocolHeaderCodec},
+    watchdog::Watchdog,
     SendBound,
 };
 
 use super::{
-    engine::ConnectionEngine, ConnectionHandle, OpenError, DEFAULT_CHANNEL_MAX,
-    DEFAULT_MAX_FRAME_SIZE,
+    engine::ConnectionEngine, session_frame_channel, ConnectStrategy, ConnectionHandle,
+    ContainerIdPolicy, OpenError, QosWeights, ServerCertVerification, SessionFrameSenders,
+    WriteBatching, DEFAULT_CHANNEL_MAX, DEFAULT_MAX_FRAME_SIZE,
 };
 
 #[cfg(feature = "tracing")]
@@ -43,22 +50,12 @@ use tracing::instrument;
 pub(crate) const DEFAULT_CONTROL_CHAN_BUF: usize = 128;
 pub(crate) const DEFAULT_OUTGOING_BUFFER_SIZE: usize = u16::MAX as usize;
 
-cfg_not_wasm32! {
-    fn default_port(scheme: &str) -> Option<u16> {
-        match scheme {
-            "amqp" => Some(fe2o3_amqp_types::definitions::PORT),
-            "amqps" => Some(fe2o3_amqp_types::definitions::SECURE_PORT),
-            _ => None,
-        }
-    }
-}
-
 pub(crate) mod mode {
     /// Type state for [`crate::connection::Builder`]
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub struct ConnectorWithId {}
     /// Type state for [`crate::connection::Builder`]
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub struct ConnectorNoId {}
 }
 
@@ -77,6 +74,11 @@ pub struct Builder<'a, Mode, Tls> {
     /// URL domain
     pub domain: Option<&'a str>,
 
+    /// Server name for the TLS handshake (ie. SNI and certificate verification). This takes
+    /// precedence over the `domain` and allows connecting to a broker by IP address while
+    /// verifying the certificate against a DNS name
+    pub tls_server_name: Option<&'a str>,
+
     /// Proposed maximum frame size
     ///
     /// This includes the 8 bytes taken by the frame header
@@ -133,12 +135,59 @@ pub struct Builder<'a, Mode, Tls> {
     /// PLAIN SASL profile that is interpreted from the url.
     pub sasl_profile: Option<SaslProfile>,
 
+    /// Shared SASL credentials that take precedence over `sasl_profile`. The profile is read when
+    /// the SASL negotiation is performed, so rotated credentials are picked up on reconnect
+    pub credentials: Option<SharedCredentials>,
+
     /// TLS establishment
     ///
     /// This determines whether an AMQP TLS protocol header exchange will be performed prior to
     /// actual TLS handshake
     pub alt_tls_estab: bool,
 
+    /// How the server certificate is verified by the default TLS connector. This has no effect
+    /// if a custom TLS connector is supplied
+    pub server_cert_verification: ServerCertVerification,
+
+    /// Whether dropping the [`ConnectionHandle`] should close the connection in the background
+    /// within [`DEFAULT_CLOSE_ON_DROP_TIMEOUT`](super::DEFAULT_CLOSE_ON_DROP_TIMEOUT) so that
+    /// the remote peer is not left with a half-open connection
+    pub close_on_drop: bool,
+
+    /// Whether remotely initiated sessions are accepted on this outgoing connection (symmetric
+    /// peer mode). This only takes effect with the `"acceptor"` feature enabled
+    pub accept_incoming_sessions: bool,
+
+    /// Strategy to connect to the addresses that the hostname resolves to when the connection is
+    /// opened with an url
+    pub connect_strategy: ConnectStrategy,
+
+    /// Batching of the outgoing frames
+    pub write_batching: WriteBatching,
+
+    /// Watchdog that catches the panics in the engines of the connection and its sessions
+    pub watchdog: Option<Watchdog>,
+
+    /// Generation and validation of the container id
+    pub container_id_policy: ContainerIdPolicy,
+
+    /// Weights of the quality-of-service classes of the sessions
+    pub qos_weights: QosWeights,
+
+    /// Clock that drives the idle timeout, the heartbeat and the connection attempts. Defaults to
+    /// [`TokioClock`](crate::clock::TokioClock)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub clock: SharedClock,
+
+    /// Alternative hosts that are attempted in order if the connection to the url given to
+    /// `open()` cannot be opened
+    #[cfg(not(target_arch = "wasm32"))]
+    pub alt_hosts: Vec<String>,
+
+    /// Memory of the hosts that recently failed, which orders the attempts of the failover
+    #[cfg(not(target_arch = "wasm32"))]
+    pub host_health: HostHealth,
+
     // type state marker
     marker: PhantomData<Mode>,
 }
@@ -167,11 +216,14 @@ impl<'a, Tls> From<Builder<'a, mode::ConnectorWithId, Tls>> for Open {
 
 impl<'a, Mode: std::fmt::Debug> std::fmt::Debug for Builder<'a, Mode, ()> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Builder")
+        let mut builder = f.debug_struct("Builder");
+        builder
             .field("container_id", &self.container_id)
+            .field("container_id_policy", &self.container_id_policy)
             .field("hostname", &self.hostname)
             .field("scheme", &self.scheme)
             .field("domain", &self.domain)
+            .field("tls_server_name", &self.tls_server_name)
             .field("max_frame_size", &self.max_frame_size)
             .field("channel_max", &self.channel_max)
             .field("idle_time_out", &self.idle_time_out)
@@ -183,19 +235,34 @@ impl<'a, Mode: std::fmt::Debug> std::fmt::Debug for Builder<'a, Mode, ()> {
             .field("tls_connector", &"()")
             .field("buffer_size", &self.buffer_size)
             .field("sasl_profile", &self.sasl_profile)
-            .field("marker", &self.marker)
-            .finish()
+            .field("credentials", &self.credentials)
+            .field("server_cert_verification", &self.server_cert_verification)
+            .field("close_on_drop", &self.close_on_drop)
+            .field("accept_incoming_sessions", &self.accept_incoming_sessions)
+            .field("connect_strategy", &self.connect_strategy)
+            .field("write_batching", &self.write_batching)
+            .field("watchdog", &self.watchdog)
+            .field("qos_weights", &self.qos_weights);
+        #[cfg(not(target_arch = "wasm32"))]
+        builder
+            .field("clock", &self.clock)
+            .field("alt_hosts", &self.alt_hosts)
+            .field("host_health", &self.host_health);
+        builder.field("marker", &self.marker).finish()
     }
 }
 
 cfg_rustls! {
     impl<'a, Mode: std::fmt::Debug> std::fmt::Debug for Builder<'a, Mode, tokio_rustls::TlsConnector> {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            f.debug_struct("Builder")
+            let mut builder = f.debug_struct("Builder");
+            builder
                 .field("container_id", &self.container_id)
+                .field("container_id_policy", &self.container_id_policy)
                 .field("hostname", &self.hostname)
                 .field("scheme", &self.scheme)
                 .field("domain", &self.domain)
+                .field("tls_server_name", &self.tls_server_name)
                 .field("max_frame_size", &self.max_frame_size)
                 .field("channel_max", &self.channel_max)
                 .field("idle_time_out", &self.idle_time_out)
@@ -207,8 +274,20 @@ cfg_rustls! {
                 .field("tls_connector", &"tokio_rustls::TlsConnector")
                 .field("buffer_size", &self.buffer_size)
                 .field("sasl_profile", &self.sasl_profile)
-                .field("marker", &self.marker)
-                .finish()
+                .field("credentials", &self.credentials)
+                .field("server_cert_verification", &self.server_cert_verification)
+                .field("close_on_drop", &self.close_on_drop)
+                .field("accept_incoming_sessions", &self.accept_incoming_sessions)
+                .field("connect_strategy", &self.connect_strategy)
+                .field("write_batching", &self.write_batching)
+                .field("watchdog", &self.watchdog)
+                .field("qos_weights", &self.qos_weights);
+            #[cfg(not(target_arch = "wasm32"))]
+            builder
+                .field("clock", &self.clock)
+                .field("alt_hosts", &self.alt_hosts)
+                .field("host_health", &self.host_health);
+            builder.field("marker", &self.marker).finish()
         }
     }
 }
@@ -219,11 +298,14 @@ cfg_not_wasm32! {
             for Builder<'a, Mode, tokio_native_tls::TlsConnector>
         {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                f.debug_struct("Builder")
+                let mut builder = f.debug_struct("Builder");
+                builder
                     .field("container_id", &self.container_id)
+                    .field("container_id_policy", &self.container_id_policy)
                     .field("hostname", &self.hostname)
                     .field("scheme", &self.scheme)
                     .field("domain", &self.domain)
+                    .field("tls_server_name", &self.tls_server_name)
                     .field("max_frame_size", &self.max_frame_size)
                     .field("channel_max", &self.channel_max)
                     .field("idle_time_out", &self.idle_time_out)
@@ -235,8 +317,20 @@ cfg_not_wasm32! {
                     .field("tls_connector", &"tokio_native_tls::TlsConnector")
                     .field("buffer_size", &self.buffer_size)
                     .field("sasl_profile", &self.sasl_profile)
-                    .field("marker", &self.marker)
-                    .finish()
+                    .field("credentials", &self.credentials)
+                    .field("server_cert_verification", &self.server_cert_verification)
+                    .field("close_on_drop", &self.close_on_drop)
+                    .field("accept_incoming_sessions", &self.accept_incoming_sessions)
+                    .field("connect_strategy", &self.connect_strategy)
+                    .field("write_batching", &self.write_batching)
+                    .field("watchdog", &self.watchdog)
+                    .field("qos_weights", &self.qos_weights);
+                #[cfg(not(target_arch = "wasm32"))]
+                builder
+                    .field("clock", &self.clock)
+                    .field("alt_hosts", &self.alt_hosts)
+                    .field("host_health", &self.host_health);
+                builder.field("marker", &self.marker).finish()
             }
         }
     }
@@ -256,6 +350,7 @@ impl<'a, Mode> Builder<'a, Mode, ()> {
             hostname: None,
             scheme: "amqp", // Assume non-TLS by default
             domain: None,
+            tls_server_name: None,
             // set to 512 before Open frame is sent
             max_frame_size: MaxFrameSize(DEFAULT_MAX_FRAME_SIZE),
             channel_max: ChannelMax(DEFAULT_CHANNEL_MAX),
@@ -270,7 +365,22 @@ impl<'a, Mode> Builder<'a, Mode, ()> {
 
             buffer_size: DEFAULT_OUTGOING_BUFFER_SIZE,
             sasl_profile: None,
+            credentials: None,
             alt_tls_estab: false,
+            server_cert_verification: ServerCertVerification::default(),
+            close_on_drop: false,
+            accept_incoming_sessions: false,
+            connect_strategy: ConnectStrategy::default(),
+            write_batching: WriteBatching::default(),
+            container_id_policy: ContainerIdPolicy::default(),
+            qos_weights: QosWeights::default(),
+            watchdog: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            clock: crate::clock::default_clock(),
+            #[cfg(not(target_arch = "wasm32"))]
+            alt_hosts: Vec::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            host_health: HostHealth::default(),
 
             marker: PhantomData,
         }
@@ -288,6 +398,7 @@ impl<'a, Tls> Builder<'a, mode::ConnectorNoId, Tls> {
             hostname: self.hostname,
             scheme: self.scheme,
             domain: self.domain,
+            tls_server_name: self.tls_server_name,
             // set to 512 before Open frame is sent
             max_frame_size: self.max_frame_size,
             channel_max: self.channel_max,
@@ -302,11 +413,35 @@ impl<'a, Tls> Builder<'a, mode::ConnectorNoId, Tls> {
 
             buffer_size: self.buffer_size,
             sasl_profile: self.sasl_profile,
+            credentials: self.credentials,
             alt_tls_estab: self.alt_tls_estab,
+            server_cert_verification: self.server_cert_verification,
+            close_on_drop: self.close_on_drop,
+            accept_incoming_sessions: self.accept_incoming_sessions,
+            connect_strategy: self.connect_strategy,
+            write_batching: self.write_batching,
+            container_id_policy: self.container_id_policy,
+            qos_weights: self.qos_weights,
+            watchdog: self.watchdog,
+            #[cfg(not(target_arch = "wasm32"))]
+            clock: self.clock,
+            #[cfg(not(target_arch = "wasm32"))]
+            alt_hosts: self.alt_hosts,
+            #[cfg(not(target_arch = "wasm32"))]
+            host_health: self.host_health,
 
             marker: PhantomData,
         }
     }
+
+    /// Generates the id of the source container with the [`ContainerIdPolicy`]
+    ///
+    /// The generated id can be queried with [`ConnectionHandle::container_id`] once the
+    /// connection is opened
+    pub fn generated_container_id(self) -> Builder<'a, mode::ConnectorWithId, Tls> {
+        let id = self.container_id_policy.generate();
+        self.container_id(id)
+    }
 }
 
 impl<'a, Mode, Tls> Builder<'a, Mode, Tls> {
@@ -336,6 +471,7 @@ impl<'a, Mode, Tls> Builder<'a, Mode, Tls> {
                 hostname: self.hostname,
                 scheme: self.scheme,
                 domain: self.domain,
+                tls_server_name: self.tls_server_name,
                 // set to 512 before Open frame is sent
                 max_frame_size: self.max_frame_size,
                 channel_max: self.channel_max,
@@ -350,7 +486,22 @@ impl<'a, Mode, Tls> Builder<'a, Mode, Tls> {
 
                 buffer_size: self.buffer_size,
                 sasl_profile: self.sasl_profile,
+                credentials: self.credentials,
                 alt_tls_estab: self.alt_tls_estab,
+                server_cert_verification: self.server_cert_verification,
+                close_on_drop: self.close_on_drop,
+                accept_incoming_sessions: self.accept_incoming_sessions,
+                connect_strategy: self.connect_strategy,
+                write_batching: self.write_batching,
+                container_id_policy: self.container_id_policy,
+                qos_weights: self.qos_weights,
+                watchdog: self.watchdog,
+                #[cfg(not(target_arch = "wasm32"))]
+                clock: self.clock,
+                #[cfg(not(target_arch = "wasm32"))]
+                alt_hosts: self.alt_hosts,
+                #[cfg(not(target_arch = "wasm32"))]
+                host_health: self.host_health,
 
                 marker: PhantomData,
             }
@@ -399,6 +550,7 @@ impl<'a, Mode, Tls> Builder<'a, Mode, Tls> {
                     hostname: self.hostname,
                     scheme: self.scheme,
                     domain: self.domain,
+                    tls_server_name: self.tls_server_name,
                     // set to 512 before Open frame is sent
                     max_frame_size: self.max_frame_size,
                     channel_max: self.channel_max,
@@ -413,7 +565,22 @@ impl<'a, Mode, Tls> Builder<'a, Mode, Tls> {
 
                     buffer_size: self.buffer_size,
                     sasl_profile: self.sasl_profile,
+                    credentials: self.credentials,
                     alt_tls_estab: self.alt_tls_estab,
+                    server_cert_verification: self.server_cert_verification,
+                    close_on_drop: self.close_on_drop,
+                    accept_incoming_sessions: self.accept_incoming_sessions,
+                    connect_strategy: self.connect_strategy,
+                    write_batching: self.write_batching,
+                    container_id_policy: self.container_id_policy,
+                    qos_weights: self.qos_weights,
+                    watchdog: self.watchdog,
+                    #[cfg(not(target_arch = "wasm32"))]
+                    clock: self.clock,
+                    #[cfg(not(target_arch = "wasm32"))]
+                    alt_hosts: self.alt_hosts,
+                    #[cfg(not(target_arch = "wasm32"))]
+                    host_health: self.host_health,
 
                     marker: PhantomData,
                 }
@@ -441,6 +608,15 @@ impl<'a, Mode, Tls> Builder<'a, Mode, Tls> {
         self
     }
 
+    /// Server name for the TLS handshake, which takes precedence over the `domain`
+    ///
+    /// This is useful when the broker is reached by IP address (eg. without DNS) but presents a
+    /// certificate issued for a DNS name.
+    pub fn tls_server_name(mut self, server_name: impl Into<Option<&'a str>>) -> Self {
+        self.tls_server_name = server_name.into();
+        self
+    }
+
     /// Proposed maximum frame size
     ///
     /// This includes the 8 bytes taken by the frame header
@@ -536,6 +712,20 @@ impl<'a, Mode, Tls> Builder<'a, Mode, Tls> {
         self
     }
 
+    /// Desire the `sole-connection-for-container` capability, which asks the remote peer to
+    /// allow only one connection for the container id.
+    ///
+    /// If the remote peer refuses the connection because the container id is already in use,
+    /// opening the connection fails with [`OpenError::ContainerIdInUse`]. See
+    /// [`sole_connection`](crate::connection::sole_connection) for more details.
+    pub fn sole_connection_for_container(self) -> Self {
+        let capability = Symbol::from(Capability::SoleConnectionForContainer);
+        match &self.desired_capabilities {
+            Some(capabilities) if capabilities.contains(&capability) => self,
+            _ => self.add_desired_capabilities(capability),
+        }
+    }
+
     /// Connection properties
     pub fn properties(mut self, properties: Fields) -> Self {
         self.properties = Some(properties);
@@ -559,6 +749,18 @@ impl<'a, Mode, Tls> Builder<'a, Mode, Tls> {
         self
     }
 
+    /// Shared SASL credentials that can be rotated without rebuilding the builder
+    ///
+    /// The current profile is read every time a connection is opened, and it takes precedence
+    /// over the `sasl_profile` and the username and password in the url. If the credentials are
+    /// cleared, the `sasl_profile` is used instead. The same credentials are exposed by
+    /// [`ConnectionHandle::credentials`], so that
+    /// [`ConnectionHandle::update_credentials`] affects the next connection opened with them.
+    pub fn credentials(mut self, credentials: SharedCredentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
     /// Set the alternative tls_establishment
     ///
     /// Please see part 5.2.1 of the core spec
@@ -566,9 +768,163 @@ impl<'a, Mode, Tls> Builder<'a, Mode, Tls> {
         self.alt_tls_estab = value;
         self
     }
+
+    /// Whether dropping the [`ConnectionHandle`] should perform a best-effort close
+    ///
+    /// If enabled, dropping a [`ConnectionHandle`] that is not yet closed makes sure the Close
+    /// frame is sent, even if the control channel of the connection is full, and blocks until the
+    /// Close frames are exchanged with the remote peer or
+    /// [`DEFAULT_CLOSE_ON_DROP_TIMEOUT`](super::DEFAULT_CLOSE_ON_DROP_TIMEOUT) has elapsed, after
+    /// which the event loop is stopped. This lets a short-lived program drop the connection right
+    /// before shutting down the runtime without losing the Close frame.
+    ///
+    /// The drop only blocks on a multi-thread runtime, through
+    /// [`block_in_place`](tokio::task::block_in_place). On a current-thread runtime, the close is
+    /// done by a detached task, which is not run if the runtime shuts down first.
+    pub fn close_on_drop(mut self, value: bool) -> Self {
+        self.close_on_drop = value;
+        self
+    }
+
+    /// Strategy to connect to the addresses that the hostname resolves to when the connection is
+    /// opened with an url
+    ///
+    /// Defaults to [`ConnectStrategy::Sequential`] without a per-attempt timeout
+    pub fn connect_strategy(mut self, strategy: ConnectStrategy) -> Self {
+        self.connect_strategy = strategy;
+        self
+    }
+
+    /// Alternative hosts that are attempted in order if the connection to the url given to
+    /// `open()` cannot be opened, eg. the other brokers of a cluster. The hosts that failed
+    /// recently are attempted last, see [`HostHealth`]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn alt_hosts<I>(mut self, urls: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.alt_hosts = urls.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Memory of the hosts that recently failed. The same [`HostHealth`] should be given to the
+    /// builder when a lost connection is re-opened, so that a dead primary is not attempted first
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn host_health(mut self, health: HostHealth) -> Self {
+        self.host_health = health;
+        self
+    }
+
+    /// The url given to `open()` followed by the alternative hosts
+    #[cfg(not(target_arch = "wasm32"))]
+    fn failover_hosts(&self, url: Url) -> Result<Vec<Url>, url::ParseError> {
+        let mut hosts = vec![url];
+        for alt_host in &self.alt_hosts {
+            hosts.push(Url::parse(alt_host)?);
+        }
+        Ok(hosts)
+    }
+
+    /// Batching of the outgoing frames
+    ///
+    /// Defaults to [`WriteBatching::default()`], which only batches the frames that are already
+    /// queued
+    pub fn write_batching(mut self, write_batching: WriteBatching) -> Self {
+        self.write_batching = write_batching;
+        self
+    }
+
+    /// Watchdog that catches the panics in the engines of the connection and its sessions
+    ///
+    /// A panic is returned as [`Error::EnginePanicked`](super::Error::EnginePanicked) by the
+    /// connection or session handle, broadcast to the subscribers of the watchdog and passed to
+    /// its supervisor hook
+    pub fn watchdog(mut self, watchdog: Watchdog) -> Self {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
+    /// Generation and validation of the container id
+    ///
+    /// The policy must be set before [`generated_container_id`](Builder::generated_container_id)
+    /// is called, and any container id is validated against it before the connection is opened
+    pub fn container_id_policy(mut self, policy: ContainerIdPolicy) -> Self {
+        self.container_id_policy = policy;
+        self
+    }
+
+    /// Weights of the quality-of-service classes of the sessions
+    ///
+    /// Defaults to [`QosWeights::default()`]. The class of a session is set with
+    /// [`session::Builder::qos_class`](crate::session::Builder::qos_class)
+    pub fn qos_weights(mut self, qos_weights: QosWeights) -> Self {
+        self.qos_weights = qos_weights;
+        self
+    }
+
+    /// Clock that drives the idle timeout, the heartbeat and the connection attempts
+    ///
+    /// This is mainly useful in tests that exercise the timeouts deterministically with a
+    /// [`MockClock`](crate::clock::MockClock)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// How the server certificate is verified by the default TLS connector
+    ///
+    /// This has no effect if a custom TLS connector is supplied. See [`ServerCertVerification`]
+    /// for the available modes.
+    pub fn server_cert_verification(mut self, verification: ServerCertVerification) -> Self {
+        self.server_cert_verification = verification;
+        self
+    }
+
+    /// The name used for the TLS handshake, ie. the `tls_server_name` or otherwise the `domain`
+    #[cfg_attr(not(any(feature = "rustls", feature = "native-tls")), allow(dead_code))]
+    fn tls_name(&self) -> Result<&'a str, OpenError> {
+        self.tls_server_name
+            .or(self.domain)
+            .ok_or(OpenError::InvalidDomain)
+    }
+
+    cfg_acceptor! {
+        /// Whether remotely initiated sessions should be accepted (symmetric peer mode)
+        ///
+        /// This allows a peer that dials out to still serve sessions and links that are initiated
+        /// by the remote peer. Remotely initiated sessions can be accepted with
+        /// [`ConnectionHandle::accept_session`], and links that are attached by the remote peer
+        /// can then be accepted on the returned
+        /// [`ListenerSessionHandle`](crate::acceptor::ListenerSessionHandle).
+        ///
+        /// Remotely initiated sessions are rejected if this is not enabled. At most `buffer_size`
+        /// sessions can wait to be accepted, and the ones beyond that are ended right away with
+        /// the `amqp:resource-limit-exceeded` error.
+        pub fn accept_incoming_sessions(mut self, value: bool) -> Self {
+            self.accept_incoming_sessions = value;
+            self
+        }
+    }
 }
 
 impl<'a, Tls> Builder<'a, mode::ConnectorWithId, Tls> {
+    /// Checks that the configuration is coherent
+    ///
+    /// This is performed by all the `open` methods before any IO takes place, so an incoherent
+    /// configuration is rejected with a descriptive error instead of failing in the event loop.
+    pub fn validate(&self) -> Result<(), OpenError> {
+        if self.max_frame_size.0 < MIN_MAX_FRAME_SIZE as u32 {
+            return Err(OpenError::MaxFrameSizeTooSmall(self.max_frame_size.0));
+        }
+        if self.buffer_size == 0 {
+            return Err(OpenError::ZeroBufferSize);
+        }
+        self.container_id_policy.validate(&self.container_id)?;
+        Ok(())
+    }
+
     /// Performs SASL negotiation
     #[cfg_attr(feature = "tracing", instrument(skip_all, fields(hostname = ?self.hostname)))]
     pub async fn negotiate_sasl<Io>(
@@ -590,7 +946,7 @@ impl<'a, Tls> Builder<'a, mode::ConnectorWithId, Tls> {
             #[cfg(feature = "log")]
             log::trace!("received = {:?}", frame);
 
-            match profile.on_frame(frame, self.hostname)? {
+            match profile.on_frame(frame, self.hostname).await? {
                 Negotiation::Init(init) => {
                     let frame = sasl::Frame::Init(init);
                     #[cfg(feature = "tracing")]
@@ -634,10 +990,18 @@ impl<'a, Tls> Builder<'a, mode::ConnectorWithId, Tls> {
         F: FnOnce(
             ConnectionEngine<Io, Connection>,
             mpsc::Sender<ConnectionControl>,
-            mpsc::Sender<SessionFrame>,
+            SessionFrameSenders,
         ) -> Result<ConnectionHandle<()>, OpenError>,
     {
-        match self.sasl_profile.take() {
+        let profile = match self.credentials.as_ref().and_then(|c| c.get()) {
+            Some(profile) => Some(profile),
+            None => self.sasl_profile.take(),
+        };
+        let credentials = match self.credentials.take() {
+            Some(credentials) => credentials,
+            None => SharedCredentials::from(profile.clone()),
+        };
+        let mut connection_handle = match profile {
             Some(profile) => {
                 let (reader, writer) = tokio::io::split(stream);
                 let framed_write = FramedWrite::new(writer, ProtocolHeaderCodec::new());
@@ -654,10 +1018,15 @@ impl<'a, Tls> Builder<'a, mode::ConnectorWithId, Tls> {
 
                 // Then perform AMQP negotiation
                 self.connect_amqp_with_framed(framed_write, framed_read, spawn_engine_fn)
-                    .await
+                    .await?
             }
-            None => self.connect_amqp_with_stream(stream, spawn_engine_fn).await,
-        }
+            None => {
+                self.connect_amqp_with_stream(stream, spawn_engine_fn)
+                    .await?
+            }
+        };
+        connection_handle.credentials = credentials;
+        Ok(connection_handle)
     }
 
     async fn connect_amqp_with_stream<Io, F>(
@@ -670,7 +1039,7 @@ impl<'a, Tls> Builder<'a, mode::ConnectorWithId, Tls> {
         F: FnOnce(
             ConnectionEngine<Io, Connection>,
             mpsc::Sender<ConnectionControl>,
-            mpsc::Sender<SessionFrame>,
+            SessionFrameSenders,
         ) -> Result<ConnectionHandle<()>, OpenError>,
     {
         let (reader, writer) = tokio::io::split(stream);
@@ -691,7 +1060,7 @@ impl<'a, Tls> Builder<'a, mode::ConnectorWithId, Tls> {
         F: FnOnce(
             ConnectionEngine<Io, Connection>,
             mpsc::Sender<ConnectionControl>,
-            mpsc::Sender<SessionFrame>,
+            SessionFrameSenders,
         ) -> Result<ConnectionHandle<()>, OpenError>,
     {
         // Exchange AMQP headers
@@ -700,24 +1069,58 @@ impl<'a, Tls> Builder<'a, mode::ConnectorWithId, Tls> {
             .idle_time_out
             .map(|millis| Duration::from_millis(millis as u64));
         let buffer_size = self.buffer_size;
-        let transport = Transport::negotiate_amqp_header(
+        let close_on_drop = self.close_on_drop;
+        let write_batching = self.write_batching;
+        let watchdog = self.watchdog.clone();
+        let container_id = self.container_id.clone();
+        let qos_weights = self.qos_weights;
+        #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+        let accept_incoming_sessions = self.accept_incoming_sessions;
+        #[cfg(not(target_arch = "wasm32"))]
+        let clock = self.clock.clone();
+        #[allow(unused_mut)]
+        let mut transport = Transport::negotiate_amqp_header(
             framed_write,
             framed_read,
             &mut local_state,
             idle_timeout,
         )
         .await?;
+        #[cfg(not(target_arch = "wasm32"))]
+        transport.set_clock(clock);
 
         let local_open = Open::from(self);
 
         // Create channels
         let (control_tx, control_rx) = mpsc::channel(DEFAULT_CONTROL_CHAN_BUF);
-        let (outgoing_tx, outgoing_rx) = mpsc::channel(buffer_size);
-        let connection = Connection::new(local_state, local_open);
+        let (outgoing_tx, outgoing_rx) = session_frame_channel(buffer_size, qos_weights);
+        #[allow(unused_mut)]
+        let mut connection = Connection::new(local_state, local_open);
+
+        #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+        let incoming_sessions = match accept_incoming_sessions {
+            true => {
+                let (session_listener, incoming_sessions) = mpsc::channel(buffer_size);
+                connection.session_listener = Some(session_listener);
+                Some(incoming_sessions)
+            }
+            false => None,
+        };
 
-        let engine = ConnectionEngine::open(transport, connection, control_rx, outgoing_rx).await?;
+        let mut engine =
+            ConnectionEngine::open(transport, connection, control_rx, outgoing_rx).await?;
+        engine.set_write_batching(write_batching);
+        engine.set_watchdog(watchdog.clone());
         // Self::spawn_engine(engine, control_tx, outgoing_tx)
-        (spawn_engine_fn)(engine, control_tx, outgoing_tx)
+        let mut connection_handle = (spawn_engine_fn)(engine, control_tx, outgoing_tx)?;
+        connection_handle.close_on_drop = close_on_drop;
+        connection_handle.watchdog = watchdog;
+        connection_handle.container_id = container_id;
+        #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+        {
+            connection_handle.incoming_sessions = incoming_sessions;
+        }
+        Ok(connection_handle)
     }
 }
 
@@ -738,18 +1141,13 @@ impl<'a> Builder<'a, mode::ConnectorWithId, ()> {
         F: FnOnce(
             ConnectionEngine<tokio_rustls::client::TlsStream<Io>, Connection>,
             mpsc::Sender<ConnectionControl>,
-            mpsc::Sender<SessionFrame>,
+            SessionFrameSenders,
         ) -> Result<ConnectionHandle<()>, OpenError>,
     {
-        use librustls::{ClientConfig, RootCertStore};
         use std::sync::Arc;
         use tokio_rustls::TlsConnector;
 
-        let mut root_cert_store = RootCertStore::empty();
-        root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-        let config = ClientConfig::builder()
-            .with_root_certificates(root_cert_store)
-            .with_no_client_auth();
+        let config = super::tls::rustls_client_config(&self.server_cert_verification);
         let connector = TlsConnector::from(Arc::new(config));
         let tls_stream =
             Transport::connect_tls_with_rustls(stream, domain, &connector, self.alt_tls_estab)
@@ -773,15 +1171,16 @@ impl<'a> Builder<'a, mode::ConnectorWithId, ()> {
         F: FnOnce(
             ConnectionEngine<tokio_native_tls::TlsStream<Io>, Connection>,
             mpsc::Sender<ConnectionControl>,
-            mpsc::Sender<SessionFrame>,
+            SessionFrameSenders,
         ) -> Result<ConnectionHandle<()>, OpenError>,
     {
-        let connector = libnative_tls::TlsConnector::new()
+        let connector = super::tls::native_tls_connector(&self.server_cert_verification)
             .map_err(|e| OpenError::Io(io::Error::new(io::ErrorKind::Other, format!("{:?}", e))))?;
         let connector = tokio_native_tls::TlsConnector::from(connector);
         let tls_stream =
             Transport::connect_tls_with_native_tls(stream, domain, &connector, self.alt_tls_estab)
                 .await?;
+        super::tls::verify_native_tls_peer(&tls_stream, &self.server_cert_verification, domain)?;
         self.connect_with_stream(tls_stream, spawn_engine_fn).await
     }
 }
@@ -855,29 +1254,65 @@ cfg_not_wasm32! {
         ///     .await.unwrap();
         /// ```
         ///
+        /// # Failover
+        ///
+        /// If alternative hosts are set with [`alt_hosts`](#method.alt_hosts), they are attempted
+        /// in order after the url if the connection cannot be opened, and the hosts that failed
+        /// recently are attempted last.
+        ///
+        /// ```rust, ignore
+        /// let connection = Connection::builder()
+        ///     .container_id("connection-1")
+        ///     .alt_hosts(["amqp://broker-2:5672", "amqp://broker-3:5672"])
+        ///     .open("amqp://broker-1:5672")
+        ///     .await.unwrap();
+        /// ```
         pub async fn open(
-            mut self,
+            self,
             url: impl TryInto<Url, Error = impl Into<OpenError>>,
         ) -> Result<ConnectionHandle<()>, OpenError> {
+            self.validate()?;
             let url = url.try_into().map_err(Into::into)?;
+            if self.alt_hosts.is_empty() {
+                return self.open_url(url).await;
+            }
+
+            let hosts = self.failover_hosts(url)?;
+            let clock = self.clock.clone();
+            failover::open_with_failover(hosts, &self.host_health, || clock.now(), |url| {
+                self.clone().open_url(url)
+            })
+            .await
+        }
 
+        async fn open_url(mut self, url: Url) -> Result<ConnectionHandle<()>, OpenError> {
             // Url info will override the builder fields
             // only override if value exists
             self.scheme = url.scheme();
             if let Some(hostname) = url.host_str() {
                 self.hostname = Some(hostname);
             }
-            if let Some(domain) = url.domain() {
+            if let Some(domain) = tls_name_from_url(&url) {
                 self.domain = Some(domain);
             }
             if let Ok(profile) = SaslProfile::try_from(&url) {
                 self.sasl_profile = Some(profile);
             }
 
-            let addr = url.socket_addrs(|| default_port(url.scheme()))?;
-            let stream = TcpStream::connect(&*addr).await?; // std::io::Error
+            #[cfg(feature = "websocket")]
+            if let Some(ws_url) = crate::transport::websocket::websocket_url(&url)? {
+                let stream = crate::transport::websocket::connect(&ws_url).await?;
+                // TLS is established by the WebSocket
+                self.scheme = "amqp";
+                return self.open_with_stream(stream).await;
+            }
+
+            let addrs = connect::resolve(&url, default_port(url.scheme())).await?;
+            let (stream, peer_addr) = self.connect_strategy.connect(addrs, &*self.clock).await?;
 
-            self.open_with_stream(stream).await
+            let mut connection_handle = self.open_with_stream(stream).await?;
+            connection_handle.peer_addr = Some(peer_addr);
+            Ok(connection_handle)
         }
 
         /// Open with an IO that implements `AsyncRead` and `AsyncWrite`.
@@ -922,12 +1357,13 @@ cfg_not_wasm32! {
         where
             Io: AsyncRead + AsyncWrite + std::fmt::Debug + SendBound + Unpin + 'static,
         {
+            self.validate()?;
             match self.scheme {
                 "amqp" => self.connect_with_stream(stream, spawn_engine).await,
                 "amqps" => {
                     #[cfg(all(feature = "rustls", not(feature = "native-tls")))]
                     {
-                        let domain = self.domain.ok_or(OpenError::InvalidDomain)?;
+                        let domain = self.tls_name()?;
                         return self
                             .connect_tls_with_rustls_default(stream, domain, spawn_engine)
                             .await;
@@ -939,7 +1375,7 @@ cfg_not_wasm32! {
                         not(target_arch = "wasm32")
                     ))]
                     {
-                        let domain = self.domain.ok_or_else(|| OpenError::InvalidDomain)?;
+                        let domain = self.tls_name()?;
                         return self
                             .connect_tls_with_native_tls_default(stream, domain, spawn_engine)
                             .await;
@@ -964,6 +1400,7 @@ cfg_wasm32! {
         where
             Io: AsyncRead + AsyncWrite + std::fmt::Debug + Unpin + 'static,
         {
+            self.validate()?;
             match self.scheme {
                 "amqp" => {
                     let spawn_engine_fn = |engine, control_tx, outgoing_tx| {
@@ -974,7 +1411,7 @@ cfg_wasm32! {
                 "amqps" => {
                     #[cfg(all(feature = "rustls", not(feature = "native-tls")))]
                     {
-                        let domain = self.domain.ok_or(OpenError::InvalidDomain)?;
+                        let domain = self.tls_name()?;
                         let spawn_engine_fn = |engine, control_tx, outgoing_tx| {
                             spawn_engine_on_current_local_set(engine, control_tx, outgoing_tx)
                         };
@@ -989,7 +1426,7 @@ cfg_wasm32! {
                         not(target_arch = "wasm32")
                     ))]
                     {
-                        let domain = self.domain.ok_or_else(|| OpenError::InvalidDomain)?;
+                        let domain = self.tls_name()?;
                         return self
                             .connect_tls_with_native_tls_default(stream, domain, spawn_engine)
                             .await;
@@ -1011,6 +1448,7 @@ cfg_wasm32! {
         where
             Io: AsyncRead + AsyncWrite + std::fmt::Debug + Unpin + 'static,
         {
+            self.validate()?;
             match self.scheme {
                 "amqp" => {
                     let spawn_engine_fn = |engine, control_tx, outgoing_tx| {
@@ -1021,7 +1459,7 @@ cfg_wasm32! {
                 "amqps" => {
                     #[cfg(all(feature = "rustls", not(feature = "native-tls")))]
                     {
-                        let domain = self.domain.ok_or(OpenError::InvalidDomain)?;
+                        let domain = self.tls_name()?;
                         let spawn_engine_fn = |engine, control_tx, outgoing_tx| {
                             spawn_engine_on_local_set(engine, control_tx, outgoing_tx, local_set)
                         };
@@ -1036,7 +1474,7 @@ cfg_wasm32! {
                         not(target_arch = "wasm32")
                     ))]
                     {
-                        let domain = self.domain.ok_or_else(|| OpenError::InvalidDomain)?;
+                        let domain = self.tls_name()?;
                         return self
                             .connect_tls_with_native_tls_default(stream, domain, spawn_engine)
                             .await;
@@ -1124,28 +1562,43 @@ cfg_not_wasm32! {
             /// ```
             ///
             pub async fn open(
-                mut self,
+                self,
                 url: impl TryInto<Url, Error = impl Into<OpenError>>,
             ) -> Result<ConnectionHandle<()>, OpenError> {
+                self.validate()?;
                 let url = url.try_into().map_err(Into::into)?;
+                if self.alt_hosts.is_empty() {
+                    return self.open_url(url).await;
+                }
 
+                let hosts = self.failover_hosts(url)?;
+                let clock = self.clock.clone();
+                failover::open_with_failover(hosts, &self.host_health, || clock.now(), |url| {
+                    self.clone().open_url(url)
+                })
+                .await
+            }
+
+            async fn open_url(mut self, url: Url) -> Result<ConnectionHandle<()>, OpenError> {
                 // Url info will override the builder fields
                 // only override if value exists
                 self.scheme = url.scheme();
                 if let Some(hostname) = url.host_str() {
                     self.hostname = Some(hostname);
                 }
-                if let Some(domain) = url.domain() {
+                if let Some(domain) = tls_name_from_url(&url) {
                     self.domain = Some(domain);
                 }
                 if let Ok(profile) = SaslProfile::try_from(&url) {
                     self.sasl_profile = Some(profile);
                 }
 
-                let addr = url.socket_addrs(|| default_port(url.scheme()))?;
-                let stream = TcpStream::connect(&*addr).await?; // std::io::Error
+                let addrs = connect::resolve(&url, default_port(url.scheme())).await?;
+                let (stream, peer_addr) = self.connect_strategy.connect(addrs, &*self.clock).await?;
 
-                self.open_with_stream(stream).await
+                let mut connection_handle = self.open_with_stream(stream).await?;
+                connection_handle.peer_addr = Some(peer_addr);
+                Ok(connection_handle)
             }
 
             /// Open with an IO that implements `AsyncRead` and `AsyncWrite`
@@ -1159,10 +1612,11 @@ cfg_not_wasm32! {
             where
                 Io: AsyncRead + AsyncWrite + std::fmt::Debug + SendBound + Unpin + 'static,
             {
+                self.validate()?;
                 match self.scheme {
                     "amqp" => self.connect_with_stream(stream, spawn_engine).await,
                     "amqps" => {
-                        let domain = self.domain.ok_or(OpenError::InvalidDomain)?;
+                        let domain = self.tls_name()?;
                         let tls_stream = Transport::connect_tls_with_rustls(
                             stream,
                             domain,
@@ -1252,28 +1706,43 @@ cfg_not_wasm32! {
             /// ```
             ///
             pub async fn open(
-                mut self,
+                self,
                 url: impl TryInto<Url, Error = impl Into<OpenError>>,
             ) -> Result<ConnectionHandle<()>, OpenError> {
+                self.validate()?;
                 let url = url.try_into().map_err(Into::into)?;
+                if self.alt_hosts.is_empty() {
+                    return self.open_url(url).await;
+                }
 
+                let hosts = self.failover_hosts(url)?;
+                let clock = self.clock.clone();
+                failover::open_with_failover(hosts, &self.host_health, || clock.now(), |url| {
+                    self.clone().open_url(url)
+                })
+                .await
+            }
+
+            async fn open_url(mut self, url: Url) -> Result<ConnectionHandle<()>, OpenError> {
                 // Url info will override the builder fields
                 // only override if value exists
                 self.scheme = url.scheme();
                 if let Some(hostname) = url.host_str() {
                     self.hostname = Some(hostname);
                 }
-                if let Some(domain) = url.domain() {
+                if let Some(domain) = tls_name_from_url(&url) {
                     self.domain = Some(domain);
                 }
                 if let Ok(profile) = SaslProfile::try_from(&url) {
                     self.sasl_profile = Some(profile);
                 }
 
-                let addr = url.socket_addrs(|| default_port(url.scheme()))?;
-                let stream = TcpStream::connect(&*addr).await?; // std::io::Error
+                let addrs = connect::resolve(&url, default_port(url.scheme())).await?;
+                let (stream, peer_addr) = self.connect_strategy.connect(addrs, &*self.clock).await?;
 
-                self.open_with_stream(stream).await
+                let mut connection_handle = self.open_with_stream(stream).await?;
+                connection_handle.peer_addr = Some(peer_addr);
+                Ok(connection_handle)
             }
 
             /// Open with an IO that implements `AsyncRead` and `AsyncWrite`
@@ -1287,10 +1756,11 @@ cfg_not_wasm32! {
             where
                 Io: AsyncRead + AsyncWrite + std::fmt::Debug + SendBound + Unpin + 'static,
             {
+                self.validate()?;
                 match self.scheme {
                     "amqp" => self.connect_with_stream(stream, spawn_engine).await,
                     "amqps" => {
-                        let domain = self.domain.ok_or(OpenError::InvalidDomain)?;
+                        let domain = self.tls_name()?;
                         let tls_stream = Transport::connect_tls_with_native_tls(
                             stream,
                             domain,
@@ -1311,20 +1781,35 @@ cfg_not_wasm32! {
     fn spawn_engine<Io>(
         engine: ConnectionEngine<Io, Connection>,
         control_tx: mpsc::Sender<ConnectionControl>,
-        outgoing_tx: mpsc::Sender<SessionFrame>,
+        outgoing_tx: SessionFrameSenders,
     ) -> Result<ConnectionHandle<()>, OpenError>
     where
         Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
     {
+        let idle_monitor = engine.idle_monitor().clone();
         let (handle, outcome) = engine.spawn();
 
         let connection_handle = ConnectionHandle {
             is_closed: false,
             control: control_tx,
             handle,
-            outcome,
+            outcome: outcome.into(),
             outgoing: outgoing_tx, // session_control: session_control_tx
             session_listener: (),
+            close_on_drop: false,
+            peer_addr: None,
+            credentials: SharedCredentials::default(),
+            watchdog: None,
+            container_id: String::new(),
+            #[cfg(feature = "acceptor")]
+            incoming_sessions: None,
+            #[cfg(feature = "acceptor")]
+            sessions: Default::default(),
+            #[cfg(feature = "acceptor")]
+            limits: None,
+            #[cfg(feature = "acceptor")]
+            authenticated_identity: None,
+            idle_monitor,
         };
 
         Ok(connection_handle)
@@ -1335,7 +1820,7 @@ cfg_wasm32! {
     fn spawn_engine_on_local_set<Io>(
         engine: ConnectionEngine<Io, Connection>,
         control_tx: mpsc::Sender<ConnectionControl>,
-        outgoing_tx: mpsc::Sender<SessionFrame>,
+        outgoing_tx: SessionFrameSenders,
         local_set: &tokio::task::LocalSet,
     ) -> Result<ConnectionHandle<()>, OpenError>
     where
@@ -1347,9 +1832,14 @@ cfg_wasm32! {
             is_closed: false,
             control: control_tx,
             handle,
-            outcome,
+            outcome: outcome.into(),
             outgoing: outgoing_tx, // session_control: session_control_tx
             session_listener: (),
+            close_on_drop: false,
+            peer_addr: None,
+            credentials: SharedCredentials::default(),
+            watchdog: None,
+            container_id: String::new(),
         };
 
         Ok(connection_handle)
@@ -1358,7 +1848,7 @@ cfg_wasm32! {
     fn spawn_engine_on_current_local_set<Io>(
         engine: ConnectionEngine<Io, Connection>,
         control_tx: mpsc::Sender<ConnectionControl>,
-        outgoing_tx: mpsc::Sender<SessionFrame>,
+        outgoing_tx: SessionFrameSenders,
     ) -> Result<ConnectionHandle<()>, OpenError>
     where
         Io: AsyncRead + AsyncWrite + std::fmt::Debug + Unpin + 'static,
@@ -1369,15 +1859,32 @@ cfg_wasm32! {
             is_closed: false,
             control: control_tx,
             handle,
-            outcome,
+            outcome: outcome.into(),
             outgoing: outgoing_tx, // session_control: session_control_tx
             session_listener: (),
+            close_on_drop: false,
+            peer_addr: None,
+            credentials: SharedCredentials::default(),
+            watchdog: None,
+            container_id: String::new(),
         };
 
         Ok(connection_handle)
     }
 }
 
+cfg_not_wasm32! {
+    /// The name used for the TLS handshake when the connection is opened with an url. This is the
+    /// domain, or the IP address (without the brackets of an IPv6 address) if the host is not a
+    /// domain
+    fn tls_name_from_url(url: &Url) -> Option<&str> {
+        url.domain().or_else(|| {
+            url.host_str()
+                .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use url::Url;
@@ -1388,4 +1895,46 @@ mod tests {
         assert_eq!(url.port(), None);
         let _addrs = url.socket_addrs(|| Some(5672)).unwrap();
     }
+
+    #[test]
+    fn validate_rejects_incoherent_configuration() {
+        use crate::connection::{Connection, OpenError};
+
+        let builder = Connection::builder().container_id("validate");
+        assert!(builder.validate().is_ok());
+
+        let builder = Connection::builder()
+            .container_id("validate")
+            .max_frame_size(511);
+        assert!(matches!(
+            builder.validate(),
+            Err(OpenError::MaxFrameSizeTooSmall(511))
+        ));
+
+        let builder = Connection::builder()
+            .container_id("validate")
+            .buffer_size(0);
+        assert!(matches!(builder.validate(), Err(OpenError::ZeroBufferSize)));
+    }
+
+    #[test]
+    fn tls_name_falls_back_to_ip_address_and_can_be_overridden() {
+        use crate::connection::{Connection, OpenError};
+
+        use super::tls_name_from_url;
+
+        let url: Url = "amqps://example.net:5671".try_into().unwrap();
+        assert_eq!(tls_name_from_url(&url), Some("example.net"));
+        let url: Url = "amqps://10.0.0.1:5671".try_into().unwrap();
+        assert_eq!(tls_name_from_url(&url), Some("10.0.0.1"));
+        let url: Url = "amqps://[::1]:5671".try_into().unwrap();
+        assert_eq!(tls_name_from_url(&url), Some("::1"));
+
+        let builder = Connection::builder().container_id("tls");
+        assert!(matches!(builder.tls_name(), Err(OpenError::InvalidDomain)));
+        let builder = builder.domain("10.0.0.1");
+        assert_eq!(builder.tls_name().unwrap(), "10.0.0.1");
+        let builder = builder.tls_server_name("broker.lab");
+        assert_eq!(builder.tls_name().unwrap(), "broker.lab");
+    }
 }
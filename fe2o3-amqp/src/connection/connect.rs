@@ -0,0 +1,270 @@
+//! Strategies to establish the TCP stream when a connection is opened with an url
+
+use std::time::Duration;
+
+cfg_not_wasm32! {
+    use std::{io, net::SocketAddr};
+
+    use futures_util::{stream::FuturesUnordered, StreamExt};
+    use tokio::net::TcpStream;
+    use url::{Host, Url};
+
+    use crate::clock::{self, Clock};
+}
+
+/// Default delay between two connection attempts with [`ConnectStrategy::Parallel`]
+///
+/// This value is taken from RFC 8305 (Happy Eyeballs Version 2)
+pub const DEFAULT_CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Strategy to connect to the addresses that the hostname of the url resolves to
+///
+/// The hostname is resolved asynchronously and every resolved address is attempted until one of
+/// them succeeds. The address that is eventually connected to is available with
+/// [`ConnectionHandle::peer_addr`](crate::connection::ConnectionHandle::peer_addr).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectStrategy {
+    /// Attempt the resolved addresses one after another in the order they are returned by the
+    /// resolver
+    Sequential {
+        /// Maximum time to wait for a single attempt before moving on to the next address. `None`
+        /// waits for the operating system to give up on the attempt
+        attempt_timeout: Option<Duration>,
+    },
+
+    /// Happy eyeballs style parallel attempts (RFC 8305)
+    ///
+    /// The resolved addresses are interleaved by address family, and a new attempt is started
+    /// every `attempt_delay` or as soon as the previous attempt fails, without cancelling the
+    /// attempts that are still in progress. The first successful attempt wins.
+    Parallel {
+        /// Delay before starting the next attempt while earlier attempts are still in progress
+        attempt_delay: Duration,
+    },
+}
+
+impl Default for ConnectStrategy {
+    fn default() -> Self {
+        Self::Sequential {
+            attempt_timeout: None,
+        }
+    }
+}
+
+impl ConnectStrategy {
+    /// Happy eyeballs style parallel attempts with the [`DEFAULT_CONNECTION_ATTEMPT_DELAY`]
+    pub fn parallel() -> Self {
+        Self::Parallel {
+            attempt_delay: DEFAULT_CONNECTION_ATTEMPT_DELAY,
+        }
+    }
+}
+
+cfg_not_wasm32! {
+    /// Resolves the host of the url without blocking the runtime
+    pub(crate) async fn resolve(url: &Url, default_port: Option<u16>) -> io::Result<Vec<SocketAddr>> {
+        let port = url.port().or(default_port).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "No port is found in the url")
+        })?;
+        let addrs = match url.host() {
+            Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port)).await?.collect(),
+            Some(Host::Ipv4(ip)) => vec![SocketAddr::from((ip, port))],
+            Some(Host::Ipv6(ip)) => vec![SocketAddr::from((ip, port))],
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "No host is found in the url",
+                ))
+            }
+        };
+        Ok(addrs)
+    }
+
+    impl ConnectStrategy {
+        /// Connects to one of the addresses with the strategy. The attempt timeout and delay
+        /// follow the `clock`
+        pub(crate) async fn connect(
+            &self,
+            addrs: Vec<SocketAddr>,
+            clock: &dyn Clock,
+        ) -> io::Result<(TcpStream, SocketAddr)> {
+            if addrs.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "The hostname did not resolve to any address",
+                ));
+            }
+
+            match self {
+                ConnectStrategy::Sequential { attempt_timeout } => {
+                    connect_sequential(addrs, *attempt_timeout, clock).await
+                }
+                ConnectStrategy::Parallel { attempt_delay } => {
+                    connect_parallel(interleave_families(addrs), *attempt_delay, clock).await
+                }
+            }
+        }
+    }
+
+    async fn connect_one(
+        addr: SocketAddr,
+        attempt_timeout: Option<Duration>,
+        clock: &dyn Clock,
+    ) -> io::Result<(TcpStream, SocketAddr)> {
+        let stream = match attempt_timeout {
+            Some(duration) => clock::timeout(clock, duration, TcpStream::connect(addr))
+                .await
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::TimedOut, "Connection attempt timed out")
+                })??,
+            None => TcpStream::connect(addr).await?,
+        };
+        Ok((stream, addr))
+    }
+
+    async fn connect_sequential(
+        addrs: Vec<SocketAddr>,
+        attempt_timeout: Option<Duration>,
+        clock: &dyn Clock,
+    ) -> io::Result<(TcpStream, SocketAddr)> {
+        let mut last_error = None;
+        for addr in addrs {
+            match connect_one(addr, attempt_timeout, clock).await {
+                Ok(connected) => return Ok(connected),
+                Err(error) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(%addr, %error, "Connection attempt failed");
+                    #[cfg(feature = "log")]
+                    log::debug!("Connection attempt to {} failed: {}", addr, error);
+                    last_error = Some(error);
+                }
+            }
+        }
+        // `addrs` is checked to be non-empty
+        Err(last_error.expect("At least one attempt is made"))
+    }
+
+    async fn connect_parallel(
+        addrs: Vec<SocketAddr>,
+        attempt_delay: Duration,
+        clock: &dyn Clock,
+    ) -> io::Result<(TcpStream, SocketAddr)> {
+        let mut addrs = addrs.into_iter();
+        let mut attempts = FuturesUnordered::new();
+        let mut last_error = None;
+
+        loop {
+            if attempts.is_empty() {
+                match addrs.next() {
+                    Some(addr) => attempts.push(connect_one(addr, None, clock)),
+                    // `addrs` is checked to be non-empty
+                    None => return Err(last_error.expect("At least one attempt is made")),
+                }
+            }
+
+            tokio::select! {
+                Some(result) = attempts.next() => match result {
+                    Ok(connected) => return Ok(connected),
+                    Err(error) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(%error, "Connection attempt failed");
+                        #[cfg(feature = "log")]
+                        log::debug!("Connection attempt failed: {}", error);
+                        last_error = Some(error);
+
+                        // Start the next attempt right away
+                        if let Some(addr) = addrs.next() {
+                            attempts.push(connect_one(addr, None, clock));
+                        }
+                    }
+                },
+                _ = clock.sleep(attempt_delay), if !addrs.as_slice().is_empty() => {
+                    if let Some(addr) = addrs.next() {
+                        attempts.push(connect_one(addr, None, clock));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Alternates between the address families, starting with the family of the first address
+    fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        let first_is_ipv6 = match addrs.first() {
+            Some(addr) => addr.is_ipv6(),
+            None => return addrs,
+        };
+        let (preferred, other): (Vec<_>, Vec<_>) = addrs
+            .into_iter()
+            .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+
+        let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+        let mut preferred = preferred.into_iter();
+        let mut other = other.into_iter();
+        loop {
+            match (preferred.next(), other.next()) {
+                (None, None) => break,
+                (a, b) => interleaved.extend(a.into_iter().chain(b)),
+            }
+        }
+        interleaved
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use tokio::net::TcpListener;
+
+    use crate::clock::TokioClock;
+
+    use super::{interleave_families, ConnectStrategy};
+
+    #[test]
+    fn interleave_families_alternates_between_ipv6_and_ipv4() {
+        let addrs: Vec<SocketAddr> = vec![
+            "[::1]:5672".parse().unwrap(),
+            "[::2]:5672".parse().unwrap(),
+            "[::3]:5672".parse().unwrap(),
+            "127.0.0.1:5672".parse().unwrap(),
+        ];
+        let interleaved = interleave_families(addrs.clone());
+        assert_eq!(interleaved, vec![addrs[0], addrs[3], addrs[1], addrs[2]]);
+    }
+
+    #[tokio::test]
+    async fn sequential_connect_skips_unreachable_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let reachable = listener.local_addr().unwrap();
+
+        // Bind and drop to obtain an address that refuses connections
+        let refused = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let unreachable = refused.local_addr().unwrap();
+        drop(refused);
+
+        let strategy = ConnectStrategy::Sequential {
+            attempt_timeout: Some(Duration::from_secs(1)),
+        };
+        let (_stream, addr) = strategy
+            .connect(vec![unreachable, reachable], &TokioClock)
+            .await
+            .unwrap();
+        assert_eq!(addr, reachable);
+    }
+
+    #[tokio::test]
+    async fn parallel_connect_returns_first_successful_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let reachable = listener.local_addr().unwrap();
+
+        let refused = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let unreachable = refused.local_addr().unwrap();
+        drop(refused);
+
+        let (_stream, addr) = ConnectStrategy::parallel()
+            .connect(vec![unreachable, reachable], &TokioClock)
+            .await
+            .unwrap();
+        assert_eq!(addr, reachable);
+    }
+}
@@ -0,0 +1,285 @@
+//! Generation and validation of the container id
+
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Write,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Default prefix of the generated container ids
+pub const DEFAULT_CONTAINER_ID_PREFIX: &str = "fe2o3-amqp";
+
+/// Default number of hexadecimal digits of the random suffix of the generated container ids
+pub const DEFAULT_CONTAINER_ID_SUFFIX_LEN: usize = 16;
+
+/// Default maximum length (in bytes) of a container id
+pub const DEFAULT_MAX_CONTAINER_ID_LEN: usize = 255;
+
+/// The container id is rejected by the [`ContainerIdPolicy`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum InvalidContainerId {
+    /// The container id is empty
+    #[error("Container id must not be empty")]
+    Empty,
+
+    /// The container id is longer than allowed
+    #[error("Container id of {len} bytes exceeds the maximum of {max} bytes")]
+    TooLong {
+        /// Length of the container id in bytes
+        len: usize,
+        /// Maximum length allowed by the policy
+        max: usize,
+    },
+}
+
+/// Generates and validates the container id of a connection
+///
+/// The `container-id` field of the Open frame is mandatory, and the remote peer uses it to tell
+/// the containers apart. A static id that is shared by multiple replicas of an application
+/// therefore confuses the peer (eg. with the `sole-connection-for-container` capability). A
+/// generated id has the form
+///
+/// ```text
+/// <prefix>-<host>-<pid>-<random suffix>
+/// ```
+///
+/// where the host is taken from the `HOSTNAME` (or `COMPUTERNAME`) environment variable, or
+/// `/etc/hostname` on unix, and is shortened if the id would otherwise be longer than `max_len`.
+/// The host and the pid are left out if they are not available (eg. on `wasm32`).
+///
+/// Every container id, generated or not, is validated against the policy before the connection
+/// is opened. The id must not be empty and must not be longer than `max_len` bytes.
+///
+/// # Default
+///
+/// | Field | Default Value |
+/// |-------|---------------|
+/// |`prefix`| [`DEFAULT_CONTAINER_ID_PREFIX`] |
+/// |`include_host`| `true` |
+/// |`include_pid`| `true` |
+/// |`suffix_len`| [`DEFAULT_CONTAINER_ID_SUFFIX_LEN`] |
+/// |`max_len`| [`DEFAULT_MAX_CONTAINER_ID_LEN`] |
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let policy = ContainerIdPolicy::new("order-service").include_pid(false);
+///
+/// let connection = Connection::builder()
+///     .container_id_policy(policy)
+///     .generated_container_id()
+///     .open("amqp://localhost:5672")
+///     .await
+///     .unwrap();
+/// println!("{}", connection.container_id()); // eg. "order-service-node-1-8c1e0f7a3b5d2e94"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerIdPolicy {
+    /// Prefix of the generated ids
+    pub prefix: String,
+
+    /// Whether the host name is included in the generated ids
+    pub include_host: bool,
+
+    /// Whether the process id is included in the generated ids
+    pub include_pid: bool,
+
+    /// Number of hexadecimal digits of the random suffix of the generated ids
+    pub suffix_len: usize,
+
+    /// Maximum length of a container id in bytes
+    pub max_len: usize,
+}
+
+impl Default for ContainerIdPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_CONTAINER_ID_PREFIX)
+    }
+}
+
+impl ContainerIdPolicy {
+    /// Creates a policy that generates ids with the prefix
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            include_host: true,
+            include_pid: true,
+            suffix_len: DEFAULT_CONTAINER_ID_SUFFIX_LEN,
+            max_len: DEFAULT_MAX_CONTAINER_ID_LEN,
+        }
+    }
+
+    /// Whether the host name is included in the generated ids
+    pub fn include_host(mut self, value: bool) -> Self {
+        self.include_host = value;
+        self
+    }
+
+    /// Whether the process id is included in the generated ids
+    pub fn include_pid(mut self, value: bool) -> Self {
+        self.include_pid = value;
+        self
+    }
+
+    /// Number of hexadecimal digits of the random suffix of the generated ids
+    pub fn suffix_len(mut self, suffix_len: usize) -> Self {
+        self.suffix_len = suffix_len;
+        self
+    }
+
+    /// Maximum length of a container id in bytes
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Generates a new container id
+    pub fn generate(&self) -> String {
+        let host = match self.include_host {
+            true => local_host_name(),
+            false => None,
+        };
+        let pid = match self.include_pid {
+            true => process_id(),
+            false => None,
+        };
+        self.generate_with(host.as_deref(), pid)
+    }
+
+    fn generate_with(&self, host: Option<&str>, pid: Option<u32>) -> String {
+        let mut tail = String::new();
+        if let Some(pid) = pid {
+            let _ = write!(tail, "-{}", pid);
+        }
+        if self.suffix_len > 0 {
+            tail.push('-');
+            tail.push_str(&random_hex(self.suffix_len));
+        }
+
+        let mut id = self.prefix.clone();
+        if let Some(host) = host {
+            // Shortens the host name rather than the random suffix if the id is too long
+            let available = self
+                .max_len
+                .saturating_sub(id.len() + tail.len())
+                .saturating_sub(1);
+            let host = &host[..host.len().min(available)];
+            if !host.is_empty() {
+                id.push('-');
+                id.push_str(host);
+            }
+        }
+        id.push_str(&tail);
+        match id.strip_prefix('-') {
+            Some(stripped) => stripped.to_string(),
+            None => id,
+        }
+    }
+
+    /// Checks the container id against the policy
+    pub fn validate(&self, container_id: &str) -> Result<(), InvalidContainerId> {
+        if container_id.is_empty() {
+            return Err(InvalidContainerId::Empty);
+        }
+        if container_id.len() > self.max_len {
+            return Err(InvalidContainerId::TooLong {
+                len: container_id.len(),
+                max: self.max_len,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Host name with only the characters that are safe in a container id
+fn local_host_name() -> Option<String> {
+    let host = std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(read_host_name_file)?;
+    let host: String = host
+        .trim()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'))
+        .collect();
+    (!host.is_empty()).then_some(host)
+}
+
+#[cfg(unix)]
+fn read_host_name_file() -> Option<String> {
+    std::fs::read_to_string("/etc/hostname").ok()
+}
+
+#[cfg(not(unix))]
+fn read_host_name_file() -> Option<String> {
+    None
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn process_id() -> Option<u32> {
+    Some(std::process::id())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn process_id() -> Option<u32> {
+    None
+}
+
+/// Random hexadecimal digits. The randomly seeded keys of `RandomState` are mixed with a process
+/// wide counter so that consecutive calls produce different digits
+pub(crate) fn random_hex(len: usize) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut out = String::with_capacity(len + 16);
+    while out.len() < len {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        let _ = write!(out, "{:016x}", hasher.finish());
+    }
+    out.truncate(len);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContainerIdPolicy, InvalidContainerId, DEFAULT_CONTAINER_ID_SUFFIX_LEN};
+
+    #[test]
+    fn generated_ids_have_prefix_host_pid_and_unique_suffix() {
+        let policy = ContainerIdPolicy::new("app");
+        let id = policy.generate_with(Some("node-1"), Some(42));
+        assert!(id.starts_with("app-node-1-42-"));
+        assert_eq!(
+            id.len(),
+            "app-node-1-42-".len() + DEFAULT_CONTAINER_ID_SUFFIX_LEN
+        );
+        assert_ne!(id, policy.generate_with(Some("node-1"), Some(42)));
+        assert!(policy.validate(&id).is_ok());
+
+        let policy = ContainerIdPolicy::new("").suffix_len(4);
+        let id = policy.generate_with(None, None);
+        assert_eq!(id.len(), 4);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn host_is_shortened_to_respect_max_len() {
+        let policy = ContainerIdPolicy::new("app").suffix_len(8).max_len(20);
+        let id = policy.generate_with(Some("a-very-long-host-name"), Some(7));
+        assert_eq!(id.len(), 20);
+        assert!(id.starts_with("app-a-ver-7-"));
+        assert!(policy.validate(&id).is_ok());
+    }
+
+    #[test]
+    fn invalid_ids_are_rejected() {
+        let policy = ContainerIdPolicy::default().max_len(8);
+        assert_eq!(policy.validate(""), Err(InvalidContainerId::Empty));
+        assert_eq!(
+            policy.validate("123456789"),
+            Err(InvalidContainerId::TooLong { len: 9, max: 8 })
+        );
+        assert!(policy.validate("12345678").is_ok());
+    }
+}
@@ -5,7 +5,7 @@ use std::io;
 use std::time::Duration;
 
 use fe2o3_amqp_types::definitions::{self, AmqpError};
-use fe2o3_amqp_types::performatives::Close;
+use fe2o3_amqp_types::performatives::{Close, Open};
 use futures_util::{SinkExt, StreamExt};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::mpsc::Receiver;
@@ -17,19 +17,102 @@ use crate::endpoint::{IncomingChannel, OutgoingChannel};
 use crate::frames::amqp::{self, Frame, FrameBody};
 use crate::session::frame::{SessionFrame, SessionFrameBody};
 use crate::transport::Transport;
-use crate::util::Running;
+use crate::util::{IdleTimeout, Running};
+use crate::watchdog::{self, EngineKind, Watchdog};
 use crate::{endpoint, transport, SendBound};
 
-use super::{heartbeat::HeartBeat, ConnectionState};
+use super::OutgoingSessionFrames;
+
+cfg_not_wasm32! {
+    use std::sync::Arc;
+
+    use super::idle_monitor::IdleMonitor;
+}
+use super::{heartbeat::HeartBeat, sole_connection, ConnectionState, WriteBatching};
 use super::{AllocSessionError, ConnectionInnerError, ConnectionStateError, Error, OpenError};
 
+/// Waits for the remote Open frame
+pub(crate) async fn recv_remote_open<Io>(
+    transport: &mut Transport<Io, amqp::Frame>,
+) -> Result<(IncomingChannel, Open), OpenError>
+where
+    Io: AsyncRead + Unpin,
+{
+    let frame = match transport.next().await {
+        Some(frame) => match frame {
+            Ok(fr) => fr,
+            Err(error) => return Err(error.into()),
+        },
+        None => {
+            return Err(OpenError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Expecting an Open frame",
+            )))
+        }
+    };
+    let Frame { channel, body } = frame;
+    let channel = IncomingChannel(channel);
+    match body {
+        FrameBody::Open(open) => Ok((channel, open)),
+        FrameBody::Close(close) => match close.error {
+            Some(error) => Err(OpenError::RemoteClosedWithError(error)),
+            None => Err(OpenError::RemoteClosed),
+        },
+        _ => Err(OpenError::IllegalState),
+    }
+}
+
+/// Waits for the remote Close frame, discarding any other frame
+pub(crate) async fn recv_remote_close<Io>(
+    transport: &mut Transport<Io, amqp::Frame>,
+) -> Result<(IncomingChannel, Close), OpenError>
+where
+    Io: AsyncRead + Unpin,
+{
+    loop {
+        let frame = transport.next().await.ok_or_else(|| {
+            OpenError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Expecting a Close frame",
+            ))
+        })??;
+        if let FrameBody::Close(close) = frame.body {
+            return Ok((IncomingChannel(frame.channel), close));
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct ConnectionEngine<Io, C> {
     transport: Transport<Io, amqp::Frame>,
     connection: C,
     control: Receiver<ConnectionControl>,
-    outgoing_session_frames: Receiver<SessionFrame>,
+    outgoing_session_frames: OutgoingSessionFrames,
     heartbeat: HeartBeat,
+    pending_pings: Vec<oneshot::Sender<()>>,
+    write_batching: WriteBatching,
+    // The batch of outgoing session frames that waits for the flush deadline
+    pending_batch: Option<PendingBatch>,
+    watchdog: Option<Watchdog>,
+    #[cfg(not(target_arch = "wasm32"))]
+    idle_monitor: Arc<IdleMonitor>,
+}
+
+/// Outgoing session frames that are fed to the transport but not flushed yet
+#[derive(Debug)]
+struct PendingBatch {
+    frames: usize,
+    deadline: IdleTimeout,
+}
+
+/// Resolves when the flush deadline of the pending batch elapses, and never if there is none
+async fn flush_deadline_elapsed(pending_batch: &mut Option<PendingBatch>) {
+    match pending_batch {
+        Some(batch) => {
+            let _ = (&mut batch.deadline).await;
+        }
+        None => std::future::pending().await,
+    }
 }
 
 cfg_not_wasm32! {
@@ -40,13 +123,16 @@ cfg_not_wasm32! {
         C::AllocError: Into<AllocSessionError>,
         C::CloseError: From<transport::Error>,
         C::OpenError: From<transport::Error>,
+        C::Error: From<transport::Error>,
         ConnectionInnerError: From<C::Error> + From<C::OpenError> + From<C::CloseError>,
         ConnectionStateError: From<C::OpenError> + From<C::CloseError>,
         OpenError: From<C::OpenError>,
     {
         pub fn spawn(self) -> (JoinHandle<()>, oneshot::Receiver<Result<(), Error>>) {
-            let (tx, rx) = oneshot::channel();
-            let handle = tokio::spawn(self.event_loop(tx));
+            let watchdog = self.watchdog.clone();
+            let (event_loop, rx) =
+                watchdog::supervise(EngineKind::Connection, watchdog, |tx| self.event_loop(tx));
+            let handle = tokio::spawn(event_loop);
             (handle, rx)
         }
     }
@@ -60,6 +146,7 @@ cfg_wasm32! {
         C::AllocError: Into<AllocSessionError>,
         C::CloseError: From<transport::Error>,
         C::OpenError: From<transport::Error>,
+        C::Error: From<transport::Error>,
         ConnectionInnerError: From<C::Error> + From<C::OpenError> + From<C::CloseError>,
         ConnectionStateError: From<C::OpenError> + From<C::CloseError>,
         OpenError: From<C::OpenError>,
@@ -67,8 +154,10 @@ cfg_wasm32! {
         pub fn spawn_local(
             self
         ) -> (JoinHandle<()>, oneshot::Receiver<Result<(), Error>>) {
-            let (tx, rx) = oneshot::channel();
-            let handle = tokio::task::spawn_local(self.event_loop(tx));
+            let watchdog = self.watchdog.clone();
+            let (event_loop, rx) =
+                watchdog::supervise(EngineKind::Connection, watchdog, |tx| self.event_loop(tx));
+            let handle = tokio::task::spawn_local(event_loop);
             (handle, rx)
         }
 
@@ -76,8 +165,10 @@ cfg_wasm32! {
             self,
             local_set: &tokio::task::LocalSet,
         ) -> (JoinHandle<()>, oneshot::Receiver<Result<(), Error>>) {
-            let (tx, rx) = oneshot::channel();
-            let handle = local_set.spawn_local(self.event_loop(tx));
+            let watchdog = self.watchdog.clone();
+            let (event_loop, rx) =
+                watchdog::supervise(EngineKind::Connection, watchdog, |tx| self.event_loop(tx));
+            let handle = local_set.spawn_local(event_loop);
             (handle, rx)
         }
     }
@@ -90,6 +181,7 @@ where
     C::AllocError: Into<AllocSessionError>,
     C::CloseError: From<transport::Error>,
     C::OpenError: From<transport::Error>,
+    C::Error: From<transport::Error>,
     ConnectionInnerError: From<C::Error> + From<C::OpenError> + From<C::CloseError>,
     ConnectionStateError: From<C::OpenError> + From<C::CloseError>,
     OpenError: From<C::OpenError>,
@@ -158,37 +250,102 @@ where
         }
     }
 
-    async fn open_inner(&mut self) -> Result<(), OpenError> {
-        self.connection.send_open(&mut self.transport).await?;
-
-        // Wait for an Open
-        let frame = match self.transport.next().await {
-            Some(frame) => match frame {
-                Ok(fr) => fr,
-                Err(error) => return Err(error.into()),
-            },
+    async fn open_inner(
+        &mut self,
+        remote_open: Option<(IncomingChannel, Open)>,
+    ) -> Result<(), OpenError> {
+        match remote_open {
+            // The remote Open has already been received (ie. to select a virtual host), so the
+            // local Open is sent after handling the remote one
+            Some((channel, remote_open)) => {
+                self.on_remote_open(channel, remote_open)?;
+                self.connection.send_open(&mut self.transport).await?;
+                Ok(())
+            }
             None => {
-                return Err(OpenError::Io(io::Error::new(
-                    io::ErrorKind::UnexpectedEof,
-                    "Expecting an Open frame",
-                )))
+                let result = self.open_and_recv_remote_open().await;
+                match result {
+                    Err(OpenError::RemoteClosedWithError(error))
+                        if sole_connection::is_desired(self.connection.local_open())
+                            && sole_connection::is_container_id_in_use(&error) =>
+                    {
+                        Err(OpenError::ContainerIdInUse(error))
+                    }
+                    _ => result,
+                }
             }
-        };
-        let Frame { channel, body } = frame;
-        let channel = endpoint::IncomingChannel(channel);
-        let remote_open = match body {
-            FrameBody::Open(open) => open,
-            FrameBody::Close(close) => match close.error {
-                Some(error) => return Err(OpenError::RemoteClosedWithError(error)),
-                None => return Err(OpenError::RemoteClosed),
-            },
-            _ => return Err(OpenError::IllegalState),
-        };
+        }
+    }
+
+    async fn open_and_recv_remote_open(&mut self) -> Result<(), OpenError> {
+        self.connection.send_open(&mut self.transport).await?;
+        let (channel, remote_open) = recv_remote_open(&mut self.transport).await?;
+        let establishment_failed = sole_connection::is_establishment_failed(&remote_open);
+        self.on_remote_open(channel, remote_open)?;
+
+        if establishment_failed {
+            // The remote peer is going to close the connection immediately, and the reason is
+            // carried by the Close frame
+            let (channel, close) = recv_remote_close(&mut self.transport).await?;
+            self.connection
+                .on_incoming_close(channel, close)
+                .map_err(ConnectionStateError::from)?;
+            return Err(OpenError::RemoteClosed);
+        }
+        Ok(())
+    }
+
+    /// The heartbeat follows the clock of the transport
+    #[cfg(not(target_arch = "wasm32"))]
+    fn new_heartbeat(&self, period: Duration) -> HeartBeat {
+        HeartBeat::with_clock(period, self.transport.clock().clone())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn new_heartbeat(&self, period: Duration) -> HeartBeat {
+        HeartBeat::new(period)
+    }
+
+    /// The timer follows the clock of the transport
+    #[cfg(not(target_arch = "wasm32"))]
+    fn new_timer(&self, duration: Duration) -> IdleTimeout {
+        IdleTimeout::with_clock(duration, self.transport.clock().clone())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn new_timer(&self, duration: Duration) -> IdleTimeout {
+        IdleTimeout::new(duration)
+    }
+
+    pub(crate) fn set_write_batching(&mut self, write_batching: WriteBatching) {
+        self.write_batching = write_batching;
+    }
+
+    pub(crate) fn set_watchdog(&mut self, watchdog: Option<Watchdog>) {
+        self.watchdog = watchdog;
+    }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn idle_monitor(&self) -> &Arc<IdleMonitor> {
+        &self.idle_monitor
+    }
+
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    pub(crate) fn connection(&self) -> &C {
+        &self.connection
+    }
+
+    fn on_remote_open(
+        &mut self,
+        channel: IncomingChannel,
+        remote_open: Open,
+    ) -> Result<(), OpenError> {
         // Handle incoming remote_open
         let remote_max_frame_size = remote_open.max_frame_size.0 as usize;
         let remote_idle_timeout = remote_open.idle_time_out;
         self.connection.on_incoming_open(channel, remote_open)?;
+        #[cfg(not(target_arch = "wasm32"))]
+        self.idle_monitor.on_remote_open(remote_idle_timeout);
 
         // update transport setting
         let local_max_frame_size = self.connection.local_open().max_frame_size.0 as usize;
@@ -202,7 +359,7 @@ where
             Some(0) | None => self.heartbeat = HeartBeat::never(),
             Some(millis) => {
                 let period = Duration::from_millis(*millis as u64);
-                self.heartbeat = HeartBeat::new(period);
+                self.heartbeat = self.new_heartbeat(period);
             }
         };
 
@@ -214,17 +371,47 @@ where
         transport: Transport<Io, amqp::Frame>,
         connection: C,
         control: Receiver<ConnectionControl>,
-        outgoing_session_frames: Receiver<SessionFrame>,
+        outgoing_session_frames: OutgoingSessionFrames,
+    ) -> Result<Self, OpenError> {
+        Self::open_with_remote_open(
+            transport,
+            connection,
+            control,
+            outgoing_session_frames,
+            None,
+        )
+        .await
+    }
+
+    /// Open Connection without starting the Engine::event_loop(). If `remote_open` is
+    /// `Some(_)`, the remote Open is handled before the local Open is sent
+    pub(crate) async fn open_with_remote_open(
+        transport: Transport<Io, amqp::Frame>,
+        connection: C,
+        control: Receiver<ConnectionControl>,
+        outgoing_session_frames: OutgoingSessionFrames,
+        remote_open: Option<(IncomingChannel, Open)>,
     ) -> Result<Self, OpenError> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let idle_monitor = Arc::new(IdleMonitor::new(
+            transport.clock().clone(),
+            transport.idle_timeout(),
+        ));
         let mut engine = Self {
             transport,
             connection,
             control,
             outgoing_session_frames,
             heartbeat: HeartBeat::never(),
+            pending_pings: Vec::new(),
+            write_batching: WriteBatching::default(),
+            pending_batch: None,
+            watchdog: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            idle_monitor,
         };
 
-        match engine.open_inner().await {
+        match engine.open_inner(remote_open).await {
             Ok(_) => Ok(engine),
             Err(error) => {
                 match engine.close_connection(None).await {
@@ -264,6 +451,9 @@ where
             ConnectionState::Opened => {}
             _ => return Err(ConnectionInnerError::IllegalState),
         };
+        if self.connection.is_refused_session(channel) {
+            return Ok(());
+        }
 
         match self.connection.session_tx_by_incoming_channel(channel) {
             Some(tx) => tx.send(frame).await?,
@@ -285,19 +475,23 @@ where
             FrameBody::Open(open) => {
                 let remote_idle_timeout = open.idle_time_out;
                 self.connection.on_incoming_open(channel, open)?;
+                #[cfg(not(target_arch = "wasm32"))]
+                self.idle_monitor.on_remote_open(remote_idle_timeout);
 
                 // Set heartbeat here because in pipelined-open, the Open frame
                 // may be recved after mux loop is started
                 match &remote_idle_timeout {
                     Some(millis) => {
                         let period = Duration::from_millis(*millis as u64);
-                        self.heartbeat = HeartBeat::new(period);
+                        self.heartbeat = self.new_heartbeat(period);
                     }
                     None => self.heartbeat = HeartBeat::never(),
                 };
             }
             FrameBody::Begin(begin) => {
-                self.connection.on_incoming_begin(channel, begin).await?;
+                self.connection
+                    .on_incoming_begin(channel, begin, &mut self.transport)
+                    .await?;
             }
             FrameBody::Attach(attach) => {
                 let sframe = SessionFrame::new(channel, SessionFrameBody::Attach(attach));
@@ -342,11 +536,13 @@ where
                         self.on_outgoing_session_frames(frame).await?;
                     }
 
-                    self.connection
-                        .send_close(&mut self.transport, None)
-                        .await?;
+                    // The remote error takes precedence over failing to reply
+                    let reply = self.connection.send_close(&mut self.transport, None).await;
+                    result?;
+                    reply?;
+                } else {
+                    result?;
                 }
-                result?;
             }
             FrameBody::Empty => {
                 // do nothing, IdleTimeout is tracked by Transport
@@ -399,6 +595,15 @@ where
                     log::error!("{:?}", error);
                 }
             }
+            ConnectionControl::Ping(resp) => {
+                // The responder is dropped if the connection is not opened
+                if let ConnectionState::Opened = self.connection.local_state() {
+                    self.transport.send(Frame::empty()).await?;
+                    // Pings that timed out on the caller side are no longer waited for
+                    self.pending_pings.retain(|resp| !resp.is_closed());
+                    self.pending_pings.push(resp);
+                }
+            }
         }
 
         match self.connection.local_state() {
@@ -409,10 +614,53 @@ where
 
     #[inline]
     #[cfg_attr(feature = "tracing", tracing::instrument(name = "SEND", skip_all))]
+    /// Writes the frame along with the frames that are already queued and flushes them together.
+    /// With a flush deadline, a batch that is not full is left pending until the deadline elapses
+    /// or more frames fill it up, while the event loop keeps handling the other events
     async fn on_outgoing_session_frames(
         &mut self,
         frame: SessionFrame,
     ) -> Result<Running, ConnectionInnerError> {
+        self.feed_outgoing_session_frame(frame).await?;
+
+        let mut batched = self.pending_batch.as_ref().map_or(0, |batch| batch.frames) + 1;
+        while batched < self.write_batching.max_frames {
+            match self.outgoing_session_frames.try_recv() {
+                Some(frame) => self.feed_outgoing_session_frame(frame).await?,
+                None => break,
+            }
+            batched += 1;
+        }
+
+        let flush_deadline = self.write_batching.flush_deadline;
+        if batched >= self.write_batching.max_frames || flush_deadline.is_zero() {
+            return self.flush_pending_batch().await;
+        }
+        match &mut self.pending_batch {
+            Some(batch) => batch.frames = batched,
+            None => {
+                self.pending_batch = Some(PendingBatch {
+                    frames: batched,
+                    deadline: self.new_timer(flush_deadline),
+                })
+            }
+        }
+        Ok(Running::Continue)
+    }
+
+    /// Flushes the outgoing session frames that are fed to the transport. The frames written by
+    /// the other events are sent after the pending batch, so this may find nothing left to flush
+    async fn flush_pending_batch(&mut self) -> Result<Running, ConnectionInnerError> {
+        self.pending_batch = None;
+        self.transport.flush().await?;
+        Ok(Running::Continue)
+    }
+
+    /// Encodes the frame into the write buffer of the transport without flushing
+    async fn feed_outgoing_session_frame(
+        &mut self,
+        frame: SessionFrame,
+    ) -> Result<(), ConnectionInnerError> {
         match self.connection.local_state() {
             ConnectionState::Opened => {}
             _ => return Err(ConnectionInnerError::IllegalState),
@@ -445,8 +693,18 @@ where
         tracing::trace!(channel = frame.channel, frame = ?frame.body);
         #[cfg(feature = "log")]
         log::trace!("SEND channel = {}, frame = {:?}", frame.channel, frame.body);
-        self.transport.send(frame).await?;
-        Ok(Running::Continue)
+        self.transport.feed(frame).await?;
+        Ok(())
+    }
+
+    /// Resolves all pending pings and records the traffic for the idle timeout warning
+    #[inline]
+    fn on_inbound_traffic(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.idle_monitor.on_inbound_traffic();
+        for resp in self.pending_pings.drain(..) {
+            let _ = resp.send(());
+        }
     }
 
     #[inline]
@@ -468,6 +726,27 @@ where
         error: &ConnectionInnerError,
     ) -> Result<Running, ConnectionInnerError> {
         match error {
+            ConnectionInnerError::TransportError(transport::Error::IdleTimeoutElapsed) => {
+                // The remote peer is most likely gone, so the Close is sent without waiting for
+                // the remote Close
+                let error = definitions::Error::new(
+                    AmqpError::ResourceLimitExceeded,
+                    Some(String::from("Idle timeout elapsed")),
+                    None,
+                );
+                if let ConnectionState::OpenPipe
+                | ConnectionState::OpenReceived
+                | ConnectionState::OpenSent
+                | ConnectionState::Opened
+                | ConnectionState::CloseReceived = self.connection.local_state()
+                {
+                    let _ = self
+                        .connection
+                        .send_close(&mut self.transport, Some(error))
+                        .await;
+                }
+                Ok(Running::Stop)
+            }
             ConnectionInnerError::TransportError(_) => Ok(Running::Stop),
             ConnectionInnerError::IllegalState => {
                 let error = definitions::Error::new(AmqpError::IllegalState, None, None);
@@ -486,7 +765,12 @@ where
                 Ok(Running::Stop)
             }
             ConnectionInnerError::RemoteClosed | ConnectionInnerError::RemoteClosedWithError(_) => {
-                self.close_connection(None).await
+                match self.close_connection(None).await {
+                    // The remote peer may have already dropped the transport after sending the
+                    // Close, in which case the remote error is reported instead
+                    Err(ConnectionInnerError::TransportError(_)) => Ok(Running::Stop),
+                    result => result,
+                }
             }
         }
     }
@@ -501,7 +785,10 @@ where
                     let result = match incoming {
                         Some(incoming) => {
                             match incoming {
-                                Ok(frame) => self.on_incoming(frame).await,
+                                Ok(frame) => {
+                                    self.on_inbound_traffic();
+                                    self.on_incoming(frame).await
+                                },
                                 Err(err) => Err(err.into()),
                             }
                         },
@@ -574,6 +861,9 @@ where
                         }
                     }
                 },
+                _ = flush_deadline_elapsed(&mut self.pending_batch) => {
+                    self.flush_pending_batch().await
+                },
                 frame = self.outgoing_session_frames.recv() => {
                     match frame {
                         Some(frame) => self.on_outgoing_session_frames(frame).await,
@@ -6,7 +6,13 @@ use bytes::Bytes;
 use fe2o3_amqp_types::{definitions, primitives::Binary, sasl::SaslCode};
 use tokio::{sync::mpsc, task::JoinError};
 
-use crate::transport::{self, error::NegotiationError};
+use crate::{
+    transport::{self, error::NegotiationError, protocol_header::LegacyAmqpVersion},
+    util::ReplayError,
+    watchdog::EnginePanic,
+};
+
+use super::InvalidContainerId;
 
 cfg_scram! {
     use crate::auth::error::ScramErrorKind;
@@ -35,10 +41,38 @@ pub enum OpenError {
     #[error(r#"Invalid scheme. Only "amqp" and "amqps" are supported."#)]
     InvalidScheme,
 
+    /// The configured max-frame-size is smaller than
+    /// [`MIN_MAX_FRAME_SIZE`](fe2o3_amqp_types::definitions::MIN_MAX_FRAME_SIZE)
+    #[error("max-frame-size {0} is smaller than the minimum of 512 bytes")]
+    MaxFrameSizeTooSmall(u32),
+
+    /// The configured buffer size is zero
+    #[error("Buffer size must be greater than zero")]
+    ZeroBufferSize,
+
+    /// The container id is rejected by the [`ContainerIdPolicy`](super::ContainerIdPolicy)
+    #[error(transparent)]
+    InvalidContainerId(#[from] InvalidContainerId),
+
     /// Protocol negotiation failed due to protocol header mismatch
     #[error("Protocol header mismatch. Found {0:?}")]
     ProtocolHeaderMismatch(Bytes),
 
+    /// The remote peer responded with the protocol header of an AMQP version prior to 1.0.
+    ///
+    /// This usually means that the peer doesn't support AMQP 1.0 (eg. RabbitMQ without the AMQP
+    /// 1.0 plugin enabled)
+    #[error(
+        "The remote peer only supports AMQP {version} (protocol header {header:?}), but only AMQP 1.0 is supported. \
+        If the peer is RabbitMQ, please make sure that the AMQP 1.0 plugin is enabled"
+    )]
+    LegacyAmqpProtocol {
+        /// The AMQP version of the remote peer
+        version: LegacyAmqpVersion,
+        /// The raw protocol header sent by the remote peer
+        header: Bytes,
+    },
+
     /// SASL negotiation failed
     #[error("SASL error code {:?}, additional data: {:?}", .code, .additional_data)]
     SaslError {
@@ -48,6 +82,10 @@ pub enum OpenError {
         additional_data: Option<Binary>,
     },
 
+    /// Error returned by a custom SASL mechanism
+    #[error("SASL mechanism error: {0}")]
+    SaslMechanism(Box<dyn std::error::Error + Send + Sync>),
+
     /// Error with SCRAM
     #[cfg_attr(docsrs, doc(cfg(feature = "scram")))]
     #[cfg(feature = "scram")]
@@ -77,6 +115,43 @@ pub enum OpenError {
     /// Remote peer closed connection with error during openning process
     #[error("Remote peer closed connection with error {}", .0)]
     RemoteClosedWithError(definitions::Error),
+
+    /// The connection is refused because another connection with the same container id already
+    /// exists and the `sole-connection-for-container` capability is desired
+    #[error("Container id is already in use {}", .0)]
+    ContainerIdInUse(definitions::Error),
+
+    /// The connection is refused because the maximum number of connections of the listener is
+    /// reached
+    #[cfg_attr(docsrs, doc(cfg(feature = "acceptor")))]
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    #[error("Connection limit is reached {}", .0)]
+    ConnectionLimitReached(definitions::Error),
+
+    /// The connection is refused because the listener is draining
+    #[cfg_attr(docsrs, doc(cfg(feature = "acceptor")))]
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    #[error("Listener is draining {}", .0)]
+    ListenerDraining(definitions::Error),
+
+    /// No virtual host is found for the hostname requested by the remote peer
+    #[cfg_attr(docsrs, doc(cfg(feature = "acceptor")))]
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    #[error("Virtual host {:?} is not found", .0)]
+    VirtualHostNotFound(Option<String>),
+
+    /// The WebSocket connection could not be established
+    #[cfg_attr(docsrs, doc(cfg(feature = "websocket")))]
+    #[cfg(all(feature = "websocket", not(target_arch = "wasm32")))]
+    #[error(transparent)]
+    WebSocket(Box<fe2o3_amqp_ws::Error>),
+}
+
+#[cfg(all(feature = "websocket", not(target_arch = "wasm32")))]
+impl From<fe2o3_amqp_ws::Error> for OpenError {
+    fn from(err: fe2o3_amqp_ws::Error) -> Self {
+        Self::WebSocket(Box::new(err))
+    }
 }
 
 impl From<NegotiationError> for OpenError {
@@ -84,6 +159,9 @@ impl From<NegotiationError> for OpenError {
         match err {
             NegotiationError::Io(err) => Self::Io(err),
             NegotiationError::ProtocolHeaderMismatch(buf) => Self::ProtocolHeaderMismatch(buf),
+            NegotiationError::LegacyAmqpProtocol { version, header } => {
+                Self::LegacyAmqpProtocol { version, header }
+            }
             NegotiationError::InvalidDomain => Self::InvalidDomain,
             NegotiationError::SaslError {
                 code,
@@ -92,6 +170,7 @@ impl From<NegotiationError> for OpenError {
                 code,
                 additional_data,
             },
+            NegotiationError::SaslMechanism(error) => Self::SaslMechanism(error),
             NegotiationError::DecodeError(val) => Self::DecodeError(val),
             NegotiationError::NotImplemented(description) => Self::NotImplemented(description),
             NegotiationError::IllegalState => Self::IllegalState,
@@ -102,6 +181,19 @@ impl From<NegotiationError> for OpenError {
     }
 }
 
+impl OpenError {
+    /// Get the raw protocol header sent by the remote peer if the protocol header negotiation
+    /// failed
+    pub fn peer_protocol_header(&self) -> Option<&Bytes> {
+        match self {
+            Self::ProtocolHeaderMismatch(header) | Self::LegacyAmqpProtocol { header, .. } => {
+                Some(header)
+            }
+            _ => None,
+        }
+    }
+}
+
 impl From<Infallible> for OpenError {
     fn from(_: Infallible) -> Self {
         unreachable!()
@@ -223,6 +315,32 @@ pub enum Error {
     /// This could occur only when the user attempts to close the connection
     #[error(transparent)]
     JoinError(#[from] JoinError),
+
+    /// The event loop of the connection panicked
+    #[error(transparent)]
+    EnginePanicked(#[from] EnginePanic),
+}
+
+impl ReplayError for Error {
+    fn replay(&self) -> Self {
+        match self {
+            Self::TransportError(error) => Self::TransportError(error.replay()),
+            Self::IllegalState => Self::IllegalState,
+            Self::NotImplemented(val) => Self::NotImplemented(val.clone()),
+            Self::NotFound(val) => Self::NotFound(val.clone()),
+            Self::NotAllowed(val) => Self::NotAllowed(val.clone()),
+            Self::RemoteClosed => Self::RemoteClosed,
+            Self::RemoteClosedWithError(error) => Self::RemoteClosedWithError(error.clone()),
+            // The event loop is not joined, so this is not expected to be the outcome
+            Self::JoinError(_) => Self::IllegalState,
+            Self::EnginePanicked(panic) => Self::EnginePanicked(panic.clone()),
+        }
+    }
+
+    fn stopped() -> Self {
+        // The engine somehow has already stopped running
+        Self::IllegalState
+    }
 }
 
 impl From<ConnectionInnerError> for Error {
@@ -275,3 +393,15 @@ pub enum TryCloseError {
     #[error("The connection has not received a close frame from the remote peer")]
     RemoteCloseNotReceived,
 }
+
+/// Error associated with pinging the remote peer
+#[derive(Debug, thiserror::Error)]
+pub enum PingError {
+    /// The connection is not opened or the event loop has stopped
+    #[error("Illegal local state")]
+    IllegalState,
+
+    /// No inbound traffic is observed before the timeout
+    #[error("Ping timed out")]
+    Timeout,
+}
@@ -0,0 +1,183 @@
+//! Failover between the alternative hosts of a connection
+
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use tokio::time::Instant;
+use url::Url;
+
+use crate::transport::protocol_header::default_port;
+
+use super::OpenError;
+
+/// Default time for which a host that failed is only attempted after the other hosts
+pub const DEFAULT_HOST_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Memory of the hosts that recently failed to open a connection
+///
+/// When a connection is opened with [`alt_hosts`](super::Builder::alt_hosts), the hosts that
+/// failed within the cooldown are attempted after the other hosts, so that a dead primary is not
+/// attempted first on every reconnect. A host is forgotten once a connection to it is opened.
+///
+/// The memory is shared by the clones, so the same instance should be given to the builders of
+/// the connections that replace each other, eg. when a lost connection is re-opened.
+#[derive(Debug, Clone)]
+pub struct HostHealth {
+    cooldown: Duration,
+    failures: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl Default for HostHealth {
+    fn default() -> Self {
+        Self::new(DEFAULT_HOST_COOLDOWN)
+    }
+}
+
+impl HostHealth {
+    /// Creates a memory that keeps a failed host for `cooldown`
+    pub fn new(cooldown: Duration) -> Self {
+        Self {
+            cooldown,
+            failures: Default::default(),
+        }
+    }
+
+    /// How long a failed host is attempted after the other hosts
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown
+    }
+
+    /// Forgets all failures
+    pub fn clear(&self) {
+        self.failures.lock().clear()
+    }
+
+    /// Orders the hosts for the next attempts. The hosts that did not fail within the cooldown
+    /// keep their order and come first, followed by the others from the least recent failure
+    pub(crate) fn order(&self, hosts: Vec<Url>, now: Instant) -> Vec<Url> {
+        let failures = self.failures.lock();
+        let (mut cooling_down, mut healthy): (Vec<_>, Vec<_>) =
+            hosts.into_iter().partition(|url| {
+                failures.get(&host_key(url)).is_some_and(|failed_at| {
+                    now.saturating_duration_since(*failed_at) < self.cooldown
+                })
+            });
+        cooling_down.sort_by_key(|url| failures.get(&host_key(url)).copied());
+        healthy.append(&mut cooling_down);
+        healthy
+    }
+
+    pub(crate) fn record_failure(&self, url: &Url, now: Instant) {
+        self.failures.lock().insert(host_key(url), now);
+    }
+
+    pub(crate) fn record_success(&self, url: &Url) {
+        self.failures.lock().remove(&host_key(url));
+    }
+}
+
+/// Hosts are identified by the scheme, the host and the port. `Url::origin()` is opaque for the
+/// `amqp` schemes, so the key is built by hand
+fn host_key(url: &Url) -> String {
+    let port = url.port().or_else(|| default_port(url.scheme()));
+    format!(
+        "{}://{}:{}",
+        url.scheme(),
+        url.host_str().unwrap_or_default(),
+        port.unwrap_or_default()
+    )
+}
+
+/// Attempts to open a connection to each host in turn until one succeeds. The error of the last
+/// attempt is returned if all of them fail
+pub(crate) async fn open_with_failover<T, F, Fut>(
+    hosts: Vec<Url>,
+    health: &HostHealth,
+    now: impl Fn() -> Instant,
+    mut open: F,
+) -> Result<T, OpenError>
+where
+    F: FnMut(Url) -> Fut,
+    Fut: Future<Output = Result<T, OpenError>>,
+{
+    let mut last_error = None;
+    for url in health.order(hosts, now()) {
+        match open(url.clone()).await {
+            Ok(connection) => {
+                health.record_success(&url);
+                return Ok(connection);
+            }
+            Err(error) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(host = %url, ?error, "Failed to open connection, failing over");
+                #[cfg(feature = "log")]
+                log::warn!(
+                    "Failed to open connection to {}, failing over: {:?}",
+                    url,
+                    error
+                );
+                health.record_failure(&url, now());
+                last_error = Some(error);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        OpenError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "No host to connect to",
+        ))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+    use url::Url;
+
+    use super::HostHealth;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn failed_hosts_are_attempted_last_until_the_cooldown_elapses() {
+        let health = HostHealth::new(Duration::from_secs(10));
+        let hosts = vec![
+            url("amqp://primary:5672"),
+            url("amqp://secondary:5672"),
+            url("amqp://tertiary:5672"),
+        ];
+        let start = Instant::now();
+        health.record_failure(&hosts[0], start);
+        health.record_failure(&hosts[1], start + Duration::from_secs(1));
+
+        let order = health.order(hosts.clone(), start + Duration::from_secs(2));
+        assert_eq!(
+            order,
+            vec![hosts[2].clone(), hosts[0].clone(), hosts[1].clone()]
+        );
+
+        // The primary is attempted first again once its cooldown elapsed
+        let order = health.order(hosts.clone(), start + Duration::from_secs(10));
+        assert_eq!(
+            order,
+            vec![hosts[0].clone(), hosts[2].clone(), hosts[1].clone()]
+        );
+
+        health.record_success(&hosts[1]);
+        let order = health.order(hosts.clone(), start + Duration::from_secs(2));
+        assert_eq!(
+            order,
+            vec![hosts[1].clone(), hosts[2].clone(), hosts[0].clone()]
+        );
+
+        // The default port is filled in
+        assert_eq!(
+            super::host_key(&url("amqp://primary")),
+            super::host_key(&hosts[0])
+        );
+    }
+}
@@ -1,23 +1,45 @@
 //! Implements an asynchronous heartbeat
 
-use std::{io, pin::Pin, task::Poll, time::Duration};
+use std::{io, task::Poll, time::Duration};
 
 use futures_util::Stream;
 use pin_project_lite::pin_project;
 
 cfg_not_wasm32! {
-    use tokio_stream::wrappers::IntervalStream;
+    use futures_util::ready;
+    use tokio::time::Instant;
 
-    #[derive(Debug)]
+    use crate::clock::{ClockSleep, SharedClock};
+
+    /// Ticks immediately and then once per period, the same as `tokio::time::Interval` with
+    /// the default `MissedTickBehavior::Burst`
     struct InnerStream {
-        interval: IntervalStream,
+        clock: SharedClock,
+        sleep: ClockSleep,
+        next: Instant,
+        period: Duration,
+    }
+
+    impl std::fmt::Debug for InnerStream {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("InnerStream")
+                .field("clock", &self.clock)
+                .field("next", &self.next)
+                .field("period", &self.period)
+                .finish()
+        }
     }
 
     impl InnerStream {
-        fn new(period: Duration) -> Self {
-            let interval = tokio::time::interval(period);
-            let interval = IntervalStream::new(interval);
-            Self { interval }
+        fn new(period: Duration, clock: SharedClock) -> Self {
+            let next = clock.now();
+            let sleep = clock.sleep_until(next);
+            Self {
+                clock,
+                sleep,
+                next,
+                period,
+            }
         }
     }
 
@@ -28,17 +50,17 @@ cfg_not_wasm32! {
             mut self: std::pin::Pin<&mut Self>,
             cx: &mut std::task::Context<'_>,
         ) -> std::task::Poll<Option<Self::Item>> {
-            let interval = Pin::new(&mut self.interval);
-            match interval.poll_next(cx) {
-                Poll::Ready(Some(_instant)) => Poll::Ready(Some(Ok(()))),
-                Poll::Ready(None) => Poll::Ready(None),
-                Poll::Pending => Poll::Pending,
-            }
+            ready!(self.sleep.as_mut().poll(cx));
+            let next = self.next + self.period;
+            self.next = next;
+            self.sleep = self.clock.sleep_until(next);
+            Poll::Ready(Some(Ok(())))
         }
     }
 }
 
 cfg_wasm32! {
+    use std::pin::Pin;
     use fluvio_wasm_timer::{Delay};
     use futures_util::{Future, ready};
 
@@ -71,8 +93,8 @@ cfg_wasm32! {
 }
 
 pin_project! {
-    /// A wrapper over an `Option<IntervalStream>` which will never tick ready if the underlying
-    /// `Interval` is `None`
+    /// A wrapper over an optional interval stream which will never tick ready if the underlying
+    /// interval is `None`
     #[derive(Debug)]
     pub struct HeartBeat {
         #[pin]
@@ -87,10 +109,24 @@ impl HeartBeat {
     }
 
     /// A [`HeartBeat`] that will yield `Poll::Ready(_)` per the given interval with `StreamExt::next()`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new(period: Duration) -> Self {
+        Self::with_clock(period, crate::clock::default_clock())
+    }
+
+    /// A [`HeartBeat`] that will yield `Poll::Ready(_)` per the given interval with `StreamExt::next()`
+    #[cfg(target_arch = "wasm32")]
     pub fn new(period: Duration) -> Self {
         let interval = Some(InnerStream::new(period));
         Self { interval }
     }
+
+    /// A [`HeartBeat`] that is driven by the given [`Clock`](crate::clock::Clock)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_clock(period: Duration, clock: SharedClock) -> Self {
+        let interval = Some(InnerStream::new(period, clock));
+        Self { interval }
+    }
 }
 
 impl Stream for HeartBeat {
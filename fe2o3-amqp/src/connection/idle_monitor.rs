@@ -0,0 +1,128 @@
+//! Monitoring of the idle timeout of a connection
+//!
+//! The transport closes the connection once no frame, including the empty frames sent as
+//! heartbeats, has been received from the remote peer for the local idle timeout. A
+//! [`ConnectionHandle`](super::ConnectionHandle) reports the idle timeouts advertised by both
+//! peers and warns before the local one elapses, eg. to feed a liveness probe:
+//!
+//! ```rust,ignore
+//! connection.set_idle_timeout_warning_ratio(0.5);
+//! let warning = connection.on_idle_timeout_warning().await;
+//! println!("No traffic for {:?} out of {:?}", warning.idle, warning.idle_timeout);
+//! ```
+
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::time::Instant;
+
+use crate::clock::SharedClock;
+
+/// Default fraction of the local idle timeout after which
+/// [`ConnectionHandle::on_idle_timeout_warning()`](super::ConnectionHandle::on_idle_timeout_warning)
+/// completes
+pub const DEFAULT_IDLE_TIMEOUT_WARNING_RATIO: f64 = 0.75;
+
+/// No traffic has been received from the remote peer for a fraction of the local idle timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdleTimeoutWarning {
+    /// Time since the last frame was received
+    pub idle: Duration,
+
+    /// The local idle timeout, after which the connection is closed
+    pub idle_timeout: Duration,
+}
+
+#[derive(Debug)]
+struct State {
+    remote_idle_timeout: Option<Duration>,
+    last_inbound: Instant,
+    warning_ratio: f64,
+    // The `last_inbound` that a warning has been returned for
+    warned_for: Option<Instant>,
+}
+
+/// Shared by the connection engine, which records the inbound traffic, and the handle
+#[derive(Debug)]
+pub(crate) struct IdleMonitor {
+    clock: SharedClock,
+    local_idle_timeout: Option<Duration>,
+    state: Mutex<State>,
+}
+
+impl IdleMonitor {
+    pub(crate) fn new(clock: SharedClock, local_idle_timeout: Option<Duration>) -> Self {
+        let last_inbound = clock.now();
+        Self {
+            clock,
+            local_idle_timeout,
+            state: Mutex::new(State {
+                remote_idle_timeout: None,
+                last_inbound,
+                warning_ratio: DEFAULT_IDLE_TIMEOUT_WARNING_RATIO,
+                warned_for: None,
+            }),
+        }
+    }
+
+    /// The clock of the connection, which is shared with its sessions and links
+    pub(crate) fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
+    pub(crate) fn local_idle_timeout(&self) -> Option<Duration> {
+        self.local_idle_timeout
+    }
+
+    pub(crate) fn remote_idle_timeout(&self) -> Option<Duration> {
+        self.state.lock().remote_idle_timeout
+    }
+
+    pub(crate) fn on_remote_open(&self, remote_idle_timeout: Option<u32>) {
+        self.state.lock().remote_idle_timeout = to_duration(remote_idle_timeout);
+    }
+
+    pub(crate) fn on_inbound_traffic(&self) {
+        self.state.lock().last_inbound = self.clock.now();
+    }
+
+    pub(crate) fn set_warning_ratio(&self, ratio: f64) {
+        self.state.lock().warning_ratio = ratio;
+    }
+
+    /// Waits until no traffic has been received for the warning ratio of the local idle timeout.
+    /// A silence is only warned about once
+    pub(crate) async fn wait_for_warning(&self) -> IdleTimeoutWarning {
+        let idle_timeout = match self.local_idle_timeout {
+            Some(idle_timeout) => idle_timeout,
+            None => return std::future::pending().await,
+        };
+        loop {
+            let wake_at = {
+                let mut state = self.state.lock();
+                let threshold = idle_timeout.mul_f64(state.warning_ratio);
+                let now = self.clock.now();
+                if state.warned_for == Some(state.last_inbound) {
+                    // Checks again once the next silence could reach the threshold
+                    now + threshold
+                } else if now >= state.last_inbound + threshold {
+                    state.warned_for = Some(state.last_inbound);
+                    return IdleTimeoutWarning {
+                        idle: now - state.last_inbound,
+                        idle_timeout,
+                    };
+                } else {
+                    state.last_inbound + threshold
+                }
+            };
+            self.clock.sleep_until(wake_at).await;
+        }
+    }
+}
+
+fn to_duration(idle_time_out: Option<u32>) -> Option<Duration> {
+    match idle_time_out {
+        Some(0) | None => None,
+        Some(millis) => Some(Duration::from_millis(millis as u64)),
+    }
+}
@@ -1,6 +1,6 @@
 //! Implements AMQP1.0 Connection
 
-use std::{cmp::min, collections::HashMap, sync::Arc};
+use std::{cmp::min, collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
 
 use fe2o3_amqp_types::{
     definitions::{self},
@@ -10,10 +10,7 @@ use fe2o3_amqp_types::{
 use futures_util::{Sink, SinkExt};
 use slab::Slab;
 use tokio::{
-    sync::{
-        mpsc::Sender,
-        oneshot::{self, error::TryRecvError},
-    },
+    sync::{mpsc::Sender, oneshot},
     task::JoinHandle,
 };
 
@@ -22,22 +19,59 @@ cfg_not_wasm32! {
     use url::Url;
 }
 
+cfg_acceptor! {
+    use fe2o3_amqp_types::definitions::AmqpError;
+    use tokio::sync::mpsc::error::TrySendError;
+
+    use crate::{
+        acceptor::{IncomingSession, ListenerSessionHandle, SessionAcceptor},
+        session::BeginError,
+    };
+}
+
 use crate::{
     control::ConnectionControl,
     endpoint::{self, IncomingChannel, OutgoingChannel},
     frames::amqp::{Frame, FrameBody},
+    sasl_profile::{SaslProfile, SharedCredentials},
     session::frame::{SessionFrame, SessionFrameBody, SessionIncomingItem},
     session::Session,
+    util::SharedOutcome,
+    watchdog::Watchdog,
     SendBound,
 };
 
+mod batching;
+pub use batching::*;
+
 mod builder;
 pub use builder::*;
 
+mod connect;
+pub use connect::*;
+
+mod container_id;
+pub use container_id::*;
+
+cfg_not_wasm32! {
+    mod failover;
+    pub use failover::*;
+
+    pub mod idle_monitor;
+    pub use idle_monitor::IdleTimeoutWarning;
+}
+
+mod qos;
+pub use qos::*;
+
+mod tls;
+pub use tls::*;
+
 pub(crate) mod engine;
 
 mod error;
 pub mod heartbeat;
+pub mod sole_connection;
 pub use error::*;
 
 /// Default max-frame-size.
@@ -51,6 +85,10 @@ pub const DEFAULT_MAX_FRAME_SIZE: u32 = 256 * 1024;
 /// This value is taken from `AmqpNetLite`
 pub const DEFAULT_CHANNEL_MAX: u16 = 255;
 
+/// The maximum time dropping a [`ConnectionHandle`] will block waiting for the connection to
+/// close if `close_on_drop` is enabled
+pub const DEFAULT_CLOSE_ON_DROP_TIMEOUT: Duration = Duration::from_millis(500);
+
 type SessionRelay = Arc<Sender<SessionIncomingItem>>;
 
 /// A handle to the [`Connection`] event loop.
@@ -62,11 +100,46 @@ pub struct ConnectionHandle<R> {
     pub(crate) is_closed: bool,
     pub(crate) control: Sender<ConnectionControl>,
     pub(crate) handle: JoinHandle<()>,
-    pub(crate) outcome: oneshot::Receiver<Result<(), Error>>,
+    pub(crate) outcome: SharedOutcome<Error>,
 
     // outgoing channel for session
-    pub(crate) outgoing: Sender<SessionFrame>,
+    pub(crate) outgoing: SessionFrameSenders,
     pub(crate) session_listener: R,
+
+    /// Whether to perform a best-effort blocking close on drop
+    pub(crate) close_on_drop: bool,
+
+    /// The remote address that the connection is established with when opened with an url
+    pub(crate) peer_addr: Option<SocketAddr>,
+
+    /// SASL credentials that the connection is opened with
+    pub(crate) credentials: SharedCredentials,
+
+    /// Watchdog of the connection engine, which is inherited by the sessions
+    pub(crate) watchdog: Option<Watchdog>,
+
+    /// Container id that is sent in the local Open
+    pub(crate) container_id: String,
+
+    /// Remotely initiated sessions on an outgoing connection in symmetric peer mode
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    pub(crate) incoming_sessions: Option<tokio::sync::mpsc::Receiver<IncomingSession>>,
+
+    /// Sessions accepted on this connection for administrative control
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    pub(crate) sessions: crate::acceptor::admin::SessionRegistry,
+
+    /// Resource limits of a connection that is accepted with limits
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    pub(crate) limits: Option<crate::acceptor::limits::ConnectionLimits>,
+
+    /// The identity that the remote peer authenticated with during the SASL negotiation
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    pub(crate) authenticated_identity: Option<String>,
+
+    /// Idle timeouts and inbound traffic, which are shared with the connection engine
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) idle_monitor: Arc<idle_monitor::IdleMonitor>,
 }
 
 impl<R> std::fmt::Debug for ConnectionHandle<R> {
@@ -77,11 +150,58 @@ impl<R> std::fmt::Debug for ConnectionHandle<R> {
 
 impl<R> Drop for ConnectionHandle<R> {
     fn drop(&mut self) {
-        let _ = self.control.try_send(ConnectionControl::Close(None));
+        let _result = self.control.try_send(ConnectionControl::Close(None));
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.close_on_drop && !self.is_closed {
+            self.close_on_drop(_result.is_ok(), DEFAULT_CLOSE_ON_DROP_TIMEOUT);
+        }
     }
 }
 
 impl<R> ConnectionHandle<R> {
+    /// The remote address that the connection is established with
+    ///
+    /// This is only available if the connection is opened with an url, in which case it is the
+    /// address that the [`ConnectStrategy`] eventually connected to. `None` is returned if the
+    /// connection is opened with a user-supplied stream.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// The SASL credentials that the connection is opened with
+    ///
+    /// These are the credentials given to [`Builder::credentials`], or otherwise the SASL profile
+    /// that the connection is opened with. Passing them to [`Builder::credentials`] opens a new
+    /// connection with the latest credentials, which is how a reconnect should be performed.
+    pub fn credentials(&self) -> &SharedCredentials {
+        &self.credentials
+    }
+
+    /// The container id that is sent to the remote peer in the local Open
+    ///
+    /// This is useful to find out the id that is generated with
+    /// [`Builder::generated_container_id`]
+    pub fn container_id(&self) -> &str {
+        &self.container_id
+    }
+
+    /// The watchdog that the engine of this connection and the engines of its sessions report
+    /// their panics to
+    pub fn watchdog(&self) -> Option<&Watchdog> {
+        self.watchdog.as_ref()
+    }
+
+    /// Replaces the SASL credentials for future connections without closing this connection
+    ///
+    /// The new profile is used by the next SASL negotiation performed with the shared
+    /// [`credentials`](#method.credentials), for example when the connection is re-opened after
+    /// it is lost. AMQP 1.0 cannot re-authenticate an open connection, so this connection keeps
+    /// running with the credentials it was opened with.
+    pub fn update_credentials(&self, profile: impl Into<SaslProfile>) {
+        self.credentials.update(profile)
+    }
+
     /// Checks if the underlying event loop has stopped
     pub fn is_closed(&self) -> bool {
         match self.is_closed {
@@ -104,27 +224,20 @@ impl<R> ConnectionHandle<R> {
         }
 
         let _ = self.control.try_send(ConnectionControl::Close(None));
-        match self.outcome.try_recv() {
-            Ok(res) => {
+        match self.outcome.try_get() {
+            Some(res) => {
                 self.is_closed = true;
                 Ok(res)
             }
-            Err(TryRecvError::Empty) => Err(TryCloseError::RemoteCloseNotReceived),
-            Err(TryRecvError::Closed) => {
-                self.is_closed = true;
-                // The engine somehow has already stopped running
-                Ok(Err(Error::IllegalState))
-            }
+            None => Err(TryCloseError::RemoteCloseNotReceived),
         }
     }
 
     cfg_not_wasm32! {
         /// Close the connection
         ///
-        /// An `Error::IllegalState` will be returned if this is called after executing any of
-        /// [`close`](#method.close), [`close_with_error`](#method.close_with_error) or
-        /// [`on_close`](#method.on_close). This will cause the JoinHandle to be polled after
-        /// completion, which causes a panic.
+        /// This can be called multiple times. If the connection is already closed, the outcome
+        /// of the first close is returned again.
         ///
         /// # wasm32 support
         ///
@@ -138,10 +251,8 @@ impl<R> ConnectionHandle<R> {
 
         /// Close the connection with an error
         ///
-        /// An `Error::IllegalState` will be returned if this is called after executing any of
-        /// [`close`](#method.close), [`close_with_error`](#method.close_with_error) or
-        /// [`on_close`](#method.on_close). This will cause the JoinHandle to be polled after
-        /// completion, which causes a panic.
+        /// This can be called multiple times. If the connection is already closed, the error is
+        /// not sent and the outcome of the first close is returned again.
         ///
         /// # wasm32 support
         ///
@@ -160,24 +271,128 @@ impl<R> ConnectionHandle<R> {
         }
     }
 
+    cfg_not_wasm32! {
+        /// Actively probes the liveness of the remote peer
+        ///
+        /// An empty frame is sent to the remote peer, and this resolves once any inbound traffic
+        /// (including the remote peer's heartbeat) is observed. A `PingError::Timeout` is returned
+        /// if no inbound traffic is observed before the `timeout` elapses.
+        ///
+        /// Any inbound frame resolves all the pings that are pending at the time, so a frame that
+        /// the remote peer sent before it received the empty frame also resolves the ping.
+        ///
+        /// # Example
+        ///
+        /// ```rust,ignore
+        /// connection.ping(Duration::from_secs(5)).await.unwrap();
+        /// ```
+        ///
+        /// # wasm32 support
+        ///
+        /// This method is not supported in wasm32 targets.
+        pub async fn ping(&self, timeout: Duration) -> Result<(), PingError> {
+            let (resp, resp_rx) = oneshot::channel();
+            let fut = async {
+                self.control
+                    .send(ConnectionControl::Ping(resp))
+                    .await
+                    .map_err(|_| PingError::IllegalState)?;
+                resp_rx.await.map_err(|_| PingError::IllegalState)
+            };
+            match tokio::time::timeout(timeout, fut).await {
+                Ok(result) => result,
+                Err(_) => Err(PingError::Timeout),
+            }
+        }
+
+        /// The local idle timeout, after which the connection is closed if nothing is received from
+        /// the remote peer. `None` if the idle timeout is disabled
+        ///
+        /// This is the threshold enforced by the transport, which may be twice the value
+        /// advertised in the local Open
+        pub fn local_idle_timeout(&self) -> Option<Duration> {
+            self.idle_monitor.local_idle_timeout()
+        }
+
+        /// The idle timeout advertised in the remote Open, which is the period of the empty
+        /// frames sent to keep the connection alive. `None` if the remote peer has not advertised
+        /// an idle timeout
+        pub fn remote_idle_timeout(&self) -> Option<Duration> {
+            self.idle_monitor.remote_idle_timeout()
+        }
+
+        /// Set the fraction of the local idle timeout after which
+        /// [`on_idle_timeout_warning`](#method.on_idle_timeout_warning) completes. The default is
+        /// [`DEFAULT_IDLE_TIMEOUT_WARNING_RATIO`](idle_monitor::DEFAULT_IDLE_TIMEOUT_WARNING_RATIO)
+        ///
+        /// # Panics
+        ///
+        /// Panics if `ratio` is not greater than zero and at most one
+        pub fn set_idle_timeout_warning_ratio(&self, ratio: f64) {
+            assert!(
+                ratio > 0.0 && ratio <= 1.0,
+                "ratio must be greater than zero and at most one"
+            );
+            self.idle_monitor.set_warning_ratio(ratio)
+        }
+
+        /// Waits until nothing, not even an empty frame, has been received from the remote peer
+        /// for the warning ratio of the local idle timeout
+        ///
+        /// Each silence is only warned about once, so calling this again waits for the next
+        /// silence after some traffic is received. This never completes if the local idle timeout
+        /// is disabled.
+        pub async fn on_idle_timeout_warning(&self) -> IdleTimeoutWarning {
+            self.idle_monitor.wait_for_warning().await
+        }
+    }
+
     /// Returns when the underlying event loop has stopped
     ///
-    /// An `Error::IllegalState` will be returned if this is called after executing any of
-    /// [`close`](#method.close), [`close_with_error`](#method.close_with_error) or
-    /// [`on_close`](#method.on_close). This will cause the JoinHandle to be polled after
-    /// completion, which causes a panic.
+    /// The outcome of the event loop is recorded, so this can be called multiple times (and
+    /// after [`close`](#method.close) or [`close_with_error`](#method.close_with_error)), and
+    /// the same outcome is returned every time.
     pub async fn on_close(&mut self) -> Result<(), Error> {
-        if self.is_closed {
-            return Err(Error::IllegalState);
-        }
-        match (&mut self.outcome).await {
-            Ok(res) => {
-                self.is_closed = true;
-                res
+        let res = self.outcome.wait().await;
+        self.is_closed = true;
+        res
+    }
+
+    /// Sends the Close if it is not `sent` yet and waits until the event loop has stopped or
+    /// the timeout has elapsed, after which the event loop is stopped
+    ///
+    /// On a multi-thread runtime, the current worker thread is handed over to
+    /// [`block_in_place`](tokio::task::block_in_place), so that the other tasks, including the
+    /// event loop, keep running on the other workers. The event loop cannot make progress if the
+    /// only worker thread of a current-thread runtime is blocked, so the close is left to a
+    /// detached task there. Nothing is done outside of a runtime.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn close_on_drop(&self, sent: bool, timeout: Duration) {
+        use tokio::runtime::{Handle, RuntimeFlavor};
+
+        let handle = match Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => return,
+        };
+
+        let control = self.control.clone();
+        let outcome = self.outcome.clone();
+        let event_loop = self.handle.abort_handle();
+        let close = async move {
+            let closed = tokio::time::timeout(timeout, async {
+                if !sent {
+                    let _ = control.send(ConnectionControl::Close(None)).await;
+                }
+                outcome.wait().await
+            });
+            if closed.await.is_err() {
+                event_loop.abort();
             }
-            Err(_) => {
-                self.is_closed = true;
-                Err(Error::IllegalState)
+        };
+        match handle.runtime_flavor() {
+            RuntimeFlavor::MultiThread => tokio::task::block_in_place(|| handle.block_on(close)),
+            _ => {
+                handle.spawn(close);
             }
         }
     }
@@ -196,6 +411,57 @@ impl<R> ConnectionHandle<R> {
     }
 }
 
+cfg_acceptor! {
+    impl ConnectionHandle<()> {
+        /// Waits for the next remotely initiated session
+        ///
+        /// This always returns `None` unless the connection is opened in symmetric peer mode
+        /// (see [`Builder::accept_incoming_sessions`]).
+        pub async fn next_incoming_session(&mut self) -> Option<IncomingSession> {
+            match &mut self.incoming_sessions {
+                Some(incoming_sessions) => incoming_sessions.recv().await,
+                None => None,
+            }
+        }
+
+        /// Waits for the next remotely initiated session and accepts it with the
+        /// [`SessionAcceptor`]
+        ///
+        /// The connection must be opened in symmetric peer mode (see
+        /// [`Builder::accept_incoming_sessions`]). Remotely initiated links can then be accepted
+        /// on the returned session.
+        ///
+        /// # Example
+        ///
+        /// ```rust,ignore
+        /// let mut connection = Connection::builder()
+        ///     .container_id("edge-device")
+        ///     .accept_incoming_sessions(true)
+        ///     .open("amqp://localhost:5672")
+        ///     .await
+        ///     .unwrap();
+        ///
+        /// let mut session = connection
+        ///     .accept_session(&SessionAcceptor::new())
+        ///     .await
+        ///     .unwrap();
+        /// let link = session.accept_link(&LinkAcceptor::new()).await.unwrap();
+        /// ```
+        pub async fn accept_session(
+            &mut self,
+            acceptor: &SessionAcceptor,
+        ) -> Result<ListenerSessionHandle, BeginError> {
+            let incoming_session = self
+                .next_incoming_session()
+                .await
+                .ok_or(BeginError::IllegalConnectionState)?;
+            acceptor
+                .accept_incoming_session(incoming_session, self)
+                .await
+        }
+    }
+}
+
 pub(crate) async fn deallocate_session(
     control: &mut Sender<ConnectionControl>,
     channel: OutgoingChannel,
@@ -409,6 +675,14 @@ pub struct Connection {
 
     // mutually agreed channel max
     pub(crate) agreed_channel_max: u16,
+
+    // forwards remotely initiated sessions in symmetric peer mode
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    pub(crate) session_listener: Option<Sender<IncomingSession>>,
+
+    // remotely initiated sessions that are refused, until the remote End is received
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    pub(crate) refused_sessions: HashMap<IncomingChannel, OutgoingChannel>,
 }
 
 /* ------------------------------- Public API ------------------------------- */
@@ -522,6 +796,61 @@ impl Connection {
 
             remote_open: None,
             agreed_channel_max,
+            #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+            session_listener: None,
+            #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+            refused_sessions: HashMap::new(),
+        }
+    }
+}
+
+cfg_acceptor! {
+    impl Connection {
+        /// Refuses a remotely initiated session with a Begin that is immediately followed by an
+        /// End with `resource-limit-exceeded`
+        async fn refuse_incoming_session<W>(
+            &mut self,
+            channel: IncomingChannel,
+            writer: &mut W,
+        ) -> Result<(), ConnectionInnerError>
+        where
+            W: Sink<Frame> + SendBound + Unpin,
+            ConnectionInnerError: From<W::Error>,
+        {
+            // The outgoing channel is only reserved until the remote End. Nothing is forwarded to
+            // a refused session, as the engine discards the frames it receives on the channel
+            let (tx, _) = tokio::sync::mpsc::channel(1);
+            let outgoing_channel = endpoint::Connection::allocate_session(self, tx)
+                .map_err(|_| ConnectionInnerError::IllegalState)?;
+            self.refused_sessions.insert(channel, outgoing_channel);
+
+            let begin = Begin {
+                remote_channel: Some(channel.0),
+                next_outgoing_id: 0,
+                incoming_window: 0,
+                outgoing_window: 0,
+                handle_max: Default::default(),
+                offered_capabilities: None,
+                desired_capabilities: None,
+                properties: None,
+            };
+            let error = definitions::Error::new(
+                AmqpError::ResourceLimitExceeded,
+                Some(String::from(
+                    "Too many remotely initiated sessions are waiting to be accepted",
+                )),
+                None,
+            );
+            writer
+                .send(Frame::new(outgoing_channel, FrameBody::Begin(begin)))
+                .await?;
+            writer
+                .send(Frame::new(
+                    outgoing_channel,
+                    FrameBody::End(End { error: Some(error) }),
+                ))
+                .await?;
+            Ok(())
         }
     }
 }
@@ -607,11 +936,16 @@ impl endpoint::Connection for Connection {
     }
 
     /// Reacting to remote Begin frame
-    async fn on_incoming_begin(
+    async fn on_incoming_begin<W>(
         &mut self,
         channel: IncomingChannel,
         begin: Begin,
-    ) -> Result<(), Self::Error> {
+        writer: &mut W,
+    ) -> Result<(), Self::Error>
+    where
+        W: Sink<Frame> + SendBound + Unpin,
+        Self::Error: From<W::Error>,
+    {
         match self.on_incoming_begin_inner(channel, &begin)? {
             Some(relay) => {
                 // forward begin to session
@@ -624,6 +958,24 @@ impl endpoint::Connection for Connection {
                 // If a session is locally initiated, the remote-channel MUST NOT be set. When an endpoint responds
                 // to a remotely initiated session, the remote-channel MUST be set to the channel on which the
                 // remote session sent the begin.
+                #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+                if let Some(session_listener) = &self.session_listener {
+                    let incoming_session = IncomingSession {
+                        channel: channel.0,
+                        begin,
+                    };
+                    // The event loop must not wait for the incoming sessions to be accepted, and a
+                    // session that can no longer be accepted is refused as well
+                    return match session_listener.try_send(incoming_session) {
+                        Ok(()) => Ok(()),
+                        Err(TrySendError::Full(_)) | Err(TrySendError::Closed(_)) => {
+                            self.refuse_incoming_session(channel, writer).await
+                        }
+                    };
+                }
+                #[cfg(not(all(feature = "acceptor", not(target_arch = "wasm32"))))]
+                let _ = writer;
+
                 Err(ConnectionInnerError::NotImplemented(Some(
                     "Remotely initiazted session is not supported yet".to_string(),
                 )))
@@ -642,6 +994,13 @@ impl endpoint::Connection for Connection {
             _ => return Err(ConnectionInnerError::IllegalState),
         }
 
+        // The End of a refused session only releases its outgoing channel
+        #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+        if let Some(outgoing_channel) = self.refused_sessions.remove(&channel) {
+            endpoint::Connection::deallocate_session(self, outgoing_channel);
+            return Ok(());
+        }
+
         // Forward to session
         let sframe = SessionFrame::new(channel, SessionFrameBody::End(end));
         // Drop incoming channel
@@ -697,7 +1056,7 @@ impl endpoint::Connection for Connection {
         tracing::trace!(?frame);
         #[cfg(feature = "log")]
         log::trace!("SEND frame = {:?}", frame);
-        writer.send(frame).await.map_err(Into::into)?;
+        writer.send(frame).await?;
 
         // change local state after successfully sending the frame
         match &self.local_state {
@@ -722,7 +1081,7 @@ impl endpoint::Connection for Connection {
     {
         let error_is_some = error.is_some();
         let frame = Frame::new(0u16, FrameBody::Close(Close { error }));
-        writer.send(frame).await.map_err(Into::into)?;
+        writer.send(frame).await?;
 
         match &self.local_state {
             ConnectionState::Opened => match error_is_some {
@@ -749,6 +1108,22 @@ impl endpoint::Connection for Connection {
         begin: Begin,
     ) -> Result<Frame, Self::Error> {
         // TODO: check states?
+
+        // Responding to a remotely initiated session
+        if let Some(remote_channel) = begin.remote_channel {
+            let relay = self
+                .session_by_outgoing_channel
+                .get(outgoing_channel.0 as usize)
+                .ok_or_else(|| {
+                    ConnectionInnerError::NotFound(Some(String::from(
+                        "Outgoing channel is not found",
+                    )))
+                })?;
+
+            self.session_by_incoming_channel
+                .insert(IncomingChannel(remote_channel), relay.clone());
+        }
+
         let frame = Frame::new(outgoing_channel, FrameBody::Begin(begin));
         Ok(frame)
     }
@@ -780,6 +1155,16 @@ impl endpoint::Connection for Connection {
             .get(outgoing_channel.0 as usize)
             .map(AsRef::as_ref)
     }
+
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    fn is_refused_session(&self, incoming_channel: IncomingChannel) -> bool {
+        self.refused_sessions.contains_key(&incoming_channel)
+    }
+
+    #[cfg(not(all(feature = "acceptor", not(target_arch = "wasm32"))))]
+    fn is_refused_session(&self, _incoming_channel: IncomingChannel) -> bool {
+        false
+    }
 }
 
 impl Connection {
@@ -0,0 +1,237 @@
+//! Quality-of-service classes of the sessions
+
+use tokio::sync::mpsc;
+
+use crate::session::frame::SessionFrame;
+
+/// Default weight of the [`QosClass::Interactive`] sessions
+pub const DEFAULT_INTERACTIVE_WEIGHT: u32 = 4;
+
+/// Default weight of the [`QosClass::Bulk`] sessions
+pub const DEFAULT_BULK_WEIGHT: u32 = 1;
+
+/// Quality-of-service class of a session
+///
+/// The connection engine keeps a queue of outgoing frames for each class. The frames of a session
+/// are always sent in order, and the queues are served with weighted fairness (see
+/// [`QosWeights`]) when both of them have frames waiting, so that a session that saturates the
+/// connection with bulk transfers does not hold back the frames of the interactive sessions
+/// (eg. management or CBS requests).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum QosClass {
+    /// Latency sensitive traffic. This is the default class
+    #[default]
+    Interactive,
+
+    /// Throughput oriented traffic
+    Bulk,
+}
+
+/// Weights of the [`QosClass`]es
+///
+/// When frames of both classes are waiting, the engine sends `interactive` frames of the
+/// interactive sessions for every `bulk` frames of the bulk sessions. A class with a weight of
+/// zero is only served when the other class has no frame waiting.
+///
+/// # Default
+///
+/// | Field | Default Value |
+/// |-------|---------------|
+/// |`interactive`| [`DEFAULT_INTERACTIVE_WEIGHT`] |
+/// |`bulk`| [`DEFAULT_BULK_WEIGHT`] |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QosWeights {
+    /// Weight of the interactive sessions
+    pub interactive: u32,
+
+    /// Weight of the bulk sessions
+    pub bulk: u32,
+}
+
+impl Default for QosWeights {
+    fn default() -> Self {
+        Self::new(DEFAULT_INTERACTIVE_WEIGHT, DEFAULT_BULK_WEIGHT)
+    }
+}
+
+impl QosWeights {
+    /// Creates the weights of the interactive and the bulk sessions
+    pub fn new(interactive: u32, bulk: u32) -> Self {
+        Self { interactive, bulk }
+    }
+}
+
+/// Smooth weighted round-robin between the classes that have frames waiting
+#[derive(Debug)]
+struct WeightedScheduler {
+    weights: QosWeights,
+    interactive_credit: i64,
+    bulk_credit: i64,
+}
+
+impl WeightedScheduler {
+    fn new(weights: QosWeights) -> Self {
+        Self {
+            weights,
+            interactive_credit: 0,
+            bulk_credit: 0,
+        }
+    }
+
+    fn next(&mut self, interactive_ready: bool, bulk_ready: bool) -> Option<QosClass> {
+        match (interactive_ready, bulk_ready) {
+            (false, false) => None,
+            (true, false) => Some(QosClass::Interactive),
+            (false, true) => Some(QosClass::Bulk),
+            (true, true) => {
+                let interactive = i64::from(self.weights.interactive);
+                let bulk = i64::from(self.weights.bulk);
+                self.interactive_credit += interactive;
+                self.bulk_credit += bulk;
+                if self.interactive_credit >= self.bulk_credit {
+                    self.interactive_credit -= interactive + bulk;
+                    Some(QosClass::Interactive)
+                } else {
+                    self.bulk_credit -= interactive + bulk;
+                    Some(QosClass::Bulk)
+                }
+            }
+        }
+    }
+}
+
+/// Senders of the outgoing session frames of each class
+#[derive(Debug, Clone)]
+pub(crate) struct SessionFrameSenders {
+    interactive: mpsc::Sender<SessionFrame>,
+    bulk: mpsc::Sender<SessionFrame>,
+}
+
+impl SessionFrameSenders {
+    pub(crate) fn get(&self, class: QosClass) -> &mpsc::Sender<SessionFrame> {
+        match class {
+            QosClass::Interactive => &self.interactive,
+            QosClass::Bulk => &self.bulk,
+        }
+    }
+}
+
+/// Receives the outgoing session frames of both classes in the order of the [`QosWeights`]
+#[derive(Debug)]
+pub(crate) struct OutgoingSessionFrames {
+    interactive: mpsc::Receiver<SessionFrame>,
+    bulk: mpsc::Receiver<SessionFrame>,
+    next_interactive: Option<SessionFrame>,
+    next_bulk: Option<SessionFrame>,
+    scheduler: WeightedScheduler,
+}
+
+/// Creates the channels of the outgoing session frames with a buffer of `buffer_size` frames for
+/// each class
+pub(crate) fn session_frame_channel(
+    buffer_size: usize,
+    weights: QosWeights,
+) -> (SessionFrameSenders, OutgoingSessionFrames) {
+    let (interactive_tx, interactive_rx) = mpsc::channel(buffer_size);
+    let (bulk_tx, bulk_rx) = mpsc::channel(buffer_size);
+    let senders = SessionFrameSenders {
+        interactive: interactive_tx,
+        bulk: bulk_tx,
+    };
+    let receivers = OutgoingSessionFrames {
+        interactive: interactive_rx,
+        bulk: bulk_rx,
+        next_interactive: None,
+        next_bulk: None,
+        scheduler: WeightedScheduler::new(weights),
+    };
+    (senders, receivers)
+}
+
+impl OutgoingSessionFrames {
+    /// Closes the channels. The frames that are already queued can still be received
+    pub(crate) fn close(&mut self) {
+        self.interactive.close();
+        self.bulk.close();
+    }
+
+    /// Receives the next frame that is already queued
+    pub(crate) fn try_recv(&mut self) -> Option<SessionFrame> {
+        if self.next_interactive.is_none() {
+            self.next_interactive = self.interactive.try_recv().ok();
+        }
+        if self.next_bulk.is_none() {
+            self.next_bulk = self.bulk.try_recv().ok();
+        }
+        match self
+            .scheduler
+            .next(self.next_interactive.is_some(), self.next_bulk.is_some())?
+        {
+            QosClass::Interactive => self.next_interactive.take(),
+            QosClass::Bulk => self.next_bulk.take(),
+        }
+    }
+
+    /// Receives the next frame. `None` is returned once both channels are closed and empty.
+    ///
+    /// This is cancel safe
+    pub(crate) async fn recv(&mut self) -> Option<SessionFrame> {
+        if let Some(frame) = self.try_recv() {
+            return Some(frame);
+        }
+        tokio::select! {
+            Some(frame) = self.interactive.recv() => Some(frame),
+            Some(frame) = self.bulk.recv() => Some(frame),
+            else => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::performatives::End;
+
+    use crate::session::frame::{SessionFrame, SessionFrameBody};
+
+    use super::{session_frame_channel, QosClass, QosWeights, WeightedScheduler};
+
+    #[test]
+    fn classes_are_served_by_weight_when_both_are_ready() {
+        let mut scheduler = WeightedScheduler::new(QosWeights::new(3, 1));
+        let order: Vec<_> = (0..8)
+            .map(|_| scheduler.next(true, true).unwrap())
+            .collect();
+        let interactive = order
+            .iter()
+            .filter(|class| **class == QosClass::Interactive)
+            .count();
+        assert_eq!(interactive, 6);
+        assert_eq!(scheduler.next(false, true), Some(QosClass::Bulk));
+        assert_eq!(scheduler.next(false, false), None);
+
+        let mut scheduler = WeightedScheduler::new(QosWeights::new(1, 0));
+        assert!((0..4).all(|_| scheduler.next(true, true) == Some(QosClass::Interactive)));
+    }
+
+    #[tokio::test]
+    async fn frames_of_each_class_are_kept_in_order() {
+        let (senders, mut receivers) = session_frame_channel(8, QosWeights::new(1, 1));
+        for channel in [0u16, 1] {
+            let frame = SessionFrame::new(channel, SessionFrameBody::End(End { error: None }));
+            senders.get(QosClass::Bulk).send(frame).await.unwrap();
+        }
+        let frame = SessionFrame::new(2u16, SessionFrameBody::End(End { error: None }));
+        senders
+            .get(QosClass::Interactive)
+            .send(frame)
+            .await
+            .unwrap();
+        drop(senders);
+
+        let mut channels = Vec::new();
+        while let Some(frame) = receivers.recv().await {
+            channels.push(frame.channel);
+        }
+        assert_eq!(channels, vec![2, 0, 1]);
+    }
+}
@@ -0,0 +1,141 @@
+//! Support for the `sole-connection-for-container` capability
+//!
+//! A client that desires the capability asks the remote peer to allow at most one connection for
+//! its container id (eg. to implement the uniqueness of a JMS client id). A peer that supports the
+//! capability offers it in its Open and advertises how an existing connection is handled with
+//! the `sole-connection-enforcement-policy` connection property:
+//!
+//! - [`SoleConnectionEnforcementPolicy::RefuseConnection`]: the new connection is refused. The
+//!   peer responds with an Open that carries the `amqp:connection-establishment-failed` property
+//!   and immediately closes the connection with an `amqp:invalid-field` error whose info points
+//!   at the `container-id`.
+//! - [`SoleConnectionEnforcementPolicy::CloseExisting`]: the existing connection is closed with
+//!   an `amqp:connection:forced` error whose info carries `sole-connection-enforcement`.
+
+use fe2o3_amqp_types::{
+    definitions::{self, AmqpError, ConnectionError, ErrorCondition},
+    performatives::{Capability, Open},
+    primitives::Value,
+};
+
+cfg_acceptor! {
+    use fe2o3_amqp_types::{definitions::Fields, primitives::Symbol};
+}
+
+/// Name of the connection property that holds the [`SoleConnectionEnforcementPolicy`]
+pub const SOLE_CONNECTION_ENFORCEMENT_POLICY: &str = "sole-connection-enforcement-policy";
+
+/// Key of the error info that marks an existing connection that is closed by the
+/// [`SoleConnectionEnforcementPolicy::CloseExisting`] policy
+pub const SOLE_CONNECTION_ENFORCEMENT: &str = "sole-connection-enforcement";
+
+/// Name of the connection property that signals that the Open is immediately followed by a
+/// Close
+pub const CONNECTION_ESTABLISHMENT_FAILED: &str = "amqp:connection-establishment-failed";
+
+/// Key of the error info that holds the name of an invalid field
+pub const INVALID_FIELD: &str = "invalid-field";
+
+/// Value of [`INVALID_FIELD`] when the container id is already in use
+pub const CONTAINER_ID: &str = "container-id";
+
+/// How a peer handles a new connection whose container id is already in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SoleConnectionEnforcementPolicy {
+    /// The new connection is refused
+    RefuseConnection,
+
+    /// The existing connection is closed
+    CloseExisting,
+}
+
+impl SoleConnectionEnforcementPolicy {
+    /// Find the policy advertised in the `properties` of an Open
+    pub fn from_open(open: &Open) -> Option<Self> {
+        let value = open
+            .properties
+            .as_ref()?
+            .get(SOLE_CONNECTION_ENFORCEMENT_POLICY)?;
+        match value {
+            Value::Ubyte(0) | Value::Uint(0) | Value::Int(0) => Some(Self::RefuseConnection),
+            Value::Ubyte(1) | Value::Uint(1) | Value::Int(1) => Some(Self::CloseExisting),
+            _ => None,
+        }
+    }
+}
+
+impl From<SoleConnectionEnforcementPolicy> for u32 {
+    fn from(value: SoleConnectionEnforcementPolicy) -> Self {
+        match value {
+            SoleConnectionEnforcementPolicy::RefuseConnection => 0,
+            SoleConnectionEnforcementPolicy::CloseExisting => 1,
+        }
+    }
+}
+
+/// Whether the error is sent by a peer that enforces the sole connection for a container id,
+/// either when refusing a new connection or when closing an existing connection
+pub fn is_container_id_in_use(error: &definitions::Error) -> bool {
+    match &error.condition {
+        ErrorCondition::AmqpError(AmqpError::InvalidField) => matches!(
+            error.info_value(INVALID_FIELD),
+            Some(Value::Symbol(field)) if field.as_str() == CONTAINER_ID
+        ),
+        ErrorCondition::ConnectionError(ConnectionError::ConnectionForced) => matches!(
+            error.info_value(SOLE_CONNECTION_ENFORCEMENT),
+            Some(Value::Bool(true))
+        ),
+        _ => false,
+    }
+}
+
+/// Whether the remote Open signals that the connection is going to be closed immediately
+pub(crate) fn is_establishment_failed(open: &Open) -> bool {
+    matches!(
+        open.properties
+            .as_ref()
+            .and_then(|properties| properties.get(CONNECTION_ESTABLISHMENT_FAILED)),
+        Some(Value::Bool(true))
+    )
+}
+
+/// Whether the Open desires the `sole-connection-for-container` capability
+pub(crate) fn is_desired(open: &Open) -> bool {
+    open.desires_capability(Capability::SoleConnectionForContainer)
+}
+
+cfg_acceptor! {
+    /// The error that refuses a new connection whose container id is already in use
+    pub(crate) fn refuse_connection_error() -> definitions::Error {
+        let mut info = Fields::new();
+        info.insert(
+            Symbol::from(INVALID_FIELD),
+            Value::Symbol(Symbol::from(CONTAINER_ID)),
+        );
+        definitions::Error::new(
+            AmqpError::InvalidField,
+            Some(String::from("The container id is already in use")),
+            info,
+        )
+    }
+
+    /// The error that closes an existing connection in favour of a new connection
+    pub(crate) fn close_existing_error() -> definitions::Error {
+        let mut info = Fields::new();
+        info.insert(Symbol::from(SOLE_CONNECTION_ENFORCEMENT), Value::Bool(true));
+        definitions::Error::new(
+            ConnectionError::ConnectionForced,
+            Some(String::from(
+                "The connection is replaced by a new connection with the same container id",
+            )),
+            info,
+        )
+    }
+
+    /// Marks an Open that is going to be immediately followed by a Close
+    pub(crate) fn set_establishment_failed(open: &mut Open) {
+        open.properties
+            .get_or_insert_with(Fields::new)
+            .insert(Symbol::from(CONNECTION_ESTABLISHMENT_FAILED), Value::Bool(true));
+    }
+}
@@ -0,0 +1,243 @@
+//! Verification of the server certificate with the default TLS connector
+
+#[cfg(feature = "dangerous-tls")]
+use std::sync::Arc;
+
+/// Callback that decides whether the server certificate is trusted
+///
+/// The arguments are the DER encoded end-entity certificate and the server name that is used
+/// for the TLS handshake
+#[cfg(feature = "dangerous-tls")]
+pub type ServerCertCallback = Arc<dyn Fn(&[u8], &str) -> bool + Send + Sync>;
+
+/// How the certificate of the server is verified by the default TLS connector
+///
+/// This only applies when no custom TLS connector is supplied to the builder. The modes other
+/// than [`ServerCertVerification::Default`] weaken or replace the verification against the
+/// trust anchors and are only available with the `"dangerous-tls"` feature. They are meant for
+/// lab and edge setups where the broker is reached by IP address or presents a self-signed
+/// certificate.
+#[derive(Clone, Default)]
+pub enum ServerCertVerification {
+    /// Verify the certificate chain against the default trust anchors (`webpki-roots` with
+    /// `"rustls"`, the system trust store with `"native-tls"`) and the server name
+    #[default]
+    Default,
+
+    /// Accept any certificate. This provides no protection against an active attacker
+    #[cfg_attr(docsrs, doc(cfg(feature = "dangerous-tls")))]
+    #[cfg(feature = "dangerous-tls")]
+    DangerAcceptInvalid,
+
+    /// Only accept a server whose end-entity certificate is one of the DER encoded
+    /// certificates. The chain and the server name are not verified
+    #[cfg_attr(docsrs, doc(cfg(feature = "dangerous-tls")))]
+    #[cfg(feature = "dangerous-tls")]
+    Pinned(Vec<Vec<u8>>),
+
+    /// Let the callback decide whether the end-entity certificate is trusted. The chain and the
+    /// server name are not verified
+    #[cfg_attr(docsrs, doc(cfg(feature = "dangerous-tls")))]
+    #[cfg(feature = "dangerous-tls")]
+    Custom(ServerCertCallback),
+}
+
+impl std::fmt::Debug for ServerCertVerification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Default => write!(f, "Default"),
+            #[cfg(feature = "dangerous-tls")]
+            Self::DangerAcceptInvalid => write!(f, "DangerAcceptInvalid"),
+            #[cfg(feature = "dangerous-tls")]
+            Self::Pinned(certs) => f.debug_tuple("Pinned").field(&certs.len()).finish(),
+            #[cfg(feature = "dangerous-tls")]
+            Self::Custom(_) => f.debug_tuple("Custom").field(&"Fn").finish(),
+        }
+    }
+}
+
+impl ServerCertVerification {
+    /// Whether the end-entity certificate is trusted by a mode that replaces the default
+    /// verification
+    #[cfg(all(
+        feature = "dangerous-tls",
+        any(
+            all(feature = "rustls", not(feature = "native-tls")),
+            all(
+                feature = "native-tls",
+                not(feature = "rustls"),
+                not(target_arch = "wasm32")
+            )
+        )
+    ))]
+    pub(crate) fn is_trusted(&self, end_entity: &[u8], server_name: &str) -> bool {
+        match self {
+            Self::Default => false,
+            Self::DangerAcceptInvalid => true,
+            Self::Pinned(certs) => certs.iter().any(|cert| cert == end_entity),
+            Self::Custom(callback) => callback(end_entity, server_name),
+        }
+    }
+}
+
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+pub(crate) fn rustls_client_config(
+    verification: &ServerCertVerification,
+) -> librustls::ClientConfig {
+    use librustls::{ClientConfig, RootCertStore};
+
+    match verification {
+        ServerCertVerification::Default => {
+            let mut root_cert_store = RootCertStore::empty();
+            root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+            ClientConfig::builder()
+                .with_root_certificates(root_cert_store)
+                .with_no_client_auth()
+        }
+        #[cfg(feature = "dangerous-tls")]
+        _ => ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(OverrideServerCertVerifier::new(
+                verification.clone(),
+            )))
+            .with_no_client_auth(),
+    }
+}
+
+#[cfg(all(
+    feature = "rustls",
+    not(feature = "native-tls"),
+    feature = "dangerous-tls"
+))]
+#[derive(Debug)]
+struct OverrideServerCertVerifier {
+    verification: ServerCertVerification,
+    algorithms: librustls::crypto::WebPkiSupportedAlgorithms,
+}
+
+#[cfg(all(
+    feature = "rustls",
+    not(feature = "native-tls"),
+    feature = "dangerous-tls"
+))]
+impl OverrideServerCertVerifier {
+    fn new(verification: ServerCertVerification) -> Self {
+        Self {
+            verification,
+            algorithms: librustls::crypto::ring::default_provider()
+                .signature_verification_algorithms,
+        }
+    }
+}
+
+#[cfg(all(
+    feature = "rustls",
+    not(feature = "native-tls"),
+    feature = "dangerous-tls"
+))]
+impl librustls::client::danger::ServerCertVerifier for OverrideServerCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &librustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[librustls::pki_types::CertificateDer<'_>],
+        server_name: &librustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: librustls::pki_types::UnixTime,
+    ) -> Result<librustls::client::danger::ServerCertVerified, librustls::Error> {
+        let server_name = match server_name {
+            librustls::pki_types::ServerName::DnsName(name) => name.as_ref().to_string(),
+            librustls::pki_types::ServerName::IpAddress(addr) => {
+                std::net::IpAddr::from(*addr).to_string()
+            }
+            _ => String::new(),
+        };
+        if self.verification.is_trusted(end_entity, &server_name) {
+            Ok(librustls::client::danger::ServerCertVerified::assertion())
+        } else {
+            Err(librustls::Error::InvalidCertificate(
+                librustls::CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    // The handshake signatures are still verified so that the server proves the possession of
+    // the private key of the accepted certificate
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &librustls::pki_types::CertificateDer<'_>,
+        dss: &librustls::DigitallySignedStruct,
+    ) -> Result<librustls::client::danger::HandshakeSignatureValid, librustls::Error> {
+        librustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &librustls::pki_types::CertificateDer<'_>,
+        dss: &librustls::DigitallySignedStruct,
+    ) -> Result<librustls::client::danger::HandshakeSignatureValid, librustls::Error> {
+        librustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<librustls::SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+#[cfg(all(
+    feature = "native-tls",
+    not(feature = "rustls"),
+    not(target_arch = "wasm32")
+))]
+pub(crate) fn native_tls_connector(
+    verification: &ServerCertVerification,
+) -> Result<libnative_tls::TlsConnector, libnative_tls::Error> {
+    match verification {
+        ServerCertVerification::Default => libnative_tls::TlsConnector::new(),
+        // native-tls cannot plug in a custom verifier, so the certificate is checked after the
+        // handshake with `verify_native_tls_peer`
+        #[cfg(feature = "dangerous-tls")]
+        _ => libnative_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true)
+            .build(),
+    }
+}
+
+#[cfg(all(
+    feature = "native-tls",
+    not(feature = "rustls"),
+    not(target_arch = "wasm32")
+))]
+#[cfg_attr(not(feature = "dangerous-tls"), allow(unused_variables))]
+pub(crate) fn verify_native_tls_peer<Io>(
+    tls_stream: &tokio_native_tls::TlsStream<Io>,
+    verification: &ServerCertVerification,
+    server_name: &str,
+) -> Result<(), std::io::Error>
+where
+    Io: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    match verification {
+        ServerCertVerification::Default => Ok(()),
+        #[cfg(feature = "dangerous-tls")]
+        _ => {
+            let der = tls_stream
+                .get_ref()
+                .peer_certificate()
+                .ok()
+                .flatten()
+                .and_then(|cert| cert.to_der().ok())
+                .unwrap_or_default();
+            if verification.is_trusted(&der, server_name) {
+                Ok(())
+            } else {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "The server certificate is not trusted",
+                ))
+            }
+        }
+    }
+}
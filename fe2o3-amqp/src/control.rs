@@ -10,7 +10,7 @@ use crate::{
     connection::AllocSessionError,
     endpoint::{InputHandle, OutgoingChannel, OutputHandle},
     link::LinkRelay,
-    session::{error::AllocLinkError, frame::SessionIncomingItem},
+    session::{error::AllocLinkError, frame::SessionIncomingItem, LinkInfo},
 };
 
 cfg_transaction! {
@@ -31,6 +31,7 @@ pub(crate) enum ConnectionControl {
     },
     DeallocateSession(OutgoingChannel),
     GetMaxFrameSize(oneshot::Sender<usize>),
+    Ping(oneshot::Sender<()>),
 }
 
 impl std::fmt::Display for ConnectionControl {
@@ -43,6 +44,7 @@ impl std::fmt::Display for ConnectionControl {
             } => write!(f, "AllocateSession"),
             Self::DeallocateSession(id) => write!(f, "DeallocateSession({})", id.0),
             Self::GetMaxFrameSize(_) => write!(f, "GetMaxFrameSize"),
+            Self::Ping(_) => write!(f, "Ping"),
         }
     }
 }
@@ -59,12 +61,24 @@ pub(crate) enum SessionControl {
         link_name: String,
         link_relay: LinkRelay<()>,
         input_handle: InputHandle,
+        /// Detach the attached link with the same name (if any) with `amqp:link:stolen` instead
+        /// of refusing the incoming link
+        steal_existing: bool,
         responder: oneshot::Sender<Result<OutputHandle, AllocLinkError>>,
     },
     DeallocateLink(OutputHandle),
+    /// The remote peer did not reply to the Detach in time, so the link is removed from the
+    /// session without waiting for the remote Detach
+    AbandonLink(InputHandle),
     Disposition(Disposition),
     CloseConnectionWithError((ConnectionError, Option<String>)),
     GetMaxFrameSize(oneshot::Sender<usize>),
+    GetLinks(oneshot::Sender<Vec<LinkInfo>>),
+    KickLink {
+        link_name: String,
+        error: definitions::Error,
+        responder: oneshot::Sender<bool>,
+    },
 
     // Transaction related controls
     #[cfg(feature = "transaction")]
@@ -98,12 +112,16 @@ impl std::fmt::Display for SessionControl {
                 link_name: _,
                 link_relay: _,
                 input_handle: _,
+                steal_existing: _,
                 responder: _,
             } => write!(f, "AllocateIncomingLink"),
             SessionControl::DeallocateLink(name) => write!(f, "DeallocateLink({:?})", name),
+            SessionControl::AbandonLink(handle) => write!(f, "AbandonLink({:?})", handle),
             SessionControl::Disposition(_) => write!(f, "Disposition"),
             SessionControl::CloseConnectionWithError(_) => write!(f, "CloseConnectionWithError"),
             SessionControl::GetMaxFrameSize(_) => write!(f, "GetMaxFrameSize"),
+            SessionControl::GetLinks(_) => write!(f, "GetLinks"),
+            SessionControl::KickLink { link_name, .. } => write!(f, "KickLink({})", link_name),
 
             #[cfg(feature = "transaction")]
             SessionControl::AllocateTransactionId { .. } => write!(f, "AllocateTransactionId"),
@@ -45,12 +45,17 @@ pub(crate) trait Connection {
 
     /// Reacting to remote Begin frame
     ///
-    /// Do NOT forward to session here. Forwarding is handled elsewhere.
-    fn on_incoming_begin(
+    /// Do NOT forward to session here. Forwarding is handled elsewhere. The writer is passed in
+    /// so that a remotely initiated session can be refused right away
+    fn on_incoming_begin<W>(
         &mut self,
         channel: IncomingChannel,
         begin: Begin,
-    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+        writer: &mut W,
+    ) -> impl Future<Output = Result<(), Self::Error>> + SendBound
+    where
+        W: Sink<Frame> + SendBound + Unpin,
+        Self::Error: From<W::Error>;
 
     /// Reacting to remote End frame
     fn on_incoming_end(
@@ -104,4 +109,8 @@ pub(crate) trait Connection {
         &mut self,
         outgoing_channel: OutgoingChannel,
     ) -> Option<&mpsc::Sender<SessionIncomingItem>>;
+
+    /// Whether the remotely initiated session on the channel was refused, in which case the
+    /// frames it sends until its End are discarded
+    fn is_refused_session(&self, incoming_channel: IncomingChannel) -> bool;
 }
@@ -5,7 +5,7 @@ use fe2o3_amqp_types::{
         DeliveryNumber, DeliveryTag, Error, Fields, MessageFormat, ReceiverSettleMode, Role,
         SequenceNo,
     },
-    messaging::{DeliveryState, FromBody},
+    messaging::DeliveryState,
     performatives::{Attach, Detach, Transfer},
 };
 use futures_util::Future;
@@ -15,14 +15,14 @@ use crate::{
     control::SessionControl,
     link::{
         delivery::{Delivery, DeliveryInfo},
+        message_format::{DecodeDelivery, DeliveryPayload, MessageFormatRegistry},
         state::LinkState,
         LinkFrame,
     },
-    util::{AsByteIterator, IntoReader},
     Payload,
 };
 
-use super::{OutputHandle, Settlement};
+use super::{InputHandle, OutputHandle, Settlement};
 
 pub(crate) trait LinkDetach {
     type DetachError: Send;
@@ -35,6 +35,10 @@ pub(crate) trait LinkDetach {
         closed: bool,
         error: Option<Error>,
     ) -> Result<(), Self::DetachError>;
+
+    /// Detach or close the link locally without waiting for the remote Detach, returning the
+    /// remote handle that should be removed from the session
+    fn abandon(&mut self, closed: bool) -> Option<InputHandle>;
 }
 
 pub(crate) trait LinkAttach {
@@ -81,6 +85,9 @@ pub(crate) trait LinkExt: Link {
 
     fn max_message_size(&self) -> Option<u64>;
 
+    #[cfg(feature = "debug-deliveries")]
+    fn delivery_traces(&self) -> &crate::link::delivery_trace::ArcDeliveryTraces;
+
     fn properties<F, O>(&self, op: F) -> O
     where
         F: FnOnce(&Option<Fields>) -> O;
@@ -205,10 +212,11 @@ pub(crate) trait ReceiverLink: Link + LinkExt {
         payload: P,
         section_number: u32,
         section_offset: u64,
+        message_formats: &MessageFormatRegistry,
     ) -> Result<Delivery<T>, Self::TransferError>
     where
-        for<'de> T: FromBody<'de> + Send,
-        for<'b> P: IntoReader + AsByteIterator<'b> + Send + 'a;
+        T: DecodeDelivery + Send,
+        P: DeliveryPayload + Send + 'a;
 
     async fn dispose(
         &self,
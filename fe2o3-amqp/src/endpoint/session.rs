@@ -3,15 +3,19 @@
 use std::future::Future;
 
 use fe2o3_amqp_types::{
-    definitions::Error,
+    definitions::{DeliveryTag, Error, Role},
+    messaging::DeliveryState,
     performatives::{Attach, Begin, Detach, Disposition, End, Flow, Transfer},
 };
 
 use tokio::sync::mpsc;
 
 use crate::{
-    link::LinkRelay,
-    session::frame::{SessionFrame, SessionOutgoingItem},
+    link::{LinkFrame, LinkRelay},
+    session::{
+        frame::{SessionFrame, SessionOutgoingItem},
+        LinkInfo, SharedTransferMiddleware,
+    },
     Payload, SendBound,
 };
 
@@ -46,6 +50,9 @@ pub(crate) trait Session {
 
     fn deallocate_link(&mut self, output_handle: OutputHandle);
 
+    /// Removes a link whose Detach is not replied by the remote peer in time
+    fn abandon_link(&mut self, input_handle: InputHandle);
+
     fn on_incoming_begin(
         &mut self,
         channel: IncomingChannel,
@@ -112,7 +119,55 @@ pub(crate) trait Session {
         disposition: Disposition,
     ) -> Result<SessionFrame, Self::Error>;
 
+    /// Settlement of an unsettled delivery by the local sender. `None` is returned if the
+    /// delivery is already settled by the remote receiver
+    fn on_outgoing_settlement(
+        &mut self,
+        input_handle: InputHandle,
+        delivery_tag: DeliveryTag,
+        state: Option<DeliveryState>,
+    ) -> Option<SessionFrame>;
+
     fn on_outgoing_detach(&mut self, detach: Detach) -> SessionFrame;
+
+    // Administrative control over the links
+    fn links(&self) -> Vec<LinkInfo>;
+
+    /// Detaches the link with an error on behalf of the local link endpoint. The Detach that
+    /// should be sent to the remote peer is returned, or `None` if no attached link has the name
+    fn kick_link(
+        &mut self,
+        link_name: &str,
+        error: Error,
+    ) -> impl Future<Output = Option<SessionFrame>> + Send;
+
+    /// Returns true if the outgoing frame belongs to a link that is detached by `kick_link` and
+    /// must not be sent
+    fn on_kicked_link_frame(&mut self, frame: &LinkFrame) -> bool;
+
+    /// Refuses an incoming link by replying with an Attach that is immediately followed by a
+    /// Detach carrying the error. `role` is the role of the local link endpoint. Frames that still
+    /// arrive on the input handle are discarded until the remote Detach is received
+    fn reject_incoming_link(
+        &mut self,
+        link_name: String,
+        role: Role,
+        input_handle: InputHandle,
+        error: Error,
+    ) -> [SessionFrame; 2];
+
+    // Fair dispatching of incoming transfers
+    fn has_inbound_backlog(&self) -> bool;
+
+    /// Forwards one queued incoming frame to a link. Returns the link flows that are no longer
+    /// held back
+    fn dispatch_inbound_backlog(&mut self) -> impl Future<Output = Vec<LinkFlow>> + Send;
+
+    /// Returns `None` if the outgoing link flow is held back
+    fn hold_outgoing_flow(&mut self, flow: LinkFlow) -> Option<LinkFlow>;
+
+    /// Returns the middleware that transforms the transfer payloads on the session
+    fn transfer_middleware(&self) -> Option<SharedTransferMiddleware>;
 }
 
 pub(crate) trait SessionExt: Session {
@@ -5,7 +5,10 @@ use fe2o3_amqp_types::performatives::{
     Attach, Begin, Close, Detach, Disposition, End, Flow, Open, Performative, Transfer,
 };
 use serde::{ser::Serialize, Deserialize};
-use serde_amqp::{de::Deserializer, read::IoReader};
+use serde_amqp::{
+    de::{DecodeLimits, Deserializer},
+    read::IoReader,
+};
 use tokio_util::codec::{Decoder, Encoder};
 
 use crate::Payload;
@@ -61,6 +64,10 @@ impl Frame {
 pub struct FrameEncoder {
     /// Max frame size for the transport
     max_frame_body_size: usize,
+
+    /// Checks the outgoing performatives against the spec
+    #[cfg(feature = "strict-validation")]
+    validator: super::validation::Validator,
 }
 
 fn write_header(dst: &mut BytesMut, channel: u16) {
@@ -74,6 +81,8 @@ impl FrameEncoder {
     pub(crate) fn new(max_frame_size: usize) -> Self {
         Self {
             max_frame_body_size: max_frame_size - 4,
+            #[cfg(feature = "strict-validation")]
+            validator: Default::default(),
         }
     }
 
@@ -112,7 +121,8 @@ impl FrameEncoder {
             // Send middle frames
             transfer.delivery_id = None;
             transfer.delivery_tag = None;
-            transfer.message_format = None;
+            // The message-format is kept on the continuation transfers so that it never
+            // differs from the one on the first transfer of the delivery
             transfer.settled = None;
             transfer.rcv_settle_mode = None;
             buf.clear();
@@ -159,6 +169,9 @@ impl Encoder<Frame> for FrameEncoder {
     fn encode(&mut self, item: Frame, dst: &mut BytesMut) -> Result<(), Self::Error> {
         use serde_amqp::ser::Serializer;
 
+        #[cfg(feature = "strict-validation")]
+        self.validator.validate(&item)?;
+
         match item.body {
             FrameBody::Open(performative) => {
                 write_header(dst, item.channel);
@@ -240,7 +253,7 @@ impl Decoder for FrameDecoder {
             FrameBody::Empty
         } else {
             let reader = IoReader::new(src.reader());
-            let mut deserializer = Deserializer::new(reader);
+            let mut deserializer = Deserializer::with_limits(reader, DecodeLimits::default());
             let performative: Performative = Deserialize::deserialize(&mut deserializer)?;
 
             match performative {
@@ -14,6 +14,12 @@ pub enum Error {
     /// AMQP error: not implemented
     #[error("AmqpError: NotImplemented")]
     NotImplemented,
+
+    /// An outgoing performative violates the spec
+    #[cfg_attr(docsrs, doc(cfg(feature = "strict-validation")))]
+    #[cfg(feature = "strict-validation")]
+    #[error(transparent)]
+    Validation(#[from] super::validation::ValidationError),
 }
 
 impl From<serde_amqp::Error> for Error {
@@ -11,3 +11,7 @@ pub const FRAME_TYPE_SASL: u8 = 0x01;
 
 mod error;
 pub use error::Error;
+
+cfg_strict_validation! {
+    pub mod validation;
+}
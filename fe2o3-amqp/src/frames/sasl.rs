@@ -60,7 +60,7 @@ impl Decoder for FrameCodec {
 
     fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         use bytes::Buf;
-        use serde_amqp::de::Deserializer;
+        use serde_amqp::de::{DecodeLimits, Deserializer};
 
         let doff = src.get_u8();
         let ftype = src.get_u8();
@@ -75,7 +75,7 @@ impl Decoder for FrameCodec {
         }
 
         let reader = IoReader::new(src.reader());
-        let mut deserializer = Deserializer::new(reader);
+        let mut deserializer = Deserializer::with_limits(reader, DecodeLimits::default());
         let frame: Frame = Deserialize::deserialize(&mut deserializer)?;
         Ok(Some(frame))
     }
@@ -0,0 +1,479 @@
+//! Validation of the outgoing performatives against the invariants of the spec
+//!
+//! With the `"strict-validation"` feature, the [`FrameEncoder`](super::amqp::FrameEncoder)
+//! checks every performative before it is encoded and fails with a [`ValidationError`] that
+//! names the violated invariant instead of putting a malformed frame on the wire. This is meant
+//! to catch the misuse of the low-level builders (eg. a hand-made `Attach` or `Transfer`) during
+//! development, where the remote peer would otherwise close the connection with a vague error or
+//! silently ignore the offending field.
+//!
+//! The settle modes of a link are taken from the Attach that is sent by the local endpoint, so the
+//! transfers are checked against the modes that the local endpoint asked for.
+
+use std::collections::HashMap;
+
+use fe2o3_amqp_types::{
+    definitions::{
+        DeliveryNumber, MessageFormat, ReceiverSettleMode, Role, SenderSettleMode,
+        MIN_MAX_FRAME_SIZE,
+    },
+    messaging::TargetArchetype,
+    performatives::{Attach, Disposition, Flow, Open, Transfer},
+};
+
+use super::amqp::{Frame, FrameBody};
+
+/// Maximum length of a delivery tag in bytes
+const MAX_DELIVERY_TAG_LENGTH: usize = 32;
+
+/// A violation of the spec by an outgoing performative
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ValidationError {
+    /// The container-id of the Open frame is empty
+    #[error("Open: container-id must not be empty")]
+    EmptyContainerId,
+
+    /// The max-frame-size of the Open frame is below the minimum
+    #[error("Open: max-frame-size {0} is smaller than the minimum of {MIN_MAX_FRAME_SIZE}")]
+    MaxFrameSizeTooSmall(u32),
+
+    /// The name of the link is empty
+    #[error("Attach: the link name must not be empty")]
+    EmptyLinkName,
+
+    /// A sending link endpoint did not set the initial-delivery-count
+    #[error("Attach {name:?}: initial-delivery-count must be set when the role is sender")]
+    MissingInitialDeliveryCount {
+        /// Name of the link
+        name: String,
+    },
+
+    /// The address of a terminus that is requested to be dynamically created is set
+    #[error("Attach {name:?}: the {terminus} address must not be set when dynamic is true")]
+    DynamicNodeWithAddress {
+        /// Name of the link
+        name: String,
+
+        /// `"source"` or `"target"`
+        terminus: &'static str,
+    },
+
+    /// The dynamic-node-properties of a terminus are set while dynamic is false
+    #[error("Attach {name:?}: the {terminus} dynamic-node-properties must not be set unless dynamic is true")]
+    DynamicNodePropertiesWithoutDynamic {
+        /// Name of the link
+        name: String,
+
+        /// `"source"` or `"target"`
+        terminus: &'static str,
+    },
+
+    /// A link flow field is set on a Flow frame that does not carry a handle
+    #[error("Flow: {field} must not be set when the handle is not set")]
+    LinkFlowWithoutHandle {
+        /// Name of the field
+        field: &'static str,
+    },
+
+    /// The first transfer of a delivery does not carry the delivery-id
+    #[error(
+        "Transfer on handle {handle}: delivery-id must be set on the first transfer of a delivery"
+    )]
+    MissingDeliveryId {
+        /// Handle of the link
+        handle: u32,
+    },
+
+    /// The first transfer of a delivery does not carry the delivery-tag
+    #[error(
+        "Transfer on handle {handle}: delivery-tag must be set on the first transfer of a delivery"
+    )]
+    MissingDeliveryTag {
+        /// Handle of the link
+        handle: u32,
+    },
+
+    /// The delivery-tag is longer than 32 bytes
+    #[error("Transfer on handle {handle}: delivery-tag is {length} bytes long, which is more than {MAX_DELIVERY_TAG_LENGTH}")]
+    DeliveryTagTooLong {
+        /// Handle of the link
+        handle: u32,
+
+        /// Length of the delivery tag
+        length: usize,
+    },
+
+    /// The settled flag of a transfer contradicts the snd-settle-mode of the link
+    #[error("Transfer on handle {handle}: settled is {settled} but the snd-settle-mode of the link is {mode:?}")]
+    SettledContradictsSndSettleMode {
+        /// Handle of the link
+        handle: u32,
+
+        /// The settled flag of the transfer
+        settled: bool,
+
+        /// The snd-settle-mode of the link
+        mode: SenderSettleMode,
+    },
+
+    /// A transfer asks for the receiver to settle second on a link whose rcv-settle-mode is first
+    #[error("Transfer on handle {handle}: rcv-settle-mode second is illegal on a link whose rcv-settle-mode is first")]
+    RcvSettleModeSecondOnFirst {
+        /// Handle of the link
+        handle: u32,
+    },
+
+    /// A continuation transfer carries a different message-format than the first transfer
+    #[error("Transfer on handle {handle}: message-format {found} differs from {expected} of the first transfer of the delivery")]
+    MessageFormatChanged {
+        /// Handle of the link
+        handle: u32,
+
+        /// The message format of the first transfer
+        expected: MessageFormat,
+
+        /// The message format of the continuation transfer
+        found: MessageFormat,
+    },
+
+    /// The last delivery-id of a Disposition precedes the first
+    #[error("Disposition: last {last} precedes first {first}")]
+    DispositionRangeReversed {
+        /// The first delivery-id
+        first: DeliveryNumber,
+
+        /// The last delivery-id
+        last: DeliveryNumber,
+    },
+}
+
+/// What is known of a link from the Attach sent by the local endpoint
+#[derive(Debug)]
+struct LinkInfo {
+    role: Role,
+    snd_settle_mode: SenderSettleMode,
+    rcv_settle_mode: ReceiverSettleMode,
+
+    /// Message format of the delivery whose transfers are not complete
+    incomplete: Option<Option<MessageFormat>>,
+}
+
+/// Validates the outgoing frames of a connection, keeping track of the links that are attached
+/// on each channel
+#[derive(Debug, Default)]
+pub(crate) struct Validator {
+    links: HashMap<(u16, u32), LinkInfo>,
+}
+
+impl Validator {
+    pub fn validate(&mut self, frame: &Frame) -> Result<(), ValidationError> {
+        match &frame.body {
+            FrameBody::Open(open) => validate_open(open),
+            FrameBody::Attach(attach) => {
+                validate_attach(attach)?;
+                self.links.insert(
+                    (frame.channel, attach.handle.0),
+                    LinkInfo {
+                        role: attach.role.clone(),
+                        snd_settle_mode: attach.snd_settle_mode.clone(),
+                        rcv_settle_mode: attach.rcv_settle_mode.clone(),
+                        incomplete: None,
+                    },
+                );
+                Ok(())
+            }
+            FrameBody::Flow(flow) => validate_flow(flow),
+            FrameBody::Transfer { performative, .. } => {
+                self.validate_transfer(frame.channel, performative)
+            }
+            FrameBody::Disposition(disposition) => validate_disposition(disposition),
+            FrameBody::Detach(detach) => {
+                self.links.remove(&(frame.channel, detach.handle.0));
+                Ok(())
+            }
+            FrameBody::End(_) => {
+                self.links
+                    .retain(|(channel, _), _| *channel != frame.channel);
+                Ok(())
+            }
+            FrameBody::Close(_) => {
+                self.links.clear();
+                Ok(())
+            }
+            FrameBody::Begin(_) | FrameBody::Empty => Ok(()),
+        }
+    }
+
+    fn validate_transfer(
+        &mut self,
+        channel: u16,
+        transfer: &Transfer,
+    ) -> Result<(), ValidationError> {
+        let handle = transfer.handle.0;
+        // The transfers on a handle that was not attached by this encoder are left to the
+        // remote peer
+        let link = match self.links.get_mut(&(channel, handle)) {
+            Some(link) if matches!(link.role, Role::Sender) => link,
+            _ => return Ok(()),
+        };
+
+        match &link.incomplete {
+            None => {
+                if transfer.delivery_id.is_none() {
+                    return Err(ValidationError::MissingDeliveryId { handle });
+                }
+                match &transfer.delivery_tag {
+                    None => return Err(ValidationError::MissingDeliveryTag { handle }),
+                    Some(tag) if tag.len() > MAX_DELIVERY_TAG_LENGTH => {
+                        return Err(ValidationError::DeliveryTagTooLong {
+                            handle,
+                            length: tag.len(),
+                        })
+                    }
+                    Some(_) => {}
+                }
+            }
+            Some(expected) => {
+                if let (Some(expected), Some(found)) = (expected, transfer.message_format) {
+                    if *expected != found {
+                        return Err(ValidationError::MessageFormatChanged {
+                            handle,
+                            expected: *expected,
+                            found,
+                        });
+                    }
+                }
+            }
+        }
+
+        // A resumed or aborted delivery may be settled regardless of the settle mode
+        if let (Some(settled), false, false) = (transfer.settled, transfer.resume, transfer.aborted)
+        {
+            let contradicts = match link.snd_settle_mode {
+                SenderSettleMode::Unsettled => settled,
+                SenderSettleMode::Settled => !settled,
+                SenderSettleMode::Mixed => false,
+            };
+            if contradicts {
+                return Err(ValidationError::SettledContradictsSndSettleMode {
+                    handle,
+                    settled,
+                    mode: link.snd_settle_mode.clone(),
+                });
+            }
+        }
+
+        if let (Some(ReceiverSettleMode::Second), ReceiverSettleMode::First) =
+            (&transfer.rcv_settle_mode, &link.rcv_settle_mode)
+        {
+            return Err(ValidationError::RcvSettleModeSecondOnFirst { handle });
+        }
+
+        link.incomplete = match (transfer.more && !transfer.aborted, link.incomplete.take()) {
+            (false, _) => None,
+            (true, Some(message_format)) => Some(message_format),
+            (true, None) => Some(transfer.message_format),
+        };
+        Ok(())
+    }
+}
+
+fn validate_open(open: &Open) -> Result<(), ValidationError> {
+    if open.container_id.is_empty() {
+        return Err(ValidationError::EmptyContainerId);
+    }
+    if open.max_frame_size.0 < MIN_MAX_FRAME_SIZE as u32 {
+        return Err(ValidationError::MaxFrameSizeTooSmall(open.max_frame_size.0));
+    }
+    Ok(())
+}
+
+fn validate_attach(attach: &Attach) -> Result<(), ValidationError> {
+    let name = || attach.name.clone();
+    if attach.name.is_empty() {
+        return Err(ValidationError::EmptyLinkName);
+    }
+    if matches!(attach.role, Role::Sender) && attach.initial_delivery_count.is_none() {
+        return Err(ValidationError::MissingInitialDeliveryCount { name: name() });
+    }
+
+    // The endpoint that asks for a dynamic node leaves its address to the remote peer: the
+    // receiver for the source and the sender for the target
+    if let Some(source) = &attach.source {
+        if source.dynamic && source.address.is_some() && matches!(attach.role, Role::Receiver) {
+            return Err(ValidationError::DynamicNodeWithAddress {
+                name: name(),
+                terminus: "source",
+            });
+        }
+        if !source.dynamic && source.dynamic_node_properties.is_some() {
+            return Err(ValidationError::DynamicNodePropertiesWithoutDynamic {
+                name: name(),
+                terminus: "source",
+            });
+        }
+    }
+    if let Some(TargetArchetype::Target(target)) = attach.target.as_deref() {
+        if target.dynamic && target.address.is_some() && matches!(attach.role, Role::Sender) {
+            return Err(ValidationError::DynamicNodeWithAddress {
+                name: name(),
+                terminus: "target",
+            });
+        }
+        if !target.dynamic && target.dynamic_node_properties.is_some() {
+            return Err(ValidationError::DynamicNodePropertiesWithoutDynamic {
+                name: name(),
+                terminus: "target",
+            });
+        }
+    }
+    Ok(())
+}
+
+fn validate_flow(flow: &Flow) -> Result<(), ValidationError> {
+    if flow.handle.is_some() {
+        return Ok(());
+    }
+    let field = if flow.delivery_count.is_some() {
+        "delivery-count"
+    } else if flow.link_credit.is_some() {
+        "link-credit"
+    } else if flow.available.is_some() {
+        "available"
+    } else {
+        return Ok(());
+    };
+    Err(ValidationError::LinkFlowWithoutHandle { field })
+}
+
+fn validate_disposition(disposition: &Disposition) -> Result<(), ValidationError> {
+    match disposition.last {
+        // The delivery-ids are serial numbers (RFC-1982)
+        Some(last) if last.wrapping_sub(disposition.first) > i32::MAX as u32 => {
+            Err(ValidationError::DispositionRangeReversed {
+                first: disposition.first,
+                last,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::{
+        definitions::{DeliveryTag, Handle, ReceiverSettleMode, Role, SenderSettleMode},
+        messaging::{Source, Target},
+        performatives::{Attach, Detach, Transfer},
+    };
+
+    use super::{ValidationError, Validator};
+    use crate::frames::amqp::{Frame, FrameBody};
+
+    fn attach(role: Role, snd_settle_mode: SenderSettleMode) -> Attach {
+        Attach {
+            name: "link".into(),
+            handle: Handle(0),
+            role,
+            snd_settle_mode,
+            rcv_settle_mode: ReceiverSettleMode::First,
+            source: Some(Box::new(Source::builder().address("q1").build())),
+            target: Some(Box::new(Target::builder().address("q1").build().into())),
+            unsettled: None,
+            incomplete_unsettled: false,
+            initial_delivery_count: Some(0),
+            max_message_size: None,
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        }
+    }
+
+    fn transfer(settled: Option<bool>, more: bool) -> Transfer {
+        Transfer {
+            handle: Handle(0),
+            delivery_id: Some(0),
+            delivery_tag: Some(DeliveryTag::from(vec![0])),
+            message_format: Some(0),
+            settled,
+            more,
+            rcv_settle_mode: None,
+            state: None,
+            resume: false,
+            aborted: false,
+            batchable: false,
+        }
+    }
+
+    fn validate(validator: &mut Validator, body: FrameBody) -> Result<(), ValidationError> {
+        validator.validate(&Frame::new(0u16, body))
+    }
+
+    fn validate_transfer(
+        validator: &mut Validator,
+        performative: Transfer,
+    ) -> Result<(), ValidationError> {
+        validate(
+            validator,
+            FrameBody::Transfer {
+                performative,
+                payload: Default::default(),
+            },
+        )
+    }
+
+    #[test]
+    fn dynamic_target_of_a_sender_must_not_have_an_address() {
+        let mut validator = Validator::default();
+        let mut attach = attach(Role::Sender, SenderSettleMode::Mixed);
+        attach.target = Some(Box::new(
+            Target::builder().address("q1").dynamic(true).build().into(),
+        ));
+        assert!(matches!(
+            validate(&mut validator, FrameBody::Attach(attach)),
+            Err(ValidationError::DynamicNodeWithAddress {
+                terminus: "target",
+                ..
+            })
+        ));
+
+        let mut attach = self::attach(Role::Sender, SenderSettleMode::Mixed);
+        attach.initial_delivery_count = None;
+        assert!(matches!(
+            validate(&mut validator, FrameBody::Attach(attach)),
+            Err(ValidationError::MissingInitialDeliveryCount { .. })
+        ));
+    }
+
+    #[test]
+    fn transfers_are_checked_against_the_settle_mode_of_the_link() {
+        let mut validator = Validator::default();
+        let attach = attach(Role::Sender, SenderSettleMode::Unsettled);
+        validate(&mut validator, FrameBody::Attach(attach)).unwrap();
+
+        let error = validate_transfer(&mut validator, transfer(Some(true), false)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Transfer on handle 0: settled is true but the snd-settle-mode of the link is Unsettled"
+        );
+
+        // The continuation transfers do not carry the delivery tag
+        validate_transfer(&mut validator, transfer(Some(false), true)).unwrap();
+        let mut continuation = transfer(None, false);
+        continuation.delivery_tag = None;
+        validate_transfer(&mut validator, continuation.clone()).unwrap();
+        assert_eq!(
+            validate_transfer(&mut validator, continuation),
+            Err(ValidationError::MissingDeliveryTag { handle: 0 })
+        );
+
+        // The checks stop once the link is detached
+        let detach = Detach {
+            handle: Handle(0),
+            closed: true,
+            error: None,
+        };
+        validate(&mut validator, FrameBody::Detach(detach)).unwrap();
+        validate_transfer(&mut validator, transfer(Some(true), false)).unwrap();
+    }
+}
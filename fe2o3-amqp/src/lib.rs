@@ -24,9 +24,18 @@
 //! |---------|-------------|
 //! |`"rustls"`| enables TLS integration with `tokio-rustls` and `rustls` |
 //! |`"native-tls"`| enables TLS integration with `tokio-native-tls` and `native-tls`|
+//! |`"dangerous-tls"`| enables accepting invalid, pinned or callback verified server certificates with the default TLS connector |
 //! |`"acceptor"`| enables `ConnectionAcceptor`, `SessionAcceptor`, and `LinkAcceptor`|
 //! |`"transaction"`| enables `Controller`, `Transaction`, `OwnedTransaction` and `control_link_acceptor` |
 //! |`"scram"`| enables SCRAM auth |
+//! |`"gzip"`| enables `"gzip"` compression of message bodies with `flate2` |
+//! |`"deflate"`| enables `"deflate"` compression of message bodies with `flate2` |
+//! |`"zstd"`| enables `"zstd"` compression of message bodies with `zstd` |
+//! |`"checksum"`| enables writing and verifying a SHA-256 of the bare message in the footer |
+//! |`"websocket"`| enables opening connections with `ws://` and `wss://` urls using `fe2o3-amqp-ws` |
+//! |`"chaos"`| enables `ChaosTransport`, which injects latency and faults into the IO stream for testing |
+//! |`"debug-deliveries"`| records the frames of the recent deliveries of every link, which are dumped with `Sender::delivery_trace()` and `Receiver::delivery_trace()` |
+//! |`"strict-validation"`| validates the outgoing performatives against the invariants of the spec and fails with a descriptive error before a malformed frame is sent |
 //! |`"tracing"`| enables logging with `tracing` |
 //! |`"log"`| enables logging with `log` |
 //!
@@ -153,6 +162,17 @@
 //! }
 //! ```
 //!
+//! With the `"websocket"` feature, the builder opens the WebSocket itself from a `ws://` or
+//! `wss://` url. See `transport::websocket` for details
+//!
+//! ```rust,ignore
+//! let mut connection = Connection::builder()
+//!     .container_id("connection-1")
+//!     .open("ws://localhost:5673")
+//!     .await
+//!     .unwrap();
+//! ```
+//!
 //! # More examples
 //!
 //! More examples of sending and receiving can be found on the [GitHub
@@ -191,6 +211,7 @@ pub(crate) mod control;
 pub(crate) mod endpoint;
 pub(crate) mod util;
 
+pub mod address;
 pub mod auth;
 pub mod connection;
 pub mod frames;
@@ -198,6 +219,12 @@ pub mod link;
 pub mod sasl_profile;
 pub mod session;
 pub mod transport;
+pub mod watchdog;
+
+cfg_not_wasm32! {
+    pub mod clock;
+    pub mod recovery;
+}
 
 cfg_acceptor! {
     pub mod acceptor;
@@ -0,0 +1,162 @@
+//! Retrying a link attach that is refused with a transient error
+
+use std::time::Duration;
+
+use fe2o3_amqp_types::definitions::{self, AmqpError, ErrorCondition};
+
+/// Default number of attempts of [`AttachRetry`], including the first attempt
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Default delay before the first retry of [`AttachRetry`]
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Default upper bound on the delay between two attempts of [`AttachRetry`]
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Retry policy of a link attach that is refused by the remote peer
+///
+/// A broker may refuse an Attach for reasons that go away on their own, eg. with
+/// `amqp:resource-limit-exceeded` while it is under load, or with `amqp:not-found` while a queue
+/// that is created on demand is not yet available. If the remote peer closes the link with an
+/// error whose condition is one of `conditions`, the attach is attempted again after a delay
+/// that starts at `initial_backoff` and is multiplied by `multiplier` after every attempt, up to
+/// `max_backoff`. The error of the last attempt is returned once `max_attempts` attempts have
+/// been made, and any other error is returned right away.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use fe2o3_amqp::link::AttachRetry;
+/// use fe2o3_amqp::types::definitions::AmqpError;
+///
+/// let sender = Sender::builder()
+///     .name("sender")
+///     .target("q1")
+///     .attach_retry(AttachRetry::new(10).retry_on(AmqpError::NotFound))
+///     .attach(&mut session)
+///     .await
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttachRetry {
+    /// Maximum number of attempts, including the first attempt
+    pub max_attempts: u32,
+
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+
+    /// Upper bound on the delay between two attempts
+    pub max_backoff: Duration,
+
+    /// Factor by which the delay grows after every attempt
+    pub multiplier: f64,
+
+    /// Error conditions that the attach is retried on
+    pub conditions: Vec<ErrorCondition>,
+}
+
+impl Default for AttachRetry {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ATTEMPTS)
+    }
+}
+
+impl AttachRetry {
+    /// Creates a policy that makes at most `max_attempts` attempts and only retries on
+    /// `amqp:resource-limit-exceeded`
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            multiplier: 2.0,
+            conditions: vec![AmqpError::ResourceLimitExceeded.into()],
+        }
+    }
+
+    /// Sets the delay before the first retry and the upper bound on the delay
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Sets the factor by which the delay grows after every attempt
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Also retry on an error condition
+    pub fn retry_on(mut self, condition: impl Into<ErrorCondition>) -> Self {
+        let condition = condition.into();
+        if !self.conditions.contains(&condition) {
+            self.conditions.push(condition);
+        }
+        self
+    }
+
+    /// Waits before the next attempt if the `attempt`-th attempt (starting at one) failed with a
+    /// retryable error. Returns `false` if the attach should not be retried
+    pub(crate) async fn wait_before_retry(&self, attempt: u32, error: &definitions::Error) -> bool {
+        let delay = match self.delay(attempt, error) {
+            Some(delay) => delay,
+            None => return false,
+        };
+        #[cfg(feature = "tracing")]
+        tracing::warn!(attempt, ?error, ?delay, "Attach is refused, retrying");
+        #[cfg(feature = "log")]
+        log::warn!(
+            "Attach is refused (attempt {}), retrying in {:?}: {:?}",
+            attempt,
+            delay,
+            error
+        );
+        tokio::time::sleep(delay).await;
+        true
+    }
+
+    /// Returns how long to wait before the next attempt if the `attempt`-th attempt (starting at
+    /// one) failed with the error
+    fn delay(&self, attempt: u32, error: &definitions::Error) -> Option<Duration> {
+        if attempt >= self.max_attempts || !self.conditions.contains(&error.condition) {
+            return None;
+        }
+        let factor = self.multiplier.max(1.0).powi((attempt - 1) as i32);
+        let delay = self.initial_backoff.as_secs_f64() * factor;
+        Some(Duration::from_secs_f64(
+            delay.min(self.max_backoff.as_secs_f64()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fe2o3_amqp_types::definitions::{self, AmqpError};
+
+    use super::AttachRetry;
+
+    #[test]
+    fn delay_grows_up_to_the_max_backoff() {
+        let retry = AttachRetry::new(5).backoff(Duration::from_secs(1), Duration::from_secs(3));
+        let error = definitions::Error::new(AmqpError::ResourceLimitExceeded, None, None);
+        let delays: Vec<_> = (1..6).map(|attempt| retry.delay(attempt, &error)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_secs(1)),
+                Some(Duration::from_secs(2)),
+                Some(Duration::from_secs(3)),
+                Some(Duration::from_secs(3)),
+                None
+            ]
+        );
+
+        let not_found = definitions::Error::new(AmqpError::NotFound, None, None);
+        assert_eq!(retry.delay(1, &not_found), None);
+        let retry = retry.retry_on(AmqpError::NotFound);
+        assert_eq!(retry.delay(1, &not_found), Some(Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,275 @@
+//! A sender that buffers messages while the link is disconnected
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use fe2o3_amqp_types::messaging::{Outcome, SerializableBody};
+use futures_util::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+
+use crate::session::SessionHandle;
+
+use super::{
+    delivery::Sendable, BufferedSendError, DetachThenResumeSenderError, SendError, Sender,
+};
+
+/// What to do with a message when the buffer of a [`BufferedSender`] is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// The new message is handed back with [`BufferedSendError::BufferFull`]
+    #[default]
+    Reject,
+
+    /// The oldest buffered message is discarded to make room for the new message
+    DropOldest,
+
+    /// The new message is discarded
+    DropNewest,
+}
+
+/// A [`Sender`] that keeps accepting messages while the link is disconnected
+///
+/// Messages are sent without waiting for the settlement, and each message is identified by a
+/// sequence number that is returned by [`send`](#method.send). If sending fails because the link
+/// or the underlying session is gone, the message is kept in a bounded buffer, and so are the
+/// messages that are sent afterwards. The buffer is flushed in order once the link is resumed on
+/// a new session with [`reconnect`](#method.reconnect).
+///
+/// Deliveries that are still unsettled when the link is lost are replayed by the link resumption
+/// with the same delivery tags, so they are not buffered again.
+///
+/// The settlements are obtained with [`next_settlement`](#method.next_settlement), which reports
+/// each message at most once even if the message is transferred more than once.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let sender = Sender::attach(&mut session, "telemetry", "q1").await.unwrap();
+/// let mut sender = BufferedSender::new(sender, 1000, OverflowPolicy::DropOldest);
+///
+/// let id = sender.send("reading").await.unwrap();
+///
+/// // After the broker restarts
+/// let mut connection = Connection::open("telemetry-agent", "amqp://localhost:5672").await.unwrap();
+/// let mut session = Session::begin(&mut connection).await.unwrap();
+/// sender.reconnect(&session).await.unwrap();
+///
+/// while let Some((id, result)) = sender.next_settlement().await {
+///     println!("{}: {:?}", id, result);
+/// }
+/// ```
+pub struct BufferedSender<T> {
+    sender: Sender,
+    connected: bool,
+    capacity: usize,
+    overflow_policy: OverflowPolicy,
+    next_id: u64,
+    dropped: u64,
+
+    /// Messages waiting to be sent
+    buffer: VecDeque<(u64, Sendable<T>)>,
+
+    /// Messages that are sent but not yet settled
+    unsettled: HashMap<u64, Sendable<T>>,
+
+    /// Ids of the messages whose settlement is yet to be reported
+    pending: HashSet<u64>,
+
+    in_flight: FuturesUnordered<BoxFuture<'static, (u64, Result<Outcome, SendError>)>>,
+    ready: VecDeque<(u64, Result<Outcome, SendError>)>,
+}
+
+impl<T> std::fmt::Debug for BufferedSender<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferedSender")
+            .field("sender", &self.sender)
+            .field("connected", &self.connected)
+            .field("capacity", &self.capacity)
+            .field("overflow_policy", &self.overflow_policy)
+            .field("buffered", &self.buffer.len())
+            .field("dropped", &self.dropped)
+            .finish()
+    }
+}
+
+impl<T> BufferedSender<T>
+where
+    T: SerializableBody,
+{
+    /// Wraps an attached [`Sender`]. At most `capacity` messages are buffered while the link is
+    /// disconnected
+    pub fn new(sender: Sender, capacity: usize, overflow_policy: OverflowPolicy) -> Self {
+        Self {
+            sender,
+            connected: true,
+            capacity,
+            overflow_policy,
+            next_id: 0,
+            dropped: 0,
+            buffer: VecDeque::new(),
+            unsettled: HashMap::new(),
+            pending: HashSet::new(),
+            in_flight: FuturesUnordered::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Get a reference to the wrapped [`Sender`]
+    pub fn sender(&self) -> &Sender {
+        &self.sender
+    }
+
+    /// Whether the link is believed to be connected
+    ///
+    /// This turns false once sending fails because the link or the session is gone, and turns
+    /// true again after a successful [`reconnect`](#method.reconnect)
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Number of messages waiting in the buffer
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Number of messages discarded by the [`OverflowPolicy`]
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Sends a message without waiting for the settlement, or buffers the message if the link
+    /// is disconnected
+    ///
+    /// The returned sequence number identifies the message in
+    /// [`next_settlement`](#method.next_settlement). A message that is discarded by the
+    /// [`OverflowPolicy`] is never reported. Errors that are not caused by a lost link (eg.
+    /// failing to encode the message) are returned immediately.
+    pub async fn send(
+        &mut self,
+        sendable: impl Into<Sendable<T>>,
+    ) -> Result<u64, BufferedSendError<T>> {
+        let id = self.next_id;
+        let sendable = sendable.into();
+
+        self.flush().await;
+        if self.connected && self.buffer.is_empty() {
+            if let Err(Some(error)) = self.transmit(id, sendable).await {
+                return Err(BufferedSendError::Send(error));
+            }
+        } else {
+            self.enqueue(id, sendable)
+                .map_err(BufferedSendError::BufferFull)?;
+        }
+
+        self.next_id += 1;
+        Ok(id)
+    }
+
+    /// Sends the buffered messages in order if the link is connected
+    pub async fn flush(&mut self) {
+        while self.connected {
+            let (id, sendable) = match self.buffer.pop_front() {
+                Some(item) => item,
+                None => break,
+            };
+            if let Err(Some(error)) = self.transmit(id, sendable).await {
+                self.pending.insert(id);
+                self.ready.push_back((id, Err(error)));
+            }
+        }
+    }
+
+    /// Resumes the link on a new session and then flushes the buffered messages
+    ///
+    /// The deliveries that were unsettled when the link was lost are replayed by the link
+    /// resumption with the same delivery tags.
+    pub async fn reconnect<R>(
+        &mut self,
+        session: &SessionHandle<R>,
+    ) -> Result<(), DetachThenResumeSenderError> {
+        self.sender.detach_then_resume_on_session(session).await?;
+        self.connected = true;
+        self.flush().await;
+        Ok(())
+    }
+
+    /// Waits for the next settlement
+    ///
+    /// `None` is returned if there is no message whose settlement is pending. Messages that are
+    /// still in the buffer are not waited for.
+    pub async fn next_settlement(&mut self) -> Option<(u64, Result<Outcome, SendError>)> {
+        loop {
+            if let Some((id, result)) = self.ready.pop_front() {
+                if self.pending.remove(&id) {
+                    return Some((id, result));
+                }
+                continue;
+            }
+
+            let (id, result) = self.in_flight.next().await?;
+            match result {
+                Err(SendError::LinkStateError(_)) | Err(SendError::Detached(_)) => {
+                    // The delivery is lost together with the link, so it is sent again after
+                    // reconnecting
+                    self.connected = false;
+                    if let Some(sendable) = self.unsettled.remove(&id) {
+                        self.buffer.push_front((id, sendable));
+                    }
+                }
+                result => {
+                    self.unsettled.remove(&id);
+                    // Suppress the settlement of a message that is already reported
+                    if self.pending.remove(&id) {
+                        return Some((id, result));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Closes the link
+    ///
+    /// Messages that are still buffered are discarded
+    pub async fn close(self) -> Result<(), super::DetachError> {
+        self.sender.close().await
+    }
+
+    /// Sends a message. `Err(None)` means the link is lost and the message is buffered, and
+    /// `Err(Some(_))` means the message cannot be sent at all
+    async fn transmit(&mut self, id: u64, sendable: Sendable<T>) -> Result<(), Option<SendError>> {
+        match self.sender.send_batchable_ref(&sendable).await {
+            Ok(fut) => {
+                self.pending.insert(id);
+                self.unsettled.insert(id, sendable);
+                self.in_flight
+                    .push(fut.map(move |result| (id, result)).boxed());
+                Ok(())
+            }
+            Err(SendError::LinkStateError(_)) | Err(SendError::Detached(_)) => {
+                self.connected = false;
+                self.buffer.push_front((id, sendable));
+                Err(None)
+            }
+            Err(error) => Err(Some(error)),
+        }
+    }
+
+    /// Hands the message back if the buffer is full and the overflow policy rejects it
+    fn enqueue(&mut self, id: u64, sendable: Sendable<T>) -> Result<(), Box<Sendable<T>>> {
+        if self.buffer.len() >= self.capacity {
+            match self.overflow_policy {
+                OverflowPolicy::Reject => return Err(Box::new(sendable)),
+                OverflowPolicy::DropOldest if !self.buffer.is_empty() => {
+                    if let Some((dropped_id, _)) = self.buffer.pop_front() {
+                        self.pending.remove(&dropped_id);
+                    }
+                    self.dropped += 1;
+                }
+                OverflowPolicy::DropOldest | OverflowPolicy::DropNewest => {
+                    self.dropped += 1;
+                    return Ok(());
+                }
+            }
+        }
+        self.buffer.push_back((id, sendable));
+        Ok(())
+    }
+}
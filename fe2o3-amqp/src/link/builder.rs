@@ -3,15 +3,18 @@
 use std::{
     marker::PhantomData,
     sync::{atomic::AtomicU32, Arc},
+    time::Duration,
 };
 
 use fe2o3_amqp_types::{
-    definitions::{Fields, ReceiverSettleMode, SenderSettleMode, SequenceNo},
-    messaging::{Source, Target, TargetArchetype},
-    primitives::{Symbol, Ulong},
+    definitions::{
+        DeliveryTag, Fields, ReceiverSettleMode, SenderSettleMode, SequenceNo, MIN_MAX_FRAME_SIZE,
+    },
+    messaging::{DeliveryState, Source, Target, TargetArchetype},
+    primitives::{OrderedMap, Symbol, Ulong, Value},
 };
 use parking_lot::RwLock;
-use tokio::sync::{mpsc, Notify};
+use tokio::sync::{mpsc, watch, Notify};
 
 use crate::{
     connection::DEFAULT_OUTGOING_BUFFER_SIZE,
@@ -22,16 +25,36 @@ use crate::{
 };
 
 use super::{
-    receiver::{CreditMode, ReceiverInner},
+    byte_credit::{ByteWindow, BYTE_LIMIT_KEY},
+    message_format::MessageFormatRegistry,
+    receiver::{CreditMode, EmptyBodyPolicy, ReceiverInner},
     role,
-    sender::SenderInner,
+    sender::{AvailableMode, SenderInner},
     state::{LinkFlowState, LinkFlowStateInner, LinkState},
     target_archetype::VerifyTargetArchetype,
     ArcUnsettledMap, Receiver, ReceiverAttachError, ReceiverFlowState, ReceiverLink,
     ReceiverRelayFlowState, Sender, SenderAttachError, SenderFlowState, SenderLink,
-    SenderRelayFlowState,
+    SenderRelayFlowState, SuspendedLink,
 };
 
+cfg_checksum! {
+    use super::checksum::Checksum;
+}
+
+cfg_compression! {
+    use super::compression::Compression;
+}
+
+cfg_not_wasm32! {
+    use super::attach_retry::AttachRetry;
+    use super::dedup::{Deduplication, Deduplicator};
+    use super::disposition_batching::{DispositionBatcher, DispositionBatching};
+    use super::keep_alive::{LinkEvents, LinkKeepAlive};
+    use super::latency::{ArcSettlementLatency, SettlementLatency, SlowSettlementAlert};
+    use super::rate_limit::{RateLimit, RateLimiter};
+    use super::spool::Spooling;
+}
+
 cfg_transaction! {
     use crate::transaction::Controller;
 
@@ -39,27 +62,27 @@ cfg_transaction! {
 }
 
 /// Type state for link::builder::Builder;
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WithoutName;
 
 /// Type state for link::builder::Builder;
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WithName;
 
 /// Type state for link::builder::Builder;
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WithoutTarget;
 
 /// Type state for link::builder::Builder;
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WithTarget;
 
 /// Type state for link::builder::Builder;
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WithoutSource;
 
 /// Type state for link::builder::Builder;
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WithSource;
 
 /// Builder for a Link
@@ -88,6 +111,12 @@ pub struct Builder<Role, T, NameState, SS, TS> {
     /// The maximum message size supported by the link endpoint
     pub max_message_size: Option<Ulong>,
 
+    /// The maximum size of the transfer frames emitted by the link. The connection negotiated
+    /// max frame size is used if this is `None` or larger than the negotiated value.
+    ///
+    /// This field has no effect on Receiver
+    pub max_transfer_frame_size: Option<u32>,
+
     /// The extension capabilities the sender supports
     pub offered_capabilities: Option<Vec<Symbol>>,
 
@@ -103,6 +132,67 @@ pub struct Builder<Role, T, NameState, SS, TS> {
     /// Credit mode of the link. This has no effect if a sender is built
     pub credit_mode: CreditMode,
 
+    /// Rate limit on the incoming messages, which paces the credit replenished in
+    /// `CreditMode::Auto`. This has no effect if a sender is built
+    #[cfg(not(target_arch = "wasm32"))]
+    pub rate_limit: Option<RateLimit>,
+
+    /// Window of the recently received message ids that are used to drop the redelivered
+    /// messages. This has no effect if a sender is built
+    #[cfg(not(target_arch = "wasm32"))]
+    pub deduplication: Option<Deduplication>,
+
+    /// Spooling of the large multi-frame deliveries to disk. This has no effect if a sender is
+    /// built
+    #[cfg(not(target_arch = "wasm32"))]
+    pub spooling: Option<Spooling>,
+
+    /// Batching of the accepts into ranged dispositions. This has no effect if a sender is built
+    #[cfg(not(target_arch = "wasm32"))]
+    pub disposition_batching: Option<DispositionBatching>,
+
+    /// Unsettled map of a suspended receiver that is restored and sent on attach, so that the
+    /// remote sender can resume or settle these deliveries. This has no effect if a sender is
+    /// built
+    pub unsettled: Option<OrderedMap<DeliveryTag, Option<DeliveryState>>>,
+
+    /// How the `available` field advertised in the Flow frames is maintained. This has no
+    /// effect if a receiver is built
+    pub available_mode: AvailableMode,
+
+    /// How long `detach()` and `close()` wait for the remote peer to reply with a Detach. The
+    /// link is detached locally and `DetachError::Timeout` is returned once the timeout elapses.
+    ///
+    /// # Default
+    ///
+    /// `None`, which waits indefinitely
+    pub detach_timeout: Option<Duration>,
+
+    /// Retry policy of the attach if the remote peer refuses it with a transient error. See
+    /// [`AttachRetry`] for details
+    ///
+    /// # Default
+    ///
+    /// `None`, which returns the error of the first attempt
+    #[cfg(not(target_arch = "wasm32"))]
+    pub attach_retry: Option<AttachRetry>,
+
+    /// Periodic Flow with `echo` set that checks that the remote link endpoint is responsive.
+    /// See [`LinkKeepAlive`] for details
+    ///
+    /// # Default
+    ///
+    /// `None`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub keep_alive: Option<LinkKeepAlive>,
+
+    /// Reports the deliveries whose settlement takes longer than a threshold. See
+    /// [`SlowSettlementAlert`] for details
+    ///
+    /// This field has no effect on Receiver
+    #[cfg(not(target_arch = "wasm32"))]
+    pub slow_settlement_alert: Option<SlowSettlementAlert>,
+
     /// Whether the receiver will automatically accept all incoming deliveries
     ///
     /// This field has no effect on Sender
@@ -112,6 +202,40 @@ pub struct Builder<Role, T, NameState, SS, TS> {
     /// `false`
     pub auto_accept: bool,
 
+    /// Codecs of the custom message formats that are decoded by `Receiver::recv_custom()`
+    ///
+    /// This field has no effect on Sender
+    pub message_formats: MessageFormatRegistry,
+
+    /// How the receiver handles the incoming messages that carry no body section
+    ///
+    /// This field has no effect on Sender
+    ///
+    /// # Default
+    ///
+    /// [`EmptyBodyPolicy::Decode`]
+    pub empty_body_policy: EmptyBodyPolicy,
+
+    /// Whether the receiver returns the prefetched deliveries in the order of the `priority` of
+    /// their header instead of the order of arrival
+    ///
+    /// This field has no effect on Sender
+    ///
+    /// # Default
+    ///
+    /// `false`
+    pub priority_ordering: bool,
+
+    /// Limit on the payload bytes that the receiver grants to the sender in addition to the link
+    /// credit. See [`byte_credit`](super::byte_credit) for details
+    ///
+    /// This field has no effect on Sender
+    ///
+    /// # Default
+    ///
+    /// `None`
+    pub byte_window: Option<u64>,
+
     /// Whether to verify the `source` field of the incoming Attach frame
     ///
     /// Default to true
@@ -122,6 +246,41 @@ pub struct Builder<Role, T, NameState, SS, TS> {
     /// Default to true
     pub verify_incoming_target: bool,
 
+    /// Compression of the `Data` body sections of outgoing messages
+    ///
+    /// This field has no effect on Receiver
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(any(feature = "gzip", feature = "deflate", feature = "zstd")))
+    )]
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+    pub compression: Option<Compression>,
+
+    /// Whether the receiver will transparently decompress the `Data` body sections of incoming
+    /// messages that are compressed with a recognized `content-encoding`
+    ///
+    /// This field has no effect on Sender
+    ///
+    /// # Default
+    ///
+    /// `false`
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(any(feature = "gzip", feature = "deflate", feature = "zstd")))
+    )]
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+    pub decompress: bool,
+
+    /// The SHA-256 of the bare message that a sender writes into the footer of outgoing messages
+    /// and that a receiver verifies on incoming messages
+    ///
+    /// # Default
+    ///
+    /// `None`
+    #[cfg_attr(docsrs, doc(cfg(feature = "checksum")))]
+    #[cfg(feature = "checksum")]
+    pub checksum: Option<Checksum>,
+
     // Type state markers
     role: PhantomData<Role>,
     name_state: PhantomData<NameState>,
@@ -139,20 +298,48 @@ impl<Role, T> Default for Builder<Role, T, WithoutName, WithoutSource, WithoutTa
             target: Default::default(),
             initial_delivery_count: Default::default(),
             max_message_size: Default::default(),
+            max_transfer_frame_size: Default::default(),
             offered_capabilities: Default::default(),
             desired_capabilities: Default::default(),
             properties: Default::default(),
 
             buffer_size: DEFAULT_OUTGOING_BUFFER_SIZE,
             credit_mode: Default::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            rate_limit: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            deduplication: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            spooling: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            disposition_batching: None,
+            unsettled: None,
+            available_mode: Default::default(),
+            detach_timeout: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            attach_retry: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            keep_alive: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            slow_settlement_alert: None,
             role: PhantomData,
             name_state: PhantomData,
             source_state: PhantomData,
             target_state: PhantomData,
 
             auto_accept: false,
+            message_formats: MessageFormatRegistry::default(),
+            empty_body_policy: EmptyBodyPolicy::default(),
+            priority_ordering: false,
+            byte_window: None,
             verify_incoming_source: true,
             verify_incoming_target: true,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            compression: None,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            decompress: false,
+            #[cfg(feature = "checksum")]
+            checksum: None,
         }
     }
 }
@@ -178,6 +365,63 @@ impl<T, NameState, SS, TS> Builder<role::ReceiverMarker, T, NameState, SS, TS> {
         self.auto_accept = value;
         self
     }
+
+    /// Sets the codecs of the custom message formats that are decoded by
+    /// `Receiver::recv_custom()`
+    pub fn message_formats(mut self, registry: MessageFormatRegistry) -> Self {
+        self.message_formats = registry;
+        self
+    }
+
+    /// Sets how the receiver handles the incoming messages that carry no body section
+    ///
+    /// Default value: [`EmptyBodyPolicy::Decode`]
+    pub fn empty_body_policy(mut self, policy: EmptyBodyPolicy) -> Self {
+        self.empty_body_policy = policy;
+        self
+    }
+
+    /// Sets whether the receiver returns the prefetched deliveries in the order of the `priority`
+    /// of their header instead of the order of arrival
+    ///
+    /// Only the deliveries that have already arrived when `recv()` is called are reordered, so
+    /// this is only meaningful when the credit allows several deliveries to be prefetched. The
+    /// deliveries of the same priority, and the messages without a header that have the default
+    /// priority of 4, keep the order of arrival. A delivery that spans multiple transfer frames
+    /// is not reordered, and the deliveries that arrive after it are not moved ahead of it.
+    ///
+    /// Default value: `false`
+    pub fn priority_ordering(mut self, value: bool) -> Self {
+        self.priority_ordering = value;
+        self
+    }
+
+    /// Sets the limit on the payload bytes that are received but not yet taken by `recv()`
+    ///
+    /// The limit is granted to the sender in the link properties on top of the link credit, and
+    /// is only followed by the senders that implement the convention described in
+    /// [`byte_credit`](super::byte_credit). A delivery is started as long as the limit is not
+    /// reached, so a single message larger than the window is still received.
+    ///
+    /// Default value: `None`
+    pub fn byte_window(mut self, window: u64) -> Self {
+        self.byte_window = Some(window);
+        self
+    }
+
+    cfg_compression! {
+        /// Sets whether the receiver will transparently decompress the `Data` body sections of
+        /// incoming messages that are compressed with a recognized `content-encoding`.
+        ///
+        /// The decompression stops once the message exceeds the `max_message_size` of the link,
+        /// and the message is then rejected with `amqp:link:message-size-exceeded`.
+        ///
+        /// Default value: `false`
+        pub fn decompress(mut self, value: bool) -> Self {
+            self.decompress = value;
+            self
+        }
+    }
 }
 
 impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
@@ -191,10 +435,28 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
             target: self.target,
             initial_delivery_count: self.initial_delivery_count,
             max_message_size: self.max_message_size,
+            max_transfer_frame_size: self.max_transfer_frame_size,
             offered_capabilities: self.offered_capabilities,
             desired_capabilities: self.desired_capabilities,
             buffer_size: self.buffer_size,
             credit_mode: self.credit_mode,
+            #[cfg(not(target_arch = "wasm32"))]
+            rate_limit: self.rate_limit,
+            #[cfg(not(target_arch = "wasm32"))]
+            deduplication: self.deduplication,
+            #[cfg(not(target_arch = "wasm32"))]
+            spooling: self.spooling,
+            #[cfg(not(target_arch = "wasm32"))]
+            disposition_batching: self.disposition_batching,
+            unsettled: self.unsettled,
+            available_mode: self.available_mode,
+            detach_timeout: self.detach_timeout,
+            #[cfg(not(target_arch = "wasm32"))]
+            attach_retry: self.attach_retry,
+            #[cfg(not(target_arch = "wasm32"))]
+            keep_alive: self.keep_alive,
+            #[cfg(not(target_arch = "wasm32"))]
+            slow_settlement_alert: self.slow_settlement_alert,
             properties: Default::default(),
 
             role: self.role,
@@ -203,8 +465,18 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
             target_state: self.target_state,
 
             auto_accept: self.auto_accept,
+            message_formats: self.message_formats,
+            empty_body_policy: self.empty_body_policy,
+            priority_ordering: self.priority_ordering,
+            byte_window: self.byte_window,
             verify_incoming_source: self.verify_incoming_source,
             verify_incoming_target: self.verify_incoming_target,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            compression: self.compression,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            decompress: self.decompress,
+            #[cfg(feature = "checksum")]
+            checksum: self.checksum,
         }
     }
 
@@ -218,10 +490,28 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
             target: self.target,
             initial_delivery_count: self.initial_delivery_count,
             max_message_size: self.max_message_size,
+            max_transfer_frame_size: self.max_transfer_frame_size,
             offered_capabilities: self.offered_capabilities,
             desired_capabilities: self.desired_capabilities,
             buffer_size: self.buffer_size,
             credit_mode: self.credit_mode,
+            #[cfg(not(target_arch = "wasm32"))]
+            rate_limit: self.rate_limit,
+            #[cfg(not(target_arch = "wasm32"))]
+            deduplication: self.deduplication,
+            #[cfg(not(target_arch = "wasm32"))]
+            spooling: self.spooling,
+            #[cfg(not(target_arch = "wasm32"))]
+            disposition_batching: self.disposition_batching,
+            unsettled: self.unsettled,
+            available_mode: self.available_mode,
+            detach_timeout: self.detach_timeout,
+            #[cfg(not(target_arch = "wasm32"))]
+            attach_retry: self.attach_retry,
+            #[cfg(not(target_arch = "wasm32"))]
+            keep_alive: self.keep_alive,
+            #[cfg(not(target_arch = "wasm32"))]
+            slow_settlement_alert: self.slow_settlement_alert,
             properties: Default::default(),
 
             role: PhantomData,
@@ -230,8 +520,18 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
             target_state: self.target_state,
 
             auto_accept: self.auto_accept,
+            message_formats: self.message_formats,
+            empty_body_policy: self.empty_body_policy,
+            priority_ordering: self.priority_ordering,
+            byte_window: self.byte_window,
             verify_incoming_source: self.verify_incoming_source,
             verify_incoming_target: self.verify_incoming_target,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            compression: self.compression,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            decompress: self.decompress,
+            #[cfg(feature = "checksum")]
+            checksum: self.checksum,
         }
     }
 
@@ -245,10 +545,28 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
             target: self.target,
             initial_delivery_count: self.initial_delivery_count,
             max_message_size: self.max_message_size,
+            max_transfer_frame_size: self.max_transfer_frame_size,
             offered_capabilities: self.offered_capabilities,
             desired_capabilities: self.desired_capabilities,
             buffer_size: self.buffer_size,
             credit_mode: self.credit_mode,
+            #[cfg(not(target_arch = "wasm32"))]
+            rate_limit: self.rate_limit,
+            #[cfg(not(target_arch = "wasm32"))]
+            deduplication: self.deduplication,
+            #[cfg(not(target_arch = "wasm32"))]
+            spooling: self.spooling,
+            #[cfg(not(target_arch = "wasm32"))]
+            disposition_batching: self.disposition_batching,
+            unsettled: self.unsettled,
+            available_mode: self.available_mode,
+            detach_timeout: self.detach_timeout,
+            #[cfg(not(target_arch = "wasm32"))]
+            attach_retry: self.attach_retry,
+            #[cfg(not(target_arch = "wasm32"))]
+            keep_alive: self.keep_alive,
+            #[cfg(not(target_arch = "wasm32"))]
+            slow_settlement_alert: self.slow_settlement_alert,
             properties: Default::default(),
 
             role: PhantomData,
@@ -257,8 +575,18 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
             target_state: self.target_state,
 
             auto_accept: self.auto_accept,
+            message_formats: self.message_formats,
+            empty_body_policy: self.empty_body_policy,
+            priority_ordering: self.priority_ordering,
+            byte_window: self.byte_window,
             verify_incoming_source: self.verify_incoming_source,
             verify_incoming_target: self.verify_incoming_target,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            compression: self.compression,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            decompress: self.decompress,
+            #[cfg(feature = "checksum")]
+            checksum: self.checksum,
         }
     }
 
@@ -284,11 +612,29 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
             target: self.target,
             initial_delivery_count: self.initial_delivery_count,
             max_message_size: self.max_message_size,
+            max_transfer_frame_size: self.max_transfer_frame_size,
             offered_capabilities: self.offered_capabilities,
             desired_capabilities: self.desired_capabilities,
             properties: self.properties,
             buffer_size: self.buffer_size,
             credit_mode: self.credit_mode,
+            #[cfg(not(target_arch = "wasm32"))]
+            rate_limit: self.rate_limit,
+            #[cfg(not(target_arch = "wasm32"))]
+            deduplication: self.deduplication,
+            #[cfg(not(target_arch = "wasm32"))]
+            spooling: self.spooling,
+            #[cfg(not(target_arch = "wasm32"))]
+            disposition_batching: self.disposition_batching,
+            unsettled: self.unsettled,
+            available_mode: self.available_mode,
+            detach_timeout: self.detach_timeout,
+            #[cfg(not(target_arch = "wasm32"))]
+            attach_retry: self.attach_retry,
+            #[cfg(not(target_arch = "wasm32"))]
+            keep_alive: self.keep_alive,
+            #[cfg(not(target_arch = "wasm32"))]
+            slow_settlement_alert: self.slow_settlement_alert,
 
             role: self.role,
             name_state: self.name_state,
@@ -296,8 +642,18 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
             target_state: self.target_state,
 
             auto_accept: self.auto_accept,
+            message_formats: self.message_formats,
+            empty_body_policy: self.empty_body_policy,
+            priority_ordering: self.priority_ordering,
+            byte_window: self.byte_window,
             verify_incoming_source: self.verify_incoming_source,
             verify_incoming_target: self.verify_incoming_target,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            compression: self.compression,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            decompress: self.decompress,
+            #[cfg(feature = "checksum")]
+            checksum: self.checksum,
         }
     }
 
@@ -314,10 +670,28 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
             target: Some(target.into()), // setting target
             initial_delivery_count: self.initial_delivery_count,
             max_message_size: self.max_message_size,
+            max_transfer_frame_size: self.max_transfer_frame_size,
             offered_capabilities: self.offered_capabilities,
             desired_capabilities: self.desired_capabilities,
             buffer_size: self.buffer_size,
             credit_mode: self.credit_mode,
+            #[cfg(not(target_arch = "wasm32"))]
+            rate_limit: self.rate_limit,
+            #[cfg(not(target_arch = "wasm32"))]
+            deduplication: self.deduplication,
+            #[cfg(not(target_arch = "wasm32"))]
+            spooling: self.spooling,
+            #[cfg(not(target_arch = "wasm32"))]
+            disposition_batching: self.disposition_batching,
+            unsettled: self.unsettled,
+            available_mode: self.available_mode,
+            detach_timeout: self.detach_timeout,
+            #[cfg(not(target_arch = "wasm32"))]
+            attach_retry: self.attach_retry,
+            #[cfg(not(target_arch = "wasm32"))]
+            keep_alive: self.keep_alive,
+            #[cfg(not(target_arch = "wasm32"))]
+            slow_settlement_alert: self.slow_settlement_alert,
             properties: Default::default(),
 
             role: self.role,
@@ -326,8 +700,18 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
             target_state: PhantomData,
 
             auto_accept: self.auto_accept,
+            message_formats: self.message_formats,
+            empty_body_policy: self.empty_body_policy,
+            priority_ordering: self.priority_ordering,
+            byte_window: self.byte_window,
             verify_incoming_source: self.verify_incoming_source,
             verify_incoming_target: self.verify_incoming_target,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            compression: self.compression,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            decompress: self.decompress,
+            #[cfg(feature = "checksum")]
+            checksum: self.checksum,
         }
     }
 
@@ -345,10 +729,28 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
                 target: Some(coordinator), // setting target
                 initial_delivery_count: self.initial_delivery_count,
                 max_message_size: self.max_message_size,
+                max_transfer_frame_size: self.max_transfer_frame_size,
                 offered_capabilities: self.offered_capabilities,
                 desired_capabilities: self.desired_capabilities,
                 buffer_size: self.buffer_size,
                 credit_mode: self.credit_mode,
+                #[cfg(not(target_arch = "wasm32"))]
+                rate_limit: self.rate_limit,
+                #[cfg(not(target_arch = "wasm32"))]
+                deduplication: self.deduplication,
+                #[cfg(not(target_arch = "wasm32"))]
+                spooling: self.spooling,
+                #[cfg(not(target_arch = "wasm32"))]
+                disposition_batching: self.disposition_batching,
+                unsettled: self.unsettled,
+                available_mode: self.available_mode,
+                detach_timeout: self.detach_timeout,
+                #[cfg(not(target_arch = "wasm32"))]
+                attach_retry: self.attach_retry,
+                #[cfg(not(target_arch = "wasm32"))]
+                keep_alive: self.keep_alive,
+                #[cfg(not(target_arch = "wasm32"))]
+                slow_settlement_alert: self.slow_settlement_alert,
                 properties: Default::default(),
 
                 role: self.role,
@@ -357,8 +759,18 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
                 target_state: PhantomData,
 
                 auto_accept: self.auto_accept,
+                message_formats: self.message_formats,
+                empty_body_policy: self.empty_body_policy,
+                priority_ordering: self.priority_ordering,
+                byte_window: self.byte_window,
                 verify_incoming_source: self.verify_incoming_source,
                 verify_incoming_target: self.verify_incoming_target,
+                #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+                compression: self.compression,
+                #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+                decompress: self.decompress,
+                #[cfg(feature = "checksum")]
+                checksum: self.checksum,
             }
         }
     }
@@ -405,6 +817,29 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
         self
     }
 
+    /// Set how long `detach()` and `close()` wait for the remote Detach before detaching the link
+    /// locally
+    pub fn detach_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.detach_timeout = timeout.into();
+        self
+    }
+
+    /// Retry the attach with a backoff if the remote peer refuses it with one of the error
+    /// conditions of the policy
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn attach_retry(mut self, retry: AttachRetry) -> Self {
+        self.attach_retry = Some(retry);
+        self
+    }
+
+    /// Send a Flow with `echo` set every interval of the keep-alive and report the missing
+    /// replies to the subscribers of the link events
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn keep_alive(mut self, keep_alive: LinkKeepAlive) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
     /// Set whether the link should verify incoming source
     pub fn verify_incoming_source(mut self, verify: bool) -> Self {
         self.verify_incoming_source = verify;
@@ -417,6 +852,24 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
         self
     }
 
+    cfg_checksum! {
+        /// Write the SHA-256 of the bare message into the footer of outgoing messages if the link
+        /// is a sender, or verify it on incoming messages if the link is a receiver
+        pub fn checksum(mut self, checksum: Checksum) -> Self {
+            self.checksum = Some(checksum);
+            self
+        }
+    }
+
+    /// A receiver settle mode of `Second` has no effect on deliveries that are settled by the
+    /// sender before sending
+    fn has_incompatible_settle_modes(&self) -> bool {
+        matches!(
+            (&self.snd_settle_mode, &self.rcv_settle_mode),
+            (SenderSettleMode::Settled, ReceiverSettleMode::Second)
+        )
+    }
+
     pub(crate) fn create_link<C, M>(
         self,
         unsettled: ArcUnsettledMap<M>,
@@ -432,6 +885,7 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
         Link::<Role, T, C, M> {
             role: PhantomData,
             local_state,
+            state_watch: watch::channel(LinkState::Unattached).0,
             // state_code,
             name: self.name,
             output_handle: Some(output_handle),
@@ -441,6 +895,7 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
             source: self.source,
             target: self.target,
             max_message_size,
+            max_transfer_frame_size: self.max_transfer_frame_size.map(|size| size as usize),
             offered_capabilities: self.offered_capabilities,
             desired_capabilities: self.desired_capabilities,
 
@@ -451,11 +906,91 @@ impl<Role, T, NameState, SS, TS> Builder<Role, T, NameState, SS, TS> {
             unsettled,
             verify_incoming_source: self.verify_incoming_source,
             verify_incoming_target: self.verify_incoming_target,
+            #[cfg(feature = "debug-deliveries")]
+            delivery_traces: Default::default(),
+        }
+    }
+}
+
+impl<Role, NameState, SS, TS> Builder<Role, Target, NameState, SS, TS> {
+    /// Resumes a link that was suspended with `DetachedSender::to_suspended_link()` or
+    /// `DetachedReceiver::to_suspended_link()`, possibly in another process
+    ///
+    /// This sets the name, source and target of the link. The delivery count of a suspended
+    /// sender is used as the initial delivery count, and the unsettled map of a suspended
+    /// receiver is restored. See [`SuspendedLink`] for details.
+    pub fn resume_from(
+        self,
+        link: SuspendedLink,
+    ) -> Builder<Role, Target, WithName, WithSource, WithTarget> {
+        Builder {
+            name: link.name,
+            snd_settle_mode: self.snd_settle_mode,
+            rcv_settle_mode: self.rcv_settle_mode,
+            source: link.source,
+            target: link.target,
+            initial_delivery_count: link.delivery_count,
+            max_message_size: self.max_message_size,
+            max_transfer_frame_size: self.max_transfer_frame_size,
+            offered_capabilities: self.offered_capabilities,
+            desired_capabilities: self.desired_capabilities,
+            properties: self.properties,
+            buffer_size: self.buffer_size,
+            credit_mode: self.credit_mode,
+            #[cfg(not(target_arch = "wasm32"))]
+            rate_limit: self.rate_limit,
+            #[cfg(not(target_arch = "wasm32"))]
+            deduplication: self.deduplication,
+            #[cfg(not(target_arch = "wasm32"))]
+            spooling: self.spooling,
+            #[cfg(not(target_arch = "wasm32"))]
+            disposition_batching: self.disposition_batching,
+            unsettled: Some(link.unsettled),
+            available_mode: self.available_mode,
+            detach_timeout: self.detach_timeout,
+            #[cfg(not(target_arch = "wasm32"))]
+            attach_retry: self.attach_retry,
+            #[cfg(not(target_arch = "wasm32"))]
+            keep_alive: self.keep_alive,
+            #[cfg(not(target_arch = "wasm32"))]
+            slow_settlement_alert: self.slow_settlement_alert,
+
+            role: self.role,
+            name_state: PhantomData,
+            source_state: PhantomData,
+            target_state: PhantomData,
+
+            auto_accept: self.auto_accept,
+            message_formats: self.message_formats,
+            empty_body_policy: self.empty_body_policy,
+            priority_ordering: self.priority_ordering,
+            byte_window: self.byte_window,
+            verify_incoming_source: self.verify_incoming_source,
+            verify_incoming_target: self.verify_incoming_target,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            compression: self.compression,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            decompress: self.decompress,
+            #[cfg(feature = "checksum")]
+            checksum: self.checksum,
         }
     }
 }
 
 impl<T, NameState, SS, TS> Builder<role::SenderMarker, T, NameState, SS, TS> {
+    /// Checks that the configuration is coherent
+    ///
+    /// This is performed by `attach` before the link is allocated on the session.
+    pub fn validate(&self) -> Result<(), SenderAttachError> {
+        if self.has_incompatible_settle_modes() {
+            return Err(SenderAttachError::IncompatibleSettleModes);
+        }
+        if self.buffer_size == 0 {
+            return Err(SenderAttachError::ZeroBufferSize);
+        }
+        Ok(())
+    }
+
     /// This MUST NOT be null if role is sender,
     /// and it is ignored if the role is receiver.
     /// See subsection 2.6.7.
@@ -463,9 +998,53 @@ impl<T, NameState, SS, TS> Builder<role::SenderMarker, T, NameState, SS, TS> {
         self.initial_delivery_count = count;
         self
     }
+
+    /// Caps the size of the transfer frames emitted by the link below the connection negotiated
+    /// max frame size, so that a link sending large messages does not block the other links on
+    /// the same connection until the whole message is written.
+    ///
+    /// The value cannot be smaller than [`MIN_MAX_FRAME_SIZE`]
+    pub fn max_transfer_frame_size(mut self, max_size: u32) -> Self {
+        self.max_transfer_frame_size = Some(std::cmp::max(MIN_MAX_FRAME_SIZE as u32, max_size));
+        self
+    }
+
+    /// Set how the `available` field advertised in the Flow frames is maintained
+    pub fn available_mode(mut self, available_mode: AvailableMode) -> Self {
+        self.available_mode = available_mode;
+        self
+    }
+
+    /// Report the deliveries whose settlement takes longer than the threshold of the alert
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn slow_settlement_alert(mut self, alert: SlowSettlementAlert) -> Self {
+        self.slow_settlement_alert = Some(alert);
+        self
+    }
+
+    cfg_compression! {
+        /// Compress the `Data` body sections of outgoing messages
+        pub fn compression(mut self, compression: Compression) -> Self {
+            self.compression = Some(compression);
+            self
+        }
+    }
 }
 
 impl<T, NameState, SS, TS> Builder<role::ReceiverMarker, T, NameState, SS, TS> {
+    /// Checks that the configuration is coherent
+    ///
+    /// This is performed by `attach` before the link is allocated on the session.
+    pub fn validate(&self) -> Result<(), ReceiverAttachError> {
+        if self.has_incompatible_settle_modes() {
+            return Err(ReceiverAttachError::IncompatibleSettleModes);
+        }
+        if self.buffer_size == 0 {
+            return Err(ReceiverAttachError::ZeroBufferSize);
+        }
+        Ok(())
+    }
+
     /// Set the credit mode for the receiver.
     ///
     /// If the credit mode is `Auto`, the receiver will automatically send flow frames when the
@@ -475,6 +1054,44 @@ impl<T, NameState, SS, TS> Builder<role::ReceiverMarker, T, NameState, SS, TS> {
         self.credit_mode = credit_mode;
         self
     }
+
+    /// Set the rate limit on the incoming messages
+    ///
+    /// The rate is enforced by replenishing the credit in `CreditMode::Auto` no faster than the
+    /// limit allows, so the messages are held back by the remote peer instead of being buffered
+    /// locally. See [`RateLimit`] for details.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Drop the redelivered messages whose `message-id` is found in a window of the recently
+    /// received message ids. The duplicates are accepted without being returned from `recv()`.
+    /// See [`Deduplication`] for details.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn deduplication(mut self, deduplication: Deduplication) -> Self {
+        self.deduplication = Some(deduplication);
+        self
+    }
+
+    /// Spool the multi-frame deliveries that exceed a size threshold to a temporary file instead
+    /// of buffering them in memory. The spooled deliveries are received with
+    /// `Receiver::recv_spooled()`. See [`Spooling`] for details.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn spooling(mut self, spooling: Spooling) -> Self {
+        self.spooling = Some(spooling);
+        self
+    }
+
+    /// Hold back the accepts, including the ones of `auto_accept`, and send the consecutive
+    /// delivery ids as a range in a single disposition. See [`DispositionBatching`] for when the
+    /// accepts are sent.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn disposition_batching(mut self, disposition_batching: DispositionBatching) -> Self {
+        self.disposition_batching = Some(disposition_batching);
+        self
+    }
 }
 
 impl Builder<role::SenderMarker, Target, WithName, WithSource, WithTarget> {
@@ -529,19 +1146,67 @@ where
     }
 
     async fn attach_inner<R>(
+        self,
+        session: &mut SessionHandle<R>,
+    ) -> Result<SenderInner<SenderLink<T>>, SenderAttachError> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(retry) = self.attach_retry.clone() {
+            let mut attempt = 1;
+            loop {
+                let result = self.clone().attach_once(session).await;
+                match &result {
+                    Err(SenderAttachError::RemoteClosedWithError(error))
+                        if retry.wait_before_retry(attempt, error).await =>
+                    {
+                        attempt += 1
+                    }
+                    _ => return result,
+                }
+            }
+        }
+        self.attach_once(session).await
+    }
+
+    async fn attach_once<R>(
         mut self,
         session: &mut SessionHandle<R>,
     ) -> Result<SenderInner<SenderLink<T>>, SenderAttachError> {
+        self.validate()?;
         let buffer_size = self.buffer_size;
+        let available_mode = self.available_mode;
+        let detach_timeout = self.detach_timeout;
+        #[cfg(not(target_arch = "wasm32"))]
+        let keep_alive = self.keep_alive;
+        #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+        let compression = self.compression.take();
+        #[cfg(feature = "checksum")]
+        let checksum = self.checksum.take();
         let (incoming_tx, mut incoming_rx) = mpsc::channel::<LinkIncomingItem>(self.buffer_size);
         let outgoing = session.outgoing.clone();
         let (producer, consumer) = self.create_flow_state_containers();
         let unsettled = Arc::new(RwLock::new(None));
 
-        let link_relay = LinkRelay::new_sender(incoming_tx, producer, unsettled.clone());
+        #[cfg(not(target_arch = "wasm32"))]
+        let latency: ArcSettlementLatency =
+            Arc::new(SettlementLatency::new(self.slow_settlement_alert.take()));
+        let link_relay = LinkRelay::new_sender(
+            incoming_tx,
+            producer,
+            unsettled.clone(),
+            #[cfg(not(target_arch = "wasm32"))]
+            latency.clone(),
+        );
+        #[cfg(feature = "debug-deliveries")]
+        let delivery_traces = crate::link::delivery_trace::ArcDeliveryTraces::default();
+        #[cfg(feature = "debug-deliveries")]
+        let link_relay = link_relay.with_delivery_traces(delivery_traces.clone());
         let output_handle =
             session::allocate_link(&session.control, self.name.clone(), link_relay).await?;
         let mut link = self.create_link(unsettled, output_handle, consumer);
+        #[cfg(feature = "debug-deliveries")]
+        {
+            link.delivery_traces = delivery_traces;
+        }
 
         match link
             .exchange_attach(&session.outgoing, &mut incoming_rx, &session.control, false)
@@ -572,12 +1237,37 @@ where
         }
 
         // Attach completed, return Sender
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut events = LinkEvents::default();
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(keep_alive) = keep_alive {
+            events.spawn_keep_alive(
+                keep_alive,
+                link.flow_state.state().clone(),
+                link.output_handle.clone(),
+                outgoing.clone(),
+                link.watch_state(),
+                session.clock.clone(),
+            );
+        }
         let inner = SenderInner {
             link,
             buffer_size,
             session: session.control.clone(),
             outgoing,
             incoming: incoming_rx,
+            available_mode,
+            detach_timeout,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            compression,
+            #[cfg(feature = "checksum")]
+            checksum,
+            #[cfg(not(target_arch = "wasm32"))]
+            latency,
+            #[cfg(not(target_arch = "wasm32"))]
+            events,
+            #[cfg(not(target_arch = "wasm32"))]
+            clock: session.clock.clone(),
             // marker: PhantomData,
         };
         Ok(inner)
@@ -632,17 +1322,75 @@ where
     }
 
     async fn attach_inner<R>(
+        self,
+        session: &mut SessionHandle<R>,
+    ) -> Result<ReceiverInner<ReceiverLink<T>>, ReceiverAttachError> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(retry) = self.attach_retry.clone() {
+            let mut attempt = 1;
+            loop {
+                let result = self.clone().attach_once(session).await;
+                match &result {
+                    Err(ReceiverAttachError::RemoteClosedWithError(error))
+                        if retry.wait_before_retry(attempt, error).await =>
+                    {
+                        attempt += 1
+                    }
+                    _ => return result,
+                }
+            }
+        }
+        self.attach_once(session).await
+    }
+
+    async fn attach_once<R>(
         mut self,
         session: &mut SessionHandle<R>,
     ) -> Result<ReceiverInner<ReceiverLink<T>>, ReceiverAttachError> {
+        self.validate()?;
         // TODO: how to avoid clone?
         let buffer_size = self.buffer_size;
         let credit_mode = self.credit_mode.clone();
+        let byte_window = self
+            .byte_window
+            .map(|window| Box::new(ByteWindow::new(window)));
+        if let Some(window) = &byte_window {
+            // The initial limit is carried by the Attach
+            self.properties
+                .get_or_insert_with(Fields::new)
+                .insert(Symbol::from(BYTE_LIMIT_KEY), Value::Ulong(window.limit()));
+        }
         let (incoming_tx, mut incoming_rx) = mpsc::channel::<LinkIncomingItem>(self.buffer_size);
         let outgoing = session.outgoing.clone();
         let (relay_flow_state, flow_state) = self.create_flow_state_containers();
-        let unsettled = Arc::new(RwLock::new(None));
+        let restored = self.unsettled.take().filter(|map| !map.is_empty());
+        let is_resuming = restored.is_some();
+        let unsettled = Arc::new(RwLock::new(restored));
         let auto_accept = self.auto_accept;
+        let detach_timeout = self.detach_timeout;
+        #[cfg(not(target_arch = "wasm32"))]
+        let keep_alive = self.keep_alive;
+        let message_formats = std::mem::take(&mut self.message_formats);
+        let empty_body_policy = self.empty_body_policy;
+        let priority_ordering = self.priority_ordering;
+        #[cfg(not(target_arch = "wasm32"))]
+        let rate_limiter = self
+            .rate_limit
+            .map(|limit| Box::new(RateLimiter::new(limit, session.clock.now())));
+        #[cfg(not(target_arch = "wasm32"))]
+        let spooling = self.spooling.take();
+        #[cfg(not(target_arch = "wasm32"))]
+        let disposition_batcher = self
+            .disposition_batching
+            .map(|config| Box::new(parking_lot::Mutex::new(DispositionBatcher::new(config))));
+        #[cfg(not(target_arch = "wasm32"))]
+        let deduplicator = self
+            .deduplication
+            .map(|config| Box::new(Deduplicator::new(config)));
+        #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+        let decompress = self.decompress;
+        #[cfg(feature = "checksum")]
+        let checksum = self.checksum.take();
 
         let link_relay = LinkRelay::new_receiver(
             incoming_tx,
@@ -650,16 +1398,26 @@ where
             unsettled.clone(),
             self.rcv_settle_mode.clone(),
         );
+        #[cfg(feature = "debug-deliveries")]
+        let delivery_traces = crate::link::delivery_trace::ArcDeliveryTraces::default();
+        #[cfg(feature = "debug-deliveries")]
+        let link_relay = link_relay.with_delivery_traces(delivery_traces.clone());
         // Create Link in Session
         // Any error here will be on the Session level and thus it should immediately return with an error
         let output_handle =
             session::allocate_link(&session.control, self.name.clone(), link_relay).await?;
         let mut link = self.create_link(unsettled, output_handle, flow_state);
+        #[cfg(feature = "debug-deliveries")]
+        {
+            link.delivery_traces = delivery_traces;
+        }
 
         match link
             .exchange_attach(&session.outgoing, &mut incoming_rx, &session.control, false)
             .await
         {
+            // The remote sender resumes or settles the deliveries of a restored unsettled map
+            Ok(_) if is_resuming => {}
             Ok(outcome) => outcome.complete_or(ReceiverAttachError::IllegalState)?,
             Err(attach_error) => {
                 let err = link
@@ -674,6 +1432,19 @@ where
             }
         }
 
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut events = LinkEvents::default();
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(keep_alive) = keep_alive {
+            events.spawn_keep_alive(
+                keep_alive,
+                link.flow_state.clone(),
+                link.output_handle.clone(),
+                outgoing.clone(),
+                link.watch_state(),
+                session.clock.clone(),
+            );
+        }
         let mut inner = ReceiverInner {
             link,
             buffer_size,
@@ -684,6 +1455,37 @@ where
             outgoing,
             incoming: incoming_rx,
             incomplete_transfer: None,
+            detach_timeout,
+            #[cfg(not(target_arch = "wasm32"))]
+            rate_limiter,
+            #[cfg(not(target_arch = "wasm32"))]
+            deduplicator,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            decompress,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            decompressed_size_exceeded: false,
+            #[cfg(feature = "checksum")]
+            checksum,
+            #[cfg(feature = "checksum")]
+            checksum_mismatch: false,
+            message_formats,
+            empty_body_policy,
+            delivery_tag_violations: 0,
+            #[cfg(not(target_arch = "wasm32"))]
+            unsettled_arrivals: Default::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            spooling,
+            #[cfg(not(target_arch = "wasm32"))]
+            disposition_batcher,
+            priority_ordering,
+            prefetched: Default::default(),
+            byte_window,
+            #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+            transfer_authorization: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            events,
+            #[cfg(not(target_arch = "wasm32"))]
+            clock: session.clock.clone(),
         };
 
         if let CreditMode::Auto(credit) = inner.credit_mode {
@@ -705,7 +1507,61 @@ cfg_transaction! {
 
             self.attach_inner(session).await.map(|inner| Controller {
                 inner: Mutex::new(inner),
+                in_doubt: Default::default(),
             })
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::definitions::{ReceiverSettleMode, SenderSettleMode};
+
+    use crate::{
+        link::{ReceiverAttachError, SenderAttachError},
+        Receiver, Sender,
+    };
+
+    #[test]
+    fn validate_rejects_incompatible_settle_modes() {
+        let builder = Sender::builder()
+            .sender_settle_mode(SenderSettleMode::Settled)
+            .receiver_settle_mode(ReceiverSettleMode::Second);
+        assert!(matches!(
+            builder.validate(),
+            Err(SenderAttachError::IncompatibleSettleModes)
+        ));
+
+        let builder = Receiver::builder()
+            .sender_settle_mode(SenderSettleMode::Settled)
+            .receiver_settle_mode(ReceiverSettleMode::Second);
+        assert!(matches!(
+            builder.validate(),
+            Err(ReceiverAttachError::IncompatibleSettleModes)
+        ));
+
+        let builder = Receiver::builder()
+            .sender_settle_mode(SenderSettleMode::Unsettled)
+            .receiver_settle_mode(ReceiverSettleMode::Second);
+        assert!(builder.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_zero_buffer_size() {
+        assert!(Sender::builder().validate().is_ok());
+
+        let mut builder = Sender::builder();
+        builder.buffer_size = 0;
+        assert!(matches!(
+            builder.validate(),
+            Err(SenderAttachError::ZeroBufferSize)
+        ));
+
+        let mut builder = Receiver::builder();
+        builder.buffer_size = 0;
+        assert!(matches!(
+            builder.validate(),
+            Err(ReceiverAttachError::ZeroBufferSize)
+        ));
+    }
+}
@@ -0,0 +1,170 @@
+//! Flow control by the size of the payloads
+//!
+//! The link credit only limits the number of messages, which does not bound the memory used by a
+//! receiver whose messages vary from a few bytes to hundreds of megabytes. A receiver that is
+//! built with [`Builder::byte_window()`](super::builder::Builder::byte_window) additionally
+//! grants a limit on the total payload bytes of the link, which is carried in the link properties
+//! (and thus in the Attach and every Flow) under the [`BYTE_LIMIT_KEY`] key as a `ulong`.
+//!
+//! The limit is the total number of payload bytes that the sender may have sent on the link, and
+//! a sender only starts a new delivery while the bytes it has sent are below the limit. A delivery
+//! may therefore exceed the limit by its own size, so that a message larger than the window is
+//! never blocked forever. The receiver raises the limit by the window once half of the window has
+//! been received.
+//!
+//! Senders of this crate follow the limit whenever the remote receiver grants one, and other
+//! implementations simply ignore the property.
+
+use fe2o3_amqp_types::{definitions::Fields, primitives::Value};
+
+/// Key of the byte limit in the link properties
+pub const BYTE_LIMIT_KEY: &str = "byte-limit";
+
+/// Gets the byte limit from the properties of an Attach or a Flow
+pub(crate) fn byte_limit(properties: Option<&Fields>) -> Option<u64> {
+    match properties?.get(BYTE_LIMIT_KEY)? {
+        Value::Ulong(limit) => Some(*limit),
+        Value::Uint(limit) => Some(*limit as u64),
+        Value::Long(limit) => u64::try_from(*limit).ok(),
+        _ => None,
+    }
+}
+
+/// Byte limit granted by a receiver
+#[derive(Debug, Clone)]
+pub(crate) struct ByteWindow {
+    window: u64,
+    received: u64,
+    limit: u64,
+}
+
+impl ByteWindow {
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            received: 0,
+            limit: window,
+        }
+    }
+
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Records the payload of an incoming transfer. A new limit is returned if it should be
+    /// granted to the sender
+    pub fn on_received(&mut self, len: u64) -> Option<u64> {
+        self.received = self.received.saturating_add(len);
+        if self.limit.saturating_sub(self.received) > self.window / 2 {
+            return None;
+        }
+        self.limit = self.received.saturating_add(self.window);
+        Some(self.limit)
+    }
+}
+
+/// Byte limit granted to a sender by the remote receiver
+#[derive(Debug, Default)]
+pub(crate) struct ByteCredit {
+    limit: Option<u64>,
+    sent: u64,
+}
+
+impl ByteCredit {
+    pub fn set_limit(&mut self, limit: u64) {
+        self.limit = Some(limit);
+    }
+
+    /// The number of bytes that may still be sent, or `None` if the receiver does not limit
+    /// the bytes
+    pub fn remaining(&self) -> Option<u64> {
+        self.limit.map(|limit| limit.saturating_sub(self.sent))
+    }
+
+    /// Whether a new delivery may be started
+    pub fn is_available(&self) -> bool {
+        self.remaining()
+            .map(|remaining| remaining > 0)
+            .unwrap_or(true)
+    }
+
+    /// The number of deliveries with the given payload sizes that may be started one after
+    /// another, each while the bytes sent before it are below the limit
+    pub fn deliveries_available(&self, payload_lens: &[usize]) -> usize {
+        let mut remaining = match self.remaining() {
+            Some(remaining) => remaining,
+            None => return payload_lens.len(),
+        };
+        let mut count = 0;
+        for len in payload_lens {
+            if remaining == 0 {
+                break;
+            }
+            remaining = remaining.saturating_sub(*len as u64);
+            count += 1;
+        }
+        count
+    }
+
+    pub fn on_sent(&mut self, len: u64) {
+        self.sent = self.sent.saturating_add(len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::{definitions::Fields, primitives::Value};
+
+    use super::{byte_limit, ByteCredit, ByteWindow, BYTE_LIMIT_KEY};
+
+    #[test]
+    fn limit_is_raised_once_half_the_window_is_received() {
+        let mut window = ByteWindow::new(100);
+        assert_eq!(window.limit(), 100);
+        assert_eq!(window.on_received(30), None);
+        assert_eq!(window.on_received(20), Some(150));
+        // A delivery larger than the window
+        assert_eq!(window.on_received(500), Some(650));
+    }
+
+    #[test]
+    fn sender_stops_at_the_limit() {
+        let mut credit = ByteCredit::default();
+        assert!(credit.is_available());
+        credit.on_sent(1000);
+        assert_eq!(credit.remaining(), None);
+
+        credit.set_limit(1500);
+        assert!(credit.is_available());
+        credit.on_sent(600);
+        assert_eq!(credit.remaining(), Some(0));
+        assert!(!credit.is_available());
+    }
+
+    #[test]
+    fn batch_is_cut_once_the_limit_is_reached() {
+        let mut credit = ByteCredit::default();
+        assert_eq!(credit.deliveries_available(&[1000, 1000, 1000]), 3);
+
+        // The second delivery starts below the limit and exceeds it by its own size
+        credit.set_limit(1500);
+        assert_eq!(credit.deliveries_available(&[1000, 1000, 1000]), 2);
+        assert_eq!(credit.deliveries_available(&[500, 1000, 10]), 2);
+        credit.on_sent(1500);
+        assert_eq!(credit.deliveries_available(&[10]), 0);
+    }
+
+    #[test]
+    fn byte_limit_is_read_from_properties() {
+        let mut properties = Fields::new();
+        assert_eq!(byte_limit(Some(&properties)), None);
+        properties.insert(BYTE_LIMIT_KEY.into(), Value::Ulong(42));
+        assert_eq!(byte_limit(Some(&properties)), Some(42));
+        properties.insert(BYTE_LIMIT_KEY.into(), Value::Long(-1));
+        assert_eq!(byte_limit(Some(&properties)), None);
+    }
+}
@@ -0,0 +1,194 @@
+//! Integrity verification of messages with a SHA-256 of the bare message in the footer
+//!
+//! A [`Sender`](crate::Sender) configured with [`Checksum`] writes the SHA-256 of the bare
+//! message (the `properties`, `application-properties` and body sections) into the footer of
+//! every outgoing message under [`Checksum::key`]. A [`Receiver`](crate::Receiver) configured
+//! with the same key recomputes the hash of every incoming message that carries one and rejects
+//! the message with `amqp:decode-error` if the hashes do not match. Messages without the key are
+//! delivered unchecked.
+//!
+//! The footer is the only section that intermediaries may change besides the header and the
+//! annotations, so the hash survives brokers that add annotations. The hash is computed over the
+//! sections as they are encoded by this crate; an intermediary that re-encodes the bare message
+//! with different encodings of the same values makes the check fail.
+//!
+//! Messages that are sent pre-encoded (eg. with
+//! [`Sender::send_encoded()`](crate::Sender::send_encoded)) are not stamped.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fe2o3_amqp::link::checksum::Checksum;
+//!
+//! let sender = Sender::builder()
+//!     .name("rust-sender-link-1")
+//!     .target("q1")
+//!     .checksum(Checksum::new())
+//!     .attach(&mut session)
+//!     .await
+//!     .unwrap();
+//! ```
+
+use bytes::{BufMut, Bytes, BytesMut};
+use fe2o3_amqp_types::{
+    messaging::{
+        annotations::{AnnotationKey, OwnedKey},
+        message::__private::{Deserializable, Serializable},
+        Annotations, Body, Footer, Message,
+    },
+    primitives::{Binary, Symbol},
+};
+use serde::Serialize;
+use serde_amqp::{ser::Serializer, Value};
+use sha2::{Digest, Sha256};
+
+/// Default key of the hash in the footer
+pub const DEFAULT_CHECKSUM_KEY: &str = "x-opt-sha256";
+
+/// SHA-256 of the bare message carried in the footer
+///
+/// | Field | Default Value |
+/// |-------|---------------|
+/// |`key`| [`DEFAULT_CHECKSUM_KEY`] |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    /// The key of the hash in the footer
+    pub key: Symbol,
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Self {
+            key: Symbol::from(DEFAULT_CHECKSUM_KEY),
+        }
+    }
+}
+
+/// The result of checking the hash of an incoming message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Verification {
+    /// The message does not carry a hash or cannot be decoded
+    Unchecked,
+
+    /// The hash matches the bare message
+    Valid,
+
+    /// The hash does not match the bare message
+    Mismatch,
+}
+
+impl Checksum {
+    /// Creates a new [`Checksum`] with the [`DEFAULT_CHECKSUM_KEY`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the key of the hash in the footer
+    pub fn key(mut self, key: impl Into<Symbol>) -> Self {
+        self.key = key.into();
+        self
+    }
+
+    /// Writes the hash of the bare message into the footer of an encoded message
+    pub(crate) fn stamp_payload(&self, payload: Bytes) -> Result<Bytes, serde_amqp::Error> {
+        let Deserializable(mut message): Deserializable<Message<Body<Value>>> =
+            serde_amqp::from_slice(&payload)?;
+        let digest = bare_message_digest(&message)?;
+        message
+            .footer
+            .get_or_insert_with(|| Footer(Annotations::new()))
+            .0
+            .insert(OwnedKey::Symbol(self.key.clone()), Value::Binary(digest));
+
+        let mut payload = BytesMut::new();
+        let mut serializer = Serializer::from((&mut payload).writer());
+        Serializable(message).serialize(&mut serializer)?;
+        Ok(payload.freeze())
+    }
+
+    /// Checks the hash in the footer of an encoded message
+    pub(crate) fn verify_payload(&self, payload: &[u8]) -> Verification {
+        let message = match serde_amqp::from_slice(payload) {
+            Ok(Deserializable::<Message<Body<Value>>>(message)) => message,
+            // The decode error is reported when the message is delivered
+            Err(_) => return Verification::Unchecked,
+        };
+        let expected = match message
+            .footer
+            .as_ref()
+            .and_then(|footer| footer.0.get(&self.key.as_str() as &dyn AnnotationKey))
+        {
+            Some(Value::Binary(expected)) => expected,
+            Some(_) => return Verification::Mismatch,
+            None => return Verification::Unchecked,
+        };
+        match bare_message_digest(&message) {
+            Ok(digest) if digest == *expected => Verification::Valid,
+            _ => Verification::Mismatch,
+        }
+    }
+}
+
+fn bare_message_digest(message: &Message<Body<Value>>) -> Result<Binary, serde_amqp::Error> {
+    let mut hasher = Sha256::new();
+    if let Some(properties) = &message.properties {
+        hasher.update(serde_amqp::to_vec(properties)?);
+    }
+    if let Some(application_properties) = &message.application_properties {
+        hasher.update(serde_amqp::to_vec(application_properties)?);
+    }
+    // A message without a body section is encoded without one
+    if !message.body.is_empty() {
+        hasher.update(serde_amqp::to_vec(&message.body)?);
+    }
+    Ok(Binary::from(hasher.finalize().to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::messaging::{
+        message::__private::{Deserializable, Serializable},
+        AmqpValue, Body, Message, MessageAnnotations, Properties,
+    };
+    use serde_amqp::Value;
+
+    use super::{Checksum, Verification};
+
+    fn encode<B: fe2o3_amqp_types::messaging::SerializableBody>(message: Message<B>) -> Vec<u8> {
+        serde_amqp::to_vec(&Serializable(message)).unwrap()
+    }
+
+    #[test]
+    fn stamped_message_is_verified() {
+        let checksum = Checksum::new();
+        let message = Message::builder()
+            .properties(Properties::builder().subject(String::from("order")).build())
+            .value("hello")
+            .build();
+        let stamped = checksum.stamp_payload(encode(message).into()).unwrap();
+        assert_eq!(checksum.verify_payload(&stamped), Verification::Valid);
+
+        // Intermediaries may add annotations
+        let Deserializable(mut message): Deserializable<Message<Body<Value>>> =
+            serde_amqp::from_slice(&stamped).unwrap();
+        message.message_annotations =
+            Some(MessageAnnotations::builder().insert("x-opt-hop", 1).build());
+        assert_eq!(
+            checksum.verify_payload(&encode(message.clone())),
+            Verification::Valid
+        );
+
+        message.body = Body::Value(AmqpValue(Value::String(String::from("hellp"))));
+        assert_eq!(
+            checksum.verify_payload(&encode(message)),
+            Verification::Mismatch
+        );
+
+        let unstamped = encode(Message::builder().value("hello").build());
+        assert_eq!(checksum.verify_payload(&unstamped), Verification::Unchecked);
+        assert_eq!(
+            Checksum::new().key("x-other").verify_payload(&stamped),
+            Verification::Unchecked
+        );
+    }
+}
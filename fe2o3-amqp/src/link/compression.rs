@@ -0,0 +1,458 @@
+//! Transparent compression of message bodies
+//!
+//! A [`Sender`](crate::Sender) can be configured to compress the [`Data`] body sections of outgoing
+//! messages with [`Compression`], and a [`Receiver`](crate::Receiver) can be configured to
+//! transparently decompress the [`Data`] body sections of incoming messages. The encoding is
+//! carried by the `content-encoding` field of the message properties.
+//!
+//! The supported encodings depend on the enabled features
+//!
+//! | Feature | Encoding |
+//! |---------|----------|
+//! |`"gzip"`| `"gzip"` |
+//! |`"deflate"`| `"deflate"` |
+//! |`"zstd"`| `"zstd"` |
+
+use std::io::{self, Read, Write};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use fe2o3_amqp_types::{
+    messaging::{
+        message::__private::{Deserializable, Serializable},
+        Batch, Body, Data, Message, Properties, SerializableBody,
+    },
+    primitives::{Binary, Symbol},
+};
+use serde::Serialize;
+use serde_amqp::{format_code::EncodingCodes, ser::Serializer, Value};
+
+use super::receiver_link::{DATA_CODE, DESCRIBED_TYPE, PROP_CODE, SMALL_ULONG_TYPE, ULONG_TYPE};
+
+/// Messages whose encoded size is smaller than this value (in bytes) are not compressed by
+/// default
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+/// A `content-encoding` that can be applied to the [`Data`] body sections of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentEncoding {
+    /// `"gzip"`
+    #[cfg_attr(docsrs, doc(cfg(feature = "gzip")))]
+    #[cfg(feature = "gzip")]
+    Gzip,
+
+    /// `"deflate"`
+    #[cfg_attr(docsrs, doc(cfg(feature = "deflate")))]
+    #[cfg(feature = "deflate")]
+    Deflate,
+
+    /// `"zstd"`
+    #[cfg_attr(docsrs, doc(cfg(feature = "zstd")))]
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl ContentEncoding {
+    /// The value of the `content-encoding` field
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "gzip")]
+            ContentEncoding::Gzip => "gzip",
+            #[cfg(feature = "deflate")]
+            ContentEncoding::Deflate => "deflate",
+            #[cfg(feature = "zstd")]
+            ContentEncoding::Zstd => "zstd",
+        }
+    }
+
+    /// Find the encoding that corresponds to the value of a `content-encoding` field. `None` is
+    /// returned if the encoding is not recognized or not enabled
+    pub fn from_content_encoding(value: &str) -> Option<Self> {
+        match value {
+            #[cfg(feature = "gzip")]
+            "gzip" => Some(ContentEncoding::Gzip),
+            #[cfg(feature = "deflate")]
+            "deflate" => Some(ContentEncoding::Deflate),
+            #[cfg(feature = "zstd")]
+            "zstd" => Some(ContentEncoding::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Compress the bytes with this encoding
+    pub fn encode(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            ContentEncoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                io::Write::write_all(&mut encoder, bytes)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "deflate")]
+            ContentEncoding::Deflate => {
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                io::Write::write_all(&mut encoder, bytes)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            ContentEncoding::Zstd => libzstd::encode_all(bytes, 0),
+        }
+    }
+
+    /// Decompress the bytes with this encoding
+    pub fn decode(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.decoder(bytes)?.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Decompress the bytes with this encoding, but stop once more than `limit` bytes are
+    /// decompressed. `None` is returned if the decompressed bytes exceed the `limit`
+    pub fn decode_with_limit(&self, bytes: &[u8], limit: u64) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = Vec::new();
+        self.decoder(bytes)?
+            .take(limit.saturating_add(1))
+            .read_to_end(&mut buf)?;
+        if buf.len() as u64 > limit {
+            return Ok(None);
+        }
+        Ok(Some(buf))
+    }
+
+    fn decoder<'a>(&self, bytes: &'a [u8]) -> io::Result<Box<dyn Read + 'a>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            ContentEncoding::Gzip => Ok(Box::new(flate2::read::GzDecoder::new(bytes))),
+            #[cfg(feature = "deflate")]
+            ContentEncoding::Deflate => Ok(Box::new(flate2::read::DeflateDecoder::new(bytes))),
+            #[cfg(feature = "zstd")]
+            ContentEncoding::Zstd => Ok(Box::new(libzstd::stream::read::Decoder::new(bytes)?)),
+        }
+    }
+}
+
+impl std::fmt::Display for ContentEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Compression of the [`Data`] body sections of outgoing messages
+///
+/// Only messages with a [`Data`] body and no `content-encoding` are compressed, and only if the
+/// encoded message is not smaller than the `threshold`. All other messages are sent unchanged.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use fe2o3_amqp::link::compression::{Compression, ContentEncoding};
+///
+/// let sender = Sender::builder()
+///     .name("rust-sender-link-1")
+///     .target("q1")
+///     .compression(Compression::new(ContentEncoding::Gzip).threshold(4 * 1024))
+///     .attach(&mut session)
+///     .await
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compression {
+    /// The encoding that is applied to the body
+    pub encoding: ContentEncoding,
+
+    /// The minimum size (in bytes) of an encoded message to be compressed
+    pub threshold: usize,
+}
+
+impl Compression {
+    /// Creates a new [`Compression`] with the [`DEFAULT_COMPRESSION_THRESHOLD`]
+    pub fn new(encoding: ContentEncoding) -> Self {
+        Self {
+            encoding,
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        }
+    }
+
+    /// Set the minimum size (in bytes) of an encoded message to be compressed
+    pub fn threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Compress the `Data` body sections of an encoded message
+    pub(crate) fn compress_payload(&self, payload: Bytes) -> Result<Bytes, serde_amqp::Error> {
+        if payload.len() < self.threshold {
+            return Ok(payload);
+        }
+
+        let mut message = match decode_data_message(&payload) {
+            Some(message) => message,
+            None => return Ok(payload),
+        };
+        let properties = message.properties.get_or_insert_with(Properties::default);
+        if properties.content_encoding.is_some() {
+            return Ok(payload);
+        }
+        properties.content_encoding = Some(Symbol::from(self.encoding.as_str()));
+
+        let body = map_data_sections(message.body, |bytes| self.encoding.encode(bytes))
+            .map_err(serde_amqp::Error::Io)?;
+        message.body = body;
+        encode_message(message)
+    }
+}
+
+/// Errors that fail the decompression of a message
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum DecompressError {
+    /// The message cannot be decompressed
+    #[error(transparent)]
+    Decode(#[from] serde_amqp::Error),
+
+    /// The decompressed `Data` body sections exceed the limit
+    #[error("The decompressed message exceeds the limit")]
+    SizeExceeded,
+}
+
+/// Decompress the `Data` body sections of an encoded message if the `content-encoding` is
+/// recognized. The `content-encoding` is removed from the decompressed message.
+///
+/// Only the properties and the `Data` body sections are decoded, the bytes of all other sections
+/// are copied as they are.
+///
+/// The decompression stops with [`DecompressError::SizeExceeded`] once the decompressed `Data`
+/// body sections exceed `limit` bytes, so that a small message cannot expand without bound.
+///
+/// The payload is returned unchanged if the message is not compressed with a recognized
+/// encoding.
+pub(crate) fn decompress_payload(
+    payload: Bytes,
+    limit: Option<u64>,
+) -> Result<Bytes, DecompressError> {
+    let sections = match split_sections(&payload) {
+        Some(sections) => sections,
+        None => return Ok(payload),
+    };
+    if !sections.iter().any(|section| section.code == DATA_CODE) {
+        return Ok(payload);
+    }
+    let mut properties: Properties = match sections
+        .iter()
+        .find(|section| section.code == PROP_CODE)
+        .and_then(|section| serde_amqp::from_slice(section.bytes).ok())
+    {
+        Some(properties) => properties,
+        None => return Ok(payload),
+    };
+    let encoding = match properties
+        .content_encoding
+        .take()
+        .and_then(|e| ContentEncoding::from_content_encoding(e.as_str()))
+    {
+        Some(encoding) => encoding,
+        None => return Ok(payload),
+    };
+
+    let mut remaining = limit.unwrap_or(u64::MAX);
+    let mut buf = BytesMut::with_capacity(payload.len());
+    let mut serializer = Serializer::from((&mut buf).writer());
+    for section in sections {
+        match section.code {
+            PROP_CODE => {
+                // The properties may have been added by the compression
+                if properties != Properties::default() {
+                    properties.serialize(&mut serializer)?;
+                }
+            }
+            DATA_CODE => {
+                let Data(bytes) = serde_amqp::from_slice(section.bytes)?;
+                let decoded = encoding
+                    .decode_with_limit(&bytes, remaining)
+                    .map_err(serde_amqp::Error::Io)?
+                    .ok_or(DecompressError::SizeExceeded)?;
+                remaining -= decoded.len() as u64;
+                Data(Binary::from(decoded)).serialize(&mut serializer)?;
+            }
+            _ => serializer
+                .writer
+                .write_all(section.bytes)
+                .map_err(serde_amqp::Error::Io)?,
+        }
+    }
+    Ok(buf.freeze())
+}
+
+/// A section of an encoded message
+struct RawSection<'a> {
+    code: u8,
+
+    /// The encoded section, including the descriptor
+    bytes: &'a [u8],
+}
+
+/// Split an encoded message into its sections without decoding them. `None` is returned if the
+/// sections cannot be delimited
+fn split_sections(mut payload: &[u8]) -> Option<Vec<RawSection<'_>>> {
+    let mut sections = Vec::new();
+    while !payload.is_empty() {
+        let (code, descriptor_len) = match payload {
+            [DESCRIBED_TYPE, SMALL_ULONG_TYPE, code, ..] => (*code, 3),
+            [DESCRIBED_TYPE, ULONG_TYPE, 0, 0, 0, 0, 0, 0, 0, code, ..] => (*code, 10),
+            _ => return None,
+        };
+        let len = descriptor_len + encoded_value_len(&payload[descriptor_len..])?;
+        let (bytes, rest) = payload.split_at(len);
+        sections.push(RawSection { code, bytes });
+        payload = rest;
+    }
+    Some(sections)
+}
+
+/// The length of the encoded value at the start of `bytes`. Only the constructors that a section
+/// value may be encoded with are recognized, and `None` is returned otherwise
+fn encoded_value_len(bytes: &[u8]) -> Option<usize> {
+    let len = match EncodingCodes::try_from(*bytes.first()?).ok()? {
+        EncodingCodes::Null | EncodingCodes::List0 => 1,
+        EncodingCodes::Vbin8
+        | EncodingCodes::Str8
+        | EncodingCodes::Sym8
+        | EncodingCodes::List8
+        | EncodingCodes::Map8
+        | EncodingCodes::Array8 => 2 + *bytes.get(1)? as usize,
+        EncodingCodes::Vbin32
+        | EncodingCodes::Str32
+        | EncodingCodes::Sym32
+        | EncodingCodes::List32
+        | EncodingCodes::Map32
+        | EncodingCodes::Array32 => {
+            let size = u32::from_be_bytes(bytes.get(1..5)?.try_into().ok()?);
+            5 + usize::try_from(size).ok()?
+        }
+        _ => return None,
+    };
+    (len <= bytes.len()).then_some(len)
+}
+
+/// Returns `None` if the message cannot be decoded or if the body is not `Data`
+fn decode_data_message(payload: &[u8]) -> Option<Message<Body<Value>>> {
+    let Deserializable(message): Deserializable<Message<Body<Value>>> =
+        serde_amqp::from_slice(payload).ok()?;
+    message.body.is_data().then_some(message)
+}
+
+fn map_data_sections<E>(
+    body: Body<Value>,
+    mut f: impl FnMut(&[u8]) -> Result<Vec<u8>, E>,
+) -> Result<Body<Value>, E> {
+    match body {
+        Body::Data(batch) => batch
+            .into_iter()
+            .map(|Data(bytes)| f(&bytes).map(|bytes| Data(Binary::from(bytes))))
+            .collect::<Result<Vec<Data>, E>>()
+            .map(|sections| Body::Data(Batch::new(sections))),
+        _ => Ok(body),
+    }
+}
+
+fn encode_message<B: SerializableBody>(message: Message<B>) -> Result<Bytes, serde_amqp::Error> {
+    let mut payload = BytesMut::new();
+    let mut serializer = Serializer::from((&mut payload).writer());
+    Serializable(message).serialize(&mut serializer)?;
+    Ok(payload.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::messaging::{AmqpValue, Footer, Header, Message, MessageAnnotations};
+
+    use super::*;
+
+    fn encoding() -> ContentEncoding {
+        #[cfg(feature = "gzip")]
+        return ContentEncoding::Gzip;
+        #[cfg(all(not(feature = "gzip"), feature = "deflate"))]
+        return ContentEncoding::Deflate;
+        #[cfg(all(not(feature = "gzip"), not(feature = "deflate")))]
+        return ContentEncoding::Zstd;
+    }
+
+    #[test]
+    fn compressed_data_body_is_restored() {
+        let json = r#"{"temperature": 21.5, "unit": "celsius"}"#.repeat(100);
+        let message = Message::builder()
+            .data(Binary::from(json.into_bytes()))
+            .build();
+        let payload = encode_message(message).unwrap();
+
+        let compression = Compression::new(encoding());
+        let compressed = compression.compress_payload(payload.clone()).unwrap();
+        assert!(compressed.len() < payload.len());
+
+        let Deserializable(decoded): Deserializable<Message<Body<Value>>> =
+            serde_amqp::from_slice(&compressed).unwrap();
+        let content_encoding = decoded.properties.unwrap().content_encoding.unwrap();
+        assert_eq!(content_encoding.as_str(), encoding().as_str());
+
+        let decompressed = decompress_payload(compressed, None).unwrap();
+        assert_eq!(decompressed, payload);
+    }
+
+    #[test]
+    fn sections_around_the_data_body_are_kept() {
+        let message = Message::builder()
+            .header(Header {
+                durable: true,
+                ..Default::default()
+            })
+            .message_annotations(MessageAnnotations::builder().insert("x-opt-key", 1).build())
+            .properties(Properties::builder().subject("reading").build())
+            .data(Binary::from(vec![7u8; 4096]))
+            .footer(Footer::builder().insert("x-checksum", "abc").build())
+            .build();
+        let payload = encode_message(message).unwrap();
+        let compressed = Compression::new(encoding())
+            .compress_payload(payload.clone())
+            .unwrap();
+        assert!(compressed.len() < payload.len());
+
+        let decompressed = decompress_payload(compressed, None).unwrap();
+        assert_eq!(decompressed, payload);
+    }
+
+    #[test]
+    fn decompression_stops_once_the_limit_is_exceeded() {
+        let message = Message::builder()
+            .data(Binary::from(vec![0u8; 1024 * 1024]))
+            .build();
+        let payload = encode_message(message).unwrap();
+        let compressed = Compression::new(encoding())
+            .compress_payload(payload)
+            .unwrap();
+
+        let result = decompress_payload(compressed.clone(), Some(64 * 1024));
+        assert!(matches!(result, Err(DecompressError::SizeExceeded)));
+        assert!(decompress_payload(compressed, Some(1024 * 1024)).is_ok());
+    }
+
+    #[test]
+    fn small_or_non_data_body_is_not_compressed() {
+        let compression = Compression::new(encoding()).threshold(16);
+
+        let message = Message::builder().data(Binary::from(vec![1u8; 8])).build();
+        let payload = encode_message(message).unwrap();
+        assert!(payload.len() < 16);
+        assert_eq!(
+            compression.compress_payload(payload.clone()).unwrap(),
+            payload
+        );
+
+        let message = Message::builder().body(AmqpValue("a".repeat(100))).build();
+        let payload = encode_message(message).unwrap();
+        assert_eq!(
+            compression.compress_payload(payload.clone()).unwrap(),
+            payload
+        );
+        assert_eq!(decompress_payload(payload.clone(), None).unwrap(), payload);
+    }
+}
@@ -0,0 +1,198 @@
+//! Deadline of a sent message, see [`Builder::deadline()`](super::delivery::Builder::deadline)
+
+use std::task::{Context, Poll};
+
+use fe2o3_amqp_types::definitions::DeliveryTag;
+
+cfg_not_wasm32! {
+    use std::fmt;
+
+    use bytes::Bytes;
+    use fe2o3_amqp_types::messaging::{DeliveryState, Header, Released};
+    use futures_util::FutureExt;
+    use tokio::{
+        sync::mpsc::{self, error::TrySendError},
+        time::Instant,
+    };
+
+    use crate::{
+        clock::{ClockSleep, SharedClock},
+        endpoint::InputHandle,
+    };
+
+    use super::{ArcSenderUnsettledMap, LinkFrame};
+}
+
+/// Re-encodes the header of an encoded message with the ttl set to the time left until the
+/// deadline, or left unchanged if the header already carries a shorter ttl
+///
+/// The header is always the first section, so the rest of the message is kept as is. `now` is
+/// read from the clock of the link.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn stamp_ttl(
+    payload: Bytes,
+    header: Option<&Header>,
+    deadline: Instant,
+    now: Instant,
+) -> Result<Bytes, serde_amqp::Error> {
+    let remaining = deadline.saturating_duration_since(now);
+    let remaining = u32::try_from(remaining.as_millis()).unwrap_or(u32::MAX);
+    let (mut header, rest) = match header {
+        Some(header) => {
+            let encoded_len = serde_amqp::to_vec(header)?.len();
+            (header.clone(), payload.slice(encoded_len..))
+        }
+        None => (Header::default(), payload),
+    };
+    header.ttl = Some(header.ttl.map_or(remaining, |ttl| ttl.min(remaining)));
+
+    let mut stamped = serde_amqp::to_vec(&header)?;
+    stamped.extend_from_slice(&rest);
+    Ok(Bytes::from(stamped))
+}
+
+/// Fails a [`DeliveryFut`](super::delivery::DeliveryFut) once the deadline elapses
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) struct DeadlineTimer {
+    clock: SharedClock,
+    deadline: Instant,
+    sleep: ClockSleep,
+    release: Option<DeadlineRelease>,
+}
+
+/// Settles the delivery as released from the sender side once the deadline elapses
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+pub(crate) struct DeadlineRelease {
+    pub(crate) unsettled: ArcSenderUnsettledMap,
+    pub(crate) input_handle: InputHandle,
+    pub(crate) writer: mpsc::Sender<LinkFrame>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl fmt::Debug for DeadlineTimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadlineTimer")
+            .field("deadline", &self.deadline)
+            .field("release", &self.release)
+            .finish()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DeadlineTimer {
+    pub(crate) fn new(
+        clock: SharedClock,
+        deadline: Instant,
+        release: Option<DeadlineRelease>,
+    ) -> Self {
+        Self {
+            sleep: clock.sleep_until(deadline),
+            clock,
+            deadline,
+            release,
+        }
+    }
+
+    /// Returns a future that completes once the deadline elapses, which does not borrow the
+    /// timer
+    pub(crate) fn elapsed(&self) -> ClockSleep {
+        self.clock.sleep_until(self.deadline)
+    }
+
+    pub(crate) fn poll_elapsed(
+        &mut self,
+        cx: &mut Context<'_>,
+        delivery_tag: &DeliveryTag,
+    ) -> Poll<()> {
+        match self.sleep.poll_unpin(cx) {
+            Poll::Ready(()) => {
+                if let Some(release) = self.release.take() {
+                    release.release(delivery_tag.clone());
+                }
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DeadlineRelease {
+    fn release(self, delivery_tag: DeliveryTag) {
+        let removed = self
+            .unsettled
+            .write()
+            .as_mut()
+            .and_then(|map| map.swap_remove(&delivery_tag));
+        // The delivery is settled by the remote receiver in the meantime
+        if removed.is_none() {
+            return;
+        }
+
+        let frame = LinkFrame::Settle {
+            input_handle: self.input_handle,
+            delivery_tag,
+            state: Some(DeliveryState::Released(Released {})),
+        };
+        // The timer is polled synchronously, so the frame is sent from a task if the outgoing
+        // buffer is full
+        if let Err(TrySendError::Full(frame)) = self.writer.try_send(frame) {
+            let writer = self.writer;
+            tokio::spawn(async move {
+                let _ = writer.send(frame).await;
+            });
+        }
+    }
+}
+
+/// Deadlines are not supported on wasm32
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug)]
+pub(crate) enum DeadlineTimer {}
+
+#[cfg(target_arch = "wasm32")]
+impl DeadlineTimer {
+    pub(crate) fn poll_elapsed(&mut self, _: &mut Context<'_>, _: &DeliveryTag) -> Poll<()> {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::messaging::{
+        message::__private::{Deserializable, Serializable},
+        Header, Message,
+    };
+    use tokio::time::{Duration, Instant};
+
+    use super::stamp_ttl;
+
+    #[tokio::test]
+    async fn ttl_is_stamped_on_the_encoded_header() {
+        let now = Instant::now();
+        let deadline = now + Duration::from_secs(10);
+        let message = Message::builder().value("hello").build();
+        let payload = serde_amqp::to_vec(&Serializable(message)).unwrap();
+        let stamped = stamp_ttl(payload.into(), None, deadline, now).unwrap();
+        let Deserializable(message): Deserializable<Message<String>> =
+            serde_amqp::from_slice(&stamped).unwrap();
+        assert_eq!(message.header.unwrap().ttl, Some(10_000));
+        assert_eq!(message.body, "hello");
+
+        // A shorter ttl is kept and the other fields of the header are unchanged
+        let header = Header::builder().durable(true).ttl(Some(500)).build();
+        let message = Message::builder()
+            .header(header.clone())
+            .value("hello")
+            .build();
+        let payload = serde_amqp::to_vec(&Serializable(message)).unwrap();
+        let stamped = stamp_ttl(payload.into(), Some(&header), deadline, now).unwrap();
+        let Deserializable(message): Deserializable<Message<String>> =
+            serde_amqp::from_slice(&stamped).unwrap();
+        let header = message.header.unwrap();
+        assert!(header.durable);
+        assert_eq!(header.ttl, Some(500));
+        assert_eq!(message.body, "hello");
+    }
+}
@@ -0,0 +1,163 @@
+//! Detection of the duplicated incoming messages on a receiver
+
+use std::{
+    collections::{HashSet, VecDeque},
+    time::Duration,
+};
+
+use fe2o3_amqp_types::messaging::MessageId;
+use tokio::time::Instant;
+
+/// Default number of message ids remembered by [`Deduplication`]
+pub const DEFAULT_DEDUP_CAPACITY: usize = 1024;
+
+/// Window of the recently received `message-id`s on a [`Receiver`](super::Receiver)
+///
+/// A message whose `message-id` is found in the window is treated as a redelivery of a message
+/// that has already been received. The duplicate is accepted (unless it is sent settled) and is
+/// not returned from `recv()`. Messages without a `message-id` are never treated as duplicates.
+///
+/// The window holds at most `capacity` message ids and, if `ttl` is set, forgets a message id
+/// once it has been in the window for longer than `ttl`. A message id is remembered as soon as the
+/// message is returned from `recv()`, so a message that is released or modified by the
+/// application would be suppressed if it is redelivered within the window, unless the message id
+/// is removed with [`Receiver::forget_message_id()`](super::Receiver::forget_message_id).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deduplication {
+    /// Maximum number of message ids in the window
+    pub capacity: usize,
+
+    /// How long a message id is kept in the window. The message ids are only evicted by the
+    /// capacity if this is `None`
+    pub ttl: Option<Duration>,
+}
+
+impl Default for Deduplication {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEDUP_CAPACITY)
+    }
+}
+
+impl Deduplication {
+    /// Creates a window that holds at most `capacity` message ids
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ttl: None,
+        }
+    }
+
+    /// Forget the message ids that are older than `ttl`
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+}
+
+/// Recently received message ids in the order of arrival
+#[derive(Debug)]
+pub(crate) struct Deduplicator {
+    config: Deduplication,
+    seen: HashSet<MessageId>,
+    order: VecDeque<(MessageId, Instant)>,
+
+    /// Number of duplicates that are dropped so far
+    pub(crate) suppressed: u64,
+}
+
+impl Deduplicator {
+    pub(crate) fn new(config: Deduplication) -> Self {
+        Self {
+            config,
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+            suppressed: 0,
+        }
+    }
+
+    pub(crate) fn config(&self) -> Deduplication {
+        self.config
+    }
+
+    fn evict(&mut self, now: Instant) {
+        while self.order.len() > self.config.capacity {
+            if let Some((id, _)) = self.order.pop_front() {
+                self.seen.remove(&id);
+            }
+        }
+        if let Some(ttl) = self.config.ttl {
+            while let Some((id, _)) = self
+                .order
+                .front()
+                .filter(|(_, seen_at)| now.saturating_duration_since(*seen_at) > ttl)
+            {
+                self.seen.remove(id);
+                self.order.pop_front();
+            }
+        }
+    }
+
+    /// Records the message id and returns whether it is already in the window
+    pub(crate) fn check_and_insert(&mut self, id: &MessageId, now: Instant) -> bool {
+        self.evict(now);
+        if self.seen.contains(id) {
+            self.suppressed += 1;
+            return true;
+        }
+        if self.config.capacity > 0 {
+            self.seen.insert(id.clone());
+            self.order.push_back((id.clone(), now));
+            self.evict(now);
+        }
+        false
+    }
+
+    /// Removes the message id from the window. Returns whether it was in the window
+    pub(crate) fn forget(&mut self, id: &MessageId) -> bool {
+        if self.seen.remove(id) {
+            self.order.retain(|(seen, _)| seen != id);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fe2o3_amqp_types::messaging::MessageId;
+    use tokio::time::Instant;
+
+    use super::{Deduplication, Deduplicator};
+
+    #[test]
+    fn oldest_message_id_is_evicted_by_capacity() {
+        let now = Instant::now();
+        let mut dedup = Deduplicator::new(Deduplication::new(2));
+        let ids: Vec<MessageId> = (0..3u64).map(MessageId::from).collect();
+        assert!(!dedup.check_and_insert(&ids[0], now));
+        assert!(!dedup.check_and_insert(&ids[1], now));
+        assert!(dedup.check_and_insert(&ids[0], now));
+        assert!(!dedup.check_and_insert(&ids[2], now));
+        assert!(!dedup.check_and_insert(&ids[0], now));
+        assert_eq!(dedup.suppressed, 1);
+
+        assert!(dedup.forget(&ids[0]));
+        assert!(!dedup.forget(&ids[0]));
+        assert!(!dedup.check_and_insert(&ids[0], now));
+    }
+
+    #[test]
+    fn message_id_is_forgotten_after_ttl() {
+        let now = Instant::now();
+        let config = Deduplication::new(10).ttl(Duration::from_secs(1));
+        let mut dedup = Deduplicator::new(config);
+        let id = MessageId::from(String::from("id"));
+        assert!(!dedup.check_and_insert(&id, now));
+        assert!(dedup.check_and_insert(&id, now + Duration::from_millis(500)));
+        assert!(!dedup.check_and_insert(&id, now + Duration::from_millis(1500)));
+        assert_eq!(dedup.suppressed, 1);
+    }
+}
@@ -1,9 +1,14 @@
 //! Helper types differentiating message delivery
 
+use bytes::Bytes;
 use fe2o3_amqp_types::{
     definitions::{DeliveryNumber, DeliveryTag, Handle, MessageFormat, ReceiverSettleMode},
-    messaging::{Accepted, DeliveryState, Message, Outcome, SerializableBody, MESSAGE_FORMAT},
-    primitives::BinaryRef,
+    messaging::{
+        message::{Builder as MessageBuilder, EmptyBody},
+        Accepted, Body, DeliveryState, Message, Outcome, Properties, SerializableBody,
+        MESSAGE_FORMAT,
+    },
+    primitives::{BinaryRef, Value},
 };
 use futures_util::FutureExt;
 use pin_project_lite::pin_project;
@@ -16,7 +21,10 @@ use crate::{
 };
 use crate::{util::AsDeliveryState, Payload};
 
-use super::{LinkStateError, SendError};
+use super::{deadline::DeadlineTimer, LinkStateError, SendError};
+
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::time::Instant;
 
 /// Delivery information that is needed for disposing a message
 #[derive(Clone)]
@@ -94,6 +102,10 @@ pub struct Delivery<T> {
     pub(crate) rcv_settle_mode: Option<ReceiverSettleMode>,
 
     pub(crate) message: Message<T>,
+
+    /// When the delivery was received, which is used to decrement the ttl when it is forwarded
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) received_at: tokio::time::Instant,
 }
 
 impl<T> Delivery<T> {
@@ -137,6 +149,34 @@ impl<T> Delivery<T> {
         self.message.body
     }
 
+    /// Creates a builder for the response to this delivery
+    ///
+    /// The `to` of the response is the `reply-to` of the request, and the `correlation-id` is
+    /// the `message-id` of the request (or its `correlation-id` if it has no `message-id`). The
+    /// response belongs to the group given by the `reply-to-group-id` of the request. The body and
+    /// the other sections are left for the caller to set.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let request: Delivery<String> = receiver.recv().await?;
+    /// receiver.accept(&request).await?;
+    ///
+    /// let response = request.reply_builder().value("pong").build();
+    /// sender.send(response).await?;
+    /// ```
+    pub fn reply_builder(&self) -> MessageBuilder<EmptyBody> {
+        let request = self.message.properties.as_ref();
+        let properties = Properties {
+            to: request.and_then(|p| p.reply_to.clone()),
+            correlation_id: request
+                .and_then(|p| p.message_id.clone().or_else(|| p.correlation_id.clone())),
+            group_id: request.and_then(|p| p.reply_to_group_id.clone()),
+            ..Default::default()
+        };
+        Message::builder().properties(properties)
+    }
+
     /// Consume the delivery into the delivery info and message.
     /// The message format will be lost.
     pub fn into_parts(self) -> (DeliveryInfo, Message<T>) {
@@ -191,6 +231,15 @@ pub struct Sendable<T> {
     /// Please note that this field will be neglected if the negotiated
     /// sender settle mode is NOT equal to `SenderSettleMode::Mixed`
     pub settled: Option<bool>,
+
+    /// The instant by which the message must be settled. See
+    /// [`Builder::deadline()`](Builder::deadline)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub deadline: Option<Instant>,
+
+    /// Whether the delivery is settled as released from the sender side if the deadline elapses
+    #[cfg(not(target_arch = "wasm32"))]
+    pub release_after_deadline: bool,
 }
 
 impl Sendable<Uninitialized> {
@@ -200,6 +249,105 @@ impl Sendable<Uninitialized> {
     }
 }
 
+impl Sendable<Body<Value>> {
+    /// Creates a [`Sendable`] with a message that carries no body section, eg. a marker or a
+    /// keep-alive. The other sections can be set on `message` before it is sent
+    pub fn empty() -> Self {
+        Self::from(Message::builder().body(Body::Empty).build())
+    }
+}
+
+impl<T> Sendable<T> {
+    /// Creates a [`Sendable`] that re-publishes a received message with the default
+    /// [`ForwardPolicy`]
+    ///
+    /// See [`from_delivery_with_policy()`](Self::from_delivery_with_policy) for how the message is
+    /// changed.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let delivery: Delivery<Body<Value>> = receiver.recv().await?;
+    /// let outcome = sender.send(Sendable::from_delivery(delivery.clone())).await?;
+    /// ```
+    pub fn from_delivery(delivery: Delivery<T>) -> Self {
+        Self::from_delivery_with_policy(delivery, ForwardPolicy::default())
+    }
+
+    /// Creates a [`Sendable`] that re-publishes a received message
+    ///
+    /// The bare message (properties, application properties, body) and the message format are
+    /// kept as they are. The annotations and the footer are kept according to `policy`. The
+    /// header is updated for the next hop:
+    ///
+    /// - the ttl is decremented by the time elapsed since the delivery was received, down to
+    ///   zero (the ttl is left unchanged on `wasm32`)
+    /// - the delivery-count is incremented
+    /// - first-acquirer is cleared, as the message has already been acquired
+    pub fn from_delivery_with_policy(delivery: Delivery<T>, policy: ForwardPolicy) -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let elapsed = delivery.received_at.elapsed();
+        let message_format = delivery.message_format.unwrap_or(MESSAGE_FORMAT);
+        let mut message = delivery.message;
+
+        if let Some(header) = message.header.as_mut() {
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(ttl) = header.ttl.as_mut() {
+                let elapsed = u32::try_from(elapsed.as_millis()).unwrap_or(u32::MAX);
+                *ttl = ttl.saturating_sub(elapsed);
+            }
+            header.delivery_count = header.delivery_count.saturating_add(1);
+            header.first_acquirer = false;
+        }
+        if !policy.delivery_annotations {
+            message.delivery_annotations = None;
+        }
+        if !policy.message_annotations {
+            message.message_annotations = None;
+        }
+        if !policy.footer {
+            message.footer = None;
+        }
+
+        Self {
+            message,
+            message_format,
+            settled: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            deadline: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            release_after_deadline: false,
+        }
+    }
+}
+
+/// The sections of a received message that are kept by [`Sendable::from_delivery_with_policy()`]
+///
+/// The delivery-annotations are meant for the next hop only and are dropped by default, while the
+/// message-annotations and the footer are kept by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForwardPolicy {
+    /// Whether the delivery-annotations are kept
+    pub delivery_annotations: bool,
+
+    /// Whether the message-annotations are kept
+    pub message_annotations: bool,
+
+    /// Whether the footer is kept. The footer may carry a signature or a hash that no longer
+    /// matches if the message is changed
+    pub footer: bool,
+}
+
+impl Default for ForwardPolicy {
+    fn default() -> Self {
+        Self {
+            delivery_annotations: false,
+            message_annotations: true,
+            footer: true,
+        }
+    }
+}
+
 impl<T, U> From<T> for Sendable<U>
 where
     T: Into<Message<U>>,
@@ -210,6 +358,10 @@ where
             message: value.into(),
             message_format: MESSAGE_FORMAT,
             settled: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            deadline: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            release_after_deadline: false,
         }
     }
 }
@@ -228,6 +380,13 @@ pub struct Builder<T> {
     /// Indicates whether the message is considered settled by the sender
     pub settled: Option<bool>,
     // pub batchable: bool,
+    /// The instant by which the message must be settled
+    #[cfg(not(target_arch = "wasm32"))]
+    pub deadline: Option<Instant>,
+
+    /// Whether the delivery is settled as released if the deadline elapses
+    #[cfg(not(target_arch = "wasm32"))]
+    pub release_after_deadline: bool,
 }
 
 impl Default for Builder<Uninitialized> {
@@ -244,6 +403,10 @@ impl Builder<Uninitialized> {
             message_format: MESSAGE_FORMAT,
             settled: None,
             // batchable: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            deadline: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            release_after_deadline: false,
         }
     }
 }
@@ -256,6 +419,29 @@ impl<State> Builder<State> {
             message_format: self.message_format,
             settled: self.settled,
             // batchable: self.batchable,
+            #[cfg(not(target_arch = "wasm32"))]
+            deadline: self.deadline,
+            #[cfg(not(target_arch = "wasm32"))]
+            release_after_deadline: self.release_after_deadline,
+        }
+    }
+
+    /// Already encoded message that is sent as is, bypassing serialization. This is useful to
+    /// replay recorded payloads or to relay messages received by another AMQP implementation
+    ///
+    /// The bytes are the encoded sections of the message (ie. the bare message optionally
+    /// surrounded by the header, annotations and footer), and are split into transfers according
+    /// to the negotiated frame size. They are not compressed even if compression is enabled on
+    /// the sender
+    pub fn bare_message_bytes(self, bytes: impl Into<Bytes>) -> Builder<Bytes> {
+        Builder {
+            message: bytes.into(),
+            message_format: self.message_format,
+            settled: self.settled,
+            #[cfg(not(target_arch = "wasm32"))]
+            deadline: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            release_after_deadline: false,
         }
     }
 
@@ -275,6 +461,43 @@ impl<State> Builder<State> {
 }
 
 impl<T> Builder<Message<T>> {
+    cfg_not_wasm32! {
+        /// The instant by which the message must be settled, which gives the send a single
+        /// end-to-end time budget
+        ///
+        /// The `ttl` of the header is set to the time left until the deadline when the message is
+        /// sent (unless the header already carries a shorter `ttl`), so that the remote peer can
+        /// expire the message. The [`DeliveryFut`] of the delivery (and thus
+        /// [`Sender::send()`](crate::Sender::send)) fails with [`SendError::DeadlineElapsed`] if
+        /// the delivery is not settled by the deadline.
+        ///
+        /// # Example
+        ///
+        /// ```rust,ignore
+        /// let sendable = Sendable::builder()
+        ///     .message("hello AMQP")
+        ///     .deadline(Instant::now() + Duration::from_secs(5))
+        ///     .release_after_deadline(true)
+        ///     .build();
+        /// match sender.send(sendable).await {
+        ///     Err(SendError::DeadlineElapsed) => { /* retry or give up */ }
+        ///     result => { /* ... */ }
+        /// }
+        /// ```
+        pub fn deadline(mut self, deadline: Instant) -> Self {
+            self.deadline = Some(deadline);
+            self
+        }
+
+        /// Whether the delivery is settled as released from the sender side if the deadline
+        /// elapses, after which the remote receiver can no longer settle it. This has no effect
+        /// without a [`deadline`](Self::deadline)
+        pub fn release_after_deadline(mut self, release: bool) -> Self {
+            self.release_after_deadline = release;
+            self
+        }
+    }
+
     /// Builds a [`Sendable`]
     pub fn build(self) -> Sendable<T> {
         Sendable {
@@ -282,6 +505,10 @@ impl<T> Builder<Message<T>> {
             message_format: self.message_format,
             settled: self.settled,
             // batchable: self.batchable,
+            #[cfg(not(target_arch = "wasm32"))]
+            deadline: self.deadline,
+            #[cfg(not(target_arch = "wasm32"))]
+            release_after_deadline: self.release_after_deadline,
         }
     }
 }
@@ -292,6 +519,58 @@ impl<T> From<Builder<Message<T>>> for Sendable<T> {
     }
 }
 
+/// An already encoded message before sending
+///
+/// # Example
+///
+/// ```rust, ignore
+/// let sendable = Sendable::builder()
+///     .bare_message_bytes(recorded_payload)
+///     .build();
+/// sender.send_encoded(sendable).await.unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct EncodedSendable {
+    /// The encoded message
+    pub payload: Bytes,
+
+    /// Please see page 82 of the AMQP 1.0 core specification
+    pub message_format: MessageFormat,
+
+    /// Whether the message will be sent pre-settled
+    ///
+    /// Please note that this field will be neglected if the negotiated
+    /// sender settle mode is NOT equal to `SenderSettleMode::Mixed`
+    pub settled: Option<bool>,
+}
+
+impl From<Bytes> for EncodedSendable {
+    fn from(payload: Bytes) -> Self {
+        Self {
+            payload,
+            message_format: MESSAGE_FORMAT,
+            settled: None,
+        }
+    }
+}
+
+impl Builder<Bytes> {
+    /// Builds an [`EncodedSendable`]
+    pub fn build(self) -> EncodedSendable {
+        EncodedSendable {
+            payload: self.message,
+            message_format: self.message_format,
+            settled: self.settled,
+        }
+    }
+}
+
+impl From<Builder<Bytes>> for EncodedSendable {
+    fn from(builder: Builder<Bytes>) -> Self {
+        builder.build()
+    }
+}
+
 /// An unsettled message stored in the Sender's unsettled map
 #[derive(Debug)]
 pub(crate) struct UnsettledMessage {
@@ -299,6 +578,8 @@ pub(crate) struct UnsettledMessage {
     pub(crate) state: Option<DeliveryState>,
     pub(crate) message_format: u32,
     pub(crate) sender: oneshot::Sender<Option<DeliveryState>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) created: tokio::time::Instant,
 }
 
 impl UnsettledMessage {
@@ -313,6 +594,8 @@ impl UnsettledMessage {
             state,
             message_format,
             sender,
+            #[cfg(not(target_arch = "wasm32"))]
+            created: tokio::time::Instant::now(),
         }
     }
 
@@ -345,6 +628,7 @@ pin_project! {
         #[pin]
         // Reserved for future use on actively sending disposition from Sender
         settlement: Settlement,
+        deadline: Option<DeadlineTimer>,
         outcome_marker: PhantomData<O>
     }
 }
@@ -360,12 +644,19 @@ impl<O> DeliveryFut<O> {
             } => delivery_tag,
         }
     }
+
+    /// Fails the future once the deadline elapses
+    pub(crate) fn with_deadline(mut self, deadline: Option<DeadlineTimer>) -> Self {
+        self.deadline = deadline;
+        self
+    }
 }
 
 impl<O> From<Settlement> for DeliveryFut<O> {
     fn from(settlement: Settlement) -> Self {
         Self {
             settlement,
+            deadline: None,
             outcome_marker: PhantomData,
         }
     }
@@ -398,6 +689,14 @@ pub trait FromOneshotRecvError {
     fn from_oneshot_recv_error(err: RecvError) -> Self;
 }
 
+/// This trait defines how to interprete a delivery that is not settled before its deadline
+///
+/// This is public for compatibility with rust versions <= 1.58.0
+pub trait FromDeadlineElapsed {
+    /// how to interprete an elapsed deadline
+    fn from_deadline_elapsed() -> Self;
+}
+
 impl FromOneshotRecvError for SendResult {
     fn from_oneshot_recv_error(_: RecvError) -> Self {
         Err(LinkStateError::IllegalSessionState.into())
@@ -412,33 +711,31 @@ impl FromPreSettled for SendResult {
     }
 }
 
+impl FromDeadlineElapsed for SendResult {
+    fn from_deadline_elapsed() -> Self {
+        Err(SendError::DeadlineElapsed)
+    }
+}
+
 impl FromDeliveryState for SendResult {
     fn from_none() -> Self {
         Err(SendError::IllegalDeliveryState)
     }
 
     fn from_delivery_state(state: DeliveryState) -> Self {
-        match state {
-            // DeliveryState::Accepted(accepted) | DeliveryState::Received(_) => Ok(accepted),
-            // DeliveryState::Rejected(rejected) => Err(SendError::Rejected(rejected)),
-            // DeliveryState::Released(released) => Err(SendError::Released(released)),
-            // DeliveryState::Modified(modified) => Err(SendError::Modified(modified)),
-            DeliveryState::Accepted(accepted) => Ok(Outcome::Accepted(accepted)),
-            DeliveryState::Rejected(rejected) => Ok(Outcome::Rejected(rejected)),
-            DeliveryState::Released(released) => Ok(Outcome::Released(released)),
-            DeliveryState::Modified(modified) => Ok(Outcome::Modified(modified)),
-            DeliveryState::Received(_) => Err(SendError::NonTerminalDeliveryState),
+        match Outcome::try_from(state) {
             #[cfg(feature = "transaction")]
-            DeliveryState::Declared(_) | DeliveryState::TransactionalState(_) => {
-                Err(SendError::IllegalDeliveryState)
-            }
+            Ok(Outcome::Declared(_)) => Err(SendError::IllegalDeliveryState),
+            Ok(outcome) => Ok(outcome),
+            Err(DeliveryState::Received(_)) => Err(SendError::NonTerminalDeliveryState),
+            Err(_) => Err(SendError::IllegalDeliveryState),
         }
     }
 }
 
 impl<O> Future for DeliveryFut<O>
 where
-    O: FromPreSettled + FromDeliveryState + FromOneshotRecvError,
+    O: FromPreSettled + FromDeliveryState + FromOneshotRecvError + FromDeadlineElapsed,
 {
     type Output = O;
 
@@ -452,11 +749,17 @@ where
         match &mut *settlement {
             Settlement::Settled(_) => Poll::Ready(O::from_settled()),
             Settlement::Unsettled {
-                delivery_tag: _,
+                delivery_tag,
                 outcome,
             } => {
                 match outcome.poll_unpin(cx) {
-                    Poll::Pending => Poll::Pending,
+                    Poll::Pending => match this.deadline {
+                        Some(deadline) => match deadline.poll_elapsed(cx, delivery_tag) {
+                            Poll::Ready(()) => Poll::Ready(O::from_deadline_elapsed()),
+                            Poll::Pending => Poll::Pending,
+                        },
+                        None => Poll::Pending,
+                    },
                     Poll::Ready(result) => {
                         match result {
                             Ok(Some(state)) => Poll::Ready(O::from_delivery_state(state)),
@@ -522,4 +825,156 @@ mod tests {
         let sendable = Sendable::from(value);
         assert_eq!(sendable.message.body, Data(Binary::from("Foo")));
     }
+
+    #[test]
+    fn test_bare_message_bytes_into_encoded_sendable() {
+        use super::EncodedSendable;
+
+        let sendable = Sendable::builder()
+            .message_format(1)
+            .bare_message_bytes(vec![0x00, 0x53, 0x77, 0x40])
+            .settled(true)
+            .build();
+        assert_eq!(&sendable.payload[..], &[0x00, 0x53, 0x77, 0x40]);
+        assert_eq!(sendable.message_format, 1);
+        assert_eq!(sendable.settled, Some(true));
+
+        let sendable = EncodedSendable::from(bytes::Bytes::from_static(&[0x00, 0x53, 0x77, 0x40]));
+        assert_eq!(sendable.message_format, 0);
+        assert_eq!(sendable.settled, None);
+    }
+
+    #[test]
+    fn reply_builder_addresses_the_response_to_the_requester() {
+        use fe2o3_amqp_types::messaging::{MessageId, Properties};
+
+        use super::Delivery;
+
+        let delivery = |properties: Properties| Delivery {
+            link_output_handle: 0.into(),
+            delivery_id: 0,
+            delivery_tag: Binary::from(vec![0u8]),
+            message_format: None,
+            rcv_settle_mode: None,
+            message: Message::builder()
+                .properties(properties)
+                .value("ping")
+                .build(),
+            received_at: tokio::time::Instant::now(),
+        };
+
+        let request = Properties::builder()
+            .message_id(MessageId::from(7u64))
+            .correlation_id(MessageId::from(String::from("conversation")))
+            .reply_to("client-inbox")
+            .reply_to_group_id(String::from("client-group"))
+            .build();
+        let response = delivery(request).reply_builder().value("pong").build();
+        let properties = response.properties.unwrap();
+        assert_eq!(properties.to.as_deref(), Some("client-inbox"));
+        assert_eq!(properties.correlation_id, Some(MessageId::from(7u64)));
+        assert_eq!(properties.group_id.as_deref(), Some("client-group"));
+        assert_eq!(properties.message_id, None);
+        assert_eq!(response.body, AmqpValue("pong"));
+
+        // The correlation-id of the request is used if it has no message-id
+        let request = Properties::builder()
+            .correlation_id(MessageId::from(String::from("conversation")))
+            .build();
+        let properties = delivery(request)
+            .reply_builder()
+            .build()
+            .properties
+            .unwrap();
+        assert_eq!(
+            properties.correlation_id,
+            Some(MessageId::from(String::from("conversation")))
+        );
+        assert_eq!(properties.to, None);
+    }
+
+    #[test]
+    fn test_rejected_delivery_state_preserves_error_info() {
+        use fe2o3_amqp_types::{
+            definitions::{AmqpError, Error, Fields},
+            messaging::{DeliveryState, Rejected},
+            primitives::{Symbol, Value},
+        };
+
+        use super::{FromDeliveryState, SendResult};
+
+        let mut info = Fields::new();
+        info.insert(
+            Symbol::from("com.microsoft:tracking-id"),
+            Value::from("abc-123"),
+        );
+        let state = DeliveryState::Rejected(Rejected::new(Error::new(
+            AmqpError::ResourceLimitExceeded,
+            String::from("quota exceeded"),
+            info,
+        )));
+
+        let outcome = SendResult::from_delivery_state(state).unwrap();
+        let error = outcome.rejected_error().unwrap();
+        assert_eq!(error.description.as_deref(), Some("quota exceeded"));
+        assert_eq!(
+            error.info_value("com.microsoft:tracking-id"),
+            Some(&Value::from("abc-123"))
+        );
+    }
+
+    #[test]
+    fn forwarded_delivery_keeps_the_bare_message_and_updates_the_header() {
+        use std::time::Duration;
+
+        use fe2o3_amqp_types::messaging::{
+            DeliveryAnnotations, Header, MessageAnnotations, Properties,
+        };
+
+        use super::{Delivery, ForwardPolicy};
+
+        let delivery = || Delivery {
+            link_output_handle: 0.into(),
+            delivery_id: 0,
+            delivery_tag: Binary::from(vec![0u8]),
+            message_format: Some(3),
+            rcv_settle_mode: None,
+            message: Message::builder()
+                .header(Header {
+                    durable: true,
+                    ttl: Some(1000),
+                    first_acquirer: true,
+                    delivery_count: 1,
+                    ..Default::default()
+                })
+                .delivery_annotations(DeliveryAnnotations::builder().insert("hop", 1).build())
+                .message_annotations(MessageAnnotations::builder().insert("x-opt", 1).build())
+                .properties(Properties::builder().message_id(7u64).build())
+                .value("payload")
+                .build(),
+            received_at: tokio::time::Instant::now() - Duration::from_millis(100),
+        };
+
+        let sendable = Sendable::from_delivery(delivery());
+        assert_eq!(sendable.message_format, 3);
+        assert_eq!(sendable.settled, None);
+        let header = sendable.message.header.unwrap();
+        assert!(header.durable);
+        assert!(header.ttl.unwrap() <= 900);
+        assert!(!header.first_acquirer);
+        assert_eq!(header.delivery_count, 2);
+        assert!(sendable.message.delivery_annotations.is_none());
+        assert!(sendable.message.message_annotations.is_some());
+        assert!(sendable.message.properties.unwrap().message_id.is_some());
+        assert_eq!(sendable.message.body, AmqpValue("payload"));
+
+        let policy = ForwardPolicy {
+            delivery_annotations: true,
+            message_annotations: false,
+            footer: true,
+        };
+        let sendable = Sendable::from_delivery_with_policy(delivery(), policy);
+        assert!(sendable.message.delivery_annotations.is_some());
+        assert!(sendable.message.message_annotations.is_none());
+    }
 }
@@ -0,0 +1,291 @@
+//! Traces of the recent deliveries of a link for debugging stuck deliveries
+//!
+//! With the `"debug-deliveries"` feature, every link records the frames of its most recent
+//! deliveries in a ring buffer of [`DEFAULT_TRACE_CAPACITY`] deliveries: the transfers that are
+//! sent or received, the dispositions that are sent or received, and when each of them happened.
+//! The trace of a delivery is obtained by its delivery tag with
+//! [`Sender::delivery_trace()`](super::Sender::delivery_trace) or
+//! [`Receiver::delivery_trace()`](super::Receiver::delivery_trace), and the traces of the
+//! deliveries that are not settled yet with `unsettled_delivery_traces()`. The [`Display`]
+//! implementation of [`DeliveryTrace`] dumps the trace in a human readable form.
+//!
+//! [`Display`]: std::fmt::Display
+
+use std::{collections::VecDeque, fmt, sync::Arc, time::SystemTime};
+
+use fe2o3_amqp_types::{
+    definitions::{DeliveryNumber, DeliveryTag},
+    messaging::DeliveryState,
+};
+use parking_lot::Mutex;
+
+/// Number of deliveries whose traces are kept by a link
+pub const DEFAULT_TRACE_CAPACITY: usize = 256;
+
+/// A frame of a delivery that is sent or received by the link
+#[derive(Debug, Clone)]
+pub enum DeliveryEventKind {
+    /// A transfer of the delivery is sent. The delivery id is assigned by the session afterwards
+    TransferSent {
+        /// Whether the delivery is sent settled
+        settled: bool,
+
+        /// Whether more transfers of the delivery follow
+        more: bool,
+
+        /// Whether the transfer resumes the delivery after the link is re-attached
+        resume: bool,
+    },
+
+    /// A transfer of the delivery is received
+    TransferReceived {
+        /// The delivery id, which is only carried by the first transfer of a delivery
+        delivery_id: Option<DeliveryNumber>,
+
+        /// Whether the delivery is settled by the sender
+        settled: Option<bool>,
+
+        /// Whether more transfers of the delivery follow
+        more: bool,
+
+        /// Whether the transfer resumes the delivery after the link is re-attached
+        resume: bool,
+    },
+
+    /// A disposition of the delivery is sent
+    DispositionSent {
+        /// Whether the delivery is settled by the disposition
+        settled: bool,
+
+        /// The delivery state carried by the disposition
+        state: Option<DeliveryState>,
+    },
+
+    /// A disposition of the delivery is received
+    DispositionReceived {
+        /// Whether the delivery is settled by the disposition
+        settled: bool,
+
+        /// The delivery state carried by the disposition
+        state: Option<DeliveryState>,
+    },
+}
+
+/// A frame of a delivery and when it is sent or received
+#[derive(Debug, Clone)]
+pub struct DeliveryEvent {
+    /// When the frame is sent or received
+    pub at: SystemTime,
+
+    /// The frame
+    pub kind: DeliveryEventKind,
+}
+
+/// The recorded frames of a delivery
+#[derive(Debug, Clone)]
+pub struct DeliveryTrace {
+    /// Tag of the delivery
+    pub delivery_tag: DeliveryTag,
+
+    /// The frames of the delivery in the order they are sent or received
+    pub events: Vec<DeliveryEvent>,
+
+    /// The latest delivery state of the delivery
+    pub state: Option<DeliveryState>,
+
+    /// Whether the delivery is settled by either end
+    pub settled: bool,
+}
+
+impl DeliveryTrace {
+    fn new(delivery_tag: DeliveryTag) -> Self {
+        Self {
+            delivery_tag,
+            events: Vec::new(),
+            state: None,
+            settled: false,
+        }
+    }
+
+    fn record(&mut self, kind: DeliveryEventKind) {
+        match &kind {
+            DeliveryEventKind::TransferSent { settled, .. } => self.settled |= *settled,
+            DeliveryEventKind::TransferReceived { settled, .. } => {
+                self.settled |= settled.unwrap_or(false)
+            }
+            DeliveryEventKind::DispositionSent { settled, state }
+            | DeliveryEventKind::DispositionReceived { settled, state } => {
+                self.settled |= *settled;
+                if state.is_some() {
+                    self.state = state.clone();
+                }
+            }
+        }
+        self.events.push(DeliveryEvent {
+            at: SystemTime::now(),
+            kind,
+        });
+    }
+}
+
+impl fmt::Display for DeliveryTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "delivery-tag 0x")?;
+        for byte in self.delivery_tag.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        writeln!(f, " (settled: {}, state: {:?})", self.settled, self.state)?;
+
+        let start = self.events.first().map(|event| event.at);
+        for event in &self.events {
+            // The offsets are relative to the first frame of the delivery
+            let offset = start
+                .and_then(|start| event.at.duration_since(start).ok())
+                .unwrap_or_default();
+            writeln!(f, "  +{:?} {:?}", offset, event.kind)?;
+        }
+        Ok(())
+    }
+}
+
+/// Ring buffer of the traces of the most recent deliveries of a link
+#[derive(Debug)]
+pub(crate) struct DeliveryTraces {
+    capacity: usize,
+    traces: VecDeque<DeliveryTrace>,
+
+    // Tag of the incoming multi-transfer delivery, whose continuation transfers do not carry the
+    // delivery tag
+    incoming_tag: Option<DeliveryTag>,
+}
+
+impl Default for DeliveryTraces {
+    fn default() -> Self {
+        Self::new(DEFAULT_TRACE_CAPACITY)
+    }
+}
+
+impl DeliveryTraces {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            traces: VecDeque::new(),
+            incoming_tag: None,
+        }
+    }
+
+    pub fn record(&mut self, delivery_tag: &DeliveryTag, kind: DeliveryEventKind) {
+        if self.capacity == 0 {
+            return;
+        }
+        // The recent deliveries are the most likely to be looked up
+        match self
+            .traces
+            .iter_mut()
+            .rev()
+            .find(|trace| trace.delivery_tag == *delivery_tag)
+        {
+            Some(trace) => trace.record(kind),
+            None => {
+                if self.traces.len() >= self.capacity {
+                    self.traces.pop_front();
+                }
+                let mut trace = DeliveryTrace::new(delivery_tag.clone());
+                trace.record(kind);
+                self.traces.push_back(trace);
+            }
+        }
+    }
+
+    /// Records an incoming transfer, which belongs to the previous delivery if it does not carry
+    /// a delivery tag
+    pub fn record_incoming_transfer(
+        &mut self,
+        delivery_tag: Option<&DeliveryTag>,
+        kind: DeliveryEventKind,
+    ) {
+        if let Some(delivery_tag) = delivery_tag {
+            self.incoming_tag = Some(delivery_tag.clone());
+        }
+        if let Some(delivery_tag) = self.incoming_tag.clone() {
+            self.record(&delivery_tag, kind);
+        }
+    }
+
+    pub fn get(&self, delivery_tag: &DeliveryTag) -> Option<DeliveryTrace> {
+        self.traces
+            .iter()
+            .rev()
+            .find(|trace| trace.delivery_tag == *delivery_tag)
+            .cloned()
+    }
+
+    pub fn unsettled(&self) -> Vec<DeliveryTrace> {
+        self.traces
+            .iter()
+            .filter(|trace| !trace.settled)
+            .cloned()
+            .collect()
+    }
+}
+
+pub(crate) type ArcDeliveryTraces = Arc<Mutex<DeliveryTraces>>;
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::{
+        definitions::DeliveryTag,
+        messaging::{Accepted, DeliveryState},
+    };
+
+    use super::{DeliveryEventKind, DeliveryTraces};
+
+    fn sent(settled: bool) -> DeliveryEventKind {
+        DeliveryEventKind::TransferSent {
+            settled,
+            more: false,
+            resume: false,
+        }
+    }
+
+    #[test]
+    fn oldest_delivery_is_evicted() {
+        let mut traces = DeliveryTraces::new(2);
+        for tag in 0u8..3 {
+            traces.record(&DeliveryTag::from(vec![tag]), sent(false));
+        }
+        assert!(traces.get(&DeliveryTag::from(vec![0])).is_none());
+        assert_eq!(traces.unsettled().len(), 2);
+
+        traces.record(
+            &DeliveryTag::from(vec![1]),
+            DeliveryEventKind::DispositionReceived {
+                settled: true,
+                state: Some(DeliveryState::Accepted(Accepted {})),
+            },
+        );
+        let trace = traces.get(&DeliveryTag::from(vec![1])).unwrap();
+        assert!(trace.settled);
+        assert_eq!(trace.events.len(), 2);
+        assert!(matches!(trace.state, Some(DeliveryState::Accepted(_))));
+        assert!(trace
+            .to_string()
+            .starts_with("delivery-tag 0x01 (settled: true"));
+        assert_eq!(traces.unsettled().len(), 1);
+    }
+
+    #[test]
+    fn continuation_transfers_belong_to_the_previous_delivery() {
+        let mut traces = DeliveryTraces::default();
+        let tag = DeliveryTag::from(vec![7]);
+        let received = |more| DeliveryEventKind::TransferReceived {
+            delivery_id: None,
+            settled: None,
+            more,
+            resume: false,
+        };
+        traces.record_incoming_transfer(Some(&tag), received(true));
+        traces.record_incoming_transfer(None, received(false));
+        assert_eq!(traces.get(&tag).unwrap().events.len(), 2);
+    }
+}
@@ -0,0 +1,438 @@
+//! Dispatching the deliveries of a receiver to typed handlers
+//!
+//! [`ReceiverDispatcher`] is a convenience layer for the common consumer setup where each kind of
+//! message is handled by its own async function. Handlers are registered per source address and
+//! optionally per `subject` of the message properties. The dispatcher manages the credit of the
+//! receiver, decodes the body into the type expected by the handler, and disposes the delivery
+//! according to the result of the handler.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let dispatcher = ReceiverDispatcher::new()
+//!     .on_subject("orders", "created", |message: Message<Order>| async move {
+//!         create_order(message.body).await
+//!     })
+//!     .on_address("orders", |message: Message<Value>| async move {
+//!         log_unknown_order_event(message).await
+//!     })
+//!     .on_panic(|panic| eprintln!("{}", panic));
+//!
+//! let mut receiver = Receiver::attach(&mut session, "orders-receiver", "orders").await.unwrap();
+//! let error = dispatcher.run(&mut receiver).await;
+//! ```
+
+use std::{collections::HashMap, fmt, future::Future, panic::AssertUnwindSafe, sync::Arc};
+
+use fe2o3_amqp_types::{
+    definitions::{self, AmqpError, DeliveryTag, SequenceNo},
+    messaging::{
+        Accepted, AmqpSequence, AmqpValue, Body, Data, DeliveryState, Message, Modified, Rejected,
+    },
+    primitives::Value,
+};
+use futures_util::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+use serde::de::DeserializeOwned;
+
+use super::{receiver::CreditMode, DispatcherError, Receiver, RecvError, DEFAULT_CREDIT};
+
+/// A handler of a [`ReceiverDispatcher`] that panicked
+#[derive(Debug, Clone)]
+pub struct HandlerPanic {
+    /// Source address of the receiver
+    pub address: Option<String>,
+
+    /// Subject of the message
+    pub subject: Option<String>,
+
+    /// Tag of the delivery
+    pub delivery_tag: DeliveryTag,
+
+    /// The panic message, if the payload of the panic is a string
+    pub message: Option<String>,
+}
+
+impl fmt::Display for HandlerPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Handler of address {:?} and subject {:?} panicked",
+            self.address, self.subject
+        )?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+type BoxedHandler =
+    Arc<dyn Fn(Message<Body<Value>>) -> BoxFuture<'static, DeliveryState> + Send + Sync>;
+type PanicHook = Arc<dyn Fn(&HandlerPanic) + Send + Sync>;
+
+#[derive(Default)]
+struct AddressRoutes {
+    by_subject: HashMap<String, BoxedHandler>,
+    any_subject: Option<BoxedHandler>,
+}
+
+/// Dispatches the deliveries of a [`Receiver`] to the handlers registered for its source address
+/// and the `subject` of the messages
+///
+/// A delivery is dispatched to the first handler that is found among
+///
+/// 1. the handler registered with [`on_subject`](Self::on_subject) for the address and subject,
+/// 2. the handler registered with [`on_address`](Self::on_address) for the address,
+/// 3. the handler registered with [`fallback`](Self::fallback).
+///
+/// The body is decoded into the type of the handler with [`serde_amqp::from_value()`]. An
+/// amqp-value body is decoded from its value, a single data section from its bytes (eg. into
+/// [`Binary`](crate::types::primitives::Binary)), a single amqp-sequence section from its list,
+/// and a message without body from `null`. The delivery is then disposed with
+///
+/// - [`Accepted`] if the handler returns `Ok(())`,
+/// - [`Rejected`] with `amqp:internal-error` and the error as description if the handler returns
+///   an error,
+/// - [`Rejected`] with `amqp:decode-error` if the body cannot be decoded,
+/// - [`Modified`] with `delivery-failed` if the handler panics, so that the delivery is
+///   redelivered. The panic is reported to the hook set with [`on_panic`](Self::on_panic),
+/// - [`Modified`] with `undeliverable-here` if there is no handler for the delivery.
+///
+/// The receiver is switched to [`CreditMode::Manual`] and the credit is kept such that at most
+/// [`max_concurrency`](Self::max_concurrency) deliveries are handled at the same time. The handler
+/// futures are polled on the task that runs [`run`](Self::run), so CPU intensive work should be
+/// moved to a separate task by the handler.
+pub struct ReceiverDispatcher {
+    routes: HashMap<String, AddressRoutes>,
+    fallback: Option<BoxedHandler>,
+    max_concurrency: SequenceNo,
+    panic_hook: Option<PanicHook>,
+}
+
+impl fmt::Debug for ReceiverDispatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let routes: Vec<(&String, Vec<&String>)> = self
+            .routes
+            .iter()
+            .map(|(address, routes)| (address, routes.by_subject.keys().collect()))
+            .collect();
+        f.debug_struct("ReceiverDispatcher")
+            .field("routes", &routes)
+            .field("fallback", &self.fallback.is_some())
+            .field("max_concurrency", &self.max_concurrency)
+            .finish()
+    }
+}
+
+impl Default for ReceiverDispatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReceiverDispatcher {
+    /// Creates a dispatcher without any handler
+    pub fn new() -> Self {
+        Self {
+            routes: HashMap::new(),
+            fallback: None,
+            max_concurrency: DEFAULT_CREDIT,
+            panic_hook: None,
+        }
+    }
+
+    /// Registers the handler of the messages on `address` whose subject has no handler
+    /// registered with [`on_subject`](Self::on_subject). This replaces the previous handler of
+    /// the address
+    pub fn on_address<T, E, F, Fut>(mut self, address: impl Into<String>, handler: F) -> Self
+    where
+        T: DeserializeOwned + Send + 'static,
+        E: fmt::Display,
+        F: Fn(Message<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+    {
+        self.routes.entry(address.into()).or_default().any_subject = Some(boxed(handler));
+        self
+    }
+
+    /// Registers the handler of the messages on `address` with `subject`. This replaces the
+    /// previous handler of the address and subject
+    pub fn on_subject<T, E, F, Fut>(
+        mut self,
+        address: impl Into<String>,
+        subject: impl Into<String>,
+        handler: F,
+    ) -> Self
+    where
+        T: DeserializeOwned + Send + 'static,
+        E: fmt::Display,
+        F: Fn(Message<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+    {
+        self.routes
+            .entry(address.into())
+            .or_default()
+            .by_subject
+            .insert(subject.into(), boxed(handler));
+        self
+    }
+
+    /// Registers the handler of the messages that no other handler is registered for
+    pub fn fallback<T, E, F, Fut>(mut self, handler: F) -> Self
+    where
+        T: DeserializeOwned + Send + 'static,
+        E: fmt::Display,
+        F: Fn(Message<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+    {
+        self.fallback = Some(boxed(handler));
+        self
+    }
+
+    /// Sets the maximum number of deliveries that are handled at the same time, which is also
+    /// the credit of the receiver. Defaults to [`DEFAULT_CREDIT`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_concurrency` is zero
+    pub fn max_concurrency(mut self, max_concurrency: SequenceNo) -> Self {
+        assert!(
+            max_concurrency > 0,
+            "max_concurrency must be greater than zero"
+        );
+        self.max_concurrency = max_concurrency;
+        self
+    }
+
+    /// Sets a hook that is called when a handler panics
+    pub fn on_panic(mut self, hook: impl Fn(&HandlerPanic) + Send + Sync + 'static) -> Self {
+        self.panic_hook = Some(Arc::new(hook));
+        self
+    }
+
+    fn handler(&self, address: Option<&String>, subject: Option<&String>) -> Option<&BoxedHandler> {
+        let routes = address.and_then(|address| self.routes.get(address));
+        routes
+            .and_then(|routes| {
+                subject
+                    .and_then(|subject| routes.by_subject.get(subject))
+                    .or(routes.any_subject.as_ref())
+            })
+            .or(self.fallback.as_ref())
+    }
+
+    /// Receives and dispatches the deliveries until the receiver fails, eg. when the link is
+    /// detached or closed by the remote peer, and returns the error
+    ///
+    /// The receivers of several addresses can be served by the same dispatcher by running one
+    /// `run` future per receiver. The handler futures that are still pending when an error is
+    /// returned, or when the returned future is dropped, are dropped and their deliveries are left
+    /// unsettled. The receiver stays in [`CreditMode::Manual`] afterwards.
+    pub async fn run(&self, receiver: &mut Receiver) -> DispatcherError {
+        let address = receiver
+            .source()
+            .as_ref()
+            .and_then(|source| source.address.clone());
+
+        // The credit is set right below, so the link doesn't need to be drained
+        receiver.inner.credit_mode = CreditMode::Manual;
+        receiver.set_auto_accept(false);
+        if let Err(error) = receiver.set_credit(self.max_concurrency).await {
+            return RecvError::LinkStateError(error.into()).into();
+        }
+
+        let mut handling = FuturesUnordered::new();
+        let mut outstanding: SequenceNo = 0;
+
+        let handle = |message: Message<Body<Value>>, delivery_tag: DeliveryTag| {
+            let subject = message
+                .properties
+                .as_ref()
+                .and_then(|properties| properties.subject.clone());
+            let handler = self.handler(address.as_ref(), subject.as_ref()).cloned();
+            let address = address.clone();
+            async move {
+                let handler = match handler {
+                    Some(handler) => handler,
+                    None => {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!(?address, ?subject, "No handler for the delivery");
+                        #[cfg(feature = "log")]
+                        log::warn!(
+                            "No handler for the delivery on address {:?} with subject {:?}",
+                            address,
+                            subject
+                        );
+                        return DeliveryState::Modified(Modified {
+                            delivery_failed: None,
+                            undeliverable_here: Some(true),
+                            message_annotations: None,
+                        });
+                    }
+                };
+                // The handler is called inside the future so that a panic before it returns its
+                // future is caught as well
+                let result = AssertUnwindSafe(async move { handler(message).await })
+                    .catch_unwind()
+                    .await;
+                result.unwrap_or_else(|payload| {
+                    let panic = HandlerPanic {
+                        address,
+                        subject,
+                        delivery_tag,
+                        message: payload
+                            .downcast_ref::<&str>()
+                            .map(|message| message.to_string())
+                            .or_else(|| payload.downcast_ref::<String>().cloned()),
+                    };
+                    #[cfg(feature = "tracing")]
+                    tracing::error!(%panic);
+                    #[cfg(feature = "log")]
+                    log::error!("{}", panic);
+                    if let Some(hook) = &self.panic_hook {
+                        hook(&panic);
+                    }
+                    DeliveryState::Modified(Modified {
+                        delivery_failed: Some(true),
+                        undeliverable_here: None,
+                        message_annotations: None,
+                    })
+                })
+            }
+        };
+
+        loop {
+            tokio::select! {
+                Some((info, state)) = handling.next() => {
+                    if let Err(error) = receiver.inner.dispose(info, None, state).await {
+                        return error.into();
+                    }
+                    outstanding -= 1;
+                    // Top up the credit so that the deliveries being handled and the credit add
+                    // up to `max_concurrency`
+                    let _ = receiver.set_credit(self.max_concurrency - outstanding).await;
+                }
+                // `Receiver::recv` is cancel safe
+                result = receiver.recv::<Body<Value>>(), if outstanding < self.max_concurrency => {
+                    let delivery = match result {
+                        Ok(delivery) => delivery,
+                        Err(error) => return error.into(),
+                    };
+                    outstanding += 1;
+                    let delivery_tag = delivery.delivery_tag().clone();
+                    let (info, message) = delivery.into_parts();
+                    let state = handle(message, delivery_tag);
+                    handling.push(async move { (info, state.await) });
+                }
+            }
+        }
+    }
+}
+
+fn boxed<T, E, F, Fut>(handler: F) -> BoxedHandler
+where
+    T: DeserializeOwned + Send + 'static,
+    E: fmt::Display,
+    F: Fn(Message<T>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), E>> + Send + 'static,
+{
+    Arc::new(move |message: Message<Body<Value>>| {
+        let message = match decode_message(message) {
+            Ok(message) => message,
+            Err(error) => {
+                let state = rejected(AmqpError::DecodeError, error.to_string());
+                return futures_util::future::ready(state).boxed();
+            }
+        };
+        let result = handler(message);
+        async move {
+            match result.await {
+                Ok(()) => DeliveryState::Accepted(Accepted {}),
+                Err(error) => rejected(AmqpError::InternalError, error.to_string()),
+            }
+        }
+        .boxed()
+    })
+}
+
+fn rejected(condition: AmqpError, description: String) -> DeliveryState {
+    DeliveryState::Rejected(Rejected {
+        error: Some(definitions::Error::new(condition, description, None)),
+    })
+}
+
+fn decode_message<T: DeserializeOwned>(
+    message: Message<Body<Value>>,
+) -> Result<Message<T>, serde_amqp::Error> {
+    let body = decode_body(message.body)?;
+    Ok(Message {
+        header: message.header,
+        delivery_annotations: message.delivery_annotations,
+        message_annotations: message.message_annotations,
+        properties: message.properties,
+        application_properties: message.application_properties,
+        body,
+        footer: message.footer,
+    })
+}
+
+fn decode_body<T: DeserializeOwned>(body: Body<Value>) -> Result<T, serde_amqp::Error> {
+    let value = match body {
+        Body::Value(AmqpValue(value)) => value,
+        Body::Data(sections) => match single_section(sections)? {
+            Data(bytes) => Value::Binary(bytes),
+        },
+        Body::Sequence(sections) => match single_section(sections)? {
+            AmqpSequence(values) => Value::List(values),
+        },
+        Body::Empty => Value::Null,
+    };
+    serde_amqp::from_value(value)
+}
+
+fn single_section<S>(sections: impl IntoIterator<Item = S>) -> Result<S, serde_amqp::Error> {
+    let mut sections = sections.into_iter();
+    match (sections.next(), sections.next()) {
+        (Some(section), None) => Ok(section),
+        _ => Err(serde::de::Error::custom("Expecting a single body section")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::{
+        messaging::{AmqpSequence, AmqpValue, Batch, Body, Data},
+        primitives::{Binary, Value},
+    };
+
+    use super::decode_body;
+
+    #[test]
+    fn bodies_are_decoded_into_the_handler_type() {
+        let body = Body::Value(AmqpValue(Value::String("hello".into())));
+        assert_eq!(decode_body::<String>(body).unwrap(), "hello");
+
+        let body = Body::Data(Batch::new(vec![Data(Binary::from(vec![1, 2]))]));
+        assert_eq!(
+            decode_body::<Binary>(body).unwrap(),
+            Binary::from(vec![1, 2])
+        );
+
+        let body = Body::Sequence(Batch::new(vec![AmqpSequence(vec![
+            Value::Int(1),
+            Value::Int(2),
+        ])]));
+        assert_eq!(decode_body::<Vec<i32>>(body).unwrap(), vec![1, 2]);
+
+        assert_eq!(decode_body::<Option<i32>>(Body::Empty).unwrap(), None);
+
+        let body = Body::Data(Batch::new(vec![
+            Data(Binary::from(vec![1])),
+            Data(Binary::from(vec![2])),
+        ]));
+        assert!(decode_body::<Binary>(body).is_err());
+
+        let body = Body::Value(AmqpValue(Value::Int(1)));
+        assert!(decode_body::<String>(body).is_err());
+    }
+}
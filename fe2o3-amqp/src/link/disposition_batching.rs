@@ -0,0 +1,160 @@
+//! Batching of the accepts of a receiver into ranged dispositions
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::delivery::DeliveryInfo;
+
+/// Default maximum time an accept is held back
+pub const DEFAULT_DISPOSITION_BATCHING_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Default maximum number of accepts that are held back before they are sent
+pub const DEFAULT_DISPOSITION_BATCHING_MAX_IDS: usize = 500;
+
+/// Configuration for merging the accepts of a receiver into ranged dispositions
+///
+/// At high message rates, sending one Disposition per accepted delivery costs as many frames as
+/// the deliveries themselves. With batching enabled, the accepts (including the ones of
+/// `auto_accept`) are held back and the consecutive delivery ids are sent as a range in a single
+/// Disposition. The pending accepts are sent
+///
+/// - once `max_ids` accepts are pending,
+/// - once the oldest pending accept is held back for `interval`, either when the next
+///   disposition is sent or while the receiver waits for the next delivery in `recv()`,
+/// - before any other disposition, so the dispositions are sent in order,
+/// - with [`Receiver::flush_dispositions()`](super::Receiver::flush_dispositions),
+/// - when the receiver is detached, closed or dropped.
+///
+/// The credit in `CreditMode::Auto` is replenished as the deliveries are accepted, regardless of
+/// whether the accepts are still held back.
+///
+/// | Field | Default Value |
+/// |-------|---------------|
+/// |`interval`| [`DEFAULT_DISPOSITION_BATCHING_INTERVAL`] |
+/// |`max_ids`| [`DEFAULT_DISPOSITION_BATCHING_MAX_IDS`] |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DispositionBatching {
+    /// The maximum time an accept is held back before it is sent
+    pub interval: Duration,
+
+    /// The maximum number of pending accepts. All pending accepts are sent once this is reached
+    pub max_ids: usize,
+}
+
+impl Default for DispositionBatching {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_DISPOSITION_BATCHING_INTERVAL,
+            max_ids: DEFAULT_DISPOSITION_BATCHING_MAX_IDS,
+        }
+    }
+}
+
+impl DispositionBatching {
+    /// Creates a new disposition batching configuration
+    pub fn new(interval: Duration, max_ids: usize) -> Self {
+        Self { interval, max_ids }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct DispositionBatcher {
+    config: DispositionBatching,
+    pending: Vec<DeliveryInfo>,
+
+    // When the oldest pending accept must be sent
+    deadline: Option<Instant>,
+}
+
+impl DispositionBatcher {
+    pub fn new(config: DispositionBatching) -> Self {
+        Self {
+            config,
+            pending: Vec::new(),
+            deadline: None,
+        }
+    }
+
+    pub fn config(&self) -> DispositionBatching {
+        self.config
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Holds back an accept. Returns whether the pending accepts should be sent
+    pub fn push(&mut self, info: DeliveryInfo, now: Instant) -> bool {
+        let deadline = *self
+            .deadline
+            .get_or_insert_with(|| now + self.config.interval);
+        self.pending.push(info);
+        self.pending.len() >= self.config.max_ids || now >= deadline
+    }
+
+    /// The pending accepts, which stay pending until they are removed with
+    /// [`remove_sent`](Self::remove_sent) so that they are not lost if sending them is cancelled
+    pub fn pending(&self) -> Vec<DeliveryInfo> {
+        self.pending.clone()
+    }
+
+    /// Removes the `count` oldest pending accepts once they are sent
+    pub fn remove_sent(&mut self, count: usize, now: Instant) {
+        self.pending.drain(..count.min(self.pending.len()));
+        self.deadline = match self.pending.is_empty() {
+            true => None,
+            // Accepts that are pushed while the others are being sent are not held back longer
+            false => Some(now),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fe2o3_amqp_types::{
+        definitions::{DeliveryTag, Handle},
+        messaging::Message,
+    };
+    use tokio::time::Instant;
+
+    use crate::link::delivery::{Delivery, DeliveryInfo};
+
+    use super::{DispositionBatcher, DispositionBatching};
+
+    fn info(delivery_id: u32) -> DeliveryInfo {
+        let delivery = Delivery {
+            link_output_handle: Handle(0),
+            delivery_id,
+            delivery_tag: DeliveryTag::from(delivery_id.to_be_bytes().to_vec()),
+            message_format: None,
+            rcv_settle_mode: None,
+            message: Message::builder().value(()).build(),
+            received_at: Instant::now(),
+        };
+        DeliveryInfo::from(delivery)
+    }
+
+    #[test]
+    fn accepts_are_sent_when_full_or_due() {
+        let config = DispositionBatching::new(Duration::from_millis(5), 3);
+        let mut batcher = DispositionBatcher::new(config);
+        let start = Instant::now();
+
+        assert!(batcher.deadline().is_none());
+        assert!(!batcher.push(info(0), start));
+        assert_eq!(batcher.deadline(), Some(start + Duration::from_millis(5)));
+        assert!(!batcher.push(info(1), start));
+        assert!(batcher.push(info(2), start));
+
+        let pending = batcher.pending();
+        assert_eq!(pending.len(), 3);
+        batcher.remove_sent(pending.len(), start);
+        assert!(batcher.deadline().is_none());
+
+        assert!(!batcher.push(info(3), start));
+        assert!(batcher.push(info(4), start + Duration::from_millis(5)));
+    }
+}
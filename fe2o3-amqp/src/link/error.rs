@@ -1,4 +1,6 @@
-use fe2o3_amqp_types::definitions::{self, AmqpError, ErrorCondition, SessionError};
+use fe2o3_amqp_types::definitions::{
+    self, AmqpError, DeliveryTag, ErrorCondition, MessageFormat, SessionError,
+};
 use serde_amqp::primitives::Symbol;
 use tokio::sync::TryLockError;
 
@@ -7,7 +9,7 @@ use crate::session::error::AllocLinkError;
 #[cfg(docsrs)]
 use fe2o3_amqp_types::transaction::Coordinator;
 
-use super::{receiver::DetachedReceiver, sender::DetachedSender};
+use super::{delivery::Sendable, receiver::DetachedReceiver, sender::DetachedSender};
 
 /// Error associated with detaching
 #[derive(Debug, thiserror::Error)]
@@ -38,6 +40,23 @@ pub enum DetachError {
     /// Remote peer closed the link with an error
     #[error("Remote peer closed the link with an error: {}", .0)]
     RemoteClosedWithError(definitions::Error),
+
+    /// The remote peer did not reply with a Detach before the configured detach timeout. The link
+    /// is detached locally and removed from the session
+    #[error("Timed out waiting for the remote Detach")]
+    Timeout,
+}
+
+impl DetachError {
+    /// Get a reference to the error sent by the remote peer, if any
+    pub fn remote_error(&self) -> Option<&definitions::Error> {
+        match self {
+            Self::RemoteDetachedWithError(error) | Self::RemoteClosedWithError(error) => {
+                Some(error)
+            }
+            _ => None,
+        }
+    }
 }
 
 /// Errors associated with attaching a link as sender
@@ -116,6 +135,64 @@ pub enum SenderAttachError {
     /// Remote peer closed the link with an error
     #[error("Remote peer closed with error {:?}", .0)]
     RemoteClosedWithError(definitions::Error),
+
+    /// The sender settle mode `Settled` is configured together with the receiver settle mode
+    /// `Second`, which only applies to unsettled deliveries
+    #[error("Sender settle mode Settled cannot be used with receiver settle mode Second")]
+    IncompatibleSettleModes,
+
+    /// The combination of settle modes desired by the remote peer is refused by the
+    /// [`SettleModePolicy`](crate::acceptor::SettleModePolicy) of the listener
+    #[cfg(feature = "acceptor")]
+    #[error("The desired combination of settle modes is not supported")]
+    SettleModesNotSupported,
+
+    /// The link is denied by the [`Authorizer`](crate::acceptor::Authorizer) of the listener.
+    /// The link is closed with the error
+    #[cfg(feature = "acceptor")]
+    #[error("The link is not authorized {}", .0)]
+    Unauthorized(definitions::Error),
+
+    /// The configured buffer size is zero
+    #[error("Buffer size must be greater than zero")]
+    ZeroBufferSize,
+}
+
+/// Error with sending a message with a [`BufferedSender`](super::BufferedSender)
+#[derive(Debug, thiserror::Error)]
+pub enum BufferedSendError<T> {
+    /// The buffer is full and the overflow policy is
+    /// [`OverflowPolicy::Reject`](super::OverflowPolicy::Reject). The message is handed back
+    #[error("The buffer is full")]
+    BufferFull(Box<Sendable<T>>),
+
+    /// The message cannot be sent
+    #[error(transparent)]
+    Send(#[from] SendError),
+}
+
+/// Error that stops a [`MessageGroupProcessor`](super::message_group::MessageGroupProcessor)
+#[derive(Debug, thiserror::Error)]
+pub enum MessageGroupError {
+    /// The receiver failed to receive a delivery
+    #[error(transparent)]
+    Recv(#[from] RecvError),
+
+    /// The outcome of a delivery cannot be sent
+    #[error(transparent)]
+    Disposition(#[from] DispositionError),
+}
+
+/// Error that stops a [`ReceiverDispatcher`](super::dispatcher::ReceiverDispatcher)
+#[derive(Debug, thiserror::Error)]
+pub enum DispatcherError {
+    /// The receiver failed to receive a delivery
+    #[error(transparent)]
+    Recv(#[from] RecvError),
+
+    /// The outcome of a delivery cannot be sent
+    #[error(transparent)]
+    Disposition(#[from] DispositionError),
 }
 
 /// Error associated with sending a message
@@ -141,6 +218,26 @@ pub enum SendError {
     /// Error serializing message
     #[error("Error encoding message")]
     MessageEncodeError,
+
+    /// The delivery is not settled before the deadline of the message. See
+    /// [`Builder::deadline()`](crate::link::delivery::Builder::deadline)
+    #[error("The deadline elapsed before the delivery is settled")]
+    DeadlineElapsed,
+}
+
+impl SendError {
+    /// Get a reference to the error sent by the remote peer, if any
+    ///
+    /// The full error, including the `info` fields, is preserved. A rejected delivery is not a
+    /// [`SendError`], and its error can be found with
+    /// [`Outcome::rejected_error`](fe2o3_amqp_types::messaging::Outcome::rejected_error)
+    pub fn remote_error(&self) -> Option<&definitions::Error> {
+        match self {
+            Self::LinkStateError(error) => error.remote_error(),
+            Self::Detached(error) => error.remote_error(),
+            _ => None,
+        }
+    }
 }
 
 impl From<serde_amqp::Error> for SendError {
@@ -272,6 +369,27 @@ pub enum ReceiverAttachError {
     /// The desired filter(s) on the receiver is not supported by the remote peer
     #[error("{:?}", .0)]
     DesiredFilterNotSupported(#[from] DesiredFilterNotSupported),
+
+    /// The sender settle mode `Settled` is configured together with the receiver settle mode
+    /// `Second`, which only applies to unsettled deliveries
+    #[error("Sender settle mode Settled cannot be used with receiver settle mode Second")]
+    IncompatibleSettleModes,
+
+    /// The combination of settle modes desired by the remote peer is refused by the
+    /// [`SettleModePolicy`](crate::acceptor::SettleModePolicy) of the listener
+    #[cfg(feature = "acceptor")]
+    #[error("The desired combination of settle modes is not supported")]
+    SettleModesNotSupported,
+
+    /// The link is denied by the [`Authorizer`](crate::acceptor::Authorizer) of the listener.
+    /// The link is closed with the error
+    #[cfg(feature = "acceptor")]
+    #[error("The link is not authorized {}", .0)]
+    Unauthorized(definitions::Error),
+
+    /// The configured buffer size is zero
+    #[error("Buffer size must be greater than zero")]
+    ZeroBufferSize,
 }
 
 impl From<AllocLinkError> for ReceiverAttachError {
@@ -304,6 +422,10 @@ impl<'a> TryFrom<&'a ReceiverAttachError> for definitions::Error {
             ReceiverAttachError::DynamicNodePropertiesIsSomeWhenDynamicIsFalse => {
                 AmqpError::InvalidField.into()
             }
+            #[cfg(feature = "acceptor")]
+            ReceiverAttachError::SettleModesNotSupported => AmqpError::NotImplemented.into(),
+            #[cfg(feature = "acceptor")]
+            ReceiverAttachError::Unauthorized(error) => return Ok(error.clone()),
             _ => return Err(value),
         };
 
@@ -333,7 +455,9 @@ impl TryFrom<DetachError> for SenderAttachError {
                 Ok(Self::RemoteClosedWithError(error))
             }
             // DetachError::NonDetachFrameReceived
-            DetachError::ClosedByRemote | DetachError::DetachedByRemote => Err(value),
+            DetachError::ClosedByRemote | DetachError::DetachedByRemote | DetachError::Timeout => {
+                Err(value)
+            }
         }
     }
 }
@@ -351,7 +475,9 @@ impl TryFrom<DetachError> for ReceiverAttachError {
                 Ok(Self::RemoteClosedWithError(error))
             }
             // DetachError::NonDetachFrameReceived
-            DetachError::ClosedByRemote | DetachError::DetachedByRemote => Err(value),
+            DetachError::ClosedByRemote | DetachError::DetachedByRemote | DetachError::Timeout => {
+                Err(value)
+            }
         }
     }
 }
@@ -376,6 +502,10 @@ impl<'a> TryFrom<&'a SenderAttachError> for definitions::Error {
             SenderAttachError::SourceAddressIsSomeWhenDynamicIsTrue => {
                 AmqpError::InvalidField.into()
             }
+            #[cfg(feature = "acceptor")]
+            SenderAttachError::SettleModesNotSupported => AmqpError::NotImplemented.into(),
+            #[cfg(feature = "acceptor")]
+            SenderAttachError::Unauthorized(error) => return Ok(error.clone()),
 
             #[cfg(feature = "transaction")]
             SenderAttachError::DesireTxnCapabilitiesNotSupported => return Err(value),
@@ -447,6 +577,18 @@ pub enum LinkStateError {
     ExpectImmediateDetach,
 }
 
+impl LinkStateError {
+    /// Get a reference to the error sent by the remote peer, if any
+    pub fn remote_error(&self) -> Option<&definitions::Error> {
+        match self {
+            Self::RemoteDetachedWithError(error) | Self::RemoteClosedWithError(error) => {
+                Some(error)
+            }
+            _ => None,
+        }
+    }
+}
+
 impl From<DetachError> for LinkStateError {
     fn from(value: DetachError) -> Self {
         match value {
@@ -456,6 +598,7 @@ impl From<DetachError> for LinkStateError {
             DetachError::ClosedByRemote => Self::RemoteClosed,
             DetachError::DetachedByRemote => Self::RemoteDetached,
             DetachError::RemoteClosedWithError(error) => Self::RemoteClosedWithError(error),
+            DetachError::Timeout => Self::ExpectImmediateDetach,
             // DetachError::NonDetachFrameReceived => Self::ExpectImmediateDetach,
         }
     }
@@ -492,6 +635,23 @@ pub(crate) enum ReceiverTransferError {
     /// Field is inconsisten in multi-frame delivery
     #[error("Field is inconsisten in multi-frame delivery")]
     InconsistentFieldInMultiFrameDelivery,
+
+    /// No codec is registered for the message format
+    #[error("No codec is registered for message format {}", .0)]
+    UnknownMessageFormat(MessageFormat),
+
+    /// The delivery tag of a new delivery is in use by an unsettled delivery
+    #[error("Delivery tag {:?} is in use by an unsettled delivery", .0)]
+    DeliveryTagReused(DeliveryTag),
+
+    /// The resumed delivery is not found among the unsettled deliveries
+    #[error("Resumed delivery tag {:?} is not found among the unsettled deliveries", .0)]
+    ConflictingResume(DeliveryTag),
+
+    /// Failed to read or write the spool file of a delivery
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("Failed to spool the delivery: {}", .0)]
+    SpoolError(std::io::Error),
 }
 
 /// Errors associated with receiving
@@ -529,6 +689,26 @@ pub enum RecvError {
     /// Transactional acquision is not supported yet
     #[error("Transactional acquisition is not implemented")]
     TransactionalAcquisitionIsNotImeplemented,
+
+    /// No codec is registered for the message format of a delivery that is received with
+    /// `recv_custom()`
+    #[error("No codec is registered for message format {}", .0)]
+    UnknownMessageFormat(MessageFormat),
+
+    /// The peer reused the delivery tag of an unsettled delivery for a new delivery. The link is
+    /// detached with an `amqp:not-allowed` error
+    #[error("Delivery tag {:?} is in use by an unsettled delivery", .0)]
+    DeliveryTagReused(DeliveryTag),
+
+    /// The peer resumed a delivery that is not found among the unsettled deliveries. The link is
+    /// detached with an `amqp:not-allowed` error
+    #[error("Resumed delivery tag {:?} is not found among the unsettled deliveries", .0)]
+    ConflictingResume(DeliveryTag),
+
+    /// Failed to write a large delivery to its spool file or to read it back
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("Failed to spool the delivery: {}", .0)]
+    SpoolError(std::io::Error),
 }
 
 impl From<ReceiverTransferError> for RecvError {
@@ -547,6 +727,13 @@ impl From<ReceiverTransferError> for RecvError {
             ReceiverTransferError::IllegalState => {
                 RecvError::LinkStateError(LinkStateError::IllegalState)
             }
+            ReceiverTransferError::UnknownMessageFormat(format) => {
+                RecvError::UnknownMessageFormat(format)
+            }
+            ReceiverTransferError::DeliveryTagReused(tag) => RecvError::DeliveryTagReused(tag),
+            ReceiverTransferError::ConflictingResume(tag) => RecvError::ConflictingResume(tag),
+            #[cfg(not(target_arch = "wasm32"))]
+            ReceiverTransferError::SpoolError(error) => RecvError::SpoolError(error),
         }
     }
 }
@@ -578,6 +765,32 @@ impl From<IllegalLinkStateError> for LinkStateError {
     }
 }
 
+/// Errors with settling a delivery from the sender side
+#[derive(Debug, thiserror::Error)]
+pub enum SettleError {
+    /// Illegal local state
+    #[error("Illegal local state")]
+    IllegalState,
+
+    /// Session has dropped
+    #[error("Session has dropped")]
+    IllegalSessionState,
+
+    /// The delivery is not found among the unsettled deliveries. It may be pre-settled or already
+    /// settled by the remote receiver
+    #[error("Delivery is not found among the unsettled deliveries")]
+    DeliveryNotFound,
+}
+
+impl From<IllegalLinkStateError> for SettleError {
+    fn from(value: IllegalLinkStateError) -> Self {
+        match value {
+            IllegalLinkStateError::IllegalState => Self::IllegalState,
+            IllegalLinkStateError::IllegalSessionState => Self::IllegalSessionState,
+        }
+    }
+}
+
 impl From<IllegalLinkStateError> for ReceiverAttachError {
     fn from(value: IllegalLinkStateError) -> Self {
         match value {
@@ -762,6 +975,18 @@ impl From<LinkRelayError> for definitions::Error {
     }
 }
 
+/// Error with attaching a [`LinkPair`](super::LinkPair)
+#[derive(Debug, thiserror::Error)]
+pub enum LinkPairAttachError {
+    /// Error with attaching the sender
+    #[error(transparent)]
+    Sender(#[from] SenderAttachError),
+
+    /// Error with attaching the receiver. The sender is closed in this case
+    #[error(transparent)]
+    Receiver(#[from] ReceiverAttachError),
+}
+
 /// Error with `Sender::detach_then_resume_on_session`
 #[derive(Debug, thiserror::Error)]
 pub enum DetachThenResumeSenderError {
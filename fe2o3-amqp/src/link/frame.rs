@@ -1,13 +1,14 @@
-use fe2o3_amqp_types::performatives::{Attach, Detach, Disposition, Transfer};
+use fe2o3_amqp_types::{
+    definitions::DeliveryTag,
+    messaging::DeliveryState,
+    performatives::{Attach, Detach, Disposition, Transfer},
+};
 
 use crate::{
     endpoint::{InputHandle, LinkFlow},
     Payload,
 };
 
-#[cfg(feature = "transaction")]
-use fe2o3_amqp_types::transaction::TransactionId;
-
 pub(crate) type LinkIncomingItem = LinkFrame;
 
 /// Link frames.
@@ -23,12 +24,15 @@ pub(crate) enum LinkFrame {
         payload: Payload,
     },
     Disposition(Disposition),
-    Detach(Detach),
 
-    #[cfg(feature = "transaction")]
-    /// Indicating to the receiver that Txn controller side is requesting for
-    /// a transactional acquisition
-    Acquisition(TransactionId),
+    /// Settlement of an unsettled delivery by the sender. The session resolves the delivery-id
+    /// from the delivery-tag as the sender does not know the delivery-id
+    Settle {
+        input_handle: InputHandle,
+        delivery_tag: DeliveryTag,
+        state: Option<DeliveryState>,
+    },
+    Detach(Detach),
 }
 
 impl std::fmt::Debug for LinkFrame {
@@ -47,9 +51,17 @@ impl std::fmt::Debug for LinkFrame {
                 .field("payload.len", &payload.len())
                 .finish(),
             Self::Disposition(arg0) => f.debug_tuple("Disposition").field(arg0).finish(),
+            Self::Settle {
+                input_handle,
+                delivery_tag,
+                state,
+            } => f
+                .debug_struct("Settle")
+                .field("input_handle", input_handle)
+                .field("delivery_tag", delivery_tag)
+                .field("state", state)
+                .finish(),
             Self::Detach(arg0) => f.debug_tuple("Detach").field(arg0).finish(),
-            #[cfg(feature = "transaction")]
-            Self::Acquisition(arg0) => f.debug_tuple("Acquisition").field(arg0).finish(),
         }
     }
 }
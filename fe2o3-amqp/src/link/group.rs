@@ -0,0 +1,444 @@
+//! Competing consumers on the same address
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use fe2o3_amqp_types::{
+    definitions::{self, SequenceNo},
+    messaging::{
+        Accepted, Address, DeliveryState, FromBody, Message, Modified, Rejected, Released,
+    },
+};
+use futures_util::Stream;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::session::SessionHandle;
+
+use super::{
+    delivery::{Delivery, DeliveryInfo},
+    receiver::CreditMode,
+    DetachError, DispositionError, IllegalLinkStateError, Receiver, ReceiverAttachError, RecvError,
+};
+
+/// A delivery received by one of the members of a [`ReceiverGroup`]
+#[derive(Debug)]
+pub struct GroupDelivery<T> {
+    member: usize,
+    delivery: Delivery<T>,
+}
+
+impl<T> GroupDelivery<T> {
+    /// Index of the member that received the delivery
+    pub fn member(&self) -> usize {
+        self.member
+    }
+
+    /// Get a reference to the delivery
+    pub fn delivery(&self) -> &Delivery<T> {
+        &self.delivery
+    }
+
+    /// Get the message
+    pub fn message(&self) -> &Message<T> {
+        self.delivery.message()
+    }
+
+    /// Get the body
+    pub fn body(&self) -> &T {
+        self.delivery.body()
+    }
+
+    /// Consumes the delivery into the info needed for disposition and the message
+    pub fn into_parts(self) -> (GroupDeliveryInfo, Message<T>) {
+        let (info, message) = self.delivery.into_parts();
+        let info = GroupDeliveryInfo {
+            member: self.member,
+            info,
+        };
+        (info, message)
+    }
+}
+
+/// Information that is needed for disposing a delivery received by a [`ReceiverGroup`]
+#[derive(Debug, Clone)]
+pub struct GroupDeliveryInfo {
+    member: usize,
+    info: DeliveryInfo,
+}
+
+impl GroupDeliveryInfo {
+    /// Index of the member that received the delivery
+    pub fn member(&self) -> usize {
+        self.member
+    }
+
+    /// Get the delivery info
+    pub fn delivery_info(&self) -> &DeliveryInfo {
+        &self.info
+    }
+}
+
+impl<T> From<&GroupDelivery<T>> for GroupDeliveryInfo {
+    fn from(delivery: &GroupDelivery<T>) -> Self {
+        Self {
+            member: delivery.member,
+            info: DeliveryInfo::from(&delivery.delivery),
+        }
+    }
+}
+
+enum Command {
+    Dispose {
+        info: DeliveryInfo,
+        state: DeliveryState,
+        resp: oneshot::Sender<Result<(), DispositionError>>,
+    },
+    Close {
+        resp: oneshot::Sender<Result<(), DetachError>>,
+    },
+}
+
+/// A group of [`Receiver`]s on the same address whose deliveries are merged into one [`Stream`]
+///
+/// This is the typical work queue setup with competing consumers. The receivers can be attached
+/// on different sessions and connections, and each receiver is driven by its own task.
+///
+/// Each receiver is switched to [`CreditMode::Manual`] and keeps at most `prefetch` deliveries
+/// that are received but not yet disposed. The credit of a receiver is only replenished when its
+/// deliveries are disposed, so a slow worker does not hoard messages that other receivers could
+/// take. Every delivery is therefore expected to be disposed with a [`GroupDisposer`], which sends
+/// the disposition on the link that received the delivery.
+///
+/// A receiver whose link fails yields the error once and then leaves the group. The stream ends
+/// once every receiver has left the group.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use futures_util::StreamExt;
+/// use fe2o3_amqp::link::ReceiverGroup;
+///
+/// let mut group = ReceiverGroup::<String>::attach(
+///     vec![&mut session1, &mut session2], "workers", "q1", 10
+/// ).await.unwrap();
+/// let disposer = group.disposer();
+/// while let Some(delivery) = group.next().await {
+///     let delivery = delivery.unwrap();
+///     let disposer = disposer.clone();
+///     tokio::spawn(async move {
+///         // process the delivery
+///         disposer.accept(&delivery).await.unwrap();
+///     });
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ReceiverGroup<T> {
+    deliveries: mpsc::UnboundedReceiver<Result<GroupDelivery<T>, RecvError>>,
+    disposer: GroupDisposer,
+}
+
+impl<T> ReceiverGroup<T>
+where
+    for<'de> T: FromBody<'de> + Send + Sync + 'static,
+{
+    /// Creates a group from attached receivers. Each receiver keeps at most `prefetch` deliveries
+    /// that are not yet disposed
+    ///
+    /// This must be called inside a tokio runtime because each receiver is driven by a spawned
+    /// task.
+    pub fn new(receivers: Vec<Receiver>, prefetch: SequenceNo) -> Self {
+        let (deliveries_tx, deliveries) = mpsc::unbounded_channel();
+        let members = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(index, receiver)| {
+                let (tx, rx) = mpsc::unbounded_channel();
+                tokio::spawn(run_member(
+                    index,
+                    receiver,
+                    prefetch,
+                    deliveries_tx.clone(),
+                    rx,
+                ));
+                tx
+            })
+            .collect();
+        Self {
+            deliveries,
+            disposer: GroupDisposer { members },
+        }
+    }
+
+    /// Attaches one receiver to the address on each session. The receivers are named
+    /// `"<base_name>-<index>"`
+    ///
+    /// The receivers that are already attached are closed if any receiver fails to attach.
+    pub async fn attach<'a, R: 'a>(
+        sessions: impl IntoIterator<Item = &'a mut SessionHandle<R>>,
+        base_name: impl Into<String>,
+        address: impl Into<Address>,
+        prefetch: SequenceNo,
+    ) -> Result<Self, ReceiverAttachError> {
+        let base_name = base_name.into();
+        let address = address.into();
+        let mut receivers = Vec::new();
+        for (index, session) in sessions.into_iter().enumerate() {
+            let result = Receiver::builder()
+                .name(format!("{}-{}", base_name, index))
+                .source(address.clone())
+                .credit_mode(CreditMode::Manual)
+                .attach(session)
+                .await;
+            match result {
+                Ok(receiver) => receivers.push(receiver),
+                Err(error) => {
+                    for receiver in receivers {
+                        let _ = receiver.close().await;
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        Ok(Self::new(receivers, prefetch))
+    }
+}
+
+impl<T> ReceiverGroup<T> {
+    /// Number of receivers that joined the group, including the receivers that have left
+    pub fn len(&self) -> usize {
+        self.disposer.members.len()
+    }
+
+    /// Whether the group has no receiver at all
+    pub fn is_empty(&self) -> bool {
+        self.disposer.members.is_empty()
+    }
+
+    /// Get a handle that disposes the deliveries of this group
+    pub fn disposer(&self) -> GroupDisposer {
+        self.disposer.clone()
+    }
+
+    /// Waits for the next delivery from any receiver. `None` is returned once every receiver has
+    /// left the group
+    pub async fn recv(&mut self) -> Option<Result<GroupDelivery<T>, RecvError>> {
+        self.deliveries.recv().await
+    }
+
+    /// Accepts the delivery. See [`GroupDisposer::accept`]
+    pub async fn accept(
+        &self,
+        delivery_info: impl Into<GroupDeliveryInfo>,
+    ) -> Result<(), DispositionError> {
+        self.disposer.accept(delivery_info).await
+    }
+
+    /// Rejects the delivery. See [`GroupDisposer::reject`]
+    pub async fn reject(
+        &self,
+        delivery_info: impl Into<GroupDeliveryInfo>,
+        error: impl Into<Option<definitions::Error>>,
+    ) -> Result<(), DispositionError> {
+        self.disposer.reject(delivery_info, error).await
+    }
+
+    /// Releases the delivery. See [`GroupDisposer::release`]
+    pub async fn release(
+        &self,
+        delivery_info: impl Into<GroupDeliveryInfo>,
+    ) -> Result<(), DispositionError> {
+        self.disposer.release(delivery_info).await
+    }
+
+    /// Modifies the delivery. See [`GroupDisposer::modify`]
+    pub async fn modify(
+        &self,
+        delivery_info: impl Into<GroupDeliveryInfo>,
+        modified: Modified,
+    ) -> Result<(), DispositionError> {
+        self.disposer.modify(delivery_info, modified).await
+    }
+
+    /// Closes all receivers concurrently
+    ///
+    /// All receivers are always closed. The first error is returned if any fails. Receivers that
+    /// have already left the group are skipped.
+    pub async fn close(self) -> Result<(), DetachError> {
+        let closing = self.disposer.members.iter().filter_map(|member| {
+            let (resp, rx) = oneshot::channel();
+            member.send(Command::Close { resp }).ok().map(|_| rx)
+        });
+        let results = futures_util::future::join_all(closing).await;
+        results
+            .into_iter()
+            // The task has already stopped if the response is dropped
+            .filter_map(Result::ok)
+            .collect()
+    }
+}
+
+impl<T> Stream for ReceiverGroup<T> {
+    type Item = Result<GroupDelivery<T>, RecvError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.deliveries.poll_recv(cx)
+    }
+}
+
+/// A cloneable handle that sends the disposition of a delivery on the link of the
+/// [`ReceiverGroup`] member that received it
+#[derive(Debug, Clone)]
+pub struct GroupDisposer {
+    members: Vec<mpsc::UnboundedSender<Command>>,
+}
+
+impl std::fmt::Debug for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Dispose { info, state, .. } => f
+                .debug_struct("Dispose")
+                .field("info", info)
+                .field("state", state)
+                .finish(),
+            Self::Close { .. } => f.debug_struct("Close").finish(),
+        }
+    }
+}
+
+impl GroupDisposer {
+    /// Accepts the delivery
+    pub async fn accept(
+        &self,
+        delivery_info: impl Into<GroupDeliveryInfo>,
+    ) -> Result<(), DispositionError> {
+        let state = DeliveryState::Accepted(Accepted {});
+        self.dispose(delivery_info.into(), state).await
+    }
+
+    /// Rejects the delivery
+    pub async fn reject(
+        &self,
+        delivery_info: impl Into<GroupDeliveryInfo>,
+        error: impl Into<Option<definitions::Error>>,
+    ) -> Result<(), DispositionError> {
+        let state = DeliveryState::Rejected(Rejected {
+            error: error.into(),
+        });
+        self.dispose(delivery_info.into(), state).await
+    }
+
+    /// Releases the delivery
+    pub async fn release(
+        &self,
+        delivery_info: impl Into<GroupDeliveryInfo>,
+    ) -> Result<(), DispositionError> {
+        let state = DeliveryState::Released(Released {});
+        self.dispose(delivery_info.into(), state).await
+    }
+
+    /// Modifies the delivery
+    pub async fn modify(
+        &self,
+        delivery_info: impl Into<GroupDeliveryInfo>,
+        modified: Modified,
+    ) -> Result<(), DispositionError> {
+        let state = DeliveryState::Modified(modified);
+        self.dispose(delivery_info.into(), state).await
+    }
+
+    async fn dispose(
+        &self,
+        delivery_info: GroupDeliveryInfo,
+        state: DeliveryState,
+    ) -> Result<(), DispositionError> {
+        let member = self
+            .members
+            .get(delivery_info.member)
+            .ok_or(IllegalLinkStateError::IllegalState)?;
+        let (resp, rx) = oneshot::channel();
+        member
+            .send(Command::Dispose {
+                info: delivery_info.info,
+                state,
+                resp,
+            })
+            .map_err(|_| IllegalLinkStateError::IllegalState)?;
+        rx.await.map_err(|_| IllegalLinkStateError::IllegalState)?
+    }
+}
+
+async fn run_member<T>(
+    index: usize,
+    mut receiver: Receiver,
+    prefetch: SequenceNo,
+    deliveries: mpsc::UnboundedSender<Result<GroupDelivery<T>, RecvError>>,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+) where
+    for<'de> T: FromBody<'de> + Send + Sync + 'static,
+{
+    // The credit is set right below, so the link doesn't need to be drained
+    receiver.inner.credit_mode = CreditMode::Manual;
+    receiver.set_auto_accept(false);
+
+    // Number of deliveries that are received but not yet disposed
+    let mut outstanding: SequenceNo = 0;
+    if let Err(error) = receiver.set_credit(prefetch).await {
+        let _ = deliveries.send(Err(RecvError::LinkStateError(error.into())));
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Dispose { info, state, resp }) => {
+                    let result = receiver.inner.dispose(info, None, state).await;
+                    if result.is_ok() {
+                        outstanding = outstanding.saturating_sub(1);
+                        // Top up the credit so that the received but not yet disposed deliveries
+                        // and the credit add up to `prefetch`
+                        let _ = receiver.set_credit(prefetch - outstanding).await;
+                    }
+                    let _ = resp.send(result);
+                }
+                Some(Command::Close { resp }) => {
+                    let _ = resp.send(receiver.close().await);
+                    return;
+                }
+                None => {
+                    let _ = receiver.close().await;
+                    return;
+                }
+            },
+            // `Receiver::recv` is cancel safe
+            result = receiver.recv::<T>(), if outstanding < prefetch => match result {
+                Ok(delivery) => {
+                    outstanding += 1;
+                    let _ = deliveries.send(Ok(GroupDelivery {
+                        member: index,
+                        delivery,
+                    }));
+                }
+                Err(error) => {
+                    let _ = deliveries.send(Err(error));
+                    // Leave the group so that the stream ends once every receiver has left
+                    drop(deliveries);
+                    // Keep serving the commands so that pending dispositions are answered
+                    while let Some(command) = commands.recv().await {
+                        match command {
+                            Command::Dispose { resp, .. } => {
+                                let _ = resp.send(Err(IllegalLinkStateError::IllegalState));
+                            }
+                            Command::Close { resp } => {
+                                let _ = resp.send(receiver.close().await);
+                                return;
+                            }
+                        }
+                    }
+                    return;
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! A sender that stamps the outgoing messages with a producer id and a sequence number
+
+use std::collections::VecDeque;
+
+use fe2o3_amqp_types::{
+    messaging::{
+        annotations::{AnnotationKey, OwnedKey},
+        Message, MessageAnnotations, Outcome, SerializableBody,
+    },
+    primitives::Value,
+};
+
+use crate::session::SessionHandle;
+
+use super::{delivery::Sendable, DetachThenResumeSenderError, LinkStateError, SendError, Sender};
+
+/// Key of the producer id in the message annotations
+pub const PRODUCER_ID_KEY: &str = "x-opt-producer-id";
+
+/// Key of the sequence number in the message annotations
+pub const PRODUCER_SEQUENCE_KEY: &str = "x-opt-producer-sequence";
+
+/// The producer id and the sequence number that an [`IdempotentSender`] stamps on a message
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProducerStamp {
+    /// Id of the producer
+    pub producer_id: String,
+
+    /// Sequence number of the message, which increases by one for every message of the producer
+    pub sequence: u64,
+}
+
+impl ProducerStamp {
+    /// Reads the stamp from the message annotations of a message
+    pub fn from_message<T>(message: &Message<T>) -> Option<Self> {
+        let annotations = message.message_annotations.as_ref()?;
+        let producer_id = match annotations.get(&PRODUCER_ID_KEY as &dyn AnnotationKey)? {
+            Value::String(producer_id) => producer_id.clone(),
+            _ => return None,
+        };
+        let sequence = match annotations.get(&PRODUCER_SEQUENCE_KEY as &dyn AnnotationKey)? {
+            Value::Ulong(sequence) => *sequence,
+            _ => return None,
+        };
+        Some(Self {
+            producer_id,
+            sequence,
+        })
+    }
+
+    fn stamp<T>(&self, message: &mut Message<T>) {
+        let annotations = message
+            .message_annotations
+            .get_or_insert_with(|| MessageAnnotations(Default::default()));
+        annotations.insert(
+            OwnedKey::from(PRODUCER_ID_KEY),
+            Value::String(self.producer_id.clone()),
+        );
+        annotations.insert(
+            OwnedKey::from(PRODUCER_SEQUENCE_KEY),
+            Value::Ulong(self.sequence),
+        );
+    }
+}
+
+/// A [`Sender`] that makes the re-sent messages recognizable as duplicates
+///
+/// Every message is stamped with the id of the producer and a sequence number in the message
+/// annotations (under [`PRODUCER_ID_KEY`] and [`PRODUCER_SEQUENCE_KEY`]) before it is sent. The
+/// sequence number starts at zero, or at the number given to
+/// [`with_next_sequence`](#method.with_next_sequence), and increases by one for every message.
+///
+/// If the link is lost before the outcome of a message is known, the stamped message is kept and
+/// is sent again with the same producer id and sequence number by
+/// [`reconnect`](#method.reconnect). A broker or a consumer that remembers the highest sequence
+/// number of each producer can then drop the copies of a message that it has already received,
+/// for example with [`ProducerStamp::from_message`].
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let sender = Sender::attach(&mut session, "orders", "q1").await.unwrap();
+/// let mut sender = IdempotentSender::new(sender, "order-service-1");
+///
+/// if let Err(error) = sender.send("order").await {
+///     // After the connection is re-established
+///     for (sequence, result) in sender.reconnect(&new_session).await.unwrap() {
+///         println!("{}: {:?}", sequence, result);
+///     }
+/// }
+/// ```
+pub struct IdempotentSender<T> {
+    sender: Sender,
+    producer_id: String,
+    next_sequence: u64,
+
+    /// Stamped messages whose outcome is not known because the link was lost, in the order of
+    /// their sequence numbers
+    unconfirmed: VecDeque<(u64, Sendable<T>)>,
+}
+
+impl<T> std::fmt::Debug for IdempotentSender<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdempotentSender")
+            .field("sender", &self.sender)
+            .field("producer_id", &self.producer_id)
+            .field("next_sequence", &self.next_sequence)
+            .field("unconfirmed", &self.unconfirmed.len())
+            .finish()
+    }
+}
+
+impl<T> IdempotentSender<T>
+where
+    T: SerializableBody,
+{
+    /// Wraps an attached [`Sender`]. The sequence numbers start at zero
+    pub fn new(sender: Sender, producer_id: impl Into<String>) -> Self {
+        Self::with_next_sequence(sender, producer_id, 0)
+    }
+
+    /// Wraps an attached [`Sender`] and continues the sequence of a producer, eg. after the
+    /// application restarts
+    pub fn with_next_sequence(
+        sender: Sender,
+        producer_id: impl Into<String>,
+        next_sequence: u64,
+    ) -> Self {
+        Self {
+            sender,
+            producer_id: producer_id.into(),
+            next_sequence,
+            unconfirmed: VecDeque::new(),
+        }
+    }
+
+    /// Get a reference to the wrapped [`Sender`]
+    pub fn sender(&self) -> &Sender {
+        &self.sender
+    }
+
+    /// Id of the producer
+    pub fn producer_id(&self) -> &str {
+        &self.producer_id
+    }
+
+    /// The sequence number of the next message
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    /// Number of messages that are sent again on [`reconnect`](#method.reconnect)
+    pub fn unconfirmed(&self) -> usize {
+        self.unconfirmed.len()
+    }
+
+    /// Stamps the message with the next sequence number and sends it
+    ///
+    /// If the link is lost, the message is kept and the error is returned. While there are
+    /// unconfirmed messages, a new message is not sent but queued behind them, and
+    /// [`LinkStateError::IllegalState`] is returned, so that the messages are always sent in the
+    /// order of their sequence numbers.
+    pub async fn send(&mut self, sendable: impl Into<Sendable<T>>) -> Result<Outcome, SendError> {
+        let sequence = self.next_sequence;
+        let mut sendable = sendable.into();
+        ProducerStamp {
+            producer_id: self.producer_id.clone(),
+            sequence,
+        }
+        .stamp(&mut sendable.message);
+        self.next_sequence += 1;
+
+        if !self.unconfirmed.is_empty() {
+            self.unconfirmed.push_back((sequence, sendable));
+            return Err(SendError::LinkStateError(LinkStateError::IllegalState));
+        }
+
+        let result = self.sender.send_ref(&sendable).await;
+        if is_link_lost(&result) {
+            self.unconfirmed.push_back((sequence, sendable));
+        }
+        result
+    }
+
+    /// Resumes the link on a new session and sends the unconfirmed messages again with the same
+    /// sequence numbers
+    ///
+    /// The results of the messages that are sent again are returned with their sequence numbers.
+    /// If the link is lost again, the message and those after it are kept for the next
+    /// reconnect.
+    pub async fn reconnect<R>(
+        &mut self,
+        session: &SessionHandle<R>,
+    ) -> Result<Vec<(u64, Result<Outcome, SendError>)>, DetachThenResumeSenderError> {
+        self.sender.detach_then_resume_on_session(session).await?;
+
+        let mut results = Vec::with_capacity(self.unconfirmed.len());
+        while let Some((sequence, sendable)) = self.unconfirmed.pop_front() {
+            let result = self.sender.send_ref(&sendable).await;
+            if is_link_lost(&result) {
+                self.unconfirmed.push_front((sequence, sendable));
+                results.push((sequence, result));
+                break;
+            }
+            results.push((sequence, result));
+        }
+        Ok(results)
+    }
+
+    /// Closes the link
+    ///
+    /// The unconfirmed messages are discarded
+    pub async fn close(self) -> Result<(), super::DetachError> {
+        self.sender.close().await
+    }
+}
+
+fn is_link_lost(result: &Result<Outcome, SendError>) -> bool {
+    matches!(
+        result,
+        Err(SendError::LinkStateError(_)) | Err(SendError::Detached(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::messaging::Message;
+
+    use super::ProducerStamp;
+
+    #[test]
+    fn stamp_is_read_back_from_the_message() {
+        let mut message = Message::builder().value("order").build();
+        assert_eq!(ProducerStamp::from_message(&message), None);
+
+        let stamp = ProducerStamp {
+            producer_id: "producer".to_string(),
+            sequence: 7,
+        };
+        stamp.stamp(&mut message);
+        assert_eq!(ProducerStamp::from_message(&message), Some(stamp));
+    }
+}
@@ -7,6 +7,10 @@ use super::{
     ReceiverTransferError,
 };
 
+cfg_not_wasm32! {
+    use super::spool::{Spool, SpooledBody, Spooling};
+}
+
 macro_rules! or_assign {
     ($self:ident, $other:ident, $field:ident) => {
         match &$self.performative.$field {
@@ -34,6 +38,17 @@ pub(crate) struct IncompleteTransfer {
     pub buffer: Vec<Payload>,
     pub section_number: Option<u32>,
     pub section_offset: u64,
+
+    // Whether the last transfer is appended and the delivery is being completed
+    pub complete: bool,
+
+    // The spool file once the buffered payload exceeds the spooling threshold
+    #[cfg(not(target_arch = "wasm32"))]
+    pub spool: Option<Spool>,
+
+    // The spool file once it is closed
+    #[cfg(not(target_arch = "wasm32"))]
+    pub spooled: Option<SpooledBody>,
 }
 
 impl IncompleteTransfer {
@@ -44,6 +59,11 @@ impl IncompleteTransfer {
             buffer: vec![partial_payload], // TODO: handle payload split across re-attachment
             section_number: Some(number),
             section_offset: offset,
+            complete: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            spool: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            spooled: None,
         }
     }
 
@@ -115,6 +135,42 @@ impl IncompleteTransfer {
         self.buffer.push(other);
     }
 
+    /// Moves the buffered payload to the spool file if the delivery is already spooled or the
+    /// buffered payload exceeds the threshold
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe because the payload that is not written yet stays in the buffer
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn spool_if_exceeds(&mut self, config: &Spooling) {
+        if self.spool.is_none() {
+            let buffered: usize = self.buffer.iter().map(|chunk| chunk.len()).sum();
+            if buffered <= config.threshold {
+                return;
+            }
+            self.spool = Some(Spool::create(config));
+        }
+        if let Some(spool) = &mut self.spool {
+            spool.write_from(&mut self.buffer).await; // cancel safe
+        }
+    }
+
+    /// Writes the rest of the buffered payload to the spool file and closes it. The closed file
+    /// is kept in `spooled`
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe because the spool is only dropped once it is closed
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn finish_spool(&mut self) -> std::io::Result<()> {
+        if let Some(spool) = &mut self.spool {
+            let spooled = spool.finish(&mut self.buffer).await; // cancel safe
+            self.spool = None;
+            self.spooled = Some(spooled?);
+        }
+        Ok(())
+    }
+
     fn position_of_section_number_and_offset(
         &self,
         section_number: u32,
@@ -149,6 +205,11 @@ impl IncompleteTransfer {
         section_number: u32,
         section_offset: u64,
     ) {
+        // The position cannot be found once the payload is spooled, so the spooled payload is kept
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.spool.is_some() {
+            return;
+        }
         if let Some(mut index) =
             self.position_of_section_number_and_offset(section_number, section_offset)
         {
@@ -0,0 +1,215 @@
+//! Keep-alive of a single link with Flow frames that request an echo
+//!
+//! The connection idle-timeout only detects a dead transport. A link can still stop making
+//! progress on a healthy connection, eg. when the remote session is stuck or the remote link
+//! endpoint has been forgotten by a broker. A link attached with a [`LinkKeepAlive`] sends a Flow
+//! with `echo` set every `interval` and expects the remote peer to reply with a Flow within
+//! `timeout`. Every keep-alive without a reply is reported as a [`LinkEvent::KeepAliveMissed`] to
+//! the subscribers of the link events, and the first reply after missed keep-alives is reported
+//! as [`LinkEvent::KeepAliveRestored`]. The link itself is left attached, so that the application
+//! decides when a link is considered dead.
+//!
+//! The keep-alive runs for as long as the link stays attached and is not resumed when a detached
+//! link is re-attached.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fe2o3_amqp::link::{LinkEvent, LinkKeepAlive};
+//!
+//! let sender = Sender::builder()
+//!     .name("rust-sender-link-1")
+//!     .target("q1")
+//!     .keep_alive(LinkKeepAlive::new(Duration::from_secs(5)).timeout(Duration::from_secs(2)))
+//!     .attach(&mut session)
+//!     .await
+//!     .unwrap();
+//!
+//! let mut events = sender.subscribe_events();
+//! while let Ok(event) = events.recv().await {
+//!     if let LinkEvent::KeepAliveMissed { missed } = event {
+//!         if missed >= 3 {
+//!             // Consider the link dead and re-create it
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    sync::{broadcast, mpsc, watch},
+    task::JoinHandle,
+};
+
+use crate::{
+    clock::{self, SharedClock},
+    endpoint::OutputHandle,
+};
+
+use super::{
+    state::{LinkFlowState, LinkState},
+    LinkFrame,
+};
+
+/// Number of [`LinkEvent`]s that are buffered for a lagging subscriber
+pub const DEFAULT_LINK_EVENT_CAPACITY: usize = 16;
+
+/// An event of a link that is reported to the subscribers of the link events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// The remote peer did not reply to a keep-alive within the timeout
+    KeepAliveMissed {
+        /// Number of consecutive keep-alives without a reply, including this one
+        missed: u32,
+    },
+
+    /// The remote peer replied to a keep-alive after one or more keep-alives were missed
+    KeepAliveRestored,
+}
+
+/// Periodic Flow with `echo` set that checks that the remote link endpoint is responsive. See
+/// the [module level documentation](self) for details
+///
+/// | Field | Default Value |
+/// |-------|---------------|
+/// |`interval`| given to [`LinkKeepAlive::new`] |
+/// |`timeout`| the `interval` |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkKeepAlive {
+    /// Time between two keep-alives
+    pub interval: Duration,
+
+    /// How long to wait for the reply to a keep-alive
+    pub timeout: Duration,
+}
+
+impl LinkKeepAlive {
+    /// Creates a keep-alive that is sent every `interval` and waits at most `interval` for the
+    /// reply
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            timeout: interval,
+        }
+    }
+
+    /// Set how long to wait for the reply to a keep-alive
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// The events of a link and the keep-alive task that reports them
+#[derive(Debug)]
+pub(crate) struct LinkEvents {
+    events: broadcast::Sender<LinkEvent>,
+    keep_alive: Option<JoinHandle<()>>,
+}
+
+impl Default for LinkEvents {
+    fn default() -> Self {
+        Self {
+            events: broadcast::channel(DEFAULT_LINK_EVENT_CAPACITY).0,
+            keep_alive: None,
+        }
+    }
+}
+
+impl LinkEvents {
+    /// Starts the keep-alive of a link that has just been attached
+    pub(crate) fn spawn_keep_alive<R>(
+        &mut self,
+        keep_alive: LinkKeepAlive,
+        flow_state: Arc<LinkFlowState<R>>,
+        output_handle: Option<OutputHandle>,
+        outgoing: mpsc::Sender<LinkFrame>,
+        state: watch::Receiver<LinkState>,
+        clock: SharedClock,
+    ) where
+        R: Send + Sync + 'static,
+    {
+        let output_handle = match output_handle {
+            Some(output_handle) => output_handle,
+            None => return,
+        };
+        let task = tokio::spawn(run_keep_alive(
+            keep_alive,
+            flow_state,
+            output_handle,
+            outgoing,
+            state,
+            self.events.clone(),
+            clock,
+        ));
+        if let Some(previous) = self.keep_alive.replace(task) {
+            previous.abort();
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<LinkEvent> {
+        self.events.subscribe()
+    }
+}
+
+impl Drop for LinkEvents {
+    fn drop(&mut self) {
+        if let Some(task) = self.keep_alive.take() {
+            task.abort();
+        }
+    }
+}
+
+async fn run_keep_alive<R>(
+    keep_alive: LinkKeepAlive,
+    flow_state: Arc<LinkFlowState<R>>,
+    output_handle: OutputHandle,
+    outgoing: mpsc::Sender<LinkFrame>,
+    mut state: watch::Receiver<LinkState>,
+    events: broadcast::Sender<LinkEvent>,
+    clock: SharedClock,
+) {
+    let mut next = clock.now() + keep_alive.interval;
+    let mut missed = 0;
+    loop {
+        tokio::select! {
+            _ = clock.sleep_until(next) => {}
+            _ = state.wait_for(|state| *state != LinkState::Attached) => return,
+        }
+        // A keep-alive that is sent late delays the following ones
+        next = clock.now() + keep_alive.interval;
+
+        // The waiter is registered first so that the reply cannot be missed
+        let remote_flow = flow_state.wait_for_remote_flow();
+        let flow = flow_state
+            .lock
+            .read()
+            .as_link_flow(output_handle.clone(), true);
+        if outgoing.send(LinkFrame::Flow(flow)).await.is_err() {
+            return;
+        }
+
+        let reply = tokio::select! {
+            reply = clock::timeout(&*clock, keep_alive.timeout, remote_flow) => reply,
+            _ = state.wait_for(|state| *state != LinkState::Attached) => return,
+        };
+        match reply {
+            Some(Ok(_)) => {
+                if missed > 0 {
+                    missed = 0;
+                    let _ = events.send(LinkEvent::KeepAliveRestored);
+                }
+            }
+            Some(Err(_)) => return,
+            None => {
+                missed += 1;
+                #[cfg(feature = "tracing")]
+                tracing::warn!(missed, "Link keep-alive is not answered");
+                #[cfg(feature = "log")]
+                log::warn!("Link keep-alive is not answered, missed = {}", missed);
+                let _ = events.send(LinkEvent::KeepAliveMissed { missed });
+            }
+        }
+    }
+}
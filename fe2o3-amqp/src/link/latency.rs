@@ -0,0 +1,317 @@
+//! Settlement latency of the outgoing deliveries
+//!
+//! A [`Sender`](super::Sender) measures the time from when a delivery is written until the remote
+//! receiver settles it, or until the remote receiver replies with a terminal state if the link
+//! uses `ReceiverSettleMode::Second`. The latencies are collected in a histogram whose
+//! percentiles are returned by [`Sender::latency_stats()`](super::Sender::latency_stats), and the
+//! deliveries that take longer than the threshold of a [`SlowSettlementAlert`] are reported as
+//! [`SlowSettlement`]s. Deliveries that are sent settled are not measured.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use fe2o3_amqp_types::{definitions::DeliveryTag, messaging::DeliveryState};
+use parking_lot::Mutex;
+use tokio::sync::broadcast;
+
+/// Number of [`SlowSettlement`]s that are buffered for a lagging subscriber
+pub const DEFAULT_SLOW_SETTLEMENT_CAPACITY: usize = 64;
+
+/// Number of bits of a latency (in microseconds) that select the bucket within a power of two.
+/// The width of a bucket is at most 1/8 of its lower bound
+const SUB_BUCKET_BITS: u32 = 3;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+
+/// A snapshot of the settlement latencies of a sender
+///
+/// The percentiles are the upper bounds of the histogram buckets, which overestimate the actual
+/// latencies by at most 12.5%. All the durations are zero if no delivery has been settled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// Number of settled deliveries
+    pub count: u64,
+
+    /// Lowest latency
+    pub min: Duration,
+
+    /// Mean latency
+    pub mean: Duration,
+
+    /// Median latency
+    pub p50: Duration,
+
+    /// 90th percentile
+    pub p90: Duration,
+
+    /// 99th percentile
+    pub p99: Duration,
+
+    /// 99.9th percentile
+    pub p999: Duration,
+
+    /// Highest latency
+    pub max: Duration,
+}
+
+/// A delivery whose settlement took longer than the threshold of the [`SlowSettlementAlert`]
+#[derive(Debug, Clone)]
+pub struct SlowSettlement {
+    /// Tag of the delivery
+    pub delivery_tag: DeliveryTag,
+
+    /// Time from when the delivery was written until it was settled
+    pub latency: Duration,
+
+    /// The state that the delivery was settled with
+    pub state: Option<DeliveryState>,
+}
+
+type SlowSettlementHook = Arc<dyn Fn(&SlowSettlement) + Send + Sync>;
+
+/// Reports the deliveries whose settlement takes longer than a threshold
+///
+/// The slow settlements are broadcast to the subscribers returned by
+/// [`Sender::subscribe_slow_settlements()`](super::Sender::subscribe_slow_settlements) and passed
+/// to the hook, if any.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use fe2o3_amqp::link::SlowSettlementAlert;
+///
+/// let sender = Sender::builder()
+///     .name("sender")
+///     .target("q1")
+///     .slow_settlement_alert(
+///         SlowSettlementAlert::new(Duration::from_millis(500)).on_slow_settlement(|slow| {
+///             metrics::counter!("slow_settlements").increment(1);
+///         }),
+///     )
+///     .attach(&mut session)
+///     .await
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct SlowSettlementAlert {
+    threshold: Duration,
+    hook: Option<SlowSettlementHook>,
+}
+
+impl fmt::Debug for SlowSettlementAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlowSettlementAlert")
+            .field("threshold", &self.threshold)
+            .field("hook", &self.hook.as_ref().map(|_| "Fn(&SlowSettlement)"))
+            .finish()
+    }
+}
+
+impl SlowSettlementAlert {
+    /// Creates an alert for the deliveries whose settlement takes longer than `threshold`
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            hook: None,
+        }
+    }
+
+    /// Sets a hook that is called with every slow settlement
+    ///
+    /// The hook is called on the task of the session engine, so it should hand the work over to
+    /// another task instead of blocking
+    pub fn on_slow_settlement(
+        mut self,
+        hook: impl Fn(&SlowSettlement) + Send + Sync + 'static,
+    ) -> Self {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// The latency above which a settlement is reported
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+}
+
+/// Histogram of latencies with buckets whose width grows with the latency
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LatencyHistogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum_micros: u128,
+    min_micros: u64,
+    max_micros: u64,
+}
+
+fn bucket_index(micros: u64) -> usize {
+    if micros < SUB_BUCKETS {
+        return micros as usize;
+    }
+    let shift = 63 - micros.leading_zeros() - SUB_BUCKET_BITS;
+    let sub_bucket = (micros >> shift) - SUB_BUCKETS;
+    ((shift as u64 + 1) * SUB_BUCKETS + sub_bucket) as usize
+}
+
+fn bucket_upper_bound(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let sub_bucket = index % SUB_BUCKETS;
+    let bound = ((SUB_BUCKETS + sub_bucket + 1) as u128) << shift;
+    (bound - 1).min(u64::MAX as u128) as u64
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+        let index = bucket_index(micros);
+        if self.buckets.len() <= index {
+            self.buckets.resize(index + 1, 0);
+        }
+        self.buckets[index] += 1;
+        if self.count == 0 || micros < self.min_micros {
+            self.min_micros = micros;
+        }
+        self.max_micros = self.max_micros.max(micros);
+        self.count += 1;
+        self.sum_micros += micros as u128;
+    }
+
+    /// Returns the upper bound of the bucket that holds the `quantile` (between 0 and 1)
+    fn percentile_micros(&self, quantile: f64) -> u64 {
+        let rank = ((quantile * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_upper_bound(index).clamp(self.min_micros, self.max_micros);
+            }
+        }
+        self.max_micros
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        if self.count == 0 {
+            return LatencyStats::default();
+        }
+        let percentile = |quantile| Duration::from_micros(self.percentile_micros(quantile));
+        LatencyStats {
+            count: self.count,
+            min: Duration::from_micros(self.min_micros),
+            mean: Duration::from_micros((self.sum_micros / self.count as u128) as u64),
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            p999: percentile(0.999),
+            max: Duration::from_micros(self.max_micros),
+        }
+    }
+}
+
+/// Settlement latencies of a sender, which are shared with the `LinkRelay` that processes the
+/// incoming dispositions
+#[derive(Debug)]
+pub(crate) struct SettlementLatency {
+    histogram: Mutex<LatencyHistogram>,
+    alert: Option<SlowSettlementAlert>,
+    slow_settlements: broadcast::Sender<SlowSettlement>,
+}
+
+impl Default for SettlementLatency {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl SettlementLatency {
+    pub fn new(alert: Option<SlowSettlementAlert>) -> Self {
+        let (slow_settlements, _) = broadcast::channel(DEFAULT_SLOW_SETTLEMENT_CAPACITY);
+        Self {
+            histogram: Default::default(),
+            alert,
+            slow_settlements,
+        }
+    }
+
+    pub fn record(
+        &self,
+        delivery_tag: &DeliveryTag,
+        latency: Duration,
+        state: &Option<DeliveryState>,
+    ) {
+        self.histogram.lock().record(latency);
+
+        let alert = match &self.alert {
+            Some(alert) if latency > alert.threshold => alert,
+            _ => return,
+        };
+        let slow = SlowSettlement {
+            delivery_tag: delivery_tag.clone(),
+            latency,
+            state: state.clone(),
+        };
+        #[cfg(feature = "tracing")]
+        tracing::warn!(delivery_tag = ?slow.delivery_tag, latency = ?slow.latency, "Slow settlement");
+        #[cfg(feature = "log")]
+        log::warn!(
+            "Slow settlement of delivery {:?}: {:?}",
+            slow.delivery_tag,
+            slow.latency
+        );
+        // There may be no subscriber
+        let _ = self.slow_settlements.send(slow.clone());
+        if let Some(hook) = &alert.hook {
+            hook(&slow);
+        }
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        self.histogram.lock().stats()
+    }
+
+    pub fn reset(&self) {
+        *self.histogram.lock() = LatencyHistogram::default();
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SlowSettlement> {
+        self.slow_settlements.subscribe()
+    }
+}
+
+pub(crate) type ArcSettlementLatency = Arc<SettlementLatency>;
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{bucket_index, bucket_upper_bound, LatencyHistogram};
+
+    #[test]
+    fn buckets_cover_the_latencies() {
+        for micros in (0..5000).chain([u64::MAX / 3, u64::MAX]) {
+            let upper_bound = bucket_upper_bound(bucket_index(micros));
+            assert!(upper_bound >= micros);
+            assert!(upper_bound - micros <= micros / 8);
+        }
+    }
+
+    #[test]
+    fn percentiles_are_within_the_bucket_width() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.stats().count, 0);
+        for millis in 1..=100 {
+            histogram.record(Duration::from_millis(millis));
+        }
+        let stats = histogram.stats();
+        assert_eq!(stats.count, 100);
+        assert_eq!(stats.min, Duration::from_millis(1));
+        assert_eq!(stats.max, Duration::from_millis(100));
+        assert_eq!(stats.mean, Duration::from_micros(50_500));
+        for (percentile, expected) in [(stats.p50, 50), (stats.p90, 90), (stats.p99, 99)] {
+            let expected = Duration::from_millis(expected);
+            assert!(percentile >= expected && percentile <= expected + expected / 8);
+        }
+        assert_eq!(stats.p999, stats.max);
+    }
+}
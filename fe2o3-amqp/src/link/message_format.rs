@@ -0,0 +1,357 @@
+//! Registry of the custom (non-zero) message formats
+//!
+//! The `message-format` field of a transfer tells the receiver how the payload is encoded. The
+//! upper three octets identify the format and the lowest octet its version. A value of zero
+//! ([`MESSAGE_FORMAT`]) is the message format defined by the AMQP 1.0 specification, and any other
+//! value is vendor specific.
+//!
+//! A [`MessageFormatCodec`] can be registered for a proprietary format with
+//! [`MessageFormatRegistry::register`], and the registry is given to the receiver with
+//! [`Builder::message_formats`](crate::link::builder::Builder::message_formats). The deliveries
+//! are then received with [`Receiver::recv_custom()`](crate::link::Receiver::recv_custom), which
+//! decodes the payload with the codec registered for its message format.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! #[derive(Debug)]
+//! struct Utf8Codec;
+//!
+//! impl MessageFormatCodec for Utf8Codec {
+//!     type Message = String;
+//!     type Error = std::string::FromUtf8Error;
+//!
+//!     fn decode(&self, _: MessageFormat, payload: &[u8]) -> Result<String, Self::Error> {
+//!         String::from_utf8(payload.to_vec())
+//!     }
+//! }
+//!
+//! let mut registry = MessageFormatRegistry::new();
+//! registry.register(message_format(0x00_1234, 0), Utf8Codec);
+//!
+//! let mut receiver = Receiver::builder()
+//!     .name("rust-receiver-link-1")
+//!     .source("q1")
+//!     .message_formats(registry)
+//!     .attach(&mut session)
+//!     .await
+//!     .unwrap();
+//!
+//! let delivery = receiver.recv_custom().await.unwrap();
+//! let text: &String = delivery.body().downcast_ref().unwrap();
+//! ```
+
+use std::{any::Any, collections::HashMap, fmt, io::Read, sync::Arc};
+
+use fe2o3_amqp_types::{
+    definitions::MessageFormat,
+    messaging::{message::DecodeIntoMessage, FromBody, Message, MESSAGE_FORMAT},
+};
+
+use crate::util::IntoReader;
+
+use super::ReceiverTransferError;
+
+cfg_not_wasm32! {
+    use super::spool::SpooledBody;
+}
+
+/// Creates a message format from the 24-bit format code and the version
+pub const fn message_format(format_code: u32, version: u8) -> MessageFormat {
+    (format_code << 8) | version as u32
+}
+
+/// Format code (the upper three octets) of a message format
+pub const fn format_code(message_format: MessageFormat) -> u32 {
+    message_format >> 8
+}
+
+/// Version (the lowest octet) of a message format
+pub const fn format_version(message_format: MessageFormat) -> u8 {
+    (message_format & 0xff) as u8
+}
+
+/// Decodes the payload of the deliveries with a custom message format
+pub trait MessageFormatCodec: fmt::Debug + Send + Sync + 'static {
+    /// Type of the decoded message
+    type Message: Any + Send + Sync;
+
+    /// Error decoding the payload
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Decodes the payload of a delivery. The payload is the concatenation of the payloads of all
+    /// the transfers of the delivery
+    fn decode(
+        &self,
+        message_format: MessageFormat,
+        payload: &[u8],
+    ) -> Result<Self::Message, Self::Error>;
+}
+
+type ErasedDecodeResult =
+    Result<Arc<dyn Any + Send + Sync>, Box<dyn std::error::Error + Send + Sync>>;
+
+trait ErasedCodec: fmt::Debug + Send + Sync {
+    fn decode_erased(&self, message_format: MessageFormat, payload: &[u8]) -> ErasedDecodeResult;
+}
+
+impl<C: MessageFormatCodec> ErasedCodec for C {
+    fn decode_erased(&self, message_format: MessageFormat, payload: &[u8]) -> ErasedDecodeResult {
+        match self.decode(message_format, payload) {
+            Ok(message) => Ok(Arc::new(message)),
+            Err(error) => Err(Box::new(error)),
+        }
+    }
+}
+
+/// The message formats that a receiver can decode with [`Receiver::recv_custom()`](crate::link::Receiver::recv_custom)
+#[derive(Debug, Clone, Default)]
+pub struct MessageFormatRegistry {
+    codecs: HashMap<MessageFormat, Arc<dyn ErasedCodec>>,
+}
+
+impl MessageFormatRegistry {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the codec of a message format. Returns whether a codec was already registered
+    /// for the message format, in which case it is replaced
+    pub fn register<C: MessageFormatCodec>(
+        &mut self,
+        message_format: MessageFormat,
+        codec: C,
+    ) -> bool {
+        self.codecs
+            .insert(message_format, Arc::new(codec))
+            .is_some()
+    }
+
+    /// Removes the codec of a message format. Returns whether a codec was registered
+    pub fn unregister(&mut self, message_format: MessageFormat) -> bool {
+        self.codecs.remove(&message_format).is_some()
+    }
+
+    /// Whether a codec is registered for the message format
+    pub fn contains(&self, message_format: MessageFormat) -> bool {
+        self.codecs.contains_key(&message_format)
+    }
+
+    /// The registered message formats in ascending order
+    pub fn formats(&self) -> impl Iterator<Item = MessageFormat> {
+        let mut formats: Vec<_> = self.codecs.keys().copied().collect();
+        formats.sort_unstable();
+        formats.into_iter()
+    }
+
+    /// Whether no codec is registered
+    pub fn is_empty(&self) -> bool {
+        self.codecs.is_empty()
+    }
+
+    fn decode(&self, message_format: MessageFormat, payload: &[u8]) -> Option<ErasedDecodeResult> {
+        self.codecs
+            .get(&message_format)
+            .map(|codec| codec.decode_erased(message_format, payload))
+    }
+}
+
+/// A message with a custom message format that is decoded by the codec registered in the
+/// [`MessageFormatRegistry`]
+#[derive(Clone)]
+pub struct CustomFormat {
+    message_format: MessageFormat,
+    message: Arc<dyn Any + Send + Sync>,
+}
+
+impl fmt::Debug for CustomFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomFormat")
+            .field("message_format", &self.message_format)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CustomFormat {
+    /// The message format of the delivery
+    pub fn message_format(&self) -> MessageFormat {
+        self.message_format
+    }
+
+    /// Whether the decoded message is of type `T`
+    pub fn is<T: Any>(&self) -> bool {
+        self.message.is::<T>()
+    }
+
+    /// Returns a reference to the decoded message if it is of type `T`
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.message.downcast_ref()
+    }
+
+    /// Returns the decoded message if it is of type `T`
+    pub fn downcast<T: Any + Send + Sync>(self) -> Result<Arc<T>, Self> {
+        let message_format = self.message_format;
+        self.message.downcast().map_err(|message| Self {
+            message_format,
+            message,
+        })
+    }
+}
+
+/// Decodes the payload of a complete delivery
+pub(crate) trait DecodeDelivery: Sized {
+    fn decode_delivery(
+        registry: &MessageFormatRegistry,
+        message_format: Option<MessageFormat>,
+        reader: impl Read,
+    ) -> Result<Message<Self>, ReceiverTransferError>;
+
+    /// Whether a delivery that is spooled to disk is decoded with
+    /// [`decode_spooled`](DecodeDelivery::decode_spooled). Otherwise the receiver reads the spool
+    /// file back into memory and decodes it with
+    /// [`decode_delivery`](DecodeDelivery::decode_delivery)
+    #[cfg(not(target_arch = "wasm32"))]
+    const KEEPS_SPOOLED: bool = false;
+
+    /// Decodes a delivery that is spooled to disk by streaming the spool file
+    #[cfg(not(target_arch = "wasm32"))]
+    fn decode_spooled(
+        registry: &MessageFormatRegistry,
+        message_format: Option<MessageFormat>,
+        spooled: SpooledBody,
+    ) -> Result<Message<Self>, ReceiverTransferError> {
+        let file = spooled.open().map_err(ReceiverTransferError::SpoolError)?;
+        Self::decode_delivery(registry, message_format, std::io::BufReader::new(file))
+    }
+}
+
+/// Payload of a complete delivery
+pub(crate) trait DeliveryPayload {
+    fn decode<T: DecodeDelivery>(
+        self,
+        registry: &MessageFormatRegistry,
+        message_format: Option<MessageFormat>,
+    ) -> Result<Message<T>, ReceiverTransferError>;
+}
+
+impl<P: IntoReader> DeliveryPayload for P {
+    fn decode<T: DecodeDelivery>(
+        self,
+        registry: &MessageFormatRegistry,
+        message_format: Option<MessageFormat>,
+    ) -> Result<Message<T>, ReceiverTransferError> {
+        T::decode_delivery(registry, message_format, self.into_reader())
+    }
+}
+
+impl<T> DecodeDelivery for T
+where
+    for<'de> T: FromBody<'de>,
+{
+    fn decode_delivery(
+        _registry: &MessageFormatRegistry,
+        _message_format: Option<MessageFormat>,
+        reader: impl Read,
+    ) -> Result<Message<Self>, ReceiverTransferError> {
+        T::decode_into_message(reader).map_err(|_| ReceiverTransferError::MessageDecodeError)
+    }
+}
+
+impl DecodeDelivery for CustomFormat {
+    fn decode_delivery(
+        registry: &MessageFormatRegistry,
+        message_format: Option<MessageFormat>,
+        mut reader: impl Read,
+    ) -> Result<Message<Self>, ReceiverTransferError> {
+        let message_format = message_format.unwrap_or(MESSAGE_FORMAT);
+        let mut payload = Vec::new();
+        reader
+            .read_to_end(&mut payload)
+            .map_err(|_| ReceiverTransferError::MessageDecodeError)?;
+        match registry.decode(message_format, &payload) {
+            Some(Ok(message)) => Ok(Message {
+                header: None,
+                delivery_annotations: None,
+                message_annotations: None,
+                properties: None,
+                application_properties: None,
+                body: CustomFormat {
+                    message_format,
+                    message,
+                },
+                footer: None,
+            }),
+            Some(Err(_error)) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(message_format, error = ?_error, "Failed to decode message");
+                #[cfg(feature = "log")]
+                log::warn!(
+                    "Failed to decode message with format {}: {:?}",
+                    message_format,
+                    _error
+                );
+                Err(ReceiverTransferError::MessageDecodeError)
+            }
+            None => Err(ReceiverTransferError::UnknownMessageFormat(message_format)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::definitions::MessageFormat;
+
+    use super::{
+        format_code, format_version, message_format, CustomFormat, DecodeDelivery,
+        MessageFormatCodec, MessageFormatRegistry,
+    };
+    use crate::link::ReceiverTransferError;
+
+    #[derive(Debug)]
+    struct Utf8Codec;
+
+    impl MessageFormatCodec for Utf8Codec {
+        type Message = String;
+        type Error = std::string::FromUtf8Error;
+
+        fn decode(&self, _: MessageFormat, payload: &[u8]) -> Result<String, Self::Error> {
+            String::from_utf8(payload.to_vec())
+        }
+    }
+
+    #[test]
+    fn message_format_is_split_into_code_and_version() {
+        let format = message_format(0x80_0137, 2);
+        assert_eq!(format, 0x8001_3702);
+        assert_eq!(format_code(format), 0x80_0137);
+        assert_eq!(format_version(format), 2);
+    }
+
+    #[test]
+    fn registered_codec_decodes_custom_format() {
+        let format = message_format(0x00_1234, 0);
+        let mut registry = MessageFormatRegistry::new();
+        assert!(!registry.register(format, Utf8Codec));
+        assert!(!registry.register(message_format(0x00_1234, 1), Utf8Codec));
+        assert_eq!(
+            registry.formats().collect::<Vec<_>>(),
+            vec![format, message_format(0x00_1234, 1)]
+        );
+
+        let message =
+            CustomFormat::decode_delivery(&registry, Some(format), &b"hello"[..]).unwrap();
+        assert_eq!(message.body.message_format(), format);
+        assert_eq!(message.body.downcast_ref::<String>().unwrap(), "hello");
+        assert!(message.body.downcast::<Vec<u8>>().is_err());
+
+        assert!(matches!(
+            CustomFormat::decode_delivery(&registry, Some(format), &[0xff][..]),
+            Err(ReceiverTransferError::MessageDecodeError)
+        ));
+        assert!(matches!(
+            CustomFormat::decode_delivery(&registry, None, &b"hello"[..]),
+            Err(ReceiverTransferError::UnknownMessageFormat(0))
+        ));
+    }
+}
@@ -0,0 +1,158 @@
+//! Ordered processing of the deliveries of message groups
+//!
+//! The messages that share the same `group-id` in their properties form a message group, and
+//! brokers use message groups to express that these messages must be processed in order.
+//! [`MessageGroupProcessor`] processes the deliveries of a [`Receiver`] concurrently across the
+//! groups, and one at a time within a group.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let mut receiver = Receiver::attach(&mut session, "orders-receiver", "orders").await.unwrap();
+//! let processor = MessageGroupProcessor::new(|delivery: Delivery<String>| async move {
+//!     match update_account(delivery.body()).await {
+//!         Ok(_) => Outcome::Accepted(Accepted {}),
+//!         Err(_) => Outcome::Released(Released {}),
+//!     }
+//! });
+//! let error = processor.run(&mut receiver).await;
+//! ```
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+};
+
+use fe2o3_amqp_types::messaging::{DeliveryState, FromBody, Outcome};
+use futures_util::{stream::FuturesUnordered, StreamExt};
+
+use super::{
+    delivery::{Delivery, DeliveryInfo},
+    MessageGroupError, Receiver, DEFAULT_CREDIT,
+};
+
+/// Processes the deliveries of a [`Receiver`] in order within each message group
+///
+/// The deliveries are grouped by the `group-id` of their properties. The handler is called on the
+/// next delivery of a group only after the outcome of the previous delivery of the same group is
+/// sent to the remote peer, while the deliveries of different groups are processed concurrently.
+/// Deliveries without a `group-id` are not ordered.
+///
+/// The handler futures are polled on the task that runs [`run`](Self::run), so CPU intensive work
+/// should be moved to a separate task by the handler. At most
+/// [`max_outstanding`](Self::max_outstanding) deliveries are received but not yet disposed, and the
+/// receiver stops receiving until a delivery is disposed. The auto-accept of the receiver is
+/// turned off, because the disposition is the outcome returned by the handler.
+pub struct MessageGroupProcessor<F> {
+    handler: F,
+    max_outstanding: usize,
+}
+
+impl<F> std::fmt::Debug for MessageGroupProcessor<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MessageGroupProcessor")
+            .field("max_outstanding", &self.max_outstanding)
+            .finish()
+    }
+}
+
+impl<F> MessageGroupProcessor<F> {
+    /// Creates a processor that calls `handler` on each delivery and disposes the delivery with
+    /// the returned outcome
+    pub fn new(handler: F) -> Self {
+        Self {
+            handler,
+            max_outstanding: DEFAULT_CREDIT as usize,
+        }
+    }
+
+    /// Sets the maximum number of deliveries that are received but not yet disposed. Defaults to
+    /// [`DEFAULT_CREDIT`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_outstanding` is zero
+    pub fn max_outstanding(mut self, max_outstanding: usize) -> Self {
+        assert!(
+            max_outstanding > 0,
+            "max_outstanding must be greater than zero"
+        );
+        self.max_outstanding = max_outstanding;
+        self
+    }
+
+    /// Receives and processes the deliveries until the receiver fails, eg. when the link is
+    /// detached or closed by the remote peer, and returns the error
+    ///
+    /// The handler futures that are still pending when an error is returned, or when the returned
+    /// future is dropped, are dropped and their deliveries are left unsettled.
+    pub async fn run<T, Fut>(&self, receiver: &mut Receiver) -> MessageGroupError
+    where
+        for<'de> T: FromBody<'de> + Send,
+        F: Fn(Delivery<T>) -> Fut,
+        Fut: Future<Output = Outcome>,
+    {
+        receiver.set_auto_accept(false);
+
+        // The deliveries of a group that wait for the previous delivery of the group. A group is
+        // present as long as one of its deliveries is being processed
+        let mut groups: HashMap<String, VecDeque<Delivery<T>>> = HashMap::new();
+        let mut processing = FuturesUnordered::new();
+        let mut outstanding = 0;
+
+        let process = |delivery: Delivery<T>| {
+            let group_id = group_id(&delivery);
+            let info = DeliveryInfo::from(&delivery);
+            let outcome = (self.handler)(delivery);
+            async move { (group_id, info, outcome.await) }
+        };
+
+        loop {
+            tokio::select! {
+                Some((group_id, info, outcome)) = processing.next() => {
+                    let state = DeliveryState::from(outcome);
+                    if let Err(error) = receiver.inner.dispose(info, None, state).await {
+                        return error.into();
+                    }
+                    outstanding -= 1;
+
+                    if let Some(group_id) = group_id {
+                        let next = groups.get_mut(&group_id).and_then(VecDeque::pop_front);
+                        match next {
+                            Some(delivery) => processing.push(process(delivery)),
+                            None => {
+                                groups.remove(&group_id);
+                            }
+                        }
+                    }
+                }
+                // `Receiver::recv` is cancel safe
+                result = receiver.recv::<T>(), if outstanding < self.max_outstanding => {
+                    let delivery = match result {
+                        Ok(delivery) => delivery,
+                        Err(error) => return error.into(),
+                    };
+                    outstanding += 1;
+                    match group_id(&delivery) {
+                        Some(group_id) => match groups.get_mut(&group_id) {
+                            Some(queue) => queue.push_back(delivery),
+                            None => {
+                                groups.insert(group_id, VecDeque::new());
+                                processing.push(process(delivery));
+                            }
+                        },
+                        None => processing.push(process(delivery)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn group_id<T>(delivery: &Delivery<T>) -> Option<String> {
+    delivery
+        .message()
+        .properties
+        .as_ref()
+        .and_then(|properties| properties.group_id.clone())
+}
@@ -5,7 +5,7 @@ use std::{marker::PhantomData, sync::Arc};
 use bytes::{BufMut, BytesMut};
 use fe2o3_amqp_types::{
     definitions::{
-        self, DeliveryNumber, DeliveryTag, MessageFormat, ReceiverSettleMode, Role,
+        self, DeliveryNumber, DeliveryTag, Handle, MessageFormat, ReceiverSettleMode, Role,
         SenderSettleMode, SequenceNo, SessionError,
     },
     messaging::{DeliveryState, Received, Source, Target, TargetArchetype},
@@ -13,48 +13,105 @@ use fe2o3_amqp_types::{
     primitives::{OrderedMap, Symbol},
 };
 
+pub use buffered::{BufferedSender, OverflowPolicy};
+pub use dispatcher::{HandlerPanic, ReceiverDispatcher};
 pub use error::*;
-
+pub use group::{GroupDelivery, GroupDeliveryInfo, GroupDisposer, ReceiverGroup};
+pub use idempotent::{IdempotentSender, ProducerStamp};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use attach_retry::AttachRetry;
+#[cfg(not(target_arch = "wasm32"))]
+pub use dedup::Deduplication;
+#[cfg(not(target_arch = "wasm32"))]
+pub use disposition_batching::DispositionBatching;
+#[cfg(not(target_arch = "wasm32"))]
+pub use keep_alive::{LinkEvent, LinkKeepAlive};
+#[cfg(not(target_arch = "wasm32"))]
+pub use latency::{LatencyStats, SlowSettlement, SlowSettlementAlert};
+pub use message_format::{CustomFormat, MessageFormatCodec, MessageFormatRegistry};
+pub use message_group::MessageGroupProcessor;
+pub use pair::{LinkPair, LinkPairBuilder};
 use parking_lot::RwLock;
+#[cfg(not(target_arch = "wasm32"))]
+pub use rate_limit::RateLimit;
 pub use receiver::Receiver;
 pub use sender::Sender;
 use serde::Serialize;
 use serde_amqp::ser::Serializer;
-use tokio::sync::{mpsc, oneshot};
+pub use sharded::ShardedSender;
+#[cfg(not(target_arch = "wasm32"))]
+pub use spool::{DeliveryBody, SpooledBody, Spooling};
+pub use state::{LinkState, RemoteFlowState};
+pub use stream::ReceiverStream;
+pub use suspended::SuspendedLink;
+use tokio::sync::{mpsc, oneshot, watch};
+#[cfg(not(target_arch = "wasm32"))]
+pub use unsettled::UnsettledDelivery;
 
 use crate::{
     control::SessionControl,
     endpoint::{self, InputHandle, LinkAttach, LinkDetach, LinkFlow, OutputHandle, Settlement},
     link::delivery::UnsettledMessage,
+    session::InboundDispatcher,
     util::{AsDeliveryState, Consumer, Produce, Producer},
     Payload,
 };
 
 use self::{
-    delivery::Delivery,
-    resumption::ResumingDelivery,
-    state::{LinkFlowState, LinkState},
+    delivery::Delivery, resumption::ResumingDelivery, state::LinkFlowState,
     target_archetype::VerifyTargetArchetype,
 };
 
-cfg_transaction! {
-    use crate::transaction::TXN_ID_KEY;
+cfg_checksum! {
+    pub mod checksum;
+}
+
+cfg_compression! {
+    pub mod compression;
+}
+
+cfg_debug_deliveries! {
+    pub mod delivery_trace;
 }
 
 mod frame;
 pub(crate) use frame::*;
+pub mod buffered;
 pub mod builder;
+pub mod byte_credit;
+mod deadline;
 pub mod delivery;
+pub mod dispatcher;
 mod error;
+pub mod group;
+pub mod idempotent;
 mod incomplete_transfer;
+pub mod message_format;
+pub mod message_group;
+pub mod pair;
+mod priority;
+cfg_not_wasm32! {
+    pub mod attach_retry;
+    pub mod dedup;
+    pub mod disposition_batching;
+    pub mod keep_alive;
+    pub mod latency;
+    pub mod rate_limit;
+    pub mod spool;
+    pub mod unsettled;
+}
 pub mod receiver;
 mod receiver_link;
 pub(crate) mod resumption;
 pub mod sender;
 mod sender_link;
+pub mod sharded;
 pub(crate) mod shared_inner;
 mod source;
 pub(crate) mod state;
+pub mod stream;
+pub mod suspended;
 pub(crate) mod target_archetype;
 
 /// Default amount of link credit
@@ -87,13 +144,13 @@ pub mod role {
     use fe2o3_amqp_types::definitions::Role;
 
     /// Type state for link::builder::Builder
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub struct SenderMarker {
         _private: (),
     }
 
     /// Type state for link::builder::Builder
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub struct ReceiverMarker {
         _private: (),
     }
@@ -190,6 +247,8 @@ pub(crate) struct Link<R, T, F, M> {
     pub(crate) role: PhantomData<R>,
 
     pub(crate) local_state: LinkState,
+    // Publishes every change of `local_state`
+    pub(crate) state_watch: watch::Sender<LinkState>,
     // pub(crate) state_code: Arc<AtomicU8>,
     pub(crate) name: String,
 
@@ -207,6 +266,9 @@ pub(crate) struct Link<R, T, F, M> {
     /// If zero, the attach frame should treated is None
     pub(crate) max_message_size: u64,
 
+    /// Caps the size of the outgoing transfer frames below the connection max frame size
+    pub(crate) max_transfer_frame_size: Option<usize>,
+
     // capabilities
     pub(crate) offered_capabilities: Option<Vec<Symbol>>, // TODO: Add accessor fns
     pub(crate) desired_capabilities: Option<Vec<Symbol>>, // TODO: Add accessor fns
@@ -217,6 +279,32 @@ pub(crate) struct Link<R, T, F, M> {
 
     pub(crate) verify_incoming_source: bool,
     pub(crate) verify_incoming_target: bool,
+
+    /// Traces of the recent deliveries, which are shared with the `LinkRelay`
+    #[cfg(feature = "debug-deliveries")]
+    pub(crate) delivery_traces: delivery_trace::ArcDeliveryTraces,
+}
+
+impl<R, T, F, M> Link<R, T, F, M> {
+    pub(crate) fn set_local_state(&mut self, state: LinkState) {
+        self.state_watch.send_replace(state.clone());
+        self.local_state = state;
+    }
+
+    /// Watches the local state of the link
+    pub(crate) fn watch_state(&self) -> watch::Receiver<LinkState> {
+        self.state_watch.subscribe()
+    }
+
+    /// Records a frame of a delivery in the delivery traces
+    #[cfg(feature = "debug-deliveries")]
+    pub(crate) fn trace_delivery(
+        &self,
+        delivery_tag: &DeliveryTag,
+        kind: delivery_trace::DeliveryEventKind,
+    ) {
+        self.delivery_traces.lock().record(delivery_tag, kind)
+    }
 }
 
 impl<R, T, F, M> Link<R, T, F, M>
@@ -367,18 +455,18 @@ where
                 writer.send(frame).await // cancel safe
                     .map_err(|_| SendAttachErrorKind::IllegalSessionState)?;
                 if incomplete_unsettled {
-                    self.local_state = LinkState::IncompleteAttachSent
+                    self.set_local_state(LinkState::IncompleteAttachSent)
                 } else {
-                    self.local_state = LinkState::AttachSent
+                    self.set_local_state(LinkState::AttachSent)
                 }
             }
             LinkState::AttachReceived => {
                 writer.send(frame).await // cancel safe
                     .map_err(|_| SendAttachErrorKind::IllegalSessionState)?;
                 if incomplete_unsettled {
-                    self.local_state = LinkState::IncompleteAttachExchanged
+                    self.set_local_state(LinkState::IncompleteAttachExchanged)
                 } else {
-                    self.local_state = LinkState::Attached
+                    self.set_local_state(LinkState::Attached)
                 }
             }
             _ => return Err(SendAttachErrorKind::IllegalState),
@@ -428,21 +516,21 @@ where
                 | LinkState::IncompleteAttachExchanged
                 | LinkState::IncompleteAttachSent
                 | LinkState::IncompleteAttachReceived => {
-                    self.local_state = LinkState::CloseReceived;
+                    self.set_local_state(LinkState::CloseReceived);
                     match detach.error {
                         Some(error) => Err(DetachError::RemoteClosedWithError(error)),
                         None => Ok(()),
                     }
                 }
                 LinkState::DetachSent => {
-                    self.local_state = LinkState::CloseReceived;
+                    self.set_local_state(LinkState::CloseReceived);
                     match detach.error {
                         Some(error) => Err(DetachError::RemoteClosedWithError(error)),
                         None => Err(DetachError::ClosedByRemote),
                     }
                 }
                 LinkState::CloseSent => {
-                    self.local_state = LinkState::Closed;
+                    self.set_local_state(LinkState::Closed);
                     let _ = self.output_handle.take();
                     match detach.error {
                         Some(error) => Err(DetachError::RemoteClosedWithError(error)),
@@ -453,9 +541,9 @@ where
             },
             false => {
                 match self.local_state {
-                    LinkState::Attached => self.local_state = LinkState::DetachReceived,
+                    LinkState::Attached => self.set_local_state(LinkState::DetachReceived),
                     LinkState::DetachSent => {
-                        self.local_state = LinkState::Detached;
+                        self.set_local_state(LinkState::Detached);
                         // Dropping output handle as it is already detached
                         let _ = self.output_handle.take();
                     }
@@ -482,12 +570,12 @@ where
     ) -> Result<(), Self::DetachError> {
         // Change the state whether sending the detach frame succeeds or not
         match (&self.local_state, closed) {
-            (LinkState::Attached, false) => self.local_state = LinkState::DetachSent,
-            (LinkState::DetachReceived, false) => self.local_state = LinkState::Detached,
+            (LinkState::Attached, false) => self.set_local_state(LinkState::DetachSent),
+            (LinkState::DetachReceived, false) => self.set_local_state(LinkState::Detached),
             (LinkState::CloseReceived, false) => return Err(DetachError::ClosedByRemote),
-            (LinkState::Attached, true) => self.local_state = LinkState::CloseSent,
+            (LinkState::Attached, true) => self.set_local_state(LinkState::CloseSent),
             (LinkState::DetachReceived, true) => return Err(DetachError::DetachedByRemote),
-            (LinkState::CloseReceived, true) => self.local_state = LinkState::Closed,
+            (LinkState::CloseReceived, true) => self.set_local_state(LinkState::Closed),
             _ => return Err(DetachError::IllegalState),
         };
 
@@ -516,6 +604,15 @@ where
             None => Err(DetachError::IllegalState),
         }
     }
+
+    fn abandon(&mut self, closed: bool) -> Option<InputHandle> {
+        self.set_local_state(match closed {
+            true => LinkState::Closed,
+            false => LinkState::Detached,
+        });
+        self.output_handle.take();
+        self.input_handle.take()
+    }
 }
 
 #[derive(Debug)]
@@ -528,6 +625,10 @@ pub(crate) enum LinkRelay<O> {
         flow_state: SenderRelayFlowState,
         unsettled: ArcSenderUnsettledMap,
         receiver_settle_mode: ReceiverSettleMode,
+        #[cfg(feature = "debug-deliveries")]
+        delivery_traces: delivery_trace::ArcDeliveryTraces,
+        #[cfg(not(target_arch = "wasm32"))]
+        latency: latency::ArcSettlementLatency,
     },
     Receiver {
         tx: mpsc::Sender<LinkIncomingItem>,
@@ -536,14 +637,39 @@ pub(crate) enum LinkRelay<O> {
         unsettled: ArcReceiverUnsettledMap,
         receiver_settle_mode: ReceiverSettleMode,
         more: bool,
+        #[cfg(feature = "debug-deliveries")]
+        delivery_traces: delivery_trace::ArcDeliveryTraces,
     },
 }
 
+impl<O> LinkRelay<O> {
+    /// Role of the local link endpoint
+    pub(crate) fn role(&self) -> Role {
+        match self {
+            Self::Sender { .. } => Role::Sender,
+            Self::Receiver { .. } => Role::Receiver,
+        }
+    }
+
+    #[cfg(feature = "debug-deliveries")]
+    fn delivery_traces(&self) -> &delivery_trace::ArcDeliveryTraces {
+        match self {
+            Self::Sender {
+                delivery_traces, ..
+            }
+            | Self::Receiver {
+                delivery_traces, ..
+            } => delivery_traces,
+        }
+    }
+}
+
 impl LinkRelay<()> {
     pub fn new_sender(
         tx: mpsc::Sender<LinkIncomingItem>,
         flow_state: SenderRelayFlowState,
         unsettled: ArcSenderUnsettledMap,
+        #[cfg(not(target_arch = "wasm32"))] latency: latency::ArcSettlementLatency,
     ) -> Self {
         Self::Sender {
             tx,
@@ -551,6 +677,10 @@ impl LinkRelay<()> {
             flow_state,
             unsettled,
             receiver_settle_mode: Default::default(),
+            #[cfg(feature = "debug-deliveries")]
+            delivery_traces: Default::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            latency,
         }
     }
 
@@ -567,9 +697,25 @@ impl LinkRelay<()> {
             unsettled,
             receiver_settle_mode,
             more: false,
+            #[cfg(feature = "debug-deliveries")]
+            delivery_traces: Default::default(),
         }
     }
 
+    /// Shares the delivery traces of the link with the relay
+    #[cfg(feature = "debug-deliveries")]
+    pub fn with_delivery_traces(mut self, traces: delivery_trace::ArcDeliveryTraces) -> Self {
+        match &mut self {
+            Self::Sender {
+                delivery_traces, ..
+            }
+            | Self::Receiver {
+                delivery_traces, ..
+            } => *delivery_traces = traces,
+        }
+        self
+    }
+
     pub fn with_output_handle(self, output_handle: OutputHandle) -> LinkRelay<OutputHandle> {
         match self {
             LinkRelay::Sender {
@@ -577,6 +723,10 @@ impl LinkRelay<()> {
                 flow_state,
                 unsettled,
                 receiver_settle_mode,
+                #[cfg(feature = "debug-deliveries")]
+                delivery_traces,
+                #[cfg(not(target_arch = "wasm32"))]
+                latency,
                 ..
             } => LinkRelay::Sender {
                 tx,
@@ -584,6 +734,10 @@ impl LinkRelay<()> {
                 flow_state,
                 unsettled,
                 receiver_settle_mode,
+                #[cfg(feature = "debug-deliveries")]
+                delivery_traces,
+                #[cfg(not(target_arch = "wasm32"))]
+                latency,
             },
             LinkRelay::Receiver {
                 tx,
@@ -591,6 +745,8 @@ impl LinkRelay<()> {
                 unsettled,
                 receiver_settle_mode,
                 more,
+                #[cfg(feature = "debug-deliveries")]
+                delivery_traces,
                 ..
             } => LinkRelay::Receiver {
                 tx,
@@ -599,18 +755,27 @@ impl LinkRelay<()> {
                 unsettled,
                 receiver_settle_mode,
                 more,
+                #[cfg(feature = "debug-deliveries")]
+                delivery_traces,
             },
         }
     }
 }
 
 impl LinkRelay<OutputHandle> {
-    // pub(crate) fn output_handle(&self) -> &OutputHandle {
-    //     match self {
-    //         Self::Sender { output_handle, .. } => output_handle,
-    //         Self::Receiver { output_handle, .. } => output_handle,
-    //     }
-    // }
+    pub(crate) fn output_handle(&self) -> &OutputHandle {
+        match self {
+            Self::Sender { output_handle, .. } => output_handle,
+            Self::Receiver { output_handle, .. } => output_handle,
+        }
+    }
+
+    pub(crate) fn unsettled_count(&self) -> usize {
+        match self {
+            Self::Sender { unsettled, .. } => unsettled.read().as_ref().map_or(0, |m| m.len()),
+            Self::Receiver { unsettled, .. } => unsettled.read().as_ref().map_or(0, |m| m.len()),
+        }
+    }
 
     pub(crate) async fn send(
         &mut self,
@@ -622,7 +787,6 @@ impl LinkRelay<OutputHandle> {
         }
     }
 
-    #[allow(unused_variables)]
     pub(crate) async fn on_incoming_flow(
         &mut self,
         flow: LinkFlow,
@@ -631,23 +795,8 @@ impl LinkRelay<OutputHandle> {
             LinkRelay::Sender {
                 flow_state,
                 output_handle,
-                tx,
                 ..
             } => {
-                #[cfg(feature = "transaction")]
-                {
-                    use serde_amqp::Value;
-                    match flow.properties.as_ref().and_then(|m| m.get(TXN_ID_KEY)) {
-                        Some(Value::Binary(txn_id)) => {
-                            let frame = LinkFrame::Acquisition(txn_id.clone());
-                            tx.send(frame)
-                                .await
-                                .map_err(|_| LinkRelayError::UnattachedHandle)?;
-                        }
-                        Some(_) | None => {}
-                    }
-                }
-
                 let ret = flow_state.produce((flow, output_handle.clone())).await;
                 Ok(ret)
             }
@@ -673,10 +822,21 @@ impl LinkRelay<OutputHandle> {
         // sessions
         delivery_tag: DeliveryTag,
     ) -> bool {
+        #[cfg(feature = "debug-deliveries")]
+        self.delivery_traces().lock().record(
+            &delivery_tag,
+            delivery_trace::DeliveryEventKind::DispositionReceived {
+                settled,
+                state: state.clone(),
+            },
+        );
+
         match self {
             LinkRelay::Sender {
                 unsettled,
                 receiver_settle_mode,
+                #[cfg(not(target_arch = "wasm32"))]
+                latency,
                 ..
             } => {
                 let echo = if settled {
@@ -686,12 +846,16 @@ impl LinkRelay<OutputHandle> {
 
                     // Since we are settling (ie. forgetting) this message, we don't care whether the
                     // receiving end is alive or not
-                    {
+                    let settled_msg = {
                         let mut guard = unsettled.write();
-                        guard
-                            .as_mut()
-                            .and_then(|m| m.swap_remove(&delivery_tag))
-                            .map(|msg| msg.settle_with_state(state));
+                        guard.as_mut().and_then(|m| m.swap_remove(&delivery_tag))
+                    };
+                    if let Some(msg) = settled_msg {
+                        // The hook of a slow settlement alert must not be called while holding
+                        // the lock on the unsettled map
+                        #[cfg(not(target_arch = "wasm32"))]
+                        latency.record(&delivery_tag, msg.created.elapsed(), &state);
+                        let _ = msg.settle_with_state(state);
                     }
                     false
                 } else {
@@ -699,21 +863,25 @@ impl LinkRelay<OutputHandle> {
                         Some(s) => s.is_terminal(),
                         None => false, // Probably should not assume the state is not specified
                     };
-                    {
+                    let settled_msg = {
                         let mut guard = unsettled.write();
                         // Once the receiving application has finished processing the message,
                         // it indicates to the link endpoint a **terminal delivery state** that
                         // reflects the outcome of the application processing
                         if is_terminal {
-                            let _result = guard
-                                .as_mut()
-                                .and_then(|m| m.swap_remove(&delivery_tag))
-                                .map(|msg| msg.settle_with_state(state));
-                        } else if let Some(msg) =
-                            guard.as_mut().and_then(|m| m.get_mut(&delivery_tag))
-                        {
-                            msg.state = state;
+                            guard.as_mut().and_then(|m| m.swap_remove(&delivery_tag))
+                        } else {
+                            if let Some(msg) = guard.as_mut().and_then(|m| m.get_mut(&delivery_tag))
+                            {
+                                msg.state = state.clone();
+                            }
+                            None
                         }
+                    };
+                    if let Some(msg) = settled_msg {
+                        #[cfg(not(target_arch = "wasm32"))]
+                        latency.record(&delivery_tag, msg.created.elapsed(), &state);
+                        let _result = msg.settle_with_state(state);
                     }
 
                     // If the receiver is in mode Second, it will send a non-settled terminal state
@@ -758,15 +926,36 @@ impl LinkRelay<OutputHandle> {
     /// LinkRelay operates in session's event loop
     ///
     /// The session needs a map of delivery_id and delivery_tag
+    ///
+    /// If `dispatcher` is `Some(_)`, the transfer is queued instead of waiting for the link to
+    /// take it
     pub(crate) async fn on_incoming_transfer(
         &mut self,
         transfer: Transfer,
         payload: Payload,
+        dispatcher: Option<&mut InboundDispatcher>,
     ) -> Result<Option<(DeliveryNumber, DeliveryTag)>, LinkRelayError> {
+        #[cfg(feature = "debug-deliveries")]
+        if let LinkRelay::Receiver {
+            delivery_traces, ..
+        } = self
+        {
+            delivery_traces.lock().record_incoming_transfer(
+                transfer.delivery_tag.as_ref(),
+                delivery_trace::DeliveryEventKind::TransferReceived {
+                    delivery_id: transfer.delivery_id,
+                    settled: transfer.settled,
+                    more: transfer.more,
+                    resume: transfer.resume,
+                },
+            );
+        }
+
         match self {
             LinkRelay::Sender { .. } => Err(LinkRelayError::TransferFrameToSender),
             LinkRelay::Receiver {
                 tx,
+                output_handle,
                 receiver_settle_mode,
                 more,
                 ..
@@ -776,13 +965,20 @@ impl LinkRelay<OutputHandle> {
                 let delivery_tag = transfer.delivery_tag.clone();
                 let transfer_more = transfer.more;
 
-                tx.send(LinkFrame::Transfer {
+                let frame = LinkFrame::Transfer {
                     input_handle: InputHandle::from(transfer.handle.clone()),
                     performative: transfer,
                     payload,
-                })
-                .await
-                .map_err(|_| LinkRelayError::UnattachedHandle)?;
+                };
+                match dispatcher {
+                    Some(dispatcher) => {
+                        dispatcher.dispatch(&Handle::from(output_handle.clone()), tx, frame)?
+                    }
+                    None => tx
+                        .send(frame)
+                        .await
+                        .map_err(|_| LinkRelayError::UnattachedHandle)?,
+                }
 
                 if !settled {
                     if let ReceiverSettleMode::Second = receiver_settle_mode {
@@ -0,0 +1,198 @@
+//! A sender and a receiver that are attached and detached together
+
+use fe2o3_amqp_types::{definitions::Fields, messaging};
+
+use crate::session::SessionHandle;
+
+use super::{DetachError, LinkPairAttachError, Receiver, Sender};
+
+/// A [`Sender`] and a [`Receiver`] attached to the same address on the same session
+///
+/// This is a common pattern for full-duplex request/response channels (eg. management, CBS and
+/// RPC protocols). The two links are named after a shared base name (`"<base_name>-sender"` and
+/// `"<base_name>-receiver"`), carry the same link properties, and are closed together.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use fe2o3_amqp::link::LinkPair;
+///
+/// let mut pair = LinkPair::attach(&mut session, "rpc-client", "rpc-server").await.unwrap();
+/// pair.sender_mut().send("request").await.unwrap();
+/// let delivery = pair.receiver_mut().recv::<String>().await.unwrap();
+/// pair.receiver_mut().accept(&delivery).await.unwrap();
+/// pair.close().await.unwrap();
+/// ```
+#[derive(Debug)]
+pub struct LinkPair {
+    sender: Sender,
+    receiver: Receiver,
+}
+
+impl LinkPair {
+    /// Creates a builder for [`LinkPair`]
+    pub fn builder() -> LinkPairBuilder {
+        LinkPairBuilder::default()
+    }
+
+    /// Attaches a sender and a receiver to the address with the default configuration
+    pub async fn attach<R>(
+        session: &mut SessionHandle<R>,
+        base_name: impl Into<String>,
+        address: impl Into<messaging::Address>,
+    ) -> Result<Self, LinkPairAttachError> {
+        Self::builder()
+            .base_name(base_name)
+            .address(address)
+            .attach(session)
+            .await
+    }
+
+    /// Get a reference to the sender
+    pub fn sender(&self) -> &Sender {
+        &self.sender
+    }
+
+    /// Get a mutable reference to the sender
+    pub fn sender_mut(&mut self) -> &mut Sender {
+        &mut self.sender
+    }
+
+    /// Get a reference to the receiver
+    pub fn receiver(&self) -> &Receiver {
+        &self.receiver
+    }
+
+    /// Get a mutable reference to the receiver
+    pub fn receiver_mut(&mut self) -> &mut Receiver {
+        &mut self.receiver
+    }
+
+    /// Splits the pair into the sender and the receiver. The two links will no longer be closed
+    /// together
+    pub fn into_inner(self) -> (Sender, Receiver) {
+        (self.sender, self.receiver)
+    }
+
+    /// Closes both links concurrently
+    ///
+    /// Both links are always closed. The error of the sender is returned if both fail.
+    pub async fn close(self) -> Result<(), DetachError> {
+        let (sender_result, receiver_result) =
+            futures_util::join!(self.sender.close(), self.receiver.close());
+        sender_result.and(receiver_result)
+    }
+
+    /// Closes both links concurrently with the same error
+    pub async fn close_with_error(
+        self,
+        error: impl Into<fe2o3_amqp_types::definitions::Error>,
+    ) -> Result<(), DetachError> {
+        let error = error.into();
+        let (sender_result, receiver_result) = futures_util::join!(
+            self.sender.close_with_error(error.clone()),
+            self.receiver.close_with_error(error)
+        );
+        sender_result.and(receiver_result)
+    }
+
+    /// Detaches both links concurrently without closing them
+    ///
+    /// Both links are always detached. The error of the sender is returned if both fail.
+    pub async fn detach(self) -> Result<(), DetachError> {
+        let (sender_result, receiver_result) =
+            futures_util::join!(self.sender.detach(), self.receiver.detach());
+        sender_result
+            .map(|_| ())
+            .map_err(|(_, error)| error)
+            .and(receiver_result.map(|_| ()).map_err(|(_, error)| error))
+    }
+}
+
+/// Builder for [`LinkPair`]
+///
+/// | Field | Default Value |
+/// |-------|---------------|
+/// |`base_name`| `String::default()` |
+/// |`address`| `String::default()` |
+/// |`reply_address`| `None` (the `base_name` is used) |
+/// |`properties`| `None` |
+#[derive(Debug, Clone, Default)]
+pub struct LinkPairBuilder {
+    /// Base name of the links. The sender is named `"<base_name>-sender"` and the receiver is
+    /// named `"<base_name>-receiver"`
+    pub base_name: String,
+
+    /// Target address of the sender and source address of the receiver
+    pub address: messaging::Address,
+
+    /// Target address of the receiver. This is usually the address that the remote peer will
+    /// send replies to (ie. the `reply-to` property of a request). The `base_name` is used if
+    /// this is not set
+    pub reply_address: Option<messaging::Address>,
+
+    /// Link properties that are set on both links
+    pub properties: Option<Fields>,
+}
+
+impl LinkPairBuilder {
+    /// Base name of the links
+    pub fn base_name(mut self, base_name: impl Into<String>) -> Self {
+        self.base_name = base_name.into();
+        self
+    }
+
+    /// Target address of the sender and source address of the receiver
+    pub fn address(mut self, address: impl Into<messaging::Address>) -> Self {
+        self.address = address.into();
+        self
+    }
+
+    /// Target address of the receiver
+    pub fn reply_address(mut self, reply_address: impl Into<messaging::Address>) -> Self {
+        self.reply_address = Some(reply_address.into());
+        self
+    }
+
+    /// Link properties that are set on both links
+    pub fn properties(mut self, properties: Fields) -> Self {
+        self.properties = Some(properties);
+        self
+    }
+
+    /// Attaches the sender and then the receiver
+    ///
+    /// The sender is closed if the receiver fails to attach.
+    pub async fn attach<R>(
+        self,
+        session: &mut SessionHandle<R>,
+    ) -> Result<LinkPair, LinkPairAttachError> {
+        // NOTE: link properties must be set after the target because setting the target resets
+        // the properties of the link builder
+        let mut sender_builder = Sender::builder()
+            .name(format!("{}-sender", self.base_name))
+            .target(self.address.clone());
+        if let Some(properties) = self.properties.clone() {
+            sender_builder = sender_builder.properties(properties);
+        }
+        let sender = sender_builder.attach(session).await?;
+
+        let reply_address = self.reply_address.unwrap_or_else(|| self.base_name.clone());
+        let mut receiver_builder = Receiver::builder()
+            .name(format!("{}-receiver", self.base_name))
+            .source(self.address)
+            .target(reply_address);
+        if let Some(properties) = self.properties {
+            receiver_builder = receiver_builder.properties(properties);
+        }
+        let receiver = match receiver_builder.attach(session).await {
+            Ok(receiver) => receiver,
+            Err(error) => {
+                let _ = sender.close().await;
+                return Err(error.into());
+            }
+        };
+
+        Ok(LinkPair { sender, receiver })
+    }
+}
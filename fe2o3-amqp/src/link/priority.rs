@@ -0,0 +1,150 @@
+//! Reordering of the prefetched deliveries of a receiver by message priority
+
+use std::collections::VecDeque;
+
+use fe2o3_amqp_types::{messaging::Header, performatives::Detach};
+
+use super::LinkFrame;
+
+/// Frames that are taken off the incoming channel of a receiver ahead of time so that the
+/// deliveries can be returned in the order of the `priority` of their header
+#[derive(Debug, Default)]
+pub(crate) struct PriorityBuffer {
+    // The priority is `None` for the frames that cannot be reordered
+    frames: VecDeque<(LinkFrame, Option<u8>)>,
+}
+
+impl PriorityBuffer {
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn push(&mut self, frame: LinkFrame) {
+        let priority = match &frame {
+            LinkFrame::Transfer {
+                performative,
+                payload,
+                ..
+            } if !performative.more => Some(header_priority(payload)),
+            _ => None,
+        };
+        self.frames.push_back((frame, priority));
+    }
+
+    /// Takes the frame that was received first
+    pub fn pop_front(&mut self) -> Option<LinkFrame> {
+        self.frames.pop_front().map(|(frame, _)| frame)
+    }
+
+    /// Takes the single frame delivery with the highest priority that is received before any
+    /// other kind of frame. The deliveries of the same priority are taken in the order of arrival
+    pub fn pop_highest_priority(&mut self) -> Option<LinkFrame> {
+        let mut highest: Option<(usize, u8)> = None;
+        for (index, (_, priority)) in self.frames.iter().enumerate() {
+            let priority = match priority {
+                Some(priority) => *priority,
+                None => break,
+            };
+            if highest.map(|(_, p)| priority > p).unwrap_or(true) {
+                highest = Some((index, priority));
+            }
+        }
+        match highest {
+            Some((index, _)) => self.frames.remove(index).map(|(frame, _)| frame),
+            None => self.pop_front(),
+        }
+    }
+
+    /// Takes the prefetched Detach, discarding the frames before it
+    pub fn take_detach(&mut self) -> Option<Detach> {
+        while let Some(frame) = self.pop_front() {
+            if let LinkFrame::Detach(detach) = frame {
+                return Some(detach);
+            }
+        }
+        None
+    }
+}
+
+/// The priority in the header of an encoded message, or the default priority if the message has
+/// no header
+fn header_priority(payload: &[u8]) -> u8 {
+    serde_amqp::from_slice::<Header>(payload)
+        .map(|header| header.priority)
+        .unwrap_or_default()
+        .0
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use fe2o3_amqp_types::{
+        messaging::{message::__private::Serializable, Header, Message},
+        performatives::{Detach, Transfer},
+    };
+
+    use super::PriorityBuffer;
+    use crate::{endpoint::InputHandle, link::LinkFrame};
+
+    fn transfer(id: u32, priority: Option<u8>) -> LinkFrame {
+        let message = Message::builder().value(id);
+        let message = match priority {
+            Some(priority) => message.header(Header::builder().priority(priority).build()),
+            None => message,
+        };
+        let payload = serde_amqp::to_vec(&Serializable(message.build())).unwrap();
+        LinkFrame::Transfer {
+            input_handle: InputHandle(0),
+            performative: Transfer {
+                handle: 0.into(),
+                delivery_id: Some(id),
+                delivery_tag: Some(id.to_be_bytes().to_vec().into()),
+                message_format: Some(0),
+                settled: Some(true),
+                more: false,
+                rcv_settle_mode: None,
+                state: None,
+                resume: false,
+                aborted: false,
+                batchable: false,
+            },
+            payload: Bytes::from(payload),
+        }
+    }
+
+    fn delivery_id(frame: Option<LinkFrame>) -> Option<u32> {
+        match frame {
+            Some(LinkFrame::Transfer { performative, .. }) => performative.delivery_id,
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn deliveries_are_taken_by_priority_then_arrival() {
+        let mut buffer = PriorityBuffer::default();
+        buffer.push(transfer(0, Some(1)));
+        buffer.push(transfer(1, None));
+        buffer.push(transfer(2, Some(9)));
+        buffer.push(transfer(3, Some(4)));
+        buffer.push(LinkFrame::Detach(Detach {
+            handle: 0.into(),
+            closed: true,
+            error: None,
+        }));
+        buffer.push(transfer(4, Some(9)));
+
+        let order: Vec<_> = (0..4)
+            .map(|_| delivery_id(buffer.pop_highest_priority()).unwrap())
+            .collect();
+        // The message without a header has the default priority of 4
+        assert_eq!(order, [2, 1, 3, 0]);
+
+        // Deliveries after the Detach are not moved ahead of it
+        assert!(matches!(
+            buffer.pop_highest_priority(),
+            Some(LinkFrame::Detach(_))
+        ));
+        assert_eq!(delivery_id(buffer.pop_highest_priority()), Some(4));
+        assert!(buffer.is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+//! Pacing of the incoming messages on a receiver
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Upper bound on the rate of incoming messages on a [`Receiver`](super::Receiver)
+///
+/// The rate is enforced by holding back the link credit rather than buffering the messages, so
+/// the messages that the receiver is not ready for stay on the remote peer where the backlog is
+/// visible. The rate limit only takes effect in [`CreditMode::Auto`](super::receiver::CreditMode),
+/// in which the credit is replenished no faster than the limit allows. The credit granted on
+/// attach and up to one second worth of messages can arrive as a burst.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimit {
+    /// Maximum number of messages per second
+    Messages(u32),
+
+    /// Maximum number of payload bytes per second. As the size of the next message is not known
+    /// in advance, the credit is held back once the budget is exceeded until it is paid back
+    Bytes(u64),
+}
+
+impl RateLimit {
+    fn per_second(&self) -> f64 {
+        match self {
+            RateLimit::Messages(rate) => *rate as f64,
+            RateLimit::Bytes(rate) => *rate as f64,
+        }
+    }
+}
+
+/// Token bucket that holds up to one second worth of budget
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+
+    /// The link credit that is granted the last time, which bounds how many deliveries are
+    /// processed before the credit is replenished
+    pub(crate) granted: u32,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.per_second(),
+            last_refill: now,
+            granted: u32::MAX,
+        }
+    }
+
+    pub(crate) fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.last_refill = self.last_refill.max(now);
+        let rate = self.limit.per_second();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+    }
+
+    /// Records a received transfer frame. The budget can become negative
+    pub(crate) fn on_transfer(&mut self, payload_len: usize, complete: bool, now: Instant) {
+        self.refill(now);
+        self.tokens -= match self.limit {
+            RateLimit::Messages(_) if complete => 1.0,
+            RateLimit::Messages(_) => 0.0,
+            RateLimit::Bytes(_) => payload_len as f64,
+        };
+    }
+
+    /// How long to wait until credit can be granted again
+    pub(crate) fn delay(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        let rate = self.limit.per_second();
+        if rate <= 0.0 {
+            return Duration::MAX;
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) / rate)
+    }
+
+    /// The link credit that can be granted now, which is at most `max_credit`
+    pub(crate) fn credit(&mut self, max_credit: u32, now: Instant) -> u32 {
+        self.refill(now);
+        match self.limit {
+            RateLimit::Messages(_) => (self.tokens.max(0.0) as u32).min(max_credit),
+            RateLimit::Bytes(_) if self.tokens >= 1.0 => max_credit,
+            RateLimit::Bytes(_) => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::{RateLimit, RateLimiter};
+
+    #[test]
+    fn message_credit_follows_the_budget() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(RateLimit::Messages(10), start);
+        assert_eq!(limiter.credit(100, start), 10);
+        for _ in 0..10 {
+            limiter.on_transfer(0, true, start);
+        }
+        assert_eq!(limiter.credit(100, start), 0);
+        let delay = limiter.delay(start);
+        assert!(delay > Duration::from_millis(99) && delay <= Duration::from_millis(100));
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.delay(later), Duration::ZERO);
+        assert_eq!(limiter.credit(3, later), 3);
+        assert_eq!(limiter.credit(100, later), 5);
+    }
+
+    #[test]
+    fn byte_budget_is_paid_back_before_granting_credit() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(RateLimit::Bytes(1000), start);
+        limiter.on_transfer(2000, false, start);
+        limiter.on_transfer(1000, true, start);
+        assert_eq!(limiter.credit(100, start), 0);
+        let delay = limiter.delay(start);
+        assert!(delay > Duration::from_millis(2000) && delay <= Duration::from_millis(2001));
+
+        let later = start + Duration::from_millis(2001);
+        assert_eq!(limiter.credit(100, later), 100);
+    }
+}
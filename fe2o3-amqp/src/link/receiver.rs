@@ -1,48 +1,86 @@
 //! Implementation of AMQP1.0 receiver
 
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
 
 use fe2o3_amqp_types::{
-    definitions::{self, DeliveryTag, Fields, SequenceNo},
+    definitions::{self, AmqpError, DeliveryTag, Fields, Role, SequenceNo},
     messaging::{
-        Accepted, Address, DeliveryState, FromBody, Modified, Rejected, Released, Source, Target,
+        Accepted, Address, Body, DeliveryState, FromBody, Modified, Rejected, Released, Source,
+        Target,
     },
     performatives::{Attach, Detach, Transfer},
+    primitives::{Symbol, Value},
 };
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
 cfg_not_wasm32! {
-    use std::time::Duration;
-    use tokio::time::{error::Elapsed, timeout};
+    use tokio::{
+        sync::broadcast,
+        time::{error::Elapsed, timeout, Instant},
+    };
+
+    use fe2o3_amqp_types::messaging::MessageId;
+
+    use futures_util::FutureExt;
+    use parking_lot::Mutex;
+
+    use super::dedup::{Deduplication, Deduplicator};
+    use super::disposition_batching::{DispositionBatcher, DispositionBatching};
+    use super::keep_alive::{LinkEvent, LinkEvents};
+    use super::rate_limit::{RateLimit, RateLimiter};
+    use super::spool::{DeliveryBody, Spooling};
+    use super::unsettled::{UnsettledArrivals, UnsettledDelivery};
+
+    use crate::clock::{self, SharedClock};
 }
 
 use crate::{
     control::SessionControl,
     endpoint::{self, LinkAttach, LinkDetach, LinkExt},
     session::SessionHandle,
+    util::{AsByteIterator, IdleTimeout, IntoReader},
     Payload,
 };
 
 use super::{
     builder::{self, WithTarget, WithoutName, WithoutSource},
+    byte_credit::{ByteWindow, BYTE_LIMIT_KEY},
     delivery::{Delivery, DeliveryInfo},
     error::DetachError,
     incomplete_transfer::IncompleteTransfer,
-    receiver_link::count_number_of_sections_and_offset,
+    message_format::{CustomFormat, DecodeDelivery, MessageFormatRegistry},
+    priority::PriorityBuffer,
+    receiver_link::{count_number_of_sections_and_offset, has_body_section},
     role,
     shared_inner::{LinkEndpointInner, LinkEndpointInnerDetach, LinkEndpointInnerReattach},
-    ArcReceiverUnsettledMap, DetachThenResumeReceiverError, DispositionError,
-    IllegalLinkStateError, LinkFrame, LinkRelay, LinkStateError, ReceiverAttachError,
+    stream::ReceiverStream,
+    ArcReceiverUnsettledMap, DetachThenResumeReceiverError, DispositionError, FlowError,
+    IllegalLinkStateError, LinkFrame, LinkRelay, LinkState, LinkStateError, ReceiverAttachError,
     ReceiverAttachExchange, ReceiverFlowState, ReceiverLink, ReceiverResumeError,
-    ReceiverResumeErrorKind, ReceiverTransferError, RecvError, DEFAULT_CREDIT,
+    ReceiverResumeErrorKind, ReceiverTransferError, RecvError, RemoteFlowState, SuspendedLink,
+    DEFAULT_CREDIT,
 };
 
-cfg_transaction! {
-    use fe2o3_amqp_types::definitions::AmqpError;
+cfg_checksum! {
+    use super::checksum::{Checksum, Verification};
 }
 
+cfg_compression! {
+    use super::compression;
+}
+
+cfg_debug_deliveries! {
+    use super::delivery_trace::DeliveryTrace;
+}
+
+#[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+use crate::acceptor::authorization::TransferAuthorization;
+
 #[cfg(docsrs)]
-use fe2o3_amqp_types::messaging::{AmqpSequence, AmqpValue, Batch, Body};
+use fe2o3_amqp_types::messaging::{AmqpSequence, AmqpValue, Batch, FromEmptyBody};
 
 /// Credit mode for the link
 #[derive(Debug, Clone)]
@@ -62,6 +100,23 @@ impl Default for CreditMode {
     }
 }
 
+/// How the receiver handles the incoming messages that carry no body section
+///
+/// Some brokers send messages that only carry a header or properties, eg. as markers or
+/// keep-alives. Such a message can only be decoded into a body type whose [`FromEmptyBody`]
+/// implementation does not return an error, eg. `Option<T>` or [`Body<T>`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmptyBodyPolicy {
+    /// The message is decoded into the requested body type like any other message. Decoding
+    /// fails if the body type does not accept an empty body
+    #[default]
+    Decode,
+
+    /// The message is accepted and then dropped, and the receiver keeps waiting for the next
+    /// message
+    Skip,
+}
+
 /// An AMQP1.0 receiver
 ///
 /// # Attach a new receiver with default configurations
@@ -137,11 +192,139 @@ impl Receiver {
         &self.inner.credit_mode
     }
 
-    /// Set the credit mode
+    /// Switch the credit mode of the attached link
+    ///
+    /// Switching from `CreditMode::Auto` to `CreditMode::Manual` drains the link, so the remote
+    /// sender uses up or gives back the outstanding credit and the consumption pauses until credit
+    /// is set with [`Receiver::set_credit()`]. Switching to `CreditMode::Auto(n)` issues a credit
+    /// of `n` and stops draining. The link stays attached, so the state of the link on the remote
+    /// peer (eg. the unsettled deliveries) is kept.
+    ///
+    /// The messages that are already in flight when the link is drained can still be received
+    /// with [`Receiver::recv()`].
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe as internally it only `.await` on sending over `tokio::mpsc::Sender`
+    pub async fn set_credit_mode(
+        &mut self,
+        credit_mode: CreditMode,
+    ) -> Result<(), IllegalLinkStateError> {
+        self.inner.set_credit_mode(credit_mode).await
+    }
+
+    /// Get the rate limit on the incoming messages
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.inner
+            .rate_limiter
+            .as_ref()
+            .map(|limiter| limiter.limit())
+    }
+
+    /// Set the rate limit on the incoming messages. This only takes effect in `CreditMode::Auto`
     ///
-    /// This will not send a flow to the remote peer even if credits in `CreditMode::Auto` is changed.
-    pub fn set_credit_mode(&mut self, credit_mode: CreditMode) {
-        self.inner.credit_mode = credit_mode;
+    /// See [`RateLimit`] for how the rate is enforced.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_rate_limit(&mut self, rate_limit: Option<RateLimit>) {
+        self.inner.rate_limiter =
+            rate_limit.map(|limit| Box::new(RateLimiter::new(limit, self.inner.clock.now())));
+    }
+
+    /// Get the window of the recently received message ids that is used to drop the
+    /// redelivered messages
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn deduplication(&self) -> Option<Deduplication> {
+        self.inner.deduplicator.as_ref().map(|dedup| dedup.config())
+    }
+
+    /// Set the window of the recently received message ids that is used to drop the redelivered
+    /// messages. The message ids that are already in the window are discarded
+    ///
+    /// See [`Deduplication`] for how the duplicates are handled.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_deduplication(&mut self, deduplication: Option<Deduplication>) {
+        self.inner.deduplicator = deduplication.map(|config| Box::new(Deduplicator::new(config)));
+    }
+
+    /// Get the batching of the accepts into ranged dispositions
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn disposition_batching(&self) -> Option<DispositionBatching> {
+        self.inner
+            .disposition_batcher
+            .as_ref()
+            .map(|batcher| batcher.lock().config())
+    }
+
+    /// Sends the accepts that are held back by [`DispositionBatching`]
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe. The accepts that are not sent yet stay pending
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn flush_dispositions(&self) -> Result<(), DispositionError> {
+        self.inner.flush_dispositions().await
+    }
+
+    /// Get the spooling of the large multi-frame deliveries to disk
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn spooling(&self) -> Option<&Spooling> {
+        self.inner.spooling.as_ref()
+    }
+
+    /// Set the spooling of the large multi-frame deliveries to disk. This takes effect from the
+    /// next delivery
+    ///
+    /// See [`Spooling`] for details.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_spooling(&mut self, spooling: Option<Spooling>) {
+        self.inner.spooling = spooling;
+    }
+
+    /// The limit on the payload bytes that are received but not yet taken by `recv()`, or `None`
+    /// if the receiver only grants link credit
+    ///
+    /// See [`byte_credit`](crate::link::byte_credit) for how the limit is granted.
+    pub fn byte_window(&self) -> Option<u64> {
+        self.inner
+            .byte_window
+            .as_ref()
+            .map(|window| window.window())
+    }
+
+    /// Whether the prefetched deliveries are returned in the order of their priority
+    pub fn priority_ordering(&self) -> bool {
+        self.inner.priority_ordering
+    }
+
+    /// Set whether the prefetched deliveries are returned in the order of their priority
+    ///
+    /// See [`Builder::priority_ordering()`](crate::link::builder::Builder::priority_ordering) for
+    /// details.
+    pub fn set_priority_ordering(&mut self, value: bool) {
+        self.inner.priority_ordering = value;
+    }
+
+    /// Number of the redelivered messages that are dropped by the deduplication
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn suppressed_duplicates(&self) -> u64 {
+        self.inner
+            .deduplicator
+            .as_ref()
+            .map(|dedup| dedup.suppressed)
+            .unwrap_or(0)
+    }
+
+    /// Remove a message id from the deduplication window so that a redelivery of the message is
+    /// not treated as a duplicate, eg. after the message is released. Returns whether the
+    /// message id was in the window
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn forget_message_id(&mut self, message_id: &MessageId) -> bool {
+        self.inner
+            .deduplicator
+            .as_mut()
+            .map(|dedup| dedup.forget(message_id))
+            .unwrap_or(false)
     }
 
     /// Get the `auto_accept` field of receiver
@@ -154,6 +337,27 @@ impl Receiver {
         self.inner.auto_accept = value;
     }
 
+    /// Number of times the peer reused the delivery tag of an unsettled delivery or resumed a
+    /// delivery that is not found among the unsettled deliveries
+    ///
+    /// Each violation is returned by [`Receiver::recv()`] as [`RecvError::DeliveryTagReused`] or
+    /// [`RecvError::ConflictingResume`], and the link is detached with an `amqp:not-allowed` error.
+    /// The count is kept when the link is re-attached.
+    pub fn delivery_tag_violations(&self) -> u64 {
+        self.inner.delivery_tag_violations
+    }
+
+    /// Get how long `detach()` and `close()` wait for the remote Detach
+    pub fn detach_timeout(&self) -> Option<Duration> {
+        self.inner.detach_timeout
+    }
+
+    /// Set how long `detach()` and `close()` wait for the remote Detach before detaching the link
+    /// locally
+    pub fn set_detach_timeout(&mut self, timeout: impl Into<Option<Duration>>) {
+        self.inner.detach_timeout = timeout.into();
+    }
+
     /// Get a reference to the link's source field
     pub fn source(&self) -> &Option<Source> {
         &self.inner.link.source
@@ -296,6 +500,81 @@ impl Receiver {
         self.inner.recv().await
     }
 
+    /// Turns the receiver into a [`Stream`](futures_util::Stream) of deliveries, which receives
+    /// a message whenever it is polled. See [`ReceiverStream`] for details
+    ///
+    /// ```rust,ignore
+    /// use futures_util::StreamExt;
+    ///
+    /// let mut stream = receiver.into_stream::<String>();
+    /// while let Some(delivery) = stream.next().await {
+    ///     let delivery = delivery.unwrap();
+    ///     stream.receiver_mut().await.accept(&delivery).await.unwrap();
+    /// }
+    /// ```
+    pub fn into_stream<T>(self) -> ReceiverStream<T>
+    where
+        for<'de> T: FromBody<'de> + Send + Sync + 'static,
+    {
+        ReceiverStream::new(self)
+    }
+
+    /// Receive a message that may have been spooled to disk
+    ///
+    /// A delivery that is spooled because it exceeds the threshold of [`Spooling`] is returned
+    /// as [`DeliveryBody::File`], and any other delivery is decoded into
+    /// [`DeliveryBody::Memory`]. The deliveries are never spooled if spooling is not enabled.
+    ///
+    /// ```rust,ignore
+    /// let delivery: Delivery<DeliveryBody<Body<Value>>> = receiver.recv_spooled().await.unwrap();
+    /// if let DeliveryBody::File(spooled) = delivery.body() {
+    ///     let mut file = spooled.open().unwrap();
+    ///     // Stream the encoded message from the file
+    /// }
+    /// receiver.accept(&delivery).await.unwrap();
+    /// ```
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is cancel-safe.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn recv_spooled<B>(&mut self) -> Result<Delivery<DeliveryBody<B>>, RecvError>
+    where
+        for<'de> B: FromBody<'de> + Send,
+    {
+        self.inner.recv().await
+    }
+
+    /// Receive a message with a custom message format
+    ///
+    /// The payload is decoded by the codec that is registered for the `message-format` of the
+    /// delivery in [`Receiver::message_formats()`]. [`RecvError::UnknownMessageFormat`] is
+    /// returned if no codec is registered for the message format, including the standard AMQP
+    /// message format (zero).
+    ///
+    /// ```rust,ignore
+    /// let delivery: Delivery<CustomFormat> = receiver.recv_custom().await.unwrap();
+    /// let message: &MyFormat = delivery.body().downcast_ref().unwrap();
+    /// receiver.accept(&delivery).await.unwrap();
+    /// ```
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is cancel-safe.
+    pub async fn recv_custom(&mut self) -> Result<Delivery<CustomFormat>, RecvError> {
+        self.inner.recv().await
+    }
+
+    /// Codecs of the custom message formats that are decoded by [`Receiver::recv_custom()`]
+    pub fn message_formats(&self) -> &MessageFormatRegistry {
+        &self.inner.message_formats
+    }
+
+    /// Mutable reference to the codecs of the custom message formats
+    pub fn message_formats_mut(&mut self) -> &mut MessageFormatRegistry {
+        &mut self.inner.message_formats
+    }
+
     /// Set the link credit. This will stop draining if the link is in a draining cycle
     pub async fn set_credit(&mut self, credit: SequenceNo) -> Result<(), IllegalLinkStateError> {
         self.inner.set_credit(credit).await
@@ -309,12 +588,95 @@ impl Receiver {
         self.inner.drain().await
     }
 
+    /// Requests the remote sender to reply with its flow state by sending a Flow with `echo` set
+    /// to true. The returned state carries the `delivery-count`, `link-credit` and `available`
+    /// (ie. the number of messages the sender claims to have in backlog) of the remote sender.
+    ///
+    /// This resolves with the next Flow received from the remote peer, which may not wait for
+    /// the echo if the remote peer happens to send a Flow at the same time. This waits
+    /// indefinitely if the remote peer never replies, so it should be wrapped inside a timeout
+    /// if the remote peer is not trusted to honor the echo request.
+    pub async fn request_flow_echo(&mut self) -> Result<RemoteFlowState, FlowError> {
+        self.inner.request_flow_echo().await
+    }
+
+    /// Returns the deliveries that are not yet settled, in the order they arrived
+    ///
+    /// Each delivery comes with the time since its first transfer arrived, its current state and
+    /// its payload size, eg. to detect the deliveries that the application never disposed. The
+    /// snapshot is a copy and is not updated afterwards
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn unsettled_snapshot(&mut self) -> Vec<UnsettledDelivery> {
+        let guard = self.inner.link.unsettled.read();
+        self.inner
+            .unsettled_arrivals
+            .snapshot(guard.as_ref(), self.inner.clock.now())
+    }
+
+    cfg_debug_deliveries! {
+        /// Returns the recorded frames of a recent delivery, eg. to find out whether a delivery
+        /// was disposed and whether the remote sender settled it. `None` is returned if the
+        /// delivery is older than the
+        /// [`DEFAULT_TRACE_CAPACITY`](super::delivery_trace::DEFAULT_TRACE_CAPACITY) most recent
+        /// deliveries
+        pub fn delivery_trace(&self, delivery_tag: &DeliveryTag) -> Option<DeliveryTrace> {
+            self.inner.link.delivery_traces.lock().get(delivery_tag)
+        }
+
+        /// Returns the recorded frames of the recent deliveries that are not settled yet, in the
+        /// order they arrived
+        pub fn unsettled_delivery_traces(&self) -> Vec<DeliveryTrace> {
+            self.inner.link.delivery_traces.lock().unsettled()
+        }
+    }
+
+    /// Watches the local state of the link, eg. to wait until the link is attached or to detect a
+    /// Detach from the remote sender. The returned receiver is notified on every state transition
+    /// and is closed once the link is dropped
+    ///
+    /// Only the latest state is kept, so a receiver that lags behind may not see the intermediate
+    /// states
+    pub fn watch_state(&self) -> watch::Receiver<LinkState> {
+        self.inner.link.watch_state()
+    }
+
+    /// Subscribes to the events of the link, such as the keep-alives that the remote peer does
+    /// not reply to when the receiver is attached with a [`LinkKeepAlive`](super::LinkKeepAlive)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn subscribe_events(&self) -> broadcast::Receiver<LinkEvent> {
+        self.inner.events.subscribe()
+    }
+
+    /// Returns the properties carried by the last Flow received from the remote sender
+    pub fn remote_flow_properties(&self) -> Option<Fields> {
+        self.inner.link.flow_state.remote_properties()
+    }
+
+    /// Watches the properties carried by the Flows from the remote sender. The returned receiver
+    /// is notified whenever a Flow carries properties that differ from the last ones
+    pub fn watch_remote_flow_properties(&self) -> watch::Receiver<Option<Fields>> {
+        self.inner.link.flow_state.watch_remote_properties()
+    }
+
+    /// Sends a Flow that carries the current flow state of the link with `properties` added to
+    /// the properties of the link. The `properties` are only carried by this Flow and do not
+    /// change the properties of the link
+    pub async fn send_flow_properties(&mut self, properties: Fields) -> Result<(), FlowError> {
+        self.inner
+            .link
+            .send_flow_with_properties(&self.inner.outgoing, properties)
+            .await
+    }
+
     /// Detach the link.
     ///
     /// This will send a `Detach` performative with the `closed` field set to false. If the remote
     /// peer responds with a Detach performative whose `closed` field is set to true, the link will
     /// re-attach and then close by exchanging closing Detach performatives.
     pub async fn detach(mut self) -> Result<DetachedReceiver, (DetachedReceiver, DetachError)> {
+        // A failure to send the accepts is reported by the detach
+        #[cfg(not(target_arch = "wasm32"))]
+        let _ = self.inner.flush_dispositions().await;
         match self.inner.detach_with_error(None).await {
             Ok(_) => Ok(DetachedReceiver { inner: self.inner }),
             Err(err) => Err((DetachedReceiver { inner: self.inner }, err)),
@@ -330,6 +692,8 @@ impl Receiver {
         mut self,
         error: impl Into<definitions::Error>,
     ) -> Result<DetachedReceiver, (DetachedReceiver, DetachError)> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let _ = self.inner.flush_dispositions().await;
         match self.inner.detach_with_error(Some(error.into())).await {
             Ok(_) => Ok(DetachedReceiver { inner: self.inner }),
             Err(err) => Err((DetachedReceiver { inner: self.inner }, err)),
@@ -359,6 +723,8 @@ impl Receiver {
         new_session: &SessionHandle<R>,
     ) -> Result<ReceiverAttachExchange, DetachThenResumeReceiverError> {
         // detach the link
+        #[cfg(not(target_arch = "wasm32"))]
+        let _ = self.inner.flush_dispositions().await;
         let detach_result = self
             .inner
             .detach_with_error(None)
@@ -385,6 +751,8 @@ impl Receiver {
     ///
     /// This will send a Detach performative with the `closed` field set to true.
     pub async fn close(mut self) -> Result<(), DetachError> {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.inner.flush_dispositions().await?;
         self.inner.close_with_error(None).await
     }
 
@@ -397,6 +765,8 @@ impl Receiver {
     ) -> Result<(), DetachError> {
         // Stop link transfer before closing
         self.set_credit(0).await?;
+        #[cfg(not(target_arch = "wasm32"))]
+        self.inner.flush_dispositions().await?;
         self.inner.close_with_error(Some(error.into())).await
     }
 
@@ -545,10 +915,98 @@ pub(crate) struct ReceiverInner<L: endpoint::ReceiverLink> {
 
     // Wrap in a box to avoid clippy warning large_enum_variant on link acceptor's output
     pub(crate) incomplete_transfer: Option<Box<IncompleteTransfer>>,
+
+    // How long to wait for the remote Detach
+    pub(crate) detach_timeout: Option<Duration>,
+
+    // Paces the credit replenished in `CreditMode::Auto`. Boxed for the same reason as
+    // `incomplete_transfer`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) rate_limiter: Option<Box<RateLimiter>>,
+
+    // Recently received message ids to drop the redelivered messages. Boxed for the same reason
+    // as `incomplete_transfer`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) deduplicator: Option<Box<Deduplicator>>,
+
+    // Whether to decompress the incoming message bodies
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+    pub(crate) decompress: bool,
+
+    // Whether the delivery that is being completed exceeds the `max_message_size` once
+    // decompressed
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+    pub(crate) decompressed_size_exceeded: bool,
+
+    // Verifies the hash in the footer of the incoming messages
+    #[cfg(feature = "checksum")]
+    pub(crate) checksum: Option<Checksum>,
+
+    // Whether the hash of the delivery that is being completed does not match
+    #[cfg(feature = "checksum")]
+    pub(crate) checksum_mismatch: bool,
+
+    // Codecs of the custom message formats used by `recv_custom()`
+    pub(crate) message_formats: MessageFormatRegistry,
+
+    // How the messages without a body section are handled
+    pub(crate) empty_body_policy: EmptyBodyPolicy,
+
+    // Number of times the peer reused a delivery tag or resumed an unknown delivery
+    pub(crate) delivery_tag_violations: u64,
+
+    // Arrival time and size of the unsettled incoming deliveries
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) unsettled_arrivals: UnsettledArrivals,
+
+    // Spooling of the large multi-frame deliveries to disk
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) spooling: Option<Spooling>,
+
+    // Accepts that are held back to be sent as ranged dispositions. Boxed for the same reason as
+    // `incomplete_transfer`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) disposition_batcher: Option<Box<Mutex<DispositionBatcher>>>,
+
+    // Whether the prefetched deliveries are returned in the order of their priority
+    pub(crate) priority_ordering: bool,
+
+    // Frames taken off `incoming` ahead of time to be reordered by priority. Boxed for the same
+    // reason as `incomplete_transfer`
+    pub(crate) prefetched: Box<PriorityBuffer>,
+
+    // Limit on the payload bytes granted to the sender in the link properties. Boxed for the
+    // same reason as `incomplete_transfer`
+    pub(crate) byte_window: Option<Box<ByteWindow>>,
+
+    // Authorization of the deliveries on a link accepted by a listener. Boxed for the same reason
+    // as `incomplete_transfer`
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    pub(crate) transfer_authorization: Option<Box<TransferAuthorization>>,
+
+    // Events of the link, such as the missed keep-alives
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) events: LinkEvents,
+
+    // Clock of the connection that drives the timers of the link
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) clock: SharedClock,
 }
 
 impl<L: endpoint::ReceiverLink> Drop for ReceiverInner<L> {
     fn drop(&mut self) {
+        // The accepts are sent only if there is room in the outgoing channel, like the Detach
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(batcher) = &self.disposition_batcher {
+            let pending = batcher.lock().pending();
+            if !pending.is_empty() {
+                let state = DeliveryState::Accepted(Accepted {});
+                let _ = self
+                    .link
+                    .dispose_all(&self.outgoing, pending, None, state, false)
+                    .now_or_never();
+            }
+        }
         if let Some(handle) = self.link.output_handle_mut().take() {
             let detach = Detach {
                 handle: handle.into(),
@@ -586,10 +1044,22 @@ where
         &mut self.incoming
     }
 
+    fn take_prefetched_detach(&mut self) -> Option<Detach> {
+        self.prefetched.take_detach()
+    }
+
     fn buffer_size(&self) -> usize {
         self.buffer_size
     }
 
+    fn detach_timer(&self) -> Option<IdleTimeout> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let timer = |duration| IdleTimeout::with_clock(duration, self.clock.clone());
+        #[cfg(target_arch = "wasm32")]
+        let timer = IdleTimeout::new;
+        self.detach_timeout.map(timer)
+    }
+
     fn as_new_link_relay(&self, tx: mpsc::Sender<LinkFrame>) -> LinkRelay<()> {
         LinkRelay::Receiver {
             tx,
@@ -600,6 +1070,8 @@ where
             // This only controls whether a multi-transfer delivery id
             // will be added to sessions map
             more: false,
+            #[cfg(feature = "debug-deliveries")]
+            delivery_traces: self.link.delivery_traces().clone(),
         }
     }
 
@@ -686,13 +1158,41 @@ where
 {
     pub(crate) async fn recv<T>(&mut self) -> Result<Delivery<T>, RecvError>
     where
-        for<'de> T: FromBody<'de> + Send,
+        T: DecodeDelivery + Send,
     {
         loop {
-            match self.recv_inner().await? // FIXME: cancel safe? if oneshot channel is cancel safe
+            // The pending accepts are sent once they are due while waiting for the next delivery
+            #[cfg(not(target_arch = "wasm32"))]
+            let next = match self.disposition_deadline() {
+                Some(deadline) => {
+                    let clock = self.clock.clone();
+                    match clock::timeout_at(&*clock, deadline, self.recv_inner()).await {
+                        Some(next) => next,
+                        None => {
+                            self.flush_dispositions().await?;
+                            continue;
+                        }
+                    }
+                }
+                None => self.recv_inner().await,
+            };
+            #[cfg(target_arch = "wasm32")]
+            let next = self.recv_inner().await;
+
+            match next? // FIXME: cancel safe? if oneshot channel is cancel safe
             {
+                #[cfg(not(target_arch = "wasm32"))]
+                Some(delivery) if self.is_duplicate(&delivery) => {
+                    // The duplicate is already accepted if auto accept is enabled
+                    if !self.auto_accept {
+                        self.dispose(&delivery, None, Accepted {}.into()).await?;
+                    }
+                    continue;
+                }
                 Some(delivery) => return Ok(delivery),
-                None => continue, // Incomplete transfer, there are more transfer frames coming
+                // Incomplete transfer, there are more transfer frames coming, or the delivery is
+                // rejected by the authorizer
+                None => continue,
             }
         }
     }
@@ -703,17 +1203,20 @@ where
     #[inline]
     pub(crate) async fn recv_inner<T>(&mut self) -> Result<Option<Delivery<T>>, RecvError>
     where
-        for<'de> T: FromBody<'de> + Send,
+        T: DecodeDelivery + Send,
     {
-        let frame = self
-            .incoming
-            .recv()
-            .await // cancel safe
-            .ok_or(LinkStateError::IllegalSessionState)?;
+        // The completion of a multi-frame delivery was cancelled after its last transfer
+        if matches!(&self.incomplete_transfer, Some(incomplete) if incomplete.complete) {
+            return self.on_complete_buffered_transfer().await; // cancel safe
+        }
+
+        let frame = self.next_frame().await?; // cancel safe
 
         match frame {
             LinkFrame::Detach(detach) => {
                 let closed = detach.closed;
+                #[cfg(not(target_arch = "wasm32"))]
+                self.flush_dispositions().await?; // cancel safe
                 self.link.send_detach(&self.outgoing, closed, None).await?; // cancel safe
                 self.link
                     .on_incoming_detach(detach)
@@ -727,24 +1230,196 @@ where
                 input_handle: _,
                 performative,
                 payload,
-            } => self.on_incoming_transfer(performative, payload).await, // cancel safe
-            LinkFrame::Attach(_) => Err(LinkStateError::IllegalState.into()),
+            } => {
+                let granted = self
+                    .byte_window
+                    .as_mut()
+                    .and_then(|window| window.on_received(payload.len() as u64));
+                if let Some(limit) = granted {
+                    self.grant_byte_limit(limit).await?; // cancel safe
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                let payload_len = payload.len();
+                #[cfg(not(target_arch = "wasm32"))]
+                self.record_arrival(&performative, payload_len);
+                let result = self.on_incoming_transfer(performative, payload).await; // cancel safe
+                #[cfg(not(target_arch = "wasm32"))]
+                if let (Some(limiter), Ok(delivery)) = (&mut self.rate_limiter, &result) {
+                    limiter.on_transfer(payload_len, delivery.is_some(), self.clock.now());
+                }
+                if let Err(
+                    error @ (RecvError::DeliveryTagReused(_) | RecvError::ConflictingResume(_)),
+                ) = &result
+                {
+                    self.on_delivery_tag_violation(error).await;
+                }
+                result
+            }
+            LinkFrame::Attach(_) | LinkFrame::Settle { .. } => {
+                Err(LinkStateError::IllegalState.into())
+            }
             LinkFrame::Flow(_) | LinkFrame::Disposition(_) => {
                 // Flow and Disposition are handled by LinkRelay which runs
                 // in the session loop
                 unreachable!()
             }
-            #[cfg(feature = "transaction")]
-            LinkFrame::Acquisition(_) => {
-                let error = definitions::Error::new(
-                    AmqpError::NotImplemented,
-                    "Transactional acquisition is not implemented".to_string(),
-                    None,
-                );
-                self.close_with_error(Some(error)).await?; // FIXME: cancel safe? if oneshot chanenl is cancel safe
-                Err(RecvError::TransactionalAcquisitionIsNotImeplemented)
+        }
+    }
+
+    /// Raises the byte limit in the link properties and sends it to the sender in a Flow
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe because it only `.await` on sending over a `tokio::mpsc::Sender`
+    async fn grant_byte_limit(&mut self, limit: u64) -> Result<(), IllegalLinkStateError> {
+        self.link.properties_mut(|properties| {
+            properties
+                .get_or_insert_with(Fields::new)
+                .insert(Symbol::from(BYTE_LIMIT_KEY), Value::Ulong(limit));
+        });
+        self.link.send_flow(&self.outgoing, None, None, false).await
+    }
+
+    /// Detaches the link with an error that describes the misuse of the delivery tag
+    async fn on_delivery_tag_violation(&mut self, error: &RecvError) {
+        self.delivery_tag_violations += 1;
+        #[cfg(feature = "tracing")]
+        tracing::error!(?error, "Protocol violation by the peer");
+        #[cfg(feature = "log")]
+        log::error!("Protocol violation by the peer: {:?}", error);
+
+        let error = definitions::Error::new(AmqpError::NotAllowed, error.to_string(), None);
+        if let Err(_err) = self.detach_with_error(Some(error)).await {
+            #[cfg(feature = "tracing")]
+            tracing::error!(detach_error = ?_err);
+            #[cfg(feature = "log")]
+            log::error!("detach_error = {:?}", _err);
+        }
+    }
+
+    /// Records the arrival of an unsettled transfer for [`Receiver::unsettled_snapshot()`]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn record_arrival(&mut self, transfer: &Transfer, payload_len: usize) {
+        if let Some(incomplete) = &self.incomplete_transfer {
+            if let Some(delivery_tag) = &incomplete.performative.delivery_tag {
+                self.unsettled_arrivals
+                    .on_continuation(delivery_tag, payload_len);
+            }
+            return;
+        }
+        if let (Some(delivery_tag), false) =
+            (&transfer.delivery_tag, transfer.settled == Some(true))
+        {
+            self.unsettled_arrivals
+                .prune(self.link.unsettled().read().as_ref());
+            self.unsettled_arrivals.on_first_transfer(
+                delivery_tag.clone(),
+                payload_len,
+                self.clock.now(),
+            );
+        }
+    }
+
+    /// Records the `message-id` of the delivery and returns whether it is a redelivery
+    #[cfg(not(target_arch = "wasm32"))]
+    fn is_duplicate<T>(&mut self, delivery: &Delivery<T>) -> bool {
+        let dedup = match &mut self.deduplicator {
+            Some(dedup) => dedup,
+            None => return false,
+        };
+        match delivery
+            .message()
+            .properties
+            .as_ref()
+            .and_then(|p| p.message_id.as_ref())
+        {
+            Some(id) => dedup.check_and_insert(id, self.clock.now()),
+            None => false,
+        }
+    }
+
+    /// Takes the next frame, which is the prefetched delivery of the highest priority if the
+    /// priority ordering is enabled
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe because all internal `.await` points are cancel safe
+    async fn next_frame(&mut self) -> Result<LinkFrame, RecvError> {
+        // The continuation frames of a multi-frame delivery are never reordered
+        if self.priority_ordering && self.incomplete_transfer.is_none() {
+            if self.prefetched.is_empty() {
+                let frame = self.next_incoming_frame().await?; // cancel safe
+                self.prefetched.push(frame);
+            }
+            while let Ok(frame) = self.incoming.try_recv() {
+                self.prefetched.push(frame);
+            }
+            if let Some(frame) = self.prefetched.pop_highest_priority() {
+                return Ok(frame);
+            }
+        }
+        match self.prefetched.pop_front() {
+            Some(frame) => Ok(frame),
+            None => self.next_incoming_frame().await,
+        }
+    }
+
+    /// Waits for the next frame on `incoming` while replenishing the credit that is held back by
+    /// the rate limit
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe because all internal `.await` points are cancel safe
+    async fn next_incoming_frame(&mut self) -> Result<LinkFrame, RecvError> {
+        #[cfg(not(target_arch = "wasm32"))]
+        while let Some(delay) = self.paced_credit_delay() {
+            tokio::select! {
+                frame = self.incoming.recv() => {
+                    return frame.ok_or_else(|| LinkStateError::IllegalSessionState.into())
+                }
+                _ = self.clock.sleep(delay) => self.replenish_paced_credit().await?,
             }
         }
+
+        self.incoming
+            .recv()
+            .await // cancel safe
+            .ok_or_else(|| LinkStateError::IllegalSessionState.into())
+    }
+
+    /// How long to wait before the credit can be replenished. `None` is returned if no credit is
+    /// due or there is no rate limit
+    #[cfg(not(target_arch = "wasm32"))]
+    fn paced_credit_delay(&mut self) -> Option<Duration> {
+        let limiter = self.rate_limiter.as_mut()?;
+        let max_credit = match self.credit_mode {
+            CreditMode::Auto(max_credit) => max_credit,
+            CreditMode::Manual => return None,
+        };
+        let threshold = (max_credit / 2).min(limiter.granted).max(1);
+        if self.processed.load(Ordering::Acquire) < threshold {
+            return None;
+        }
+        Some(limiter.delay(self.clock.now()))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn replenish_paced_credit(&mut self) -> Result<(), IllegalLinkStateError> {
+        let credit = match (&mut self.rate_limiter, &self.credit_mode) {
+            (Some(limiter), CreditMode::Auto(max_credit)) => {
+                let credit = limiter.credit(*max_credit, self.clock.now());
+                if credit == 0 {
+                    return Ok(());
+                }
+                limiter.granted = credit;
+                credit
+            }
+            _ => return Ok(()),
+        };
+        self.processed.store(0, Ordering::Release);
+        self.link
+            .send_flow(&self.outgoing, Some(credit), Some(false), false)
+            .await // cancel safe
     }
 
     fn on_transfer_state(
@@ -770,7 +1445,10 @@ where
             .map_err(Into::into)
     }
 
-    fn on_incomplete_transfer(
+    /// # Cancel safety
+    ///
+    /// This is cancel safe because the transfer is buffered before the spool file is written
+    async fn on_incomplete_transfer(
         &mut self,
         transfer: Transfer,
         payload: Payload,
@@ -780,30 +1458,28 @@ where
             Some(incomplete) => {
                 incomplete.or_assign(transfer)?;
                 incomplete.append(payload);
-
-                if let Some(delivery_tag) = incomplete.performative.delivery_tag.clone() {
-                    // Update unsettled map in the link
-                    self.link.on_incomplete_transfer(
-                        delivery_tag,
-                        incomplete.section_number.unwrap_or(0),
-                        incomplete.section_offset,
-                    );
-                }
             }
             None => {
                 let incomplete = IncompleteTransfer::new(transfer, payload);
-                if let Some(delivery_tag) = incomplete.performative.delivery_tag.clone() {
-                    // Update unsettled map in the link
-                    self.link.on_incomplete_transfer(
-                        delivery_tag,
-                        incomplete.section_number.unwrap_or(0),
-                        incomplete.section_offset,
-                    );
-                }
                 self.incomplete_transfer = Some(Box::new(incomplete));
             }
         }
 
+        if let Some(incomplete) = &mut self.incomplete_transfer {
+            if let Some(delivery_tag) = incomplete.performative.delivery_tag.clone() {
+                // Update unsettled map in the link
+                self.link.on_incomplete_transfer(
+                    delivery_tag,
+                    incomplete.section_number.unwrap_or(0),
+                    incomplete.section_offset,
+                );
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(config) = &self.spooling {
+                incomplete.spool_if_exceeds(config).await; // cancel safe
+            }
+        }
+
         Ok(())
     }
 
@@ -816,7 +1492,7 @@ where
         payload: Payload,
     ) -> Result<Option<Delivery<T>>, RecvError>
     where
-        for<'de> T: FromBody<'de> + Send,
+        T: DecodeDelivery + Send,
     {
         // need to check whether the incoming transfer matches
         match (
@@ -835,15 +1511,9 @@ where
                         payload,
                         section_number,
                         section_offset,
+                        &self.message_formats,
                     )?;
-
-                    // Auto accept the message and leave settled to be determined based on rcv_settle_mode
-                    if self.auto_accept {
-                        self.dispose(&delivery, None, Accepted {}.into()).await?;
-                        // cancel safe
-                    }
-
-                    Ok(Some(delivery))
+                    self.on_delivery(delivery).await // cancel safe
                 } else {
                     // The new Transfer belongs to the buffered incomplete transfer
                     self.on_complete_transfer(transfer, payload).await // cancel safe
@@ -856,6 +1526,131 @@ where
         }
     }
 
+    /// Verifies the hash in the footer and decompresses the payload (if enabled) before passing
+    /// it to the link
+    fn on_complete_payload<'a, T, P>(
+        &'a mut self,
+        transfer: Transfer,
+        payload: P,
+        section_number: u32,
+        section_offset: u64,
+    ) -> Result<Delivery<T>, ReceiverTransferError>
+    where
+        T: DecodeDelivery + Send,
+        for<'b> P: IntoReader + AsByteIterator<'b> + Send + 'a,
+    {
+        #[cfg(any(
+            feature = "checksum",
+            feature = "gzip",
+            feature = "deflate",
+            feature = "zstd"
+        ))]
+        if self.processes_payload() {
+            use std::io::Read;
+
+            let mut buf = Vec::new();
+            payload
+                .into_reader()
+                .read_to_end(&mut buf)
+                .map_err(|_| ReceiverTransferError::MessageDecodeError)?;
+            let payload = self.process_payload(Payload::from(buf));
+            let result = self.link.on_complete_transfer(
+                transfer,
+                payload,
+                section_number,
+                section_offset,
+                &self.message_formats,
+            );
+            if result.is_err() {
+                self.clear_payload_verdicts();
+            }
+            return result;
+        }
+
+        self.link.on_complete_transfer(
+            transfer,
+            payload,
+            section_number,
+            section_offset,
+            &self.message_formats,
+        )
+    }
+
+    /// Whether the incoming payload is verified or decompressed
+    #[cfg(any(
+        feature = "checksum",
+        feature = "gzip",
+        feature = "deflate",
+        feature = "zstd"
+    ))]
+    fn processes_payload(&self) -> bool {
+        #[cfg(feature = "checksum")]
+        if self.checksum.is_some() {
+            return true;
+        }
+        #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+        if self.decompress {
+            return true;
+        }
+        false
+    }
+
+    /// Verifies the hash in the footer (if enabled) and then decompresses the payload (if
+    /// enabled). A mismatch or a message that exceeds the `max_message_size` once decompressed is
+    /// recorded for `on_delivery()` to reject the delivery
+    #[cfg(any(
+        feature = "checksum",
+        feature = "gzip",
+        feature = "deflate",
+        feature = "zstd"
+    ))]
+    fn process_payload(&mut self, payload: Payload) -> Payload {
+        #[cfg(feature = "checksum")]
+        if let Some(checksum) = &self.checksum {
+            self.checksum_mismatch = checksum.verify_payload(&payload) == Verification::Mismatch;
+        }
+
+        #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+        if self.decompress {
+            let limit = self.link.max_message_size();
+            // A message that cannot be decompressed is delivered as is, and the application can
+            // still find the `content-encoding` in the message properties
+            match compression::decompress_payload(payload.clone(), limit) {
+                Ok(decompressed) => return decompressed,
+                Err(compression::DecompressError::SizeExceeded) => {
+                    self.decompressed_size_exceeded = true;
+                }
+                Err(_error) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(error = ?_error, "Failed to decompress message");
+                    #[cfg(feature = "log")]
+                    log::warn!("Failed to decompress message: {:?}", _error);
+                }
+            }
+        }
+
+        payload
+    }
+
+    /// Forgets the verdicts of `process_payload()` on a delivery that is not passed to
+    /// `on_delivery()`
+    #[cfg(any(
+        feature = "checksum",
+        feature = "gzip",
+        feature = "deflate",
+        feature = "zstd"
+    ))]
+    fn clear_payload_verdicts(&mut self) {
+        #[cfg(feature = "checksum")]
+        {
+            self.checksum_mismatch = false;
+        }
+        #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+        {
+            self.decompressed_size_exceeded = false;
+        }
+    }
+
     /// # Cancel safety
     ///
     /// This is cancel safe because all internal `.await` point(s) are cancel safe
@@ -865,27 +1660,233 @@ where
         payload: Payload,
     ) -> Result<Option<Delivery<T>>, RecvError>
     where
-        for<'de> T: FromBody<'de> + Send,
+        T: DecodeDelivery + Send,
     {
-        let delivery = match self.incomplete_transfer.take() {
-            Some(mut incomplete) => {
-                incomplete.or_assign(transfer)?;
-                incomplete.append(payload); // This also computes the section number and offset incrementally
-
-                self.link.on_complete_transfer(
-                    incomplete.performative,
-                    incomplete.buffer,
-                    incomplete.section_number.unwrap_or(0),
-                    incomplete.section_offset,
-                )?
+        if let Some(incomplete) = &mut self.incomplete_transfer {
+            if let Err(error) = incomplete.or_assign(transfer) {
+                self.incomplete_transfer = None;
+                return Err(error.into());
             }
-            None => {
-                let (section_number, section_offset) =
-                    count_number_of_sections_and_offset(&payload);
-                self.link
-                    .on_complete_transfer(transfer, payload, section_number, section_offset)?
+            incomplete.append(payload); // This also computes the section number and offset incrementally
+            incomplete.complete = true;
+            return self.on_complete_buffered_transfer().await; // cancel safe
+        }
+
+        let (section_number, section_offset) = count_number_of_sections_and_offset(&payload);
+        if self.skips_empty_body(&payload) {
+            let delivery =
+                self.on_complete_payload(transfer, payload, section_number, section_offset)?;
+            return self.skip_empty_body(delivery).await; // cancel safe
+        }
+        let delivery =
+            self.on_complete_payload(transfer, payload, section_number, section_offset)?;
+        self.on_delivery(delivery).await // cancel safe
+    }
+
+    /// Completes the buffered multi-frame delivery once its last transfer is appended
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe because the delivery stays in `incomplete_transfer` until the spool
+    /// file is done with, and `recv_inner()` resumes the completion if it is cancelled
+    async fn on_complete_buffered_transfer<T>(&mut self) -> Result<Option<Delivery<T>>, RecvError>
+    where
+        T: DecodeDelivery + Send,
+    {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Err(error) = self.finish_spool::<T>().await {
+            self.incomplete_transfer = None;
+            return Err(error);
+        }
+
+        let incomplete = match self.incomplete_transfer.take() {
+            Some(incomplete) => incomplete,
+            None => return Ok(None),
+        };
+
+        // A spooled delivery is only left on disk if the type keeps it there
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(spooled) = incomplete.spooled {
+            let delivery = self.link.on_complete_transfer(
+                incomplete.performative,
+                spooled,
+                incomplete.section_number.unwrap_or(0),
+                incomplete.section_offset,
+                &self.message_formats,
+            );
+            #[cfg(any(
+                feature = "checksum",
+                feature = "gzip",
+                feature = "deflate",
+                feature = "zstd"
+            ))]
+            if delivery.is_err() {
+                self.clear_payload_verdicts();
             }
+            return self.on_delivery(delivery?).await; // cancel safe
+        }
+
+        if self.skips_empty_body(&incomplete.buffer) {
+            let delivery = self.on_complete_payload(
+                incomplete.performative,
+                incomplete.buffer,
+                incomplete.section_number.unwrap_or(0),
+                incomplete.section_offset,
+            )?;
+            return self.skip_empty_body(delivery).await; // cancel safe
+        }
+
+        let delivery = self.on_complete_payload(
+            incomplete.performative,
+            incomplete.buffer,
+            incomplete.section_number.unwrap_or(0),
+            incomplete.section_offset,
+        )?;
+        self.on_delivery(delivery).await // cancel safe
+    }
+
+    /// Closes the spool file of the buffered delivery. The spooled payload is read back into the
+    /// buffer unless the type keeps it on disk, in which case the spooled payload is verified and
+    /// decompressed (if enabled) like a payload in memory
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe because the spool file is kept in `incomplete_transfer` until it is
+    /// read back or replaced
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn finish_spool<T: DecodeDelivery>(&mut self) -> Result<(), RecvError> {
+        #[cfg(any(
+            feature = "checksum",
+            feature = "gzip",
+            feature = "deflate",
+            feature = "zstd"
+        ))]
+        let processes_payload = self.processes_payload();
+        #[cfg(not(any(
+            feature = "checksum",
+            feature = "gzip",
+            feature = "deflate",
+            feature = "zstd"
+        )))]
+        let processes_payload = false;
+
+        let incomplete = match &mut self.incomplete_transfer {
+            Some(incomplete) => incomplete,
+            None => return Ok(()),
+        };
+        incomplete
+            .finish_spool()
+            .await // cancel safe
+            .map_err(RecvError::SpoolError)?;
+        let spooled = match &incomplete.spooled {
+            Some(spooled) => spooled,
+            None => return Ok(()),
         };
+        if T::KEEPS_SPOOLED && !processes_payload {
+            return Ok(());
+        }
+
+        let payload = spooled
+            .read_payload()
+            .await // cancel safe
+            .map_err(RecvError::SpoolError)?;
+        if !T::KEEPS_SPOOLED {
+            incomplete.spooled = None;
+            incomplete.buffer.push(payload);
+            return Ok(());
+        }
+
+        // A decompressed payload replaces the spool file, which stays in `incomplete_transfer`
+        // until the new file is closed
+        #[cfg(any(
+            feature = "checksum",
+            feature = "gzip",
+            feature = "deflate",
+            feature = "zstd"
+        ))]
+        {
+            let processed = self.process_payload(payload.clone());
+            if processed != payload {
+                let config = self.spooling.clone().unwrap_or_else(|| Spooling::new(0));
+                let spooled = super::spool::Spool::create(&config)
+                    .finish(&mut vec![processed])
+                    .await // cancel safe
+                    .map_err(RecvError::SpoolError)?;
+                if let Some(incomplete) = &mut self.incomplete_transfer {
+                    incomplete.spooled = Some(spooled);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects the delivery if it is denied by the authorizer of the listener, and otherwise
+    /// accepts it if auto accept is enabled. `None` is returned if the delivery is rejected
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe because all internal `.await` point(s) are cancel safe
+    async fn on_delivery<T>(
+        &mut self,
+        delivery: Delivery<T>,
+    ) -> Result<Option<Delivery<T>>, RecvError> {
+        #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+        if std::mem::take(&mut self.decompressed_size_exceeded) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                delivery_id = delivery.delivery_id(),
+                "Decompressed message exceeds the max-message-size"
+            );
+            #[cfg(feature = "log")]
+            log::warn!(
+                "Decompressed message exceeds the max-message-size delivery_id={}",
+                delivery.delivery_id()
+            );
+
+            let error = definitions::Error::new(
+                definitions::LinkError::MessageSizeExceeded,
+                Some(String::from(
+                    "The decompressed message exceeds the max-message-size",
+                )),
+                None,
+            );
+            let state = Rejected { error: Some(error) }.into();
+            self.dispose(&delivery, None, state).await?; // cancel safe
+            return Ok(None);
+        }
+
+        #[cfg(feature = "checksum")]
+        if std::mem::take(&mut self.checksum_mismatch) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(delivery_id = delivery.delivery_id(), "Checksum mismatch");
+            #[cfg(feature = "log")]
+            log::warn!("Checksum mismatch delivery_id={}", delivery.delivery_id());
+
+            let error = definitions::Error::new(
+                AmqpError::DecodeError,
+                Some(String::from(
+                    "The checksum in the footer does not match the message",
+                )),
+                None,
+            );
+            let state = Rejected { error: Some(error) }.into();
+            self.dispose(&delivery, None, state).await?; // cancel safe
+            return Ok(None);
+        }
+
+        #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+        if let Some(authorization) = &self.transfer_authorization {
+            let message = delivery.message();
+            let denied = authorization.authorize(
+                message.properties.as_ref(),
+                message.application_properties.as_ref(),
+            );
+            if let Some(error) = denied {
+                let state = Rejected { error: Some(error) }.into();
+                self.dispose(&delivery, None, state).await?; // cancel safe
+                return Ok(None);
+            }
+        }
 
         // Auto accept the message and leave settled to be determined based on rcv_settle_mode
         if self.auto_accept {
@@ -895,6 +1896,28 @@ where
         Ok(Some(delivery))
     }
 
+    /// Whether the complete payload is dropped because it carries no body section
+    fn skips_empty_body<'a, B>(&self, payload: &'a B) -> bool
+    where
+        B: AsByteIterator<'a>,
+    {
+        matches!(self.empty_body_policy, EmptyBodyPolicy::Skip) && !has_body_section(payload)
+    }
+
+    /// Accepts the delivery without a body section so that it is settled and the credit is
+    /// replenished
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe because all internal `.await` point(s) are cancel safe
+    async fn skip_empty_body<T>(
+        &mut self,
+        delivery: Delivery<Body<Value>>,
+    ) -> Result<Option<Delivery<T>>, RecvError> {
+        self.dispose(&delivery, None, Accepted {}.into()).await?; // cancel safe
+        Ok(None)
+    }
+
     /// # Cancel safety
     ///
     /// This is cancel safe because all internal `.await` point(s) are cancel safe
@@ -905,16 +1928,24 @@ where
         payload: Payload,
     ) -> Result<Option<Delivery<T>>, RecvError>
     where
-        for<'de> T: FromBody<'de> + Send,
+        T: DecodeDelivery + Send,
     {
         // Aborted messages SHOULD be discarded by the recipient (any payload
         // within the frame carrying the performative MUST be ignored). An aborted
         // message is implicitly settled
         if transfer.aborted {
-            let _ = self.incomplete_transfer.take();
+            if let Some(incomplete) = self.incomplete_transfer.take() {
+                // The tag of the aborted delivery may be used again
+                if let Some(delivery_tag) = &incomplete.performative.delivery_tag {
+                    let mut guard = self.link.unsettled().write();
+                    let _ = guard.as_mut().and_then(|map| map.swap_remove(delivery_tag));
+                }
+            }
             return Ok(None);
         }
 
+        self.check_delivery_tag(&transfer)?;
+
         if let Some(state) = transfer.state.clone() {
             // Setting the state
             // on the transfer can be thought of as being equivalent to sending a disposition immediately before
@@ -927,7 +1958,7 @@ where
             // Partial transfer of the delivery
             // There is only ONE incomplet transfer locally, so the partial transfer must belong to the
             // same delivery
-            self.on_incomplete_transfer(transfer, payload)?;
+            self.on_incomplete_transfer(transfer, payload).await?;
             // Partial delivery doesn't yield a complete message
             Ok(None)
         } else if transfer.resume {
@@ -938,6 +1969,38 @@ where
         }
     }
 
+    /// Checks the delivery tag of the first transfer of a delivery against the unsettled map
+    ///
+    /// The tag of a new delivery must not be in use by an unsettled delivery, and a resumed
+    /// delivery must be among the unsettled deliveries
+    fn check_delivery_tag(&self, transfer: &Transfer) -> Result<(), ReceiverTransferError> {
+        // The following transfers of a multi-frame delivery are checked by `IncompleteTransfer`
+        if self.incomplete_transfer.is_some() && !transfer.resume {
+            return Ok(());
+        }
+        let delivery_tag = match &transfer.delivery_tag {
+            Some(delivery_tag) => delivery_tag,
+            None => return Ok(()),
+        };
+
+        let is_unsettled = self
+            .link
+            .unsettled()
+            .read()
+            .as_ref()
+            .map(|map| map.contains_key(delivery_tag))
+            .unwrap_or(false);
+        match (transfer.resume, is_unsettled) {
+            (false, true) => Err(ReceiverTransferError::DeliveryTagReused(
+                delivery_tag.clone(),
+            )),
+            (true, false) => Err(ReceiverTransferError::ConflictingResume(
+                delivery_tag.clone(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
     /// Set the link credit. This will stop draining if the link is in a draining cycle
     ///
     /// # Cancel safety
@@ -949,12 +2012,38 @@ where
         if let CreditMode::Auto(_) = self.credit_mode {
             self.credit_mode = CreditMode::Auto(credit)
         }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(limiter) = &mut self.rate_limiter {
+            limiter.granted = credit;
+        }
 
         self.link
             .send_flow(&self.outgoing, Some(credit), Some(false), false)
             .await // cancel safe
     }
 
+    /// Switches the credit mode, draining the link when leaving `CreditMode::Auto`
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe as internally it only `.await` on sending over `tokio::mpsc::Sender`
+    pub async fn set_credit_mode(
+        &mut self,
+        credit_mode: CreditMode,
+    ) -> Result<(), IllegalLinkStateError> {
+        match (&self.credit_mode, credit_mode) {
+            (CreditMode::Manual, CreditMode::Manual) => Ok(()),
+            (CreditMode::Auto(_), CreditMode::Manual) => {
+                self.credit_mode = CreditMode::Manual;
+                self.drain().await // cancel safe
+            }
+            (_, CreditMode::Auto(credit)) => {
+                self.credit_mode = CreditMode::Auto(credit);
+                self.set_credit(credit).await // cancel safe
+            }
+        }
+    }
+
     /// This is cancel safe because all internal `.await` points are cancel safe
     #[inline]
     pub(crate) async fn dispose(
@@ -964,6 +2053,19 @@ where
         state: DeliveryState,
     ) -> Result<(), DispositionError> {
         let delivery_info = delivery_info.into();
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(batcher) = &self.disposition_batcher {
+            if settled.is_none() && matches!(state, DeliveryState::Accepted(_)) {
+                let is_due = batcher.lock().push(delivery_info, self.clock.now());
+                if is_due {
+                    self.flush_dispositions().await?; // cancel safe
+                }
+                let prev = self.processed.fetch_add(1, Ordering::Release);
+                return self.update_credit_if_auto(prev + 1).await; // cancel safe
+            }
+            // The pending accepts are sent first to keep the dispositions in order
+            self.flush_dispositions().await?; // cancel safe
+        }
         self.link
             .dispose(&self.outgoing, delivery_info, settled, state, false)
             .await?; // cancel safe
@@ -982,6 +2084,8 @@ where
         state: DeliveryState,
     ) -> Result<(), DispositionError> {
         let total = delivery_infos.len() as u32;
+        #[cfg(not(target_arch = "wasm32"))]
+        self.flush_dispositions().await?; // cancel safe
         self.link
             .dispose_all(&self.outgoing, delivery_infos, settled, state, false)
             .await?; // cancel safe
@@ -991,9 +2095,46 @@ where
         Ok(())
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    fn disposition_deadline(&self) -> Option<Instant> {
+        self.disposition_batcher
+            .as_ref()
+            .and_then(|batcher| batcher.lock().deadline())
+    }
+
+    /// Sends the pending accepts as ranged dispositions
+    ///
+    /// This is cancel safe because the accepts are only removed from the batcher once they are
+    /// sent. An accept may be sent twice if this is cancelled, which the remote peer ignores
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) async fn flush_dispositions(&self) -> Result<(), DispositionError> {
+        let pending = match &self.disposition_batcher {
+            Some(batcher) => batcher.lock().pending(),
+            None => return Ok(()),
+        };
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let count = pending.len();
+        let state = DeliveryState::Accepted(Accepted {});
+        self.link
+            .dispose_all(&self.outgoing, pending, None, state, false)
+            .await?; // cancel safe
+        if let Some(batcher) = &self.disposition_batcher {
+            batcher.lock().remove_sent(count, self.clock.now());
+        }
+        Ok(())
+    }
+
     /// This is cancel safe because it only `.await` on a cancel safe future
     #[inline]
     async fn update_credit_if_auto(&self, processed: u32) -> Result<(), DispositionError> {
+        // The credit is replenished by `recv` at the pace of the rate limit
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.rate_limiter.is_some() {
+            return Ok(());
+        }
+
         if let CreditMode::Auto(max_credit) = self.credit_mode {
             if processed >= max_credit / 2 {
                 // Reset link credit
@@ -1024,6 +2165,17 @@ where
             .send_flow(&self.outgoing, None, Some(true), false)
             .await
     }
+
+    /// Sends a Flow with `echo` set to true and waits for the next Flow from the remote peer
+    #[inline]
+    pub async fn request_flow_echo(&mut self) -> Result<RemoteFlowState, FlowError> {
+        // The waiter is registered first so that the reply cannot be missed
+        let remote_flow = self.link.flow_state().wait_for_remote_flow();
+        self.link
+            .send_flow(&self.outgoing, None, None, true)
+            .await?;
+        remote_flow.await.map_err(|_| FlowError::IllegalState)
+    }
 }
 
 impl ReceiverInner<ReceiverLink<Target>> {
@@ -1191,6 +2343,20 @@ impl DetachedReceiver {
         &mut self.inner.link.target
     }
 
+    /// Captures the state of the detached receiver so that it can be resumed from another
+    /// process with [`Builder::resume_from()`](crate::link::builder::Builder::resume_from)
+    pub fn to_suspended_link(&self) -> SuspendedLink {
+        let link = &self.inner.link;
+        SuspendedLink {
+            name: link.name.clone(),
+            role: Role::Receiver,
+            source: link.source.clone(),
+            target: link.target.clone(),
+            delivery_count: link.flow_state.delivery_count(),
+            unsettled: link.unsettled.read().clone().unwrap_or_default(),
+        }
+    }
+
     /// Resume the receiver link
     ///
     /// Please note that the link may need to be detached and then resume multiple
@@ -1,15 +1,16 @@
-use fe2o3_amqp_types::{
-    definitions::{Fields, Handle},
-    messaging::{message::DecodeIntoMessage, FromBody},
-};
+use fe2o3_amqp_types::definitions::{Fields, Handle};
 use serde_amqp::format_code::EncodingCodes;
 
 use crate::{
     endpoint::LinkExt,
-    util::{is_consecutive, AsByteIterator, IntoReader},
+    util::{is_consecutive, AsByteIterator},
 };
 
-use super::{delivery::DeliveryInfo, *};
+use super::{
+    delivery::DeliveryInfo,
+    message_format::{DecodeDelivery, DeliveryPayload, MessageFormatRegistry},
+    *,
+};
 
 pub(crate) const DESCRIBED_TYPE: u8 = EncodingCodes::DescribedType as u8;
 pub(crate) const SMALL_ULONG_TYPE: u8 = EncodingCodes::SmallUlong as u8;
@@ -129,10 +130,11 @@ where
         payload: P,
         section_number: u32,
         section_offset: u64,
+        message_formats: &MessageFormatRegistry,
     ) -> Result<Delivery<T>, Self::TransferError>
     where
-        for<'de> T: FromBody<'de> + Send,
-        for<'b> P: IntoReader + AsByteIterator<'b> + Send + 'a,
+        T: DecodeDelivery + Send,
+        P: DeliveryPayload + Send + 'a,
     {
         match self.local_state {
             LinkState::Attached | LinkState::IncompleteAttachExchanged => {}
@@ -157,8 +159,7 @@ where
         let (message, mode) = if settled_by_sender {
             // If the message is pre-settled, there is no need to
             // add to the unsettled map and no need to reply to the Sender
-            let message = T::decode_into_message(payload.into_reader())
-                .map_err(|_| Self::TransferError::MessageDecodeError)?;
+            let message = payload.decode::<T>(message_formats, message_format)?;
             (message, None)
         } else {
             // If the message is being sent settled by the sender, the value of this
@@ -177,8 +178,7 @@ where
                 None => None,
             };
 
-            let message = T::decode_into_message(payload.into_reader())
-                .map_err(|_| Self::TransferError::MessageDecodeError)?;
+            let message = payload.decode::<T>(message_formats, message_format)?;
 
             let state = DeliveryState::Received(Received {
                 section_number, // What is section number?
@@ -217,6 +217,8 @@ where
             message_format,
             rcv_settle_mode: mode,
             message,
+            #[cfg(not(target_arch = "wasm32"))]
+            received_at: tokio::time::Instant::now(),
         };
 
         Ok(delivery)
@@ -268,6 +270,15 @@ where
 
         // Only dispose if message is found in unsettled map
         if unsettled_state.is_some() {
+            #[cfg(feature = "debug-deliveries")]
+            self.trace_delivery(
+                &delivery_info.delivery_tag,
+                delivery_trace::DeliveryEventKind::DispositionSent {
+                    settled,
+                    state: Some(state.clone()),
+                },
+            );
+
             let disposition = Disposition {
                 role: Role::Receiver,
                 first: delivery_info.delivery_id,
@@ -363,6 +374,40 @@ where
     (section_numbers, offset as u64)
 }
 
+/// Whether the payload carries a `data`, `amqp-sequence` or `amqp-value` section
+///
+/// The descriptor of a body section may also appear inside the encoded value of another section,
+/// so only a negative answer is exact
+pub(crate) fn has_body_section<'a, B>(bytes: &'a B) -> bool
+where
+    B: AsByteIterator<'a>,
+{
+    let is_body_code = |code: u8| matches!(code, DATA_CODE | AMQP_SEQ_CODE | AMQP_VAL_CODE);
+
+    // A descriptor encoded as an ulong takes 10 bytes
+    let mut window = [0u8; 10];
+    for (i, &byte) in bytes.as_byte_iterator().enumerate() {
+        window.rotate_left(1);
+        window[9] = byte;
+        if i >= 2
+            && window[7] == DESCRIBED_TYPE
+            && window[8] == SMALL_ULONG_TYPE
+            && is_body_code(window[9])
+        {
+            return true;
+        }
+        if i >= 9
+            && window[0] == DESCRIBED_TYPE
+            && window[1] == ULONG_TYPE
+            && window[2..9].iter().all(|&b| b == 0)
+            && is_body_code(window[9])
+        {
+            return true;
+        }
+    }
+    false
+}
+
 pub(crate) fn is_section_header(b0: u8, b1: u8, b2: u8) -> bool {
     matches!(
         (b0, b1, b2),
@@ -475,6 +520,17 @@ impl<T> ReceiverLink<T> {
             }
         }
 
+        #[cfg(feature = "debug-deliveries")]
+        for info in consecutive_infos {
+            self.trace_delivery(
+                &info.delivery_tag,
+                delivery_trace::DeliveryEventKind::DispositionSent {
+                    settled,
+                    state: Some(state.clone()),
+                },
+            );
+        }
+
         let disposition = Disposition {
             role: Role::Receiver,
             first: consecutive_infos[0].delivery_id,
@@ -490,6 +546,30 @@ impl<T> ReceiverLink<T> {
             .map_err(|_| DispositionError::IllegalSessionState)
     }
 
+    /// Sends a Flow that carries the current flow state with `properties` added to the
+    /// properties of the link. The `properties` only apply to this Flow
+    pub(crate) async fn send_flow_with_properties(
+        &self,
+        writer: &mpsc::Sender<LinkFrame>,
+        properties: Fields,
+    ) -> Result<(), FlowError> {
+        let handle = self
+            .output_handle
+            .clone()
+            .ok_or(FlowError::IllegalState)?
+            .into();
+
+        let mut flow = self.get_link_flow(handle, None, None, false);
+        let fields = flow.properties.get_or_insert_with(Fields::new);
+        for (key, value) in properties.into_inner() {
+            fields.insert(key, value);
+        }
+        writer
+            .send(LinkFrame::Flow(flow))
+            .await
+            .map_err(|_| FlowError::IllegalSessionState)
+    }
+
     fn get_link_flow(
         &self,
         handle: Handle,
@@ -594,19 +674,19 @@ where
 
         match (&self.local_state, remote_attach.incomplete_unsettled) {
             (LinkState::AttachSent, false) => {
-                self.local_state = LinkState::Attached;
+                self.set_local_state(LinkState::Attached);
             }
             (LinkState::IncompleteAttachSent, false) => {
-                self.local_state = LinkState::IncompleteAttachExchanged;
+                self.set_local_state(LinkState::IncompleteAttachExchanged);
             }
             (LinkState::Unattached, false) | (LinkState::Detached, false) => {
-                self.local_state = LinkState::AttachReceived; // re-attaching
+                self.set_local_state(LinkState::AttachReceived); // re-attaching
             }
             (LinkState::AttachSent, true) | (LinkState::IncompleteAttachSent, true) => {
-                self.local_state = LinkState::IncompleteAttachExchanged;
+                self.set_local_state(LinkState::IncompleteAttachExchanged);
             }
             (LinkState::Unattached, true) | (LinkState::Detached, true) => {
-                self.local_state = LinkState::IncompleteAttachReceived; // re-attaching
+                self.set_local_state(LinkState::IncompleteAttachReceived); // re-attaching
             }
             _ => return Err(ReceiverAttachError::IllegalState),
         };
@@ -768,6 +848,11 @@ where
         }
     }
 
+    #[cfg(feature = "debug-deliveries")]
+    fn delivery_traces(&self) -> &super::delivery_trace::ArcDeliveryTraces {
+        &self.delivery_traces
+    }
+
     fn properties<F, O>(&self, op: F) -> O
     where
         F: FnOnce(&Option<Fields>) -> O,
@@ -863,6 +948,16 @@ where
                     Err(_) => attach_error,
                 }
             }
+            #[cfg(feature = "acceptor")]
+            ReceiverAttachError::SettleModesNotSupported | ReceiverAttachError::Unauthorized(_) => {
+                match (&attach_error).try_into() {
+                    Ok(error) => match self.send_detach(writer, true, Some(error)).await {
+                        Ok(_) => recv_detach(self, reader, attach_error).await,
+                        Err(_) => ReceiverAttachError::IllegalSessionState,
+                    },
+                    Err(_) => attach_error,
+                }
+            }
             _ => attach_error,
         }
     }
@@ -902,7 +997,7 @@ mod tests {
     };
     use serde_amqp::to_vec;
 
-    use crate::link::receiver_link::count_number_of_sections_and_offset;
+    use crate::link::receiver_link::{count_number_of_sections_and_offset, has_body_section};
 
     use super::is_consecutive;
 
@@ -959,4 +1054,22 @@ mod tests {
         let final_slice = &vals[prev_ind..];
         assert_eq!(final_slice, expected.last().unwrap())
     }
+
+    #[test]
+    fn test_has_body_section() {
+        let mut message = Message::builder()
+            .header(Header::default())
+            .body(Body::<Value>::Empty)
+            .build();
+        let buf = to_vec(&Serializable(&message)).unwrap();
+        assert!(!has_body_section(&buf));
+
+        message.body = Body::Value(AmqpValue(Value::Null));
+        let buf = to_vec(&Serializable(&message)).unwrap();
+        assert!(has_body_section(&buf));
+
+        // Descriptor encoded as an ulong
+        let buf = [0x00, 0x80, 0, 0, 0, 0, 0, 0, 0, 0x77, 0x40];
+        assert!(has_body_section(&buf.to_vec()));
+    }
 }
@@ -1,15 +1,25 @@
 //! Implementation of AMQP1.0 sender
 
+use std::{sync::Arc, time::Duration};
+
 use bytes::{Bytes, BytesMut};
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, watch, Notify};
 
 cfg_not_wasm32! {
-    use std::time::Duration;
-    use tokio::time::{error::Elapsed, timeout};
+    use tokio::time::{error::Elapsed, timeout, Instant};
+
+    use tokio::sync::broadcast;
+
+    use super::deadline::{stamp_ttl, DeadlineRelease};
+    use super::keep_alive::{LinkEvent, LinkEvents};
+    use super::latency::{ArcSettlementLatency, LatencyStats, SlowSettlement};
+    use super::unsettled::UnsettledDelivery;
+
+    use crate::clock::SharedClock;
 }
 
 use fe2o3_amqp_types::{
-    definitions::{self, DeliveryTag, Fields, MessageFormat, SenderSettleMode},
+    definitions::{self, DeliveryTag, Fields, MessageFormat, Role, SenderSettleMode},
     messaging::{
         message::__private::Serializable, Address, DeliveryState, Outcome, SerializableBody,
         Source, Target,
@@ -20,30 +30,114 @@ use fe2o3_amqp_types::{
 
 use crate::{
     control::SessionControl,
-    endpoint::{self, LinkAttach, LinkDetach, LinkExt, Settlement},
+    endpoint::{self, LinkAttach, LinkDetach, LinkExt, OutputHandle, Settlement},
     session::SessionHandle,
+    util::IdleTimeout,
     Payload,
 };
 
 use super::{
     builder::{self, WithSource, WithoutName, WithoutTarget},
-    delivery::{DeliveryFut, Sendable, UnsettledMessage},
+    deadline::DeadlineTimer,
+    delivery::{DeliveryFut, EncodedSendable, Sendable, UnsettledMessage},
     error::DetachError,
     resumption::ResumingDelivery,
     role,
     shared_inner::{
         recv_remote_detach, LinkEndpointInner, LinkEndpointInnerDetach, LinkEndpointInnerReattach,
     },
-    ArcSenderUnsettledMap, DetachThenResumeSenderError, LinkFrame, LinkRelay, LinkStateError,
-    SendError, SenderAttachError, SenderAttachExchange, SenderFlowState, SenderLink,
-    SenderResumeError, SenderResumeErrorKind,
+    state::LinkFlowState,
+    ArcSenderUnsettledMap, DetachThenResumeSenderError, FlowError, LinkFrame, LinkRelay, LinkState,
+    LinkStateError, RemoteFlowState, SendError, SenderAttachError, SenderAttachExchange,
+    SenderFlowState, SenderLink, SenderResumeError, SenderResumeErrorKind, SettleError,
+    SuspendedLink,
 };
 
+cfg_checksum! {
+    use super::checksum::Checksum;
+}
+
+cfg_compression! {
+    use super::compression::Compression;
+}
+
+cfg_debug_deliveries! {
+    use super::delivery_trace::DeliveryTrace;
+}
+
 #[cfg(docsrs)]
 use fe2o3_amqp_types::messaging::{
     AmqpSequence, AmqpValue, Batch, Body, Data, IntoBody, Message, MESSAGE_FORMAT,
 };
 
+/// How the `available` field advertised by the sender in its Flow frames is maintained
+///
+/// The `available` field tells the receiver how many messages the sender could send if it had
+/// enough link credit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AvailableMode {
+    /// The value is only changed by [`Sender::set_available`]
+    #[default]
+    Manual,
+
+    /// The sender advertises the messages that are waiting for link credit
+    Auto,
+}
+
+/// A handle that pauses and resumes a [`Sender`] from another task
+///
+/// The handle is obtained with [`Sender::pause_handle`] and belongs to the current attachment of
+/// the sender. Pausing or resuming fails with [`FlowError::IllegalState`] once the link is
+/// detached.
+#[derive(Debug, Clone)]
+pub struct SenderPauseHandle {
+    flow_state: Arc<LinkFlowState<role::SenderMarker>>,
+    notifier: Arc<Notify>,
+    output_handle: Option<OutputHandle>,
+    outgoing: mpsc::Sender<LinkFrame>,
+}
+
+impl SenderPauseHandle {
+    /// Whether the sender is paused
+    pub fn is_paused(&self) -> bool {
+        self.flow_state.is_paused()
+    }
+
+    /// Pauses the sender and advertises an `available` of zero to the remote receiver with a
+    /// Flow. Nothing is sent if the sender is already paused
+    ///
+    /// The sends that are not yet transferred, including the ones that are already waiting for
+    /// link credit, wait until the sender is resumed. The link credit granted in the meantime is
+    /// kept.
+    pub async fn pause(&self) -> Result<(), FlowError> {
+        match self.flow_state.pause() {
+            true => self.send_flow().await,
+            false => Ok(()),
+        }
+    }
+
+    /// Resumes the sender, advertises the `available` from before the pause with a Flow and wakes
+    /// up the waiting send. Nothing is sent if the sender is not paused
+    pub async fn resume(&self) -> Result<(), FlowError> {
+        if !self.flow_state.resume() {
+            return Ok(());
+        }
+        let result = self.send_flow().await;
+        // A permit is stored if the send is not waiting yet, so the wake up cannot be missed
+        self.notifier.notify_one();
+        result
+    }
+
+    async fn send_flow(&self) -> Result<(), FlowError> {
+        let handle = self.output_handle.clone().ok_or(FlowError::IllegalState)?;
+        let flow = self.flow_state.lock.read().as_link_flow(handle, false);
+        self.outgoing
+            .send(LinkFrame::Flow(flow))
+            .await
+            .map_err(|_| FlowError::IllegalSessionState)
+    }
+}
+
 /// An AMQP1.0 sender
 ///
 /// # Attach a new sender with default configurations
@@ -78,6 +172,7 @@ use fe2o3_amqp_types::messaging::{
 /// |`target`| `None` |
 /// |`initial_delivery_count`| `0` |
 /// |`max_message_size`| `None` |
+/// |`max_transfer_frame_size`| `None` |
 /// |`offered_capabilities`| `None` |
 /// |`desired_capabilities`| `None` |
 /// |`Properties`| `None` |
@@ -123,6 +218,66 @@ impl Sender {
         self.inner.link.max_message_size()
     }
 
+    /// Get the available mode of the sender
+    pub fn available_mode(&self) -> AvailableMode {
+        self.inner.available_mode
+    }
+
+    /// Set the available mode
+    ///
+    /// This will not send a flow to the remote peer
+    pub fn set_available_mode(&mut self, available_mode: AvailableMode) {
+        self.inner.available_mode = available_mode;
+    }
+
+    /// Get how long `detach()` and `close()` wait for the remote Detach
+    pub fn detach_timeout(&self) -> Option<Duration> {
+        self.inner.detach_timeout
+    }
+
+    /// Set how long `detach()` and `close()` wait for the remote Detach before detaching the link
+    /// locally
+    pub fn set_detach_timeout(&mut self, timeout: impl Into<Option<Duration>>) {
+        self.inner.detach_timeout = timeout.into();
+    }
+
+    /// Set the number of messages that the sender could send if it had enough link credit and
+    /// advertise it to the remote receiver with a Flow
+    ///
+    /// The value is decremented locally for every message that is sent afterwards, which is
+    /// how the receiver keeps track of it as well.
+    pub async fn set_available(&mut self, available: u32) -> Result<(), FlowError> {
+        self.inner.set_available(available).await
+    }
+
+    /// Stops consuming link credit without detaching and advertises an `available` of zero to the
+    /// remote receiver. See [`SenderPauseHandle::pause`]
+    pub async fn pause(&mut self) -> Result<(), FlowError> {
+        self.pause_handle().pause().await
+    }
+
+    /// Resumes a paused sender. See [`SenderPauseHandle::resume`]
+    pub async fn resume(&mut self) -> Result<(), FlowError> {
+        self.pause_handle().resume().await
+    }
+
+    /// Whether the sender is paused
+    pub fn is_paused(&self) -> bool {
+        self.inner.link.flow_state.as_ref().is_paused()
+    }
+
+    /// Get a handle that pauses and resumes the sender, which can be used while a `send()` is
+    /// waiting for link credit on another task
+    pub fn pause_handle(&self) -> SenderPauseHandle {
+        let flow_state = &self.inner.link.flow_state;
+        SenderPauseHandle {
+            flow_state: flow_state.state().clone(),
+            notifier: flow_state.notifier.clone(),
+            output_handle: self.inner.link.output_handle.clone(),
+            outgoing: self.inner.outgoing.clone(),
+        }
+    }
+
     /// Get a reference to the link's source field
     pub fn source(&self) -> &Option<Source> {
         &self.inner.link.source
@@ -173,6 +328,7 @@ impl Sender {
     /// |`target`| `None` |
     /// |`initial_delivery_count`| `0` |
     /// |`max_message_size`| `None` |
+    /// |`max_transfer_frame_size`| `None` |
     /// |`offered_capabilities`| `None` |
     /// |`desired_capabilities`| `None` |
     /// |`Properties`| `None` |
@@ -384,12 +540,11 @@ impl Sender {
         &mut self,
         sendable: impl Into<Sendable<T>>,
     ) -> Result<Outcome, SendError> {
-        let fut = self
-            .inner
-            .send_with_state::<T, SendError>(sendable.into(), None, false)
-            .await
-            .map(DeliveryFut::from)?;
-        fut.await
+        let sendable = sendable.into();
+        let deadline = self.inner.deadline_timer(&sendable);
+        let fut = self.inner.send_with_state(sendable, None, false);
+        let settlement = sent_before_deadline(fut, deadline.as_ref()).await?;
+        DeliveryFut::from(settlement).with_deadline(deadline).await
     }
 
     /// Like [`send()`](#method.send) but takes a reference to the message
@@ -400,12 +555,10 @@ impl Sender {
         &mut self,
         sendable: &Sendable<T>,
     ) -> Result<Outcome, SendError> {
-        let fut = self
-            .inner
-            .send_ref_with_state::<T, SendError>(sendable, None, false)
-            .await
-            .map(DeliveryFut::from)?;
-        fut.await
+        let deadline = self.inner.deadline_timer(sendable);
+        let fut = self.inner.send_ref_with_state(sendable, None, false);
+        let settlement = sent_before_deadline(fut, deadline.as_ref()).await?;
+        DeliveryFut::from(settlement).with_deadline(deadline).await
     }
 
     cfg_not_wasm32! {
@@ -437,10 +590,11 @@ impl Sender {
         &mut self,
         sendable: impl Into<Sendable<T>>,
     ) -> Result<DeliveryFut<Result<Outcome, SendError>>, SendError> {
-        self.inner
-            .send_with_state(sendable.into(), None, true)
-            .await
-            .map(DeliveryFut::from)
+        let sendable = sendable.into();
+        let deadline = self.inner.deadline_timer(&sendable);
+        let fut = self.inner.send_with_state(sendable, None, true);
+        let settlement = sent_before_deadline(fut, deadline.as_ref()).await?;
+        Ok(DeliveryFut::from(settlement).with_deadline(deadline))
     }
 
     /// Like [`send_batchable()`](#method.send_batchable) but this only takes a reference.
@@ -451,12 +605,245 @@ impl Sender {
         &mut self,
         sendable: &Sendable<T>,
     ) -> Result<DeliveryFut<Result<Outcome, SendError>>, SendError> {
+        self.send_ref_unacknowledged(sendable, true).await
+    }
+
+    /// Transfers the message and returns the future of its outcome
+    pub(crate) async fn send_ref_unacknowledged<T: SerializableBody>(
+        &mut self,
+        sendable: &Sendable<T>,
+        batchable: bool,
+    ) -> Result<DeliveryFut<Result<Outcome, SendError>>, SendError> {
+        let deadline = self.inner.deadline_timer(sendable);
+        let fut = self.inner.send_ref_with_state(sendable, None, batchable);
+        let settlement = sent_before_deadline(fut, deadline.as_ref()).await?;
+        Ok(DeliveryFut::from(settlement).with_deadline(deadline))
+    }
+
+    /// Send multiple messages without waiting for the acknowledgements. The returned futures are
+    /// in the same order as the messages.
+    ///
+    /// This is like calling [`send_batchable()`](#method.send_batchable) for every message, but
+    /// all the messages are serialized upfront and the link credit of as many messages as
+    /// possible is taken at once, which avoids most of the per-message overhead when sending a
+    /// large number of small messages. The `batchable` field of every `Transfer` is set to true.
+    ///
+    /// If an error occurs, the messages before the one that failed have already been sent. The
+    /// deadline of a message only fails its delivery future, the batch keeps waiting for link
+    /// credit regardless of the deadlines.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let sendables = (0..1000).map(|i| Sendable::from(format!("message {}", i)));
+    /// let futs = sender.send_batch(sendables).await.unwrap();
+    /// for fut in futs {
+    ///     fut.await.unwrap();
+    /// }
+    /// ```
+    pub async fn send_batch<T: SerializableBody>(
+        &mut self,
+        sendables: impl IntoIterator<Item = Sendable<T>>,
+    ) -> Result<Vec<DeliveryFut<Result<Outcome, SendError>>>, SendError> {
+        self.inner.send_batch(sendables.into_iter().collect()).await
+    }
+
+    /// Send an already encoded message and wait for acknowledgement (disposition)
+    ///
+    /// The payload is not serialized, but it is still split into transfers and is subject to
+    /// the link credit like any other message. See
+    /// [`Builder::bare_message_bytes()`](crate::link::delivery::Builder::bare_message_bytes)
+    ///
+    /// ```rust,ignore
+    /// let sendable = Sendable::builder()
+    ///     .bare_message_bytes(recorded_payload)
+    ///     .build();
+    /// let outcome = sender.send_encoded(sendable).await.unwrap();
+    /// ```
+    pub async fn send_encoded(
+        &mut self,
+        sendable: impl Into<EncodedSendable>,
+    ) -> Result<Outcome, SendError> {
+        let EncodedSendable {
+            payload,
+            message_format,
+            settled,
+        } = sendable.into();
+        let fut = self
+            .inner
+            .send_payload::<SendError>(payload, message_format, settled, None, false)
+            .await
+            .map(DeliveryFut::from)?;
+        fut.await
+    }
+
+    /// Like [`send_encoded()`](#method.send_encoded) but this does not wait for the
+    /// acknowledgement
+    pub async fn send_encoded_batchable(
+        &mut self,
+        sendable: impl Into<EncodedSendable>,
+    ) -> Result<DeliveryFut<Result<Outcome, SendError>>, SendError> {
+        let EncodedSendable {
+            payload,
+            message_format,
+            settled,
+        } = sendable.into();
         self.inner
-            .send_ref_with_state(sendable, None, true)
+            .send_payload(payload, message_format, settled, None, true)
             .await
             .map(DeliveryFut::from)
     }
 
+    /// Settles an unsettled delivery from the sender side with the last known state of the
+    /// delivery
+    ///
+    /// A Disposition with `role` set to sender and `settled` set to true is sent to the remote
+    /// receiver, after which the sender is no longer responsible for the delivery, for example
+    /// it will not be re-transmitted when the link is resumed. This is useful once the
+    /// application has confirmed the message by other means or the message has expired. The
+    /// [`DeliveryFut`] of the delivery resolves with the state the delivery is settled with.
+    ///
+    /// Returns [`SettleError::DeliveryNotFound`] if the delivery is pre-settled or already
+    /// settled by the remote receiver.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let fut = sender.send_batchable("hello AMQP").await.unwrap();
+    /// let delivery_tag = fut.delivery_tag().clone();
+    ///
+    /// // The message is confirmed by the application
+    /// sender.settle(delivery_tag).await.unwrap();
+    /// ```
+    pub async fn settle(&mut self, delivery_tag: DeliveryTag) -> Result<(), SettleError> {
+        self.inner.settle(delivery_tag, None).await
+    }
+
+    /// Like [`settle()`](#method.settle) but settles the delivery with the given state, which is
+    /// carried by the Disposition
+    pub async fn settle_with_state(
+        &mut self,
+        delivery_tag: DeliveryTag,
+        state: DeliveryState,
+    ) -> Result<(), SettleError> {
+        self.inner.settle(delivery_tag, Some(state)).await
+    }
+
+    /// Requests the remote receiver to reply with its flow state by sending a Flow with `echo`
+    /// set to true. The returned state carries the `delivery-count` and `link-credit` of the
+    /// remote receiver.
+    ///
+    /// This resolves with the next Flow received from the remote peer, which may not wait for
+    /// the echo if the remote peer happens to send a Flow at the same time. This waits
+    /// indefinitely if the remote peer never replies, so it should be wrapped inside a timeout
+    /// if the remote peer is not trusted to honor the echo request.
+    pub async fn request_flow_echo(&mut self) -> Result<RemoteFlowState, FlowError> {
+        self.inner.request_flow_echo().await
+    }
+
+    /// Returns the deliveries that are waiting to be settled by the remote receiver, in the order
+    /// they were sent
+    ///
+    /// Each delivery comes with its age, current state and payload size, eg. to detect the
+    /// deliveries that are stuck or to export the unsettled map to a dashboard. The snapshot is a
+    /// copy and is not updated afterwards
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn unsettled_snapshot(&self) -> Vec<UnsettledDelivery> {
+        let guard = self.inner.link.unsettled.read();
+        UnsettledDelivery::from_sender_map(guard.as_ref(), Instant::now())
+    }
+
+    /// Returns the percentiles of the time from when a delivery is written until the remote
+    /// receiver settles it, over all the deliveries settled since the link was attached or since
+    /// [`reset_latency_stats`](#method.reset_latency_stats). See [`latency`](super::latency) for
+    /// details
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn latency_stats(&self) -> LatencyStats {
+        self.inner.latency.stats()
+    }
+
+    /// Clears the settlement latencies, eg. after they are exported so that the next snapshot
+    /// only covers the latest interval
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reset_latency_stats(&self) {
+        self.inner.latency.reset()
+    }
+
+    /// Subscribes to the settlements that take longer than the threshold of the
+    /// [`SlowSettlementAlert`](super::SlowSettlementAlert) given to the builder. Nothing is
+    /// received if the sender is attached without an alert
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn subscribe_slow_settlements(&self) -> broadcast::Receiver<SlowSettlement> {
+        self.inner.latency.subscribe()
+    }
+
+    /// Subscribes to the events of the link, such as the keep-alives that the remote peer does
+    /// not reply to when the sender is attached with a [`LinkKeepAlive`](super::LinkKeepAlive)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn subscribe_events(&self) -> broadcast::Receiver<LinkEvent> {
+        self.inner.events.subscribe()
+    }
+
+    cfg_debug_deliveries! {
+        /// Returns the recorded frames of a recent delivery, eg. to find out whether a delivery
+        /// that is stuck was ever sent or whether the remote receiver replied to it. `None` is
+        /// returned if the delivery is older than the
+        /// [`DEFAULT_TRACE_CAPACITY`](super::delivery_trace::DEFAULT_TRACE_CAPACITY) most recent
+        /// deliveries
+        pub fn delivery_trace(&self, delivery_tag: &DeliveryTag) -> Option<DeliveryTrace> {
+            self.inner.link.delivery_traces.lock().get(delivery_tag)
+        }
+
+        /// Returns the recorded frames of the recent deliveries that are not settled yet, in the
+        /// order they were sent
+        pub fn unsettled_delivery_traces(&self) -> Vec<DeliveryTrace> {
+            self.inner.link.delivery_traces.lock().unsettled()
+        }
+    }
+
+    /// Watches the local state of the link, eg. to wait until the link is attached or to detect a
+    /// Detach from the remote receiver. The returned receiver is notified on every state transition
+    /// and is closed once the link is dropped
+    ///
+    /// Only the latest state is kept, so a receiver that lags behind may not see the intermediate
+    /// states
+    pub fn watch_state(&self) -> watch::Receiver<LinkState> {
+        self.inner.link.watch_state()
+    }
+
+    /// Returns the payload bytes that may still be sent before the remote receiver raises its
+    /// byte limit, or `None` if the remote receiver does not limit the bytes
+    ///
+    /// See [`byte_credit`](crate::link::byte_credit) for how the limit is granted.
+    pub fn byte_credit(&self) -> Option<u64> {
+        self.inner.link.flow_state.as_ref().byte_credit()
+    }
+
+    /// Returns the properties carried by the last Flow received from the remote receiver
+    pub fn remote_flow_properties(&self) -> Option<Fields> {
+        self.inner.link.flow_state.as_ref().remote_properties()
+    }
+
+    /// Watches the properties carried by the Flows from the remote receiver. The returned
+    /// receiver is notified whenever a Flow carries properties that differ from the last ones
+    pub fn watch_remote_flow_properties(&self) -> watch::Receiver<Option<Fields>> {
+        self.inner
+            .link
+            .flow_state
+            .as_ref()
+            .watch_remote_properties()
+    }
+
+    /// Sends a Flow that carries the current flow state of the link with `properties` added to
+    /// the properties of the link. The `properties` are only carried by this Flow and do not
+    /// change the properties of the link
+    pub async fn send_flow_properties(&mut self, properties: Fields) -> Result<(), FlowError> {
+        self.inner
+            .link
+            .send_flow_with_properties(&self.inner.outgoing, properties)
+            .await
+    }
+
     /// Returns when the remote peer detach/close the link
     pub async fn on_detach(&mut self) -> DetachError {
         match recv_remote_detach(&mut self.inner).await {
@@ -492,6 +879,31 @@ pub(crate) struct SenderInner<L: endpoint::SenderLink> {
     // Outgoing mpsc channel to send the Link frames
     pub(crate) outgoing: mpsc::Sender<LinkFrame>,
     pub(crate) incoming: mpsc::Receiver<LinkFrame>,
+
+    pub(crate) available_mode: AvailableMode,
+
+    // How long to wait for the remote Detach
+    pub(crate) detach_timeout: Option<Duration>,
+
+    // Compression of the outgoing message bodies
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+    pub(crate) compression: Option<Compression>,
+
+    // Hash of the bare message that is written into the footer
+    #[cfg(feature = "checksum")]
+    pub(crate) checksum: Option<Checksum>,
+
+    // Settlement latencies, which are shared with the `LinkRelay`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) latency: ArcSettlementLatency,
+
+    // Events of the link, such as the missed keep-alives
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) events: LinkEvents,
+
+    // Clock of the connection that drives the timers of the link
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) clock: SharedClock,
 }
 
 impl<L: endpoint::SenderLink> Drop for SenderInner<L> {
@@ -537,6 +949,14 @@ where
         self.buffer_size
     }
 
+    fn detach_timer(&self) -> Option<IdleTimeout> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let timer = |duration| IdleTimeout::with_clock(duration, self.clock.clone());
+        #[cfg(target_arch = "wasm32")]
+        let timer = IdleTimeout::new;
+        self.detach_timeout.map(timer)
+    }
+
     fn as_new_link_relay(&self, tx: mpsc::Sender<LinkFrame>) -> LinkRelay<()> {
         LinkRelay::Sender {
             tx,
@@ -545,6 +965,10 @@ where
             // TODO: what else to do during re-attaching
             unsettled: self.link.unsettled().clone(),
             receiver_settle_mode: self.link.rcv_settle_mode().clone(),
+            #[cfg(feature = "debug-deliveries")]
+            delivery_traces: self.link.delivery_traces().clone(),
+            #[cfg(not(target_arch = "wasm32"))]
+            latency: self.latency.clone(),
         }
     }
 
@@ -618,6 +1042,7 @@ where
 impl<L> SenderInner<L>
 where
     L: endpoint::SenderLink<
+            FlowError = FlowError,
             TransferError = LinkStateError,
             AttachError = SenderAttachError,
             DetachError = DetachError,
@@ -635,24 +1060,7 @@ where
         T: SerializableBody,
         E: From<L::TransferError> + From<serde_amqp::Error>,
     {
-        use bytes::BufMut;
-        use serde::Serialize;
-        use serde_amqp::ser::Serializer;
-
-        let Sendable {
-            message,
-            message_format,
-            settled,
-        } = sendable;
-
-        // serialize message
-        let mut payload = BytesMut::new();
-        let mut serializer = Serializer::from((&mut payload).writer());
-        Serializable(message).serialize(&mut serializer)?;
-        let payload = payload.freeze();
-
-        self.send_payload(payload, message_format, settled, state, batchable)
-            .await
+        self.send_ref_with_state(&sendable, state, batchable).await
     }
 
     pub(crate) async fn send_ref_with_state<T, E>(
@@ -664,6 +1072,23 @@ where
     where
         T: SerializableBody,
         E: From<L::TransferError> + From<serde_amqp::Error>,
+    {
+        let payload = self.encode_sendable(sendable)?;
+        self.send_payload(
+            payload,
+            sendable.message_format,
+            sendable.settled,
+            state,
+            batchable,
+        )
+        .await
+    }
+
+    /// Serializes the message, and stamps and transforms it according to the options of the
+    /// sendable and the link
+    fn encode_sendable<T>(&self, sendable: &Sendable<T>) -> Result<Payload, serde_amqp::Error>
+    where
+        T: SerializableBody,
     {
         use bytes::BufMut;
         use serde::Serialize;
@@ -671,8 +1096,9 @@ where
 
         let Sendable {
             message,
-            message_format,
-            settled,
+            #[cfg(not(target_arch = "wasm32"))]
+            deadline,
+            ..
         } = sendable;
 
         // serialize message
@@ -680,9 +1106,28 @@ where
         let mut serializer = Serializer::from((&mut payload).writer());
         Serializable(message).serialize(&mut serializer)?;
         let payload = payload.freeze();
-
-        self.send_payload(payload, *message_format, *settled, state, batchable)
-            .await
+        #[cfg(not(target_arch = "wasm32"))]
+        let payload = match deadline {
+            Some(deadline) => stamp_ttl(
+                payload,
+                message.header.as_ref(),
+                *deadline,
+                self.clock.now(),
+            )?,
+            None => payload,
+        };
+        #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+        let payload = match &self.compression {
+            Some(compression) => compression.compress_payload(payload)?,
+            None => payload,
+        };
+        // The hash covers the body as it is sent
+        #[cfg(feature = "checksum")]
+        let payload = match &self.checksum {
+            Some(checksum) => checksum.stamp_payload(payload)?,
+            None => payload,
+        };
+        Ok(payload)
     }
 
     pub(crate) async fn send_payload<E>(
@@ -696,9 +1141,14 @@ where
     where
         E: From<L::TransferError> + From<serde_amqp::Error>,
     {
+        let pending = match self.available_mode {
+            AvailableMode::Auto => self.advertise_pending_messages(1).await?,
+            AvailableMode::Manual => false,
+        };
+
         // send a transfer, checking state will be implemented in SenderLink
         let detached_fut = self.incoming.recv(); // cancel safe
-        let settlement = self
+        let result = self
             .link
             .send_payload(
                 &self.outgoing,
@@ -709,12 +1159,167 @@ where
                 state,
                 batchable,
             )
+            .await;
+        if pending && result.is_err() {
+            // The message is no longer waiting for link credit
+            self.link
+                .flow_state()
+                .as_ref()
+                .available_mut(|available| available.saturating_sub(1));
+        }
+        Ok(result?)
+    }
+
+    /// Advertises the messages that are about to wait for link credit. Returns whether the
+    /// messages are counted in the `available` field
+    async fn advertise_pending_messages(&self, count: u32) -> Result<bool, LinkStateError> {
+        let flow_state = self.link.flow_state().as_ref();
+        // A paused sender keeps advertising zero until it is resumed
+        if flow_state.link_credit() > 0 || flow_state.is_paused() {
+            return Ok(false);
+        }
+
+        // The value is decremented once the link credit is consumed
+        let available = flow_state.available().saturating_add(count);
+        self.link
+            .send_flow(&self.outgoing, None, Some(available), false)
             .await?;
-        Ok(settlement)
+        Ok(true)
     }
 }
 
 impl SenderInner<SenderLink<Target>> {
+    pub(crate) async fn send_batch<T>(
+        &mut self,
+        sendables: Vec<Sendable<T>>,
+    ) -> Result<Vec<DeliveryFut<Result<Outcome, SendError>>>, SendError>
+    where
+        T: SerializableBody,
+    {
+        // All the messages are encoded before any credit is taken
+        let mut encoded = Vec::with_capacity(sendables.len());
+        for sendable in &sendables {
+            let payload = self.encode_sendable(sendable)?;
+            let deadline = self.deadline_timer(sendable);
+            encoded.push((payload, sendable.message_format, sendable.settled, deadline));
+        }
+
+        let mut futs = Vec::with_capacity(encoded.len());
+        // The byte limit of the remote receiver cuts the batch by the sizes of the payloads
+        let payload_lens: Vec<usize> = encoded.iter().map(|(payload, ..)| payload.len()).collect();
+        let mut encoded = encoded.into_iter();
+        let mut remaining = u32::try_from(encoded.len()).unwrap_or(u32::MAX);
+        let mut pending = false;
+        while remaining > 0 {
+            if !pending {
+                pending = match self.available_mode {
+                    AvailableMode::Auto => self.advertise_pending_messages(remaining).await?,
+                    AvailableMode::Manual => false,
+                };
+            }
+
+            let detached_fut = self.incoming.recv(); // cancel safe
+            let (first_tag, count) = match self
+                .link
+                .get_delivery_tags_or_detached(
+                    &self.outgoing,
+                    detached_fut,
+                    &payload_lens[futs.len()..],
+                )
+                .await
+            {
+                Ok(tags) => tags,
+                Err(error) => {
+                    if pending {
+                        // The messages are no longer waiting for link credit
+                        self.link
+                            .flow_state()
+                            .as_ref()
+                            .available_mut(|available| available.saturating_sub(remaining));
+                    }
+                    return Err(error.into());
+                }
+            };
+            remaining -= count;
+
+            let first_delivery_count = u32::from_be_bytes(first_tag);
+            for (offset, (payload, message_format, settled, deadline)) in
+                (0..count).zip(encoded.by_ref())
+            {
+                let tag = first_delivery_count.wrapping_add(offset).to_be_bytes();
+                self.link
+                    .flow_state()
+                    .as_ref()
+                    .on_payload_sent(payload.len());
+                let transfer = self.link.generate_non_resuming_transfer_performative(
+                    DeliveryTag::from(tag),
+                    message_format,
+                    settled,
+                    None,
+                    true,
+                )?;
+                let settlement = endpoint::SenderLink::send_payload_with_transfer(
+                    &mut self.link,
+                    &self.outgoing,
+                    message_format,
+                    transfer,
+                    payload,
+                )
+                .await?;
+                futs.push(DeliveryFut::from(settlement).with_deadline(deadline));
+            }
+        }
+        Ok(futs)
+    }
+
+    /// The timer that fails the delivery of the message once its deadline elapses
+    fn deadline_timer<T>(&self, sendable: &Sendable<T>) -> Option<DeadlineTimer> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let deadline = sendable.deadline?;
+            let release = match sendable.release_after_deadline {
+                true => self
+                    .link
+                    .input_handle
+                    .clone()
+                    .map(|input_handle| DeadlineRelease {
+                        unsettled: self.link.unsettled.clone(),
+                        input_handle,
+                        writer: self.outgoing.clone(),
+                    }),
+                false => None,
+            };
+            Some(DeadlineTimer::new(self.clock.clone(), deadline, release))
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = sendable;
+            None
+        }
+    }
+
+    pub(crate) async fn set_available(&self, available: u32) -> Result<(), FlowError> {
+        endpoint::SenderLink::send_flow(&self.link, &self.outgoing, None, Some(available), false)
+            .await
+    }
+
+    /// Sends a Flow with `echo` set to true and waits for the next Flow from the remote peer
+    pub(crate) async fn request_flow_echo(&self) -> Result<RemoteFlowState, FlowError> {
+        // The waiter is registered first so that the reply cannot be missed
+        let remote_flow = self.link.flow_state().as_ref().wait_for_remote_flow();
+        endpoint::SenderLink::send_flow(&self.link, &self.outgoing, None, None, true).await?;
+        remote_flow.await.map_err(|_| FlowError::IllegalState)
+    }
+
+    pub(crate) async fn settle(
+        &mut self,
+        delivery_tag: DeliveryTag,
+        state: Option<DeliveryState>,
+    ) -> Result<(), SettleError> {
+        self.link.settle(&self.outgoing, delivery_tag, state).await
+    }
+
     /// Resumes a delivery with the given state and payload.
     ///
     /// The resume operation should not replace the unsettled map entry.
@@ -992,6 +1597,8 @@ impl SenderInner<SenderLink<Target>> {
                     // Upon completion of this reduction of state, the two parties MUST suspend and
                     // re-attempt to resume the link.
                     self.detach_with_error(None).await?;
+                    // The output handle is released by the detach
+                    self.reallocate_output_handle().await?;
                 }
             }
         }
@@ -1000,6 +1607,36 @@ impl SenderInner<SenderLink<Target>> {
     }
 }
 
+/// Waits for the message to be sent, which fails with [`SendError::DeadlineElapsed`] if the
+/// deadline elapses first, eg. while waiting for link credit
+///
+/// The timer is not borrowed by the returned future, which is `Send` as long as `fut` is.
+fn sent_before_deadline<F>(
+    fut: F,
+    deadline: Option<&DeadlineTimer>,
+) -> impl std::future::Future<Output = Result<Settlement, SendError>>
+where
+    F: std::future::Future<Output = Result<Settlement, SendError>>,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    let elapsed = deadline.map(DeadlineTimer::elapsed);
+    #[cfg(target_arch = "wasm32")]
+    let _ = deadline;
+
+    async move {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(elapsed) = elapsed {
+            return tokio::select! {
+                biased;
+                result = fut => result,
+                _ = elapsed => Err(SendError::DeadlineElapsed),
+            };
+        }
+
+        fut.await
+    }
+}
+
 /// A detached sender
 ///
 /// # Example
@@ -1054,6 +1691,22 @@ impl DetachedSender {
         &mut self.inner.link.target
     }
 
+    /// Captures the state of the detached sender so that it can be resumed from another process
+    /// with [`Builder::resume_from()`](crate::link::builder::Builder::resume_from)
+    ///
+    /// The payloads of the unsettled deliveries are not captured
+    pub fn to_suspended_link(&self) -> SuspendedLink {
+        let link = &self.inner.link;
+        SuspendedLink {
+            name: link.name.clone(),
+            role: Role::Sender,
+            source: link.source.clone(),
+            target: link.target.clone(),
+            delivery_count: link.flow_state.as_ref().delivery_count(),
+            unsettled: SuspendedLink::sender_unsettled(link.unsettled.read().as_ref()),
+        }
+    }
+
     /// Resume the sender link on the original session
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
     pub async fn resume(mut self) -> Result<Sender, SenderResumeError> {
@@ -1128,7 +1781,8 @@ impl DetachedSender {
         mut self,
         session: &SessionHandle<R>,
     ) -> Result<Sender, SenderResumeError> {
-        *self.inner.session_control_mut() = session.control.clone();
+        self.inner.session = session.control.clone();
+        self.inner.outgoing = session.outgoing.clone();
         self.resume().await
     }
 
@@ -1150,7 +1804,8 @@ impl DetachedSender {
             session: &SessionHandle<R>,
             duration: Duration,
         ) -> Result<Sender, SenderResumeError> {
-            *self.inner.session_control_mut() = session.control.clone();
+            self.inner.session = session.control.clone();
+            self.inner.outgoing = session.outgoing.clone();
             self.resume_with_timeout(duration).await
         }
 
@@ -1161,7 +1816,8 @@ impl DetachedSender {
             session: &SessionHandle<R>,
             duration: Duration,
         ) -> Result<Sender, SenderResumeError> {
-            *self.inner.session_control_mut() = session.control.clone();
+            self.inner.session = session.control.clone();
+            self.inner.outgoing = session.outgoing.clone();
             self.resume_incoming_attach_with_timeout(remote_attach, duration)
                 .await
         }
@@ -8,6 +8,9 @@ use crate::endpoint::LinkExt;
 
 use super::{resumption::resume_delivery, *};
 
+#[cfg(feature = "transaction")]
+use fe2o3_amqp_types::transaction::TransactionalState;
+
 impl<T> SenderLink<T>
 where
     T: Into<TargetArchetype>
@@ -17,6 +20,34 @@ where
         + Send
         + Sync,
 {
+    /// Whether the delivery is sent settled
+    fn is_transfer_settled(&self, transfer: &Transfer) -> bool {
+        transfer.settled.unwrap_or(match self.snd_settle_mode {
+            SenderSettleMode::Settled => true,
+            SenderSettleMode::Unsettled => false,
+            SenderSettleMode::Mixed => false,
+        })
+    }
+
+    #[cfg(feature = "debug-deliveries")]
+    fn trace_transfer(
+        &self,
+        delivery_tag: Option<&DeliveryTag>,
+        transfer: &Transfer,
+        settled: bool,
+    ) {
+        if let Some(delivery_tag) = delivery_tag {
+            self.trace_delivery(
+                delivery_tag,
+                delivery_trace::DeliveryEventKind::TransferSent {
+                    settled,
+                    more: transfer.more,
+                    resume: transfer.resume,
+                },
+            );
+        }
+    }
+
     /// # Cancel safety
     ///
     /// This is cancel safe because all internal `.await` are cancel safe
@@ -26,35 +57,35 @@ where
         mut transfer: Transfer,
         mut payload: Payload,
     ) -> Result<bool, LinkStateError> {
-        let settled = transfer.settled.unwrap_or(match self.snd_settle_mode {
-            SenderSettleMode::Settled => true,
-            SenderSettleMode::Unsettled => false,
-            SenderSettleMode::Mixed => false,
-        });
+        let settled = self.is_transfer_settled(&transfer);
         let input_handle = self
             .input_handle
             .clone()
             .ok_or(LinkStateError::IllegalState)?;
+        // The continuation transfers do not carry the delivery tag
+        #[cfg(feature = "debug-deliveries")]
+        let delivery_tag = transfer.delivery_tag.clone();
 
         // Check message size
         // If this field is zero or unset, there is no maximum size imposed by the link endpoint.
-        let more = (self.max_message_size != 0) && (payload.len() as u64 > self.max_message_size);
-        if !more {
-            transfer.more = false;
-            send_transfer(writer, input_handle, transfer, payload.clone()).await?;
-        // cancel safe
-        } else {
+        let max_partial_size = self.max_partial_payload_size(&transfer);
+        let more = matches!(max_partial_size, Some(size) if payload.len() > size);
+        if let (true, Some(max_partial_size)) = (more, max_partial_size) {
             // Send the first frame
-            let partial = payload.split_to(self.max_message_size as usize);
+            let partial = payload.split_to(max_partial_size);
             transfer.more = true;
+            #[cfg(feature = "debug-deliveries")]
+            self.trace_transfer(delivery_tag.as_ref(), &transfer, settled);
             send_transfer(writer, input_handle.clone(), transfer.clone(), partial).await?; // cancel safe
 
             // Send the transfers in the middle
-            while payload.len() > self.max_message_size as usize {
-                let partial = payload.split_to(self.max_message_size as usize);
+            while payload.len() > max_partial_size {
+                let partial = payload.split_to(max_partial_size);
                 transfer.delivery_tag = None;
-                transfer.message_format = None;
+                // The message-format is kept on every transfer of the delivery
                 transfer.settled = None;
+                #[cfg(feature = "debug-deliveries")]
+                self.trace_transfer(delivery_tag.as_ref(), &transfer, settled);
                 send_transfer(writer, input_handle.clone(), transfer.clone(), partial).await?;
                 // cancel safe
             }
@@ -64,12 +95,74 @@ where
             // data MAY be trans- ferred in additional transfer frames by setting the more flag on
             // all but the last transfer frame
             transfer.more = false;
+            #[cfg(feature = "debug-deliveries")]
+            self.trace_transfer(delivery_tag.as_ref(), &transfer, settled);
             send_transfer(writer, input_handle, transfer, payload).await?; // cancel safe
+        } else {
+            transfer.more = false;
+            #[cfg(feature = "debug-deliveries")]
+            self.trace_transfer(delivery_tag.as_ref(), &transfer, settled);
+            send_transfer(writer, input_handle, transfer, payload.clone()).await?;
+            // cancel safe
         }
 
         Ok(settled)
     }
 
+    /// Settles an unsettled delivery from the sender side. The last known state of the delivery
+    /// is used if `state` is `None`
+    pub(crate) async fn settle(
+        &mut self,
+        writer: &mpsc::Sender<LinkFrame>,
+        delivery_tag: DeliveryTag,
+        state: Option<DeliveryState>,
+    ) -> Result<(), SettleError> {
+        let input_handle = self.input_handle.clone().ok_or(SettleError::IllegalState)?;
+        let unsettled = {
+            let mut guard = self.unsettled.write();
+            guard
+                .as_mut()
+                .and_then(|m| m.swap_remove(&delivery_tag))
+                .ok_or(SettleError::DeliveryNotFound)?
+        };
+        let state = state.or_else(|| unsettled.state.clone());
+
+        let frame = LinkFrame::Settle {
+            input_handle,
+            delivery_tag,
+            state: state.clone(),
+        };
+        let result = writer
+            .send(frame)
+            .await
+            .map_err(|_| SettleError::IllegalSessionState);
+        let _ = unsettled.settle_with_state(state);
+        result
+    }
+
+    /// The max size of the payload carried by each transfer frame if the link pre-splits the
+    /// delivery, which happens if either the `max_message_size` or the `max_transfer_frame_size`
+    /// is set
+    fn max_partial_payload_size(&self, transfer: &Transfer) -> Option<usize> {
+        let by_message_size =
+            (self.max_message_size != 0).then_some(self.max_message_size as usize);
+        let by_frame_size = self.max_transfer_frame_size.map(|max_frame_size| {
+            // The delivery-id is assigned by the session, so the largest possible value is used
+            // to estimate the size of the performative
+            let mut transfer = transfer.clone();
+            transfer.delivery_id = Some(DeliveryNumber::MAX);
+            transfer.more = true;
+            let performative_size = serde_amqp::to_vec(&transfer).map_or(0, |buf| buf.len());
+            // 8 bytes of frame header
+            max_frame_size.saturating_sub(8 + performative_size).max(1)
+        });
+
+        match (by_message_size, by_frame_size) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
     pub(crate) async fn get_delivery_tag_or_detached<Fut>(
         &mut self,
         writer: &mpsc::Sender<LinkFrame>,
@@ -91,36 +184,65 @@ where
                 Ok(tag)
             },
             frame = detached => { // cancel safe
-                match frame {
-                    // If remote has detached the link
-                    Some(LinkFrame::Detach(detach)) => {
-                        // FIXME: if the sender is not trying to send anything, this is
-                        // probably not responsive enough
-                        let closed = detach.closed;
-                        self.send_detach(writer, closed, None).await?;
-                        let result = self.on_incoming_detach(detach);
-
-                        match (result, closed) {
-                            (Ok(_), true) => Err(LinkStateError::RemoteClosed),
-                            (Ok(_), false) => Err(LinkStateError::RemoteDetached),
-                            (Err(err), _) => Err(LinkStateError::from(err)),
-                        }
-                    },
-                    Some(_frame) => {
-                        // Other frames should not forwarded to the sender by the session
-                        #[cfg(feature = "tracing")]
-                        tracing::error!("Unexpected frame: {:?}", _frame);
-                        #[cfg(feature = "log")]
-                        log::error!("Unexpected frame: {:?}", _frame);
-
-                        Err(LinkStateError::ExpectImmediateDetach)
-                    }
-                    None => {
-                        // Other frames should not forwarded to the sender by the session
-                        Err(LinkStateError::ExpectImmediateDetach)
-                    }
+                self.on_frame_while_waiting_for_credit(writer, frame).await
+            }
+        }
+    }
+
+    /// Like [`get_delivery_tag_or_detached`](#method.get_delivery_tag_or_detached) but takes the
+    /// link credit of the messages with the given payload sizes at once, within the byte limit of
+    /// the remote receiver. Returns the tag of the first message along with the number of
+    /// messages, whose tags follow consecutively
+    pub(crate) async fn get_delivery_tags_or_detached<Fut>(
+        &mut self,
+        writer: &mpsc::Sender<LinkFrame>,
+        detached: Fut,
+        payload_lens: &[usize],
+    ) -> Result<([u8; 4], u32), LinkStateError>
+    where
+        Fut: Future<Output = Option<LinkFrame>> + Send,
+    {
+        tokio::select! {
+            tags = self.flow_state.consume_up_to(payload_lens) => Ok(tags),
+            frame = detached => { // cancel safe
+                self.on_frame_while_waiting_for_credit(writer, frame).await
+            }
+        }
+    }
+
+    async fn on_frame_while_waiting_for_credit<O>(
+        &mut self,
+        writer: &mpsc::Sender<LinkFrame>,
+        frame: Option<LinkFrame>,
+    ) -> Result<O, LinkStateError> {
+        match frame {
+            // If remote has detached the link
+            Some(LinkFrame::Detach(detach)) => {
+                // FIXME: if the sender is not trying to send anything, this is
+                // probably not responsive enough
+                let closed = detach.closed;
+                self.send_detach(writer, closed, None).await?;
+                let result = self.on_incoming_detach(detach);
+
+                match (result, closed) {
+                    (Ok(_), true) => Err(LinkStateError::RemoteClosed),
+                    (Ok(_), false) => Err(LinkStateError::RemoteDetached),
+                    (Err(err), _) => Err(LinkStateError::from(err)),
                 }
             }
+            Some(_frame) => {
+                // Other frames should not forwarded to the sender by the session
+                #[cfg(feature = "tracing")]
+                tracing::error!("Unexpected frame: {:?}", _frame);
+                #[cfg(feature = "log")]
+                log::error!("Unexpected frame: {:?}", _frame);
+
+                Err(LinkStateError::ExpectImmediateDetach)
+            }
+            None => {
+                // Other frames should not forwarded to the sender by the session
+                Err(LinkStateError::ExpectImmediateDetach)
+            }
         }
     }
 
@@ -150,6 +272,17 @@ where
         // unsettled delivery from a dissociated link endpoint
         let resume = false;
 
+        // While the receiver is transactionally acquiring messages, the transfers are associated
+        // with the acquisition's transaction
+        #[cfg(feature = "transaction")]
+        let state = match (state, self.flow_state.as_ref().acquisition()) {
+            (None, Some(txn_id)) => Some(DeliveryState::TransactionalState(TransactionalState {
+                txn_id,
+                outcome: None,
+            })),
+            (state, _) => state,
+        };
+
         let transfer = Transfer {
             handle,
             delivery_id: None, // This will be set by the session
@@ -220,6 +353,7 @@ where
         let tag = self.get_delivery_tag_or_detached(writer, detached).await?;
         // Delivery count is incremented when consuming credit
         let delivery_tag = DeliveryTag::from(tag);
+        self.flow_state.as_ref().on_payload_sent(payload.len());
 
         let transfer = self.generate_non_resuming_transfer_performative(
             delivery_tag,
@@ -250,14 +384,17 @@ where
             .delivery_tag
             .clone()
             .ok_or(LinkStateError::IllegalState)?;
-        let settled = self
-            .send_transfer_without_modifying_unsettled_map(writer, transfer, payload)
-            .await?;
-        match settled {
-            true => Ok(Settlement::Settled(delivery_tag)),
+        match self.is_transfer_settled(&transfer) {
+            true => {
+                self.send_transfer_without_modifying_unsettled_map(writer, transfer, payload)
+                    .await?;
+                Ok(Settlement::Settled(delivery_tag))
+            }
             // If not set on the first (or only) transfer for a (multi-transfer)
             // delivery, then the settled flag MUST be interpreted as being false.
             false => {
+                // The delivery is tracked before the transfer is sent because the receiver may
+                // settle it before the send returns
                 let (tx, rx) = oneshot::channel();
                 let unsettled = UnsettledMessage::new(payload_copy, None, message_format, tx);
                 {
@@ -267,6 +404,15 @@ where
                         .insert(delivery_tag.clone(), unsettled);
                 }
 
+                if let Err(error) = self
+                    .send_transfer_without_modifying_unsettled_map(writer, transfer, payload)
+                    .await
+                {
+                    let mut guard = self.unsettled.write();
+                    guard.as_mut().map(|map| map.swap_remove(&delivery_tag));
+                    return Err(error);
+                }
+
                 Ok(Settlement::Unsettled {
                     delivery_tag,
                     outcome: rx,
@@ -298,6 +444,14 @@ where
                 msg.state = Some(state.clone());
             }
         }
+        #[cfg(feature = "debug-deliveries")]
+        self.trace_delivery(
+            &delivery_tag,
+            delivery_trace::DeliveryEventKind::DispositionSent {
+                settled,
+                state: Some(state.clone()),
+            },
+        );
 
         send_disposition(writer, delivery_id, None, settled, Some(state), batchable).await
     }
@@ -332,6 +486,14 @@ where
                     msg.state = Some(state.clone());
                 }
             }
+            #[cfg(feature = "debug-deliveries")]
+            self.trace_delivery(
+                &delivery_tag,
+                delivery_trace::DeliveryEventKind::DispositionSent {
+                    settled,
+                    state: Some(state.clone()),
+                },
+            );
 
             match (first, last) {
                 // First pair
@@ -425,6 +587,30 @@ async fn send_disposition(
 }
 
 impl<T> SenderLink<T> {
+    /// Sends a Flow that carries the current flow state with `properties` added to the
+    /// properties of the link. The `properties` only apply to this Flow
+    pub(crate) async fn send_flow_with_properties(
+        &self,
+        writer: &mpsc::Sender<LinkFrame>,
+        properties: Fields,
+    ) -> Result<(), FlowError> {
+        let handle = self
+            .output_handle
+            .clone()
+            .ok_or(FlowError::IllegalState)?
+            .into();
+
+        let mut flow = self.get_link_flow(handle, None, None, false);
+        let fields = flow.properties.get_or_insert_with(Fields::new);
+        for (key, value) in properties.into_inner() {
+            fields.insert(key, value);
+        }
+        writer
+            .send(LinkFrame::Flow(flow))
+            .await
+            .map_err(|_| FlowError::IllegalSessionState)
+    }
+
     fn get_link_flow(
         &self,
         handle: Handle,
@@ -598,19 +784,19 @@ where
 
         match (&self.local_state, remote_attach.incomplete_unsettled) {
             (LinkState::AttachSent, false) => {
-                self.local_state = LinkState::Attached;
+                self.set_local_state(LinkState::Attached);
             }
             (LinkState::IncompleteAttachSent, false) => {
-                self.local_state = LinkState::IncompleteAttachExchanged;
+                self.set_local_state(LinkState::IncompleteAttachExchanged);
             }
             (LinkState::Unattached, false) | (LinkState::Detached, false) => {
-                self.local_state = LinkState::AttachReceived; // re-attaching
+                self.set_local_state(LinkState::AttachReceived); // re-attaching
             }
             (LinkState::AttachSent, true) | (LinkState::IncompleteAttachSent, true) => {
-                self.local_state = LinkState::IncompleteAttachExchanged;
+                self.set_local_state(LinkState::IncompleteAttachExchanged);
             }
             (LinkState::Unattached, true) | (LinkState::Detached, true) => {
-                self.local_state = LinkState::IncompleteAttachReceived; // re-attaching
+                self.set_local_state(LinkState::IncompleteAttachReceived); // re-attaching
             }
             _ => return Err(SenderAttachError::IllegalState),
         };
@@ -669,6 +855,9 @@ where
         self.max_message_size =
             get_max_message_size(self.max_message_size, remote_attach.max_message_size);
 
+        self.flow_state
+            .as_ref()
+            .update_byte_limit(remote_attach.properties.as_ref());
         if let Some(remote_properties) = remote_attach.properties {
             self.properties_mut(|local_properties| {
                 local_properties
@@ -759,6 +948,11 @@ where
         }
     }
 
+    #[cfg(feature = "debug-deliveries")]
+    fn delivery_traces(&self) -> &super::delivery_trace::ArcDeliveryTraces {
+        &self.delivery_traces
+    }
+
     fn properties<F, O>(&self, op: F) -> O
     where
         F: FnOnce(&Option<Fields>) -> O,
@@ -848,6 +1042,10 @@ where
             SenderAttachError::DesireTxnCapabilitiesNotSupported => {
                 try_detach_with_error(self, attach_error, writer, reader).await
             }
+            #[cfg(feature = "acceptor")]
+            SenderAttachError::SettleModesNotSupported | SenderAttachError::Unauthorized(_) => {
+                try_detach_with_error(self, attach_error, writer, reader).await
+            }
 
             _ => attach_error,
         }
@@ -903,3 +1101,78 @@ where
         None => SenderAttachError::IllegalSessionState,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use fe2o3_amqp_types::{definitions::MIN_MAX_FRAME_SIZE, performatives::Transfer};
+    use parking_lot::RwLock;
+    use tokio::sync::{mpsc, Notify};
+
+    use crate::{
+        endpoint::{InputHandle, OutputHandle},
+        link::{state::LinkFlowStateInner, LinkFlowState, LinkFrame, Sender},
+        util::Consumer,
+    };
+
+    #[tokio::test]
+    async fn transfer_frames_are_capped_by_max_transfer_frame_size() {
+        let flow_state = LinkFlowState::sender(LinkFlowStateInner {
+            initial_delivery_count: 0,
+            delivery_count: 0,
+            link_credit: 0,
+            available: 0,
+            drain: false,
+            properties: None,
+        });
+        let consumer = Consumer::new(Arc::new(Notify::new()), Arc::new(flow_state));
+        let mut link = Sender::builder()
+            .name("bulk")
+            .target("q1")
+            .max_transfer_frame_size(MIN_MAX_FRAME_SIZE as u32)
+            .create_link(Arc::new(RwLock::new(None)), OutputHandle(0), consumer);
+        link.input_handle = Some(InputHandle(0));
+
+        let transfer = Transfer {
+            handle: 0.into(),
+            delivery_id: None,
+            delivery_tag: Some(vec![0u8; 4].into()),
+            message_format: Some(0),
+            settled: None,
+            more: false,
+            rcv_settle_mode: None,
+            state: None,
+            resume: false,
+            aborted: false,
+            batchable: false,
+        };
+        let payload = Bytes::from(vec![1u8; 4 * MIN_MAX_FRAME_SIZE]);
+        let (tx, mut rx) = mpsc::channel(100);
+        link.send_transfer_without_modifying_unsettled_map(&tx, transfer, payload.clone())
+            .await
+            .unwrap();
+        drop(tx);
+
+        let mut frames = Vec::new();
+        while let Some(LinkFrame::Transfer {
+            mut performative,
+            payload,
+            ..
+        }) = rx.recv().await
+        {
+            let more = performative.more;
+            performative.delivery_id = Some(u32::MAX);
+            let performative_size = serde_amqp::to_vec(&performative).unwrap().len();
+            assert!(8 + performative_size + payload.len() <= MIN_MAX_FRAME_SIZE);
+            frames.push((more, payload));
+        }
+
+        assert!(frames.len() > 4);
+        assert!(frames[..frames.len() - 1].iter().all(|(more, _)| *more));
+        assert!(!frames.last().unwrap().0);
+        let received: Vec<u8> = frames.into_iter().flat_map(|(_, p)| p).collect();
+        assert_eq!(received, payload);
+    }
+}
@@ -0,0 +1,279 @@
+//! Multiple sender links with messages routed by a key
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    hash::{Hash, Hasher},
+};
+
+use fe2o3_amqp_types::messaging::{Address, Outcome, SerializableBody};
+
+use crate::session::SessionHandle;
+
+use super::{
+    delivery::{DeliveryFut, Sendable},
+    DetachError, LinkStateError, SendError, Sender, SenderAttachError,
+};
+
+/// Number of points each link occupies on the hash ring
+const VIRTUAL_NODES_PER_SHARD: u64 = 64;
+
+/// A group of [`Sender`]s that routes each message to one of the links by the hash of a key
+///
+/// The links are placed on a consistent hash ring, so all messages with the same key go through
+/// the same link and keep their relative order, while messages with different keys are spread
+/// across the links. The links can be attached to the same node to multiply the throughput, or
+/// to one address per partition.
+///
+/// When a link is found to be detached or its session is gone, the link is taken off the ring and
+/// only the keys that were routed to that link are moved to the remaining links. A message that
+/// has not been transferred yet is retried on the next link. A message whose outcome is lost
+/// together with the link is not retried, and the error is returned. A link can be put back with
+/// [`replace_shard`](#method.replace_shard) or [`mark_live`](#method.mark_live).
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use fe2o3_amqp::link::ShardedSender;
+///
+/// let mut sender = ShardedSender::attach(&mut session, "telemetry", "q1", 4).await.unwrap();
+/// let outcome = sender.send("device-42", "reading").await.unwrap();
+/// sender.close().await.unwrap();
+/// ```
+#[derive(Debug)]
+pub struct ShardedSender {
+    shards: Vec<Sender>,
+    live: Vec<bool>,
+    ring: BTreeMap<u64, usize>,
+}
+
+impl ShardedSender {
+    /// Creates a [`ShardedSender`] from attached links. All links are considered live
+    pub fn new(shards: Vec<Sender>) -> Self {
+        let mut sender = Self {
+            live: vec![false; shards.len()],
+            shards,
+            ring: BTreeMap::new(),
+        };
+        for index in 0..sender.shards.len() {
+            sender.mark_live(index);
+        }
+        sender
+    }
+
+    /// Attaches `count` links to the same address. The links are named `"<base_name>-<index>"`
+    ///
+    /// The links that are already attached are closed if any link fails to attach.
+    pub async fn attach<R>(
+        session: &mut SessionHandle<R>,
+        base_name: impl Into<String>,
+        address: impl Into<Address>,
+        count: usize,
+    ) -> Result<Self, SenderAttachError> {
+        let address = address.into();
+        Self::attach_partitions(session, base_name, (0..count).map(|_| address.clone())).await
+    }
+
+    /// Attaches one link per address. The links are named `"<base_name>-<index>"`
+    ///
+    /// The links that are already attached are closed if any link fails to attach.
+    pub async fn attach_partitions<R>(
+        session: &mut SessionHandle<R>,
+        base_name: impl Into<String>,
+        addresses: impl IntoIterator<Item = impl Into<Address>>,
+    ) -> Result<Self, SenderAttachError> {
+        let base_name = base_name.into();
+        let mut shards = Vec::new();
+        for (index, address) in addresses.into_iter().enumerate() {
+            let result = Sender::builder()
+                .name(format!("{}-{}", base_name, index))
+                .target(address)
+                .attach(session)
+                .await;
+            match result {
+                Ok(sender) => shards.push(sender),
+                Err(error) => {
+                    for sender in shards {
+                        let _ = sender.close().await;
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        Ok(Self::new(shards))
+    }
+
+    /// Number of links, including the links that are not live
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Whether there is no link at all
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    /// Number of links that messages are routed to
+    pub fn live_shards(&self) -> usize {
+        self.live.iter().filter(|live| **live).count()
+    }
+
+    /// Whether messages are routed to the link at `index`
+    pub fn is_live(&self, index: usize) -> bool {
+        self.live.get(index).copied().unwrap_or(false)
+    }
+
+    /// Get a reference to the link at `index`
+    pub fn shard(&self, index: usize) -> Option<&Sender> {
+        self.shards.get(index)
+    }
+
+    /// Get a mutable reference to the link at `index`
+    ///
+    /// This can be used to resume a link that is taken off the ring, which should then be put
+    /// back with [`mark_live`](#method.mark_live)
+    pub fn shard_mut(&mut self, index: usize) -> Option<&mut Sender> {
+        self.shards.get_mut(index)
+    }
+
+    /// Index of the link that messages with `key` are routed to. `None` is returned if no link is
+    /// live
+    pub fn shard_of<K: Hash + ?Sized>(&self, key: &K) -> Option<usize> {
+        let hash = hash_of(key);
+        self.ring
+            .range(hash..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, index)| *index)
+    }
+
+    /// Takes the link at `index` off the ring. The keys routed to this link are moved to the
+    /// other live links
+    pub fn mark_dead(&mut self, index: usize) {
+        if let Some(live) = self.live.get_mut(index) {
+            if *live {
+                *live = false;
+                self.ring.retain(|_, i| *i != index);
+            }
+        }
+    }
+
+    /// Puts the link at `index` back on the ring. The keys that were routed to this link before
+    /// it was taken off are routed to it again
+    pub fn mark_live(&mut self, index: usize) {
+        if let Some(live) = self.live.get_mut(index) {
+            if !*live {
+                *live = true;
+                for replica in 0..VIRTUAL_NODES_PER_SHARD {
+                    self.ring.insert(hash_of(&(index, replica)), index);
+                }
+            }
+        }
+    }
+
+    /// Replaces the link at `index` with a newly attached link and puts it back on the ring.
+    /// The old link is returned
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds
+    pub fn replace_shard(&mut self, index: usize, sender: Sender) -> Sender {
+        let old = std::mem::replace(&mut self.shards[index], sender);
+        self.mark_live(index);
+        old
+    }
+
+    /// Sends a message through the link that `key` is routed to and waits for the outcome
+    ///
+    /// If the link is found to be lost before the message is transferred, the link is taken off
+    /// the ring and the message is sent through the link that `key` is routed to next.
+    pub async fn send<K, T>(
+        &mut self,
+        key: &K,
+        sendable: impl Into<Sendable<T>>,
+    ) -> Result<Outcome, SendError>
+    where
+        K: Hash + ?Sized,
+        T: SerializableBody,
+    {
+        let sendable = sendable.into();
+        let (index, fut) = self.transfer(key, &sendable, false).await?;
+        let result = fut.await;
+        if let Err(error) = &result {
+            if is_link_lost(error) {
+                self.mark_dead(index);
+            }
+        }
+        result
+    }
+
+    /// Like [`send`](#method.send) but returns a future that resolves to the outcome without
+    /// waiting for it
+    ///
+    /// The link is only taken off the ring if it is found to be lost before the message is
+    /// transferred.
+    pub async fn send_batchable<K, T>(
+        &mut self,
+        key: &K,
+        sendable: impl Into<Sendable<T>>,
+    ) -> Result<DeliveryFut<Result<Outcome, SendError>>, SendError>
+    where
+        K: Hash + ?Sized,
+        T: SerializableBody,
+    {
+        let sendable = sendable.into();
+        self.transfer(key, &sendable, true)
+            .await
+            .map(|(_, fut)| fut)
+    }
+
+    /// Closes all links concurrently
+    ///
+    /// All links are always closed. The first error is returned if any fails.
+    pub async fn close(self) -> Result<(), DetachError> {
+        let results =
+            futures_util::future::join_all(self.shards.into_iter().map(|sender| sender.close()))
+                .await;
+        results.into_iter().collect()
+    }
+
+    async fn transfer<K, T>(
+        &mut self,
+        key: &K,
+        sendable: &Sendable<T>,
+        batchable: bool,
+    ) -> Result<(usize, DeliveryFut<Result<Outcome, SendError>>), SendError>
+    where
+        K: Hash + ?Sized,
+        T: SerializableBody,
+    {
+        loop {
+            let index = self
+                .shard_of(key)
+                .ok_or(SendError::LinkStateError(LinkStateError::IllegalState))?;
+            let result = self.shards[index]
+                .inner
+                .send_ref_with_state::<T, SendError>(sendable, None, batchable)
+                .await;
+            match result {
+                Ok(settlement) => return Ok((index, DeliveryFut::from(settlement))),
+                Err(error) if is_link_lost(&error) => {
+                    self.mark_dead(index);
+                    if self.ring.is_empty() {
+                        return Err(error);
+                    }
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+fn is_link_lost(error: &SendError) -> bool {
+    matches!(error, SendError::LinkStateError(_) | SendError::Detached(_))
+}
+
+fn hash_of<K: Hash + ?Sized>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
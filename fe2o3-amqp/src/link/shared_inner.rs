@@ -5,6 +5,7 @@ use crate::{
     control::SessionControl,
     endpoint::{self, LinkAttach, LinkDetach, LinkExt},
     session::{self, error::AllocLinkError},
+    util::IdleTimeout,
 };
 
 use super::{state::LinkState, DetachError, LinkFrame, LinkRelay};
@@ -24,8 +25,17 @@ where
 
     fn reader_mut(&mut self) -> &mut mpsc::Receiver<LinkFrame>;
 
+    /// A Detach that has already been taken off the reader ahead of time
+    fn take_prefetched_detach(&mut self) -> Option<Detach> {
+        None
+    }
+
     fn buffer_size(&self) -> usize;
 
+    /// The timer of the wait for the remote Detach, which is driven by the clock of the link.
+    /// `None` waits indefinitely
+    fn detach_timer(&self) -> Option<IdleTimeout>;
+
     fn as_new_link_relay(&self, tx: mpsc::Sender<LinkFrame>) -> LinkRelay<()>;
 
     fn session_control(&self) -> &mpsc::Sender<SessionControl>;
@@ -124,58 +134,19 @@ where
         &mut self,
         error: Option<definitions::Error>,
     ) -> Result<(), <Self::Link as LinkDetach>::DetachError> {
-        match self.link().local_state() {
-            LinkState::Unattached
-            | LinkState::AttachSent
-            | LinkState::IncompleteAttachSent
-            | LinkState::IncompleteAttachReceived
-            | LinkState::IncompleteAttachExchanged
-            | LinkState::AttachReceived
-            | LinkState::Attached => {
-                // Send a non-closing detach
-                self.send_detach(false, error).await?;
-
-                let remote_detach = recv_remote_detach(self).await?;
-                if remote_detach.closed {
-                    // Note that one peer MAY send a closing detach while its partner is
-                    // sending a non-closing detach. In this case, the partner MUST
-                    // signal that it has closed the link by reattaching and then sending
-                    // a closing detach.
-                    reattach_and_then_close(self).await?;
-
-                    // A peer closes a link by sending the detach frame with the handle for the
-                    // specified link, and the closed flag set to true. The partner will destroy
-                    // the corresponding link endpoint, and reply with its own detach frame with
-                    // the closed flag set to true.
-                    Err(DetachError::ClosedByRemote)
-                } else {
-                    self.link_mut().on_incoming_detach(remote_detach)
+        let result = match self.detach_timer() {
+            Some(timeout) => {
+                tokio::select! {
+                    result = detach_inner(self, error) => result,
+                    _ = timeout => Err(DetachError::Timeout),
                 }
             }
-            LinkState::DetachSent => {
-                let remote_detach = recv_remote_detach(self).await?;
-                if remote_detach.closed {
-                    reattach_and_then_close(self).await?;
-                    Err(DetachError::ClosedByRemote)
-                } else {
-                    self.link_mut().on_incoming_detach(remote_detach)
-                }
-            }
-            LinkState::DetachReceived => self.send_detach(false, error).await,
-            LinkState::Detached => Ok(()),
-            LinkState::CloseSent => {
-                // This should be impossible.
-                // FIXME: treat it as if remote closed
-                let _remote_detach = recv_remote_detach(self).await?;
-                reattach_and_then_close(self).await?;
-                Err(DetachError::ClosedByRemote)
-            }
-            LinkState::CloseReceived => {
-                self.send_detach(true, error).await?;
-                Err(DetachError::ClosedByRemote)
-            }
-            LinkState::Closed => Err(DetachError::ClosedByRemote),
+            None => detach_inner(self, error).await,
+        };
+        if let Err(DetachError::Timeout) = result {
+            abandon(self, false).await;
         }
+        result
     }
 
     /// # Cancel safety
@@ -185,57 +156,175 @@ where
         &mut self,
         error: Option<definitions::Error>,
     ) -> Result<(), <Self::Link as LinkDetach>::DetachError> {
-        match self.link().local_state() {
-            LinkState::Unattached
-            | LinkState::AttachSent
-            | LinkState::IncompleteAttachSent
-            | LinkState::IncompleteAttachReceived
-            | LinkState::IncompleteAttachExchanged
-            | LinkState::AttachReceived
-            | LinkState::Attached => {
-                // Send detach with closed=true and wait for remote closing detach
-                // The sender will be dropped after close
-                self.send_detach(true, error)
-                    .await // cancel safe
-                    .map_err(|_| DetachError::IllegalSessionState)?;
-
-                // Wait for remote detach
-                let remote_detach = recv_remote_detach(self).await?; // cancel safe
-                if remote_detach.closed {
-                    // If the remote detach contains an error, the error will be propagated
-                    // back by `on_incoming_detach`
-                    self.link_mut().on_incoming_detach(remote_detach)
-                } else {
-                    reattach_and_then_close(self).await // FIXME: cancel safe? if oneshot channel is cancel safe
+        let result = match self.detach_timer() {
+            Some(timeout) => {
+                tokio::select! {
+                    result = close_inner(self, error) => result,
+                    _ = timeout => Err(DetachError::Timeout),
                 }
             }
-            LinkState::DetachSent => {
-                // FIXME: this should be impossible
-                // Wait for remote detach
-                let _remote_detach = recv_remote_detach(self).await?; // cancel safe
-                reattach_and_then_close(self).await?; // FIXME: cancel safe? if oneshot channel is cancel safe
-                Err(DetachError::DetachedByRemote)
+            None => close_inner(self, error).await,
+        };
+        if let Err(DetachError::Timeout) = result {
+            abandon(self, true).await;
+        }
+        result
+    }
+}
+
+async fn detach_inner<T>(
+    link_inner: &mut T,
+    error: Option<definitions::Error>,
+) -> Result<(), DetachError>
+where
+    T: LinkEndpointInner + LinkEndpointInnerReattach + Send + Sync,
+    T::Link: LinkDetach<DetachError = DetachError>,
+    <T::Link as LinkAttach>::AttachError: From<AllocLinkError> + Sync,
+{
+    match link_inner.link().local_state() {
+        LinkState::Unattached
+        | LinkState::AttachSent
+        | LinkState::IncompleteAttachSent
+        | LinkState::IncompleteAttachReceived
+        | LinkState::IncompleteAttachExchanged
+        | LinkState::AttachReceived
+        | LinkState::Attached => {
+            // Send a non-closing detach
+            link_inner.send_detach(false, error).await?;
+
+            let remote_detach = recv_remote_detach(link_inner).await?;
+            if remote_detach.closed {
+                // Note that one peer MAY send a closing detach while its partner is
+                // sending a non-closing detach. In this case, the partner MUST
+                // signal that it has closed the link by reattaching and then sending
+                // a closing detach.
+                reattach_and_then_close(link_inner).await?;
+
+                // A peer closes a link by sending the detach frame with the handle for the
+                // specified link, and the closed flag set to true. The partner will destroy
+                // the corresponding link endpoint, and reply with its own detach frame with
+                // the closed flag set to true.
+                Err(DetachError::ClosedByRemote)
+            } else {
+                link_inner.link_mut().on_incoming_detach(remote_detach)
             }
-            LinkState::DetachReceived => self
-                .send_detach(true, error)
-                .await // cancel safe
-                .map_err(|_| DetachError::IllegalSessionState),
-            LinkState::Detached => reattach_and_then_close(self).await, // FIXME: cancel safe? if oneshot channel is cancel safe
-            LinkState::CloseSent => {
-                // Wait for remote detach
-                let remote_detach = recv_remote_detach(self).await?; // cancel safe
-                if remote_detach.closed {
-                    self.link_mut().on_incoming_detach(remote_detach)
-                } else {
-                    reattach_and_then_close(self).await // FIXME: cancel safe? if oneshot channel is cancel safe
-                }
+        }
+        LinkState::DetachSent => {
+            let remote_detach = recv_remote_detach(link_inner).await?;
+            if remote_detach.closed {
+                reattach_and_then_close(link_inner).await?;
+                Err(DetachError::ClosedByRemote)
+            } else {
+                link_inner.link_mut().on_incoming_detach(remote_detach)
             }
-            LinkState::CloseReceived => self
+        }
+        LinkState::DetachReceived => link_inner.send_detach(false, error).await,
+        LinkState::Detached => Ok(()),
+        LinkState::CloseSent => {
+            // This should be impossible.
+            // FIXME: treat it as if remote closed
+            let _remote_detach = recv_remote_detach(link_inner).await?;
+            reattach_and_then_close(link_inner).await?;
+            Err(DetachError::ClosedByRemote)
+        }
+        LinkState::CloseReceived => {
+            link_inner.send_detach(true, error).await?;
+            Err(DetachError::ClosedByRemote)
+        }
+        LinkState::Closed => Err(DetachError::ClosedByRemote),
+    }
+}
+
+/// # Cancel safety
+///
+/// This should be cancel safe if oneshot channel is cancel safe
+async fn close_inner<T>(
+    link_inner: &mut T,
+    error: Option<definitions::Error>,
+) -> Result<(), DetachError>
+where
+    T: LinkEndpointInner + LinkEndpointInnerReattach + Send + Sync,
+    T::Link: LinkDetach<DetachError = DetachError>,
+    <T::Link as LinkAttach>::AttachError: From<AllocLinkError> + Sync,
+{
+    match link_inner.link().local_state() {
+        LinkState::Unattached
+        | LinkState::AttachSent
+        | LinkState::IncompleteAttachSent
+        | LinkState::IncompleteAttachReceived
+        | LinkState::IncompleteAttachExchanged
+        | LinkState::AttachReceived
+        | LinkState::Attached => {
+            // Send detach with closed=true and wait for remote closing detach
+            // The sender will be dropped after close
+            link_inner
                 .send_detach(true, error)
                 .await // cancel safe
-                .map_err(|_| DetachError::IllegalSessionState),
-            LinkState::Closed => Ok(()),
+                .map_err(|_| DetachError::IllegalSessionState)?;
+
+            // Wait for remote detach
+            let remote_detach = recv_remote_detach(link_inner).await?; // cancel safe
+            if remote_detach.closed {
+                // If the remote detach contains an error, the error will be propagated
+                // back by `on_incoming_detach`
+                link_inner.link_mut().on_incoming_detach(remote_detach)
+            } else {
+                reattach_and_then_close(link_inner).await // FIXME: cancel safe? if oneshot channel is cancel safe
+            }
+        }
+        LinkState::DetachSent => {
+            // FIXME: this should be impossible
+            // Wait for remote detach
+            let _remote_detach = recv_remote_detach(link_inner).await?; // cancel safe
+            reattach_and_then_close(link_inner).await?; // FIXME: cancel safe? if oneshot channel is cancel safe
+            Err(DetachError::DetachedByRemote)
         }
+        LinkState::DetachReceived => link_inner
+            .send_detach(true, error)
+            .await // cancel safe
+            .map_err(|_| DetachError::IllegalSessionState),
+        LinkState::Detached => reattach_and_then_close(link_inner).await, // FIXME: cancel safe? if oneshot channel is cancel safe
+        LinkState::CloseSent => {
+            // Wait for remote detach
+            let remote_detach = recv_remote_detach(link_inner).await?; // cancel safe
+            if remote_detach.closed {
+                link_inner.link_mut().on_incoming_detach(remote_detach)
+            } else {
+                reattach_and_then_close(link_inner).await // FIXME: cancel safe? if oneshot channel is cancel safe
+            }
+        }
+        LinkState::CloseReceived => link_inner
+            .send_detach(true, error)
+            .await // cancel safe
+            .map_err(|_| DetachError::IllegalSessionState),
+        LinkState::Closed => Ok(()),
+    }
+}
+
+/// Detach or close the link locally after the remote peer failed to reply with a Detach in time,
+/// and remove the link from the session
+async fn abandon<T>(link_inner: &mut T, closed: bool)
+where
+    T: LinkEndpointInner + Send + Sync,
+    <T::Link as LinkAttach>::AttachError: From<AllocLinkError> + Send + Sync,
+{
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        link = link_inner.link().name(),
+        "Timed out waiting for remote detach"
+    );
+    #[cfg(feature = "log")]
+    log::warn!(
+        "Timed out waiting for remote detach on link {}",
+        link_inner.link().name()
+    );
+
+    if let Some(input_handle) = link_inner.link_mut().abandon(closed) {
+        // An error means the session has already stopped
+        let _ = link_inner
+            .session_control()
+            .send(SessionControl::AbandonLink(input_handle))
+            .await;
     }
 }
 
@@ -278,6 +367,9 @@ where
     T::Link: LinkDetach<DetachError = DetachError>,
     <T::Link as LinkAttach>::AttachError: From<AllocLinkError> + Sync,
 {
+    if let Some(detach) = link_inner.take_prefetched_detach() {
+        return Ok(detach);
+    }
     loop {
         match link_inner
             .reader_mut()
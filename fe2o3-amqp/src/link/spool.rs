@@ -0,0 +1,332 @@
+//! Spooling of the large incoming deliveries to disk
+//!
+//! A broker may deliver a message that is much larger than the application expects, and a
+//! multi-frame delivery is normally buffered in memory until its last transfer arrives. With
+//! [`Spooling`] enabled on a receiver, the payload of a multi-frame delivery is written to a
+//! temporary file once it exceeds the threshold, and the delivery is received with
+//! [`Receiver::recv_spooled()`](super::Receiver::recv_spooled) as a [`DeliveryBody::File`].
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let mut receiver = Receiver::builder()
+//!     .name("rust-receiver-link-1")
+//!     .source("q1")
+//!     .spooling(Spooling::new(4 * 1024 * 1024))
+//!     .attach(&mut session)
+//!     .await
+//!     .unwrap();
+//!
+//! let delivery: Delivery<DeliveryBody<Body<Value>>> = receiver.recv_spooled().await.unwrap();
+//! match delivery.body() {
+//!     DeliveryBody::Memory(body) => println!("{:?}", body),
+//!     DeliveryBody::File(spooled) => println!("{} bytes in {:?}", spooled.len(), spooled.path()),
+//! }
+//! receiver.accept(&delivery).await.unwrap();
+//! ```
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use fe2o3_amqp_types::{
+    definitions::MessageFormat,
+    messaging::{message::DecodeIntoMessage, FromBody, Message},
+};
+
+use crate::{connection::random_hex, Payload};
+
+use super::{
+    message_format::{DecodeDelivery, DeliveryPayload, MessageFormatRegistry},
+    ReceiverTransferError,
+};
+
+/// Spools the multi-frame deliveries that exceed a size threshold to disk
+///
+/// The transfers of a multi-frame delivery are buffered in memory until more than `threshold`
+/// bytes are buffered. The buffered payload and the following transfers are then written to a
+/// temporary file in `dir` (or [`std::env::temp_dir()`] if `dir` is `None`). Single-frame
+/// deliveries are never spooled, as they are bounded by the `max-frame-size` of the connection.
+///
+/// A spooled delivery is not checked for an empty body. Only
+/// [`Receiver::recv_spooled()`](super::Receiver::recv_spooled) keeps the delivery on disk; the
+/// other `recv` methods read the spooled file back into memory to decode the message. If the
+/// receiver verifies checksums or decompresses messages, `recv_spooled()` also reads the spooled
+/// file back to verify and decompress it, and a decompressed message is spooled to a new file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spooling {
+    /// Number of buffered bytes above which a delivery is spooled
+    pub threshold: usize,
+
+    /// Directory of the spool files
+    pub dir: Option<PathBuf>,
+}
+
+impl Spooling {
+    /// Spools the deliveries that exceed `threshold` bytes to the temporary directory
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            dir: None,
+        }
+    }
+
+    /// Writes the spool files to `dir` instead of the temporary directory
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+}
+
+/// Body of a delivery received with [`Receiver::recv_spooled()`](super::Receiver::recv_spooled)
+#[derive(Debug)]
+pub enum DeliveryBody<B> {
+    /// The delivery was buffered in memory and decoded as usual
+    Memory(B),
+
+    /// The delivery was spooled to disk. The other sections of the message are left empty, as
+    /// the file holds the whole encoded message (see [`SpooledBody`])
+    File(SpooledBody),
+}
+
+impl<B> DeliveryBody<B> {
+    /// Whether the delivery was spooled to disk
+    pub fn is_file(&self) -> bool {
+        matches!(self, DeliveryBody::File(_))
+    }
+}
+
+/// An encoded message that is spooled to a file
+///
+/// The file holds all the sections of the message as they were received. It is removed when the
+/// `SpooledBody` is dropped, unless it is moved elsewhere with [`SpooledBody::persist()`].
+#[derive(Debug)]
+pub struct SpooledBody {
+    path: PathBuf,
+    len: u64,
+    persisted: bool,
+}
+
+impl SpooledBody {
+    /// Path of the spool file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Size of the encoded message in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the encoded message is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Opens the spool file to stream the encoded message
+    pub fn open(&self) -> io::Result<File> {
+        File::open(&self.path)
+    }
+
+    /// Reads the spool file back into memory and decodes the message. A message that cannot be
+    /// decoded is returned as an error of kind [`io::ErrorKind::InvalidData`]
+    pub fn decode<T>(&self) -> io::Result<Message<T>>
+    where
+        for<'de> T: FromBody<'de>,
+    {
+        let file = self.open()?;
+        T::decode_into_message(BufReader::new(file))
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /// Reads the spool file back into memory without blocking the runtime
+    pub(crate) async fn read_payload(&self) -> io::Result<Payload> {
+        let bytes = tokio::fs::read(&self.path).await?;
+        Ok(Payload::from(bytes))
+    }
+
+    /// Moves the spool file to `path` so that it is kept after the `SpooledBody` is dropped
+    pub fn persist(mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::rename(&self.path, path)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for SpooledBody {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl DeliveryPayload for SpooledBody {
+    fn decode<T: DecodeDelivery>(
+        self,
+        registry: &MessageFormatRegistry,
+        message_format: Option<MessageFormat>,
+    ) -> Result<Message<T>, ReceiverTransferError> {
+        T::decode_spooled(registry, message_format, self)
+    }
+}
+
+impl<B> DecodeDelivery for DeliveryBody<B>
+where
+    for<'de> B: FromBody<'de>,
+{
+    const KEEPS_SPOOLED: bool = true;
+
+    fn decode_delivery(
+        _registry: &MessageFormatRegistry,
+        _message_format: Option<MessageFormat>,
+        reader: impl io::Read,
+    ) -> Result<Message<Self>, ReceiverTransferError> {
+        let message = B::decode_into_message(reader)
+            .map_err(|_| ReceiverTransferError::MessageDecodeError)?;
+        Ok(Message {
+            header: message.header,
+            delivery_annotations: message.delivery_annotations,
+            message_annotations: message.message_annotations,
+            properties: message.properties,
+            application_properties: message.application_properties,
+            body: DeliveryBody::Memory(message.body),
+            footer: message.footer,
+        })
+    }
+
+    fn decode_spooled(
+        _registry: &MessageFormatRegistry,
+        _message_format: Option<MessageFormat>,
+        spooled: SpooledBody,
+    ) -> Result<Message<Self>, ReceiverTransferError> {
+        Ok(Message {
+            header: None,
+            delivery_annotations: None,
+            message_annotations: None,
+            properties: None,
+            application_properties: None,
+            body: DeliveryBody::File(spooled),
+            footer: None,
+        })
+    }
+}
+
+/// The spool file of a multi-frame delivery that is being received
+///
+/// The file is written by a blocking task so that the disk does not block the runtime. At most
+/// [`SPOOL_CHANNEL_CAPACITY`] chunks wait for the task, so that a slow disk holds back the reading
+/// of the link instead of piling up the payload in memory. A failed write is reported by
+/// [`finish()`](Spool::finish).
+#[derive(Debug)]
+pub(crate) struct Spool {
+    chunks: Option<mpsc::Sender<Payload>>,
+    writer: JoinHandle<io::Result<SpooledBody>>,
+}
+
+/// Number of chunks that may wait for the disk
+const SPOOL_CHANNEL_CAPACITY: usize = 16;
+
+impl Spool {
+    pub(crate) fn create(config: &Spooling) -> Self {
+        let dir = config.dir.clone().unwrap_or_else(std::env::temp_dir);
+        let path = dir.join(format!(
+            "fe2o3-amqp-spool-{}-{}",
+            std::process::id(),
+            random_hex(16)
+        ));
+        let (chunks, mut rx) = mpsc::channel::<Payload>(SPOOL_CHANNEL_CAPACITY);
+        let writer = tokio::task::spawn_blocking(move || {
+            let file = File::options().write(true).create_new(true).open(&path)?;
+            // The file is removed if writing fails
+            let mut body = SpooledBody {
+                path,
+                len: 0,
+                persisted: false,
+            };
+            let mut writer = BufWriter::new(file);
+            while let Some(chunk) = rx.blocking_recv() {
+                writer.write_all(&chunk)?;
+                body.len += chunk.len() as u64;
+            }
+            writer.flush()?;
+            Ok(body)
+        });
+        Self {
+            chunks: Some(chunks),
+            writer,
+        }
+    }
+
+    /// Moves the chunks from the front of `buffer` to the file
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe because a chunk is only taken off `buffer` once there is room for it
+    pub(crate) async fn write_from(&mut self, buffer: &mut Vec<Payload>) {
+        let chunks = match &self.chunks {
+            Some(chunks) => chunks,
+            None => return,
+        };
+        while !buffer.is_empty() {
+            match chunks.reserve().await {
+                Ok(permit) => permit.send(buffer.remove(0)),
+                // The writer only stops early on an error, which is returned by `finish()`
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Moves the rest of `buffer` to the file and closes it
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe, and the spool must be dropped once this returns
+    pub(crate) async fn finish(&mut self, buffer: &mut Vec<Payload>) -> io::Result<SpooledBody> {
+        self.write_from(buffer).await; // cancel safe
+        self.chunks = None;
+        (&mut self.writer).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::{
+        messaging::{message::__private::Serializable, AmqpValue, Body, Message},
+        primitives::Value,
+    };
+
+    use crate::Payload;
+
+    use super::{Spool, Spooling};
+
+    #[tokio::test]
+    async fn spooled_message_is_decoded_and_removed_on_drop() {
+        let message = Message::builder().value("large message").build();
+        let encoded = serde_amqp::to_vec(&Serializable(message)).unwrap();
+        let (first, second) = encoded.split_at(encoded.len() / 2);
+
+        let mut spool = Spool::create(&Spooling::new(0));
+        let mut buffer = vec![Payload::copy_from_slice(first)];
+        spool.write_from(&mut buffer).await;
+        assert!(buffer.is_empty());
+        buffer.push(Payload::copy_from_slice(second));
+        let spooled = spool.finish(&mut buffer).await.unwrap();
+        assert_eq!(spooled.len(), encoded.len() as u64);
+
+        let decoded = spooled.decode::<Body<Value>>().unwrap();
+        assert_eq!(
+            decoded.body,
+            Body::Value(AmqpValue(Value::from("large message")))
+        );
+
+        let path = spooled.path().to_path_buf();
+        assert!(path.exists());
+        drop(spooled);
+        assert!(!path.exists());
+    }
+}
@@ -3,19 +3,29 @@
 use std::{marker::PhantomData, sync::Arc};
 
 use fe2o3_amqp_types::definitions::{Fields, SequenceNo};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::{oneshot, watch};
 
 use crate::{
     endpoint::{LinkFlow, OutputHandle},
     util::{Consume, ProducerState, TryConsume},
 };
 
-use super::{role, ReceiverTransferError, SenderFlowState, SenderTryConsumeError};
+use super::{
+    byte_credit::{self, ByteCredit},
+    role, ReceiverTransferError, SenderFlowState, SenderTryConsumeError,
+};
+
+cfg_transaction! {
+    use fe2o3_amqp_types::transaction::TransactionId;
+
+    use crate::transaction::TXN_ID_KEY;
+}
 
 /// Link state.
 ///
 /// There is no official definition of the link state in the specification
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum LinkState {
     /// The initial state after initialization
     Unattached,
@@ -81,10 +91,54 @@ impl LinkFlowStateInner {
     }
 }
 
+/// The flow state of the remote link endpoint as carried by the latest Flow frame received
+/// from the remote peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteFlowState {
+    /// The remote endpoint's value for the delivery-count sequence number
+    pub delivery_count: Option<SequenceNo>,
+
+    /// The remote endpoint's value for the link-credit
+    pub link_credit: Option<u32>,
+
+    /// The number of messages awaiting credit at the sender
+    pub available: Option<u32>,
+
+    /// The remote endpoint's drain mode
+    pub drain: bool,
+
+    /// Link state properties
+    pub properties: Option<Fields>,
+}
+
+impl From<&LinkFlow> for RemoteFlowState {
+    fn from(flow: &LinkFlow) -> Self {
+        Self {
+            delivery_count: flow.delivery_count,
+            link_credit: flow.link_credit,
+            available: flow.available,
+            drain: flow.drain,
+            properties: flow.properties.clone(),
+        }
+    }
+}
+
 /// The Sender and Receiver handle link flow control differently
 #[derive(Debug)]
 pub(crate) struct LinkFlowState<R> {
     pub(crate) lock: RwLock<LinkFlowStateInner>,
+    // Waiting for the next Flow from the remote peer after a Flow with `echo` is sent
+    echo_waiters: Mutex<Vec<oneshot::Sender<RemoteFlowState>>>,
+    // The properties carried by the last Flow from the remote peer
+    remote_properties: watch::Sender<Option<Fields>>,
+    // The byte limit granted by the remote receiver and the payload bytes sent so far
+    byte_credit: Mutex<ByteCredit>,
+    // The `available` to restore on resume while the sender is paused
+    paused: Mutex<Option<u32>>,
+    // The txn-id carried in the properties of the last Flow from a transactionally acquiring
+    // receiver
+    #[cfg(feature = "transaction")]
+    acquisition: Mutex<Option<TransactionId>>,
     role: PhantomData<R>,
 }
 
@@ -92,15 +146,118 @@ impl<R> LinkFlowState<R> {
     pub(crate) fn new(inner: LinkFlowStateInner) -> Self {
         Self {
             lock: RwLock::new(inner),
+            echo_waiters: Mutex::new(Vec::new()),
+            remote_properties: watch::channel(None).0,
+            byte_credit: Mutex::new(ByteCredit::default()),
+            paused: Mutex::new(None),
+            #[cfg(feature = "transaction")]
+            acquisition: Mutex::new(None),
             role: PhantomData,
         }
     }
+
+    /// Registers a waiter that is resolved with the next Flow received from the remote peer.
+    ///
+    /// This must be called before the Flow with `echo` is sent so that the reply cannot be missed
+    pub(crate) fn wait_for_remote_flow(&self) -> oneshot::Receiver<RemoteFlowState> {
+        let (tx, rx) = oneshot::channel();
+        let mut waiters = self.echo_waiters.lock();
+        // Waiters whose request has been cancelled are no longer relevant
+        waiters.retain(|waiter| !waiter.is_closed());
+        waiters.push(tx);
+        rx
+    }
+
+    /// The properties carried by the last Flow from the remote peer
+    pub(crate) fn remote_properties(&self) -> Option<Fields> {
+        self.remote_properties.borrow().clone()
+    }
+
+    /// Watches the properties carried by the Flows from the remote peer. The receiver is only
+    /// notified when the properties change
+    pub(crate) fn watch_remote_properties(&self) -> watch::Receiver<Option<Fields>> {
+        self.remote_properties.subscribe()
+    }
+
+    fn update_remote_properties(&self, flow: &LinkFlow) {
+        self.remote_properties.send_if_modified(|properties| {
+            if *properties == flow.properties {
+                return false;
+            }
+            properties.clone_from(&flow.properties);
+            true
+        });
+    }
+
+    fn notify_echo_waiters(&self, flow: &LinkFlow) {
+        let mut waiters = self.echo_waiters.lock();
+        if waiters.is_empty() {
+            return;
+        }
+        let remote = RemoteFlowState::from(flow);
+        for waiter in waiters.drain(..) {
+            let _ = waiter.send(remote.clone());
+        }
+    }
 }
 
 impl LinkFlowState<role::SenderMarker> {
     pub(crate) fn sender(inner: LinkFlowStateInner) -> Self {
         Self::new(inner)
     }
+
+    /// The transaction that the remote receiver is acquiring messages with, if any
+    #[cfg(feature = "transaction")]
+    pub(crate) fn acquisition(&self) -> Option<TransactionId> {
+        self.acquisition.lock().clone()
+    }
+
+    /// Takes the byte limit from the properties of the remote Attach or Flow. The last limit is
+    /// kept if the properties do not carry one
+    pub(crate) fn update_byte_limit(&self, properties: Option<&Fields>) {
+        if let Some(limit) = byte_credit::byte_limit(properties) {
+            self.byte_credit.lock().set_limit(limit);
+        }
+    }
+
+    /// The payload bytes that may still be sent, or `None` if the remote receiver does not limit
+    /// the bytes
+    pub(crate) fn byte_credit(&self) -> Option<u64> {
+        self.byte_credit.lock().remaining()
+    }
+
+    pub(crate) fn on_payload_sent(&self, len: usize) {
+        self.byte_credit.lock().on_sent(len as u64);
+    }
+
+    /// Stops the consumption of link credit and sets `available` to zero. Returns `false` if the
+    /// sender is already paused
+    pub(crate) fn pause(&self) -> bool {
+        let mut paused = self.paused.lock();
+        if paused.is_some() {
+            return false;
+        }
+        let mut state = self.lock.write();
+        *paused = Some(state.available);
+        state.available = 0;
+        true
+    }
+
+    /// Restores `available` to its value before the pause. Returns `false` if the sender is not
+    /// paused
+    pub(crate) fn resume(&self) -> bool {
+        match self.paused.lock().take() {
+            Some(available) => {
+                self.lock.write().available = available;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused.lock().is_some()
+    }
 }
 
 impl LinkFlowState<role::ReceiverMarker> {
@@ -120,6 +277,21 @@ impl LinkFlowState<role::SenderMarker> {
         flow: LinkFlow,
         output_handle: OutputHandle,
     ) -> Option<LinkFlow> {
+        self.notify_echo_waiters(&flow);
+        self.update_remote_properties(&flow);
+        self.update_byte_limit(flow.properties.as_ref());
+
+        // The acquisition lasts until the receiver sends a Flow without the txn-id
+        #[cfg(feature = "transaction")]
+        {
+            let txn_id = flow
+                .properties
+                .as_ref()
+                .and_then(|m| m.get(TXN_ID_KEY))
+                .and_then(|value| TransactionId::try_from(value).ok());
+            *self.acquisition.lock() = txn_id;
+        }
+
         let mut state = self.lock.write();
 
         // delivery count
@@ -185,6 +357,8 @@ impl LinkFlowState<role::ReceiverMarker> {
         flow: LinkFlow,
         output_handle: OutputHandle,
     ) -> Option<LinkFlow> {
+        self.notify_echo_waiters(&flow);
+        self.update_remote_properties(&flow);
         let mut state = self.lock.write();
 
         // delivery count
@@ -243,6 +417,10 @@ impl<R> LinkFlowState<R> {
         self.lock.read().initial_delivery_count
     }
 
+    pub fn delivery_count(&self) -> SequenceNo {
+        self.lock.read().delivery_count
+    }
+
     pub fn initial_delivery_count_mut(&self, f: impl Fn(u32) -> u32) {
         let mut guard = self.lock.write();
         let new = f(guard.initial_delivery_count);
@@ -255,6 +433,16 @@ impl<R> LinkFlowState<R> {
         guard.delivery_count = new;
     }
 
+    pub fn available(&self) -> u32 {
+        self.lock.read().available
+    }
+
+    pub fn available_mut(&self, f: impl Fn(u32) -> u32) {
+        let mut guard = self.lock.write();
+        let new = f(guard.available);
+        guard.available = new;
+    }
+
     /// This is async because it is protected behind an async RwLock
     pub fn properties(&self) -> Option<Fields> {
         self.lock.read().properties.clone()
@@ -303,6 +491,12 @@ impl Consume for SenderFlowState {
     /// does not have any effect. Thus, this IS cancel safe.
     async fn consume(&mut self, item: Self::Item) -> Self::Outcome {
         loop {
+            // The byte limit is raised by a Flow just like the link credit, and a paused sender
+            // is woken up on resume
+            if self.state().is_paused() || !self.state().byte_credit.lock().is_available() {
+                self.notifier.notified().await; // **NOT** cancel safe
+                continue;
+            }
             match consume_link_credit(&self.state().lock, item) {
                 Ok(outcome) => return outcome,
                 Err(_) => self.notifier.notified().await, // **NOT** cancel safe
@@ -315,6 +509,9 @@ impl TryConsume for SenderFlowState {
     type Error = SenderTryConsumeError;
 
     fn try_consume(&mut self, item: Self::Item) -> Result<Self::Outcome, Self::Error> {
+        if self.state().is_paused() || !self.state().byte_credit.lock().is_available() {
+            return Err(Self::Error::InsufficientCredit);
+        }
         let mut state = self
             .state()
             .lock
@@ -326,11 +523,52 @@ impl TryConsume for SenderFlowState {
             let tag = state.delivery_count.to_be_bytes();
             state.delivery_count = state.delivery_count.wrapping_add(item);
             state.link_credit = state.link_credit.saturating_sub(item);
+            state.available = state.available.saturating_sub(item);
             Ok(tag)
         }
     }
 }
 
+impl SenderFlowState {
+    /// Like [`Consume::consume`] but takes the link credit of the messages with the given payload
+    /// sizes while holding the lock once. The messages are cut at the first one that would start
+    /// once the byte limit is reached, see [`byte_credit`](super::byte_credit). Waits
+    /// asynchronously until there is some credit, and returns the tag of the first message along
+    /// with the number of messages the credit is taken for
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe. The credit is only taken once the wait has completed, and a lost
+    /// place in the queue of the `Notify` has no effect because there is only one consumer.
+    pub(crate) async fn consume_up_to(&mut self, payload_lens: &[usize]) -> ([u8; 4], u32) {
+        debug_assert!(!payload_lens.is_empty());
+        loop {
+            let by_bytes = self
+                .state()
+                .byte_credit
+                .lock()
+                .deliveries_available(payload_lens);
+            if self.state().is_paused() || by_bytes == 0 {
+                self.notifier.notified().await; // cancel safe
+                continue;
+            }
+            {
+                let max = u32::try_from(by_bytes).unwrap_or(u32::MAX);
+                let mut state = self.state().lock.write();
+                let count = state.link_credit.min(max);
+                if count > 0 {
+                    let tag = state.delivery_count.to_be_bytes();
+                    state.delivery_count = state.delivery_count.wrapping_add(count);
+                    state.link_credit -= count;
+                    state.available = state.available.saturating_sub(count);
+                    return (tag, count);
+                }
+            }
+            self.notifier.notified().await; // cancel safe
+        }
+    }
+}
+
 fn consume_link_credit(
     lock: &RwLock<LinkFlowStateInner>,
     count: u32,
@@ -343,6 +581,8 @@ fn consume_link_credit(
         let tag = state.delivery_count.to_be_bytes();
         state.delivery_count = state.delivery_count.wrapping_add(count);
         state.link_credit = state.link_credit.saturating_sub(count);
+        // The receiver decrements its value of available for every incoming message as well
+        state.available = state.available.saturating_sub(count);
         Ok(tag)
     }
 }
@@ -497,4 +737,43 @@ mod tests {
         // All credits have been consumed already
         assert_pending!(consumer.consume(1));
     }
+
+    #[cfg(feature = "transaction")]
+    #[test]
+    fn test_sender_flow_state_tracks_acquisition() {
+        use fe2o3_amqp_types::{definitions::Fields, transaction::TransactionId};
+        use serde_amqp::{primitives::Symbol, Value};
+
+        use crate::transaction::TXN_ID_KEY;
+
+        let flow_state = LinkFlowState::sender(LinkFlowStateInner {
+            initial_delivery_count: 0,
+            delivery_count: 0,
+            link_credit: 0,
+            available: 0,
+            drain: false,
+            properties: None,
+        });
+        assert!(flow_state.acquisition().is_none());
+
+        let txn_id = TransactionId::from(vec![1, 2, 3]);
+        let mut properties = Fields::new();
+        properties.insert(Symbol::from(TXN_ID_KEY), Value::from(txn_id.clone()));
+        let link_flow = LinkFlow {
+            link_credit: Some(2),
+            properties: Some(properties),
+            ..Default::default()
+        };
+        flow_state.on_incoming_flow(link_flow, OutputHandle(0));
+        assert_eq!(flow_state.acquisition(), Some(txn_id));
+
+        // The receiver clears the txn-id and drains when the acquisition ends
+        let link_flow = LinkFlow {
+            link_credit: Some(0),
+            drain: true,
+            ..Default::default()
+        };
+        flow_state.on_incoming_flow(link_flow, OutputHandle(0));
+        assert!(flow_state.acquisition().is_none());
+    }
 }
@@ -0,0 +1,164 @@
+//! A [`Stream`] of the deliveries of a [`Receiver`]
+
+use std::{
+    fmt,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use fe2o3_amqp_types::messaging::FromBody;
+use futures_util::{future::BoxFuture, stream::FusedStream, FutureExt, Stream};
+use tokio::sync::oneshot;
+
+use super::{delivery::Delivery, Receiver, RecvError};
+
+type Received<T> = Option<Result<Delivery<T>, RecvError>>;
+
+/// A receive that owns the receiver until it completes or is told to stop
+struct InFlight<T> {
+    fut: BoxFuture<'static, (Receiver, Received<T>)>,
+    stop: oneshot::Sender<()>,
+}
+
+/// A [`Stream`] that yields the deliveries of a [`Receiver`]. See [`Receiver::into_stream()`]
+///
+/// A message is only received while the stream is polled, so a consumer that stops polling
+/// stops taking messages off the link, and the link credit is topped up by the receiver
+/// according to its [`CreditMode`](super::receiver::CreditMode) just like with
+/// [`Receiver::recv()`]. A receive that is in progress is kept across polls, so the stream can
+/// be polled in a `select!` loop without losing any message.
+///
+/// The stream ends after yielding a [`RecvError::LinkStateError`], ie. once the link is detached
+/// or closed. The other errors only concern a single delivery.
+///
+/// # Disposition
+///
+/// The receiver is owned by the stream, and is borrowed back with
+/// [`receiver_mut()`](#method.receiver_mut) to dispose the deliveries
+///
+/// ```rust,ignore
+/// use futures_util::StreamExt;
+///
+/// let mut stream = receiver.into_stream::<String>();
+/// while let Some(delivery) = stream.next().await {
+///     let delivery = delivery.unwrap();
+///     stream.receiver_mut().await.accept(&delivery).await.unwrap();
+/// }
+/// ```
+pub struct ReceiverStream<T> {
+    receiver: Option<Receiver>,
+    in_flight: Option<InFlight<T>>,
+    terminated: bool,
+}
+
+impl<T> fmt::Debug for ReceiverStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReceiverStream")
+            .field("receiver", &self.receiver)
+            .field("in_flight", &self.in_flight.is_some())
+            .field("terminated", &self.terminated)
+            .finish()
+    }
+}
+
+impl<T> ReceiverStream<T>
+where
+    for<'de> T: FromBody<'de> + Send + Sync + 'static,
+{
+    /// Wraps the receiver into a stream
+    pub fn new(receiver: Receiver) -> Self {
+        Self {
+            receiver: Some(receiver),
+            in_flight: None,
+            terminated: false,
+        }
+    }
+
+    /// Get a mutable reference to the receiver, eg. to dispose a delivery or to set the credit
+    ///
+    /// A receive that is in progress is stopped first, which does not lose any message because
+    /// [`Receiver::recv()`] is cancel-safe.
+    pub async fn receiver_mut(&mut self) -> &mut Receiver {
+        if let Some(InFlight { fut, stop }) = self.in_flight.take() {
+            let _ = stop.send(());
+            let (receiver, _stopped) = fut.await;
+            self.receiver = Some(receiver);
+        }
+        self.receiver
+            .as_mut()
+            .expect("The receiver is owned by the stream unless a receive is in progress")
+    }
+
+    /// Stops the receive that is in progress, if any, and returns the receiver
+    pub async fn into_inner(mut self) -> Receiver {
+        self.receiver_mut().await;
+        self.receiver
+            .take()
+            .expect("The receiver is returned by receiver_mut()")
+    }
+}
+
+impl<T> Stream for ReceiverStream<T>
+where
+    for<'de> T: FromBody<'de> + Send + Sync + 'static,
+{
+    type Item = Result<Delivery<T>, RecvError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.terminated {
+            return Poll::Ready(None);
+        }
+
+        let in_flight = match &mut self.in_flight {
+            Some(in_flight) => in_flight,
+            None => {
+                let receiver = self
+                    .receiver
+                    .take()
+                    .expect("The receiver is owned by the stream unless a receive is in progress");
+                let (stop, stopped) = oneshot::channel();
+                self.in_flight.insert(InFlight {
+                    fut: recv_until_stopped(receiver, stopped).boxed(),
+                    stop,
+                })
+            }
+        };
+
+        let (receiver, received) = match in_flight.fut.poll_unpin(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.in_flight = None;
+        self.receiver = Some(receiver);
+        if let Some(Err(RecvError::LinkStateError(_))) = &received {
+            self.terminated = true;
+        }
+        Poll::Ready(received)
+    }
+}
+
+impl<T> FusedStream for ReceiverStream<T>
+where
+    for<'de> T: FromBody<'de> + Send + Sync + 'static,
+{
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
+
+async fn recv_until_stopped<T>(
+    mut receiver: Receiver,
+    stopped: oneshot::Receiver<()>,
+) -> (Receiver, Received<T>)
+where
+    for<'de> T: FromBody<'de> + Send,
+{
+    let received = tokio::select! {
+        // The stop request wins so that no delivery is received once the receiver is reclaimed
+        biased;
+        _ = stopped => None,
+        // `Receiver::recv` is cancel safe
+        result = receiver.recv::<T>() => Some(result),
+    };
+    (receiver, received)
+}
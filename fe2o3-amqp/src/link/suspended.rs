@@ -0,0 +1,124 @@
+//! Serializable state of a detached link
+//!
+//! A link that is detached without being closed keeps its terminus on the remote peer, and it can
+//! be resumed later by attaching a link with the same name. [`SuspendedLink`] captures what is
+//! needed to do that from another process (or after a restart), so it can be persisted with
+//! [`SuspendedLink::to_bytes()`] and fed to [`Builder::resume_from()`](super::builder::Builder::resume_from).
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let detached = receiver.detach().await.unwrap();
+//! std::fs::write("receiver.link", detached.to_suspended_link().to_bytes().unwrap()).unwrap();
+//!
+//! // After a restart
+//! let suspended = SuspendedLink::from_bytes(&std::fs::read("receiver.link").unwrap()).unwrap();
+//! let mut receiver = Receiver::builder()
+//!     .resume_from(suspended)
+//!     .attach(&mut session)
+//!     .await
+//!     .unwrap();
+//! ```
+
+use fe2o3_amqp_types::{
+    definitions::{DeliveryTag, Role, SequenceNo},
+    messaging::{DeliveryState, Source, Target},
+    primitives::OrderedMap,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{delivery::UnsettledMessage, UnsettledMap};
+
+/// State of a detached link that can be persisted and resumed later
+///
+/// The unsettled map of a sender only holds the delivery tags and states, as the payloads are not
+/// persisted. The deliveries listed in [`unsettled`](Self::unsettled) of a suspended sender are in
+/// doubt and should be sent again by the application after the sender is resumed. The unsettled
+/// map of a suspended receiver is restored on attach so that the remote sender can resume or
+/// settle these deliveries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuspendedLink {
+    /// Name of the link
+    pub name: String,
+
+    /// Role of the link
+    pub role: Role,
+
+    /// Source of the link
+    pub source: Option<Source>,
+
+    /// Target of the link
+    pub target: Option<Target>,
+
+    /// Delivery count of the link when it was detached
+    pub delivery_count: SequenceNo,
+
+    /// Tags and states of the deliveries that were unsettled when the link was detached
+    pub unsettled: OrderedMap<DeliveryTag, Option<DeliveryState>>,
+}
+
+impl SuspendedLink {
+    pub(crate) fn sender_unsettled(
+        map: Option<&UnsettledMap<UnsettledMessage>>,
+    ) -> OrderedMap<DeliveryTag, Option<DeliveryState>> {
+        map.into_iter()
+            .flat_map(|map| map.iter())
+            .map(|(tag, message)| (tag.clone(), message.state.clone()))
+            .collect()
+    }
+
+    /// Encodes the suspended link
+    pub fn to_bytes(&self) -> Result<Vec<u8>, serde_amqp::Error> {
+        serde_amqp::to_vec(self)
+    }
+
+    /// Decodes a suspended link encoded with [`SuspendedLink::to_bytes()`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, serde_amqp::Error> {
+        serde_amqp::from_slice(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::{
+        definitions::{DeliveryTag, Role},
+        messaging::{Accepted, DeliveryState, Source, Target},
+        primitives::OrderedMap,
+    };
+
+    use super::SuspendedLink;
+
+    #[test]
+    fn suspended_link_round_trips_through_bytes() {
+        let mut unsettled = OrderedMap::new();
+        unsettled.insert(DeliveryTag::from(vec![1]), None);
+        unsettled.insert(
+            DeliveryTag::from(vec![2]),
+            Some(DeliveryState::Accepted(Accepted {})),
+        );
+        let suspended = SuspendedLink {
+            name: "receiver".to_string(),
+            role: Role::Receiver,
+            source: Some(Source::builder().address("q1").build()),
+            target: Some(Target::builder().address("local").build()),
+            delivery_count: 7,
+            unsettled,
+        };
+
+        let decoded = SuspendedLink::from_bytes(&suspended.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.name, "receiver");
+        assert_eq!(decoded.role, Role::Receiver);
+        assert_eq!(decoded.source.unwrap().address.as_deref(), Some("q1"));
+        assert_eq!(decoded.target.unwrap().address.as_deref(), Some("local"));
+        assert_eq!(decoded.delivery_count, 7);
+        let tags: Vec<_> = decoded.unsettled.keys().cloned().collect();
+        assert_eq!(
+            tags,
+            [DeliveryTag::from(vec![1]), DeliveryTag::from(vec![2])]
+        );
+        assert!(matches!(
+            decoded.unsettled.get(&DeliveryTag::from(vec![2])),
+            Some(Some(DeliveryState::Accepted(_)))
+        ));
+    }
+}
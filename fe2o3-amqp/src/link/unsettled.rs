@@ -0,0 +1,167 @@
+//! Snapshots of the unsettled deliveries of a link
+
+use std::{collections::HashMap, time::Duration};
+
+use fe2o3_amqp_types::{definitions::DeliveryTag, messaging::DeliveryState};
+use tokio::time::Instant;
+
+use super::{delivery::UnsettledMessage, UnsettledMap};
+
+/// A delivery in the unsettled map of a link at the time of the snapshot
+///
+/// The snapshots are returned by [`Sender::unsettled_snapshot()`](super::Sender::unsettled_snapshot)
+/// and [`Receiver::unsettled_snapshot()`](super::Receiver::unsettled_snapshot) in the order the
+/// deliveries were added to the unsettled map, so a delivery that has been stuck for a long time
+/// can be spotted by its `age`.
+#[derive(Debug, Clone)]
+pub struct UnsettledDelivery {
+    /// Delivery tag of the delivery
+    pub delivery_tag: DeliveryTag,
+
+    /// Time since the delivery was sent or, on a receiver, since its first transfer arrived.
+    ///
+    /// This is `None` for a delivery that a receiver only knows from the unsettled map of a
+    /// resumed link
+    pub age: Option<Duration>,
+
+    /// Current state of the delivery
+    pub state: Option<DeliveryState>,
+
+    /// Size of the encoded message in bytes.
+    ///
+    /// This is `None` for a delivery that a receiver only knows from the unsettled map of a
+    /// resumed link
+    pub payload_size: Option<usize>,
+}
+
+impl UnsettledDelivery {
+    pub(crate) fn from_sender_map(
+        map: Option<&UnsettledMap<UnsettledMessage>>,
+        now: Instant,
+    ) -> Vec<Self> {
+        map.into_iter()
+            .flat_map(|map| map.iter())
+            .map(|(delivery_tag, message)| Self {
+                delivery_tag: delivery_tag.clone(),
+                age: Some(now.saturating_duration_since(message.created)),
+                state: message.state.clone(),
+                payload_size: Some(message.payload.len()),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct Arrival {
+    at: Instant,
+    payload_size: usize,
+}
+
+/// Arrival time and size of the incoming deliveries
+///
+/// The receiver's unsettled map only holds the delivery states and is also updated by the session
+/// (eg. when the remote sender settles a delivery), so the arrivals are kept aside and the ones
+/// that are no longer in the unsettled map are pruned lazily
+#[derive(Debug, Default)]
+pub(crate) struct UnsettledArrivals {
+    arrivals: HashMap<DeliveryTag, Arrival>,
+}
+
+impl UnsettledArrivals {
+    /// Minimum number of arrivals that are kept before the settled ones are pruned
+    const PRUNE_THRESHOLD: usize = 64;
+
+    /// Records the first transfer of a delivery. This replaces the arrival of a settled delivery
+    /// with the same tag
+    pub(crate) fn on_first_transfer(
+        &mut self,
+        delivery_tag: DeliveryTag,
+        payload_size: usize,
+        now: Instant,
+    ) {
+        self.arrivals.insert(
+            delivery_tag,
+            Arrival {
+                at: now,
+                payload_size,
+            },
+        );
+    }
+
+    /// Adds the payload of a continuation transfer to the size of the delivery
+    pub(crate) fn on_continuation(&mut self, delivery_tag: &DeliveryTag, payload_size: usize) {
+        if let Some(arrival) = self.arrivals.get_mut(delivery_tag) {
+            arrival.payload_size += payload_size;
+        }
+    }
+
+    /// Drops the arrivals of the deliveries that are no longer unsettled once there are more than
+    /// twice as many arrivals as unsettled deliveries
+    pub(crate) fn prune<M>(&mut self, map: Option<&UnsettledMap<M>>) {
+        let unsettled = map.map_or(0, |map| map.len());
+        if self.arrivals.len() <= Self::PRUNE_THRESHOLD.max(unsettled * 2) {
+            return;
+        }
+        self.arrivals
+            .retain(|tag, _| map.is_some_and(|map| map.contains_key(tag)));
+    }
+
+    pub(crate) fn snapshot(
+        &mut self,
+        map: Option<&UnsettledMap<Option<DeliveryState>>>,
+        now: Instant,
+    ) -> Vec<UnsettledDelivery> {
+        self.arrivals
+            .retain(|tag, _| map.is_some_and(|map| map.contains_key(tag)));
+        map.into_iter()
+            .flat_map(|map| map.iter())
+            .map(|(delivery_tag, state)| {
+                let arrival = self.arrivals.get(delivery_tag);
+                UnsettledDelivery {
+                    delivery_tag: delivery_tag.clone(),
+                    age: arrival.map(|arrival| now.saturating_duration_since(arrival.at)),
+                    state: state.clone(),
+                    payload_size: arrival.map(|arrival| arrival.payload_size),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fe2o3_amqp_types::{
+        definitions::DeliveryTag,
+        messaging::{Accepted, DeliveryState},
+        primitives::OrderedMap,
+    };
+    use tokio::time::Instant;
+
+    use super::UnsettledArrivals;
+
+    #[test]
+    fn arrivals_are_joined_with_the_unsettled_map() {
+        let start = Instant::now();
+        let tag = |b: u8| DeliveryTag::from(vec![b]);
+        let mut arrivals = UnsettledArrivals::default();
+        arrivals.on_first_transfer(tag(1), 10, start);
+        arrivals.on_continuation(&tag(1), 5);
+        arrivals.on_first_transfer(tag(2), 7, start);
+
+        // Delivery 2 is settled, and delivery 3 is restored from a resumed link
+        let mut map = OrderedMap::new();
+        map.insert(tag(3), None);
+        map.insert(tag(1), Some(DeliveryState::Accepted(Accepted {})));
+
+        let snapshot = arrivals.snapshot(Some(&map), start + Duration::from_secs(2));
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].delivery_tag, tag(3));
+        assert_eq!(snapshot[0].age, None);
+        assert_eq!(snapshot[0].payload_size, None);
+        assert_eq!(snapshot[1].age, Some(Duration::from_secs(2)));
+        assert_eq!(snapshot[1].payload_size, Some(15));
+        assert_eq!(arrivals.arrivals.len(), 1);
+    }
+}
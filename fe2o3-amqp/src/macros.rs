@@ -52,6 +52,51 @@ macro_rules! cfg_not_transaction {
     }
 }
 
+/// Fault injection relies on `tokio::time`, which is not available in wasm32 targets
+macro_rules! cfg_chaos {
+    ($($item:item)*) => {
+        $(
+            #[cfg_attr(docsrs, doc(cfg(feature = "chaos")))]
+            #[cfg(not(target_arch = "wasm32"))]
+            #[cfg(feature = "chaos")]
+            $item
+        )*
+    }
+}
+
+/// `fe2o3-amqp-ws` uses `web_sys::WebSocket` in wasm32 targets, which cannot be opened from a url
+/// by the builder
+macro_rules! cfg_websocket {
+    ($($item:item)*) => {
+        $(
+            #[cfg_attr(docsrs, doc(cfg(feature = "websocket")))]
+            #[cfg(not(target_arch = "wasm32"))]
+            #[cfg(feature = "websocket")]
+            $item
+        )*
+    }
+}
+
+macro_rules! cfg_debug_deliveries {
+    ($($item:item)*) => {
+        $(
+            #[cfg_attr(docsrs, doc(cfg(feature = "debug-deliveries")))]
+            #[cfg(feature = "debug-deliveries")]
+            $item
+        )*
+    }
+}
+
+macro_rules! cfg_strict_validation {
+    ($($item:item)*) => {
+        $(
+            #[cfg_attr(docsrs, doc(cfg(feature = "strict-validation")))]
+            #[cfg(feature = "strict-validation")]
+            $item
+        )*
+    }
+}
+
 macro_rules! cfg_native_tls {
     ($($item:item)*) => {
         $(
@@ -81,3 +126,23 @@ macro_rules! cfg_scram {
         )*
     }
 }
+
+macro_rules! cfg_checksum {
+    ($($item:item)*) => {
+        $(
+            #[cfg_attr(docsrs, doc(cfg(feature = "checksum")))]
+            #[cfg(feature = "checksum")]
+            $item
+        )*
+    }
+}
+
+macro_rules! cfg_compression {
+    ($($item:item)*) => {
+        $(
+            #[cfg_attr(docsrs, doc(cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))))]
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+            $item
+        )*
+    }
+}
@@ -0,0 +1,539 @@
+//! Automatic recovery of a connection, its sessions and its links after a network drop
+//!
+//! A [`RecoveringConnection`] opens its connections with a user provided function, so that any
+//! [`Builder`](crate::connection::Builder) option (eg. TLS, SASL or
+//! [`alt_hosts`](crate::connection::Builder::alt_hosts)) can be used. The sessions begun with
+//! [`RecoveringConnection::begin_session()`] and the links attached on them are recovered
+//! together:
+//!
+//! 1. A [`RecoveringSender`] or [`RecoveringReceiver`] whose link fails checks whether its
+//!    session or the connection is gone.
+//! 2. If so, the connection is re-opened following the [`ReconnectPolicy`] and every session is
+//!    begun again. This is only done once for all the links that notice the same drop.
+//! 3. The link is resumed on the new session with the same name, which exchanges the unsettled
+//!    maps and re-transmits the unsettled deliveries as described in the resuming of deliveries
+//!    of the AMQP 1.0 specification. The operation that failed is then retried.
+//!
+//! A link that is detached or closed by the remote peer while the connection and the session are
+//! fine is not recovered, and the error is returned.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fe2o3_amqp::recovery::{RecoveringConnection, ReconnectPolicy};
+//!
+//! let connection = RecoveringConnection::open(
+//!     || Connection::open("recovering-client", "amqp://localhost:5672"),
+//!     ReconnectPolicy::default(),
+//! )
+//! .await
+//! .unwrap();
+//! let session = connection.begin_session().await.unwrap();
+//! let mut sender = session
+//!     .attach_sender(Sender::builder().name("sender").target("q1"))
+//!     .await
+//!     .unwrap();
+//!
+//! // Sent even if the broker restarts in the meantime
+//! let outcome = sender.send("hello").await.unwrap();
+//! ```
+
+use std::{future::Future, sync::Arc, time::Duration};
+
+use fe2o3_amqp_types::messaging::{FromBody, Outcome, SerializableBody, Target};
+use futures_util::{future::BoxFuture, FutureExt};
+use tokio::sync::{Mutex, MutexGuard, Notify};
+
+use crate::{
+    connection::{self, ConnectionHandle, OpenError},
+    link::{
+        builder::{Builder, WithName, WithSource, WithTarget},
+        delivery::{Delivery, Sendable},
+        role, DetachError, DetachThenResumeReceiverError, DetachThenResumeSenderError,
+        ReceiverAttachError, RecvError, SendError, SenderAttachError,
+    },
+    session::{BeginError, SessionHandle},
+    Receiver, Sender, Session,
+};
+
+/// Default number of attempts to re-open the connection, including the first attempt
+pub const DEFAULT_MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
+/// Default delay before the second attempt to re-open the connection
+pub const DEFAULT_RECONNECT_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Default upper bound on the delay between two attempts to re-open the connection
+pub const DEFAULT_RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How a lost connection is re-opened
+///
+/// The first attempt is made right away. If it fails, the next attempt is made after
+/// `initial_backoff`, and the delay is multiplied by `multiplier` after every attempt, up to
+/// `max_backoff`. The error of the last attempt is returned once `max_attempts` attempts have
+/// failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Maximum number of attempts, including the first attempt
+    pub max_attempts: u32,
+
+    /// Delay before the second attempt
+    pub initial_backoff: Duration,
+
+    /// Upper bound on the delay between two attempts
+    pub max_backoff: Duration,
+
+    /// Factor by which the delay grows after every attempt
+    pub multiplier: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_RECONNECT_ATTEMPTS)
+    }
+}
+
+impl ReconnectPolicy {
+    /// Creates a policy that makes at most `max_attempts` attempts
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff: DEFAULT_RECONNECT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_RECONNECT_MAX_BACKOFF,
+            multiplier: 2.0,
+        }
+    }
+
+    /// Sets the delay before the second attempt and the upper bound on the delay
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Sets the factor by which the delay grows after every attempt
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Returns how long to wait after the `attempt`-th attempt (starting at one) failed, or
+    /// `None` if no attempt is left
+    fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let factor = self.multiplier.max(1.0).powi((attempt - 1) as i32);
+        let delay = self.initial_backoff.as_secs_f64() * factor;
+        Some(Duration::from_secs_f64(
+            delay.min(self.max_backoff.as_secs_f64()),
+        ))
+    }
+}
+
+/// Errors of a [`RecoveringConnection`] and of its links
+#[derive(Debug, thiserror::Error)]
+pub enum RecoveryError {
+    /// The connection could not be re-opened
+    #[error(transparent)]
+    Open(#[from] OpenError),
+
+    /// A session could not be begun again
+    #[error(transparent)]
+    Begin(#[from] BeginError),
+
+    /// The sender could not be resumed on the new session
+    #[error(transparent)]
+    ResumeSender(#[from] DetachThenResumeSenderError),
+
+    /// The receiver could not be resumed on the new session
+    #[error(transparent)]
+    ResumeReceiver(#[from] DetachThenResumeReceiverError),
+
+    /// Sending failed for a reason other than losing the connection
+    #[error(transparent)]
+    Send(#[from] SendError),
+
+    /// Receiving failed for a reason other than losing the connection
+    #[error(transparent)]
+    Recv(#[from] RecvError),
+
+    /// The [`RecoveringConnection`] is closed
+    #[error("The recovering connection is closed")]
+    Closed,
+}
+
+type Connect =
+    dyn Fn() -> BoxFuture<'static, Result<ConnectionHandle<()>, OpenError>> + Send + Sync;
+
+#[derive(Debug)]
+struct SessionSlot {
+    handle: SessionHandle<()>,
+    // Incremented whenever the session is begun again
+    generation: u64,
+}
+
+#[derive(Debug)]
+struct State {
+    connection: ConnectionHandle<()>,
+    sessions: Vec<SessionSlot>,
+    reconnects: u64,
+    closed: bool,
+}
+
+struct Shared {
+    connect: Box<Connect>,
+    policy: ReconnectPolicy,
+    state: Mutex<State>,
+    // Wakes up the recoveries waiting for their next attempt once the connection is closed
+    closing: Notify,
+}
+
+impl std::fmt::Debug for Shared {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Shared")
+            .field("policy", &self.policy)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+impl Shared {
+    /// Re-opens the connection and begins the sessions again if they are gone. The state is
+    /// returned locked so that the caller can resume its link before the next drop is handled.
+    ///
+    /// The state is only locked during an attempt, so that [`RecoveringConnection::close()`] can
+    /// stop the recovery while it waits for the next attempt
+    async fn restore(&self) -> Result<MutexGuard<'_, State>, RecoveryError> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let mut state = self.state.lock().await;
+            if state.closed {
+                return Err(RecoveryError::Closed);
+            }
+            let error = match self.try_restore(&mut state).await {
+                Ok(()) => return Ok(state),
+                Err(error) => error,
+            };
+            let delay = match self.policy.delay(attempt) {
+                Some(delay) => delay,
+                None => return Err(error),
+            };
+            #[cfg(feature = "tracing")]
+            tracing::warn!(attempt, ?error, ?delay, "Recovery failed, retrying");
+            #[cfg(feature = "log")]
+            log::warn!(
+                "Recovery failed (attempt {}), retrying in {:?}: {:?}",
+                attempt,
+                delay,
+                error
+            );
+
+            // Registered before the state is unlocked so that a concurrent `close()` is not missed
+            let closing = self.closing.notified();
+            drop(state);
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = closing => return Err(RecoveryError::Closed),
+            }
+        }
+    }
+
+    async fn try_restore(&self, state: &mut State) -> Result<(), RecoveryError> {
+        let State {
+            connection,
+            sessions,
+            reconnects,
+            ..
+        } = state;
+
+        let reconnected = connection.is_closed();
+        if reconnected {
+            *connection = (self.connect)().await?;
+            *reconnects += 1;
+        }
+        for slot in sessions.iter_mut() {
+            if reconnected || slot.handle.is_ended() {
+                slot.handle = Session::begin(connection).await?;
+                slot.generation += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A connection that is re-opened along with its sessions and links after a network drop. See
+/// the [module level documentation](self) for details
+#[derive(Debug, Clone)]
+pub struct RecoveringConnection {
+    shared: Arc<Shared>,
+}
+
+impl RecoveringConnection {
+    /// Opens the connection with `connect`, which is called again whenever the connection needs
+    /// to be re-opened
+    pub async fn open<F, Fut>(connect: F, policy: ReconnectPolicy) -> Result<Self, OpenError>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<ConnectionHandle<()>, OpenError>> + Send + 'static,
+    {
+        let connection = connect().await?;
+        let connect: Box<Connect> = Box::new(move || connect().boxed());
+        let state = State {
+            connection,
+            sessions: Vec::new(),
+            reconnects: 0,
+            closed: false,
+        };
+        Ok(Self {
+            shared: Arc::new(Shared {
+                connect,
+                policy,
+                state: Mutex::new(state),
+                closing: Notify::new(),
+            }),
+        })
+    }
+
+    /// Begins a session that is begun again whenever the connection is re-opened
+    pub async fn begin_session(&self) -> Result<RecoveringSession, RecoveryError> {
+        let mut state = self.shared.restore().await?;
+        let handle = Session::begin(&mut state.connection).await?;
+        let index = state.sessions.len();
+        state.sessions.push(SessionSlot {
+            handle,
+            generation: 0,
+        });
+        Ok(RecoveringSession {
+            shared: self.shared.clone(),
+            index,
+        })
+    }
+
+    /// Number of times the connection has been re-opened
+    pub async fn reconnects(&self) -> u64 {
+        self.shared.state.lock().await.reconnects
+    }
+
+    /// Ends the sessions and closes the connection. The links are no longer recovered afterwards,
+    /// and should be closed beforehand. A recovery waiting for its next attempt fails with
+    /// [`RecoveryError::Closed`]
+    pub async fn close(self) -> Result<(), connection::Error> {
+        let mut state = self.shared.state.lock().await;
+        state.closed = true;
+        self.shared.closing.notify_waiters();
+        for slot in state.sessions.iter_mut() {
+            let _ = slot.handle.end().await;
+        }
+        state.connection.close().await
+    }
+}
+
+/// A session of a [`RecoveringConnection`] on which recovering links are attached
+#[derive(Debug, Clone)]
+pub struct RecoveringSession {
+    shared: Arc<Shared>,
+    index: usize,
+}
+
+impl RecoveringSession {
+    /// Attaches a sender that is resumed whenever the session is begun again
+    pub async fn attach_sender(
+        &self,
+        builder: Builder<role::SenderMarker, Target, WithName, WithSource, WithTarget>,
+    ) -> Result<RecoveringSender, RecoveringAttachError<SenderAttachError>> {
+        let mut state = self.shared.restore().await?;
+        let slot = &mut state.sessions[self.index];
+        let sender = builder
+            .attach(&mut slot.handle)
+            .await
+            .map_err(RecoveringAttachError::Attach)?;
+        Ok(RecoveringSender {
+            sender,
+            session: self.clone(),
+            generation: slot.generation,
+        })
+    }
+
+    /// Attaches a receiver that is resumed whenever the session is begun again
+    pub async fn attach_receiver(
+        &self,
+        builder: Builder<role::ReceiverMarker, Target, WithName, WithSource, WithTarget>,
+    ) -> Result<RecoveringReceiver, RecoveringAttachError<ReceiverAttachError>> {
+        let mut state = self.shared.restore().await?;
+        let slot = &mut state.sessions[self.index];
+        let receiver = builder
+            .attach(&mut slot.handle)
+            .await
+            .map_err(RecoveringAttachError::Attach)?;
+        Ok(RecoveringReceiver {
+            receiver,
+            session: self.clone(),
+            generation: slot.generation,
+        })
+    }
+}
+
+/// Error with attaching a link on a [`RecoveringSession`]
+#[derive(Debug, thiserror::Error)]
+pub enum RecoveringAttachError<E> {
+    /// The connection or the session could not be restored before attaching
+    #[error(transparent)]
+    Recovery(#[from] RecoveryError),
+
+    /// The link could not be attached
+    #[error(transparent)]
+    Attach(E),
+}
+
+/// A [`Sender`] that is resumed on the new session after a network drop
+///
+/// A message whose transfer fails because the connection is lost is sent again on the resumed
+/// link. A message that is transferred but not yet settled when the connection is lost is
+/// re-transmitted by the link resumption with the same delivery tag, and its outcome is still
+/// returned by [`send`](#method.send).
+#[derive(Debug)]
+pub struct RecoveringSender {
+    sender: Sender,
+    session: RecoveringSession,
+    generation: u64,
+}
+
+impl RecoveringSender {
+    /// Get a reference to the underlying [`Sender`]
+    pub fn sender(&self) -> &Sender {
+        &self.sender
+    }
+
+    /// Send a message and wait for its outcome, recovering the link if the connection is lost in
+    /// the meantime
+    pub async fn send<T: SerializableBody>(
+        &mut self,
+        sendable: impl Into<Sendable<T>>,
+    ) -> Result<Outcome, RecoveryError> {
+        let sendable = sendable.into();
+        let fut = loop {
+            match self.sender.send_ref_unacknowledged(&sendable, false).await {
+                Ok(fut) => break fut,
+                Err(error @ (SendError::LinkStateError(_) | SendError::Detached(_))) => {
+                    self.recover(error).await?
+                }
+                Err(error) => return Err(error.into()),
+            }
+        };
+
+        tokio::pin!(fut);
+        loop {
+            tokio::select! {
+                biased;
+                outcome = &mut fut => return Ok(outcome?),
+                error = self.sender.on_detach() => self.recover(error.into()).await?,
+            }
+        }
+    }
+
+    /// Closes the link
+    pub async fn close(self) -> Result<(), DetachError> {
+        self.sender.close().await
+    }
+
+    /// Resumes the link if its session has been begun again, or returns the error that the link
+    /// failed with otherwise
+    async fn recover(&mut self, error: SendError) -> Result<(), RecoveryError> {
+        let state = self.session.shared.restore().await?;
+        let slot = &state.sessions[self.session.index];
+        if slot.generation == self.generation {
+            return Err(error.into());
+        }
+        self.sender
+            .detach_then_resume_on_session(&slot.handle)
+            .await?;
+        self.generation = slot.generation;
+        Ok(())
+    }
+}
+
+/// A [`Receiver`] that is resumed on the new session after a network drop
+///
+/// The deliveries that are received are disposed with [`receiver_mut`](#method.receiver_mut).
+/// A delivery that is not yet settled when the connection is lost is sent again by the remote
+/// peer after the link is resumed, so a delivery should not be disposed after
+/// [`recv`](#method.recv) has recovered the link.
+#[derive(Debug)]
+pub struct RecoveringReceiver {
+    receiver: Receiver,
+    session: RecoveringSession,
+    generation: u64,
+}
+
+impl RecoveringReceiver {
+    /// Get a reference to the underlying [`Receiver`]
+    pub fn receiver(&self) -> &Receiver {
+        &self.receiver
+    }
+
+    /// Get a mutable reference to the underlying [`Receiver`], eg. to dispose a delivery
+    pub fn receiver_mut(&mut self) -> &mut Receiver {
+        &mut self.receiver
+    }
+
+    /// Receive a message, recovering the link if the connection is lost in the meantime
+    pub async fn recv<T>(&mut self) -> Result<Delivery<T>, RecoveryError>
+    where
+        for<'de> T: FromBody<'de> + Send,
+    {
+        loop {
+            match self.receiver.recv().await {
+                Ok(delivery) => return Ok(delivery),
+                Err(error @ RecvError::LinkStateError(_)) => self.recover(error).await?,
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    /// Closes the link
+    pub async fn close(self) -> Result<(), DetachError> {
+        self.receiver.close().await
+    }
+
+    /// Resumes the link if its session has been begun again, or returns the error that the link
+    /// failed with otherwise
+    async fn recover(&mut self, error: RecvError) -> Result<(), RecoveryError> {
+        let state = self.session.shared.restore().await?;
+        let slot = &state.sessions[self.session.index];
+        if slot.generation == self.generation {
+            return Err(error.into());
+        }
+        // The unsettled deliveries are sent again by the remote peer after the exchange
+        let _exchange = self
+            .receiver
+            .detach_then_resume_on_session(&slot.handle)
+            .await?;
+        self.generation = slot.generation;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::ReconnectPolicy;
+
+    #[test]
+    fn reconnect_delay_grows_up_to_the_max_backoff() {
+        let policy =
+            ReconnectPolicy::new(5).backoff(Duration::from_secs(1), Duration::from_secs(3));
+        let delays: Vec<_> = (1..6).map(|attempt| policy.delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_secs(1)),
+                Some(Duration::from_secs(2)),
+                Some(Duration::from_secs(3)),
+                Some(Duration::from_secs(3)),
+                None
+            ]
+        );
+    }
+}
@@ -0,0 +1,82 @@
+//! SASL credentials that can be rotated while they are shared by connections
+
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use super::SaslProfile;
+
+/// A [`SaslProfile`] that is shared by the connection builder and the [`ConnectionHandle`]s that
+/// are opened with it
+///
+/// The profile is read every time a SASL negotiation is performed, so a rotated password or
+/// token is picked up by the next (re)connect without rebuilding the builder or tearing down the
+/// connections that are already open. AMQP 1.0 has no re-authentication of an open connection,
+/// so an open connection keeps running with the credentials it was opened with.
+///
+/// [`ConnectionHandle`]: crate::connection::ConnectionHandle
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use fe2o3_amqp::{Connection, sasl_profile::SharedCredentials};
+///
+/// let credentials = SharedCredentials::new(("user", "old-password"));
+/// let connection = Connection::builder()
+///     .container_id("client")
+///     .credentials(credentials.clone())
+///     .open("amqp://localhost:5672")
+///     .await
+///     .unwrap();
+///
+/// // Later, the password is rotated
+/// connection.update_credentials(("user", "new-password"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SharedCredentials {
+    profile: Arc<RwLock<Option<SaslProfile>>>,
+}
+
+impl SharedCredentials {
+    /// Creates new shared credentials with a SASL profile
+    pub fn new(profile: impl Into<SaslProfile>) -> Self {
+        Self {
+            profile: Arc::new(RwLock::new(Some(profile.into()))),
+        }
+    }
+
+    /// Get a copy of the current SASL profile. `None` is returned if no SASL negotiation should
+    /// be performed
+    pub fn get(&self) -> Option<SaslProfile> {
+        self.profile.read().clone()
+    }
+
+    /// Replaces the SASL profile. This takes effect on the next SASL negotiation
+    pub fn update(&self, profile: impl Into<SaslProfile>) {
+        *self.profile.write() = Some(profile.into());
+    }
+
+    /// Removes the SASL profile so that the next connection skips SASL negotiation
+    pub fn clear(&self) {
+        *self.profile.write() = None;
+    }
+
+    /// Whether `self` and `other` share the same profile
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.profile, &other.profile)
+    }
+}
+
+impl From<SaslProfile> for SharedCredentials {
+    fn from(profile: SaslProfile) -> Self {
+        Self::new(profile)
+    }
+}
+
+impl From<Option<SaslProfile>> for SharedCredentials {
+    fn from(profile: Option<SaslProfile>) -> Self {
+        Self {
+            profile: Arc::new(RwLock::new(profile)),
+        }
+    }
+}
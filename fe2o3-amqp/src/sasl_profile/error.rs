@@ -8,6 +8,10 @@ pub enum Error {
     #[error("Not implemented {0:?}")]
     NotImplemented(Option<String>),
 
+    /// Error returned by a custom SASL mechanism
+    #[error("SASL mechanism error: {0}")]
+    Mechanism(Box<dyn std::error::Error + Send + Sync>),
+
     /// Error with SCRAM
     #[cfg_attr(docsrs, doc(cfg(feature = "scram")))]
     #[cfg(feature = "scram")]
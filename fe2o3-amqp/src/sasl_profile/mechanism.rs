@@ -0,0 +1,122 @@
+//! Pluggable client side SASL mechanisms
+
+use std::{future::Future, pin::Pin};
+
+use fe2o3_amqp_types::{
+    primitives::{Binary, Symbol},
+    sasl::{SaslChallenge, SaslOutcome, SaslResponse},
+};
+
+use super::{Error, SaslProfile};
+
+/// The future returned by the steps of a [`SaslClientMechanism`]
+pub type SaslClientMechanismFuture<'a, T> =
+    Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 'a>>;
+
+/// Client side SASL mechanism that is not built into [`SaslProfile`] (eg. OAUTHBEARER, XOAUTH2,
+/// MSSBCBS)
+///
+/// The mechanism takes part in every step of the negotiation. It produces the initial response,
+/// answers any number of intermediate challenges, and inspects the outcome, including the
+/// `additional-data` sent by the server. Producing a response is asynchronous so that the
+/// mechanism can `.await` while doing so, for example to fetch a token from an identity
+/// provider, and the negotiation waits for each step to finish before the response is sent to
+/// the server. Returning an error from any step fails the negotiation.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use fe2o3_amqp::sasl_profile::{SaslClientMechanism, SaslClientMechanismFuture, SaslProfile};
+///
+/// #[derive(Debug, Clone)]
+/// struct XOAuth2 { user: String, tokens: TokenProvider }
+///
+/// impl SaslClientMechanism for XOAuth2 {
+///     fn mechanism(&self) -> Symbol {
+///         Symbol::from("XOAUTH2")
+///     }
+///
+///     fn initial_response<'a>(
+///         &'a mut self,
+///         _hostname: Option<&'a str>,
+///     ) -> SaslClientMechanismFuture<'a, Option<Binary>> {
+///         Box::pin(async move {
+///             let token = self.tokens.fetch().await?;
+///             let response = format!("user={}\x01auth=Bearer {}\x01\x01", self.user, token);
+///             Ok(Some(Binary::from(response.into_bytes())))
+///         })
+///     }
+/// }
+///
+/// let connection = Connection::builder()
+///     .container_id("connection-1")
+///     .sasl_profile(SaslProfile::custom(XOAuth2 { user, tokens }))
+///     .open("amqp://localhost:5672")
+///     .await
+///     .unwrap();
+/// ```
+pub trait SaslClientMechanism: BoxCloneSaslClientMechanism + std::fmt::Debug + Send + Sync {
+    /// Name of the mechanism, which must be one of the mechanisms supported by the server
+    fn mechanism(&self) -> Symbol;
+
+    /// The initial response that is sent in the `sasl-init` frame
+    fn initial_response<'a>(
+        &'a mut self,
+        hostname: Option<&'a str>,
+    ) -> SaslClientMechanismFuture<'a, Option<Binary>>;
+
+    /// Respond to a `sasl-challenge` frame. This may be called multiple times during one
+    /// negotiation.
+    ///
+    /// Challenges are not supported by default
+    fn on_challenge(
+        &mut self,
+        challenge: SaslChallenge,
+    ) -> SaslClientMechanismFuture<'_, SaslResponse> {
+        let _ = challenge;
+        let error = Error::NotImplemented(Some(format!(
+            "SASL Challenge is not implemented for {:?}",
+            self.mechanism()
+        )));
+        Box::pin(async move { Err(error) })
+    }
+
+    /// Inspect the `sasl-outcome` frame (eg. verify the server-final message or extract a token
+    /// from the `additional-data`). The negotiation fails if an error is returned even if the
+    /// outcome code is `Ok`.
+    ///
+    /// The outcome is accepted by default
+    fn on_outcome(&mut self, outcome: &SaslOutcome) -> Result<(), Error> {
+        let _ = outcome;
+        Ok(())
+    }
+}
+
+/// Clones a boxed [`SaslClientMechanism`]. This is implemented for all mechanisms that
+/// implement [`Clone`]
+pub trait BoxCloneSaslClientMechanism {
+    /// Clones the mechanism into a new box
+    fn box_clone(&self) -> Box<dyn SaslClientMechanism>;
+}
+
+impl<T> BoxCloneSaslClientMechanism for T
+where
+    T: SaslClientMechanism + Clone + 'static,
+{
+    fn box_clone(&self) -> Box<dyn SaslClientMechanism> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn SaslClientMechanism> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+impl SaslProfile {
+    /// Creates a [`SaslProfile`] with a custom [`SaslClientMechanism`]
+    pub fn custom(mechanism: impl SaslClientMechanism + 'static) -> Self {
+        Self::Custom(Box::new(mechanism))
+    }
+}
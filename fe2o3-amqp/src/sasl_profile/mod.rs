@@ -12,6 +12,12 @@ use crate::frames::sasl;
 mod error;
 pub use error::Error;
 
+mod mechanism;
+pub use mechanism::{BoxCloneSaslClientMechanism, SaslClientMechanism, SaslClientMechanismFuture};
+
+mod credentials;
+pub use credentials::SharedCredentials;
+
 cfg_scram! {
     use crate::auth::error::ScramErrorKind;
 
@@ -51,6 +57,17 @@ pub enum SaslProfile {
         password: String,
     },
 
+    /// SASL profile for PLAIN mechanism with an authorization identity, which asks the server to
+    /// act as `authzid` after authenticating as `username` (eg. a proxy impersonating a user)
+    PlainWithAuthzid {
+        /// Authorization identity
+        authzid: String,
+        /// Username
+        username: String,
+        /// Password
+        password: String,
+    },
+
     /// SASL-SCRAM-SHA-1
     #[cfg_attr(docsrs, doc(cfg(feature = "scram")))]
     #[cfg(feature = "scram")]
@@ -65,6 +82,9 @@ pub enum SaslProfile {
     #[cfg_attr(docsrs, doc(cfg(feature = "scram")))]
     #[cfg(feature = "scram")]
     ScramSha512(SaslScramSha512),
+
+    /// SASL profile with a custom mechanism
+    Custom(Box<dyn SaslClientMechanism>),
 }
 
 impl<T1, T2> From<(T1, T2)> for SaslProfile
@@ -103,13 +123,24 @@ impl<'a> TryFrom<&'a Url> for SaslProfile {
 }
 
 impl SaslProfile {
+    /// Creates a PLAIN profile that authenticates as `username` and asks to act as `authzid`
+    pub fn plain_with_authzid(
+        authzid: impl Into<String>,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        Self::PlainWithAuthzid {
+            authzid: authzid.into(),
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
     pub(crate) fn mechanism(&self) -> Symbol {
         let value = match self {
+            SaslProfile::Custom(mechanism) => return mechanism.mechanism(),
             SaslProfile::Anonymous => ANONYMOUS,
-            SaslProfile::Plain {
-                username: _,
-                password: _,
-            } => PLAIN,
+            SaslProfile::Plain { .. } | SaslProfile::PlainWithAuthzid { .. } => PLAIN,
             #[cfg(feature = "scram")]
             SaslProfile::ScramSha1(_) => SCRAM_SHA_1,
             #[cfg(feature = "scram")]
@@ -120,19 +151,20 @@ impl SaslProfile {
         Symbol::from(value)
     }
 
-    pub(crate) fn initial_response(&mut self) -> Option<Binary> {
-        match self {
+    pub(crate) async fn initial_response(
+        &mut self,
+        hostname: Option<&str>,
+    ) -> Result<Option<Binary>, Error> {
+        let response = match self {
             SaslProfile::Anonymous => None,
             SaslProfile::Plain { username, password } => {
-                let username = username.as_bytes();
-                let password = password.as_bytes();
-                let mut buf = Vec::with_capacity(username.len() + password.len() + 2);
-                buf.put_u8(0);
-                buf.put_slice(username);
-                buf.put_u8(0);
-                buf.put_slice(password);
-                Some(Binary::from(buf))
+                Some(plain_initial_response("", username, password))
             }
+            SaslProfile::PlainWithAuthzid {
+                authzid,
+                username,
+                password,
+            } => Some(plain_initial_response(authzid, username, password)),
             #[cfg(feature = "scram")]
             SaslProfile::ScramSha1(scram_sha1) => Some(Binary::from(
                 scram_sha1.client.compute_client_first_message().to_vec(),
@@ -145,12 +177,14 @@ impl SaslProfile {
             SaslProfile::ScramSha512(scram_sha512) => Some(Binary::from(
                 scram_sha512.client.compute_client_first_message().to_vec(),
             )),
-        }
+            SaslProfile::Custom(mechanism) => return mechanism.initial_response(hostname).await,
+        };
+        Ok(response)
     }
 
     /// How a SASL profile should respond to a SASL frame
     #[cfg_attr(not(feature = "scram"), allow(unused_variables))]
-    pub(crate) fn on_frame(
+    pub(crate) async fn on_frame(
         &mut self,
         frame: sasl::Frame,
         hostname: Option<&str>,
@@ -163,7 +197,7 @@ impl SaslProfile {
                 if mechanisms.sasl_server_mechanisms.0.contains(&mechanism) {
                     let init = SaslInit {
                         mechanism,
-                        initial_response: self.initial_response(),
+                        initial_response: self.initial_response(hostname).await?,
                         hostname: hostname.map(Into::into),
                     };
                     Ok(Negotiation::Init(init))
@@ -175,9 +209,11 @@ impl SaslProfile {
                 }
             }
             Frame::Challenge(challenge) => match self {
-                SaslProfile::Anonymous | SaslProfile::Plain { .. } => Err(Error::NotImplemented(
-                    Some("SASL Challenge is not implemented for ANONYMOUS or PLAIN.".to_string()),
-                )),
+                SaslProfile::Anonymous
+                | SaslProfile::Plain { .. }
+                | SaslProfile::PlainWithAuthzid { .. } => Err(Error::NotImplemented(Some(
+                    "SASL Challenge is not implemented for ANONYMOUS or PLAIN.".to_string(),
+                ))),
                 #[cfg(feature = "scram")]
                 SaslProfile::ScramSha1(SaslScramSha1 { client })
                 | SaslProfile::ScramSha256(SaslScramSha256 { client })
@@ -191,10 +227,16 @@ impl SaslProfile {
 
                     Ok(Negotiation::Response(response))
                 }
+                SaslProfile::Custom(mechanism) => mechanism
+                    .on_challenge(challenge)
+                    .await
+                    .map(Negotiation::Response),
             },
             Frame::Outcome(outcome) => {
                 match self {
-                    SaslProfile::Anonymous | SaslProfile::Plain { .. } => {}
+                    SaslProfile::Anonymous
+                    | SaslProfile::Plain { .. }
+                    | SaslProfile::PlainWithAuthzid { .. } => {}
                     #[cfg(feature = "scram")]
                     SaslProfile::ScramSha1(SaslScramSha1 { client })
                     | SaslProfile::ScramSha256(SaslScramSha256 { client })
@@ -207,6 +249,7 @@ impl SaslProfile {
                             client.validate_server_final(server_final)?;
                         }
                     }
+                    SaslProfile::Custom(mechanism) => mechanism.on_outcome(&outcome)?,
                 }
                 Ok(Negotiation::Outcome(outcome))
             }
@@ -218,6 +261,17 @@ impl SaslProfile {
     }
 }
 
+/// `authzid NUL authcid NUL passwd` as defined in RFC 4616. An empty `authzid` is omitted
+fn plain_initial_response(authzid: &str, username: &str, password: &str) -> Binary {
+    let mut buf = Vec::with_capacity(authzid.len() + username.len() + password.len() + 2);
+    buf.put_slice(authzid.as_bytes());
+    buf.put_u8(0);
+    buf.put_slice(username.as_bytes());
+    buf.put_u8(0);
+    buf.put_slice(password.as_bytes());
+    Binary::from(buf)
+}
+
 #[cfg(test)]
 mod tests {
     use url::Url;
@@ -247,13 +301,24 @@ mod tests {
         assert!(result.is_err());
     }
 
-    #[test]
-    fn test_plain_initial_response() {
+    #[tokio::test]
+    async fn test_plain_initial_response() {
         let mut profile = SaslProfile::Plain {
             username: String::from("user"),
             password: String::from("example"),
         };
-        let response = profile.initial_response();
+        let response = profile.initial_response(None).await.unwrap();
         println!("{:?}", response);
     }
+
+    #[tokio::test]
+    async fn plain_initial_response_carries_the_authzid() {
+        let mut profile = SaslProfile::plain_with_authzid("alice", "proxy", "secret");
+        let response = profile.initial_response(None).await.unwrap().unwrap();
+        assert_eq!(&response[..], b"alice\0proxy\0secret");
+
+        let mut profile = SaslProfile::from(("proxy", "secret"));
+        let response = profile.initial_response(None).await.unwrap().unwrap();
+        assert_eq!(&response[..], b"\0proxy\0secret");
+    }
 }
@@ -1,22 +1,31 @@
 //! Session builder
 
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
 
-use fe2o3_amqp_types::definitions::{Fields, Handle, TransferNumber};
+use fe2o3_amqp_types::{
+    definitions::{Fields, Handle, TransferNumber},
+    performatives::Begin,
+};
 use serde_amqp::primitives::Symbol;
 use slab::Slab;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
 use crate::{
-    connection::{AllocSessionError, ConnectionHandle},
+    connection::{AllocSessionError, ConnectionHandle, QosClass},
     control::SessionControl,
     endpoint::OutgoingChannel,
-    session::{engine::SessionEngine, SessionState},
+    session::{engine::SessionEngine, SendBlocking, SessionState, TransferIds},
     util::Constant,
     Session,
 };
 
-use super::{error::BeginError, SessionHandle, DEFAULT_WINDOW};
+use super::{
+    error::BeginError, FairDispatch, FlowCoalescing, InboundDispatcher, SessionHandle,
+    SharedTransferMiddleware, TransferMiddleware, DEFAULT_WINDOW,
+};
 
 pub(crate) const DEFAULT_SESSION_CONTROL_BUFFER_SIZE: usize = 128;
 pub(crate) const DEFAULT_SESSION_MUX_BUFFER_SIZE: usize = u16::MAX as usize;
@@ -49,6 +58,25 @@ pub struct Builder {
     /// that are used by links attached to the session
     pub buffer_size: usize,
 
+    /// Merge outgoing link flow updates within a small window into fewer frames. Flow
+    /// coalescing is disabled if this is `None`
+    pub flow_coalescing: Option<FlowCoalescing>,
+
+    /// Queue incoming transfers per link and dispatch them fairly among the links. Fair
+    /// dispatch is disabled if this is `None`
+    pub fair_dispatch: Option<FairDispatch>,
+
+    /// Quality-of-service class that the connection engine schedules the outgoing frames of the
+    /// session with
+    pub qos_class: QosClass,
+
+    /// The transfer numbering of the previous session that this session resumes. The remote
+    /// peer's Begin is checked against it
+    pub resumed_from: Option<TransferIds>,
+
+    /// Async transformation of incoming and outgoing transfer payloads
+    pub(crate) transfer_middleware: Option<SharedTransferMiddleware>,
+
     /// Acceptor for incoming transaction control links
     #[cfg(not(target_arch = "wasm32"))]
     #[cfg(all(feature = "transaction", feature = "acceptor"))]
@@ -66,6 +94,11 @@ impl Default for Builder {
             desired_capabilities: None,
             properties: None,
             buffer_size: DEFAULT_SESSION_MUX_BUFFER_SIZE,
+            flow_coalescing: None,
+            fair_dispatch: None,
+            qos_class: QosClass::default(),
+            resumed_from: None,
+            transfer_middleware: None,
 
             #[cfg(not(target_arch = "wasm32"))]
             #[cfg(all(feature = "transaction", feature = "acceptor"))]
@@ -74,6 +107,29 @@ impl Default for Builder {
     }
 }
 
+/// Warns if the remote peer restarted its transfer numbering when the session is resumed, in
+/// which case the delivery-ids of its deliveries may be ambiguous in the logs of either peer
+fn check_resumed_numbering(resumed_from: Option<&TransferIds>, remote_begin: &Begin) {
+    let expected = match resumed_from {
+        Some(ids) => ids.next_incoming_id,
+        None => return,
+    };
+    if remote_begin.next_outgoing_id != expected {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            expected,
+            actual = remote_begin.next_outgoing_id,
+            "Remote peer did not continue its transfer numbering"
+        );
+        #[cfg(feature = "log")]
+        log::warn!(
+            "Remote peer did not continue its transfer numbering: expected next-outgoing-id {}, got {}",
+            expected,
+            remote_begin.next_outgoing_id
+        );
+    }
+}
+
 cfg_transaction! {
     cfg_acceptor! {
         use crate::transaction::{
@@ -81,19 +137,25 @@ cfg_transaction! {
         };
 
         impl Builder {
+            #[allow(clippy::too_many_arguments)]
             pub(crate) fn into_txn_session(
                 self,
                 control: mpsc::Sender<SessionControl>,
                 outgoing: mpsc::Sender<crate::link::LinkFrame>,
                 outgoing_channel: OutgoingChannel,
                 control_link_acceptor: ControlLinkAcceptor,
-                local_state: SessionState,
+                state_watch: watch::Sender<SessionState>,
+                send_blocking_watch: watch::Sender<SendBlocking>,
+                transfer_ids_watch: watch::Sender<TransferIds>,
+                clock: crate::clock::SharedClock,
             ) -> TxnSession<Session> {
-                let txn_manager = TransactionManager::new(outgoing, control_link_acceptor);
+                let txn_manager = TransactionManager::new(outgoing, control_link_acceptor, clock);
+                let local_state = state_watch.borrow().clone();
                 let session = Session {
                     // control,
                     outgoing_channel,
                     local_state,
+                    state_watch,
                     initial_outgoing_id: Constant::new(self.next_outgoing_id),
                     next_outgoing_id: self.next_outgoing_id,
                     incoming_window: self.incoming_window,
@@ -103,6 +165,8 @@ cfg_transaction! {
                     next_incoming_id: 0,
                     remote_incoming_window: 0,
                     remote_incoming_window_exhausted_buffer: VecDeque::new(),
+                    send_blocking_watch,
+                    transfer_ids_watch,
                     remote_outgoing_window: 0,
                     offered_capabilities: self.offered_capabilities,
                     desired_capabilities: self.desired_capabilities,
@@ -111,7 +175,12 @@ cfg_transaction! {
                     link_name_by_output_handle: Slab::new(),
                     link_by_name: HashMap::new(),
                     link_by_input_handle: HashMap::new(),
+                    abandoned_input_handles: HashSet::new(),
+                    kicked_output_handles: HashSet::new(),
+                    link_addresses: HashMap::new(),
                     delivery_tag_by_id: HashMap::new(),
+                    inbound_dispatcher: self.fair_dispatch.map(InboundDispatcher::new),
+                    transfer_middleware: self.transfer_middleware,
                 };
 
                 TxnSession {
@@ -134,11 +203,15 @@ impl Builder {
         self,
         // control: mpsc::Sender<SessionControl>,
         outgoing_channel: OutgoingChannel,
-        local_state: SessionState,
+        state_watch: watch::Sender<SessionState>,
+        send_blocking_watch: watch::Sender<SendBlocking>,
+        transfer_ids_watch: watch::Sender<TransferIds>,
     ) -> Session {
+        let local_state = state_watch.borrow().clone();
         Session {
             outgoing_channel,
             local_state,
+            state_watch,
             initial_outgoing_id: Constant::new(self.next_outgoing_id),
             next_outgoing_id: self.next_outgoing_id,
             incoming_window: self.incoming_window,
@@ -148,6 +221,8 @@ impl Builder {
             next_incoming_id: 0,
             remote_incoming_window: 0,
             remote_incoming_window_exhausted_buffer: VecDeque::new(),
+            send_blocking_watch,
+            transfer_ids_watch,
             remote_outgoing_window: 0,
             offered_capabilities: self.offered_capabilities,
             desired_capabilities: self.desired_capabilities,
@@ -156,7 +231,12 @@ impl Builder {
             link_name_by_output_handle: Slab::new(),
             link_by_name: HashMap::new(),
             link_by_input_handle: HashMap::new(),
+            abandoned_input_handles: HashSet::new(),
+            kicked_output_handles: HashSet::new(),
+            link_addresses: HashMap::new(),
             delivery_tag_by_id: HashMap::new(),
+            inbound_dispatcher: self.fair_dispatch.map(InboundDispatcher::new),
+            transfer_middleware: self.transfer_middleware,
         }
     }
 
@@ -166,6 +246,16 @@ impl Builder {
         self
     }
 
+    /// Resumes the transfer numbering of a previous session, whose ids are obtained with
+    /// [`SessionHandle::transfer_ids()`](crate::session::SessionHandle::transfer_ids) after it
+    /// ended. The next-outgoing-id is set to the one of the previous session, and a warning is
+    /// logged if the remote peer does not continue its own numbering
+    pub fn resume_from(mut self, ids: TransferIds) -> Self {
+        self.next_outgoing_id = ids.next_outgoing_id;
+        self.resumed_from = Some(ids);
+        self
+    }
+
     /// The initial incoming-window of the sender
     pub fn incoming_window(mut self, value: TransferNumber) -> Self {
         self.incoming_window = value;
@@ -227,6 +317,66 @@ impl Builder {
         self
     }
 
+    /// Merge outgoing link flow updates within a small time window into fewer flow frames.
+    ///
+    /// Flow frames that set `drain` or `echo`, or that carry properties, are always sent
+    /// immediately. Flow coalescing is disabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust, ignore
+    /// let session = Session::builder()
+    ///     .flow_coalescing(FlowCoalescing::default())
+    ///     .begin(&mut connection)
+    ///     .await.unwrap();
+    /// ```
+    pub fn flow_coalescing(mut self, flow_coalescing: impl Into<Option<FlowCoalescing>>) -> Self {
+        self.flow_coalescing = flow_coalescing.into();
+        self
+    }
+
+    /// Queue incoming transfers in per-link inbound queues and dispatch them fairly among the
+    /// links, so that a slow receiver doesn't hold up other links on the same session.
+    ///
+    /// Fair dispatch is disabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust, ignore
+    /// let session = Session::builder()
+    ///     .fair_dispatch(FairDispatch::default())
+    ///     .begin(&mut connection)
+    ///     .await.unwrap();
+    /// ```
+    pub fn fair_dispatch(mut self, fair_dispatch: impl Into<Option<FairDispatch>>) -> Self {
+        self.fair_dispatch = fair_dispatch.into();
+        self
+    }
+
+    /// Quality-of-service class of the session. See [`QosClass`] for details.
+    ///
+    /// Defaults to [`QosClass::Interactive`]
+    ///
+    /// # Example
+    ///
+    /// ```rust, ignore
+    /// let bulk_session = Session::builder()
+    ///     .qos_class(QosClass::Bulk)
+    ///     .begin(&mut connection)
+    ///     .await.unwrap();
+    /// ```
+    pub fn qos_class(mut self, qos_class: QosClass) -> Self {
+        self.qos_class = qos_class;
+        self
+    }
+
+    /// Transform the payloads of all incoming and outgoing transfers on the session with the
+    /// middleware. See [`TransferMiddleware`] for details.
+    pub fn transfer_middleware(mut self, middleware: impl TransferMiddleware + 'static) -> Self {
+        self.transfer_middleware = Some(SharedTransferMiddleware(Arc::new(middleware)));
+        self
+    }
+
     // TODO
     // /// Enable handling remotely initiated control link and transaction by setting the
     // /// `control_link_acceptor` field
@@ -239,6 +389,23 @@ impl Builder {
     //     self
     // }
 
+    /// Checks that the configuration is coherent
+    ///
+    /// This is performed by all the `begin` methods before the session is allocated on the
+    /// connection.
+    pub fn validate(&self) -> Result<(), BeginError> {
+        if self.incoming_window == 0 {
+            return Err(BeginError::ZeroIncomingWindow);
+        }
+        if self.outgoing_window == 0 {
+            return Err(BeginError::ZeroOutgoingWindow);
+        }
+        if self.buffer_size == 0 {
+            return Err(BeginError::ZeroBufferSize);
+        }
+        Ok(())
+    }
+
     cfg_not_wasm32! {
         /// Begins a new session
         ///
@@ -255,7 +422,13 @@ impl Builder {
             self,
             connection: &mut ConnectionHandle<()>,
         ) -> Result<SessionHandle<()>, BeginError> {
-            let local_state = SessionState::Unmapped;
+            self.validate()?;
+            let (state_watch, state) = watch::channel(SessionState::Unmapped);
+            let (send_blocking_watch, send_blocking) = watch::channel(SendBlocking::default());
+            let (transfer_ids_watch, transfer_ids) = watch::channel(TransferIds::default());
+            let resumed_from = self.resumed_from;
+            let flow_coalescing = self.flow_coalescing;
+            let qos_class = self.qos_class;
             let (session_control_tx, session_control_rx) =
                 mpsc::channel::<SessionControl>(DEFAULT_SESSION_CONTROL_BUFFER_SIZE);
             let (incoming_tx, incoming_rx) = mpsc::channel(self.buffer_size);
@@ -274,22 +447,25 @@ impl Builder {
             };
 
             #[cfg(not(all(feature = "transaction", feature = "acceptor")))]
-            let (engine_handle, outcome) = {
-                let session = self.into_session(outgoing_channel, local_state);
-                let engine = SessionEngine::begin_client_session(
+            let ((engine_handle, outcome), remote_begin) = {
+                let session = self.into_session(outgoing_channel, state_watch, send_blocking_watch, transfer_ids_watch);
+                let (mut engine, remote_begin) = SessionEngine::begin_client_session(
                     connection.control.clone(),
                     session,
                     session_control_rx,
                     incoming_rx,
-                    connection.outgoing.clone(),
+                    connection.outgoing.get(qos_class).clone(),
                     outgoing_rx,
+                    flow_coalescing,
                 )
                 .await?;
-                engine.spawn()
+                engine.set_watchdog(connection.watchdog.clone());
+                engine.set_clock(connection.idle_monitor.clock().clone());
+                (engine.spawn(), remote_begin)
             };
 
             #[cfg(all(feature = "transaction", feature = "acceptor"))]
-            let (engine_handle, outcome) = {
+            let ((engine_handle, outcome), remote_begin) = {
                 let mut this = self;
                 match this.control_link_acceptor.take() {
                     Some(control_link_acceptor) => {
@@ -298,42 +474,60 @@ impl Builder {
                             outgoing_tx.clone(),
                             outgoing_channel,
                             control_link_acceptor,
-                            local_state,
+                            state_watch,
+                            send_blocking_watch,
+                            transfer_ids_watch,
+                            connection.idle_monitor.clock().clone(),
                         );
-                        let engine = SessionEngine::begin_client_session(
+                        let (mut engine, remote_begin) = SessionEngine::begin_client_session(
                             connection.control.clone(),
                             session,
                             session_control_rx,
                             incoming_rx,
-                            connection.outgoing.clone(),
+                            connection.outgoing.get(qos_class).clone(),
                             outgoing_rx,
+                            flow_coalescing,
                         )
                         .await?;
-                        engine.spawn()
+                        engine.set_watchdog(connection.watchdog.clone());
+                        engine.set_clock(connection.idle_monitor.clock().clone());
+                        (engine.spawn(), remote_begin)
                     }
                     None => {
-                        let session = this.into_session(outgoing_channel, local_state);
-                        let engine = SessionEngine::begin_client_session(
+                        let session = this.into_session(outgoing_channel, state_watch, send_blocking_watch, transfer_ids_watch);
+                        let (mut engine, remote_begin) = SessionEngine::begin_client_session(
                             connection.control.clone(),
                             session,
                             session_control_rx,
                             incoming_rx,
-                            connection.outgoing.clone(),
+                            connection.outgoing.get(qos_class).clone(),
                             outgoing_rx,
+                            flow_coalescing,
                         )
                         .await?;
-                        engine.spawn()
+                        engine.set_watchdog(connection.watchdog.clone());
+                        engine.set_clock(connection.idle_monitor.clock().clone());
+                        (engine.spawn(), remote_begin)
                     }
                 }
             };
 
+            check_resumed_numbering(resumed_from.as_ref(), &remote_begin);
             let handle = SessionHandle {
                 is_ended: false,
                 control: session_control_tx,
                 engine_handle,
-                outcome,
+                outcome: outcome.into(),
                 outgoing: outgoing_tx,
                 link_listener: (),
+                remote_begin,
+                state,
+                send_blocking,
+                transfer_ids,
+                resumed_from,
+                #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+                authenticated_identity: None,
+                clock: connection.idle_monitor.clock().clone(),
             };
             Ok(handle)
         }
@@ -356,7 +550,13 @@ impl Builder {
             connection: &mut ConnectionHandle<()>,
             local_set: &tokio::task::LocalSet,
         ) -> Result<SessionHandle<()>, BeginError> {
-            let local_state = SessionState::Unmapped;
+            self.validate()?;
+            let (state_watch, state) = watch::channel(SessionState::Unmapped);
+            let (send_blocking_watch, send_blocking) = watch::channel(SendBlocking::default());
+            let (transfer_ids_watch, transfer_ids) = watch::channel(TransferIds::default());
+            let resumed_from = self.resumed_from;
+            let flow_coalescing = self.flow_coalescing;
+            let qos_class = self.qos_class;
             let (session_control_tx, session_control_rx) =
                 mpsc::channel::<SessionControl>(DEFAULT_SESSION_CONTROL_BUFFER_SIZE);
             let (incoming_tx, incoming_rx) = mpsc::channel(self.buffer_size);
@@ -374,27 +574,37 @@ impl Builder {
                 },
             };
 
-            let (engine_handle, outcome) = {
-                let session = self.into_session(outgoing_channel, local_state);
-                let engine = SessionEngine::begin_client_session(
+            let ((engine_handle, outcome), remote_begin) = {
+                let session = self.into_session(outgoing_channel, state_watch, send_blocking_watch, transfer_ids_watch);
+                let (mut engine, remote_begin) = SessionEngine::begin_client_session(
                     connection.control.clone(),
                     session,
                     session_control_rx,
                     incoming_rx,
-                    connection.outgoing.clone(),
+                    connection.outgoing.get(qos_class).clone(),
                     outgoing_rx,
+                    flow_coalescing,
                 )
                 .await?;
-                engine.spawn_on_local_set(local_set)
+                engine.set_watchdog(connection.watchdog.clone());
+                (engine.spawn_on_local_set(local_set), remote_begin)
             };
 
+            check_resumed_numbering(resumed_from.as_ref(), &remote_begin);
             let handle = SessionHandle {
                 is_ended: false,
                 control: session_control_tx,
                 engine_handle,
-                outcome,
+                outcome: outcome.into(),
                 outgoing: outgoing_tx,
                 link_listener: (),
+                remote_begin,
+                state,
+                send_blocking,
+                transfer_ids,
+                resumed_from,
+                #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+                authenticated_identity: None,
             };
             Ok(handle)
         }
@@ -416,7 +626,13 @@ impl Builder {
             self,
             connection: &mut ConnectionHandle<()>,
         ) -> Result<SessionHandle<()>, BeginError> {
-            let local_state = SessionState::Unmapped;
+            self.validate()?;
+            let (state_watch, state) = watch::channel(SessionState::Unmapped);
+            let (send_blocking_watch, send_blocking) = watch::channel(SendBlocking::default());
+            let (transfer_ids_watch, transfer_ids) = watch::channel(TransferIds::default());
+            let resumed_from = self.resumed_from;
+            let flow_coalescing = self.flow_coalescing;
+            let qos_class = self.qos_class;
             let (session_control_tx, session_control_rx) =
                 mpsc::channel::<SessionControl>(DEFAULT_SESSION_CONTROL_BUFFER_SIZE);
             let (incoming_tx, incoming_rx) = mpsc::channel(self.buffer_size);
@@ -434,29 +650,63 @@ impl Builder {
                 },
             };
 
-            let (engine_handle, outcome) = {
-                let session = self.into_session(outgoing_channel, local_state);
-                let engine = SessionEngine::begin_client_session(
+            let ((engine_handle, outcome), remote_begin) = {
+                let session = self.into_session(outgoing_channel, state_watch, send_blocking_watch, transfer_ids_watch);
+                let (mut engine, remote_begin) = SessionEngine::begin_client_session(
                     connection.control.clone(),
                     session,
                     session_control_rx,
                     incoming_rx,
-                    connection.outgoing.clone(),
+                    connection.outgoing.get(qos_class).clone(),
                     outgoing_rx,
+                    flow_coalescing,
                 )
                 .await?;
-                engine.spawn_local()
+                engine.set_watchdog(connection.watchdog.clone());
+                (engine.spawn_local(), remote_begin)
             };
 
+            check_resumed_numbering(resumed_from.as_ref(), &remote_begin);
             let handle = SessionHandle {
                 is_ended: false,
                 control: session_control_tx,
                 engine_handle,
-                outcome,
+                outcome: outcome.into(),
                 outgoing: outgoing_tx,
                 link_listener: (),
+                remote_begin,
+                state,
+                send_blocking,
+                transfer_ids,
+                resumed_from,
+                #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+                authenticated_identity: None,
             };
             Ok(handle)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::session::BeginError;
+
+    use super::Builder;
+
+    #[test]
+    fn validate_rejects_incoherent_configuration() {
+        assert!(Builder::new().validate().is_ok());
+        assert!(matches!(
+            Builder::new().incoming_window(0).validate(),
+            Err(BeginError::ZeroIncomingWindow)
+        ));
+        assert!(matches!(
+            Builder::new().outgoing_widnow(0).validate(),
+            Err(BeginError::ZeroOutgoingWindow)
+        ));
+        assert!(matches!(
+            Builder::new().buffer_size(0).validate(),
+            Err(BeginError::ZeroBufferSize)
+        ));
+    }
+}
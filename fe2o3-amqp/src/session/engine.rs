@@ -1,6 +1,6 @@
 use fe2o3_amqp_types::{
-    definitions::{self, AmqpError, SessionError},
-    performatives::End,
+    definitions::{self, AmqpError, Handle, LinkError, SessionError},
+    performatives::{Begin, End},
 };
 use tokio::{
     sync::{mpsc, oneshot},
@@ -10,16 +10,17 @@ use tokio::{
 use crate::{
     connection::{self},
     control::{ConnectionControl, SessionControl},
-    endpoint::{self, IncomingChannel, Session},
+    endpoint::{self, IncomingChannel, LinkFlow, Session},
     link::LinkFrame,
     util::Running,
+    watchdog::{self, EngineKind, Watchdog},
     SendBound,
 };
 
 use super::{
     error::{AllocLinkError, BeginError, Error, SessionInnerError},
     frame::{SessionIncomingItem, SessionOutgoingItem},
-    SessionFrame, SessionFrameBody, SessionState,
+    FlowCoalescer, FlowCoalescing, SessionFrame, SessionFrameBody, SessionState,
 };
 
 async fn send_outgoing_item(
@@ -49,6 +50,15 @@ async fn send_outgoing_item(
     Ok(())
 }
 
+/// Resolves when the pending flows held by the coalescer should be flushed. This never resolves
+/// if flow coalescing is not enabled
+async fn flow_coalescing_expired(flow_coalescer: &mut Option<FlowCoalescer>) {
+    match flow_coalescer {
+        Some(flow_coalescer) => flow_coalescer.expired().await,
+        None => std::future::pending().await,
+    }
+}
+
 pub(crate) struct SessionEngine<S: Session> {
     pub conn_control: mpsc::Sender<ConnectionControl>,
     pub session: S,
@@ -57,6 +67,8 @@ pub(crate) struct SessionEngine<S: Session> {
     pub outgoing: mpsc::Sender<SessionFrame>,
 
     pub outgoing_link_frames: mpsc::Receiver<LinkFrame>,
+    pub flow_coalescer: Option<FlowCoalescer>,
+    pub watchdog: Option<Watchdog>,
 }
 
 impl<S> SessionEngine<S>
@@ -64,6 +76,18 @@ where
     S: endpoint::Session,
     BeginError: From<S::BeginError>,
 {
+    pub(crate) fn set_watchdog(&mut self, watchdog: Option<Watchdog>) {
+        self.watchdog = watchdog;
+    }
+
+    /// Drives the timer of the flow coalescing with the clock of the connection
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn set_clock(&mut self, clock: crate::clock::SharedClock) {
+        if let Some(flow_coalescer) = &mut self.flow_coalescer {
+            flow_coalescer.set_clock(clock);
+        }
+    }
+
     pub(crate) async fn begin_client_session(
         conn_control: mpsc::Sender<ConnectionControl>,
         session: S,
@@ -71,7 +95,8 @@ where
         incoming: mpsc::Receiver<SessionIncomingItem>,
         outgoing: mpsc::Sender<SessionFrame>,
         outgoing_link_frames: mpsc::Receiver<LinkFrame>,
-    ) -> Result<Self, BeginError> {
+        flow_coalescing: Option<FlowCoalescing>,
+    ) -> Result<(Self, Begin), BeginError> {
         let mut engine = Self {
             conn_control,
             session,
@@ -79,6 +104,8 @@ where
             incoming,
             outgoing,
             outgoing_link_frames,
+            flow_coalescer: flow_coalescing.map(FlowCoalescer::new),
+            watchdog: None,
         };
 
         // send a begin
@@ -101,8 +128,10 @@ where
             },
             _ => return Err(BeginError::IllegalState),
         };
-        engine.session.on_incoming_begin(channel, remote_begin)?;
-        Ok(engine)
+        engine
+            .session
+            .on_incoming_begin(channel, remote_begin.clone())?;
+        Ok((engine, remote_begin))
     }
 }
 
@@ -114,8 +143,10 @@ cfg_not_wasm32! {
         SessionInnerError: From<S::Error> + From<S::BeginError> + From<S::EndError>,
     {
         pub fn spawn(self) -> (JoinHandle<()>, oneshot::Receiver<Result<(), Error>>) {
-            let (tx, rx) = oneshot::channel();
-            let handle = tokio::spawn(self.event_loop(tx));
+            let watchdog = self.watchdog.clone();
+            let (event_loop, rx) =
+                watchdog::supervise(EngineKind::Session, watchdog, |tx| self.event_loop(tx));
+            let handle = tokio::spawn(event_loop);
             (handle, rx)
         }
     }
@@ -129,14 +160,18 @@ cfg_wasm32! {
         SessionInnerError: From<S::Error> + From<S::BeginError> + From<S::EndError>,
     {
         pub fn spawn_local(self) -> (JoinHandle<()>, oneshot::Receiver<Result<(), Error>>) {
-            let (tx, rx) = oneshot::channel();
-            let handle = tokio::task::spawn_local(self.event_loop(tx));
+            let watchdog = self.watchdog.clone();
+            let (event_loop, rx) =
+                watchdog::supervise(EngineKind::Session, watchdog, |tx| self.event_loop(tx));
+            let handle = tokio::task::spawn_local(event_loop);
             (handle, rx)
         }
 
         pub fn spawn_on_local_set(self, local_set: &tokio::task::LocalSet) -> (JoinHandle<()>, oneshot::Receiver<Result<(), Error>>) {
-            let (tx, rx) = oneshot::channel();
-            let handle = local_set.spawn_local(self.event_loop(tx));
+            let watchdog = self.watchdog.clone();
+            let (event_loop, rx) =
+                watchdog::supervise(EngineKind::Session, watchdog, |tx| self.event_loop(tx));
+            let handle = local_set.spawn_local(event_loop);
             (handle, rx)
         }
     }
@@ -172,9 +207,27 @@ where
                 performative,
                 payload,
             } => {
-                self.session
+                let payload = match self.session.transfer_middleware() {
+                    Some(middleware) => middleware
+                        .0
+                        .on_incoming_transfer(&performative, payload)
+                        .await
+                        .map_err(SessionInnerError::TransferMiddleware)?,
+                    None => payload,
+                };
+                // A transactional resource informs the controller of the presumptive outcome
+                // of a posted transfer right away
+                if let Some(disposition) = self
+                    .session
                     .on_incoming_transfer(performative, payload)
-                    .await?;
+                    .await?
+                {
+                    let disposition = self.session.on_outgoing_disposition(disposition)?;
+                    self.outgoing
+                        .send(disposition)
+                        .await
+                        .map_err(|_| SessionInnerError::IllegalConnectionState)?;
+                }
             }
             SessionFrameBody::Disposition(disposition) => {
                 if let Some(dispositions) = self.session.on_incoming_disposition(disposition)? {
@@ -200,6 +253,7 @@ where
                     while let Some(frame) = self.outgoing_link_frames.recv().await {
                         self.on_outgoing_link_frames(frame).await?;
                     }
+                    self.flush_coalesced_flows().await?;
 
                     self.session.send_end(&self.outgoing, None).await?;
                 }
@@ -227,6 +281,7 @@ where
                 while let Some(frame) = self.outgoing_link_frames.recv().await {
                     self.on_outgoing_link_frames(frame).await?;
                 }
+                self.flush_coalesced_flows().await?;
 
                 self.session.send_end(&self.outgoing, error).await?;
             }
@@ -245,19 +300,61 @@ where
                 link_name,
                 link_relay,
                 input_handle,
+                steal_existing,
                 responder,
             } => {
-                let result =
-                    self.session
-                        .allocate_incoming_link(link_name, link_relay, input_handle);
+                if steal_existing {
+                    // The Detach must not overtake the frames that the links have already queued
+                    self.send_queued_link_frames().await?;
+                    let error = definitions::Error::new(
+                        LinkError::Stolen,
+                        Some(String::from("Link is attached again by the remote peer")),
+                        None,
+                    );
+                    if let Some(detach) = self.session.kick_link(&link_name, error).await {
+                        // Pending flows must not be sent after the link is detached
+                        self.flush_coalesced_flows().await?;
+                        self.outgoing
+                            .send(detach)
+                            .await
+                            .map_err(|_| SessionInnerError::IllegalConnectionState)?;
+                    }
+                }
+
+                let role = link_relay.role();
+                let result: Result<_, AllocLinkError> = self
+                    .session
+                    .allocate_incoming_link(link_name.clone(), link_relay, input_handle.clone())
+                    .map_err(Into::into);
+                if let Err(AllocLinkError::DuplicatedLinkName) = &result {
+                    // The attached link keeps the name and the session stays mapped
+                    self.send_queued_link_frames().await?;
+                    let error = definitions::Error::new(
+                        AmqpError::ResourceLocked,
+                        Some(format!("Link name {} is already in use", link_name)),
+                        None,
+                    );
+                    let frames =
+                        self.session
+                            .reject_incoming_link(link_name, role, input_handle, error);
+                    for frame in frames {
+                        self.outgoing
+                            .send(frame)
+                            .await
+                            .map_err(|_| SessionInnerError::IllegalConnectionState)?;
+                    }
+                }
                 responder
-                    .send(result.map_err(Into::into))
+                    .send(result)
                     // The receiving end (ie. link) must have been stopped
                     .map_err(|_| SessionInnerError::UnattachedHandle)?;
             }
             SessionControl::DeallocateLink(link_name) => {
                 self.session.deallocate_link(link_name);
             }
+            SessionControl::AbandonLink(input_handle) => {
+                self.session.abandon_link(input_handle);
+            }
             SessionControl::Disposition(disposition) => {
                 let disposition = self.session.on_outgoing_disposition(disposition)?;
                 self.outgoing
@@ -275,6 +372,28 @@ where
                     .await
                     .map_err(|_| SessionInnerError::IllegalConnectionState)?;
             }
+            SessionControl::GetLinks(resp) => {
+                let _ = resp.send(self.session.links());
+            }
+            SessionControl::KickLink {
+                link_name,
+                error,
+                responder,
+            } => {
+                let kicked = match self.session.kick_link(&link_name, error).await {
+                    Some(detach) => {
+                        // Pending flows must not be sent after the link is detached
+                        self.flush_coalesced_flows().await?;
+                        self.outgoing
+                            .send(detach)
+                            .await
+                            .map_err(|_| SessionInnerError::IllegalConnectionState)?;
+                        true
+                    }
+                    None => false,
+                };
+                let _ = responder.send(kicked);
+            }
             SessionControl::GetMaxFrameSize(resp) => {
                 self.conn_control
                     .send(ConnectionControl::GetMaxFrameSize(resp))
@@ -312,6 +431,14 @@ where
         }
     }
 
+    /// Sends the frames that the links have already queued
+    async fn send_queued_link_frames(&mut self) -> Result<(), SessionInnerError> {
+        while let Ok(frame) = self.outgoing_link_frames.try_recv() {
+            self.on_outgoing_link_frames(frame).await?;
+        }
+        Ok(())
+    }
+
     #[inline]
     async fn on_outgoing_link_frames(
         &mut self,
@@ -322,38 +449,63 @@ where
             _ => return Err(SessionInnerError::IllegalState), // End session with illegal state
         }
 
+        // The local link is not aware yet that it is detached by an administrator
+        if self.session.on_kicked_link_frame(&frame) {
+            return Ok(Running::Continue);
+        }
+
+        // Pending flows must not be sent after the link is detached
+        if let LinkFrame::Detach(_) = &frame {
+            self.flush_coalesced_flows().await?;
+        }
+
+        // A pending flow of a sending link must not overtake its transfers
+        if let LinkFrame::Transfer { performative, .. } = &frame {
+            self.flush_coalesced_flow_of(&performative.handle).await?;
+        }
+
         let outgoing_item = match frame {
             LinkFrame::Attach(attach) => self
                 .session
                 .on_outgoing_attach(attach)
                 .map(SessionOutgoingItem::SingleFrame)
                 .map(Some)?,
-            LinkFrame::Flow(flow) => self
-                .session
-                .on_outgoing_flow(flow)
-                .map(SessionOutgoingItem::SingleFrame)
-                .map(Some)?,
+            LinkFrame::Flow(flow) => match self.session.hold_outgoing_flow(flow) {
+                Some(flow) => self.on_outgoing_link_flow(flow)?,
+                None => None,
+            },
             LinkFrame::Transfer {
                 input_handle,
                 performative,
                 payload,
-            } => self
-                .session
-                .on_outgoing_transfer(input_handle, performative, payload)?,
+            } => {
+                let payload = match self.session.transfer_middleware() {
+                    Some(middleware) => middleware
+                        .0
+                        .on_outgoing_transfer(&performative, payload)
+                        .await
+                        .map_err(SessionInnerError::TransferMiddleware)?,
+                    None => payload,
+                };
+                self.session
+                    .on_outgoing_transfer(input_handle, performative, payload)?
+            }
             LinkFrame::Disposition(disposition) => self
                 .session
                 .on_outgoing_disposition(disposition)
                 .map(SessionOutgoingItem::SingleFrame)
                 .map(Some)?,
+            LinkFrame::Settle {
+                input_handle,
+                delivery_tag,
+                state,
+            } => self
+                .session
+                .on_outgoing_settlement(input_handle, delivery_tag, state)
+                .map(SessionOutgoingItem::SingleFrame),
             LinkFrame::Detach(detach) => Some(SessionOutgoingItem::SingleFrame(
                 self.session.on_outgoing_detach(detach),
             )),
-
-            #[cfg(feature = "transaction")]
-            LinkFrame::Acquisition(_) => {
-                // This is purely used to notify sender about TxnAcquisition, which is not implemented
-                unreachable!("LinkFrame::Acquisition should not appear in outgoing link frames")
-            }
         };
 
         if let Some(outgoing_item) = outgoing_item {
@@ -366,6 +518,83 @@ where
         }
     }
 
+    fn on_outgoing_link_flow(
+        &mut self,
+        flow: LinkFlow,
+    ) -> Result<Option<SessionOutgoingItem>, SessionInnerError> {
+        match &mut self.flow_coalescer {
+            Some(flow_coalescer) => match flow_coalescer.push(flow) {
+                Some(flows) => self
+                    .prepare_flow_frames(flows)
+                    .map(SessionOutgoingItem::MultipleFrames)
+                    .map(Some),
+                None => Ok(None),
+            },
+            None => self
+                .session
+                .on_outgoing_flow(flow)
+                .map(SessionOutgoingItem::SingleFrame)
+                .map(Some)
+                .map_err(Into::into),
+        }
+    }
+
+    /// Sends the link flows that were held back because of the backlog of the links
+    async fn on_released_link_flows(
+        &mut self,
+        flows: Vec<LinkFlow>,
+    ) -> Result<Running, SessionInnerError> {
+        for flow in flows {
+            if let Some(outgoing_item) = self.on_outgoing_link_flow(flow)? {
+                send_outgoing_item(&self.outgoing, outgoing_item).await?;
+            }
+        }
+        Ok(Running::Continue)
+    }
+
+    fn prepare_flow_frames(
+        &mut self,
+        flows: Vec<LinkFlow>,
+    ) -> Result<Vec<SessionFrame>, SessionInnerError> {
+        flows
+            .into_iter()
+            .map(|flow| self.session.on_outgoing_flow(flow).map_err(Into::into))
+            .collect()
+    }
+
+    fn has_coalesced_flows(&self) -> bool {
+        self.flow_coalescer
+            .as_ref()
+            .map(|flow_coalescer| flow_coalescer.has_pending())
+            .unwrap_or(false)
+    }
+
+    /// Sends all flows that are held back by the coalescer
+    async fn flush_coalesced_flows(&mut self) -> Result<Running, SessionInnerError> {
+        let flows = match &mut self.flow_coalescer {
+            Some(flow_coalescer) => flow_coalescer.take_pending(),
+            None => return Ok(Running::Continue),
+        };
+        if !flows.is_empty() {
+            let frames = self.prepare_flow_frames(flows)?;
+            send_outgoing_item(&self.outgoing, SessionOutgoingItem::MultipleFrames(frames)).await?;
+        }
+        Ok(Running::Continue)
+    }
+
+    /// Sends the flow held back by the coalescer for the link with the given output handle
+    async fn flush_coalesced_flow_of(&mut self, handle: &Handle) -> Result<(), SessionInnerError> {
+        let flow = match &mut self.flow_coalescer {
+            Some(flow_coalescer) => flow_coalescer.take_pending_of(handle),
+            None => None,
+        };
+        if let Some(flow) = flow {
+            let frame = self.session.on_outgoing_flow(flow)?;
+            send_outgoing_item(&self.outgoing, SessionOutgoingItem::SingleFrame(frame)).await?;
+        }
+        Ok(())
+    }
+
     #[inline]
     async fn on_error(&mut self, kind: &SessionInnerError) -> Result<Running, SessionInnerError> {
         use definitions::Error;
@@ -407,6 +636,9 @@ where
             SessionInnerError::RemoteEnded | SessionInnerError::RemoteEndedWithError(_) => {
                 self.end_session(None).await
             }
+            SessionInnerError::TransferMiddleware(error) => {
+                self.end_session(Some(error.clone())).await
+            }
 
             #[cfg(not(target_arch = "wasm32"))]
             #[cfg(all(feature = "transaction", feature = "acceptor"))]
@@ -555,6 +787,12 @@ where
                             Ok(Running::Continue)
                         }
                     }
+                },
+                _ = flow_coalescing_expired(&mut self.flow_coalescer), if self.has_coalesced_flows() => {
+                    self.flush_coalesced_flows().await
+                },
+                flows = self.session.dispatch_inbound_backlog(), if self.session.has_inbound_backlog() => {
+                    self.on_released_link_flows(flows).await
                 }
             };
 
@@ -3,7 +3,7 @@
 use fe2o3_amqp_types::definitions::{self};
 use tokio::task::JoinError;
 
-use crate::link::LinkRelayError;
+use crate::{link::LinkRelayError, util::ReplayError, watchdog::EnginePanic};
 
 /// Error with ending a session
 #[derive(Debug, thiserror::Error)]
@@ -47,6 +47,25 @@ pub enum BeginError {
     /// Channel max reached
     #[error("Local channel-max reached")]
     LocalChannelMaxReached,
+
+    /// The configured incoming-window is zero, so the remote peer can never send a transfer
+    #[error("incoming-window must be greater than zero")]
+    ZeroIncomingWindow,
+
+    /// The configured outgoing-window is zero, so no transfer can ever be sent
+    #[error("outgoing-window must be greater than zero")]
+    ZeroOutgoingWindow,
+
+    /// The configured buffer size is zero
+    #[error("Buffer size must be greater than zero")]
+    ZeroBufferSize,
+
+    /// The incoming session is refused because the maximum number of sessions of the connection
+    /// is reached
+    #[cfg_attr(docsrs, doc(cfg(feature = "acceptor")))]
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    #[error("Session limit of the connection is reached")]
+    SessionLimitReached,
 }
 
 impl From<SessionStateError> for BeginError {
@@ -94,6 +113,10 @@ pub(crate) enum SessionInnerError {
     #[error("Remote ended with error")]
     RemoteEndedWithError(definitions::Error),
 
+    /// The transfer middleware failed to transform a payload
+    #[error("Transfer middleware error: {}", .0)]
+    TransferMiddleware(definitions::Error),
+
     /// Unknown transaction ID
     #[cfg(not(target_arch = "wasm32"))]
     #[cfg(all(feature = "transaction", feature = "acceptor"))]
@@ -162,12 +185,48 @@ pub enum Error {
     #[deprecated]
     JoinError(#[from] JoinError),
 
+    /// The transfer middleware failed to transform a payload. The session is ended with the
+    /// error
+    #[error("Transfer middleware error: {}", .0)]
+    TransferMiddleware(definitions::Error),
+
+    /// The event loop of the session panicked
+    #[error(transparent)]
+    EnginePanicked(#[from] EnginePanic),
+
     /// Unknown transaction ID
     #[cfg(all(feature = "transaction", feature = "acceptor"))]
     #[error("Unknown transaction ID")]
     UnknownTxnId,
 }
 
+impl ReplayError for Error {
+    fn replay(&self) -> Self {
+        match self {
+            Self::UnattachedHandle => Self::UnattachedHandle,
+            Self::RemoteAttachingLinkNameNotFound => Self::RemoteAttachingLinkNameNotFound,
+            Self::HandleInUse => Self::HandleInUse,
+            Self::IllegalState => Self::IllegalState,
+            Self::IllegalConnectionState => Self::IllegalConnectionState,
+            Self::TransferFrameToSender => Self::TransferFrameToSender,
+            Self::RemoteEnded => Self::RemoteEnded,
+            Self::RemoteEndedWithError(err) => Self::RemoteEndedWithError(err.clone()),
+            // The event loop is not joined, so this is not expected to be the outcome
+            #[allow(deprecated)]
+            Self::JoinError(_) => Self::IllegalState,
+            Self::TransferMiddleware(err) => Self::TransferMiddleware(err.clone()),
+            Self::EnginePanicked(panic) => Self::EnginePanicked(panic.clone()),
+
+            #[cfg(all(feature = "transaction", feature = "acceptor"))]
+            Self::UnknownTxnId => Self::UnknownTxnId,
+        }
+    }
+
+    fn stopped() -> Self {
+        Self::IllegalState
+    }
+}
+
 impl From<SessionInnerError> for Error {
     fn from(error: SessionInnerError) -> Self {
         match error {
@@ -181,6 +240,7 @@ impl From<SessionInnerError> for Error {
             SessionInnerError::TransferFrameToSender => Self::TransferFrameToSender,
             SessionInnerError::RemoteEnded => Self::RemoteEnded,
             SessionInnerError::RemoteEndedWithError(err) => Self::RemoteEndedWithError(err),
+            SessionInnerError::TransferMiddleware(err) => Self::TransferMiddleware(err),
 
             #[cfg(not(target_arch = "wasm32"))]
             #[cfg(all(feature = "transaction", feature = "acceptor"))]
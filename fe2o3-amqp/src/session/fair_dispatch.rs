@@ -0,0 +1,379 @@
+//! Fair dispatching of incoming transfers to the links on a session
+
+use std::collections::VecDeque;
+
+use fe2o3_amqp_types::definitions::Handle;
+use futures_util::future::select_all;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::{
+    endpoint::LinkFlow,
+    link::{LinkFrame, LinkRelayError},
+};
+
+/// Default number of queued incoming transfers after which a link is considered backlogged
+pub const DEFAULT_MAX_LINK_BACKLOG: usize = 64;
+
+/// What to do with a link whose inbound queue has reached
+/// [`max_link_backlog`](FairDispatch::max_link_backlog)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverloadPolicy {
+    /// Keep queueing incoming transfers for the link. The queue is then only bounded by the
+    /// link credit issued to the remote peer
+    Queue,
+
+    /// Hold back the link credit replenishment (ie. outgoing link flow frames that don't set
+    /// `drain` or `echo` nor carry properties) of the link until its queue drops below the limit
+    #[default]
+    PauseReplenishment,
+}
+
+/// Configuration for dispatching incoming transfers fairly among the links on a session
+///
+/// By default, the session waits for a link to take an incoming transfer before handling the
+/// next incoming frame, so a slow receiver can hold up all other links on the same session.
+/// With fair dispatch enabled, transfers for a link that is not keeping up are put into a
+/// per-link inbound queue instead, and the queues are drained in a round-robin manner.
+///
+/// | Field | Default Value |
+/// |-------|---------------|
+/// |`max_link_backlog`| [`DEFAULT_MAX_LINK_BACKLOG`] |
+/// |`overload_policy`| [`OverloadPolicy::PauseReplenishment`] |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FairDispatch {
+    /// The number of queued incoming transfers after which a link is considered backlogged
+    pub max_link_backlog: usize,
+
+    /// What to do with a backlogged link
+    pub overload_policy: OverloadPolicy,
+}
+
+impl Default for FairDispatch {
+    fn default() -> Self {
+        Self {
+            max_link_backlog: DEFAULT_MAX_LINK_BACKLOG,
+            overload_policy: OverloadPolicy::default(),
+        }
+    }
+}
+
+impl FairDispatch {
+    /// Creates a new fair dispatch configuration
+    pub fn new(max_link_backlog: usize, overload_policy: OverloadPolicy) -> Self {
+        Self {
+            max_link_backlog,
+            overload_policy,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct InboundQueue {
+    output_handle: Handle,
+    tx: mpsc::Sender<LinkFrame>,
+    frames: VecDeque<LinkFrame>,
+}
+
+#[derive(Debug)]
+pub(crate) struct InboundDispatcher {
+    config: FairDispatch,
+    // Only links with queued frames are kept
+    queues: Vec<InboundQueue>,
+    // Index of the queue that is served first in the next round
+    cursor: usize,
+    held_flows: Vec<LinkFlow>,
+}
+
+impl InboundDispatcher {
+    pub fn new(config: FairDispatch) -> Self {
+        Self {
+            config,
+            queues: Vec::new(),
+            cursor: 0,
+            held_flows: Vec::new(),
+        }
+    }
+
+    pub fn has_backlog(&self) -> bool {
+        !self.queues.is_empty()
+    }
+
+    pub fn backlog_len(&self, output_handle: &Handle) -> usize {
+        self.queues
+            .iter()
+            .find(|queue| &queue.output_handle == output_handle)
+            .map(|queue| queue.frames.len())
+            .unwrap_or(0)
+    }
+
+    pub fn is_overloaded(&self, output_handle: &Handle) -> bool {
+        match self.config.overload_policy {
+            OverloadPolicy::Queue => false,
+            OverloadPolicy::PauseReplenishment => {
+                self.backlog_len(output_handle) >= self.config.max_link_backlog
+            }
+        }
+    }
+
+    /// Forwards the frame to the link without waiting. The frame is queued if the link is not
+    /// able to take it immediately or if there are frames already queued for the link
+    pub fn dispatch(
+        &mut self,
+        output_handle: &Handle,
+        tx: &mpsc::Sender<LinkFrame>,
+        frame: LinkFrame,
+    ) -> Result<(), LinkRelayError> {
+        if let Some(queue) = self
+            .queues
+            .iter_mut()
+            .find(|queue| &queue.output_handle == output_handle)
+        {
+            queue.frames.push_back(frame);
+            return Ok(());
+        }
+
+        match tx.try_send(frame) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(frame)) => {
+                let mut frames = VecDeque::new();
+                frames.push_back(frame);
+                self.queues.push(InboundQueue {
+                    output_handle: output_handle.clone(),
+                    tx: tx.clone(),
+                    frames,
+                });
+                Ok(())
+            }
+            Err(TrySendError::Closed(_)) => Err(LinkRelayError::UnattachedHandle),
+        }
+    }
+
+    /// Queues the frame behind the frames that are already queued for the link. Returns the
+    /// frame if nothing is queued for the link
+    pub fn enqueue_if_backlogged(
+        &mut self,
+        output_handle: &Handle,
+        frame: LinkFrame,
+    ) -> Option<LinkFrame> {
+        match self
+            .queues
+            .iter_mut()
+            .find(|queue| &queue.output_handle == output_handle)
+        {
+            Some(queue) => {
+                queue.frames.push_back(frame);
+                None
+            }
+            None => Some(frame),
+        }
+    }
+
+    /// Waits until one of the backlogged links is able to take a frame and forwards one frame
+    /// to that link. Links are served in a round-robin manner.
+    ///
+    /// Returns the held flows that can now be sent.
+    ///
+    /// This is cancel safe because a frame is only taken from a queue once the link has
+    /// capacity for it
+    pub async fn dispatch_backlog(&mut self) -> Vec<LinkFlow> {
+        if self.queues.is_empty() {
+            return std::future::pending().await;
+        }
+
+        let len = self.queues.len();
+        let start = self.cursor % len;
+        let reservations = (0..len).map(|i| {
+            let index = (start + i) % len;
+            let tx = self.queues[index].tx.clone();
+            Box::pin(async move { (index, tx.reserve_owned().await) })
+        });
+        // `select_all` resolves with the first ready future in iteration order
+        let ((index, reservation), _, _) = select_all(reservations).await;
+
+        match reservation {
+            Ok(permit) => {
+                let queue = &mut self.queues[index];
+                if let Some(frame) = queue.frames.pop_front() {
+                    permit.send(frame);
+                }
+                if queue.frames.is_empty() {
+                    self.queues.remove(index);
+                    self.cursor = index;
+                } else {
+                    self.cursor = index + 1;
+                }
+            }
+            Err(_) => {
+                // The link has dropped, and the queued frames are no longer needed
+                let _queue = self.queues.remove(index);
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    handle = ?_queue.output_handle,
+                    "Dropping inbound queue of a stopped link"
+                );
+                #[cfg(feature = "log")]
+                log::debug!(
+                    "Dropping inbound queue of a stopped link {:?}",
+                    _queue.output_handle
+                );
+                self.cursor = index;
+            }
+        }
+
+        self.release_held_flows()
+    }
+
+    /// Returns `Some(_)` if the flow should be sent immediately, or `None` if the flow is held
+    /// back until the backlog of the link drops below the limit
+    pub fn hold_flow(&mut self, flow: LinkFlow) -> Option<LinkFlow> {
+        if flow.drain || flow.echo || flow.properties.is_some() || !self.is_overloaded(&flow.handle)
+        {
+            // The new flow carries the latest link state and supersedes the held one
+            self.held_flows.retain(|held| held.handle != flow.handle);
+            return Some(flow);
+        }
+
+        match self
+            .held_flows
+            .iter_mut()
+            .find(|held| held.handle == flow.handle)
+        {
+            Some(held) => *held = flow,
+            None => self.held_flows.push(flow),
+        }
+        None
+    }
+
+    /// Discards the held flow of a link that is detaching
+    pub fn discard_held_flow(&mut self, output_handle: &Handle) {
+        self.held_flows.retain(|held| &held.handle != output_handle);
+    }
+
+    fn release_held_flows(&mut self) -> Vec<LinkFlow> {
+        let (held, released) = std::mem::take(&mut self.held_flows)
+            .into_iter()
+            .partition(|flow| self.is_overloaded(&flow.handle));
+        self.held_flows = held;
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fe2o3_amqp_types::{
+        definitions::{Fields, Handle},
+        performatives::Detach,
+    };
+    use tokio::sync::mpsc;
+
+    use crate::{endpoint::LinkFlow, link::LinkFrame};
+
+    use super::{FairDispatch, InboundDispatcher, OverloadPolicy};
+
+    fn detach(handle: u32) -> LinkFrame {
+        LinkFrame::Detach(Detach {
+            handle: Handle(handle),
+            closed: false,
+            error: None,
+        })
+    }
+
+    fn handle_of(frame: LinkFrame) -> u32 {
+        match frame {
+            LinkFrame::Detach(detach) => detach.handle.0,
+            _ => unreachable!(),
+        }
+    }
+
+    fn link_flow(handle: u32, link_credit: u32) -> LinkFlow {
+        LinkFlow {
+            handle: Handle(handle),
+            link_credit: Some(link_credit),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_link_does_not_block_other_links() {
+        let mut dispatcher = InboundDispatcher::new(FairDispatch::default());
+        let (slow_tx, mut slow_rx) = mpsc::channel(1);
+        let (fast_tx, mut fast_rx) = mpsc::channel(8);
+
+        dispatcher
+            .dispatch(&Handle(0), &slow_tx, detach(0))
+            .unwrap();
+        dispatcher
+            .dispatch(&Handle(0), &slow_tx, detach(0))
+            .unwrap();
+        dispatcher
+            .dispatch(&Handle(1), &fast_tx, detach(1))
+            .unwrap();
+        assert_eq!(dispatcher.backlog_len(&Handle(0)), 1);
+        assert_eq!(dispatcher.backlog_len(&Handle(1)), 0);
+        assert_eq!(handle_of(fast_rx.try_recv().unwrap()), 1);
+
+        assert_eq!(handle_of(slow_rx.recv().await.unwrap()), 0);
+        dispatcher.dispatch_backlog().await;
+        assert!(!dispatcher.has_backlog());
+        assert_eq!(handle_of(slow_rx.recv().await.unwrap()), 0);
+    }
+
+    #[tokio::test]
+    async fn backlogged_links_are_served_in_turn() {
+        let mut dispatcher = InboundDispatcher::new(FairDispatch::default());
+        let (tx0, mut rx0) = mpsc::channel(1);
+        let (tx1, mut rx1) = mpsc::channel(1);
+
+        for _ in 0..3 {
+            dispatcher.dispatch(&Handle(0), &tx0, detach(0)).unwrap();
+            dispatcher.dispatch(&Handle(1), &tx1, detach(1)).unwrap();
+        }
+        let _ = rx0.recv().await.unwrap();
+        let _ = rx1.recv().await.unwrap();
+
+        // Both links have capacity, and each of them gets one frame
+        dispatcher.dispatch_backlog().await;
+        dispatcher.dispatch_backlog().await;
+        assert_eq!(dispatcher.backlog_len(&Handle(0)), 1);
+        assert_eq!(dispatcher.backlog_len(&Handle(1)), 1);
+    }
+
+    #[tokio::test]
+    async fn flow_of_overloaded_link_is_held_back() {
+        let config = FairDispatch::new(1, OverloadPolicy::PauseReplenishment);
+        let mut dispatcher = InboundDispatcher::new(config);
+        let (tx, mut rx) = mpsc::channel(1);
+
+        dispatcher.dispatch(&Handle(0), &tx, detach(0)).unwrap();
+        dispatcher.dispatch(&Handle(0), &tx, detach(0)).unwrap();
+        assert!(dispatcher.is_overloaded(&Handle(0)));
+        assert!(dispatcher.hold_flow(link_flow(0, 1)).is_none());
+        assert!(dispatcher.hold_flow(link_flow(0, 2)).is_none());
+        assert!(dispatcher.hold_flow(link_flow(1, 1)).is_some());
+
+        let mut echo = link_flow(0, 3);
+        echo.echo = true;
+        assert!(dispatcher.hold_flow(echo).is_some());
+        let mut with_properties = link_flow(0, 3);
+        with_properties.properties = Some(Fields::new());
+        assert!(dispatcher.hold_flow(with_properties).is_some());
+        assert!(dispatcher.hold_flow(link_flow(0, 4)).is_none());
+
+        let _ = rx.recv().await.unwrap();
+        let released = dispatcher.dispatch_backlog().await;
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].link_credit, Some(4));
+    }
+
+    #[tokio::test]
+    async fn queue_policy_never_holds_flows() {
+        let config = FairDispatch::new(1, OverloadPolicy::Queue);
+        let mut dispatcher = InboundDispatcher::new(config);
+        let (tx, _rx) = mpsc::channel(1);
+
+        dispatcher.dispatch(&Handle(0), &tx, detach(0)).unwrap();
+        dispatcher.dispatch(&Handle(0), &tx, detach(0)).unwrap();
+        assert!(!dispatcher.is_overloaded(&Handle(0)));
+        assert!(dispatcher.hold_flow(link_flow(0, 1)).is_some());
+    }
+}
@@ -0,0 +1,293 @@
+//! Coalescing of outgoing link flow frames
+
+use std::time::Duration;
+
+use fe2o3_amqp_types::{definitions::Handle, performatives::Flow};
+
+use crate::{endpoint::LinkFlow, util::IdleTimeout};
+
+/// Default maximum time a pending flow update is held back
+pub const DEFAULT_FLOW_COALESCING_DELAY: Duration = Duration::from_millis(5);
+
+/// Default maximum number of pending flow updates before they are flushed
+pub const DEFAULT_FLOW_COALESCING_MAX_PENDING: usize = 64;
+
+/// Default maximum encoded size in bytes of the pending flow updates before they are flushed
+pub const DEFAULT_FLOW_COALESCING_MAX_PENDING_BYTES: usize = 2048;
+
+/// Configuration for merging outgoing link flow updates on a session
+///
+/// A flow frame always carries the latest link flow state, so pending updates for the same link
+/// can be safely replaced by the newer one. Flow frames that set either `drain` or `echo`, or
+/// that carry properties, are never delayed. A pending update is sent before the next transfer
+/// of the same link, so that the peer never sees an update older than a transfer it has already
+/// received.
+///
+/// The pending updates are flushed once the `delay` has elapsed since the first of them, or
+/// once either the number or the encoded size of the pending flow frames reaches its limit.
+///
+/// | Field | Default Value |
+/// |-------|---------------|
+/// |`delay`| [`DEFAULT_FLOW_COALESCING_DELAY`] |
+/// |`max_pending`| [`DEFAULT_FLOW_COALESCING_MAX_PENDING`] |
+/// |`max_pending_bytes`| [`DEFAULT_FLOW_COALESCING_MAX_PENDING_BYTES`] |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowCoalescing {
+    /// The maximum time a pending flow update is held back before being sent
+    pub delay: Duration,
+
+    /// The maximum number of links with pending flow updates. All pending updates are sent
+    /// once this is reached
+    pub max_pending: usize,
+
+    /// The maximum encoded size in bytes of the pending flow frames. All pending updates are
+    /// sent once this is reached
+    pub max_pending_bytes: usize,
+}
+
+impl Default for FlowCoalescing {
+    fn default() -> Self {
+        Self {
+            delay: DEFAULT_FLOW_COALESCING_DELAY,
+            max_pending: DEFAULT_FLOW_COALESCING_MAX_PENDING,
+            max_pending_bytes: DEFAULT_FLOW_COALESCING_MAX_PENDING_BYTES,
+        }
+    }
+}
+
+impl FlowCoalescing {
+    /// Creates a new flow coalescing configuration
+    pub fn new(delay: Duration, max_pending: usize) -> Self {
+        Self {
+            delay,
+            max_pending,
+            max_pending_bytes: DEFAULT_FLOW_COALESCING_MAX_PENDING_BYTES,
+        }
+    }
+
+    /// Sets the maximum encoded size in bytes of the pending flow frames
+    pub fn max_pending_bytes(mut self, value: usize) -> Self {
+        self.max_pending_bytes = value;
+        self
+    }
+}
+
+/// The encoded size of the flow frame that is going to carry the link flow. The session flow
+/// state is only known when the frame is sent, so its largest encoding is assumed
+fn encoded_frame_size(flow: &LinkFlow) -> usize {
+    let flow = Flow {
+        next_incoming_id: Some(u32::MAX),
+        incoming_window: u32::MAX,
+        next_outgoing_id: u32::MAX,
+        outgoing_window: u32::MAX,
+        handle: Some(flow.handle.clone()),
+        delivery_count: flow.delivery_count,
+        link_credit: flow.link_credit,
+        available: flow.available,
+        drain: flow.drain,
+        echo: flow.echo,
+        properties: flow.properties.clone(),
+    };
+    // 8 bytes of frame header
+    8 + serde_amqp::serialized_size(&flow).unwrap_or(0)
+}
+
+#[derive(Debug)]
+pub(crate) struct FlowCoalescer {
+    config: FlowCoalescing,
+    pending: Vec<LinkFlow>,
+    pending_bytes: usize,
+    timer: IdleTimeout,
+}
+
+impl FlowCoalescer {
+    pub fn new(config: FlowCoalescing) -> Self {
+        Self {
+            config,
+            pending: Vec::new(),
+            pending_bytes: 0,
+            timer: IdleTimeout::new(config.delay),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_clock(&mut self, clock: crate::clock::SharedClock) {
+        self.timer = IdleTimeout::with_clock(self.config.delay, clock);
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Returns `Some(_)` with the flows that should be sent immediately, or `None` if the flow
+    /// is held back
+    pub fn push(&mut self, flow: LinkFlow) -> Option<Vec<LinkFlow>> {
+        let position = self.pending.iter().position(|f| f.handle == flow.handle);
+
+        if flow.drain || flow.echo || flow.properties.is_some() {
+            // The new flow carries the latest link state and supersedes the pending one
+            if let Some(position) = position {
+                self.remove_pending(position);
+            }
+            return Some(vec![flow]);
+        }
+
+        self.pending_bytes += encoded_frame_size(&flow);
+        match position {
+            Some(position) => {
+                let replaced = std::mem::replace(&mut self.pending[position], flow);
+                self.pending_bytes -= encoded_frame_size(&replaced);
+            }
+            None => {
+                if self.pending.is_empty() {
+                    self.timer.reset();
+                }
+                self.pending.push(flow);
+            }
+        }
+
+        if self.pending.len() >= self.config.max_pending
+            || self.pending_bytes >= self.config.max_pending_bytes
+        {
+            Some(self.take_pending())
+        } else {
+            None
+        }
+    }
+
+    /// Removes the pending flow of the link with the given output handle
+    pub fn take_pending_of(&mut self, handle: &Handle) -> Option<LinkFlow> {
+        let position = self.pending.iter().position(|f| &f.handle == handle)?;
+        Some(self.remove_pending(position))
+    }
+
+    pub fn take_pending(&mut self) -> Vec<LinkFlow> {
+        self.pending_bytes = 0;
+        std::mem::take(&mut self.pending)
+    }
+
+    fn remove_pending(&mut self, position: usize) -> LinkFlow {
+        let flow = self.pending.remove(position);
+        self.pending_bytes -= encoded_frame_size(&flow);
+        flow
+    }
+
+    pub async fn expired(&mut self) {
+        let _ = (&mut self.timer).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fe2o3_amqp_types::definitions::{Fields, Handle};
+
+    use crate::endpoint::LinkFlow;
+
+    use super::{FlowCoalescer, FlowCoalescing};
+
+    fn link_flow(handle: u32, link_credit: u32) -> LinkFlow {
+        LinkFlow {
+            handle: Handle(handle),
+            link_credit: Some(link_credit),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn pending_flow_is_replaced_by_newer_flow() {
+        let config = FlowCoalescing::new(Duration::from_secs(1), 8);
+        let mut coalescer = FlowCoalescer::new(config);
+
+        assert!(coalescer.push(link_flow(0, 1)).is_none());
+        assert!(coalescer.push(link_flow(0, 2)).is_none());
+        assert!(coalescer.push(link_flow(1, 3)).is_none());
+
+        let flows = coalescer.take_pending();
+        assert_eq!(flows.len(), 2);
+        assert_eq!(flows[0].link_credit, Some(2));
+        assert_eq!(flows[1].link_credit, Some(3));
+        assert!(!coalescer.has_pending());
+    }
+
+    #[tokio::test]
+    async fn drain_and_echo_are_sent_immediately() {
+        let config = FlowCoalescing::new(Duration::from_secs(1), 8);
+        let mut coalescer = FlowCoalescer::new(config);
+
+        assert!(coalescer.push(link_flow(0, 1)).is_none());
+        let mut drain = link_flow(0, 2);
+        drain.drain = true;
+        let flows = coalescer.push(drain).unwrap();
+        assert_eq!(flows.len(), 1);
+        assert!(flows[0].drain);
+        assert!(!coalescer.has_pending());
+
+        let mut echo = link_flow(1, 2);
+        echo.echo = true;
+        assert_eq!(coalescer.push(echo).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn flow_with_properties_is_sent_immediately() {
+        let config = FlowCoalescing::new(Duration::from_secs(1), 8);
+        let mut coalescer = FlowCoalescer::new(config);
+
+        assert!(coalescer.push(link_flow(0, 1)).is_none());
+        let mut with_properties = link_flow(0, 2);
+        with_properties.properties = Some(Fields::new());
+        let flows = coalescer.push(with_properties).unwrap();
+        assert_eq!(flows.len(), 1);
+        assert!(flows[0].properties.is_some());
+        assert!(!coalescer.has_pending());
+    }
+
+    #[tokio::test]
+    async fn pending_flow_of_a_link_is_taken_alone() {
+        let config = FlowCoalescing::new(Duration::from_secs(1), 8);
+        let mut coalescer = FlowCoalescer::new(config);
+
+        assert!(coalescer.push(link_flow(0, 1)).is_none());
+        assert!(coalescer.push(link_flow(1, 2)).is_none());
+
+        let flow = coalescer.take_pending_of(&Handle(1)).unwrap();
+        assert_eq!(flow.link_credit, Some(2));
+        assert!(coalescer.take_pending_of(&Handle(1)).is_none());
+
+        let flows = coalescer.take_pending();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].handle, Handle(0));
+    }
+
+    #[tokio::test]
+    async fn flush_when_max_pending_is_reached() {
+        let config = FlowCoalescing::new(Duration::from_secs(1), 2);
+        let mut coalescer = FlowCoalescer::new(config);
+
+        assert!(coalescer.push(link_flow(0, 1)).is_none());
+        let flows = coalescer.push(link_flow(1, 1)).unwrap();
+        assert_eq!(flows.len(), 2);
+        assert!(!coalescer.has_pending());
+    }
+
+    #[tokio::test]
+    async fn flush_when_max_pending_bytes_is_reached() {
+        let frame_size = super::encoded_frame_size(&link_flow(0, 1));
+        let config =
+            FlowCoalescing::new(Duration::from_secs(1), 8).max_pending_bytes(frame_size * 2);
+        let mut coalescer = FlowCoalescer::new(config);
+
+        // Replacing the pending flow of the same link doesn't add to the pending bytes
+        assert!(coalescer.push(link_flow(0, 1)).is_none());
+        assert!(coalescer.push(link_flow(0, 2)).is_none());
+        let flows = coalescer.push(link_flow(1, 1)).unwrap();
+        assert_eq!(flows.len(), 2);
+        assert!(!coalescer.has_pending());
+
+        // The pending bytes are reset once the flows are taken
+        assert!(coalescer.push(link_flow(0, 3)).is_none());
+        assert!(coalescer.take_pending_of(&Handle(0)).is_some());
+        assert!(coalescer.push(link_flow(1, 3)).is_none());
+    }
+}
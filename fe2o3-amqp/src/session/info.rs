@@ -0,0 +1,74 @@
+//! Information about the links attached on a session, the blocking of outgoing transfers and
+//! the numbering of the transfers
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+
+use fe2o3_amqp_types::definitions::{Role, TransferNumber};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::time::Instant;
+
+/// A snapshot of a link that is attached on a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkInfo {
+    /// Name of the link
+    pub name: String,
+
+    /// Role of the local link endpoint
+    pub role: Role,
+
+    /// The handle that the local link endpoint uses
+    pub handle: u32,
+
+    /// Address of the source
+    pub source: Option<String>,
+
+    /// Address of the target
+    pub target: Option<String>,
+
+    /// Number of deliveries that are not settled yet
+    pub unsettled: usize,
+}
+
+/// A snapshot of the outgoing transfers that are held back by the incoming-window of the remote
+/// peer
+///
+/// Transfers are buffered by the session instead of being sent once the remote-incoming-window
+/// reaches zero, and they are only sent after the remote peer grows its incoming-window with a
+/// Flow. A session that stays blocked usually means that the remote peer is not keeping up with
+/// the outgoing deliveries.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SendBlocking {
+    /// Whether outgoing transfers are currently buffered
+    pub is_blocked: bool,
+
+    /// Number of the buffered outgoing transfers
+    pub buffered_transfers: usize,
+
+    /// Number of times the session has become blocked
+    pub blocked_count: u64,
+
+    /// When the session became blocked, if it is currently blocked
+    #[cfg(not(target_arch = "wasm32"))]
+    pub blocked_since: Option<Instant>,
+
+    /// Total time spent blocked, not including the current blocking
+    #[cfg(not(target_arch = "wasm32"))]
+    pub blocked_total: Duration,
+}
+
+/// The transfer numbering of a session
+///
+/// A session that is restarted in a planned way (eg. to move it to a new connection) can be begun
+/// with the ids of the previous session with
+/// [`Builder::resume_from()`](super::builder::Builder::resume_from), so that the delivery-ids keep
+/// increasing across the restart and the unsettled deliveries of both sessions can be told apart
+/// in the logs of either peer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct TransferIds {
+    /// The transfer-id that is assigned to the next outgoing transfer
+    pub next_outgoing_id: TransferNumber,
+
+    /// The transfer-id that is expected on the next incoming transfer
+    pub next_incoming_id: TransferNumber,
+}
@@ -0,0 +1,83 @@
+//! Session-scoped transformation of transfer payloads
+
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+use bytes::Bytes;
+use fe2o3_amqp_types::{definitions, performatives::Transfer};
+
+/// The future returned by [`TransferMiddleware`]
+pub type TransferMiddlewareFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Bytes, definitions::Error>> + Send + 'a>>;
+
+/// An async transformation of the payloads of the transfers on a session
+///
+/// This is useful for protocol bridges and relays that need to re-encrypt or re-compress message
+/// bodies. The middleware is applied by the session to every incoming transfer frame before the
+/// transfer is routed to the link, and to every outgoing transfer frame before it is handed to
+/// the connection.
+///
+/// The middleware is applied to each transfer frame separately, so a delivery that is split
+/// into multiple transfer frames will have each of its frames transformed individually. An
+/// outgoing payload may grow, as it is split again to fit the max frame size when the frame is
+/// encoded.
+///
+/// If the middleware returns an error, the session is ended with that error.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use fe2o3_amqp::session::{TransferMiddleware, TransferMiddlewareFuture};
+///
+/// #[derive(Debug)]
+/// struct Reencrypt { /* ... */ }
+///
+/// impl TransferMiddleware for Reencrypt {
+///     fn on_incoming_transfer<'a>(
+///         &'a self,
+///         _transfer: &'a Transfer,
+///         payload: Bytes,
+///     ) -> TransferMiddlewareFuture<'a> {
+///         Box::pin(async move { self.decrypt(payload).await })
+///     }
+/// }
+///
+/// let session = Session::builder()
+///     .transfer_middleware(Reencrypt { /* ... */ })
+///     .begin(&mut connection)
+///     .await
+///     .unwrap();
+/// ```
+pub trait TransferMiddleware: Send + Sync {
+    /// Transforms the payload of an incoming transfer frame. The payload is returned unchanged
+    /// by default
+    fn on_incoming_transfer<'a>(
+        &'a self,
+        transfer: &'a Transfer,
+        payload: Bytes,
+    ) -> TransferMiddlewareFuture<'a> {
+        let _ = transfer;
+        Box::pin(async move { Ok(payload) })
+    }
+
+    /// Transforms the payload of an outgoing transfer frame. The payload is returned unchanged
+    /// by default
+    fn on_outgoing_transfer<'a>(
+        &'a self,
+        transfer: &'a Transfer,
+        payload: Bytes,
+    ) -> TransferMiddlewareFuture<'a> {
+        let _ = transfer;
+        Box::pin(async move { Ok(payload) })
+    }
+}
+
+/// A cloneable handle to a [`TransferMiddleware`]
+#[derive(Clone)]
+pub(crate) struct SharedTransferMiddleware(pub Arc<dyn TransferMiddleware>);
+
+impl fmt::Debug for SharedTransferMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedTransferMiddleware")
+            .finish_non_exhaustive()
+    }
+}
@@ -1,11 +1,12 @@
 //! Implements AMQP1.0 Session
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use fe2o3_amqp_types::{
     definitions::{
         self, DeliveryNumber, DeliveryTag, Fields, Handle, Role, SequenceNo, TransferNumber,
     },
+    messaging::{DeliveryState, TargetArchetype},
     performatives::{Attach, Begin, Detach, Disposition, End, Flow, Transfer},
     primitives::{Symbol, Uint},
     states::SessionState,
@@ -14,7 +15,7 @@ use slab::Slab;
 use tokio::{
     sync::{
         mpsc::{self},
-        oneshot::{self, error::TryRecvError},
+        oneshot, watch,
     },
     task::JoinHandle,
 };
@@ -23,7 +24,7 @@ use crate::{
     control::SessionControl,
     endpoint::{self, IncomingChannel, InputHandle, LinkFlow, OutgoingChannel, OutputHandle},
     link::{LinkFrame, LinkRelay},
-    util::{is_consecutive, Constant},
+    util::{is_consecutive, Constant, SharedOutcome},
     Payload,
 };
 
@@ -46,6 +47,24 @@ pub use error::{BeginError, Error, TryEndError};
 mod builder;
 pub use builder::*;
 
+mod flow_coalescing;
+pub(crate) use flow_coalescing::FlowCoalescer;
+pub use flow_coalescing::{
+    FlowCoalescing, DEFAULT_FLOW_COALESCING_DELAY, DEFAULT_FLOW_COALESCING_MAX_PENDING,
+    DEFAULT_FLOW_COALESCING_MAX_PENDING_BYTES,
+};
+
+mod fair_dispatch;
+pub(crate) use fair_dispatch::InboundDispatcher;
+pub use fair_dispatch::{FairDispatch, OverloadPolicy, DEFAULT_MAX_LINK_BACKLOG};
+
+mod info;
+pub use info::{LinkInfo, SendBlocking, TransferIds};
+
+mod middleware;
+pub(crate) use middleware::SharedTransferMiddleware;
+pub use middleware::{TransferMiddleware, TransferMiddlewareFuture};
+
 use self::frame::{SessionFrame, SessionFrameBody, SessionOutgoingItem};
 
 /// Default incoming_window and outgoing_window
@@ -60,11 +79,34 @@ pub struct SessionHandle<R> {
     pub(crate) is_ended: bool,
     pub(crate) control: mpsc::Sender<SessionControl>,
     pub(crate) engine_handle: JoinHandle<()>,
-    pub(crate) outcome: oneshot::Receiver<Result<(), Error>>,
+    pub(crate) outcome: SharedOutcome<Error>,
 
     // outgoing for Link
     pub(crate) outgoing: mpsc::Sender<LinkFrame>,
     pub(crate) link_listener: R,
+
+    // The Begin received from the remote peer during the handshake
+    pub(crate) remote_begin: Begin,
+
+    // The local state of the session that is published by the session engine
+    pub(crate) state: watch::Receiver<SessionState>,
+
+    // The blocking of outgoing transfers that is published by the session engine
+    pub(crate) send_blocking: watch::Receiver<SendBlocking>,
+
+    // The transfer numbering that is published by the session engine
+    pub(crate) transfer_ids: watch::Receiver<TransferIds>,
+
+    // The transfer numbering of the previous session that this session was begun with
+    pub(crate) resumed_from: Option<TransferIds>,
+
+    // The identity that the remote peer authenticated with on a listener connection
+    #[cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+    pub(crate) authenticated_identity: Option<String>,
+
+    // The clock of the connection that drives the timers of the session and its links
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) clock: crate::clock::SharedClock,
 }
 
 impl<R> std::fmt::Debug for SessionHandle<R> {
@@ -88,6 +130,56 @@ impl<R> SessionHandle<R> {
         }
     }
 
+    /// The Begin performative received from the remote peer when the session was established
+    ///
+    /// This carries the limits advertised by the remote peer (eg. `handle-max` and the initial
+    /// windows) as well as its capabilities and session properties.
+    pub fn remote_begin(&self) -> &Begin {
+        &self.remote_begin
+    }
+
+    /// Watches the local state of the session, eg. to detect an End from the remote peer. The
+    /// returned receiver is notified on every state transition and is closed once the session
+    /// engine stops, after which it still holds the final state
+    pub fn watch_state(&self) -> watch::Receiver<SessionState> {
+        self.state.clone()
+    }
+
+    /// Whether outgoing transfers are currently buffered because the incoming-window of the
+    /// remote peer is exhausted
+    pub fn is_send_blocked(&self) -> bool {
+        self.send_blocking.borrow().is_blocked
+    }
+
+    /// A snapshot of the blocking of outgoing transfers by the incoming-window of the remote peer
+    pub fn send_blocking(&self) -> SendBlocking {
+        self.send_blocking.borrow().clone()
+    }
+
+    /// Watches the blocking of outgoing transfers. The returned receiver is notified whenever the
+    /// session becomes blocked or unblocked and whenever the number of buffered transfers changes
+    pub fn watch_send_blocking(&self) -> watch::Receiver<SendBlocking> {
+        self.send_blocking.clone()
+    }
+
+    /// The next-outgoing-id and the next-incoming-id of the session. The last values are kept
+    /// once the session has ended, so that they can be given to
+    /// [`Builder::resume_from()`](builder::Builder::resume_from) when the session is restarted
+    pub fn transfer_ids(&self) -> TransferIds {
+        *self.transfer_ids.borrow()
+    }
+
+    /// Whether the remote peer continued its own transfer numbering from the [`TransferIds`] that
+    /// the session was resumed from, ie. whether its next-outgoing-id in the Begin matches the
+    /// next-incoming-id of the previous session. `None` if the session was not resumed
+    ///
+    /// The delivery-ids of the incoming deliveries of both sessions may overlap if this is
+    /// `Some(false)`
+    pub fn remote_continues_numbering(&self) -> Option<bool> {
+        self.resumed_from
+            .map(|ids| ids.next_incoming_id == self.remote_begin.next_outgoing_id)
+    }
+
     /// Tries to end the session
     ///
     /// # Returns
@@ -102,25 +194,20 @@ impl<R> SessionHandle<R> {
         }
 
         let _ = self.control.try_send(SessionControl::End(None));
-        match self.outcome.try_recv() {
-            Ok(res) => {
+        match self.outcome.try_get() {
+            Some(res) => {
                 self.is_ended = true;
                 Ok(res)
             }
-            Err(TryRecvError::Empty) => Err(TryEndError::RemoteEndNotReceived),
-            Err(TryRecvError::Closed) => {
-                self.is_ended = true;
-                Ok(Err(Error::IllegalState))
-            }
+            None => Err(TryEndError::RemoteEndNotReceived),
         }
     }
 
     cfg_not_wasm32! {
         /// End the session
         ///
-        /// An `Error::IllegalState` will be returned if called after any of [`end`](#method.end),
-        /// [`end_with_error`](#method.end_with_error), [`on_end`](#on_end) has beend executed. This
-        /// will cause the JoinHandle to be polled after completion, which causes a panic.
+        /// This can be called multiple times. If the session has already ended, the outcome of
+        /// the first end is returned again.
         ///
         /// # wasm32 support
         ///
@@ -143,9 +230,8 @@ impl<R> SessionHandle<R> {
 
         /// End the session with an error
         ///
-        /// An `Error::IllegalState` will be returned if called after any of [`end`](#method.end),
-        /// [`end_with_error`](#method.end_with_error), [`on_end`](#on_end) has beend executed.
-        /// This will cause the JoinHandle to be polled after completion, which causes a panic.
+        /// This can be called multiple times. If the session has already ended, the error is not
+        /// sent and the outcome of the first end is returned again.
         ///
         /// # wasm32 support
         ///
@@ -166,24 +252,13 @@ impl<R> SessionHandle<R> {
 
     /// Returns when the underlying event loop has stopped
     ///
-    /// An `Error::IllegalState` will be returned if called after any of [`end`](#method.end),
-    /// [`end_with_error`](#method.end_with_error), [`on_end`](#on_end) has beend executed. This
-    /// will cause the JoinHandle to be polled after completion, which causes a panic.
+    /// The outcome of the event loop is recorded, so this can be called multiple times (and
+    /// after [`end`](#method.end) or [`end_with_error`](#method.end_with_error)), and the same
+    /// outcome is returned every time.
     pub async fn on_end(&mut self) -> Result<(), Error> {
-        if self.is_ended {
-            return Err(Error::IllegalState);
-        }
-
-        match (&mut self.outcome).await {
-            Ok(res) => {
-                self.is_ended = true;
-                res
-            }
-            Err(_) => {
-                self.is_ended = true;
-                Err(Error::IllegalState)
-            }
-        }
+        let res = self.outcome.wait().await;
+        self.is_ended = true;
+        res
     }
 }
 
@@ -258,6 +333,8 @@ pub struct Session {
 
     // local amqp states
     pub(crate) local_state: SessionState,
+    // Publishes every change of `local_state`
+    pub(crate) state_watch: watch::Sender<SessionState>,
     pub(crate) initial_outgoing_id: Constant<TransferNumber>,
     pub(crate) next_outgoing_id: TransferNumber,
     pub(crate) incoming_window: TransferNumber,
@@ -271,6 +348,10 @@ pub struct Session {
     pub(crate) remote_incoming_window: SequenceNo,
     // Outgoing transfers that are blocked by the remote-incoming-window
     pub(crate) remote_incoming_window_exhausted_buffer: VecDeque<(InputHandle, Transfer, Payload)>,
+    // Publishes the blocking of outgoing transfers by the remote-incoming-window
+    pub(crate) send_blocking_watch: watch::Sender<SendBlocking>,
+    // Publishes the next-outgoing-id and the next-incoming-id
+    pub(crate) transfer_ids_watch: watch::Sender<TransferIds>,
 
     // The remote-outgoing-window reflects the maximum number of incoming transfers that MAY
     // arrive without exceeding the remote endpoint’s outgoing-window. This value MUST be
@@ -289,8 +370,22 @@ pub struct Session {
     pub(crate) link_name_by_output_handle: Slab<String>,
     pub(crate) link_by_name: HashMap<String, Option<LinkRelay<OutputHandle>>>,
     pub(crate) link_by_input_handle: HashMap<InputHandle, LinkRelay<OutputHandle>>,
+    // Links whose Detach was not replied by the remote peer in time. Frames that still arrive on
+    // these handles are discarded until the remote Detach is received
+    pub(crate) abandoned_input_handles: HashSet<InputHandle>,
+    // Links that are detached by `kick_link`. The frames that the local links still send on these
+    // handles are discarded until the local Detach is received
+    pub(crate) kicked_output_handles: HashSet<OutputHandle>,
+    // Source and target addresses of the local links
+    pub(crate) link_addresses: HashMap<OutputHandle, (Option<String>, Option<String>)>,
     // Maps from DeliveryId to link.DeliveryCount
     pub(crate) delivery_tag_by_id: HashMap<(Role, DeliveryNumber), (InputHandle, DeliveryTag)>, // Role must be the remote peer's role
+
+    // Per-link inbound queues. Incoming transfers are forwarded to links directly if this is None
+    pub(crate) inbound_dispatcher: Option<InboundDispatcher>,
+
+    // Transformation of incoming and outgoing transfer payloads
+    pub(crate) transfer_middleware: Option<SharedTransferMiddleware>,
 }
 
 impl Session {
@@ -328,6 +423,84 @@ impl Session {
         }
     }
 
+    fn set_local_state(&mut self, state: SessionState) {
+        self.state_watch.send_replace(state.clone());
+        self.local_state = state;
+    }
+
+    fn update_transfer_ids(&self) {
+        let ids = TransferIds {
+            next_outgoing_id: self.next_outgoing_id,
+            next_incoming_id: self.next_incoming_id,
+        };
+        self.transfer_ids_watch.send_if_modified(|current| {
+            let modified = *current != ids;
+            *current = ids;
+            modified
+        });
+    }
+
+    /// Publishes the number of buffered outgoing transfers, and emits an event when the session
+    /// becomes blocked by the remote-incoming-window or unblocked
+    fn update_send_blocking(&mut self) {
+        let buffered_transfers = self.remote_incoming_window_exhausted_buffer.len();
+        let _channel = self.outgoing_channel.0;
+        self.send_blocking_watch.send_if_modified(|blocking| {
+            let is_blocked = buffered_transfers > 0;
+            if blocking.buffered_transfers == buffered_transfers
+                && blocking.is_blocked == is_blocked
+            {
+                return false;
+            }
+
+            match (blocking.is_blocked, is_blocked) {
+                (false, true) => {
+                    blocking.blocked_count += 1;
+                    #[cfg(not(target_arch = "wasm32"))]
+                    {
+                        blocking.blocked_since = Some(tokio::time::Instant::now());
+                    }
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        channel = _channel,
+                        blocked_count = blocking.blocked_count,
+                        "Outgoing transfers are blocked by the remote incoming-window"
+                    );
+                    #[cfg(feature = "log")]
+                    log::warn!("Outgoing transfers on channel {} are blocked", _channel);
+                }
+                (true, false) => {
+                    #[cfg(not(target_arch = "wasm32"))]
+                    let _blocked_for = blocking
+                        .blocked_since
+                        .take()
+                        .map(|since| since.elapsed())
+                        .unwrap_or_default();
+                    #[cfg(not(target_arch = "wasm32"))]
+                    {
+                        blocking.blocked_total += _blocked_for;
+                    }
+                    #[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
+                    tracing::info!(
+                        channel = _channel,
+                        blocked_for = ?_blocked_for,
+                        "Outgoing transfers are no longer blocked by the remote incoming-window"
+                    );
+                    #[cfg(all(feature = "log", not(target_arch = "wasm32")))]
+                    log::info!(
+                        "Outgoing transfers on channel {} were blocked for {:?}",
+                        _channel,
+                        _blocked_for
+                    );
+                }
+                _ => {}
+            }
+            blocking.is_blocked = is_blocked;
+            blocking.buffered_transfers = buffered_transfers;
+            true
+        });
+    }
+
     fn on_outgoing_transfer_inner(
         &mut self,
         input_handle: InputHandle,
@@ -368,6 +541,7 @@ impl Session {
         }
 
         self.next_outgoing_id = self.next_outgoing_id.wrapping_add(1);
+        self.update_transfer_ids();
 
         // The remote-incoming-window reflects the maximum number of outgoing transfers that can
         // be sent without exceeding the remote endpoint’s incoming-window. This value MUST be
@@ -394,6 +568,7 @@ impl Session {
         // window directly from the outgoing-window of the frame.
         self.next_incoming_id = flow.next_outgoing_id;
         self.remote_outgoing_window = flow.outgoing_window;
+        self.update_transfer_ids();
 
         match &flow.next_incoming_id {
             Some(flow_next_incoming_id) => {
@@ -424,6 +599,7 @@ impl Session {
                         .await
                         .map_err(Into::into);
                 }
+                None if self.abandoned_input_handles.contains(&input_handle) => return Ok(None),
                 None => return Err(SessionInnerError::UnattachedHandle), // End session with unattached handle?
             }
         }
@@ -542,11 +718,22 @@ impl endpoint::Session for Session {
 
     /// This should only deallocate the output handle
     fn deallocate_link(&mut self, output_handle: OutputHandle) {
+        self.link_addresses.remove(&output_handle);
         if let Some(name) = self
             .link_name_by_output_handle
             .try_remove(output_handle.0 as usize)
         {
-            let _ = self.link_by_name.remove(&name);
+            // The name of a kicked link is released by `kick_link` and may already be taken by
+            // another link
+            if !self.kicked_output_handles.remove(&output_handle) {
+                let _ = self.link_by_name.remove(&name);
+            }
+        }
+    }
+
+    fn abandon_link(&mut self, input_handle: InputHandle) {
+        if self.link_by_input_handle.remove(&input_handle).is_some() {
+            self.abandoned_input_handles.insert(input_handle);
         }
     }
 
@@ -556,8 +743,8 @@ impl endpoint::Session for Session {
         begin: Begin,
     ) -> Result<(), Self::BeginError> {
         match self.local_state {
-            SessionState::Unmapped => self.local_state = SessionState::BeginReceived,
-            SessionState::BeginSent => self.local_state = SessionState::Mapped,
+            SessionState::Unmapped => self.set_local_state(SessionState::BeginReceived),
+            SessionState::BeginSent => self.set_local_state(SessionState::Mapped),
             _ => return Err(SessionStateError::IllegalState), // End session with unattached handle?
         }
 
@@ -565,6 +752,7 @@ impl endpoint::Session for Session {
         self.next_incoming_id = begin.next_outgoing_id;
         self.remote_incoming_window = begin.incoming_window;
         self.remote_outgoing_window = begin.outgoing_window;
+        self.update_transfer_ids();
 
         Ok(())
     }
@@ -589,6 +777,7 @@ impl endpoint::Session for Session {
                         .send(LinkFrame::Attach(attach))
                         .await
                         .map_err(|_| SessionInnerError::UnattachedHandle)?;
+                    self.abandoned_input_handles.remove(&input_handle);
                     self.link_by_input_handle.insert(input_handle, relay);
 
                     Ok(())
@@ -626,6 +815,7 @@ impl endpoint::Session for Session {
             }
             let frames =
                 self.prepare_session_frames_from_buffered_transfers(output_frame_buffer)?;
+            self.update_send_blocking();
             Ok(Some(SessionOutgoingItem::MultipleFrames(frames)))
         } else {
             Ok(outgoing_session_flow.map(SessionOutgoingItem::SingleFrame))
@@ -642,13 +832,16 @@ impl endpoint::Session for Session {
         // remote-outgoing-window, and MAY (depending on policy) decrement its incoming-window.
         self.next_incoming_id = self.next_incoming_id.wrapping_add(1);
         self.remote_outgoing_window = self.remote_outgoing_window.saturating_sub(1);
+        self.update_transfer_ids();
 
         // TODO: allow user to define whether the incoming window should be decremented
 
         let input_handle = InputHandle::from(transfer.handle.clone());
         match self.link_by_input_handle.get_mut(&input_handle) {
             Some(link_relay) => {
-                let id_and_tag = link_relay.on_incoming_transfer(transfer, payload).await?;
+                let id_and_tag = link_relay
+                    .on_incoming_transfer(transfer, payload, self.inbound_dispatcher.as_mut())
+                    .await?;
 
                 // FIXME: If the unsettled map needs this
                 if let Some((delivery_id, delivery_tag)) = id_and_tag {
@@ -656,6 +849,7 @@ impl endpoint::Session for Session {
                         .insert((Role::Sender, delivery_id), (input_handle, delivery_tag));
                 }
             }
+            None if self.abandoned_input_handles.contains(&input_handle) => {}
             None => return Err(SessionInnerError::UnattachedHandle),
         };
 
@@ -743,11 +937,32 @@ impl endpoint::Session for Session {
             .link_by_input_handle
             .remove(&InputHandle::from(detach.handle.clone()))
         {
-            Some(mut link) => link
-                .on_incoming_detach(detach)
-                .await
-                .map_err(|_| SessionInnerError::UnattachedHandle),
-            None => Err(SessionInnerError::UnattachedHandle),
+            Some(mut link) => {
+                // The detach must not overtake the transfers that are still queued for the link
+                let detach = match &mut self.inbound_dispatcher {
+                    Some(dispatcher) => {
+                        let output_handle = Handle::from(link.output_handle().clone());
+                        match dispatcher
+                            .enqueue_if_backlogged(&output_handle, LinkFrame::Detach(detach))
+                        {
+                            Some(LinkFrame::Detach(detach)) => detach,
+                            _ => return Ok(()),
+                        }
+                    }
+                    None => detach,
+                };
+                link.on_incoming_detach(detach)
+                    .await
+                    .map_err(|_| SessionInnerError::UnattachedHandle)
+            }
+            None => match self
+                .abandoned_input_handles
+                .remove(&InputHandle::from(detach.handle))
+            {
+                // The remote peer replies to the Detach after the link has been abandoned
+                true => Ok(()),
+                false => Err(SessionInnerError::UnattachedHandle),
+            },
         }
     }
 
@@ -763,7 +978,7 @@ impl endpoint::Session for Session {
         log::trace!("RECV end = {:?}", end);
         match self.local_state {
             SessionState::BeginSent | SessionState::BeginReceived | SessionState::Mapped => {
-                self.local_state = SessionState::EndReceived;
+                self.set_local_state(SessionState::EndReceived);
 
                 match end.error {
                     Some(err) => Err(SessionStateError::RemoteEndedWithError(err)),
@@ -771,7 +986,7 @@ impl endpoint::Session for Session {
                 }
             }
             SessionState::EndSent | SessionState::Discarding => {
-                self.local_state = SessionState::Unmapped;
+                self.set_local_state(SessionState::Unmapped);
 
                 if let Some(error) = end.error {
                     #[cfg(feature = "tracing")]
@@ -811,14 +1026,14 @@ impl endpoint::Session for Session {
                     // The receiving half must have dropped, and thus the `Connection`
                     // event loop has stopped. It should be treated as an io error
                     .map_err(|_| SessionStateError::IllegalConnectionState)?;
-                self.local_state = SessionState::BeginSent;
+                self.set_local_state(SessionState::BeginSent);
             }
             SessionState::BeginReceived => {
                 writer
                     .send(frame)
                     .await
                     .map_err(|_| SessionStateError::IllegalConnectionState)?;
-                self.local_state = SessionState::Mapped;
+                self.set_local_state(SessionState::Mapped);
             }
             _ => return Err(SessionStateError::IllegalState),
         }
@@ -833,10 +1048,10 @@ impl endpoint::Session for Session {
     ) -> Result<(), Self::EndError> {
         match self.local_state {
             SessionState::Mapped => match error.is_some() {
-                true => self.local_state = SessionState::Discarding,
-                false => self.local_state = SessionState::EndSent,
+                true => self.set_local_state(SessionState::Discarding),
+                false => self.set_local_state(SessionState::EndSent),
             },
-            SessionState::EndReceived => self.local_state = SessionState::Unmapped,
+            SessionState::EndReceived => self.set_local_state(SessionState::Unmapped),
             _ => return Err(SessionStateError::IllegalState),
         }
 
@@ -851,6 +1066,18 @@ impl endpoint::Session for Session {
     }
 
     fn on_outgoing_attach(&mut self, attach: Attach) -> Result<SessionFrame, Self::Error> {
+        let source = attach
+            .source
+            .as_ref()
+            .and_then(|source| source.address.clone());
+        let target = attach.target.as_ref().and_then(|target| match &**target {
+            TargetArchetype::Target(target) => target.address.clone(),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        });
+        self.link_addresses
+            .insert(attach.handle.clone().into(), (source, target));
+
         let body = SessionFrameBody::Attach(attach);
         let frame = SessionFrame::new(self.outgoing_channel, body);
         Ok(frame)
@@ -892,6 +1119,7 @@ impl endpoint::Session for Session {
                 transfer,
                 payload,
             ));
+            self.update_send_blocking();
             Ok(None)
         } else if self.remote_incoming_window_exhausted_buffer.is_empty() {
             // no buffered transfer
@@ -903,14 +1131,14 @@ impl endpoint::Session for Session {
                     .len()
                     .saturating_add(1),
             );
-            self.prepare_session_frames_from_buffered_and_current_transfers(
+            let frames = self.prepare_session_frames_from_buffered_and_current_transfers(
                 output_frame_buffer,
                 input_handle,
                 transfer,
                 payload,
-            )
-            .map(SessionOutgoingItem::MultipleFrames)
-            .map(Some)
+            )?;
+            self.update_send_blocking();
+            Ok(Some(SessionOutgoingItem::MultipleFrames(frames)))
         }
     }
 
@@ -918,8 +1146,8 @@ impl endpoint::Session for Session {
         &mut self,
         disposition: Disposition,
     ) -> Result<SessionFrame, Self::Error> {
-        // Currently the sender cannot actively dispose any message
-        // because the sender doesn't have access to the delivery_id
+        // The sender doesn't have access to the delivery_id, and the settlement by the sender
+        // goes through `on_outgoing_settlement`
 
         // The remote-outgoing-window reflects the maximum number of incoming transfers that MAY
         // arrive without exceeding the remote endpoint’s outgoing-window. This value MUST be
@@ -942,11 +1170,205 @@ impl endpoint::Session for Session {
         Ok(frame)
     }
 
+    fn on_outgoing_settlement(
+        &mut self,
+        input_handle: InputHandle,
+        delivery_tag: DeliveryTag,
+        state: Option<DeliveryState>,
+    ) -> Option<SessionFrame> {
+        // The delivery is only found if the remote receiver has not settled it yet
+        let delivery_id = self
+            .delivery_tag_by_id
+            .iter()
+            .find(|((role, _), (handle, tag))| {
+                *role == Role::Receiver && *handle == input_handle && *tag == delivery_tag
+            })
+            .map(|((_, delivery_id), _)| *delivery_id)?;
+        self.delivery_tag_by_id
+            .remove(&(Role::Receiver, delivery_id));
+
+        let disposition = Disposition {
+            role: Role::Sender,
+            first: delivery_id,
+            last: None,
+            settled: true,
+            state,
+            batchable: false,
+        };
+        Some(SessionFrame::new(
+            self.outgoing_channel,
+            SessionFrameBody::Disposition(disposition),
+        ))
+    }
+
     fn on_outgoing_detach(&mut self, detach: Detach) -> SessionFrame {
+        if let Some(dispatcher) = &mut self.inbound_dispatcher {
+            dispatcher.discard_held_flow(&detach.handle);
+        }
         self.deallocate_link(detach.handle.clone().into());
         let body = SessionFrameBody::Detach(detach);
         SessionFrame::new(self.outgoing_channel, body)
     }
+
+    fn links(&self) -> Vec<LinkInfo> {
+        let mut links: Vec<LinkInfo> = self
+            .link_by_input_handle
+            .values()
+            .filter_map(|relay| {
+                let output_handle = relay.output_handle();
+                let name = self
+                    .link_name_by_output_handle
+                    .get(output_handle.0 as usize)?;
+                let (source, target) = self
+                    .link_addresses
+                    .get(output_handle)
+                    .cloned()
+                    .unwrap_or_default();
+                Some(LinkInfo {
+                    name: name.clone(),
+                    role: relay.role(),
+                    handle: output_handle.0,
+                    source,
+                    target,
+                    unsettled: relay.unsettled_count(),
+                })
+            })
+            .collect();
+        links.sort_by_key(|link| link.handle);
+        links
+    }
+
+    async fn kick_link(
+        &mut self,
+        link_name: &str,
+        error: definitions::Error,
+    ) -> Option<SessionFrame> {
+        let output_handle = self
+            .link_name_by_output_handle
+            .iter()
+            .find(|(_, name)| name.as_str() == link_name)
+            .map(|(index, _)| OutputHandle(index as u32))?;
+        let input_handle = self
+            .link_by_input_handle
+            .iter()
+            .find(|(_, relay)| *relay.output_handle() == output_handle)
+            .map(|(input_handle, _)| input_handle.clone())?;
+        let mut relay = self.link_by_input_handle.remove(&input_handle)?;
+
+        // The remote peer's reply and the frames still in flight are discarded
+        self.abandoned_input_handles.insert(input_handle.clone());
+        self.kicked_output_handles.insert(output_handle.clone());
+        // The name can be attached again right away
+        self.link_by_name.remove(link_name);
+        if let Some(dispatcher) = &mut self.inbound_dispatcher {
+            dispatcher.discard_held_flow(&output_handle.clone().into());
+        }
+
+        // The local link sees the link as closed by the remote peer
+        let _ = relay
+            .on_incoming_detach(Detach {
+                handle: input_handle.into(),
+                closed: true,
+                error: Some(error.clone()),
+            })
+            .await;
+
+        let detach = Detach {
+            handle: output_handle.into(),
+            closed: true,
+            error: Some(error),
+        };
+        Some(SessionFrame::new(
+            self.outgoing_channel,
+            SessionFrameBody::Detach(detach),
+        ))
+    }
+
+    fn on_kicked_link_frame(&mut self, frame: &LinkFrame) -> bool {
+        let handle = match frame {
+            LinkFrame::Attach(attach) => &attach.handle,
+            LinkFrame::Flow(flow) => &flow.handle,
+            LinkFrame::Transfer { performative, .. } => &performative.handle,
+            LinkFrame::Detach(detach) => &detach.handle,
+            LinkFrame::Disposition(_) | LinkFrame::Settle { .. } => return false,
+        };
+        let output_handle = OutputHandle::from(handle.clone());
+        if !self.kicked_output_handles.contains(&output_handle) {
+            return false;
+        }
+        if let LinkFrame::Detach(_) = frame {
+            self.deallocate_link(output_handle);
+        }
+        true
+    }
+
+    fn reject_incoming_link(
+        &mut self,
+        link_name: String,
+        role: Role,
+        input_handle: InputHandle,
+        error: definitions::Error,
+    ) -> [SessionFrame; 2] {
+        // The handle is released again by the Detach, so it is not reserved
+        let handle = Handle::from(self.link_name_by_output_handle.vacant_key() as u32);
+        let initial_delivery_count = match role {
+            Role::Sender => Some(0),
+            Role::Receiver => None,
+        };
+        let attach = Attach {
+            name: link_name,
+            handle: handle.clone(),
+            role,
+            snd_settle_mode: Default::default(),
+            rcv_settle_mode: Default::default(),
+            source: None,
+            target: None,
+            unsettled: None,
+            incomplete_unsettled: false,
+            initial_delivery_count,
+            max_message_size: None,
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        };
+        let detach = Detach {
+            handle,
+            closed: true,
+            error: Some(error),
+        };
+
+        // The remote Detach and the frames still in flight are discarded
+        self.abandoned_input_handles.insert(input_handle);
+        [
+            SessionFrame::new(self.outgoing_channel, SessionFrameBody::Attach(attach)),
+            SessionFrame::new(self.outgoing_channel, SessionFrameBody::Detach(detach)),
+        ]
+    }
+
+    fn has_inbound_backlog(&self) -> bool {
+        self.inbound_dispatcher
+            .as_ref()
+            .map(|dispatcher| dispatcher.has_backlog())
+            .unwrap_or(false)
+    }
+
+    async fn dispatch_inbound_backlog(&mut self) -> Vec<LinkFlow> {
+        match &mut self.inbound_dispatcher {
+            Some(dispatcher) => dispatcher.dispatch_backlog().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn hold_outgoing_flow(&mut self, flow: LinkFlow) -> Option<LinkFlow> {
+        match &mut self.inbound_dispatcher {
+            Some(dispatcher) => dispatcher.hold_flow(flow),
+            None => Some(flow),
+        }
+    }
+
+    fn transfer_middleware(&self) -> Option<SharedTransferMiddleware> {
+        self.transfer_middleware.clone()
+    }
 }
 
 fn num_messages_settled_by_disposition(first: u32, last: Option<u32>) -> u32 {
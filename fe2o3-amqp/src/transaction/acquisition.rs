@@ -8,11 +8,11 @@ use fe2o3_amqp_types::{
 
 use crate::{
     endpoint::ReceiverLink,
-    link::{delivery, DispositionError, FlowError, RecvError, SendError},
+    link::{delivery, DispositionError, FlowError, RecvError},
     Delivery, Receiver,
 };
 
-use super::{TransactionDischarge, TransactionExt, TransactionalRetirement, TXN_ID_KEY};
+use super::{TransactionExt, TransactionalRetirement, TXN_ID_KEY};
 
 /// 4.4.3 Transactional Acquisition
 ///
@@ -34,11 +34,7 @@ where
 
 impl<'r, Txn> TxnAcquisition<'r, Txn>
 where
-    Txn: TransactionExt
-        + TransactionDischarge<Error = SendError>
-        + TransactionalRetirement<RetireError = DispositionError>
-        + Send
-        + Sync,
+    Txn: TransactionExt + TransactionalRetirement<RetireError = DispositionError> + Send + Sync,
 {
     /// Get an immutable reference to the underlying transaction
     pub fn txn(&self) -> &Txn {
@@ -89,14 +85,20 @@ where
     }
 
     /// Commit the transactional acquisition
-    pub async fn commit(mut self) -> Result<(), SendError> {
+    pub async fn commit(mut self) -> Result<(), Txn::Error>
+    where
+        Txn::Error: From<FlowError>,
+    {
         self.cleanup().await?;
         self.txn.discharge(false).await?;
         Ok(())
     }
 
     /// Rollback the transactional acquisition
-    pub async fn rollback(mut self) -> Result<(), SendError> {
+    pub async fn rollback(mut self) -> Result<(), Txn::Error>
+    where
+        Txn::Error: From<FlowError>,
+    {
         self.cleanup().await?;
         self.txn.discharge(true).await?;
         Ok(())
@@ -0,0 +1,202 @@
+//! Relaying messages between two transactional resources
+
+use fe2o3_amqp_types::{
+    definitions::SequenceNo,
+    messaging::{Body, Outcome},
+};
+use serde_amqp::Value;
+
+use crate::{
+    link::{DispositionError, FlowError, RecvError},
+    Delivery, Receiver, Sender,
+};
+
+use super::{
+    Controller, ControllerSendError, PostError, Transaction, TransactionDischarge,
+    TransactionalRetirement, TxnAcquisition,
+};
+
+/// Errors with relaying a batch with [`TxnBridge`]
+///
+/// Both transactions are rolled back when any of the errors occurs, except for the
+/// [`TxnBridgeError::SourceDischarge`] which happens after the target transaction is committed.
+#[derive(Debug, thiserror::Error)]
+pub enum TxnBridgeError {
+    /// Error declaring the transaction on either side
+    #[error("Error declaring transaction {:?}", .0)]
+    Declare(ControllerSendError),
+
+    /// Error acquiring messages from the source
+    #[error("Error acquiring messages {:?}", .0)]
+    Acquire(FlowError),
+
+    /// Error receiving an acquired message
+    #[error(transparent)]
+    Recv(RecvError),
+
+    /// Error posting a message to the target
+    #[error(transparent)]
+    Post(PostError),
+
+    /// The target did not accept a posted message
+    #[error("The posted message is not accepted {:?}", .0)]
+    NotAccepted(Outcome),
+
+    /// Error accepting an acquired message on the source
+    #[error(transparent)]
+    Retire(DispositionError),
+
+    /// Error committing the transaction on the target. Nothing is relayed
+    #[error("Error committing the target transaction {:?}", .0)]
+    TargetDischarge(ControllerSendError),
+
+    /// Error committing the transaction on the source after the target transaction is
+    /// committed. The messages of the batch may be relayed again
+    #[error("Error committing the source transaction {:?}", .0)]
+    SourceDischarge(ControllerSendError),
+}
+
+/// Relays messages from a [`Receiver`] to a [`Sender`] in batches, each of which is acquired
+/// and posted in a pair of transactions
+///
+/// For every batch, a transaction is declared on the source to acquire the messages and another
+/// one is declared on the target to post them. The acquired messages are only accepted once all
+/// of them are posted, and the target transaction is committed before the source transaction so
+/// that a batch is either relayed as a whole or not at all. The only window for a duplicate is a
+/// failure to commit the source transaction after the target transaction is committed, which is
+/// reported as [`TxnBridgeError::SourceDischarge`].
+///
+/// The controllers may be attached to different connections, which allows relaying messages
+/// between two brokers that both support transactions. The receiver should be attached with
+/// [`CreditMode::Manual`](crate::link::receiver::CreditMode::Manual) so that no message is
+/// delivered outside of a transactional acquisition.
+///
+/// The delivery annotations are removed from the relayed messages as they are only meant for
+/// the immediate receiver.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let source_controller = Controller::attach(&mut source_session, "source-controller")
+///     .await
+///     .unwrap();
+/// let target_controller = Controller::attach(&mut target_session, "target-controller")
+///     .await
+///     .unwrap();
+/// let mut receiver = Receiver::builder()
+///     .name("relay-receiver")
+///     .source("q1")
+///     .credit_mode(CreditMode::Manual)
+///     .attach(&mut source_session)
+///     .await
+///     .unwrap();
+/// let mut sender = Sender::attach(&mut target_session, "relay-sender", "q2")
+///     .await
+///     .unwrap();
+///
+/// let bridge = TxnBridge::new(&source_controller, &target_controller, 10);
+/// loop {
+///     bridge.relay(&mut receiver, &mut sender).await.unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct TxnBridge<'c> {
+    source: &'c Controller,
+    target: &'c Controller,
+    batch_size: SequenceNo,
+}
+
+impl<'c> TxnBridge<'c> {
+    /// Creates a bridge that acquires with the `source` controller and posts with the `target`
+    /// controller `batch_size` messages at a time
+    pub fn new(source: &'c Controller, target: &'c Controller, batch_size: SequenceNo) -> Self {
+        Self {
+            source,
+            target,
+            batch_size,
+        }
+    }
+
+    /// Number of messages in each batch
+    pub fn batch_size(&self) -> SequenceNo {
+        self.batch_size
+    }
+
+    /// Relays one batch of messages. This waits until the whole batch is received from the
+    /// source.
+    pub async fn relay(
+        &self,
+        receiver: &mut Receiver,
+        sender: &mut Sender,
+    ) -> Result<(), TxnBridgeError> {
+        let source_txn = Transaction::declare(self.source, None)
+            .await
+            .map_err(TxnBridgeError::Declare)?;
+        let mut acquisition = source_txn
+            .acquire(receiver, self.batch_size)
+            .await
+            .map_err(TxnBridgeError::Acquire)?;
+        let target_txn = match Transaction::declare(self.target, None).await {
+            Ok(txn) => txn,
+            Err(error) => {
+                let _ = acquisition.rollback().await;
+                return Err(TxnBridgeError::Declare(error));
+            }
+        };
+
+        if let Err(error) = self.stage(&mut acquisition, &target_txn, sender).await {
+            let _ = target_txn.rollback().await;
+            let _ = acquisition.rollback().await;
+            return Err(error);
+        }
+
+        if let Err(error) = target_txn.commit().await {
+            let _ = acquisition.rollback().await;
+            return Err(TxnBridgeError::TargetDischarge(error));
+        }
+        acquisition
+            .commit()
+            .await
+            .map_err(TxnBridgeError::SourceDischarge)
+    }
+
+    /// Posts the acquired messages to the target and accepts them on the source, all of which
+    /// only take effect once the transactions are committed
+    async fn stage(
+        &self,
+        acquisition: &mut TxnAcquisition<'_, Transaction<'c>>,
+        target_txn: &Transaction<'c>,
+        sender: &mut Sender,
+    ) -> Result<(), TxnBridgeError> {
+        let mut acquired = Vec::with_capacity(self.batch_size as usize);
+        let mut futs = Vec::with_capacity(self.batch_size as usize);
+        for _ in 0..self.batch_size {
+            let delivery: Delivery<Body<Value>> =
+                acquisition.recv().await.map_err(TxnBridgeError::Recv)?;
+            let (info, mut message) = delivery.into_parts();
+            message.delivery_annotations = None;
+            let fut = target_txn
+                .post_batchable(sender, message)
+                .await
+                .map_err(TxnBridgeError::Post)?;
+            acquired.push(info);
+            futs.push(fut);
+        }
+
+        for fut in futs {
+            match fut.await.map_err(TxnBridgeError::Post)? {
+                Outcome::Accepted(_) => {}
+                outcome => return Err(TxnBridgeError::NotAccepted(outcome)),
+            }
+        }
+
+        for info in acquired {
+            acquisition
+                .txn
+                .accept(acquisition.recver, info)
+                .await
+                .map_err(TxnBridgeError::Retire)?;
+        }
+        Ok(())
+    }
+}
@@ -1,25 +1,25 @@
 use fe2o3_amqp_types::{
-    definitions::{self, SenderSettleMode},
+    definitions::{self, ErrorCondition, SenderSettleMode},
     messaging::{Accepted, DeliveryState, Message, SerializableBody},
-    transaction::{Coordinator, Declare, Declared, Discharge, TransactionId},
+    transaction::{Coordinator, Declare, Declared, Discharge, TransactionError, TransactionId},
 };
 use tokio::sync::{oneshot, Mutex};
 
 use crate::{
-    endpoint::Settlement,
+    endpoint::{LinkDetach, LinkExt, Settlement},
     link::{
         self,
         builder::{WithSource, WithoutName, WithoutTarget},
         role,
         sender::SenderInner,
-        shared_inner::LinkEndpointInnerDetach,
-        LinkStateError, SendError, SenderAttachError, SenderLink,
+        shared_inner::{LinkEndpointInner, LinkEndpointInnerDetach, LinkEndpointInnerReattach},
+        LinkFrame, LinkState, LinkStateError, SendError, SenderAttachError, SenderLink,
     },
     session::SessionHandle,
     Sendable,
 };
 
-use super::ControllerSendError;
+use super::{ControllerRecoverError, ControllerSendError};
 #[cfg(docsrs)]
 use super::{OwnedTransaction, Transaction};
 
@@ -42,6 +42,35 @@ pub(crate) type ControlLink = SenderLink<Coordinator>;
 #[derive(Debug)]
 pub struct Controller {
     pub(crate) inner: Mutex<SenderInner<ControlLink>>,
+    pub(crate) in_doubt: parking_lot::Mutex<Option<InDoubtDischarge>>,
+}
+
+/// A discharge whose outcome is lost with the control link
+///
+/// The Discharge was sent, but the control link was detached (or the session or connection was
+/// lost) before the outcome arrived, so the coordinator may or may not have discharged the
+/// transaction. The discharge is resolved with [`Controller::recover_on_session()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InDoubtDischarge {
+    /// Id of the discharged transaction
+    pub txn_id: TransactionId,
+
+    /// Whether the discharge rolls back the transaction
+    pub fail: bool,
+}
+
+/// How an [`InDoubtDischarge`] is resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DischargeResolution {
+    /// The transaction is committed
+    Committed,
+
+    /// The transaction is rolled back
+    RolledBack,
+
+    /// The coordinator no longer knows the transaction. It was either committed by the lost
+    /// discharge or rolled back when the control link was lost, and which one cannot be determined
+    InDoubt(InDoubtDischarge),
 }
 
 #[inline]
@@ -64,6 +93,34 @@ where
     }
 }
 
+/// Waits for the outcome of a delivery on the control link. `None` is returned if the control
+/// link is lost before the outcome arrives
+async fn wait_for_outcome(
+    sender: &mut SenderInner<ControlLink>,
+    outcome: oneshot::Receiver<Option<DeliveryState>>,
+) -> Option<Option<DeliveryState>> {
+    tokio::select! {
+        result = outcome => result.ok(),
+        _ = wait_for_link_loss(sender) => None,
+    }
+}
+
+/// Resolves once the remote peer detaches the control link or the session is dropped
+async fn wait_for_link_loss(sender: &mut SenderInner<ControlLink>) {
+    loop {
+        match sender.incoming.recv().await {
+            Some(LinkFrame::Detach(detach)) => {
+                let closed = detach.closed;
+                let _ = sender.send_detach(closed, None).await;
+                let _ = sender.link.on_incoming_detach(detach);
+                return;
+            }
+            Some(_) => {}
+            None => return,
+        }
+    }
+}
+
 impl Controller {
     /// Creates a new builder for controller
     pub fn builder() -> link::builder::Builder<
@@ -146,23 +203,37 @@ impl Controller {
     }
 
     /// Discharge
+    ///
+    /// [`ControllerSendError::DischargeInDoubt`] is returned and the discharge is recorded if the
+    /// control link is lost after the Discharge is sent
     pub(crate) async fn discharge(
         &self,
         txn_id: TransactionId,
         fail: impl Into<Option<bool>>,
     ) -> Result<Accepted, ControllerSendError> {
+        let fail = fail.into();
         let discharge = Discharge {
-            txn_id,
-            fail: fail.into(),
+            txn_id: txn_id.clone(),
+            fail,
         };
         // As with the declare message, it is an error if the sender sends the transfer pre-settled.
         let message = Message::builder().value(discharge).build();
         let sendable = Sendable::builder().message(message).settled(false).build();
 
-        send_on_control_link(&mut *self.inner.lock().await, sendable)
-            .await?
-            .await
-            .map_err(|_| LinkStateError::IllegalSessionState)?
+        let mut inner = self.inner.lock().await;
+        let outcome = send_on_control_link(&mut inner, sendable).await?;
+        let state = match wait_for_outcome(&mut inner, outcome).await {
+            Some(state) => state,
+            None => {
+                let in_doubt = InDoubtDischarge {
+                    txn_id,
+                    fail: fail.unwrap_or(false),
+                };
+                *self.in_doubt.lock() = Some(in_doubt.clone());
+                return Err(ControllerSendError::DischargeInDoubt(in_doubt));
+            }
+        };
+        state
             .ok_or(ControllerSendError::NonTerminalDeliveryState)?
             .accepted_or_else(|state| {
                 if let DeliveryState::Rejected(rejected) = state {
@@ -172,6 +243,89 @@ impl Controller {
                 }
             })
     }
+
+    /// The discharge whose outcome is lost with the control link, if any
+    pub fn in_doubt(&self) -> Option<InDoubtDischarge> {
+        self.in_doubt.lock().clone()
+    }
+
+    /// Re-attaches the control link on the session and resolves the discharge that is in doubt
+    ///
+    /// The control link that is lost with the previous session is abandoned, and a control link
+    /// with the same name is attached on `session`. A control link that is still attached is kept
+    /// as is. If a discharge is in doubt, the Discharge is sent again with the same `txn-id`,
+    /// which is safe as the coordinator does not know the transaction any more if the first one
+    /// went through
+    ///
+    /// | Outcome of the retried Discharge | Resolution |
+    /// |----------------------------------|------------|
+    /// | `Accepted` | [`DischargeResolution::Committed`] or [`DischargeResolution::RolledBack`] as requested |
+    /// | `amqp:transaction:rollback` or `amqp:transaction:timeout` | [`DischargeResolution::RolledBack`] |
+    /// | `amqp:transaction:unknown-id` on rollback | [`DischargeResolution::RolledBack`] |
+    /// | `amqp:transaction:unknown-id` on commit, or any other rejection | [`DischargeResolution::InDoubt`] |
+    ///
+    /// `None` is returned if no discharge is in doubt. The discharge stays in doubt if an error is
+    /// returned, and the recovery may be attempted again.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// match txn.commit().await {
+    ///     Err(ControllerSendError::DischargeInDoubt(_)) => {
+    ///         let mut connection = Connection::open("connection-2", "amqp://localhost:5672").await?;
+    ///         let mut session = Session::begin(&mut connection).await?;
+    ///         let resolution = controller.recover_on_session(&session).await?;
+    ///     }
+    ///     result => result?,
+    /// }
+    /// ```
+    pub async fn recover_on_session<R>(
+        &self,
+        session: &SessionHandle<R>,
+    ) -> Result<Option<DischargeResolution>, ControllerRecoverError> {
+        {
+            let mut inner = self.inner.lock().await;
+            let is_attached = matches!(inner.link.local_state(), LinkState::Attached)
+                && !inner.session.is_closed();
+            if !is_attached {
+                // The Declare and Discharge that are unsettled on the lost link cannot be resumed
+                let _ = inner.link.abandon(false);
+                let _ = inner.link.unsettled.write().take();
+                inner.session = session.control.clone();
+                inner.outgoing = session.outgoing.clone();
+                inner.reattach_inner().await?;
+            }
+        }
+
+        let in_doubt = match self.in_doubt() {
+            Some(in_doubt) => in_doubt,
+            None => return Ok(None),
+        };
+        let resolution = match self.discharge(in_doubt.txn_id.clone(), in_doubt.fail).await {
+            Ok(_) => match in_doubt.fail {
+                true => DischargeResolution::RolledBack,
+                false => DischargeResolution::Committed,
+            },
+            Err(ControllerSendError::Rejected(rejected)) => {
+                match rejected.error.map(|error| error.condition) {
+                    Some(ErrorCondition::TransactionError(
+                        TransactionError::Rollback | TransactionError::Timeout,
+                    )) => DischargeResolution::RolledBack,
+                    // The coordinator rolls back the transactions of a lost control link, so a
+                    // rollback has the same result whether the first Discharge went through or not
+                    Some(ErrorCondition::TransactionError(TransactionError::UnknownId))
+                        if in_doubt.fail =>
+                    {
+                        DischargeResolution::RolledBack
+                    }
+                    _ => DischargeResolution::InDoubt(in_doubt),
+                }
+            }
+            Err(error) => return Err(error.into()),
+        };
+        *self.in_doubt.lock() = None;
+        Ok(Some(resolution))
+    }
 }
 
 // TODO: implement Drop for controller to drop all non-committed transactions
@@ -14,6 +14,7 @@ use tokio::sync::mpsc;
 
 use crate::{
     acceptor::{link::SharedLinkAcceptorFields, local_receiver_link::LocalReceiverLinkAcceptor},
+    clock::SharedClock,
     control::SessionControl,
     link::{
         delivery::DeliveryInfo,
@@ -71,9 +72,17 @@ impl ControlLinkAcceptor {
         remote_attach: Attach,
         control: mpsc::Sender<SessionControl>,
         outgoing: mpsc::Sender<LinkFrame>,
+        clock: SharedClock,
     ) -> Result<TxnCoordinator, ReceiverAttachError> {
         self.inner
-            .accept_incoming_attach_inner(&self.shared, remote_attach, control, outgoing)
+            .accept_incoming_attach_inner(
+                &self.shared,
+                remote_attach,
+                control,
+                outgoing,
+                None,
+                clock,
+            )
             .await
             .map(|inner| TxnCoordinator {
                 inner,
@@ -226,7 +235,10 @@ impl TxnCoordinator {
             | RecvError::MessageDecodeError
             | RecvError::IllegalRcvSettleModeInTransfer
             | RecvError::InconsistentFieldInMultiFrameDelivery
-            | RecvError::TransactionalAcquisitionIsNotImeplemented => {
+            | RecvError::TransactionalAcquisitionIsNotImeplemented
+            | RecvError::UnknownMessageFormat(_)
+            | RecvError::DeliveryTagReused(_)
+            | RecvError::ConflictingResume(_) => {
                 #[cfg(feature = "tracing")]
                 tracing::error!(?error);
                 #[cfg(feature = "log")]
@@ -237,6 +249,17 @@ impl TxnCoordinator {
                 let _ = self.inner.close_with_error(Some(error)).await;
                 Running::Stop
             }
+            #[cfg(not(target_arch = "wasm32"))]
+            RecvError::SpoolError(_) => {
+                #[cfg(feature = "tracing")]
+                tracing::error!(?error);
+                #[cfg(feature = "log")]
+                log::error!("error = {:?}", error);
+                let error =
+                    definitions::Error::new(AmqpError::InternalError, error.to_string(), None);
+                let _ = self.inner.close_with_error(Some(error)).await;
+                Running::Stop
+            }
         }
     }
 
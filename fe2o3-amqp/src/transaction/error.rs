@@ -4,10 +4,12 @@ use fe2o3_amqp_types::{
 };
 
 use crate::link::{
-    delivery::{FromDeliveryState, FromOneshotRecvError, FromPreSettled},
+    delivery::{FromDeadlineElapsed, FromDeliveryState, FromOneshotRecvError, FromPreSettled},
     DetachError, IllegalLinkStateError, LinkStateError, SendError, SenderAttachError,
 };
 
+use super::InDoubtDischarge;
+
 /// Errors with allocation of new transacation ID
 #[derive(Debug)]
 pub(crate) enum AllocTxnIdError {
@@ -113,6 +115,15 @@ pub enum ControllerSendError {
     /// Error serializing message
     #[error("Error encoding message")]
     MessageEncodeError,
+
+    /// The deadline of the message elapsed before the delivery is settled
+    #[error("The deadline elapsed before the delivery is settled")]
+    DeadlineElapsed,
+
+    /// The control link is lost after the Discharge is sent, and the outcome of the discharge is
+    /// unknown. See [`Controller::recover_on_session()`](super::Controller::recover_on_session)
+    #[error("Outcome of discharging transaction {:?} is in doubt", .0.txn_id)]
+    DischargeInDoubt(InDoubtDischarge),
 }
 
 impl From<SendError> for ControllerSendError {
@@ -123,10 +134,41 @@ impl From<SendError> for ControllerSendError {
             SendError::NonTerminalDeliveryState => Self::NonTerminalDeliveryState,
             SendError::IllegalDeliveryState => Self::IllegalDeliveryState,
             SendError::MessageEncodeError => Self::MessageEncodeError,
+            SendError::DeadlineElapsed => Self::DeadlineElapsed,
         }
     }
 }
 
+impl From<IllegalLinkStateError> for ControllerSendError {
+    fn from(value: IllegalLinkStateError) -> Self {
+        Self::LinkStateError(value.into())
+    }
+}
+
+/// Errors with recovering the control link of a [`Controller`](super::Controller)
+#[derive(Debug, thiserror::Error)]
+pub enum ControllerRecoverError {
+    /// Error with re-attaching the control link
+    #[error(transparent)]
+    AttachError(SenderAttachError),
+
+    /// Error with sending the Discharge again. The discharge stays in doubt
+    #[error(transparent)]
+    ControllerSendError(ControllerSendError),
+}
+
+impl From<SenderAttachError> for ControllerRecoverError {
+    fn from(value: SenderAttachError) -> Self {
+        Self::AttachError(value)
+    }
+}
+
+impl From<ControllerSendError> for ControllerRecoverError {
+    fn from(value: ControllerSendError) -> Self {
+        Self::ControllerSendError(value)
+    }
+}
+
 /// Errors with declaring an OwnedTransaction
 #[derive(Debug, thiserror::Error)]
 pub enum OwnedDeclareError {
@@ -175,6 +217,12 @@ impl From<DetachError> for OwnedDischargeError {
     }
 }
 
+impl From<IllegalLinkStateError> for OwnedDischargeError {
+    fn from(value: IllegalLinkStateError) -> Self {
+        Self::ControllerSendError(value.into())
+    }
+}
+
 /// Error associated with sending a txn message
 ///
 /// It is similar to [`SendError`] but differs in how transactional states
@@ -201,6 +249,10 @@ pub enum PostError {
     /// Error serializing message
     #[error("Error encoding message")]
     MessageEncodeError,
+
+    /// The deadline of the message elapsed before the delivery is settled
+    #[error("The deadline elapsed before the delivery is settled")]
+    DeadlineElapsed,
 }
 
 impl From<serde_amqp::Error> for PostError {
@@ -242,11 +294,8 @@ impl FromDeliveryState for PostResult {
             | DeliveryState::Modified(_)
             | DeliveryState::Declared(_) => Err(PostError::IllegalDeliveryState),
             DeliveryState::TransactionalState(txn) => match txn.outcome {
-                Some(Outcome::Accepted(value)) => Ok(Outcome::Accepted(value)),
-                Some(Outcome::Rejected(value)) => Ok(Outcome::Rejected(value)),
-                Some(Outcome::Released(value)) => Ok(Outcome::Released(value)),
-                Some(Outcome::Modified(value)) => Ok(Outcome::Modified(value)),
                 Some(Outcome::Declared(_)) | None => Err(PostError::IllegalDeliveryState),
+                Some(outcome) => Ok(outcome),
             },
         }
     }
@@ -258,6 +307,12 @@ impl FromPreSettled for PostResult {
     }
 }
 
+impl FromDeadlineElapsed for PostResult {
+    fn from_deadline_elapsed() -> Self {
+        Err(PostError::DeadlineElapsed)
+    }
+}
+
 impl FromOneshotRecvError for PostResult {
     fn from_oneshot_recv_error(_: tokio::sync::oneshot::error::RecvError) -> Self {
         Err(PostError::LinkStateError(
@@ -12,7 +12,7 @@ use fe2o3_amqp_types::{
 };
 use tokio::sync::mpsc;
 
-use crate::{link::LinkFrame, Payload};
+use crate::{clock::SharedClock, link::LinkFrame, Payload};
 
 use super::{coordinator::ControlLinkAcceptor, frame::TxnWorkFrame};
 
@@ -29,17 +29,20 @@ pub(crate) struct TransactionManager {
     pub control_link_outgoing: mpsc::Sender<LinkFrame>,
     pub txns: OrderedMap<TransactionId, ResourceTransaction>,
     pub control_link_acceptor: Arc<ControlLinkAcceptor>,
+    pub clock: SharedClock,
 }
 
 impl TransactionManager {
     pub(crate) fn new(
         control_link_outgoing: mpsc::Sender<LinkFrame>,
         control_link_acceptor: ControlLinkAcceptor,
+        clock: SharedClock,
     ) -> Self {
         Self {
             control_link_outgoing,
             txns: OrderedMap::new(),
             control_link_acceptor: Arc::new(control_link_acceptor),
+            clock,
         }
     }
 }
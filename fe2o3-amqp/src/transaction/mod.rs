@@ -6,7 +6,8 @@
 //!
 //! # Controller side
 //!
-//! Please see [`Controller`], [`Transaction`], and [`OwnedTransaction`]
+//! Please see [`Controller`], [`Transaction`], and [`OwnedTransaction`]. [`TxnBridge`] relays
+//! messages between two transactional resources.
 //!
 //! # Resource side
 //!
@@ -68,6 +69,9 @@ use tokio::sync::{mpsc::error::TryRecvError, oneshot};
 mod owned;
 pub use owned::*;
 
+mod bridge;
+pub use bridge::*;
+
 pub(crate) mod control_link_frame;
 
 cfg_acceptor! {
@@ -301,9 +305,15 @@ impl<'t> TransactionDischarge for Transaction<'t> {
 
     async fn discharge(&mut self, fail: bool) -> Result<(), Self::Error> {
         if !self.is_discharged {
-            self.controller
+            let result = self
+                .controller
                 .discharge(self.declared.txn_id.clone(), fail)
-                .await?;
+                .await;
+            // The controller takes over a discharge in doubt, which must not be rolled back on drop
+            if let Err(ControllerSendError::DischargeInDoubt(_)) = result {
+                self.is_discharged = true;
+            }
+            result?;
             self.is_discharged = true;
         }
         Ok(())
@@ -453,7 +463,7 @@ impl<'t> Transaction<'t> {
         recver: &'r mut Receiver,
         credit: SequenceNo,
     ) -> Result<TxnAcquisition<'r, Transaction<'t>>, FlowError> {
-        let value = Value::Binary(self.declared.txn_id.clone());
+        let value = Value::from(self.declared.txn_id.clone());
         {
             let mut writer = recver.inner.link.flow_state.lock.write();
             match &mut writer.properties {
@@ -467,14 +477,16 @@ impl<'t> Transaction<'t> {
                 None => {
                     let mut fields = Fields::new();
                     fields.insert(Symbol::from(TXN_ID_KEY), value);
+                    writer.properties = Some(fields);
                 }
             }
         }
 
+        // The link is left draining by the cleanup of a previous acquisition
         match recver
             .inner
             .link
-            .send_flow(&recver.inner.outgoing, Some(credit), None, false)
+            .send_flow(&recver.inner.outgoing, Some(credit), Some(false), false)
             .await
         {
             Ok(_) => Ok(TxnAcquisition { txn: self, recver }),
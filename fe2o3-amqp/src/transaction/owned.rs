@@ -286,7 +286,7 @@ impl OwnedTransaction {
     ) -> Result<TxnAcquisition<'_, OwnedTransaction>, FlowError> {
         {
             let mut writer = recver.inner.link.flow_state.lock.write();
-            let value = Value::Binary(self.declared.txn_id.clone());
+            let value = Value::from(self.declared.txn_id.clone());
             match &mut writer.properties {
                 Some(fields) => {
                     if fields.contains_key(TXN_ID_KEY) {
@@ -298,14 +298,16 @@ impl OwnedTransaction {
                 None => {
                     let mut fields = Fields::new();
                     fields.insert(Symbol::from(TXN_ID_KEY), value);
+                    writer.properties = Some(fields);
                 }
             }
         }
 
+        // The link is left draining by the cleanup of a previous acquisition
         match recver
             .inner
             .link
-            .send_flow(&recver.inner.outgoing, Some(credit), None, false)
+            .send_flow(&recver.inner.outgoing, Some(credit), Some(false), false)
             .await
         {
             Ok(_) => Ok(TxnAcquisition { txn: self, recver }),
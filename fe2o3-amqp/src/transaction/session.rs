@@ -2,7 +2,7 @@
 
 
 use fe2o3_amqp_types::{
-    definitions::{self},
+    definitions::{self, DeliveryTag, Role},
     messaging::{Accepted, DeliveryState},
     performatives::{Attach, Begin, Detach, Disposition, End, Flow, Transfer},
     transaction::{TransactionError, TransactionId},
@@ -13,10 +13,11 @@ use uuid::Uuid;
 use crate::{
     control::SessionControl,
     endpoint::{self, IncomingChannel, InputHandle, LinkFlow, OutgoingChannel, OutputHandle},
-    link::{target_archetype::VariantOfTargetArchetype, LinkRelay},
+    link::{target_archetype::VariantOfTargetArchetype, LinkFrame, LinkRelay},
     session::{
         self,
         frame::{SessionFrame, SessionOutgoingItem},
+        LinkInfo, SharedTransferMiddleware,
     },
     Payload,
 };
@@ -103,11 +104,12 @@ where
         let acceptor = self.txn_manager.control_link_acceptor.clone();
         let control = self.control.clone();
         let outgoing = self.txn_manager.control_link_outgoing.clone();
+        let clock = self.txn_manager.clock.clone();
 
         tokio::spawn(async move {
             // Error accepting new control link is handled by acceptor
             if let Ok(coordinator) = acceptor
-                .accept_incoming_attach(remote_attach, control, outgoing)
+                .accept_incoming_attach(remote_attach, control, outgoing, clock)
                 .await
             {
                 coordinator.event_loop().await
@@ -254,6 +256,10 @@ where
         self.session.deallocate_link(output_handle)
     }
 
+    fn abandon_link(&mut self, input_handle: InputHandle) {
+        self.session.abandon_link(input_handle)
+    }
+
     fn on_incoming_begin(
         &mut self,
         channel: IncomingChannel,
@@ -383,7 +389,60 @@ where
         self.session.on_outgoing_disposition(disposition)
     }
 
+    fn on_outgoing_settlement(
+        &mut self,
+        input_handle: InputHandle,
+        delivery_tag: DeliveryTag,
+        state: Option<DeliveryState>,
+    ) -> Option<SessionFrame> {
+        self.session
+            .on_outgoing_settlement(input_handle, delivery_tag, state)
+    }
+
     fn on_outgoing_detach(&mut self, detach: Detach) -> SessionFrame {
         self.session.on_outgoing_detach(detach)
     }
+
+    fn links(&self) -> Vec<LinkInfo> {
+        self.session.links()
+    }
+
+    async fn kick_link(
+        &mut self,
+        link_name: &str,
+        error: definitions::Error,
+    ) -> Option<SessionFrame> {
+        self.session.kick_link(link_name, error).await
+    }
+
+    fn on_kicked_link_frame(&mut self, frame: &LinkFrame) -> bool {
+        self.session.on_kicked_link_frame(frame)
+    }
+
+    fn reject_incoming_link(
+        &mut self,
+        link_name: String,
+        role: Role,
+        input_handle: InputHandle,
+        error: definitions::Error,
+    ) -> [SessionFrame; 2] {
+        self.session
+            .reject_incoming_link(link_name, role, input_handle, error)
+    }
+
+    fn has_inbound_backlog(&self) -> bool {
+        self.session.has_inbound_backlog()
+    }
+
+    async fn dispatch_inbound_backlog(&mut self) -> Vec<LinkFlow> {
+        self.session.dispatch_inbound_backlog().await
+    }
+
+    fn hold_outgoing_flow(&mut self, flow: LinkFlow) -> Option<LinkFlow> {
+        self.session.hold_outgoing_flow(flow)
+    }
+
+    fn transfer_middleware(&self) -> Option<SharedTransferMiddleware> {
+        self.session.transfer_middleware()
+    }
 }
@@ -0,0 +1,341 @@
+//! Fault injection for the IO stream of a connection
+//!
+//! [`ChaosTransport`] wraps the stream of a connection and delays, splits, truncates or drops the
+//! writes according to a [`ChaosConfig`]. This is meant to exercise the recovery logic of an
+//! application (and the error paths of this crate) in tests, and should not be used in production.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let stream = TcpStream::connect("localhost:5672").await.unwrap();
+//! let config = ChaosConfig::new()
+//!     .latency(Duration::from_millis(20))
+//!     .jitter(Duration::from_millis(10))
+//!     .partial_writes(0.5)
+//!     .disconnects(0.001)
+//!     .seed(42);
+//! let connection = Connection::builder()
+//!     .container_id("chaos-client")
+//!     .open_with_stream(ChaosTransport::new(stream, config))
+//!     .await
+//!     .unwrap();
+//! ```
+
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use pin_project_lite::pin_project;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Sleep,
+};
+
+/// Faults injected by a [`ChaosTransport`]
+///
+/// The probabilities are rolled independently for each write (or each read that returns data for
+/// [`disconnect_probability`](Self::disconnect_probability)) and must be within `0.0..=1.0`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChaosConfig {
+    /// Delay added before each write
+    pub latency: Duration,
+
+    /// Upper bound of a random delay that is added to `latency`
+    pub jitter: Duration,
+
+    /// Probability that only a random prefix of a write is accepted, forcing the caller to write
+    /// the rest again
+    pub partial_write_probability: f64,
+
+    /// Probability that the connection is dropped. The stream is shut down and all the following
+    /// reads and writes fail with [`io::ErrorKind::ConnectionReset`]
+    pub disconnect_probability: f64,
+
+    /// Probability that only a random prefix of a write reaches the remote peer before the
+    /// connection is dropped, leaving a truncated frame on the wire
+    pub truncate_probability: f64,
+
+    /// Seed of the random number generator. A random seed is used if this is `None`
+    pub seed: Option<u64>,
+}
+
+impl ChaosConfig {
+    /// A configuration that does not inject any fault
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the delay added before each write
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Sets the upper bound of the random delay added to the latency
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the probability of a partial write
+    ///
+    /// # Panics
+    ///
+    /// Panics if `probability` is not within `0.0..=1.0`
+    pub fn partial_writes(mut self, probability: f64) -> Self {
+        self.partial_write_probability = checked_probability(probability);
+        self
+    }
+
+    /// Sets the probability of dropping the connection on a read or a write
+    ///
+    /// # Panics
+    ///
+    /// Panics if `probability` is not within `0.0..=1.0`
+    pub fn disconnects(mut self, probability: f64) -> Self {
+        self.disconnect_probability = checked_probability(probability);
+        self
+    }
+
+    /// Sets the probability of truncating a write and dropping the connection
+    ///
+    /// # Panics
+    ///
+    /// Panics if `probability` is not within `0.0..=1.0`
+    pub fn truncation(mut self, probability: f64) -> Self {
+        self.truncate_probability = checked_probability(probability);
+        self
+    }
+
+    /// Sets the seed of the random number generator so that a failing run can be reproduced
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+fn checked_probability(probability: f64) -> f64 {
+    assert!(
+        (0.0..=1.0).contains(&probability),
+        "probability must be within 0.0..=1.0"
+    );
+    probability
+}
+
+pin_project! {
+    /// Wraps the IO stream of a connection and injects the faults of a [`ChaosConfig`]
+    #[derive(Debug)]
+    pub struct ChaosTransport<Io> {
+        #[pin]
+        io: Io,
+        config: ChaosConfig,
+        rng: StdRng,
+        delay: Option<Pin<Box<Sleep>>>,
+        delayed: bool,
+        disconnected: bool,
+    }
+}
+
+impl<Io> ChaosTransport<Io> {
+    /// Wraps `io`
+    pub fn new(io: Io, config: ChaosConfig) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            io,
+            config,
+            rng,
+            delay: None,
+            delayed: false,
+            disconnected: false,
+        }
+    }
+
+    /// The injected faults
+    pub fn config(&self) -> &ChaosConfig {
+        &self.config
+    }
+
+    /// Whether a disconnect or a truncation has been injected
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+
+    /// Gets a reference to the wrapped stream
+    pub fn get_ref(&self) -> &Io {
+        &self.io
+    }
+
+    /// Consumes the wrapper and returns the wrapped stream
+    pub fn into_inner(self) -> Io {
+        self.io
+    }
+}
+
+fn connection_reset() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionReset,
+        "Connection dropped by ChaosTransport",
+    )
+}
+
+/// Shuts the stream down and fails with a reset error. Errors of the shutdown are ignored, as the
+/// stream may have been shut down by an earlier call
+fn poll_disconnect<Io: AsyncWrite>(io: Pin<&mut Io>, cx: &mut Context<'_>) -> Poll<io::Error> {
+    let _ = ready!(io.poll_shutdown(cx));
+    Poll::Ready(connection_reset())
+}
+
+/// Random prefix length of a buffer with more than one byte
+fn prefix_len(rng: &mut StdRng, len: usize) -> usize {
+    rng.gen_range(1..len)
+}
+
+impl<Io: AsyncRead> AsyncRead for ChaosTransport<Io> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.project();
+        if *this.disconnected {
+            return Poll::Ready(Err(connection_reset()));
+        }
+
+        let filled = buf.filled().len();
+        ready!(this.io.poll_read(cx, buf))?;
+        if buf.filled().len() > filled && this.rng.gen_bool(this.config.disconnect_probability) {
+            buf.set_filled(filled);
+            *this.disconnected = true;
+            return Poll::Ready(Err(connection_reset()));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<Io: AsyncWrite> AsyncWrite for ChaosTransport<Io> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        if *this.disconnected {
+            return poll_disconnect(this.io, cx).map(Err);
+        }
+        if buf.is_empty() {
+            return this.io.poll_write(cx, buf);
+        }
+
+        // The delay is only applied once per write even if the stream is not ready afterwards
+        if !*this.delayed {
+            if this.delay.is_none() {
+                let mut delay = this.config.latency;
+                if !this.config.jitter.is_zero() {
+                    delay += this.rng.gen_range(Duration::ZERO..=this.config.jitter);
+                }
+                if !delay.is_zero() {
+                    *this.delay = Some(Box::pin(tokio::time::sleep(delay)));
+                }
+            }
+            if let Some(delay) = this.delay.as_mut() {
+                ready!(delay.as_mut().poll(cx));
+                *this.delay = None;
+            }
+            *this.delayed = true;
+        }
+
+        if this.rng.gen_bool(this.config.disconnect_probability) {
+            *this.disconnected = true;
+            *this.delayed = false;
+            return poll_disconnect(this.io, cx).map(Err);
+        }
+
+        if buf.len() > 1 && this.rng.gen_bool(this.config.truncate_probability) {
+            let len = prefix_len(this.rng, buf.len());
+            ready!(this.io.poll_write(cx, &buf[..len]))?;
+            // Pretend that the whole buffer was written so that the rest is never sent
+            *this.disconnected = true;
+            *this.delayed = false;
+            return Poll::Ready(Ok(buf.len()));
+        }
+
+        let buf = if buf.len() > 1 && this.rng.gen_bool(this.config.partial_write_probability) {
+            &buf[..prefix_len(this.rng, buf.len())]
+        } else {
+            buf
+        };
+        let result = ready!(this.io.poll_write(cx, buf));
+        *this.delayed = false;
+        Poll::Ready(result)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.project();
+        match *this.disconnected {
+            true => poll_disconnect(this.io, cx).map(Err),
+            false => this.io.poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().io.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{ChaosConfig, ChaosTransport};
+
+    #[tokio::test]
+    async fn partial_writes_are_completed_by_write_all() {
+        let (local, mut remote) = tokio::io::duplex(1024);
+        let config = ChaosConfig::new()
+            .latency(Duration::from_millis(1))
+            .partial_writes(1.0)
+            .seed(7);
+        let mut local = ChaosTransport::new(local, config);
+
+        let payload: Vec<u8> = (0..=255).collect();
+        let mut count = 0;
+        let mut rest = &payload[..];
+        while !rest.is_empty() {
+            let written = local.write(rest).await.unwrap();
+            assert!(written < rest.len() || rest.len() == 1);
+            rest = &rest[written..];
+            count += 1;
+        }
+        assert!(count > 1);
+
+        let mut received = vec![0; payload.len()];
+        remote.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, payload);
+    }
+
+    #[tokio::test]
+    async fn truncated_write_drops_the_connection() {
+        let (local, mut remote) = tokio::io::duplex(1024);
+        let mut local = ChaosTransport::new(local, ChaosConfig::new().truncation(1.0).seed(7));
+
+        local.write_all(b"frame").await.unwrap();
+        assert!(local.is_disconnected());
+        let error = local.flush().await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset);
+
+        // The remote peer only gets a prefix of the frame before the end of the stream
+        let mut received = Vec::new();
+        remote.read_to_end(&mut received).await.unwrap();
+        assert!(!received.is_empty() && received.len() < b"frame".len());
+    }
+}
@@ -5,6 +5,8 @@ use fe2o3_amqp_types::{primitives::Binary, sasl::SaslCode};
 
 use crate::{frames, sasl_profile};
 
+use super::protocol_header::LegacyAmqpVersion;
+
 cfg_scram! {
     use crate::auth::error::ScramErrorKind;
 }
@@ -31,6 +33,27 @@ pub enum Error {
     /// Connection error: framing error
     #[error("Connection error: framing error")]
     FramingError,
+
+    /// An outgoing performative violates the spec
+    #[cfg_attr(docsrs, doc(cfg(feature = "strict-validation")))]
+    #[cfg(feature = "strict-validation")]
+    #[error(transparent)]
+    Validation(#[from] frames::validation::ValidationError),
+}
+
+impl Error {
+    /// Creates an equivalent error. An IO error is recreated from its kind and message
+    pub(crate) fn replay(&self) -> Self {
+        match self {
+            Self::Io(error) => Self::Io(io::Error::new(error.kind(), error.to_string())),
+            Self::IdleTimeoutElapsed => Self::IdleTimeoutElapsed,
+            Self::DecodeError(val) => Self::DecodeError(val.clone()),
+            Self::NotImplemented(val) => Self::NotImplemented(val.clone()),
+            Self::FramingError => Self::FramingError,
+            #[cfg(feature = "strict-validation")]
+            Self::Validation(error) => Self::Validation(error.clone()),
+        }
+    }
 }
 
 impl From<serde_amqp::Error> for Error {
@@ -48,6 +71,8 @@ impl From<frames::Error> for Error {
             frames::Error::Io(io) => Self::Io(io),
             frames::Error::DecodeError(val) => Self::DecodeError(val),
             frames::Error::NotImplemented => Self::NotImplemented(None),
+            #[cfg(feature = "strict-validation")]
+            frames::Error::Validation(error) => Self::Validation(error),
         }
     }
 }
@@ -60,6 +85,12 @@ pub enum NegotiationError {
     #[error("Protocol header mismatch {0:?}")]
     ProtocolHeaderMismatch(Bytes),
 
+    #[error("The remote peer responded with an AMQP {version} protocol header {header:?}")]
+    LegacyAmqpProtocol {
+        version: LegacyAmqpVersion,
+        header: Bytes,
+    },
+
     #[error("Invalid domain")]
     InvalidDomain,
 
@@ -78,6 +109,9 @@ pub enum NegotiationError {
         additional_data: Option<Binary>,
     },
 
+    #[error("SASL mechanism error: {0}")]
+    SaslMechanism(Box<dyn std::error::Error + Send + Sync>),
+
     /// Error with SCRAM
     #[cfg_attr(docsrs, doc(cfg(feature = "scram")))]
     #[cfg(feature = "scram")]
@@ -92,6 +126,11 @@ impl From<frames::Error> for NegotiationError {
             frames::Error::Io(err) => Self::Io(err),
             frames::Error::DecodeError(val) => Self::DecodeError(val),
             frames::Error::NotImplemented => Self::NotImplemented(None),
+            // Only the AMQP frames are validated, which are not sent during the negotiation
+            #[cfg(feature = "strict-validation")]
+            frames::Error::Validation(error) => {
+                Self::Io(io::Error::new(io::ErrorKind::InvalidInput, error))
+            }
         }
     }
 }
@@ -100,6 +139,7 @@ impl From<sasl_profile::Error> for NegotiationError {
     fn from(err: sasl_profile::Error) -> Self {
         match err {
             sasl_profile::Error::NotImplemented(msg) => Self::NotImplemented(msg),
+            sasl_profile::Error::Mechanism(error) => Self::SaslMechanism(error),
 
             #[cfg(feature = "scram")]
             sasl_profile::Error::ScramError(scram_error) => Self::ScramError(scram_error),
@@ -27,6 +27,23 @@ use crate::{
     util::IdleTimeout,
 };
 
+cfg_not_wasm32! {
+    use crate::clock::SharedClock;
+
+    type TransportClock = SharedClock;
+
+    fn default_transport_clock() -> TransportClock {
+        crate::clock::default_clock()
+    }
+}
+
+cfg_wasm32! {
+    /// There is no injectable clock in wasm32 targets
+    type TransportClock = ();
+
+    fn default_transport_clock() -> TransportClock {}
+}
+
 use protocol_header::ProtocolHeader;
 
 use self::{error::NegotiationError, protocol_header::ProtocolHeaderCodec};
@@ -35,6 +52,15 @@ pub(crate) mod error;
 pub use error::Error;
 pub mod protocol_header;
 
+cfg_chaos! {
+    pub mod chaos;
+    pub use chaos::{ChaosConfig, ChaosTransport};
+}
+
+cfg_websocket! {
+    pub mod websocket;
+}
+
 pin_project! {
     /// Frame transport
     #[derive(Debug)]
@@ -47,6 +73,9 @@ pin_project! {
 
         #[pin]
         idle_timeout: Option<IdleTimeout>,
+
+        clock: TransportClock,
+
         // frame type
         ftype: PhantomData<Ftype>,
     }
@@ -97,9 +126,26 @@ where
             framed_write,
             framed_read,
             idle_timeout,
+            clock: default_transport_clock(),
             ftype: PhantomData,
         }
     }
+
+    cfg_not_wasm32! {
+        /// Clock that drives the idle timeout
+        pub fn clock(&self) -> &SharedClock {
+            &self.clock
+        }
+
+        /// Drive the idle timeout with the given clock
+        pub fn set_clock(&mut self, clock: SharedClock) -> &mut Self {
+            if let Some(idle_timeout) = self.idle_timeout.as_mut() {
+                *idle_timeout = IdleTimeout::with_clock(idle_timeout.duration(), clock.clone());
+            }
+            self.clock = clock;
+            self
+        }
+    }
 }
 
 impl<Io> Transport<Io, ()>
@@ -265,10 +311,19 @@ where
         self
     }
 
+    /// The idle timeout of the transport, after which an error is returned if no frame has been
+    /// received
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout.as_ref().map(IdleTimeout::duration)
+    }
+
     /// Set the idle timeout of the transport
     pub fn set_idle_timeout(&mut self, duration: Duration) -> &mut Self {
         let idle_timeout = match duration.is_zero() {
             true => None,
+            #[cfg(not(target_arch = "wasm32"))]
+            false => Some(IdleTimeout::with_clock(duration, self.clock.clone())),
+            #[cfg(target_arch = "wasm32")]
             false => Some(IdleTimeout::new(duration)),
         };
 
@@ -386,6 +441,12 @@ where
 
     let mut buf = [0u8; 8];
     stream.read_exact(&mut buf).await?;
+    if let Some(version) = protocol_header::LegacyAmqpVersion::from_protocol_header(&buf) {
+        return Err(NegotiationError::LegacyAmqpProtocol {
+            version,
+            header: bytes::Bytes::copy_from_slice(&buf),
+        });
+    }
     std::convert::TryFrom::try_from(buf).map_err(|buf| {
         NegotiationError::Io(std::io::Error::new(
             std::io::ErrorKind::Other,
@@ -1,4 +1,18 @@
 //! Implements the protocol headers
+//!
+//! The constants and helpers of this module can be used by external tooling (eg. health checkers)
+//! to negotiate with an AMQP 1.0 peer without duplicating the bytes of the protocol headers.
+//!
+//! ```rust
+//! use fe2o3_amqp::transport::protocol_header::{
+//!     default_port, ProtocolHeader, ProtocolHeaderError, AMQP_HEADER, SASL_HEADER,
+//! };
+//!
+//! assert_eq!(ProtocolHeader::amqp().to_bytes(), AMQP_HEADER);
+//! assert_eq!(ProtocolHeader::parse(&SASL_HEADER), Ok(ProtocolHeader::sasl()));
+//! assert_eq!(ProtocolHeader::parse(b"AMQP"), Err(ProtocolHeaderError::Incomplete));
+//! assert_eq!(default_port("amqps"), Some(5671));
+//! ```
 
 use std::{
     convert::{TryFrom, TryInto},
@@ -10,7 +24,64 @@ use tokio_util::codec::{Decoder, Encoder};
 
 use super::error::NegotiationError;
 
-const PROTOCOL_HEADER_PREFIX: &[u8; 4] = b"AMQP";
+pub use fe2o3_amqp_types::definitions::{PORT, SECURE_PORT};
+
+/// The first four bytes of all the AMQP protocol headers
+pub const PROTOCOL_HEADER_PREFIX: &[u8; 4] = b"AMQP";
+
+/// Length of a protocol header in bytes
+pub const PROTOCOL_HEADER_LEN: usize = 8;
+
+/// AMQP 1.0 protocol header
+pub const AMQP_HEADER: [u8; PROTOCOL_HEADER_LEN] = header_bytes(ProtocolId::Amqp);
+
+/// AMQP 1.0 protocol header that starts a TLS negotiation
+pub const TLS_HEADER: [u8; PROTOCOL_HEADER_LEN] = header_bytes(ProtocolId::Tls);
+
+/// AMQP 1.0 protocol header that starts a SASL negotiation
+pub const SASL_HEADER: [u8; PROTOCOL_HEADER_LEN] = header_bytes(ProtocolId::Sasl);
+
+const fn header_bytes(id: ProtocolId) -> [u8; PROTOCOL_HEADER_LEN] {
+    [
+        PROTOCOL_HEADER_PREFIX[0],
+        PROTOCOL_HEADER_PREFIX[1],
+        PROTOCOL_HEADER_PREFIX[2],
+        PROTOCOL_HEADER_PREFIX[3],
+        id as u8,
+        fe2o3_amqp_types::definitions::MAJOR,
+        fe2o3_amqp_types::definitions::MINOR,
+        fe2o3_amqp_types::definitions::REVISION,
+    ]
+}
+
+/// Returns the default port of the `amqp` ([`PORT`]) and `amqps` ([`SECURE_PORT`]) url schemes
+pub fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "amqp" => Some(PORT),
+        "amqps" => Some(SECURE_PORT),
+        _ => None,
+    }
+}
+
+/// The bytes are not an AMQP 1.0 protocol header
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ProtocolHeaderError {
+    /// Fewer than [`PROTOCOL_HEADER_LEN`] bytes are available
+    #[error("Incomplete protocol header")]
+    Incomplete,
+
+    /// The bytes do not start with [`PROTOCOL_HEADER_PREFIX`]
+    #[error("Not an AMQP protocol header")]
+    NotAmqp,
+
+    /// The bytes are the protocol header of an AMQP version prior to 1.0
+    #[error("AMQP {} protocol header", .0)]
+    Legacy(LegacyAmqpVersion),
+
+    /// The protocol id is not one of AMQP, TLS or SASL
+    #[error("Unknown protocol id {}", .0)]
+    UnknownProtocolId(u8),
+}
 
 /// Protocol header
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -100,9 +171,30 @@ impl ProtocolHeader {
             ProtocolId::Sasl => true,
         }
     }
+
+    /// Parses the protocol header at the start of `bytes`. Bytes after the first
+    /// [`PROTOCOL_HEADER_LEN`] bytes are ignored
+    pub fn parse(bytes: &[u8]) -> Result<Self, ProtocolHeaderError> {
+        let header = bytes
+            .get(..PROTOCOL_HEADER_LEN)
+            .ok_or(ProtocolHeaderError::Incomplete)?;
+        if header[..4] != PROTOCOL_HEADER_PREFIX[..] {
+            return Err(ProtocolHeaderError::NotAmqp);
+        }
+        if let Some(version) = LegacyAmqpVersion::from_protocol_header(header) {
+            return Err(ProtocolHeaderError::Legacy(version));
+        }
+        let id = ProtocolId::try_from(header[4]).map_err(ProtocolHeaderError::UnknownProtocolId)?;
+        Ok(Self::new(id, header[5], header[6], header[7]))
+    }
+
+    /// Returns the bytes of the protocol header
+    pub fn to_bytes(&self) -> [u8; PROTOCOL_HEADER_LEN] {
+        self.clone().into()
+    }
 }
 
-impl From<ProtocolHeader> for [u8; 8] {
+impl From<ProtocolHeader> for [u8; PROTOCOL_HEADER_LEN] {
     fn from(value: ProtocolHeader) -> Self {
         [
             PROTOCOL_HEADER_PREFIX[0], // b'A'
@@ -206,6 +298,53 @@ impl TryFrom<u8> for ProtocolId {
     }
 }
 
+/// Versions of the AMQP protocols prior to AMQP 1.0
+///
+/// A peer that only speaks one of these versions (eg. RabbitMQ without the AMQP 1.0 plugin)
+/// responds with its own protocol header when it receives an AMQP 1.0 header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegacyAmqpVersion {
+    /// AMQP 0-8
+    V0_8,
+
+    /// AMQP 0-9
+    V0_9,
+
+    /// AMQP 0-9-1
+    V0_9_1,
+
+    /// AMQP 0-10
+    V0_10,
+}
+
+impl LegacyAmqpVersion {
+    /// Returns the pre-1.0 AMQP version if the bytes are a pre-1.0 AMQP protocol header
+    pub fn from_protocol_header(header: &[u8]) -> Option<Self> {
+        if header.len() != 8 || header[..4] != PROTOCOL_HEADER_PREFIX[..] {
+            return None;
+        }
+
+        match header[4..] {
+            [1, 1, 8, 0] => Some(Self::V0_8),
+            [1, 1, 0, 9] => Some(Self::V0_9),
+            [0, 0, 9, 1] => Some(Self::V0_9_1),
+            [1, 1, 0, 10] => Some(Self::V0_10),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for LegacyAmqpVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::V0_8 => write!(f, "0-8"),
+            Self::V0_9 => write!(f, "0-9"),
+            Self::V0_9_1 => write!(f, "0-9-1"),
+            Self::V0_10 => write!(f, "0-10"),
+        }
+    }
+}
+
 /// Encoder and Decoder for protocol headers
 #[derive(Debug, Clone)]
 pub struct ProtocolHeaderCodec {}
@@ -250,8 +389,85 @@ impl Decoder for ProtocolHeaderCodec {
         }
 
         let bytes = src.split_to(8).freeze();
+        if let Some(version) = LegacyAmqpVersion::from_protocol_header(&bytes) {
+            return Err(NegotiationError::LegacyAmqpProtocol {
+                version,
+                header: bytes,
+            });
+        }
         ProtocolHeader::try_from(bytes)
             .map(Some)
             .map_err(NegotiationError::ProtocolHeaderMismatch)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use tokio_util::codec::Decoder;
+
+    use crate::transport::error::NegotiationError;
+
+    use super::{
+        LegacyAmqpVersion, ProtocolHeader, ProtocolHeaderCodec, ProtocolHeaderError, AMQP_HEADER,
+        SASL_HEADER, TLS_HEADER,
+    };
+
+    #[test]
+    fn protocol_headers_are_parsed_and_emitted() {
+        assert_eq!(&AMQP_HEADER, b"AMQP\x00\x01\x00\x00");
+        assert_eq!(&TLS_HEADER, b"AMQP\x02\x01\x00\x00");
+        assert_eq!(&SASL_HEADER, b"AMQP\x03\x01\x00\x00");
+        for header in [
+            ProtocolHeader::amqp(),
+            ProtocolHeader::tls(),
+            ProtocolHeader::sasl(),
+        ] {
+            assert_eq!(ProtocolHeader::parse(&header.to_bytes()), Ok(header));
+        }
+
+        let cases: [(&[u8], ProtocolHeaderError); 4] = [
+            (b"AMQP\x00\x01", ProtocolHeaderError::Incomplete),
+            (b"HTTP/1.1", ProtocolHeaderError::NotAmqp),
+            (
+                b"AMQP\x00\x00\x09\x01",
+                ProtocolHeaderError::Legacy(LegacyAmqpVersion::V0_9_1),
+            ),
+            (
+                b"AMQP\x05\x01\x00\x00",
+                ProtocolHeaderError::UnknownProtocolId(5),
+            ),
+        ];
+        for (bytes, error) in cases {
+            assert_eq!(ProtocolHeader::parse(bytes), Err(error));
+        }
+    }
+
+    #[test]
+    fn detect_legacy_amqp_protocol_headers() {
+        let cases = [
+            (b"AMQP\x01\x01\x08\x00", LegacyAmqpVersion::V0_8),
+            (b"AMQP\x01\x01\x00\x09", LegacyAmqpVersion::V0_9),
+            (b"AMQP\x00\x00\x09\x01", LegacyAmqpVersion::V0_9_1),
+            (b"AMQP\x01\x01\x00\x0a", LegacyAmqpVersion::V0_10),
+        ];
+        for (header, expected) in cases {
+            let mut codec = ProtocolHeaderCodec::new();
+            let mut src = BytesMut::from(&header[..]);
+            match codec.decode(&mut src) {
+                Err(NegotiationError::LegacyAmqpProtocol {
+                    version,
+                    header: bytes,
+                }) => {
+                    assert_eq!(version, expected);
+                    assert_eq!(&bytes[..], &header[..]);
+                }
+                other => panic!("Expecting LegacyAmqpProtocol, found {:?}", other),
+            }
+        }
+
+        let header: [u8; 8] = ProtocolHeader::amqp().into();
+        assert!(LegacyAmqpVersion::from_protocol_header(&header).is_none());
+        assert_eq!(LegacyAmqpVersion::V0_9_1.to_string(), "0-9-1");
+    }
+}
@@ -10,8 +10,10 @@ use std::slice::Iter;
 use std::{pin::Pin, task::Poll, time::Duration};
 
 mod consumer;
+mod outcome;
 mod producer;
 pub use consumer::*;
+pub(crate) use outcome::{ReplayError, SharedOutcome};
 pub use producer::*;
 
 use crate::Payload;
@@ -23,25 +25,43 @@ pub(crate) enum Running {
 }
 
 cfg_not_wasm32! {
-    use tokio::time::{Instant, Sleep};
+    use tokio::time::Instant;
 
-    #[derive(Debug)]
+    use crate::clock::{ClockSleep, SharedClock};
+
+    /// The sleep is only re-created once it completes before the deadline, so resetting the
+    /// delay on every incoming frame does not allocate
     struct InnerDelay {
-        delay: Pin<Box<Sleep>>,
+        clock: SharedClock,
+        sleep: ClockSleep,
+        deadline: Instant,
         duration: Duration,
     }
 
+    impl std::fmt::Debug for InnerDelay {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("InnerDelay")
+                .field("clock", &self.clock)
+                .field("deadline", &self.deadline)
+                .field("duration", &self.duration)
+                .finish()
+        }
+    }
+
     impl InnerDelay {
-        fn new(duration: Duration) -> Self {
-            let delay = Box::pin(tokio::time::sleep(duration));
-            Self { delay, duration }
+        fn new(duration: Duration, clock: SharedClock) -> Self {
+            let deadline = clock.now() + duration;
+            let sleep = clock.sleep_until(deadline);
+            Self {
+                clock,
+                sleep,
+                deadline,
+                duration,
+            }
         }
 
         fn reset(&mut self) {
-            let now = Instant::now();
-            let next = now + self.duration;
-            // this is equivalent to wasm-timer's `reset_at`
-            self.delay.as_mut().reset(next);
+            self.deadline = self.clock.now() + self.duration;
         }
     }
 
@@ -49,8 +69,15 @@ cfg_not_wasm32! {
         type Output = io::Result<()>;
 
         fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
-            let delay = Pin::new(&mut self.delay);
-            delay.poll(cx).map(Ok)
+            loop {
+                futures_util::ready!(self.sleep.as_mut().poll(cx));
+                if self.clock.now() >= self.deadline {
+                    return Poll::Ready(Ok(()));
+                }
+                // The delay was reset after the sleep was created
+                let deadline = self.deadline;
+                self.sleep = self.clock.sleep_until(deadline);
+            }
         }
     }
 }
@@ -92,11 +119,27 @@ pub(crate) struct IdleTimeout {
 }
 
 impl IdleTimeout {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new(duration: Duration) -> Self {
+        Self::with_clock(duration, crate::clock::default_clock())
+    }
+
+    #[cfg(target_arch = "wasm32")]
     pub fn new(duration: Duration) -> Self {
         let delay = InnerDelay::new(duration);
         Self { delay }
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_clock(duration: Duration, clock: SharedClock) -> Self {
+        let delay = InnerDelay::new(duration, clock);
+        Self { delay }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.delay.duration
+    }
+
     pub fn reset(&mut self) {
         self.delay.reset();
     }
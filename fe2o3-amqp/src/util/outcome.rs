@@ -0,0 +1,147 @@
+//! Outcome of an event loop that can be awaited more than once
+
+use std::sync::Arc;
+
+use tokio::sync::{
+    oneshot::{self, error::TryRecvError},
+    Mutex,
+};
+
+/// An error that can be returned again after it has been recorded as the outcome of an event
+/// loop
+pub(crate) trait ReplayError: Sized {
+    /// Creates an equivalent error to return to the subsequent callers
+    fn replay(&self) -> Self;
+
+    /// The error returned when the event loop has stopped without reporting an outcome
+    fn stopped() -> Self;
+}
+
+#[derive(Debug)]
+enum State<E> {
+    Pending(oneshot::Receiver<Result<(), E>>),
+    Done(Result<(), E>),
+}
+
+/// The outcome reported by an event loop when it stops
+///
+/// The outcome is recorded the first time it is received, so that it can be awaited multiple
+/// times and by multiple clones concurrently. The first caller receives the original outcome, and
+/// all subsequent callers receive a replay of it.
+#[derive(Debug)]
+pub(crate) struct SharedOutcome<E> {
+    state: Arc<Mutex<State<E>>>,
+}
+
+impl<E> Clone for SharedOutcome<E> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<E> From<oneshot::Receiver<Result<(), E>>> for SharedOutcome<E> {
+    fn from(rx: oneshot::Receiver<Result<(), E>>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::Pending(rx))),
+        }
+    }
+}
+
+impl<E: ReplayError> SharedOutcome<E> {
+    /// Waits for the outcome of the event loop
+    pub async fn wait(&self) -> Result<(), E> {
+        // Concurrent callers queue on the lock and replay the outcome recorded by the first one
+        let mut state = self.state.lock().await;
+        match &mut *state {
+            State::Pending(rx) => {
+                let outcome = rx.await.unwrap_or_else(|_| Err(E::stopped()));
+                record(&mut state, outcome)
+            }
+            State::Done(outcome) => replay(outcome),
+        }
+    }
+
+    /// Gets the outcome without waiting. `None` is returned if the event loop has not reported
+    /// the outcome yet or if another caller is waiting for it
+    pub fn try_get(&self) -> Option<Result<(), E>> {
+        let mut state = self.state.try_lock().ok()?;
+        match &mut *state {
+            State::Pending(rx) => match rx.try_recv() {
+                Ok(outcome) => Some(record(&mut state, outcome)),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Closed) => Some(record(&mut state, Err(E::stopped()))),
+            },
+            State::Done(outcome) => Some(replay(outcome)),
+        }
+    }
+}
+
+fn record<E: ReplayError>(state: &mut State<E>, outcome: Result<(), E>) -> Result<(), E> {
+    *state = State::Done(replay(&outcome));
+    outcome
+}
+
+fn replay<E: ReplayError>(outcome: &Result<(), E>) -> Result<(), E> {
+    match outcome {
+        Ok(()) => Ok(()),
+        Err(error) => Err(error.replay()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::oneshot;
+
+    use super::{ReplayError, SharedOutcome};
+
+    #[derive(Debug, PartialEq)]
+    enum TestError {
+        Closed(u32),
+        Stopped,
+    }
+
+    impl ReplayError for TestError {
+        fn replay(&self) -> Self {
+            match self {
+                TestError::Closed(code) => TestError::Closed(*code),
+                TestError::Stopped => TestError::Stopped,
+            }
+        }
+
+        fn stopped() -> Self {
+            TestError::Stopped
+        }
+    }
+
+    #[tokio::test]
+    async fn outcome_is_replayed_to_concurrent_and_subsequent_callers() {
+        let (tx, rx) = oneshot::channel();
+        let outcome = SharedOutcome::from(rx);
+        assert!(outcome.try_get().is_none());
+
+        let waiters: Vec<_> = (0..3)
+            .map(|_| {
+                let outcome = outcome.clone();
+                tokio::spawn(async move { outcome.wait().await })
+            })
+            .collect();
+        tx.send(Err(TestError::Closed(7))).unwrap();
+        for waiter in waiters {
+            assert_eq!(waiter.await.unwrap(), Err(TestError::Closed(7)));
+        }
+
+        assert_eq!(outcome.wait().await, Err(TestError::Closed(7)));
+        assert_eq!(outcome.try_get(), Some(Err(TestError::Closed(7))));
+    }
+
+    #[tokio::test]
+    async fn dropped_sender_is_recorded_as_stopped() {
+        let (tx, rx) = oneshot::channel::<Result<(), TestError>>();
+        let outcome = SharedOutcome::from(rx);
+        drop(tx);
+        assert_eq!(outcome.try_get(), Some(Err(TestError::Stopped)));
+        assert_eq!(outcome.wait().await, Err(TestError::Stopped));
+    }
+}
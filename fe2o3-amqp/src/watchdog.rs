@@ -0,0 +1,243 @@
+//! Watchdog of the connection and session engines
+//!
+//! The event loops of the connection and the sessions run in their own tasks. A panic in one of
+//! them is caught and converted into an [`EnginePanic`], which is
+//!
+//! - returned by the handle of the engine (eg. [`ConnectionHandle::close()`](crate::connection::ConnectionHandle::close)
+//!   or [`SessionHandle::end()`](crate::session::SessionHandle::end)) as a fatal error,
+//! - broadcast to the subscribers of the [`Watchdog`], and
+//! - passed to the supervisor hook of the [`Watchdog`], which can trigger the recovery (eg.
+//!   re-opening the connection or re-attaching the links)
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let watchdog = Watchdog::new().on_panic(|panic| {
+//!     let _ = recovery_tx.send(panic.clone());
+//! });
+//! let mut panics = watchdog.subscribe();
+//!
+//! let mut connection = Connection::builder()
+//!     .container_id("connection-1")
+//!     .watchdog(watchdog)
+//!     .open("amqp://localhost:5672")
+//!     .await
+//!     .unwrap();
+//! ```
+
+use std::{any::Any, fmt, future::Future, panic::AssertUnwindSafe, sync::Arc};
+
+use futures_util::FutureExt;
+use tokio::sync::{broadcast, oneshot};
+
+/// Default number of [`EnginePanic`]s that are buffered for a lagging subscriber
+pub const DEFAULT_WATCHDOG_CAPACITY: usize = 16;
+
+/// The engine whose event loop panicked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EngineKind {
+    /// Connection engine
+    Connection,
+
+    /// Session engine
+    Session,
+}
+
+impl fmt::Display for EngineKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineKind::Connection => f.write_str("Connection"),
+            EngineKind::Session => f.write_str("Session"),
+        }
+    }
+}
+
+/// A panic that is caught in the event loop of an engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnginePanic {
+    /// The engine that panicked
+    pub engine: EngineKind,
+
+    /// The panic message, if the panic payload is a string
+    pub message: Option<String>,
+}
+
+impl EnginePanic {
+    fn new(engine: EngineKind, payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => Some(*message),
+            Err(payload) => payload
+                .downcast_ref::<&'static str>()
+                .map(|message| message.to_string()),
+        };
+        Self { engine, message }
+    }
+}
+
+impl fmt::Display for EnginePanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{} engine panicked: {}", self.engine, message),
+            None => write!(f, "{} engine panicked", self.engine),
+        }
+    }
+}
+
+impl std::error::Error for EnginePanic {}
+
+type PanicHook = Arc<dyn Fn(&EnginePanic) + Send + Sync>;
+
+/// Broadcasts the panics of the connection and session engines and calls the supervisor hook
+///
+/// The watchdog given to a connection is also used by all the sessions on the connection.
+/// Clones of a watchdog share the subscribers and the hook.
+#[derive(Clone)]
+pub struct Watchdog {
+    panics: broadcast::Sender<EnginePanic>,
+    hook: Option<PanicHook>,
+}
+
+impl fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watchdog")
+            .field("subscribers", &self.panics.receiver_count())
+            .field("hook", &self.hook.as_ref().map(|_| "Fn(&EnginePanic)"))
+            .finish()
+    }
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Watchdog {
+    /// Creates a watchdog that buffers up to [`DEFAULT_WATCHDOG_CAPACITY`] panics for each
+    /// subscriber
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_WATCHDOG_CAPACITY)
+    }
+
+    /// Creates a watchdog that buffers up to `capacity` panics for each subscriber
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero
+    pub fn with_capacity(capacity: usize) -> Self {
+        let (panics, _) = broadcast::channel(capacity);
+        Self { panics, hook: None }
+    }
+
+    /// Sets the supervisor hook that is called with every caught panic
+    ///
+    /// The hook is called on the task of the engine that panicked right before the task stops,
+    /// so it should hand the recovery over to another task instead of blocking
+    pub fn on_panic(mut self, hook: impl Fn(&EnginePanic) + Send + Sync + 'static) -> Self {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Subscribes to the panics that are caught after this call
+    pub fn subscribe(&self) -> broadcast::Receiver<EnginePanic> {
+        self.panics.subscribe()
+    }
+
+    fn report(&self, panic: &EnginePanic) {
+        // There may be no subscriber
+        let _ = self.panics.send(panic.clone());
+        if let Some(hook) = &self.hook {
+            hook(panic);
+        }
+    }
+}
+
+/// Wraps the event loop of an engine so that a panic is reported to the watchdog and to the
+/// handle of the engine as a fatal error
+pub(crate) fn supervise<E, F>(
+    engine: EngineKind,
+    watchdog: Option<Watchdog>,
+    event_loop: impl FnOnce(oneshot::Sender<Result<(), E>>) -> F,
+) -> (impl Future<Output = ()>, oneshot::Receiver<Result<(), E>>)
+where
+    F: Future<Output = ()>,
+    E: From<EnginePanic>,
+{
+    let (tx, rx) = oneshot::channel();
+    let (loop_tx, mut loop_rx) = oneshot::channel();
+    let event_loop = AssertUnwindSafe(event_loop(loop_tx)).catch_unwind();
+    let fut = async move {
+        match event_loop.await {
+            Ok(()) => {
+                if let Ok(outcome) = loop_rx.try_recv() {
+                    let _ = tx.send(outcome);
+                }
+            }
+            Err(payload) => {
+                let panic = EnginePanic::new(engine, payload);
+                #[cfg(feature = "tracing")]
+                tracing::error!(%panic);
+                #[cfg(feature = "log")]
+                log::error!("{}", panic);
+                if let Some(watchdog) = &watchdog {
+                    watchdog.report(&panic);
+                }
+                let _ = tx.send(Err(E::from(panic)));
+            }
+        }
+    };
+    (fut, rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{supervise, EngineKind, EnginePanic, Watchdog};
+
+    #[derive(Debug, PartialEq)]
+    enum TestError {
+        Stopped,
+        Panicked(EnginePanic),
+    }
+
+    impl From<EnginePanic> for TestError {
+        fn from(panic: EnginePanic) -> Self {
+            TestError::Panicked(panic)
+        }
+    }
+
+    #[tokio::test]
+    async fn outcome_of_event_loop_is_forwarded() {
+        let (fut, rx) = supervise(EngineKind::Session, None, |tx| async move {
+            let _ = tx.send(Err(TestError::Stopped));
+        });
+        fut.await;
+        assert_eq!(rx.await.unwrap(), Err(TestError::Stopped));
+    }
+
+    #[tokio::test]
+    async fn panic_is_reported_to_watchdog_and_handle() {
+        let (hook_tx, hook_rx) = std::sync::mpsc::channel();
+        let watchdog = Watchdog::new().on_panic(move |panic| {
+            hook_tx.send(panic.clone()).unwrap();
+        });
+        let mut panics = watchdog.subscribe();
+
+        let (fut, rx) =
+            supervise::<TestError, _>(EngineKind::Connection, Some(watchdog), |_tx| async move {
+                panic!("boom {}", 1)
+            });
+        tokio::spawn(fut).await.unwrap();
+
+        let expected = EnginePanic {
+            engine: EngineKind::Connection,
+            message: Some(String::from("boom 1")),
+        };
+        assert_eq!(
+            rx.await.unwrap(),
+            Err(TestError::Panicked(expected.clone()))
+        );
+        assert_eq!(panics.recv().await.unwrap(), expected);
+        assert_eq!(hook_rx.recv().unwrap(), expected);
+        assert_eq!(expected.to_string(), "Connection engine panicked: boom 1");
+    }
+}
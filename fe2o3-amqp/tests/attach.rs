@@ -0,0 +1,779 @@
+//! Tests attaching and accepting links
+
+#![cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+
+use fe2o3_amqp::{
+    acceptor::{LinkAcceptor, LinkEndpoint, SessionAcceptor},
+    link::delivery::Delivery,
+    Connection, Receiver, Session,
+};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn link_pair_is_attached_and_closed_together() {
+    use fe2o3_amqp::link::{DetachError, LinkPair, LinkStateError, RecvError};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("rpc-server", remote_stream).await;
+        let link_acceptor = LinkAcceptor::new();
+        let first = session.accept_link(&link_acceptor).await.unwrap();
+        let second = session.accept_link(&link_acceptor).await.unwrap();
+        match (first, second) {
+            (LinkEndpoint::Receiver(mut receiver), LinkEndpoint::Sender(mut sender)) => {
+                assert_eq!(receiver.name(), "rpc-client-sender");
+                assert_eq!(sender.name(), "rpc-client-receiver");
+                assert!(matches!(
+                    sender.on_detach().await,
+                    DetachError::ClosedByRemote
+                ));
+                assert!(matches!(
+                    receiver.recv::<String>().await,
+                    Err(RecvError::LinkStateError(LinkStateError::RemoteClosed))
+                ));
+            }
+            _ => panic!("Expecting a receiver and a sender"),
+        }
+        (connection, session)
+    });
+
+    let (_connection, mut session) = common::begin_session("rpc-client", local_stream).await;
+    let pair = LinkPair::attach(&mut session, "rpc-client", "rpc-server")
+        .await
+        .unwrap();
+    pair.close().await.unwrap();
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test]
+async fn accepted_links_are_handled_in_managed_tasks() {
+    use fe2o3_amqp::{
+        acceptor::{LinkTaskOutcome, LinkTasks},
+        link::DetachError,
+        Sender,
+    };
+    use fe2o3_amqp_types::definitions::{AmqpError, ErrorCondition};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let link_acceptor = LinkAcceptor::new();
+        let mut tasks = LinkTasks::with_max_concurrency(1);
+        for _ in 0..2 {
+            link_acceptor
+                .accept_sender_with(&mut session, &mut tasks, |mut sender| async move {
+                    if sender.name() == "faulty" {
+                        panic!("faulty handler");
+                    }
+                    sender.send("hello").await.unwrap();
+                    sender
+                })
+                .await
+                .unwrap();
+        }
+
+        let reports = tasks.join_all().await;
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].name, "receiver");
+        assert!(matches!(reports[0].outcome, LinkTaskOutcome::Closed));
+        assert_eq!(reports[1].name, "faulty");
+        assert!(
+            matches!(&reports[1].outcome, LinkTaskOutcome::Panicked(message) if message == "faulty handler")
+        );
+        (connection, session)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+
+    // Only senders are accepted on the remote side
+    let mut sender = Sender::attach(&mut session, "sender", "q1").await.unwrap();
+    match sender.on_detach().await {
+        DetachError::RemoteClosedWithError(error) => assert_eq!(
+            error.condition,
+            ErrorCondition::AmqpError(AmqpError::NotAllowed)
+        ),
+        error => panic!("Unexpected error {:?}", error),
+    }
+
+    let mut receiver = Receiver::attach(&mut session, "receiver", "q1")
+        .await
+        .unwrap();
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    receiver.accept(&delivery).await.unwrap();
+    assert_eq!(delivery.body(), "hello");
+    assert!(receiver.recv::<String>().await.is_err());
+
+    // The panic of the handler drops the link, which closes it
+    let mut faulty = Receiver::attach(&mut session, "faulty", "q1")
+        .await
+        .unwrap();
+    assert!(faulty.recv::<String>().await.is_err());
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn authorizer_denies_links_and_deliveries() {
+    use fe2o3_amqp::{
+        acceptor::{
+            error::AcceptorAttachError, AttachRequest, Authorization, Authorizer,
+            ConnectionAcceptor, Direction, SaslPlainMechanism, TransferRequest,
+        },
+        link::{LinkStateError, RecvError, SenderAttachError},
+        sasl_profile::SaslProfile,
+        Sender,
+    };
+    use fe2o3_amqp_types::{
+        definitions::{AmqpError, ErrorCondition},
+        messaging::{Message, Properties},
+    };
+
+    struct Acl;
+
+    impl Authorizer for Acl {
+        fn authorize_attach(&self, request: &AttachRequest<'_>) -> Authorization {
+            match (request.identity, request.direction, request.address) {
+                (Some("alice"), Direction::Send, Some("orders")) => Authorization::Allow,
+                _ => Authorization::deny("Only alice may send orders"),
+            }
+        }
+
+        fn authorize_transfer(&self, request: &TransferRequest<'_>) -> Authorization {
+            let user_id = request.properties.and_then(|p| p.user_id.as_ref());
+            match (user_id, request.identity) {
+                (Some(user_id), Some(identity)) if user_id.as_slice() == identity.as_bytes() => {
+                    Authorization::Allow
+                }
+                _ => Authorization::deny("The user-id does not match the identity"),
+            }
+        }
+    }
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::builder()
+            .container_id("broker")
+            .sasl_acceptor(SaslPlainMechanism::new("alice", "secret"))
+            .build()
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        assert_eq!(connection.authenticated_identity(), Some("alice"));
+        let mut session = SessionAcceptor::new()
+            .accept(&mut connection)
+            .await
+            .unwrap();
+        let link_acceptor = LinkAcceptor::builder().authorizer(Acl).build();
+
+        let result = link_acceptor.accept(&mut session).await;
+        assert!(matches!(
+            result,
+            Err(AcceptorAttachError::LocalSender(
+                SenderAttachError::Unauthorized(_)
+            ))
+        ));
+
+        let mut receiver = common::accept_receiver(&mut session, &link_acceptor).await;
+        // The forged delivery is rejected before it reaches the receiver
+        let delivery: Delivery<String> = receiver.recv().await.unwrap();
+        receiver.accept(&delivery).await.unwrap();
+        assert_eq!(delivery.body(), "from alice");
+        assert!(receiver.recv::<String>().await.is_err());
+        (connection, session)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .sasl_profile(SaslProfile::from(("alice", "secret")))
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::begin(&mut connection).await.unwrap();
+
+    // Only sending to the orders address is allowed
+    let mut receiver = Receiver::attach(&mut session, "receiver", "orders")
+        .await
+        .unwrap();
+    match receiver.recv::<String>().await {
+        Err(RecvError::LinkStateError(LinkStateError::RemoteClosedWithError(error))) => assert_eq!(
+            error.condition,
+            ErrorCondition::AmqpError(AmqpError::UnauthorizedAccess)
+        ),
+        result => panic!("Unexpected result {:?}", result),
+    }
+
+    let mut sender = Sender::attach(&mut session, "sender", "orders")
+        .await
+        .unwrap();
+    let forged = Message::builder()
+        .properties(Properties::builder().user_id(b"mallory".to_vec()).build())
+        .value("from mallory".to_string())
+        .build();
+    let outcome = sender.send(forged).await.unwrap();
+    assert_eq!(
+        outcome.rejected_error().map(|error| &error.condition),
+        Some(&ErrorCondition::AmqpError(AmqpError::UnauthorizedAccess))
+    );
+
+    let genuine = Message::builder()
+        .properties(Properties::builder().user_id(b"alice".to_vec()).build())
+        .value("from alice".to_string())
+        .build();
+    assert!(sender.send(genuine).await.unwrap().is_accepted());
+    sender.close().await.unwrap();
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn authorizer_sees_the_identity_of_a_virtual_host_connection() {
+    use fe2o3_amqp::{
+        acceptor::{
+            AttachRequest, Authorization, Authorizer, ConnectionAcceptor, SaslPlainMechanism,
+            VirtualHostAcceptor,
+        },
+        sasl_profile::SaslProfile,
+        Sender,
+    };
+
+    struct OnlyAlice;
+
+    impl Authorizer for OnlyAlice {
+        fn authorize_attach(&self, request: &AttachRequest<'_>) -> Authorization {
+            match request.identity {
+                Some("alice") => Authorization::Allow,
+                _ => Authorization::deny("Only alice may attach"),
+            }
+        }
+    }
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        // The virtual host is picked by the hostname of the SASL init
+        let tenant = ConnectionAcceptor::builder()
+            .container_id("tenant-a")
+            .sasl_acceptor(SaslPlainMechanism::new("alice", "secret"))
+            .build();
+        let mut connection = VirtualHostAcceptor::new()
+            .virtual_host("tenant-a.example.com", tenant)
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        assert_eq!(connection.authenticated_identity(), Some("alice"));
+        let mut session = SessionAcceptor::new()
+            .accept(&mut connection)
+            .await
+            .unwrap();
+        let link_acceptor = LinkAcceptor::builder().authorizer(OnlyAlice).build();
+        let mut receiver = common::accept_receiver(&mut session, &link_acceptor).await;
+        let delivery: Delivery<String> = receiver.recv().await.unwrap();
+        receiver.accept(&delivery).await.unwrap();
+        assert_eq!(delivery.body(), "from alice");
+        (connection, session, receiver)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .hostname("tenant-a.example.com")
+        .sasl_profile(SaslProfile::from(("alice", "secret")))
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::begin(&mut connection).await.unwrap();
+    let mut sender = Sender::attach(&mut session, "sender", "orders")
+        .await
+        .unwrap();
+    let outcome = sender.send("from alice".to_string()).await.unwrap();
+    assert!(outcome.is_accepted());
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn subscription_router_fans_out_to_matching_patterns() {
+    use fe2o3_amqp::{
+        acceptor::{error::RouterError, SubscriptionRouter},
+        link::RecvError,
+        types::{
+            messaging::{annotations::OwnedKey, Message, MessageAnnotations, Properties},
+            primitives::Value,
+        },
+        Sender,
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let router = SubscriptionRouter::new();
+        let mut subscriptions = Vec::new();
+        loop {
+            match session.accept_link(&LinkAcceptor::new()).await.unwrap() {
+                LinkEndpoint::Sender(sender) => {
+                    subscriptions.push(router.subscribe(sender).unwrap())
+                }
+                LinkEndpoint::Receiver(mut receiver) => {
+                    let error = router.route(&mut receiver).await;
+                    assert!(matches!(
+                        error,
+                        RouterError::Recv(RecvError::LinkStateError(_))
+                    ));
+                    receiver.close().await.unwrap();
+                    break;
+                }
+            }
+        }
+        let patterns: Vec<_> = subscriptions
+            .iter()
+            .map(|subscription| subscription.pattern().to_string())
+            .collect();
+        assert_eq!(patterns, ["orders.*", "#"]);
+        (connection, session, subscriptions)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut orders = Receiver::attach(&mut session, "orders", "orders.*")
+        .await
+        .unwrap();
+    let mut all = Receiver::attach(&mut session, "all", "#").await.unwrap();
+    let mut sender = Sender::attach(&mut session, "publisher", "orders.created")
+        .await
+        .unwrap();
+
+    // Routed by the target address of the link
+    sender.send("created").await.unwrap();
+    // Routed by the `to` field of the properties
+    let message = Message::builder()
+        .properties(Properties::builder().to(String::from("logs.error")).build())
+        .value("error")
+        .build();
+    sender.send(message).await.unwrap();
+    // Routed by the message annotation
+    let mut annotations = MessageAnnotations(Default::default());
+    annotations.insert(
+        OwnedKey::from("x-opt-routing-key"),
+        Value::String(String::from("orders.eu.created")),
+    );
+    let message = Message::builder()
+        .message_annotations(annotations)
+        .value("eu-created")
+        .build();
+    sender.send(message).await.unwrap();
+    sender.close().await.unwrap();
+
+    let delivery = orders.recv::<String>().await.unwrap();
+    assert_eq!(delivery.body(), "created");
+    orders.accept(&delivery).await.unwrap();
+    for expected in ["created", "error", "eu-created"] {
+        let delivery = all.recv::<String>().await.unwrap();
+        assert_eq!(delivery.body(), expected);
+        all.accept(&delivery).await.unwrap();
+    }
+    assert!(tokio::time::timeout(
+        std::time::Duration::from_millis(100),
+        orders.recv::<String>()
+    )
+    .await
+    .is_err());
+
+    let (_connection, _session, subscriptions) = remote.await.unwrap();
+    assert!(subscriptions
+        .iter()
+        .all(|subscription| !subscription.is_finished()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refused_attach_is_retried_with_backoff() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{
+        frames::amqp::{Frame, FrameBody},
+        link::AttachRetry,
+        transport::Transport,
+        types::{
+            definitions::{self, AmqpError, Role},
+            performatives::{Begin, Detach, Open},
+        },
+        Sender,
+    };
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (local_stream, mut remote_stream) = tokio::io::duplex(64 * 1024);
+    // A broker that refuses the first attach because it is busy
+    let broker = tokio::spawn(async move {
+        let header = *b"AMQP\x00\x01\x00\x00";
+        let mut incoming_header = [0u8; 8];
+        remote_stream
+            .read_exact(&mut incoming_header)
+            .await
+            .unwrap();
+        remote_stream.write_all(&header).await.unwrap();
+
+        let mut transport: Transport<_, Frame> = Transport::bind(remote_stream, 64 * 1024, None);
+        let frame = transport.next().await.unwrap().unwrap();
+        assert!(matches!(frame.body, FrameBody::Open(_)));
+        let open = Open {
+            container_id: String::from("broker"),
+            hostname: None,
+            max_frame_size: Default::default(),
+            channel_max: Default::default(),
+            idle_time_out: None,
+            outgoing_locales: None,
+            incoming_locales: None,
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        };
+        transport
+            .send(Frame::new(0u16, FrameBody::Open(open)))
+            .await
+            .unwrap();
+        let frame = transport.next().await.unwrap().unwrap();
+        assert!(matches!(frame.body, FrameBody::Begin(_)));
+        let begin = Begin {
+            remote_channel: Some(0),
+            next_outgoing_id: 0,
+            incoming_window: 2048,
+            outgoing_window: 2048,
+            handle_max: Default::default(),
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        };
+        transport
+            .send(Frame::new(0u16, FrameBody::Begin(begin)))
+            .await
+            .unwrap();
+
+        let mut attempts = 0;
+        loop {
+            let attach = match transport.next().await.unwrap().unwrap().body {
+                FrameBody::Attach(attach) => attach,
+                // The reply to the Detach that refused the link
+                FrameBody::Detach(_) => continue,
+                body => panic!("Unexpected frame {:?}", body),
+            };
+            attempts += 1;
+            let handle = attach.handle.clone();
+            let mut reply = attach;
+            reply.role = Role::Receiver;
+            if attempts < 2 {
+                reply.target = None;
+                let detach = Detach {
+                    handle,
+                    closed: true,
+                    error: Some(definitions::Error::new(
+                        AmqpError::ResourceLimitExceeded,
+                        String::from("The broker is busy"),
+                        None,
+                    )),
+                };
+                transport
+                    .send(Frame::new(0u16, FrameBody::Attach(reply)))
+                    .await
+                    .unwrap();
+                transport
+                    .send(Frame::new(0u16, FrameBody::Detach(detach)))
+                    .await
+                    .unwrap();
+            } else {
+                transport
+                    .send(Frame::new(0u16, FrameBody::Attach(reply)))
+                    .await
+                    .unwrap();
+                return (attempts, transport);
+            }
+        }
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let retry = AttachRetry::new(3).backoff(Duration::from_millis(10), Duration::from_millis(10));
+    let _sender = Sender::builder()
+        .name("sender")
+        .target("q1")
+        .attach_retry(retry)
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    let (attempts, _transport) = broker.await.unwrap();
+    assert_eq!(attempts, 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn attach_with_a_link_name_in_use_is_rejected() {
+    use fe2o3_amqp::{
+        frames::amqp::{Frame, FrameBody},
+        types::{
+            definitions::{AmqpError, ErrorCondition},
+            performatives::Detach,
+        },
+    };
+    use futures_util::{SinkExt, StreamExt};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let link_acceptor = LinkAcceptor::new();
+        let first = session.accept_link(&link_acceptor).await.unwrap();
+        assert!(session.accept_link(&link_acceptor).await.is_err());
+        // The session is still usable after the link is refused
+        let other = session.accept_link(&link_acceptor).await.unwrap();
+        (connection, session, first, other)
+    });
+
+    let mut transport = common::begin_raw_session(local_stream, u32::MAX).await;
+    transport
+        .send(common::raw_receiver_attach("dup", 0))
+        .await
+        .unwrap();
+    transport
+        .send(common::raw_receiver_attach("dup", 1))
+        .await
+        .unwrap();
+
+    // The incoming link is attached without a source and detached right away
+    let mut rejected = None;
+    let mut attaches = Vec::new();
+    while rejected.is_none() {
+        match transport.next().await.unwrap().unwrap().body {
+            FrameBody::Attach(attach) => attaches.push(attach),
+            FrameBody::Detach(detach) => rejected = Some(detach),
+            FrameBody::End(end) => panic!("Session is ended {:?}", end),
+            _ => {}
+        }
+    }
+    let rejected = rejected.unwrap();
+    assert_eq!(attaches.len(), 2);
+    assert!(attaches[0].source.is_some());
+    assert!(attaches[1].source.is_none());
+    assert_eq!(attaches[1].handle, rejected.handle);
+    assert!(rejected.closed);
+    assert_eq!(
+        rejected.error.unwrap().condition,
+        ErrorCondition::AmqpError(AmqpError::ResourceLocked)
+    );
+
+    // The reply to the Detach is discarded and other links can still be attached
+    let detach = Detach {
+        handle: 1.into(),
+        closed: true,
+        error: None,
+    };
+    transport
+        .send(Frame::new(0u16, FrameBody::Detach(detach)))
+        .await
+        .unwrap();
+    transport
+        .send(common::raw_receiver_attach("other", 2))
+        .await
+        .unwrap();
+    let _endpoints = remote.await.unwrap();
+    loop {
+        match transport.next().await.unwrap().unwrap().body {
+            FrameBody::Attach(attach) => {
+                assert_eq!(attach.name, "other");
+                assert!(attach.source.is_some());
+                break;
+            }
+            FrameBody::Detach(detach) => panic!("Unexpected detach {:?}", detach),
+            FrameBody::End(end) => panic!("Session is ended {:?}", end),
+            _ => {}
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn attach_with_a_link_name_in_use_steals_the_link() {
+    use fe2o3_amqp::{
+        acceptor::LinkNameCollision,
+        frames::amqp::FrameBody,
+        link::DetachError,
+        types::definitions::{ErrorCondition, LinkError},
+    };
+    use futures_util::{SinkExt, StreamExt};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let link_acceptor = LinkAcceptor::builder()
+            .link_name_collision(LinkNameCollision::Steal)
+            .build();
+        let first = session.accept_link(&link_acceptor).await.unwrap();
+        let second = session.accept_link(&link_acceptor).await.unwrap();
+        (connection, session, first, second)
+    });
+
+    let mut transport = common::begin_raw_session(local_stream, u32::MAX).await;
+    transport
+        .send(common::raw_receiver_attach("dup", 0))
+        .await
+        .unwrap();
+    transport
+        .send(common::raw_receiver_attach("dup", 1))
+        .await
+        .unwrap();
+    let (_connection, _session, first, _second) = remote.await.unwrap();
+
+    // The attached link is detached with `amqp:link:stolen` before the new link is attached
+    let mut attaches = Vec::new();
+    let mut stolen = None;
+    while attaches.len() < 2 {
+        match transport.next().await.unwrap().unwrap().body {
+            FrameBody::Attach(attach) => {
+                assert_eq!(stolen.is_some(), !attaches.is_empty());
+                attaches.push(attach)
+            }
+            FrameBody::Detach(detach) => stolen = Some(detach),
+            FrameBody::End(end) => panic!("Session is ended {:?}", end),
+            _ => {}
+        }
+    }
+    let stolen = stolen.unwrap();
+    assert_eq!(stolen.handle, attaches[0].handle);
+    assert_eq!(
+        stolen.error.unwrap().condition,
+        ErrorCondition::LinkError(LinkError::Stolen)
+    );
+    assert_eq!(attaches[1].name, "dup");
+    assert!(attaches[1].source.is_some());
+
+    let sender = common::expect_sender(first);
+    match sender.close().await {
+        Err(DetachError::RemoteClosedWithError(error)) => assert_eq!(
+            error.condition,
+            ErrorCondition::LinkError(LinkError::Stolen)
+        ),
+        other => panic!("Unexpected result {:?}", other),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn settle_modes_are_negotiated_by_the_link_acceptor_policy() {
+    use fe2o3_amqp::{
+        acceptor::SettleModePolicy,
+        frames::amqp::{Frame, FrameBody},
+        types::{
+            definitions::{AmqpError, ErrorCondition, ReceiverSettleMode, SenderSettleMode},
+            performatives::Detach,
+        },
+    };
+    use futures_util::{SinkExt, StreamExt};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let policy = SettleModePolicy::new()
+            .support(SenderSettleMode::Unsettled, ReceiverSettleMode::First)
+            .downgrade_receiver(ReceiverSettleMode::Second, ReceiverSettleMode::First);
+        let link_acceptor = LinkAcceptor::builder().settle_mode_policy(policy).build();
+        let downgraded = session.accept_link(&link_acceptor).await.unwrap();
+        assert!(session.accept_link(&link_acceptor).await.is_err());
+        (connection, session, downgraded)
+    });
+
+    let mut transport = common::begin_raw_session(local_stream, u32::MAX).await;
+    let mut downgraded = common::raw_receiver_attach("downgraded", 0);
+    if let FrameBody::Attach(attach) = &mut downgraded.body {
+        attach.snd_settle_mode = SenderSettleMode::Unsettled;
+        attach.rcv_settle_mode = ReceiverSettleMode::Second;
+    }
+    transport.send(downgraded).await.unwrap();
+    let mut refused = common::raw_receiver_attach("refused", 1);
+    if let FrameBody::Attach(attach) = &mut refused.body {
+        attach.snd_settle_mode = SenderSettleMode::Settled;
+    }
+    transport.send(refused).await.unwrap();
+
+    // The downgraded modes are sent back in the echoed Attach
+    let mut attaches = Vec::new();
+    let mut rejected = None;
+    while rejected.is_none() {
+        match transport.next().await.unwrap().unwrap().body {
+            FrameBody::Attach(attach) => attaches.push(attach),
+            FrameBody::Detach(detach) => rejected = Some(detach),
+            FrameBody::End(end) => panic!("Session is ended {:?}", end),
+            _ => {}
+        }
+    }
+    assert_eq!(attaches.len(), 2);
+    assert_eq!(attaches[0].name, "downgraded");
+    assert_eq!(attaches[0].snd_settle_mode, SenderSettleMode::Unsettled);
+    assert_eq!(attaches[0].rcv_settle_mode, ReceiverSettleMode::First);
+
+    // The combination without a rule is refused
+    let rejected = rejected.unwrap();
+    assert_eq!(attaches[1].name, "refused");
+    assert_eq!(attaches[1].handle, rejected.handle);
+    assert!(rejected.closed);
+    assert_eq!(
+        rejected.error.unwrap().condition,
+        ErrorCondition::AmqpError(AmqpError::NotImplemented)
+    );
+    let detach = Detach {
+        handle: 1.into(),
+        closed: true,
+        error: None,
+    };
+    transport
+        .send(Frame::new(0u16, FrameBody::Detach(detach)))
+        .await
+        .unwrap();
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn link_and_session_state_transitions_are_observable() {
+    use fe2o3_amqp::{
+        link::{DetachError, LinkState},
+        types::states::SessionState,
+        Sender,
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (detach_tx, detach_rx) = tokio::sync::oneshot::channel::<()>();
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        detach_rx.await.unwrap();
+        let _detached = receiver.detach().await.unwrap();
+        session.end().await.unwrap();
+        connection
+    });
+
+    let (mut connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut session_state = session.watch_state();
+    assert!(matches!(*session_state.borrow(), SessionState::Mapped));
+
+    let mut sender = Sender::attach(&mut session, "sender", "q1").await.unwrap();
+    let mut link_state = sender.watch_state();
+    assert_eq!(*link_state.borrow(), LinkState::Attached);
+
+    // A supervisor awaits the transition instead of inferring it from a failed operation
+    let supervisor = tokio::spawn(async move {
+        link_state
+            .wait_for(|state| *state == LinkState::DetachReceived)
+            .await
+            .is_ok()
+    });
+    detach_tx.send(()).unwrap();
+    assert!(matches!(
+        sender.on_detach().await,
+        DetachError::DetachedByRemote
+    ));
+    assert!(supervisor.await.unwrap());
+    let _detached = sender.detach().await.unwrap();
+
+    let ended = session_state
+        .wait_for(|state| matches!(state, SessionState::EndReceived | SessionState::Unmapped))
+        .await
+        .is_ok();
+    assert!(ended);
+
+    let _remote = remote.await.unwrap();
+    connection.close().await.unwrap();
+}
@@ -1,5 +1,13 @@
+//! Helpers shared by the integration tests
+
+#![allow(dead_code)]
+
+use fe2o3_amqp::{connection::ConnectionHandle, session::SessionHandle, Connection, Session};
 use testcontainers::{clients::Cli, core::WaitFor, Container, GenericImage};
-use tokio::sync::OnceCell;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::OnceCell,
+};
 
 static DOCKER: OnceCell<Cli> = OnceCell::const_new();
 
@@ -49,3 +57,196 @@ pub async fn setup_rabbitmq_amqp10(
     let port = node.get_host_port_ipv4(5672);
     (node, port)
 }
+
+/// Accepts a connection and then a session from the client on the other end of `stream`
+#[cfg(feature = "acceptor")]
+pub async fn accept_session<Io>(
+    container_id: &str,
+    stream: Io,
+) -> (
+    fe2o3_amqp::acceptor::ListenerConnectionHandle,
+    fe2o3_amqp::acceptor::ListenerSessionHandle,
+)
+where
+    Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+{
+    use fe2o3_amqp::acceptor::{ConnectionAcceptor, SessionAcceptor};
+
+    let mut connection = ConnectionAcceptor::new(container_id)
+        .accept(stream)
+        .await
+        .unwrap();
+    let session = SessionAcceptor::new()
+        .accept(&mut connection)
+        .await
+        .unwrap();
+    (connection, session)
+}
+
+/// Opens a connection on `stream` and begins a session on it
+pub async fn begin_session<Io>(
+    container_id: &str,
+    stream: Io,
+) -> (ConnectionHandle<()>, SessionHandle<()>)
+where
+    Io: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+{
+    let mut connection = Connection::builder()
+        .container_id(container_id)
+        .open_with_stream(stream)
+        .await
+        .unwrap();
+    let session = Session::begin(&mut connection).await.unwrap();
+    (connection, session)
+}
+
+/// Opens a connection on a raw transport, so that the tests can send frames the client API
+/// refuses to send
+pub async fn open_raw_connection(
+    mut stream: tokio::io::DuplexStream,
+    max_frame_size: u32,
+) -> fe2o3_amqp::transport::Transport<tokio::io::DuplexStream, fe2o3_amqp::frames::amqp::Frame> {
+    use fe2o3_amqp::{
+        frames::amqp::{Frame, FrameBody},
+        transport::Transport,
+        types::performatives::Open,
+    };
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let header = *b"AMQP\x00\x01\x00\x00";
+    stream.write_all(&header).await.unwrap();
+    let mut incoming_header = [0u8; 8];
+    stream.read_exact(&mut incoming_header).await.unwrap();
+    assert_eq!(incoming_header, header);
+
+    let mut transport = Transport::bind(stream, 64 * 1024, None);
+    let open = Open {
+        container_id: String::from("client"),
+        hostname: None,
+        max_frame_size: max_frame_size.into(),
+        channel_max: Default::default(),
+        idle_time_out: None,
+        outgoing_locales: None,
+        incoming_locales: None,
+        offered_capabilities: None,
+        desired_capabilities: None,
+        properties: None,
+    };
+    transport
+        .send(Frame::new(0u16, FrameBody::Open(open)))
+        .await
+        .unwrap();
+    let frame = transport.next().await.unwrap().unwrap();
+    assert!(matches!(frame.body, FrameBody::Open(_)));
+    transport
+}
+
+/// Opens a connection and begins a session on a raw transport
+pub async fn begin_raw_session(
+    stream: tokio::io::DuplexStream,
+    max_frame_size: u32,
+) -> fe2o3_amqp::transport::Transport<tokio::io::DuplexStream, fe2o3_amqp::frames::amqp::Frame> {
+    use fe2o3_amqp::frames::amqp::FrameBody;
+    use futures_util::{SinkExt, StreamExt};
+
+    let mut transport = open_raw_connection(stream, max_frame_size).await;
+    transport.send(raw_begin(0)).await.unwrap();
+    let frame = transport.next().await.unwrap().unwrap();
+    assert!(matches!(frame.body, FrameBody::Begin(_)));
+    transport
+}
+
+/// Begin of a session initiated by the raw client on `channel`
+pub fn raw_begin(channel: u16) -> fe2o3_amqp::frames::amqp::Frame {
+    use fe2o3_amqp::{
+        frames::amqp::{Frame, FrameBody},
+        types::performatives::Begin,
+    };
+
+    let begin = Begin {
+        remote_channel: None,
+        next_outgoing_id: 0,
+        incoming_window: 2048,
+        outgoing_window: 2048,
+        handle_max: Default::default(),
+        offered_capabilities: None,
+        desired_capabilities: None,
+        properties: None,
+    };
+    Frame::new(channel, FrameBody::Begin(begin))
+}
+
+/// Attach of a receiving link from the raw client
+pub fn raw_receiver_attach(name: &str, handle: u32) -> fe2o3_amqp::frames::amqp::Frame {
+    use fe2o3_amqp::{
+        frames::amqp::{Frame, FrameBody},
+        types::{
+            definitions::Role,
+            messaging::{Source, Target},
+            performatives::Attach,
+        },
+    };
+
+    let attach = Attach {
+        name: String::from(name),
+        handle: handle.into(),
+        role: Role::Receiver,
+        snd_settle_mode: Default::default(),
+        rcv_settle_mode: Default::default(),
+        source: Some(Box::new(Source::builder().address("q1").build())),
+        target: Some(Box::new(Target::builder().build().into())),
+        unsettled: None,
+        incomplete_unsettled: false,
+        initial_delivery_count: None,
+        max_message_size: None,
+        offered_capabilities: None,
+        desired_capabilities: None,
+        properties: None,
+    };
+    Frame::new(0u16, FrameBody::Attach(attach))
+}
+
+/// Accepts the next incoming link with `acceptor`, expecting the local side to be the sender
+#[cfg(feature = "acceptor")]
+pub async fn accept_sender<FS, FT>(
+    session: &mut fe2o3_amqp::acceptor::ListenerSessionHandle,
+    acceptor: &fe2o3_amqp::acceptor::LinkAcceptor<FS, FT>,
+) -> fe2o3_amqp::Sender
+where
+    FS: Fn(fe2o3_amqp::types::messaging::Source) -> Option<fe2o3_amqp::types::messaging::Source>,
+    FT: Fn(fe2o3_amqp::types::messaging::Target) -> Option<fe2o3_amqp::types::messaging::Target>,
+{
+    expect_sender(session.accept_link(acceptor).await.unwrap())
+}
+
+/// Accepts the next incoming link with `acceptor`, expecting the local side to be the receiver
+#[cfg(feature = "acceptor")]
+pub async fn accept_receiver<FS, FT>(
+    session: &mut fe2o3_amqp::acceptor::ListenerSessionHandle,
+    acceptor: &fe2o3_amqp::acceptor::LinkAcceptor<FS, FT>,
+) -> fe2o3_amqp::Receiver
+where
+    FS: Fn(fe2o3_amqp::types::messaging::Source) -> Option<fe2o3_amqp::types::messaging::Source>,
+    FT: Fn(fe2o3_amqp::types::messaging::Target) -> Option<fe2o3_amqp::types::messaging::Target>,
+{
+    expect_receiver(session.accept_link(acceptor).await.unwrap())
+}
+
+/// Unwraps an accepted link on which the local side is the sender
+#[cfg(feature = "acceptor")]
+pub fn expect_sender(endpoint: fe2o3_amqp::acceptor::LinkEndpoint) -> fe2o3_amqp::Sender {
+    match endpoint {
+        fe2o3_amqp::acceptor::LinkEndpoint::Sender(sender) => sender,
+        fe2o3_amqp::acceptor::LinkEndpoint::Receiver(_) => panic!("Expecting a sender"),
+    }
+}
+
+/// Unwraps an accepted link on which the local side is the receiver
+#[cfg(feature = "acceptor")]
+pub fn expect_receiver(endpoint: fe2o3_amqp::acceptor::LinkEndpoint) -> fe2o3_amqp::Receiver {
+    match endpoint {
+        fe2o3_amqp::acceptor::LinkEndpoint::Receiver(receiver) => receiver,
+        fe2o3_amqp::acceptor::LinkEndpoint::Sender(_) => panic!("Expecting a receiver"),
+    }
+}
@@ -0,0 +1,1640 @@
+//! Tests opening, accepting and closing connections
+
+#![cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+
+use fe2o3_amqp::{
+    acceptor::{LinkAcceptor, SessionAcceptor},
+    link::delivery::Delivery,
+    Connection, Receiver, Session,
+};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn outgoing_connection_accepts_remote_session_and_link() {
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let local = tokio::spawn(async move {
+        let mut connection = Connection::builder()
+            .container_id("edge-device")
+            .accept_incoming_sessions(true)
+            .open_with_stream(local_stream)
+            .await
+            .unwrap();
+        let mut session = connection
+            .accept_session(&SessionAcceptor::new())
+            .await
+            .unwrap();
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+
+        let outcome = sender.send("hello".to_string()).await.unwrap();
+        assert!(outcome.is_accepted());
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, sender)
+    });
+
+    let (_connection, mut session) = common::begin_session("cloud-service", remote_stream).await;
+    let mut receiver = Receiver::attach(&mut session, "receiver", "q1")
+        .await
+        .unwrap();
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    receiver.accept(&delivery).await.unwrap();
+    assert_eq!(delivery.body(), "hello");
+
+    let _endpoints = local.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn remote_session_is_refused_while_the_incoming_sessions_are_full() {
+    use fe2o3_amqp::{
+        frames::amqp::FrameBody,
+        types::{
+            definitions::AmqpError,
+            performatives::{End, Flow},
+        },
+    };
+    use futures_util::{SinkExt, StreamExt};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let (connection, mut transport) = tokio::join!(
+        Connection::builder()
+            .container_id("edge-device")
+            .accept_incoming_sessions(true)
+            .buffer_size(1)
+            .open_with_stream(local_stream),
+        common::open_raw_connection(remote_stream, u32::MAX)
+    );
+    let mut connection = connection.unwrap();
+
+    // The first session waits to be accepted and the second one does not fit
+    transport.send(common::raw_begin(0)).await.unwrap();
+    transport.send(common::raw_begin(1)).await.unwrap();
+    let frame = transport.next().await.unwrap().unwrap();
+    let refused_channel = frame.channel;
+    match frame.body {
+        FrameBody::Begin(begin) => assert_eq!(begin.remote_channel, Some(1)),
+        body => panic!("Expecting a begin, got {:?}", body),
+    }
+    let frame = transport.next().await.unwrap().unwrap();
+    assert_eq!(frame.channel, refused_channel);
+    match frame.body {
+        FrameBody::End(End { error: Some(error) }) => {
+            assert_eq!(error.condition, AmqpError::ResourceLimitExceeded.into())
+        }
+        body => panic!("Expecting an end with an error, got {:?}", body),
+    }
+
+    let _first = connection
+        .accept_session(&SessionAcceptor::new())
+        .await
+        .unwrap();
+    let frame = transport.next().await.unwrap().unwrap();
+    match frame.body {
+        FrameBody::Begin(begin) => assert_eq!(begin.remote_channel, Some(0)),
+        body => panic!("Expecting a begin, got {:?}", body),
+    }
+
+    // The frames of the refused session are discarded until its end, which releases the channel
+    let flow = Flow {
+        next_incoming_id: Some(0),
+        incoming_window: 2048,
+        next_outgoing_id: 0,
+        outgoing_window: 2048,
+        handle: None,
+        delivery_count: None,
+        link_credit: None,
+        available: None,
+        drain: false,
+        echo: false,
+        properties: None,
+    };
+    transport
+        .send(fe2o3_amqp::frames::amqp::Frame::new(
+            1u16,
+            FrameBody::Flow(flow),
+        ))
+        .await
+        .unwrap();
+    let end = End { error: None };
+    transport
+        .send(fe2o3_amqp::frames::amqp::Frame::new(
+            1u16,
+            FrameBody::End(end),
+        ))
+        .await
+        .unwrap();
+    transport.send(common::raw_begin(2)).await.unwrap();
+    let _second = connection
+        .accept_session(&SessionAcceptor::new())
+        .await
+        .unwrap();
+    let frame = transport.next().await.unwrap().unwrap();
+    assert_eq!(frame.channel, refused_channel);
+    match frame.body {
+        FrameBody::Begin(begin) => assert_eq!(begin.remote_channel, Some(2)),
+        body => panic!("Expecting a begin, got {:?}", body),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn custom_sasl_mechanism_handles_challenge_and_outcome() {
+    use std::sync::{Arc, Mutex};
+
+    use fe2o3_amqp::{
+        acceptor::{sasl_acceptor::SaslServerFrame, ConnectionAcceptor, SaslAcceptor},
+        connection::OpenError,
+        sasl_profile::{Error, SaslClientMechanism, SaslClientMechanismFuture, SaslProfile},
+        types::{
+            primitives::{Array, Binary, Symbol},
+            sasl::{SaslChallenge, SaslCode, SaslInit, SaslOutcome, SaslResponse},
+        },
+    };
+
+    /// Sends a nonce as challenge and a token as the outcome additional data
+    #[derive(Debug, Clone)]
+    struct NonceServer;
+
+    impl SaslAcceptor for NonceServer {
+        fn mechanisms(&self) -> Array<Symbol> {
+            Array::from(vec![Symbol::from("X-NONCE")])
+        }
+
+        fn on_init(&mut self, init: SaslInit) -> SaslServerFrame {
+            assert_eq!(init.initial_response.unwrap().as_ref(), b"client-first");
+            SaslServerFrame::Challenge(SaslChallenge {
+                challenge: Binary::from(b"nonce".to_vec()),
+            })
+        }
+
+        fn on_response(&mut self, response: SaslResponse) -> SaslServerFrame {
+            let code = match response.response.as_ref() {
+                b"nonce-signed" => SaslCode::Ok,
+                _ => SaslCode::Auth,
+            };
+            SaslServerFrame::Outcome(SaslOutcome {
+                code,
+                additional_data: Some(Binary::from(b"token".to_vec())),
+            })
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct NonceClient {
+        expected_token: &'static [u8],
+        token: Arc<Mutex<Option<Vec<u8>>>>,
+    }
+
+    impl SaslClientMechanism for NonceClient {
+        fn mechanism(&self) -> Symbol {
+            Symbol::from("X-NONCE")
+        }
+
+        fn initial_response<'a>(
+            &'a mut self,
+            _hostname: Option<&'a str>,
+        ) -> SaslClientMechanismFuture<'a, Option<Binary>> {
+            Box::pin(async move { Ok(Some(Binary::from(b"client-first".to_vec()))) })
+        }
+
+        fn on_challenge(
+            &mut self,
+            challenge: SaslChallenge,
+        ) -> SaslClientMechanismFuture<'_, SaslResponse> {
+            let mut response = challenge.challenge.into_vec();
+            response.extend_from_slice(b"-signed");
+            Box::pin(async move {
+                Ok(SaslResponse {
+                    response: Binary::from(response),
+                })
+            })
+        }
+
+        fn on_outcome(&mut self, outcome: &SaslOutcome) -> Result<(), Error> {
+            let token = outcome.additional_data.as_ref().map(|data| data.to_vec());
+            if token.as_deref() != Some(self.expected_token) {
+                return Err(Error::Mechanism("unexpected token".into()));
+            }
+            *self.token.lock().unwrap() = token;
+            Ok(())
+        }
+    }
+
+    for expected_token in [&b"token"[..], &b"other-token"[..]] {
+        let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+        let remote = tokio::spawn(async move {
+            let acceptor = ConnectionAcceptor::builder()
+                .container_id("server")
+                .sasl_acceptor(NonceServer)
+                .build();
+            acceptor.accept(remote_stream).await
+        });
+
+        let token = Arc::new(Mutex::new(None));
+        let result = Connection::builder()
+            .container_id("client")
+            .sasl_profile(SaslProfile::custom(NonceClient {
+                expected_token,
+                token: token.clone(),
+            }))
+            .open_with_stream(local_stream)
+            .await;
+
+        if expected_token == b"token" {
+            let mut connection = result.unwrap();
+            assert_eq!(token.lock().unwrap().as_deref(), Some(&b"token"[..]));
+            let _remote_connection = remote.await.unwrap().unwrap();
+            connection.close().await.unwrap();
+        } else {
+            assert!(matches!(result, Err(OpenError::SaslMechanism(_))));
+            assert!(token.lock().unwrap().is_none());
+            drop(result);
+            let _ = remote.await.unwrap();
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_sasl_mechanism_is_awaited_during_negotiation() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{
+        acceptor::{sasl_acceptor::SaslServerFrame, ConnectionAcceptor, SaslAcceptor},
+        connection::OpenError,
+        sasl_profile::{SaslClientMechanism, SaslClientMechanismFuture, SaslProfile},
+        types::{
+            primitives::{Array, Binary, Symbol},
+            sasl::{SaslChallenge, SaslCode, SaslInit, SaslOutcome, SaslResponse},
+        },
+    };
+
+    /// Sends a nonce as challenge and accepts the nonce signed with the key
+    #[derive(Debug, Clone)]
+    struct NonceServer;
+
+    impl SaslAcceptor for NonceServer {
+        fn mechanisms(&self) -> Array<Symbol> {
+            Array::from(vec![Symbol::from("X-NONCE")])
+        }
+
+        fn on_init(&mut self, init: SaslInit) -> SaslServerFrame {
+            assert_eq!(init.initial_response.unwrap().as_ref(), b"client-first");
+            SaslServerFrame::Challenge(SaslChallenge {
+                challenge: Binary::from(b"nonce".to_vec()),
+            })
+        }
+
+        fn on_response(&mut self, response: SaslResponse) -> SaslServerFrame {
+            let code = match response.response.as_ref() {
+                b"nonce-key" => SaslCode::Ok,
+                _ => SaslCode::Auth,
+            };
+            SaslServerFrame::Outcome(SaslOutcome {
+                code,
+                additional_data: None,
+            })
+        }
+    }
+
+    /// Signs the nonce with a key that takes a while to fetch
+    #[derive(Debug, Clone)]
+    struct NonceSigner {
+        key: &'static [u8],
+    }
+
+    impl SaslClientMechanism for NonceSigner {
+        fn mechanism(&self) -> Symbol {
+            Symbol::from("X-NONCE")
+        }
+
+        fn initial_response<'a>(
+            &'a mut self,
+            _hostname: Option<&'a str>,
+        ) -> SaslClientMechanismFuture<'a, Option<Binary>> {
+            Box::pin(async move { Ok(Some(Binary::from(b"client-first".to_vec()))) })
+        }
+
+        fn on_challenge(
+            &mut self,
+            challenge: SaslChallenge,
+        ) -> SaslClientMechanismFuture<'_, SaslResponse> {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let mut response = challenge.challenge.into_vec();
+                response.push(b'-');
+                response.extend_from_slice(self.key);
+                Ok(SaslResponse {
+                    response: Binary::from(response),
+                })
+            })
+        }
+    }
+
+    for key in [&b"key"[..], &b"other-key"[..]] {
+        let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+        let remote = tokio::spawn(async move {
+            let acceptor = ConnectionAcceptor::builder()
+                .container_id("server")
+                .sasl_acceptor(NonceServer)
+                .build();
+            acceptor.accept(remote_stream).await
+        });
+
+        let result = Connection::builder()
+            .container_id("client")
+            .sasl_profile(SaslProfile::custom(NonceSigner { key }))
+            .open_with_stream(local_stream)
+            .await;
+
+        if key == b"key" {
+            let mut connection = result.unwrap();
+            let _remote_connection = remote.await.unwrap().unwrap();
+            connection.close().await.unwrap();
+        } else {
+            assert!(matches!(
+                result,
+                Err(OpenError::SaslError {
+                    code: SaslCode::Auth,
+                    ..
+                })
+            ));
+            let _ = remote.await.unwrap();
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sole_connection_for_container_is_enforced() {
+    use std::sync::Arc;
+
+    use fe2o3_amqp::{
+        acceptor::ConnectionAcceptor,
+        connection::{
+            sole_connection::{is_container_id_in_use, SoleConnectionEnforcementPolicy},
+            Error, OpenError,
+        },
+    };
+
+    for policy in [
+        SoleConnectionEnforcementPolicy::RefuseConnection,
+        SoleConnectionEnforcementPolicy::CloseExisting,
+    ] {
+        let acceptor = Arc::new(
+            ConnectionAcceptor::builder()
+                .container_id("broker")
+                .sole_connection_enforcement(policy)
+                .build(),
+        );
+
+        let (first_local, first_remote) = tokio::io::duplex(64 * 1024);
+        let first_acceptor = acceptor.clone();
+        let first_remote =
+            tokio::spawn(async move { first_acceptor.accept(first_remote).await.unwrap() });
+        let mut first = Connection::builder()
+            .container_id("client-id")
+            .sole_connection_for_container()
+            .open_with_stream(first_local)
+            .await
+            .unwrap();
+        let _first_remote = first_remote.await.unwrap();
+
+        let (second_local, second_remote) = tokio::io::duplex(64 * 1024);
+        let second_acceptor = acceptor.clone();
+        let second_remote =
+            tokio::spawn(async move { second_acceptor.accept(second_remote).await });
+        let second = Connection::builder()
+            .container_id("client-id")
+            .sole_connection_for_container()
+            .open_with_stream(second_local)
+            .await;
+
+        match policy {
+            SoleConnectionEnforcementPolicy::RefuseConnection => {
+                assert!(matches!(second, Err(OpenError::ContainerIdInUse(_))));
+                assert!(matches!(
+                    second_remote.await.unwrap(),
+                    Err(OpenError::ContainerIdInUse(_))
+                ));
+                first.close().await.unwrap();
+            }
+            SoleConnectionEnforcementPolicy::CloseExisting => {
+                let mut second = second.unwrap();
+                let _second_remote = second_remote.await.unwrap().unwrap();
+                match first.on_close().await {
+                    Err(Error::RemoteClosedWithError(error)) => {
+                        assert!(is_container_id_in_use(&error))
+                    }
+                    other => panic!("Expecting the connection to be closed, found {:?}", other),
+                }
+                second.close().await.unwrap();
+            }
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn close_and_end_return_the_recorded_outcome_repeatedly() {
+    use fe2o3_amqp::{connection, session};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        let (mut connection, mut session) = common::accept_session("broker", remote_stream).await;
+        // The outcome of an end or close initiated by the remote peer is replayed as well
+        for _ in 0..2 {
+            assert!(matches!(
+                session.on_end().await,
+                Err(session::Error::RemoteEnded)
+            ));
+        }
+        for _ in 0..2 {
+            assert!(matches!(
+                connection.on_close().await,
+                Err(connection::Error::RemoteClosed)
+            ));
+        }
+    });
+
+    let (mut connection, mut session) = common::begin_session("client", local_stream).await;
+
+    session.end().await.unwrap();
+    session.end().await.unwrap();
+    session.on_end().await.unwrap();
+    assert!(session.is_ended());
+
+    connection.close().await.unwrap();
+    connection.close().await.unwrap();
+    connection.on_close().await.unwrap();
+    assert!(connection.is_closed());
+
+    remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn open_with_url_surfaces_the_connected_address() {
+    use fe2o3_amqp::{acceptor::ConnectionAcceptor, connection::ConnectStrategy};
+    use tokio::net::TcpListener;
+
+    // Only listen on IPv4 so that an IPv6 address of localhost, if any, is refused
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let remote = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        ConnectionAcceptor::new("broker")
+            .accept(stream)
+            .await
+            .unwrap()
+    });
+
+    let url = format!("amqp://localhost:{}", addr.port());
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .connect_strategy(ConnectStrategy::parallel())
+        .open(&url[..])
+        .await
+        .unwrap();
+    assert_eq!(connection.peer_addr(), Some(addr));
+
+    let _remote = remote.await.unwrap();
+    connection.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn open_fails_over_to_alt_hosts() {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use fe2o3_amqp::{acceptor::ConnectionAcceptor, connection::HostHealth};
+    use tokio::net::TcpListener;
+
+    // The primary accepts the TCP connections but drops them right away
+    let primary = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let primary_addr = primary.local_addr().unwrap();
+    let primary_attempts = Arc::new(AtomicUsize::new(0));
+    let attempts = primary_attempts.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = primary.accept().await.unwrap();
+            attempts.fetch_add(1, Ordering::SeqCst);
+            drop(stream);
+        }
+    });
+
+    let secondary = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let secondary_addr = secondary.local_addr().unwrap();
+    let remote = tokio::spawn(async move {
+        let mut connections = Vec::new();
+        for _ in 0..2 {
+            let (stream, _) = secondary.accept().await.unwrap();
+            let connection = ConnectionAcceptor::new("broker")
+                .accept(stream)
+                .await
+                .unwrap();
+            connections.push(connection);
+        }
+        connections
+    });
+
+    let health = HostHealth::default();
+    let primary_url = format!("amqp://127.0.0.1:{}", primary_addr.port());
+    let secondary_url = format!("amqp://127.0.0.1:{}", secondary_addr.port());
+    for _ in 0..2 {
+        let mut connection = Connection::builder()
+            .container_id("client")
+            .alt_hosts([secondary_url.clone()])
+            .host_health(health.clone())
+            .open(&primary_url[..])
+            .await
+            .unwrap();
+        assert_eq!(connection.peer_addr(), Some(secondary_addr));
+        connection.close().await.unwrap();
+    }
+
+    // The failed primary is not attempted first again within the cooldown
+    assert_eq!(primary_attempts.load(Ordering::SeqCst), 1);
+    let _remote = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rotated_credentials_are_used_on_reconnect() {
+    use fe2o3_amqp::{
+        acceptor::{ConnectionAcceptor, SaslPlainMechanism},
+        connection::OpenError,
+        sasl_profile::SharedCredentials,
+    };
+
+    async fn open(
+        credentials: SharedCredentials,
+        broker_password: &'static str,
+    ) -> (
+        Result<fe2o3_amqp::connection::ConnectionHandle<()>, OpenError>,
+        tokio::task::JoinHandle<Result<fe2o3_amqp::acceptor::ListenerConnectionHandle, OpenError>>,
+    ) {
+        let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+        let remote = tokio::spawn(async move {
+            let acceptor = ConnectionAcceptor::builder()
+                .container_id("broker")
+                .sasl_acceptor(SaslPlainMechanism::new("user", broker_password))
+                .build();
+            acceptor.accept(remote_stream).await
+        });
+        let result = Connection::builder()
+            .container_id("client")
+            .credentials(credentials)
+            .open_with_stream(local_stream)
+            .await;
+        (result, remote)
+    }
+
+    let credentials = SharedCredentials::new(("user", "old-password"));
+    let (result, remote) = open(credentials.clone(), "old-password").await;
+    let mut connection = result.unwrap();
+    let _remote_connection = remote.await.unwrap().unwrap();
+    assert!(connection.credentials().ptr_eq(&credentials));
+
+    // The password is rotated on the broker, and the open connection is not affected
+    connection.update_credentials(("user", "new-password"));
+    assert!(!connection.is_closed());
+
+    // A reconnect with the shared credentials picks up the rotated password
+    let (result, remote) = open(connection.credentials().clone(), "new-password").await;
+    let mut reconnected = result.unwrap();
+    let _remote_reconnected = remote.await.unwrap().unwrap();
+
+    // The stale password is rejected
+    let (result, remote) = open(
+        SharedCredentials::new(("user", "old-password")),
+        "new-password",
+    )
+    .await;
+    assert!(result.is_err());
+    drop(result);
+    let _ = remote.await.unwrap();
+
+    connection.close().await.unwrap();
+    reconnected.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn listener_connection_lists_and_kicks_sessions_and_links() {
+    use fe2o3_amqp::{
+        acceptor::error::AdminError,
+        link::DetachError,
+        session::Error as SessionError,
+        types::definitions::{self, AmqpError, Role},
+        Sender,
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let first = session.accept_link(&LinkAcceptor::new()).await.unwrap();
+        let second = session.accept_link(&LinkAcceptor::new()).await.unwrap();
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, first, second)
+    });
+
+    let (mut connection, mut session) = common::begin_session("client", local_stream).await;
+    let sender = Sender::attach(&mut session, "producer", "q1")
+        .await
+        .unwrap();
+    let _receiver = Receiver::attach(&mut session, "consumer", "q2")
+        .await
+        .unwrap();
+    let (broker, _broker_session, _first, _second) = remote.await.unwrap();
+
+    let clients: Vec<_> = broker.clients().await.collect();
+    assert_eq!(clients.len(), 1);
+    let channel = clients[0].channel;
+    let links = &clients[0].links;
+    assert_eq!(links.len(), 2);
+    assert_eq!(links[0].name, "producer");
+    assert_eq!(links[0].role, Role::Receiver);
+    assert_eq!(links[0].target.as_deref(), Some("q1"));
+    assert_eq!(links[1].name, "consumer");
+    assert_eq!(links[1].role, Role::Sender);
+    assert_eq!(links[1].source.as_deref(), Some("q2"));
+
+    // The remote link is closed with the error
+    let error = definitions::Error::new(AmqpError::UnauthorizedAccess, None, None);
+    broker
+        .kick_link(channel, "producer", error.clone())
+        .await
+        .unwrap();
+    assert!(matches!(
+        broker.kick_link(channel, "producer", error.clone()).await,
+        Err(AdminError::LinkNotFound)
+    ));
+    match sender.close().await {
+        Err(DetachError::RemoteClosedWithError(remote_error)) => {
+            assert_eq!(remote_error, error)
+        }
+        other => panic!("Unexpected result {:?}", other),
+    }
+    let clients: Vec<_> = broker.clients().await.collect();
+    assert_eq!(clients[0].links.len(), 1);
+    assert_eq!(clients[0].links[0].name, "consumer");
+
+    // The remote session is ended with the error
+    broker.kick_session(channel, error.clone()).await.unwrap();
+    // Ending the session locally could race with the remote End
+    match session.on_end().await {
+        Err(SessionError::RemoteEndedWithError(remote_error)) => assert_eq!(remote_error, error),
+        other => panic!("Unexpected result {:?}", other),
+    }
+    // The session is removed once its event loop stops after the remote End
+    let mut count = None;
+    for _ in 0..100 {
+        count = Some(broker.clients().await.count());
+        if count == Some(0) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(count, Some(0));
+    assert!(matches!(
+        broker.kick_session(channel, error).await,
+        Err(AdminError::SessionNotFound)
+    ));
+
+    connection.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mock_clock_drives_the_idle_timeout_of_accepted_connection() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{
+        acceptor::ConnectionAcceptor, clock::MockClock, connection::Error as ConnectionError,
+        transport,
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let broker_clock = MockClock::new();
+    let acceptor = ConnectionAcceptor::builder()
+        .container_id("broker")
+        .idle_time_out(1000u32)
+        .clock(broker_clock.clone())
+        .build();
+    let remote = tokio::spawn(async move { acceptor.accept(remote_stream).await.unwrap() });
+
+    // The heartbeat of the client only ticks once as its clock never moves
+    let _connection = Connection::builder()
+        .container_id("client")
+        .clock(MockClock::new())
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut broker_connection = remote.await.unwrap();
+
+    // The idle timeout does not elapse in real time
+    let wait = tokio::time::timeout(Duration::from_millis(200), broker_connection.on_close());
+    assert!(wait.await.is_err());
+
+    broker_clock.advance(Duration::from_millis(1500));
+    let result = broker_connection.on_close().await;
+    assert!(matches!(
+        result,
+        Err(ConnectionError::TransportError(
+            transport::Error::IdleTimeoutElapsed
+        ))
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn silent_client_is_closed_after_keep_alive_grace_period() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{
+        acceptor::{ConnectionAcceptor, KeepAlivePolicy},
+        clock::MockClock,
+        connection::Error as ConnectionError,
+        transport,
+        types::definitions::{AmqpError, ErrorCondition},
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let broker_clock = MockClock::new();
+    let policy =
+        KeepAlivePolicy::new(Duration::from_secs(1)).grace_period(Duration::from_millis(500));
+    let acceptor = ConnectionAcceptor::builder()
+        .container_id("broker")
+        .keep_alive(policy)
+        .clock(broker_clock.clone())
+        .build();
+    assert_eq!(acceptor.local_open.idle_time_out, Some(1000));
+    let remote = tokio::spawn(async move { acceptor.accept(remote_stream).await.unwrap() });
+
+    // The client honors the idle time-out with a heartbeat, which only ticks once as its clock
+    // never moves
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .clock(MockClock::new())
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut broker_connection = remote.await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Still within the grace period
+    broker_clock.advance(Duration::from_millis(1200));
+    let wait = tokio::time::timeout(Duration::from_millis(200), broker_connection.on_close());
+    assert!(wait.await.is_err());
+
+    broker_clock.advance(Duration::from_millis(400));
+    let result = broker_connection.on_close().await;
+    assert!(matches!(
+        result,
+        Err(ConnectionError::TransportError(
+            transport::Error::IdleTimeoutElapsed
+        ))
+    ));
+
+    match connection.on_close().await {
+        Err(ConnectionError::RemoteClosedWithError(error)) => assert_eq!(
+            error.condition,
+            ErrorCondition::AmqpError(AmqpError::ResourceLimitExceeded)
+        ),
+        other => panic!(
+            "Expecting the connection to be closed by the broker {:?}",
+            other
+        ),
+    }
+}
+
+/// Counts the writes that reach the underlying stream
+#[derive(Debug)]
+struct CountingStream {
+    inner: tokio::io::DuplexStream,
+    writes: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl tokio::io::AsyncRead for CountingStream {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl tokio::io::AsyncWrite for CountingStream {
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        let poll = std::pin::Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let std::task::Poll::Ready(Ok(_)) = poll {
+            self.writes
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_flush(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn queued_frames_are_written_in_batches() {
+    use std::{
+        sync::{atomic::Ordering, Arc},
+        time::Duration,
+    };
+
+    use fe2o3_amqp::{connection::WriteBatching, types::definitions::SenderSettleMode, Sender};
+
+    const COUNT: usize = 20;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let writes = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let local_stream = CountingStream {
+        inner: local_stream,
+        writes: writes.clone(),
+    };
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        for i in 0..COUNT {
+            let delivery: Delivery<String> = receiver.recv().await.unwrap();
+            assert_eq!(delivery.body(), &format!("message-{}", i));
+        }
+        (connection, session, receiver)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("producer")
+        .write_batching(WriteBatching::new(64).flush_deadline(Duration::from_millis(100)))
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::begin(&mut connection).await.unwrap();
+    let mut sender = Sender::builder()
+        .name("sender")
+        .target("q1")
+        .sender_settle_mode(SenderSettleMode::Settled)
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    let before = writes.load(Ordering::Relaxed);
+    for i in 0..COUNT {
+        sender.send(format!("message-{}", i)).await.unwrap();
+    }
+    let _endpoints = remote.await.unwrap();
+    let transfer_writes = writes.load(Ordering::Relaxed) - before;
+    assert!(
+        transfer_writes < COUNT / 2,
+        "{} transfers are written with {} writes",
+        COUNT,
+        transfer_writes
+    );
+}
+
+#[cfg(feature = "chaos")]
+#[tokio::test(flavor = "multi_thread")]
+async fn messages_survive_latency_and_partial_writes_of_chaos_transport() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{
+        transport::{ChaosConfig, ChaosTransport},
+        Sender,
+    };
+
+    const COUNT: usize = 10;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let config = ChaosConfig::new()
+        .latency(Duration::from_millis(1))
+        .jitter(Duration::from_millis(2))
+        .partial_writes(0.5)
+        .seed(11);
+    let remote_config = config.clone().seed(12);
+
+    let remote = tokio::spawn(async move {
+        let remote_stream = ChaosTransport::new(remote_stream, remote_config);
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        for i in 0..COUNT {
+            let delivery: Delivery<String> = receiver.recv().await.unwrap();
+            assert_eq!(delivery.body(), &format!("message-{}", i));
+            receiver.accept(&delivery).await.unwrap();
+        }
+        (connection, session, receiver)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("producer")
+        .open_with_stream(ChaosTransport::new(local_stream, config))
+        .await
+        .unwrap();
+    let mut session = Session::begin(&mut connection).await.unwrap();
+    let mut sender = Sender::builder()
+        .name("sender")
+        .target("q1")
+        .attach(&mut session)
+        .await
+        .unwrap();
+    for i in 0..COUNT {
+        let outcome = sender.send(format!("message-{}", i)).await.unwrap();
+        assert!(outcome.is_accepted());
+    }
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sasl_plain_authorization_identity_is_checked_by_the_validator() {
+    use fe2o3_amqp::{
+        acceptor::{ConnectionAcceptor, SaslPlainMechanism},
+        sasl_profile::SaslProfile,
+    };
+
+    async fn open(
+        profile: SaslProfile,
+    ) -> Result<fe2o3_amqp::connection::ConnectionHandle<()>, fe2o3_amqp::connection::OpenError>
+    {
+        let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+        let remote = tokio::spawn(async move {
+            // The proxy may only act as alice
+            let mechanism = SaslPlainMechanism::with_validator(|credentials| {
+                credentials.authcid == "proxy"
+                    && credentials.passwd == "secret"
+                    && matches!(credentials.authzid, None | Some("alice"))
+            });
+            let acceptor = ConnectionAcceptor::builder()
+                .container_id("broker")
+                .sasl_acceptor(mechanism)
+                .build();
+            acceptor.accept(remote_stream).await
+        });
+        let result = Connection::builder()
+            .container_id("client")
+            .sasl_profile(profile)
+            .open_with_stream(local_stream)
+            .await;
+        let _ = remote.await.unwrap();
+        result
+    }
+
+    let result = open(SaslProfile::plain_with_authzid("alice", "proxy", "secret")).await;
+    assert!(result.is_ok());
+    let result = open(SaslProfile::from(("proxy", "secret"))).await;
+    assert!(result.is_ok());
+    let result = open(SaslProfile::plain_with_authzid(
+        "mallory", "proxy", "secret",
+    ))
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn listener_limits_are_updated_at_runtime() {
+    use fe2o3_amqp::{
+        acceptor::{ApplyTo, ConnectionAcceptor, ListenerLimits, SharedLimits},
+        connection::OpenError,
+        session::{error::BeginError, Error as SessionError},
+    };
+    use fe2o3_amqp_types::definitions::{AmqpError, ErrorCondition};
+    use tokio::sync::oneshot;
+
+    let limits = SharedLimits::new(ListenerLimits {
+        max_connections: Some(1),
+        ..Default::default()
+    });
+    let mut updates = limits.subscribe();
+    let connection_acceptor = ConnectionAcceptor::builder()
+        .container_id("broker")
+        .limits(limits.clone())
+        .build();
+
+    let (updated_tx, updated_rx) = oneshot::channel();
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (refused_stream, remote_refused_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let mut connection = connection_acceptor.accept(remote_stream).await.unwrap();
+        let refused = connection_acceptor.accept(remote_refused_stream).await;
+        assert!(matches!(refused, Err(OpenError::ConnectionLimitReached(_))));
+        assert_eq!(limits.connections(), 1);
+
+        limits.update(ApplyTo::AllConnections, |limits| {
+            limits.max_sessions_per_connection = Some(1)
+        });
+        updated_tx.send(()).unwrap();
+
+        let session_acceptor = SessionAcceptor::new();
+        let session = session_acceptor.accept(&mut connection).await.unwrap();
+        let refused = session_acceptor.accept(&mut connection).await;
+        assert!(matches!(refused, Err(BeginError::SessionLimitReached)));
+        (connection, session)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let refused = Connection::builder()
+        .container_id("refused-client")
+        .open_with_stream(refused_stream)
+        .await;
+    assert!(refused.is_err());
+
+    updated_rx.await.unwrap();
+    updates.changed().await.unwrap();
+    assert_eq!(updates.borrow().max_sessions_per_connection, Some(1));
+
+    let _session = Session::begin(&mut connection).await.unwrap();
+    let mut refused = Session::begin(&mut connection).await.unwrap();
+    match refused.on_end().await {
+        Err(SessionError::RemoteEndedWithError(error)) => assert_eq!(
+            error.condition,
+            ErrorCondition::AmqpError(AmqpError::ResourceLimitExceeded)
+        ),
+        result => panic!("Unexpected result {:?}", result),
+    }
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generated_container_ids_do_not_collide() {
+    use std::sync::Arc;
+
+    use fe2o3_amqp::{
+        acceptor::ConnectionAcceptor,
+        connection::{
+            sole_connection::SoleConnectionEnforcementPolicy, ContainerIdPolicy,
+            InvalidContainerId, OpenError,
+        },
+    };
+
+    let acceptor = Arc::new(
+        ConnectionAcceptor::builder()
+            .container_id("broker")
+            .sole_connection_enforcement(SoleConnectionEnforcementPolicy::RefuseConnection)
+            .build(),
+    );
+    let policy = ContainerIdPolicy::new("replica");
+
+    let mut connections = Vec::new();
+    for _ in 0..2 {
+        let (local, remote) = tokio::io::duplex(64 * 1024);
+        let acceptor = acceptor.clone();
+        let remote = tokio::spawn(async move { acceptor.accept(remote).await.unwrap() });
+        let connection = Connection::builder()
+            .container_id_policy(policy.clone())
+            .generated_container_id()
+            .sole_connection_for_container()
+            .open_with_stream(local)
+            .await
+            .unwrap();
+        let remote = remote.await.unwrap();
+        assert_eq!(remote.container_id(), "broker");
+        assert!(connection.container_id().starts_with("replica-"));
+        connections.push((connection, remote));
+    }
+    assert_ne!(
+        connections[0].0.container_id(),
+        connections[1].0.container_id()
+    );
+    for (mut connection, _remote) in connections {
+        connection.close().await.unwrap();
+    }
+
+    let (local, _remote) = tokio::io::duplex(64 * 1024);
+    let result = Connection::builder()
+        .container_id("")
+        .open_with_stream(local)
+        .await;
+    assert!(matches!(
+        result,
+        Err(OpenError::InvalidContainerId(InvalidContainerId::Empty))
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn acceptor_takes_over_negotiated_stream() {
+    use fe2o3_amqp::{acceptor::ConnectionAcceptor, Sender};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const AMQP_HEADER: [u8; 8] = [b'A', b'M', b'Q', b'P', 0, 1, 0, 0];
+
+    let (local_stream, mut remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        // An outer layer exchanges the protocol headers before handing over the stream
+        let mut header = [0u8; 8];
+        remote_stream.read_exact(&mut header).await.unwrap();
+        assert_eq!(header, AMQP_HEADER);
+        remote_stream.write_all(&AMQP_HEADER).await.unwrap();
+
+        let mut connection = ConnectionAcceptor::new("broker")
+            .accept_negotiated(remote_stream, None)
+            .await
+            .unwrap();
+        let mut session = SessionAcceptor::new()
+            .accept(&mut connection)
+            .await
+            .unwrap();
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        let delivery: Delivery<String> = receiver.recv().await.unwrap();
+        receiver.accept(&delivery).await.unwrap();
+        assert_eq!(delivery.body(), "hello");
+        (connection, session, receiver)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut sender = Sender::attach(&mut session, "sender", "q1").await.unwrap();
+    let outcome = sender.send("hello".to_string()).await.unwrap();
+    assert!(outcome.is_accepted());
+
+    let _remote = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn draining_closes_idle_connections_and_waits_for_active_ones() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{
+        acceptor::{ConnectionAcceptor, ListenerDrain},
+        connection::{self, OpenError},
+        types::definitions::{ConnectionError, ErrorCondition},
+        Sender,
+    };
+
+    let drain = ListenerDrain::new();
+    let acceptor = std::sync::Arc::new(
+        ConnectionAcceptor::builder()
+            .container_id("broker")
+            .drain(drain.clone())
+            .build(),
+    );
+
+    // The broker receives one message per connection and settles it after `settle_after`, or
+    // never if it is `None`
+    let mut clients = Vec::new();
+    for (container_id, settle_after) in [
+        ("idle", None),
+        ("settling", Some(Duration::from_millis(50))),
+        ("stuck", None),
+    ] {
+        let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+        let acceptor = acceptor.clone();
+        let broker = tokio::spawn(async move {
+            let mut connection = acceptor.accept(remote_stream).await.unwrap();
+            if container_id == "idle" {
+                return (connection, None);
+            }
+            let mut session = SessionAcceptor::new()
+                .accept(&mut connection)
+                .await
+                .unwrap();
+            let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+            let delivery: Delivery<String> = receiver.recv().await.unwrap();
+            let settling = tokio::spawn(async move {
+                if let Some(settle_after) = settle_after {
+                    tokio::time::sleep(settle_after).await;
+                    receiver.accept(&delivery).await.unwrap();
+                }
+                (session, receiver)
+            });
+            (connection, Some(settling))
+        });
+
+        let mut connection = Connection::builder()
+            .container_id(container_id)
+            .open_with_stream(local_stream)
+            .await
+            .unwrap();
+        let mut endpoints = None;
+        if container_id != "idle" {
+            let mut session = Session::begin(&mut connection).await.unwrap();
+            let mut sender = Sender::attach(&mut session, container_id, "q1")
+                .await
+                .unwrap();
+            let outcome = sender.send_batchable("hello".to_string()).await.unwrap();
+            endpoints = Some((session, sender, outcome));
+        }
+        let broker = broker.await.unwrap();
+        clients.push((connection, broker, endpoints));
+    }
+    // Wait for the broker to receive the messages
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(drain.connections(), 3);
+
+    let report = drain
+        .drain(Duration::from_millis(500), "The broker is restarting")
+        .await;
+    assert!(drain.is_draining());
+    assert_eq!(report.idle_closed, 1);
+    assert_eq!(report.completed, 1);
+    assert_eq!(report.force_closed.len(), 1);
+    assert_eq!(
+        report.force_closed[0].container_id.as_deref(),
+        Some("stuck")
+    );
+    assert_eq!(report.force_closed[0].unsettled, 1);
+
+    for (mut connection, (mut broker_connection, _settling), _endpoints) in clients {
+        let _ = broker_connection.on_close().await;
+        match connection.on_close().await {
+            Err(connection::Error::RemoteClosedWithError(error)) => {
+                assert_eq!(
+                    error.condition,
+                    ErrorCondition::ConnectionError(ConnectionError::ConnectionForced)
+                );
+                assert_eq!(
+                    error.description.as_deref(),
+                    Some("The broker is restarting")
+                );
+            }
+            other => panic!("Expecting a forced close, found {:?}", other),
+        }
+    }
+
+    // New connections are refused
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let broker = tokio::spawn(async move { acceptor.accept(remote_stream).await });
+    let result = Connection::builder()
+        .container_id("late")
+        .open_with_stream(local_stream)
+        .await;
+    assert!(result.is_err());
+    assert!(matches!(
+        broker.await.unwrap(),
+        Err(OpenError::ListenerDraining(_))
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn idle_timeout_warning_fires_before_the_local_idle_timeout() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::acceptor::ConnectionAcceptor;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let acceptor = ConnectionAcceptor::builder()
+        .container_id("broker")
+        .idle_time_out(5000u32)
+        .build();
+    let remote = tokio::spawn(async move { acceptor.accept(remote_stream).await.unwrap() });
+
+    let connection = Connection::builder()
+        .container_id("client")
+        .idle_time_out(1000u32)
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    assert_eq!(
+        connection.local_idle_timeout(),
+        Some(Duration::from_secs(1))
+    );
+    assert_eq!(
+        connection.remote_idle_timeout(),
+        Some(Duration::from_secs(5))
+    );
+
+    // The broker only sends an empty frame every second, so the warning comes first
+    connection.set_idle_timeout_warning_ratio(0.25);
+    let warning =
+        tokio::time::timeout(Duration::from_secs(5), connection.on_idle_timeout_warning())
+            .await
+            .unwrap();
+    assert!(warning.idle >= Duration::from_millis(250));
+    assert_eq!(warning.idle_timeout, Duration::from_secs(1));
+
+    let _remote = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ping_resolves_when_the_peer_answers_with_an_empty_frame() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::frames::amqp::{Frame, FrameBody};
+    use futures_util::{SinkExt, StreamExt};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (connection, mut transport) = tokio::join!(
+        Connection::builder()
+            .container_id("client")
+            .open_with_stream(local_stream),
+        common::open_raw_connection(remote_stream, u32::MAX)
+    );
+    let connection = connection.unwrap();
+
+    let peer = async {
+        let frame = transport.next().await.unwrap().unwrap();
+        assert!(matches!(frame.body, FrameBody::Empty));
+        transport.send(Frame::empty()).await.unwrap();
+    };
+    let (result, ()) = tokio::join!(connection.ping(Duration::from_secs(5)), peer);
+    result.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ping_times_out_when_the_peer_is_silent() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{connection::PingError, frames::amqp::FrameBody};
+    use futures_util::StreamExt;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (connection, mut transport) = tokio::join!(
+        Connection::builder()
+            .container_id("client")
+            .open_with_stream(local_stream),
+        common::open_raw_connection(remote_stream, u32::MAX)
+    );
+    let connection = connection.unwrap();
+
+    let result = connection.ping(Duration::from_millis(200)).await;
+    assert!(matches!(result, Err(PingError::Timeout)));
+    // The empty frame was sent but never answered
+    let frame = transport.next().await.unwrap().unwrap();
+    assert!(matches!(frame.body, FrameBody::Empty));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn incoming_frames_are_handled_while_a_batch_waits_for_the_flush_deadline() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{
+        connection::WriteBatching,
+        frames::amqp::{Frame, FrameBody},
+        types::performatives::Close,
+    };
+    use futures_util::{SinkExt, StreamExt};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (connection, mut transport) = tokio::join!(
+        Connection::builder()
+            .container_id("client")
+            .write_batching(WriteBatching::new(32).flush_deadline(Duration::from_secs(30)))
+            .open_with_stream(local_stream),
+        common::open_raw_connection(remote_stream, u32::MAX)
+    );
+    let mut connection = connection.unwrap();
+
+    let peer = async {
+        // Let the Begin wait for the flush deadline
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let close = Close { error: None };
+        transport
+            .send(Frame::new(0u16, FrameBody::Close(close)))
+            .await
+            .unwrap();
+
+        // The Close is answered long before the flush deadline, after the pending Begin
+        let mut bodies = Vec::new();
+        loop {
+            let frame = tokio::time::timeout(Duration::from_secs(5), transport.next())
+                .await
+                .expect("The remote Close is not handled")
+                .unwrap()
+                .unwrap();
+            let is_close = matches!(frame.body, FrameBody::Close(_));
+            bodies.push(frame.body);
+            if is_close {
+                break;
+            }
+        }
+        assert!(matches!(
+            bodies[..],
+            [FrameBody::Begin(_), FrameBody::Close(_)]
+        ));
+    };
+    let (begin, ()) = tokio::join!(Session::begin(&mut connection), peer);
+    assert!(begin.is_err());
+}
+
+#[cfg(feature = "websocket")]
+#[tokio::test(flavor = "multi_thread")]
+async fn connection_is_opened_over_websocket_from_a_ws_url() {
+    use fe2o3_amqp::Sender;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_tungstenite::tungstenite::{
+        handshake::server::{Callback, ErrorResponse, Request, Response},
+        http::HeaderValue,
+        Message,
+    };
+
+    /// Accepts the `amqp` WebSocket subprotocol requested by the client
+    struct AmqpSubprotocol;
+
+    impl Callback for AmqpSubprotocol {
+        fn on_request(
+            self,
+            request: &Request,
+            mut response: Response,
+        ) -> Result<Response, ErrorResponse> {
+            let protocol = request.headers().get("Sec-WebSocket-Protocol");
+            assert_eq!(protocol, Some(&HeaderValue::from_static("amqp")));
+            response
+                .headers_mut()
+                .insert("Sec-WebSocket-Protocol", HeaderValue::from_static("amqp"));
+            Ok(response)
+        }
+    }
+
+    let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = tcp_listener.local_addr().unwrap();
+
+    let remote = tokio::spawn(async move {
+        let (tcp_stream, _) = tcp_listener.accept().await.unwrap();
+        let ws_stream = tokio_tungstenite::accept_hdr_async(tcp_stream, AmqpSubprotocol)
+            .await
+            .unwrap();
+
+        // Relays the binary messages to and from the acceptor
+        let (mut ws_sink, mut ws_source) = ws_stream.split();
+        let (broker_stream, relay_stream) = tokio::io::duplex(64 * 1024);
+        let (mut relay_read, mut relay_write) = tokio::io::split(relay_stream);
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = ws_source.next().await {
+                if let Message::Binary(bytes) = message {
+                    relay_write.write_all(&bytes).await.unwrap();
+                }
+            }
+        });
+        tokio::spawn(async move {
+            let mut buf = vec![0; 64 * 1024];
+            while let Ok(n @ 1..) = relay_read.read(&mut buf).await {
+                if ws_sink.send(Message::binary(&buf[..n])).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (connection, mut session) = common::accept_session("broker", broker_stream).await;
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        let delivery: Delivery<String> = receiver.recv().await.unwrap();
+        receiver.accept(&delivery).await.unwrap();
+        assert_eq!(delivery.body(), "hello over websocket");
+        (connection, session, receiver)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .open(format!("ws://{}", addr).as_str())
+        .await
+        .unwrap();
+    let mut session = Session::begin(&mut connection).await.unwrap();
+    let mut sender = Sender::attach(&mut session, "ws-sender", "q1")
+        .await
+        .unwrap();
+    let outcome = sender.send("hello over websocket").await.unwrap();
+    assert!(outcome.is_accepted());
+
+    let _remote = remote.await.unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn connection_is_opened_over_a_unix_domain_socket() {
+    use fe2o3_amqp::Sender;
+    use tokio::net::UnixStream;
+
+    let (local_stream, remote_stream) = UnixStream::pair().unwrap();
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        let delivery: Delivery<String> = receiver.recv().await.unwrap();
+        receiver.accept(&delivery).await.unwrap();
+        assert_eq!(delivery.body(), "hello over a unix socket");
+        (connection, session, receiver)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut sender = Sender::attach(&mut session, "unix-sender", "q1")
+        .await
+        .unwrap();
+    let outcome = sender.send("hello over a unix socket").await.unwrap();
+    assert!(outcome.is_accepted());
+
+    let _remote = remote.await.unwrap();
+}
+
+#[test]
+fn dropped_handle_closes_the_connection_before_the_runtime_shuts_down() {
+    use fe2o3_amqp::{acceptor::ConnectionAcceptor, connection::Error};
+    use tokio::runtime::Runtime;
+
+    let broker_runtime = Runtime::new().unwrap();
+    let client_runtime = Runtime::new().unwrap();
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let broker = broker_runtime.spawn(async move {
+        let mut connection = ConnectionAcceptor::new("broker")
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        connection.on_close().await
+    });
+
+    client_runtime.block_on(async move {
+        let connection = Connection::builder()
+            .container_id("client")
+            .close_on_drop(true)
+            .open_with_stream(local_stream)
+            .await
+            .unwrap();
+        drop(connection);
+    });
+    // The event loop of the client is gone with its runtime
+    drop(client_runtime);
+
+    // The broker has received the Close instead of losing the transport
+    let outcome = broker_runtime.block_on(broker).unwrap();
+    assert!(matches!(outcome, Err(Error::RemoteClosed)));
+}
+
+#[tokio::test]
+async fn dropped_handle_closes_the_connection_in_the_background() {
+    use fe2o3_amqp::{acceptor::ConnectionAcceptor, connection::Error};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let broker = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::new("broker")
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        connection.on_close().await
+    });
+
+    // Dropping does not block the only worker thread of the current-thread runtime
+    let connection = Connection::builder()
+        .container_id("client")
+        .close_on_drop(true)
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    drop(connection);
+
+    // The broker has received the Close instead of losing the transport
+    let outcome = broker.await.unwrap();
+    assert!(matches!(outcome, Err(Error::RemoteClosed)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unknown_virtual_host_is_refused_with_not_found() {
+    use fe2o3_amqp::{
+        acceptor::{ConnectionAcceptor, VirtualHostAcceptor},
+        connection::OpenError,
+        types::definitions::AmqpError,
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let acceptor = VirtualHostAcceptor::new()
+            .virtual_host("tenant-a.example.com", ConnectionAcceptor::new("tenant-a"));
+        acceptor.accept(remote_stream).await
+    });
+
+    let result = Connection::builder()
+        .container_id("client")
+        .hostname("unknown.example.com")
+        .open_with_stream(local_stream)
+        .await;
+    match result {
+        Err(OpenError::RemoteClosedWithError(error)) => {
+            assert_eq!(error.condition, AmqpError::NotFound.into())
+        }
+        other => panic!("Expecting the Close with not-found, found {:?}", other),
+    }
+
+    match remote.await.unwrap() {
+        Err(OpenError::VirtualHostNotFound(hostname)) => {
+            assert_eq!(hostname.as_deref(), Some("unknown.example.com"))
+        }
+        other => panic!(
+            "Expecting the virtual host to be not found, found {:?}",
+            other
+        ),
+    }
+}
@@ -0,0 +1,729 @@
+//! Tests the link flow control
+
+#![cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+
+use fe2o3_amqp::{
+    acceptor::{LinkAcceptor, SessionAcceptor},
+    link::delivery::Delivery,
+    Connection, Receiver,
+};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn flow_echo_returns_the_remote_flow_state() {
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        let outcome = sender.send("hello".to_string()).await.unwrap();
+        assert!(outcome.is_accepted());
+
+        let remote_flow = sender.request_flow_echo().await.unwrap();
+        assert_eq!(remote_flow.delivery_count, Some(1));
+        assert_eq!(remote_flow.link_credit, Some(199));
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, sender)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::attach(&mut session, "receiver", "q1")
+        .await
+        .unwrap();
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    receiver.accept(&delivery).await.unwrap();
+
+    let remote_flow = receiver.request_flow_echo().await.unwrap();
+    assert_eq!(remote_flow.delivery_count, Some(1));
+    assert_eq!(remote_flow.link_credit, Some(199));
+    assert_eq!(remote_flow.available, Some(0));
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sender_advertises_available_messages() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::link::{receiver::CreditMode, sender::AvailableMode};
+    use tokio::sync::oneshot;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (manual_set_tx, manual_set_rx) = oneshot::channel();
+    let (manual_checked_tx, manual_checked_rx) = oneshot::channel();
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+
+        sender.set_available(3).await.unwrap();
+        manual_set_tx.send(()).unwrap();
+        manual_checked_rx.await.unwrap();
+
+        // The message waits for link credit and is advertised as available
+        sender.set_available(0).await.unwrap();
+        sender.set_available_mode(AvailableMode::Auto);
+        let outcome = sender.send("hello".to_string()).await.unwrap();
+        assert!(outcome.is_accepted());
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, sender)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .credit_mode(CreditMode::Manual)
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    manual_set_rx.await.unwrap();
+    let remote_flow = receiver.request_flow_echo().await.unwrap();
+    assert_eq!(remote_flow.available, Some(3));
+    manual_checked_tx.send(()).unwrap();
+
+    let mut available = None;
+    for _ in 0..100 {
+        available = receiver.request_flow_echo().await.unwrap().available;
+        if available == Some(1) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(available, Some(1));
+
+    receiver.set_credit(1).await.unwrap();
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    receiver.accept(&delivery).await.unwrap();
+
+    let remote_flow = receiver.request_flow_echo().await.unwrap();
+    assert_eq!(remote_flow.available, Some(0));
+    assert_eq!(remote_flow.link_credit, Some(0));
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rate_limited_receiver_paces_the_credit() {
+    use std::time::{Duration, Instant};
+
+    use fe2o3_amqp::link::{receiver::CreditMode, RateLimit};
+
+    const COUNT: usize = 30;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+
+        let mut outcomes = Vec::new();
+        for i in 0..COUNT {
+            outcomes.push(sender.send_batchable(format!("m{}", i)).await.unwrap());
+        }
+        for outcome in outcomes {
+            assert!(outcome.await.unwrap().is_accepted());
+        }
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, sender)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .credit_mode(CreditMode::Auto(10))
+        .rate_limit(RateLimit::Messages(20))
+        .attach(&mut session)
+        .await
+        .unwrap();
+    assert_eq!(receiver.rate_limit(), Some(RateLimit::Messages(20)));
+
+    // One second worth of messages arrives as a burst, and the rest at 20 messages per second
+    let start = Instant::now();
+    for i in 0..COUNT {
+        let delivery: Delivery<String> = receiver.recv().await.unwrap();
+        assert_eq!(delivery.body(), &format!("m{}", i));
+        receiver.accept(&delivery).await.unwrap();
+    }
+    assert!(start.elapsed() >= Duration::from_millis(400));
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn receiver_switches_credit_mode_without_detaching() {
+    use fe2o3_amqp::link::receiver::CreditMode;
+    use tokio::sync::oneshot;
+
+    let (resume_tx, resume_rx) = oneshot::channel();
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+
+        assert!(sender.send("m0".to_string()).await.unwrap().is_accepted());
+        resume_rx.await.unwrap();
+        assert!(sender.send("m1".to_string()).await.unwrap().is_accepted());
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, sender)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .credit_mode(CreditMode::Auto(5))
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    assert_eq!(delivery.body(), "m0");
+    receiver.accept(&delivery).await.unwrap();
+
+    // Pausing drains the outstanding credit of the remote sender
+    receiver.set_credit_mode(CreditMode::Manual).await.unwrap();
+    assert!(matches!(receiver.credit_mode(), CreditMode::Manual));
+    let flow = receiver.request_flow_echo().await.unwrap();
+    assert_eq!(flow.link_credit, Some(0));
+
+    // Resuming issues the new credit on the same link
+    receiver.set_credit_mode(CreditMode::Auto(3)).await.unwrap();
+    let flow = receiver.request_flow_echo().await.unwrap();
+    assert_eq!(flow.link_credit, Some(3));
+    resume_tx.send(()).unwrap();
+
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    assert_eq!(delivery.body(), "m1");
+    receiver.accept(&delivery).await.unwrap();
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sender_is_paced_by_the_byte_limit_of_the_receiver() {
+    use std::time::Duration;
+
+    use tokio::sync::oneshot;
+
+    let (blocked_tx, blocked_rx) = oneshot::channel();
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        // The initial limit is carried by the Attach
+        assert_eq!(sender.byte_credit(), Some(1500));
+
+        let body = "x".repeat(1000);
+        let mut outcomes = Vec::new();
+        for _ in 0..2 {
+            outcomes.push(sender.send_batchable(body.clone()).await.unwrap());
+        }
+        assert_eq!(sender.byte_credit(), Some(0));
+        // There is still link credit but no byte credit
+        let blocked = tokio::time::timeout(
+            Duration::from_millis(100),
+            sender.send_batchable(body.clone()),
+        )
+        .await;
+        assert!(blocked.is_err());
+
+        blocked_tx.send(()).unwrap();
+        outcomes.push(sender.send_batchable(body).await.unwrap());
+        for outcome in outcomes {
+            assert!(outcome.await.unwrap().is_accepted());
+        }
+        sender.close().await.unwrap();
+        (connection, session)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .auto_accept(true)
+        .byte_window(1500)
+        .attach(&mut session)
+        .await
+        .unwrap();
+    assert_eq!(receiver.byte_window(), Some(1500));
+
+    blocked_rx.await.unwrap();
+    for _ in 0..3 {
+        let delivery = receiver.recv::<String>().await.unwrap();
+        assert_eq!(delivery.body().len(), 1000);
+    }
+
+    assert!(receiver.recv::<String>().await.is_err());
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn send_batch_stops_at_the_byte_limit_of_the_receiver() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::link::delivery::Sendable;
+    use tokio::sync::oneshot;
+
+    let (blocked_tx, blocked_rx) = oneshot::channel();
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        assert_eq!(sender.byte_credit(), Some(1500));
+
+        // The third message would start once the limit is reached, so the batch waits for the
+        // receiver to raise the limit although there is enough link credit
+        let sendables = (0..3).map(|_| Sendable::from("x".repeat(1000)));
+        let blocked =
+            tokio::time::timeout(Duration::from_millis(100), sender.send_batch(sendables)).await;
+        assert!(blocked.is_err());
+        assert_eq!(sender.byte_credit(), Some(0));
+
+        blocked_tx.send(()).unwrap();
+        sender.close().await.unwrap();
+        (connection, session)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .auto_accept(true)
+        .byte_window(1500)
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    blocked_rx.await.unwrap();
+    for _ in 0..2 {
+        let delivery = receiver.recv::<String>().await.unwrap();
+        assert_eq!(delivery.body().len(), 1000);
+    }
+
+    assert!(receiver.recv::<String>().await.is_err());
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn flow_properties_are_exchanged_and_watched() {
+    use fe2o3_amqp::types::{definitions::Fields, primitives::Value};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+
+        // The receiver sends the properties once it is told that the sender is watching
+        let mut watch = sender.watch_remote_flow_properties();
+        assert!(watch.borrow().is_none());
+        sender.send("watching".to_string()).await.unwrap();
+        watch.changed().await.unwrap();
+        let properties = watch.borrow_and_update().clone().unwrap();
+        assert_eq!(properties.get("priority"), Some(&Value::from(5i32)));
+        assert_eq!(sender.remote_flow_properties(), Some(properties));
+
+        let mut properties = Fields::new();
+        properties.insert("backlog".into(), Value::from("low"));
+        sender.send_flow_properties(properties).await.unwrap();
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, sender)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::attach(&mut session, "receiver", "q1")
+        .await
+        .unwrap();
+    let mut watch = receiver.watch_remote_flow_properties();
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    receiver.accept(&delivery).await.unwrap();
+
+    // The properties are only carried by the Flow and are not added to the link properties
+    let mut properties = Fields::new();
+    properties.insert("priority".into(), Value::from(5i32));
+    receiver.send_flow_properties(properties).await.unwrap();
+    assert!(receiver.properties(|properties| properties.is_none()));
+
+    watch.changed().await.unwrap();
+    let properties = receiver.remote_flow_properties().unwrap();
+    assert_eq!(properties.get("backlog"), Some(&Value::from("low")));
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keep_alive_reports_missed_echoes_of_a_stuck_link() {
+    use std::{sync::Arc, time::Duration};
+
+    use bytes::Bytes;
+    use fe2o3_amqp::{
+        acceptor::ConnectionAcceptor,
+        link::{LinkEvent, LinkKeepAlive},
+        session::{TransferMiddleware, TransferMiddlewareFuture},
+        types::{definitions::SenderSettleMode, performatives::Transfer},
+        Sender,
+    };
+    use tokio::sync::Semaphore;
+
+    /// Holds the session of the broker on every incoming transfer until a permit is added
+    struct Stall(Arc<Semaphore>);
+
+    impl TransferMiddleware for Stall {
+        fn on_incoming_transfer<'a>(
+            &'a self,
+            _transfer: &'a Transfer,
+            payload: Bytes,
+        ) -> TransferMiddlewareFuture<'a> {
+            Box::pin(async move {
+                self.0.acquire().await.unwrap().forget();
+                Ok(payload)
+            })
+        }
+    }
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let permits = Arc::new(Semaphore::new(0));
+
+    let stall = Stall(permits.clone());
+    let remote = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::new("broker")
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        let mut session = SessionAcceptor::builder()
+            .transfer_middleware(stall)
+            .build()
+            .accept(&mut connection)
+            .await
+            .unwrap();
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        let delivery: Delivery<String> = receiver.recv().await.unwrap();
+        assert_eq!(delivery.body(), "hello");
+        (connection, session, receiver)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut sender = Sender::builder()
+        .name("sender")
+        .target("q1")
+        .sender_settle_mode(SenderSettleMode::Settled)
+        .keep_alive(LinkKeepAlive::new(Duration::from_millis(50)))
+        .attach(&mut session)
+        .await
+        .unwrap();
+    let mut events = sender.subscribe_events();
+
+    // The connection stays healthy while the session of the broker is stuck on the transfer
+    sender.send("hello".to_string()).await.unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(event, LinkEvent::KeepAliveMissed { missed: 1 });
+
+    permits.add_permits(1);
+    let restored = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match events.recv().await.unwrap() {
+                LinkEvent::KeepAliveRestored => break,
+                LinkEvent::KeepAliveMissed { missed } => assert!(missed > 1),
+            }
+        }
+    })
+    .await;
+    assert!(restored.is_ok());
+
+    let _remote = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn send_batch_sends_batchable_transfers_beyond_the_link_credit() {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use bytes::Bytes;
+    use fe2o3_amqp::{
+        acceptor::ConnectionAcceptor,
+        link::delivery::Sendable,
+        session::{TransferMiddleware, TransferMiddlewareFuture},
+        types::performatives::Transfer,
+        Sender,
+    };
+
+    /// Counts the incoming transfers that are batchable
+    struct CountBatchable(Arc<AtomicUsize>);
+
+    impl TransferMiddleware for CountBatchable {
+        fn on_incoming_transfer<'a>(
+            &'a self,
+            transfer: &'a Transfer,
+            payload: Bytes,
+        ) -> TransferMiddlewareFuture<'a> {
+            if transfer.batchable {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+            Box::pin(async move { Ok(payload) })
+        }
+    }
+
+    // More than the default credit of the broker, so the batch has to wait for more credit
+    const COUNT: usize = 500;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let batchable = Arc::new(AtomicUsize::new(0));
+
+    let middleware = CountBatchable(batchable.clone());
+    let remote = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::new("broker")
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        let mut session = SessionAcceptor::builder()
+            .transfer_middleware(middleware)
+            .build()
+            .accept(&mut connection)
+            .await
+            .unwrap();
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        for i in 0..COUNT {
+            let delivery: Delivery<String> = receiver.recv().await.unwrap();
+            assert_eq!(delivery.body(), &format!("message {}", i));
+            receiver.accept(&delivery).await.unwrap();
+        }
+        (connection, session, receiver)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut sender = Sender::attach(&mut session, "batch", "q1").await.unwrap();
+
+    let sendables = (0..COUNT).map(|i| Sendable::from(format!("message {}", i)));
+    let futs = sender.send_batch(sendables).await.unwrap();
+    assert_eq!(futs.len(), COUNT);
+    for fut in futs {
+        assert!(fut.await.unwrap().is_accepted());
+    }
+    assert_eq!(batchable.load(Ordering::SeqCst), COUNT);
+
+    let _remote = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn coalesced_flow_of_a_sender_does_not_overtake_its_transfers() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{
+        frames::amqp::{Frame, FrameBody},
+        session::FlowCoalescing,
+        transport::Transport,
+        types::{
+            definitions::{Role, SenderSettleMode},
+            messaging::{Source, Target},
+            performatives::{Attach, Begin, Flow, Open},
+        },
+        Sender, Session,
+    };
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (local_stream, mut remote_stream) = tokio::io::duplex(64 * 1024);
+
+    // The raw peer records the delivery count of every link flow of the sender, along with the
+    // number of transfers received before it
+    let peer = tokio::spawn(async move {
+        let header = *b"AMQP\x00\x01\x00\x00";
+        let mut incoming_header = [0u8; 8];
+        remote_stream
+            .read_exact(&mut incoming_header)
+            .await
+            .unwrap();
+        assert_eq!(incoming_header, header);
+        remote_stream.write_all(&header).await.unwrap();
+
+        let mut transport: Transport<_, Frame> = Transport::bind(remote_stream, 64 * 1024, None);
+        let frame = transport.next().await.unwrap().unwrap();
+        assert!(matches!(frame.body, FrameBody::Open(_)));
+        let open = Open {
+            container_id: String::from("peer"),
+            hostname: None,
+            max_frame_size: Default::default(),
+            channel_max: Default::default(),
+            idle_time_out: None,
+            outgoing_locales: None,
+            incoming_locales: None,
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        };
+        transport
+            .send(Frame::new(0u16, FrameBody::Open(open)))
+            .await
+            .unwrap();
+
+        let frame = transport.next().await.unwrap().unwrap();
+        assert!(matches!(frame.body, FrameBody::Begin(_)));
+        let begin = Begin {
+            remote_channel: Some(0),
+            next_outgoing_id: 0,
+            incoming_window: 2048,
+            outgoing_window: 2048,
+            handle_max: Default::default(),
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        };
+        transport
+            .send(Frame::new(0u16, FrameBody::Begin(begin)))
+            .await
+            .unwrap();
+
+        let frame = transport.next().await.unwrap().unwrap();
+        let attach = match frame.body {
+            FrameBody::Attach(attach) => attach,
+            _ => panic!("Expecting an attach"),
+        };
+        let attach = Attach {
+            name: attach.name,
+            handle: 0.into(),
+            role: Role::Receiver,
+            snd_settle_mode: attach.snd_settle_mode,
+            rcv_settle_mode: attach.rcv_settle_mode,
+            source: Some(Box::new(Source::builder().build())),
+            target: Some(Box::new(Target::builder().address("q1").build().into())),
+            unsettled: None,
+            incomplete_unsettled: false,
+            initial_delivery_count: None,
+            max_message_size: None,
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        };
+        transport
+            .send(Frame::new(0u16, FrameBody::Attach(attach)))
+            .await
+            .unwrap();
+        let flow = Flow {
+            next_incoming_id: Some(0),
+            incoming_window: 2048,
+            next_outgoing_id: 0,
+            outgoing_window: 2048,
+            handle: Some(0.into()),
+            delivery_count: Some(0),
+            link_credit: Some(10),
+            available: None,
+            drain: false,
+            echo: false,
+            properties: None,
+        };
+        transport
+            .send(Frame::new(0u16, FrameBody::Flow(flow)))
+            .await
+            .unwrap();
+
+        let mut transfers = 0u32;
+        let mut flows = Vec::new();
+        // Keep reading past the coalescing delay to catch a flow that was held back
+        while let Ok(Some(frame)) =
+            tokio::time::timeout(Duration::from_millis(500), transport.next()).await
+        {
+            match frame.unwrap().body {
+                FrameBody::Transfer { .. } => transfers += 1,
+                FrameBody::Flow(flow) if flow.handle.is_some() => {
+                    flows.push((transfers, flow.delivery_count, flow.available))
+                }
+                _ => {}
+            }
+        }
+        (transport, transfers, flows)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::builder()
+        .flow_coalescing(FlowCoalescing::new(Duration::from_millis(200), 64))
+        .begin(&mut connection)
+        .await
+        .unwrap();
+    let mut sender = Sender::builder()
+        .name("sender")
+        .target("q1")
+        .sender_settle_mode(SenderSettleMode::Settled)
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    sender.set_available(2).await.unwrap();
+    sender.send("first".to_string()).await.unwrap();
+    sender.send("second".to_string()).await.unwrap();
+
+    let (_transport, transfers, flows) = peer.await.unwrap();
+    assert_eq!(transfers, 2);
+    assert_eq!(flows, vec![(0, Some(0), Some(2))]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn coalesced_flow_does_not_replace_a_flow_with_properties() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{
+        session::FlowCoalescing,
+        types::{definitions::Fields, primitives::Value},
+        Session,
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+
+        // The flow that replenishes the credit is held back well beyond this timeout
+        let mut watch = sender.watch_remote_flow_properties();
+        let properties = tokio::time::timeout(
+            Duration::from_millis(500),
+            watch.wait_for(|properties| properties.is_some()),
+        )
+        .await
+        .unwrap()
+        .unwrap()
+        .clone()
+        .unwrap();
+        assert_eq!(properties.get("priority"), Some(&Value::from(5i32)));
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, sender)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::builder()
+        .flow_coalescing(FlowCoalescing::new(Duration::from_secs(5), 64))
+        .begin(&mut connection)
+        .await
+        .unwrap();
+    let mut receiver = Receiver::attach(&mut session, "receiver", "q1")
+        .await
+        .unwrap();
+
+    let mut properties = Fields::new();
+    properties.insert("priority".into(), Value::from(5i32));
+    receiver.send_flow_properties(properties).await.unwrap();
+    receiver.set_credit(20).await.unwrap();
+
+    let _endpoints = remote.await.unwrap();
+}
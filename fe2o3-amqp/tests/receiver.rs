@@ -0,0 +1,1081 @@
+//! Tests receiving messages from an accepted sender
+
+#![cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+
+use fe2o3_amqp::{
+    acceptor::{LinkAcceptor, SessionAcceptor},
+    link::delivery::Delivery,
+    Connection, Receiver, Session,
+};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn receiver_group_merges_deliveries_and_routes_dispositions() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{acceptor::ConnectionAcceptor, link::ReceiverGroup};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (held_tx, held_rx) = tokio::sync::oneshot::channel();
+
+    let remote = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::new("broker")
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        let mut sessions = Vec::new();
+        for _ in 0..2 {
+            let session = SessionAcceptor::new()
+                .accept(&mut connection)
+                .await
+                .unwrap();
+            sessions.push(session);
+        }
+        let mut senders = Vec::new();
+        for session in &mut sessions {
+            senders.push(common::accept_sender(session, &LinkAcceptor::new()).await);
+        }
+
+        let held = senders[0].send_batchable("a".to_string()).await.unwrap();
+        for body in ["b", "c"] {
+            let outcome = senders[1].send(body.to_string()).await.unwrap();
+            assert!(outcome.is_accepted());
+        }
+
+        // The slow worker gets no more credit until its delivery is disposed
+        let result = tokio::time::timeout(
+            Duration::from_millis(100),
+            senders[0].send_batchable("d".to_string()),
+        )
+        .await;
+        assert!(result.is_err());
+
+        held_tx.send(()).unwrap();
+        assert!(held.await.unwrap().is_accepted());
+
+        // Keep the endpoints alive until the test finishes
+        (connection, sessions, senders)
+    });
+
+    let (mut connection, mut session1) = common::begin_session("workers", local_stream).await;
+    let mut session2 = Session::begin(&mut connection).await.unwrap();
+    let mut group =
+        ReceiverGroup::<String>::attach(vec![&mut session1, &mut session2], "workers", "q1", 1)
+            .await
+            .unwrap();
+    assert_eq!(group.len(), 2);
+
+    let mut held = None;
+    for _ in 0..3 {
+        let delivery = group.recv().await.unwrap().unwrap();
+        if delivery.body() == "a" {
+            assert_eq!(delivery.member(), 0);
+            held = Some(delivery);
+        } else {
+            assert_eq!(delivery.member(), 1);
+            group.accept(&delivery).await.unwrap();
+        }
+    }
+
+    held_rx.await.unwrap();
+    let disposer = group.disposer();
+    disposer.accept(&held.unwrap()).await.unwrap();
+
+    let (_connection, _sessions, senders) = remote.await.unwrap();
+    let (result, _) = tokio::join!(
+        group.close(),
+        futures_util::future::join_all(senders.into_iter().map(|sender| sender.close()))
+    );
+    result.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn deduplicating_receiver_accepts_and_drops_redelivered_messages() {
+    use fe2o3_amqp::{
+        link::Deduplication,
+        types::messaging::{Message, MessageId, Properties},
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+
+        let mut outcomes = Vec::new();
+        for (id, body) in [(1u64, "a"), (2, "b"), (1, "a"), (3, "c")] {
+            let properties = Properties::builder().message_id(id).build();
+            let message = Message::builder()
+                .properties(properties)
+                .value(body.to_string())
+                .build();
+            outcomes.push(sender.send_batchable(message).await.unwrap());
+        }
+        // The duplicate is accepted as well
+        for outcome in outcomes {
+            assert!(outcome.await.unwrap().is_accepted());
+        }
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, sender)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .deduplication(Deduplication::new(16))
+        .attach(&mut session)
+        .await
+        .unwrap();
+    assert_eq!(receiver.deduplication(), Some(Deduplication::new(16)));
+
+    let mut bodies = Vec::new();
+    for _ in 0..3 {
+        let delivery: Delivery<String> = receiver.recv().await.unwrap();
+        receiver.accept(&delivery).await.unwrap();
+        bodies.push(delivery.into_body());
+    }
+    assert_eq!(bodies, ["a", "b", "c"]);
+    assert_eq!(receiver.suppressed_duplicates(), 1);
+    assert!(receiver.forget_message_id(&MessageId::Ulong(1)));
+    assert!(!receiver.forget_message_id(&MessageId::Ulong(1)));
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn message_group_processor_orders_deliveries_within_each_group() {
+    use std::sync::{Arc, Mutex};
+
+    use fe2o3_amqp::{
+        link::{MessageGroupError, MessageGroupProcessor, RecvError},
+        types::messaging::{Accepted, Message, Outcome, Properties, Rejected},
+    };
+    use tokio::sync::Notify;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        let mut outcomes = Vec::new();
+        for (group, seq) in [("a", 0), ("a", 1), ("b", 0), ("a", 2), ("b", 1)] {
+            let message = Message::builder()
+                .properties(Properties::builder().group_id(String::from(group)).build())
+                .value(format!("{}-{}", group, seq))
+                .build();
+            outcomes.push(sender.send_batchable(message).await.unwrap());
+        }
+        let mut accepted = Vec::new();
+        for outcome in outcomes {
+            accepted.push(outcome.await.unwrap().is_accepted());
+        }
+        assert_eq!(accepted, [true, true, true, true, false]);
+        sender.close().await.unwrap();
+        (connection, session)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::attach(&mut session, "receiver", "q1")
+        .await
+        .unwrap();
+
+    let processed = Arc::new(Mutex::new(Vec::new()));
+    let b_started = Arc::new(Notify::new());
+    let processor = MessageGroupProcessor::new(|delivery: Delivery<String>| {
+        let processed = processed.clone();
+        let b_started = b_started.clone();
+        async move {
+            let body = delivery.body().clone();
+            match body.as_str() {
+                // Waits for the other group, which would never happen if the groups were
+                // processed one after the other
+                "a-0" => b_started.notified().await,
+                "b-0" => b_started.notify_one(),
+                _ => {}
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            processed.lock().unwrap().push(body.clone());
+            match body.as_str() {
+                "b-1" => Outcome::Rejected(Rejected { error: None }),
+                _ => Outcome::Accepted(Accepted {}),
+            }
+        }
+    });
+    let error = processor.run(&mut receiver).await;
+    assert!(matches!(
+        error,
+        MessageGroupError::Recv(RecvError::LinkStateError(_))
+    ));
+
+    let processed = processed.lock().unwrap().clone();
+    let group = |prefix: &str| {
+        processed
+            .iter()
+            .filter(|body| body.starts_with(prefix))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(group("a-"), ["a-0", "a-1", "a-2"]);
+    assert_eq!(group("b-"), ["b-0", "b-1"]);
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn receiver_dispatcher_maps_handler_results_into_dispositions() {
+    use std::sync::{Arc, Mutex};
+
+    use fe2o3_amqp::{
+        link::{DispatcherError, ReceiverDispatcher, RecvError},
+        types::{
+            definitions::AmqpError,
+            messaging::{Message, Outcome, Properties},
+        },
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        let builder = |subject: &str| {
+            Message::builder()
+                .properties(Properties::builder().subject(String::from(subject)).build())
+        };
+        let mut outcomes = Vec::new();
+        for body in ["order-1", "invalid"] {
+            let message = builder("created").value(String::from(body)).build();
+            outcomes.push(sender.send(message).await.unwrap());
+        }
+        let message = builder("created").value(1i32).build();
+        outcomes.push(sender.send(message).await.unwrap());
+        for subject in ["deleted", "unknown"] {
+            let message = builder(subject).value(String::from("order-1")).build();
+            outcomes.push(sender.send(message).await.unwrap());
+        }
+        sender.close().await.unwrap();
+        (connection, session, outcomes)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::attach(&mut session, "receiver", "q1")
+        .await
+        .unwrap();
+
+    let created = Arc::new(Mutex::new(Vec::new()));
+    let handled = created.clone();
+    let panics = Arc::new(Mutex::new(Vec::new()));
+    let reported = panics.clone();
+    let dispatcher = ReceiverDispatcher::new()
+        .on_subject("q1", "created", move |message: Message<String>| {
+            let handled = handled.clone();
+            async move {
+                if message.body == "invalid" {
+                    return Err("invalid order");
+                }
+                handled.lock().unwrap().push(message.body);
+                Ok(())
+            }
+        })
+        .on_subject("q1", "deleted", |_: Message<String>| async move {
+            panic!("deletion is not supported");
+            #[allow(unreachable_code)]
+            Ok::<(), String>(())
+        })
+        .on_address("q2", |_: Message<String>| async { Ok::<(), String>(()) })
+        .max_concurrency(2)
+        .on_panic(move |panic| reported.lock().unwrap().push(panic.clone()));
+    // The dispatcher can be run on another task
+    let error = tokio::spawn(async move { dispatcher.run(&mut receiver).await })
+        .await
+        .unwrap();
+    assert!(matches!(
+        error,
+        DispatcherError::Recv(RecvError::LinkStateError(_))
+    ));
+    assert_eq!(*created.lock().unwrap(), ["order-1"]);
+
+    let (_connection, _session, outcomes) = remote.await.unwrap();
+    assert!(outcomes[0].is_accepted());
+    let condition = |outcome: &Outcome| match outcome {
+        Outcome::Rejected(rejected) => rejected.error.as_ref().unwrap().condition.clone(),
+        _ => panic!("Expecting a rejected outcome"),
+    };
+    assert_eq!(condition(&outcomes[1]), AmqpError::InternalError.into());
+    assert_eq!(condition(&outcomes[2]), AmqpError::DecodeError.into());
+    match (&outcomes[3], &outcomes[4]) {
+        (Outcome::Modified(panicked), Outcome::Modified(unroutable)) => {
+            assert_eq!(panicked.delivery_failed, Some(true));
+            assert_eq!(unroutable.undeliverable_here, Some(true));
+        }
+        _ => panic!("Expecting modified outcomes"),
+    }
+
+    let panics = panics.lock().unwrap();
+    assert_eq!(panics.len(), 1);
+    assert_eq!(panics[0].subject.as_deref(), Some("deleted"));
+    assert_eq!(
+        panics[0].message.as_deref(),
+        Some("deletion is not supported")
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn prefetched_deliveries_are_received_in_priority_order() {
+    use fe2o3_amqp::types::messaging::{Header, Message};
+    use tokio::sync::oneshot;
+
+    let (sent_tx, sent_rx) = oneshot::channel();
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        let mut outcomes = Vec::new();
+        for (body, priority) in [
+            ("low", Some(1)),
+            ("first", None),
+            ("high", Some(9)),
+            ("second", Some(4)),
+        ] {
+            let message = Message::builder().value(body);
+            let message = match priority {
+                Some(priority) => message.header(Header::builder().priority(priority).build()),
+                None => message,
+            };
+            outcomes.push(sender.send_batchable(message.build()).await.unwrap());
+        }
+        sent_tx.send(()).unwrap();
+        for outcome in outcomes {
+            assert!(outcome.await.unwrap().is_accepted());
+        }
+        sender.close().await.unwrap();
+        (connection, session)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .auto_accept(true)
+        .priority_ordering(true)
+        .attach(&mut session)
+        .await
+        .unwrap();
+    assert!(receiver.priority_ordering());
+
+    // Lets all the deliveries arrive before the first recv
+    sent_rx.await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut bodies = Vec::new();
+    for _ in 0..4 {
+        let delivery = receiver.recv::<String>().await.unwrap();
+        bodies.push(delivery.body().clone());
+    }
+    // The message without a header has the default priority of 4
+    assert_eq!(bodies, ["high", "first", "second", "low"]);
+
+    assert!(receiver.recv::<String>().await.is_err());
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn accepts_are_batched_into_ranged_dispositions() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::link::{DetachError, DispositionBatching};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let broker = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        let mut outcomes = Vec::new();
+        for i in 0..4 {
+            outcomes.push(sender.send_batchable(format!("m{}", i)).await.unwrap());
+        }
+        let mut held_back = outcomes.pop().unwrap();
+        // The first three accepts are sent together once `max_ids` is reached
+        for outcome in outcomes {
+            assert!(outcome.await.unwrap().is_accepted());
+        }
+        // The fourth is held back until the interval elapses
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), &mut held_back)
+                .await
+                .is_err()
+        );
+        assert!(held_back.await.unwrap().is_accepted());
+
+        // The accept is sent when the receiver is closed
+        let last = sender.send_batchable(String::from("m4")).await.unwrap();
+        assert!(last.await.unwrap().is_accepted());
+        assert!(matches!(
+            sender.on_detach().await,
+            DetachError::ClosedByRemote
+        ));
+        (connection, session)
+    });
+
+    let (mut connection, mut session) = common::begin_session("client", local_stream).await;
+    let batching = DispositionBatching::new(Duration::from_millis(500), 3);
+    let mut receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .auto_accept(true)
+        .disposition_batching(batching)
+        .attach(&mut session)
+        .await
+        .unwrap();
+    assert_eq!(receiver.disposition_batching(), Some(batching));
+
+    for i in 0..5 {
+        let delivery: Delivery<String> = receiver.recv().await.unwrap();
+        assert_eq!(delivery.body(), &format!("m{}", i));
+    }
+    receiver.close().await.unwrap();
+
+    let _endpoints = broker.await.unwrap();
+    session.end().await.unwrap();
+    connection.close().await.unwrap();
+}
+
+#[derive(Debug)]
+struct RawPayloadCodec;
+
+impl fe2o3_amqp::link::MessageFormatCodec for RawPayloadCodec {
+    type Message = Vec<u8>;
+    type Error = std::convert::Infallible;
+
+    fn decode(
+        &self,
+        _message_format: fe2o3_amqp::types::definitions::MessageFormat,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, Self::Error> {
+        Ok(payload.to_vec())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn custom_message_format_is_decoded_by_registered_codec() {
+    use fe2o3_amqp::{
+        link::{
+            delivery::Sendable, message_format::message_format, CustomFormat,
+            MessageFormatRegistry, RecvError,
+        },
+        types::{messaging::Message, primitives::Binary},
+    };
+
+    let format = message_format(0x00_1234, 1);
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        let message = Message::builder()
+            .data(Binary::from(vec![7u8; 2000]))
+            .build();
+        let sendable = Sendable::builder()
+            .message(message)
+            .message_format(format)
+            .build();
+        sender.send(sendable).await.unwrap();
+        let sendable = Sendable::builder()
+            .message("standard".to_string())
+            .settled(true)
+            .build();
+        sender.send(sendable).await.unwrap();
+        (connection, session, sender)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .max_frame_size(512)
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::begin(&mut connection).await.unwrap();
+    let mut registry = MessageFormatRegistry::new();
+    registry.register(format, RawPayloadCodec);
+    let mut receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .message_formats(registry)
+        .attach(&mut session)
+        .await
+        .unwrap();
+    assert_eq!(
+        receiver.message_formats().formats().collect::<Vec<_>>(),
+        vec![format]
+    );
+
+    // The payload of all the transfers is given to the codec
+    let delivery: Delivery<CustomFormat> = receiver.recv_custom().await.unwrap();
+    assert_eq!(delivery.message_format(), &Some(format));
+    let payload: &Vec<u8> = delivery.body().downcast_ref().unwrap();
+    assert!(payload.len() > 2000);
+    assert!(payload.ends_with(&[7u8; 2000]));
+    receiver.accept(&delivery).await.unwrap();
+
+    // The standard message format is not decoded unless a codec is registered for it
+    match receiver.recv_custom().await {
+        Err(RecvError::UnknownMessageFormat(0)) => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+    drop(receiver);
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn large_deliveries_are_spooled_to_disk() {
+    use fe2o3_amqp::{
+        link::{DeliveryBody, Spooling},
+        types::{
+            messaging::{Body, Message},
+            primitives::{Binary, Value},
+        },
+    };
+
+    let dir = std::env::temp_dir().join(format!("fe2o3-amqp-spool-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        let message = Message::builder()
+            .data(Binary::from(vec![7u8; 4000]))
+            .build();
+        assert!(sender.send(message).await.unwrap().is_accepted());
+        assert!(sender
+            .send("small".to_string())
+            .await
+            .unwrap()
+            .is_accepted());
+        (connection, session, sender)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .max_frame_size(512)
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::begin(&mut connection).await.unwrap();
+    let mut receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .spooling(Spooling::new(1024).dir(&dir))
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    let delivery: Delivery<DeliveryBody<Body<Value>>> = receiver.recv_spooled().await.unwrap();
+    let spooled = match delivery.body() {
+        DeliveryBody::File(spooled) => spooled,
+        DeliveryBody::Memory(body) => panic!("Expecting a spooled delivery, found {:?}", body),
+    };
+    assert!(spooled.path().starts_with(&dir));
+    assert!(spooled.len() > 4000);
+    let message = spooled.decode::<Body<Value>>().unwrap();
+    match message.body {
+        Body::Data(batch) => assert_eq!(batch[0].0.as_slice(), &[7u8; 4000][..]),
+        other => panic!("Unexpected body {:?}", other),
+    }
+    receiver.accept(&delivery).await.unwrap();
+    drop(delivery);
+
+    // The deliveries below the threshold are kept in memory
+    let delivery: Delivery<DeliveryBody<String>> = receiver.recv_spooled().await.unwrap();
+    match delivery.body() {
+        DeliveryBody::Memory(body) => assert_eq!(body, "small"),
+        DeliveryBody::File(_) => panic!("Expecting a delivery in memory"),
+    }
+    receiver.accept(&delivery).await.unwrap();
+
+    // The spool file is removed once the delivery is dropped
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir(&dir).unwrap();
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spooled_deliveries_are_read_back_or_fail_with_spool_error() {
+    use fe2o3_amqp::{
+        link::{RecvError, Spooling},
+        types::{
+            messaging::{Body, Message},
+            primitives::{Binary, Value},
+        },
+    };
+
+    let dir = std::env::temp_dir().join(format!(
+        "fe2o3-amqp-spool-read-back-test-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (_connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        for _ in 0..2 {
+            let message = Message::builder()
+                .data(Binary::from(vec![7u8; 4000]))
+                .build();
+            let _outcome = sender.send(message).await;
+        }
+        std::future::pending::<()>().await;
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .max_frame_size(512)
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::begin(&mut connection).await.unwrap();
+    let mut receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .spooling(Spooling::new(1024).dir(&dir))
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    // A spooled delivery is read back into memory unless it is received with `recv_spooled()`
+    let delivery: Delivery<Body<Value>> = receiver.recv().await.unwrap();
+    match delivery.body() {
+        Body::Data(batch) => assert_eq!(batch[0].0.as_slice(), &[7u8; 4000][..]),
+        other => panic!("Unexpected body {:?}", other),
+    }
+    receiver.accept(&delivery).await.unwrap();
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+    // The spool file cannot be created in a directory that does not exist
+    std::fs::remove_dir(&dir).unwrap();
+    match receiver.recv::<Body<Value>>().await {
+        Err(RecvError::SpoolError(error)) => {
+            assert_eq!(error.kind(), std::io::ErrorKind::NotFound)
+        }
+        other => panic!("Expecting a spool error, got {:?}", other),
+    }
+    remote.abort();
+}
+
+#[cfg(feature = "gzip")]
+#[tokio::test(flavor = "multi_thread")]
+async fn spooled_delivery_survives_cancelled_recv_and_is_decompressed() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{
+        link::{compression::ContentEncoding, DeliveryBody, Spooling},
+        types::{
+            messaging::{Body, Message, Properties},
+            primitives::{Binary, Value},
+        },
+    };
+
+    // Bytes that barely compress, so that the compressed message is still spooled
+    let mut state = 0x2545_f491_u32;
+    let bytes: Vec<u8> = (0..8000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+
+    let dir = std::env::temp_dir().join(format!(
+        "fe2o3-amqp-spool-cancel-test-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let compressed = ContentEncoding::Gzip.encode(&bytes).unwrap();
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        let message = Message::builder()
+            .properties(Properties::builder().content_encoding("gzip").build())
+            .data(Binary::from(compressed))
+            .build();
+        assert!(sender.send(message).await.unwrap().is_accepted());
+        (connection, session, sender)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .max_frame_size(512)
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::begin(&mut connection).await.unwrap();
+    let mut receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .spooling(Spooling::new(1024).dir(&dir))
+        .decompress(true)
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    // Every call is cancelled at its first pending `.await`
+    let delivery = loop {
+        let recv = receiver.recv_spooled::<Body<Value>>();
+        if let Ok(result) = tokio::time::timeout(Duration::ZERO, recv).await {
+            break result.unwrap();
+        }
+        tokio::task::yield_now().await;
+    };
+    let spooled = match delivery.body() {
+        DeliveryBody::File(spooled) => spooled,
+        DeliveryBody::Memory(body) => panic!("Expecting a spooled delivery, found {:?}", body),
+    };
+    let message = spooled.decode::<Body<Value>>().unwrap();
+    assert!(message.properties.is_none());
+    match message.body {
+        Body::Data(batch) => assert_eq!(batch[0].0.as_slice(), &bytes[..]),
+        other => panic!("Unexpected body {:?}", other),
+    }
+    receiver.accept(&delivery).await.unwrap();
+    drop(delivery);
+
+    // The compressed spool file is replaced by the decompressed one
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir(&dir).unwrap();
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn messages_without_body_are_skipped_or_decoded() {
+    use fe2o3_amqp::{
+        link::{delivery::Sendable, receiver::EmptyBodyPolicy},
+        types::messaging::Properties,
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut senders = Vec::new();
+        for _ in 0..2 {
+            let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+            let mut marker = Sendable::empty();
+            marker.message.properties = Some(Properties::builder().subject("marker").build());
+            sender.send(marker).await.unwrap();
+            sender.send("after-marker".to_string()).await.unwrap();
+            senders.push(sender);
+        }
+        (connection, session, senders)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+
+    let mut receiver = Receiver::builder()
+        .name("skipping")
+        .source("q1")
+        .empty_body_policy(EmptyBodyPolicy::Skip)
+        .attach(&mut session)
+        .await
+        .unwrap();
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    receiver.accept(&delivery).await.unwrap();
+    assert_eq!(delivery.body(), "after-marker");
+
+    let mut receiver = Receiver::attach(&mut session, "decoding", "q2")
+        .await
+        .unwrap();
+    let delivery: Delivery<Option<String>> = receiver.recv().await.unwrap();
+    receiver.accept(&delivery).await.unwrap();
+    assert_eq!(delivery.body(), &None);
+    let subject = delivery
+        .message()
+        .properties
+        .as_ref()
+        .unwrap()
+        .subject
+        .clone();
+    assert_eq!(subject.as_deref(), Some("marker"));
+    let delivery: Delivery<Option<String>> = receiver.recv().await.unwrap();
+    receiver.accept(&delivery).await.unwrap();
+    assert_eq!(delivery.body().as_deref(), Some("after-marker"));
+
+    let _remote = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reused_delivery_tag_detaches_the_receiver() {
+    use fe2o3_amqp::{
+        frames::amqp::{Frame, FrameBody},
+        link::RecvError,
+        types::{
+            definitions::{AmqpError, ErrorCondition, Role},
+            messaging::Target,
+            performatives::{Detach, Transfer},
+        },
+    };
+    use futures_util::{SinkExt, StreamExt};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        let delivery: Delivery<String> = receiver.recv().await.unwrap();
+        assert_eq!(delivery.body(), "a");
+        assert_eq!(receiver.delivery_tag_violations(), 0);
+
+        // The first delivery is still unsettled when its tag is used again
+        match receiver.recv::<String>().await {
+            Err(RecvError::DeliveryTagReused(tag)) => assert_eq!(&tag[..], &[1u8][..]),
+            other => panic!("Expecting DeliveryTagReused, found {:?}", other),
+        }
+        assert_eq!(receiver.delivery_tag_violations(), 1);
+        (connection, session, receiver)
+    });
+
+    let mut transport = common::begin_raw_session(local_stream, u32::MAX).await;
+    let mut attach = common::raw_receiver_attach("raw-sender", 0);
+    if let FrameBody::Attach(attach) = &mut attach.body {
+        attach.role = Role::Sender;
+        attach.target = Some(Box::new(Target::builder().address("q1").build().into()));
+        attach.initial_delivery_count = Some(0);
+    }
+    transport.send(attach).await.unwrap();
+    loop {
+        match transport.next().await.unwrap().unwrap().body {
+            FrameBody::Flow(_) => break,
+            FrameBody::End(end) => panic!("Session is ended {:?}", end),
+            _ => {}
+        }
+    }
+
+    // An amqp-value section that holds the string "a"
+    let payload = bytes::Bytes::from_static(&[0x00, 0x53, 0x77, 0xa1, 0x01, b'a']);
+    for delivery_id in 0..2 {
+        let transfer = Transfer {
+            handle: 0.into(),
+            delivery_id: Some(delivery_id),
+            delivery_tag: Some(vec![1u8].into()),
+            message_format: Some(0),
+            settled: Some(false),
+            more: false,
+            rcv_settle_mode: None,
+            state: None,
+            resume: false,
+            aborted: false,
+            batchable: false,
+        };
+        let body = FrameBody::Transfer {
+            performative: transfer,
+            payload: payload.clone(),
+        };
+        transport.send(Frame::new(0u16, body)).await.unwrap();
+    }
+
+    let detach = loop {
+        match transport.next().await.unwrap().unwrap().body {
+            FrameBody::Detach(detach) => break detach,
+            FrameBody::End(end) => panic!("Session is ended {:?}", end),
+            _ => {}
+        }
+    };
+    assert!(!detach.closed);
+    assert_eq!(
+        detach.error.unwrap().condition,
+        ErrorCondition::AmqpError(AmqpError::NotAllowed)
+    );
+    let detach = Detach {
+        handle: 0.into(),
+        closed: false,
+        error: None,
+    };
+    transport
+        .send(Frame::new(0u16, FrameBody::Detach(detach)))
+        .await
+        .unwrap();
+    let _endpoints = remote.await.unwrap();
+}
+
+#[cfg(feature = "checksum")]
+#[tokio::test(flavor = "multi_thread")]
+async fn checksum_mismatch_is_rejected() {
+    use fe2o3_amqp::{
+        link::checksum::Checksum,
+        types::{
+            definitions::AmqpError,
+            messaging::{AmqpValue, Body, Message, Outcome},
+            primitives::Value,
+        },
+        Sender,
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    // The broker relays the stamped message as it is, then with a corrupted body
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+
+        let delivery = receiver.recv::<Body<Value>>().await.unwrap();
+        receiver.accept(&delivery).await.unwrap();
+        let message: Message<Body<Value>> = delivery.into_message();
+        assert!(message.footer.is_some());
+
+        let mut corrupted = message.clone();
+        corrupted.body = Body::Value(AmqpValue(Value::String(String::from("hellp"))));
+        let outcome = sender.send(corrupted).await.unwrap();
+        match outcome {
+            Outcome::Rejected(rejected) => {
+                let error = rejected.error.unwrap();
+                assert_eq!(error.condition, AmqpError::DecodeError.into());
+            }
+            _ => panic!("Expecting the corrupted message to be rejected"),
+        }
+
+        let outcome = sender.send(message).await.unwrap();
+        assert!(outcome.is_accepted());
+        (connection, session, receiver, sender)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+
+    let mut sender = Sender::builder()
+        .name("stamping")
+        .target("q1")
+        .checksum(Checksum::new())
+        .attach(&mut session)
+        .await
+        .unwrap();
+    let mut receiver = Receiver::builder()
+        .name("verifying")
+        .source("q1")
+        .checksum(Checksum::new())
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    sender.send("hello".to_string()).await.unwrap();
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    receiver.accept(&delivery).await.unwrap();
+    assert_eq!(delivery.body(), "hello");
+
+    let _remote = remote.await.unwrap();
+}
+
+#[cfg(feature = "gzip")]
+#[tokio::test(flavor = "multi_thread")]
+async fn decompressed_message_exceeding_the_max_message_size_is_rejected() {
+    use fe2o3_amqp::{
+        link::compression::ContentEncoding,
+        types::{
+            definitions::LinkError,
+            messaging::{Body, Data, Message, Outcome, Properties},
+            primitives::{Binary, Value},
+        },
+    };
+
+    fn gzip_message(bytes: &[u8]) -> Message<Data> {
+        let compressed = ContentEncoding::Gzip.encode(bytes).unwrap();
+        Message::builder()
+            .properties(Properties::builder().content_encoding("gzip").build())
+            .data(Binary::from(compressed))
+            .build()
+    }
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+
+        // A few compressed kilobytes that expand to one megabyte
+        let outcome = sender
+            .send(gzip_message(&[0u8; 1024 * 1024]))
+            .await
+            .unwrap();
+        match outcome {
+            Outcome::Rejected(rejected) => {
+                let error = rejected.error.unwrap();
+                assert_eq!(error.condition, LinkError::MessageSizeExceeded.into());
+            }
+            _ => panic!("Expecting the message to be rejected"),
+        }
+
+        let outcome = sender.send(gzip_message(b"hello")).await.unwrap();
+        assert!(outcome.is_accepted());
+        (connection, session, sender)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::builder()
+        .name("decompressing")
+        .source("q1")
+        .max_message_size(64 * 1024u64)
+        .decompress(true)
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    let delivery = receiver.recv::<Body<Value>>().await.unwrap();
+    receiver.accept(&delivery).await.unwrap();
+    let message = delivery.into_message();
+    assert!(message.properties.is_none());
+    match message.body {
+        Body::Data(batch) => assert_eq!(
+            batch.into_iter().next().unwrap().0,
+            Binary::from(b"hello".to_vec())
+        ),
+        body => panic!("Expecting a data body, found {:?}", body),
+    }
+
+    let _remote = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn receiver_stream_yields_deliveries_until_the_link_is_closed() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::link::RecvError;
+    use futures_util::{stream::FusedStream, StreamExt};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (start_tx, start_rx) = tokio::sync::oneshot::channel();
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        start_rx.await.unwrap();
+        for i in 0..3 {
+            let outcome = sender.send(format!("message {}", i)).await.unwrap();
+            assert!(outcome.is_accepted());
+        }
+        sender.close().await.unwrap();
+        (connection, session)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let receiver = Receiver::attach(&mut session, "stream", "q1")
+        .await
+        .unwrap();
+    let mut stream = receiver.into_stream::<String>();
+
+    // The receive that is in progress is kept when the poll times out
+    let next = tokio::time::timeout(Duration::from_millis(50), stream.next()).await;
+    assert!(next.is_err());
+    start_tx.send(()).unwrap();
+
+    for i in 0..3 {
+        let delivery = stream.next().await.unwrap().unwrap();
+        assert_eq!(delivery.body(), &format!("message {}", i));
+        stream.receiver_mut().await.accept(&delivery).await.unwrap();
+    }
+
+    let closed = stream.next().await.unwrap();
+    assert!(matches!(closed, Err(RecvError::LinkStateError(_))));
+    assert!(stream.is_terminated());
+    assert!(stream.next().await.is_none());
+
+    let _remote = remote.await.unwrap();
+}
@@ -0,0 +1,391 @@
+//! Tests resuming links and connections after a failure
+
+#![cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+
+use fe2o3_amqp::{
+    acceptor::{LinkAcceptor, LinkEndpoint},
+    link::delivery::Delivery,
+    Connection, Receiver,
+};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn buffered_sender_flushes_buffered_messages_after_reconnect() {
+    use fe2o3_amqp::{
+        acceptor::{ListenerConnectionHandle, ListenerSessionHandle},
+        link::{BufferedSendError, BufferedSender, OverflowPolicy},
+    };
+
+    type Endpoints = (ListenerConnectionHandle, ListenerSessionHandle, Receiver);
+
+    async fn broker(stream: tokio::io::DuplexStream, count: usize) -> (Vec<String>, Endpoints) {
+        let (connection, mut session) = common::accept_session("broker", stream).await;
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        let mut bodies = Vec::new();
+        for _ in 0..count {
+            let delivery: Delivery<String> = receiver.recv().await.unwrap();
+            receiver.accept(&delivery).await.unwrap();
+            bodies.push(delivery.into_body());
+        }
+        // Keep the endpoints alive until the client goes away
+        (bodies, (connection, session, receiver))
+    }
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let first = tokio::spawn(broker(remote_stream, 1));
+
+    let (mut connection, mut session) =
+        common::begin_session("telemetry-agent", local_stream).await;
+    let sender = fe2o3_amqp::Sender::attach(&mut session, "telemetry", "q1")
+        .await
+        .unwrap();
+    let mut sender = BufferedSender::new(sender, 2, OverflowPolicy::Reject);
+
+    let id = sender.send("a".to_string()).await.unwrap();
+    let (settled_id, result) = sender.next_settlement().await.unwrap();
+    assert_eq!(settled_id, id);
+    assert!(result.unwrap().is_accepted());
+
+    // The connection to the broker is lost
+    let (bodies, _endpoints) = first.await.unwrap();
+    assert_eq!(bodies, vec!["a".to_string()]);
+    let _ = session.end().await;
+    let _ = connection.close().await;
+
+    let b = sender.send("b".to_string()).await.unwrap();
+    let c = sender.send("c".to_string()).await.unwrap();
+    assert!(!sender.is_connected());
+    assert_eq!(sender.buffered(), 2);
+    assert!(matches!(
+        sender.send("d".to_string()).await,
+        Err(BufferedSendError::BufferFull(_))
+    ));
+
+    // The broker comes back
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let second = tokio::spawn(broker(remote_stream, 2));
+    let (_connection, session) = common::begin_session("telemetry-agent", local_stream).await;
+    sender.reconnect(&session).await.unwrap();
+    assert!(sender.is_connected());
+    assert_eq!(sender.buffered(), 0);
+
+    let mut settled = Vec::new();
+    while let Some((id, result)) = sender.next_settlement().await {
+        assert!(result.unwrap().is_accepted());
+        settled.push(id);
+    }
+    settled.sort();
+    assert_eq!(settled, vec![b, c]);
+
+    let (bodies, _endpoints) = second.await.unwrap();
+    assert_eq!(bodies, vec!["b".to_string(), "c".to_string()]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn suspended_receiver_is_resumed_from_its_serialized_state() {
+    use fe2o3_amqp::{
+        link::{DetachError, SuspendedLink},
+        types::definitions::Role,
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        let _unsettled = sender.send_batchable("m0".to_string()).await.unwrap();
+        assert!(matches!(
+            sender.on_detach().await,
+            DetachError::DetachedByRemote
+        ));
+        let _detached = sender.detach().await.unwrap();
+
+        // The resumed link carries on with the delivery count, so the delivery tags are not reused
+        let acceptor = LinkAcceptor::builder().initial_delivery_count(1).build();
+        let mut sender = common::accept_sender(&mut session, &acceptor).await;
+        assert_eq!(sender.name(), "durable-receiver");
+        assert!(sender.send("m1".to_string()).await.unwrap().is_accepted());
+        (connection, session, sender)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::builder()
+        .name("durable-receiver")
+        .source("q1")
+        .attach(&mut session)
+        .await
+        .unwrap();
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    assert_eq!(delivery.body(), "m0");
+
+    // The delivery is left unsettled and the link is detached without being closed
+    let detached = receiver.detach().await.unwrap();
+    let bytes = detached.to_suspended_link().to_bytes().unwrap();
+    drop(detached);
+
+    let suspended = SuspendedLink::from_bytes(&bytes).unwrap();
+    assert_eq!(suspended.name, "durable-receiver");
+    assert_eq!(suspended.role, Role::Receiver);
+    assert_eq!(
+        suspended.source.as_ref().unwrap().address.as_deref(),
+        Some("q1")
+    );
+    assert_eq!(suspended.delivery_count, 1);
+    assert_eq!(
+        suspended.unsettled.keys().collect::<Vec<_>>(),
+        [delivery.delivery_tag()]
+    );
+
+    let mut receiver = Receiver::builder()
+        .resume_from(suspended)
+        .attach(&mut session)
+        .await
+        .unwrap();
+    let snapshot = receiver.unsettled_snapshot();
+    assert_eq!(snapshot.len(), 1);
+    assert_eq!(&snapshot[0].delivery_tag, delivery.delivery_tag());
+
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    assert_eq!(delivery.body(), "m1");
+    receiver.accept(&delivery).await.unwrap();
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn recovering_connection_resumes_links_after_the_network_drops() {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use fe2o3_amqp::{
+        recovery::{ReconnectPolicy, RecoveringConnection},
+        Sender,
+    };
+    use tokio::{
+        io::DuplexStream,
+        sync::{mpsc, Mutex},
+        task::JoinHandle,
+    };
+
+    let (stream_tx, mut stream_rx) = mpsc::unbounded_channel::<DuplexStream>();
+    let (body_tx, mut body_rx) = mpsc::unbounded_channel::<String>();
+
+    let broker = tokio::spawn(async move {
+        let mut handlers = Vec::new();
+        let mut n = 0;
+        while let Some(stream) = stream_rx.recv().await {
+            let body_tx = body_tx.clone();
+            handlers.push(tokio::spawn(async move {
+                let (mut connection, mut session) = common::accept_session("broker", stream).await;
+                while let Ok(link) = session.accept_link(&LinkAcceptor::new()).await {
+                    let body_tx = body_tx.clone();
+                    tokio::spawn(async move {
+                        match link {
+                            LinkEndpoint::Receiver(mut receiver) => {
+                                while let Ok(delivery) = receiver.recv::<String>().await {
+                                    receiver.accept(&delivery).await.unwrap();
+                                    body_tx.send(delivery.into_body()).unwrap();
+                                }
+                            }
+                            LinkEndpoint::Sender(mut sender) => {
+                                let _ = sender.send(format!("connection {}", n)).await;
+                                sender.on_detach().await;
+                            }
+                        }
+                    });
+                }
+                let _ = connection.on_close().await;
+            }));
+            n += 1;
+        }
+        handlers
+    });
+
+    // The relay between the client and the broker is aborted to simulate a network drop
+    let relay: Arc<Mutex<Option<JoinHandle<()>>>> = Arc::new(Mutex::new(None));
+    let connects = Arc::new(AtomicUsize::new(0));
+
+    let connect = {
+        let relay = relay.clone();
+        let connects = connects.clone();
+        move || {
+            let relay = relay.clone();
+            let stream_tx = stream_tx.clone();
+            connects.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let (client, mut client_end) = tokio::io::duplex(64 * 1024);
+                let (mut broker_end, broker) = tokio::io::duplex(64 * 1024);
+                stream_tx.send(broker).unwrap();
+                *relay.lock().await = Some(tokio::spawn(async move {
+                    let _ = tokio::io::copy_bidirectional(&mut client_end, &mut broker_end).await;
+                }));
+                Connection::builder()
+                    .container_id("client")
+                    .open_with_stream(client)
+                    .await
+            }
+        }
+    };
+
+    let policy =
+        ReconnectPolicy::new(5).backoff(Duration::from_millis(10), Duration::from_millis(100));
+    let connection = RecoveringConnection::open(connect, policy).await.unwrap();
+    let session = connection.begin_session().await.unwrap();
+    let mut sender = session
+        .attach_sender(Sender::builder().name("recovering-sender").target("q1"))
+        .await
+        .unwrap();
+    let mut receiver = session
+        .attach_receiver(Receiver::builder().name("recovering-receiver").source("q1"))
+        .await
+        .unwrap();
+
+    assert!(sender.send("before").await.unwrap().is_accepted());
+    assert_eq!(body_rx.recv().await.unwrap(), "before");
+    let delivery = receiver.recv::<String>().await.unwrap();
+    assert_eq!(delivery.body(), "connection 0");
+    receiver.receiver_mut().accept(&delivery).await.unwrap();
+
+    relay.lock().await.take().unwrap().abort();
+
+    assert!(sender.send("after").await.unwrap().is_accepted());
+    assert_eq!(body_rx.recv().await.unwrap(), "after");
+    let delivery = receiver.recv::<String>().await.unwrap();
+    assert_eq!(delivery.body(), "connection 1");
+    receiver.receiver_mut().accept(&delivery).await.unwrap();
+
+    assert_eq!(connects.load(Ordering::SeqCst), 2);
+    assert_eq!(connection.reconnects().await, 1);
+
+    sender.close().await.unwrap();
+    receiver.close().await.unwrap();
+    connection.close().await.unwrap();
+    broker.abort();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn close_stops_the_recovery_waiting_for_the_next_attempt() {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use fe2o3_amqp::{
+        connection::OpenError,
+        recovery::{ReconnectPolicy, RecoveringConnection, RecoveryError},
+        Sender,
+    };
+    use tokio::{sync::Mutex, task::JoinHandle};
+
+    // Only the first connection reaches the broker, the later attempts are refused
+    let relay: Arc<Mutex<Option<JoinHandle<()>>>> = Arc::new(Mutex::new(None));
+    let connects = Arc::new(AtomicUsize::new(0));
+    let connect = {
+        let relay = relay.clone();
+        let connects = connects.clone();
+        move || {
+            let relay = relay.clone();
+            let attempt = connects.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if attempt > 0 {
+                    let error = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+                    return Err(OpenError::Io(error));
+                }
+                let (client, mut client_end) = tokio::io::duplex(64 * 1024);
+                let (mut broker_end, broker) = tokio::io::duplex(64 * 1024);
+                tokio::spawn(async move {
+                    let (mut connection, mut session) =
+                        common::accept_session("broker", broker).await;
+                    let _link = session.accept_link(&LinkAcceptor::new()).await;
+                    let _ = connection.on_close().await;
+                });
+                *relay.lock().await = Some(tokio::spawn(async move {
+                    let _ = tokio::io::copy_bidirectional(&mut client_end, &mut broker_end).await;
+                }));
+                Connection::builder()
+                    .container_id("client")
+                    .open_with_stream(client)
+                    .await
+            }
+        }
+    };
+
+    let policy = ReconnectPolicy::new(3).backoff(Duration::from_secs(60), Duration::from_secs(60));
+    let connection = RecoveringConnection::open(connect, policy).await.unwrap();
+    let session = connection.begin_session().await.unwrap();
+    let mut sender = session
+        .attach_sender(Sender::builder().name("recovering-sender").target("q1"))
+        .await
+        .unwrap();
+
+    relay.lock().await.take().unwrap().abort();
+    let send = tokio::spawn(async move { sender.send("after").await });
+
+    // Wait for the first attempt to fail
+    while connects.load(Ordering::SeqCst) < 2 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tokio::time::timeout(Duration::from_secs(5), connection.close())
+        .await
+        .unwrap()
+        .ok();
+    let result = tokio::time::timeout(Duration::from_secs(5), send)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(result, Err(RecoveryError::Closed)));
+    assert_eq!(connects.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resumed_sender_redelivers_the_unsettled_delivery() {
+    use fe2o3_amqp::Sender;
+    use tokio::sync::mpsc;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut attaches = 0;
+        // The first delivery is left unsettled, and every delivery after that is accepted
+        while let Ok(LinkEndpoint::Receiver(mut receiver)) =
+            session.accept_link(&LinkAcceptor::new()).await
+        {
+            while let Ok(delivery) = receiver.recv::<String>().await {
+                if attaches > 0 {
+                    let _ = receiver.accept(&delivery).await;
+                }
+                received_tx.send(delivery.into_body()).unwrap();
+            }
+            attaches += 1;
+        }
+        (connection, session)
+    });
+
+    let (mut connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut sender = Sender::attach(&mut session, "resuming", "q1")
+        .await
+        .unwrap();
+
+    let fut = sender.send_batchable("hello").await.unwrap();
+    assert_eq!(received_rx.recv().await.unwrap(), "hello");
+
+    // The unsettled delivery is kept across the detach and sent again once resumed
+    let detached = sender.detach().await.unwrap();
+    let sender = detached.resume().await.unwrap();
+    assert_eq!(received_rx.recv().await.unwrap(), "hello");
+    assert!(fut.await.unwrap().is_accepted());
+
+    sender.close().await.unwrap();
+    session.end().await.unwrap();
+    connection.close().await.unwrap();
+    let _remote = remote.await.unwrap();
+}
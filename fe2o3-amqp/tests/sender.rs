@@ -0,0 +1,728 @@
+//! Tests sending messages to an accepted receiver
+
+#![cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+
+use fe2o3_amqp::{acceptor::LinkAcceptor, link::delivery::Delivery, Connection, Receiver, Session};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn paused_sender_holds_back_sends_until_resumed() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::Sender;
+    use tokio::sync::oneshot;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (paused_tx, paused_rx) = oneshot::channel();
+    let (resume_tx, resume_rx) = oneshot::channel();
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+
+        paused_rx.await.unwrap();
+        let remote_flow = receiver.request_flow_echo().await.unwrap();
+        assert_eq!(remote_flow.available, Some(0));
+        // The sender has link credit but does not use it while paused
+        assert!(remote_flow.link_credit > Some(0));
+        let recv = receiver.recv::<String>();
+        assert!(tokio::time::timeout(Duration::from_millis(100), recv)
+            .await
+            .is_err());
+        resume_tx.send(()).unwrap();
+
+        let delivery = receiver.recv::<String>().await.unwrap();
+        assert_eq!(delivery.body(), "hello");
+        receiver.accept(&delivery).await.unwrap();
+        let remote_flow = receiver.request_flow_echo().await.unwrap();
+        assert_eq!(remote_flow.available, Some(2));
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, receiver)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut sender = Sender::attach(&mut session, "sender", "q1").await.unwrap();
+
+    sender.set_available(3).await.unwrap();
+    sender.pause().await.unwrap();
+    assert!(sender.is_paused());
+    paused_tx.send(()).unwrap();
+
+    let handle = sender.pause_handle();
+    let send = tokio::spawn(async move {
+        let outcome = sender.send("hello".to_string()).await.unwrap();
+        (sender, outcome)
+    });
+    resume_rx.await.unwrap();
+    assert!(!send.is_finished());
+    handle.resume().await.unwrap();
+    assert!(!handle.is_paused());
+
+    let (_sender, outcome) = send.await.unwrap();
+    assert!(outcome.is_accepted());
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn close_times_out_when_remote_never_replies_with_detach() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::link::DetachError;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let link_acceptor = LinkAcceptor::new();
+        // Hold on to the first link without ever responding to its Detach
+        let unresponsive = session.accept_link(&link_acceptor).await.unwrap();
+        let mut sender = common::accept_sender(&mut session, &link_acceptor).await;
+        sender.send("hello".to_string()).await.unwrap();
+        (connection, session, unresponsive, sender)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let receiver = Receiver::builder()
+        .name("unresponsive")
+        .source("q1")
+        .detach_timeout(Duration::from_millis(100))
+        .attach(&mut session)
+        .await
+        .unwrap();
+    assert!(matches!(receiver.close().await, Err(DetachError::Timeout)));
+
+    // The session remains usable after the link is cleaned up locally
+    let mut receiver = Receiver::attach(&mut session, "receiver", "q2")
+        .await
+        .unwrap();
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    receiver.accept(&delivery).await.unwrap();
+    assert_eq!(delivery.body(), "hello");
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn link_timers_follow_the_clock_of_the_connection() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{
+        clock::{Clock, MockClock},
+        link::{DetachError, SendError},
+        Sendable, Sender,
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let link_acceptor = LinkAcceptor::new();
+        // Hold on to the links without ever granting credit or responding to their Detach
+        let sender = session.accept_link(&link_acceptor).await.unwrap();
+        let receiver = session.accept_link(&link_acceptor).await.unwrap();
+        (connection, session, sender, receiver)
+    });
+
+    let clock = MockClock::new();
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .clock(clock.clone())
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::begin(&mut connection).await.unwrap();
+    let mut sender = Sender::attach(&mut session, "sender", "q1").await.unwrap();
+    let receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .detach_timeout(Duration::from_secs(10))
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    // The deadline of a message that waits for credit is read from the clock
+    let sendable = Sendable::builder()
+        .message("hello".to_string())
+        .deadline(clock.now() + Duration::from_secs(10))
+        .build();
+    let send = tokio::spawn(async move { sender.send(sendable).await });
+    let close = tokio::spawn(receiver.close());
+
+    // Neither timer elapses in real time
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!send.is_finished());
+    assert!(!close.is_finished());
+
+    clock.advance(Duration::from_secs(15));
+    let sent = tokio::time::timeout(Duration::from_secs(5), send).await;
+    assert!(matches!(sent.unwrap().unwrap(), Err(SendError::DeadlineElapsed)));
+    let closed = tokio::time::timeout(Duration::from_secs(5), close).await;
+    assert!(matches!(closed.unwrap().unwrap(), Err(DetachError::Timeout)));
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sharded_sender_routes_messages_by_key() {
+    use std::collections::HashMap;
+
+    use fe2o3_amqp::link::ShardedSender;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (counts_tx, counts_rx) = tokio::sync::oneshot::channel::<HashMap<String, usize>>();
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut receivers = Vec::new();
+        for _ in 0..2 {
+            receivers.push(common::accept_receiver(&mut session, &LinkAcceptor::new()).await);
+        }
+
+        let counts = counts_rx.await.unwrap();
+        let mut received = HashMap::new();
+        for receiver in &mut receivers {
+            let count = counts.get(receiver.name()).copied().unwrap_or(0);
+            let mut bodies = Vec::new();
+            for _ in 0..count {
+                let delivery: Delivery<String> = receiver.recv().await.unwrap();
+                receiver.accept(&delivery).await.unwrap();
+                bodies.push(delivery.into_body());
+            }
+            received.insert(receiver.name().to_string(), bodies);
+        }
+
+        // Keep the endpoints alive until the test finishes
+        (received, (connection, session, receivers))
+    });
+
+    let (_connection, mut session) = common::begin_session("telemetry-agent", local_stream).await;
+    let mut sender = ShardedSender::attach(&mut session, "telemetry", "q1", 2)
+        .await
+        .unwrap();
+    assert_eq!(sender.live_shards(), 2);
+
+    let keys: Vec<String> = (0..16).map(|i| format!("device-{}", i)).collect();
+    let mut counts = HashMap::new();
+    for key in &keys {
+        let index = sender.shard_of(key).unwrap();
+        let name = sender.shard(index).unwrap().name().to_string();
+        *counts.entry(name).or_insert(0) += 2;
+    }
+    assert_eq!(counts.len(), 2, "keys should be spread across both links");
+    counts_tx.send(counts).unwrap();
+
+    let mut outcomes = Vec::new();
+    for round in 0..2 {
+        for key in &keys {
+            let fut = sender
+                .send_batchable(key, format!("{}-{}", key, round))
+                .await
+                .unwrap();
+            outcomes.push(fut);
+        }
+    }
+    for fut in outcomes {
+        assert!(fut.await.unwrap().is_accepted());
+    }
+
+    let (received, _endpoints) = remote.await.unwrap();
+    for bodies in received.values() {
+        // Messages of the same key arrive in order on the same link
+        for key in &keys {
+            let per_key: Vec<&String> = bodies
+                .iter()
+                .filter(|body| body.rsplit_once('-').unwrap().0 == key.as_str())
+                .collect();
+            assert!(
+                per_key.is_empty() || per_key == vec![&format!("{}-0", key), &format!("{}-1", key)]
+            );
+        }
+    }
+
+    // The keys of the dead link are moved to the remaining link
+    let before: Vec<usize> = keys
+        .iter()
+        .map(|key| sender.shard_of(key).unwrap())
+        .collect();
+    sender.mark_dead(0);
+    assert_eq!(sender.live_shards(), 1);
+    for key in &keys {
+        assert_eq!(sender.shard_of(key), Some(1));
+    }
+    sender.mark_live(0);
+    let after: Vec<usize> = keys
+        .iter()
+        .map(|key| sender.shard_of(key).unwrap())
+        .collect();
+    assert_eq!(before, after);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unsettled_snapshots_list_the_pending_deliveries() {
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+
+        let outcome = sender.send_batchable("m0".to_string()).await.unwrap();
+        let snapshot = sender.unsettled_snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert!(snapshot[0].state.is_none());
+        assert!(snapshot[0].age.is_some());
+        assert!(snapshot[0].payload_size.unwrap() > 0);
+
+        assert!(outcome.await.unwrap().is_accepted());
+        assert!(sender.unsettled_snapshot().is_empty());
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, sender)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut receiver = Receiver::attach(&mut session, "receiver", "q1")
+        .await
+        .unwrap();
+
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    let snapshot = receiver.unsettled_snapshot();
+    assert_eq!(snapshot.len(), 1);
+    assert_eq!(&snapshot[0].delivery_tag, delivery.delivery_tag());
+    assert!(snapshot[0].age.is_some());
+    assert!(snapshot[0].payload_size.unwrap() > 0);
+
+    receiver.accept(&delivery).await.unwrap();
+    assert!(receiver.unsettled_snapshot().is_empty());
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sender_settles_unsettled_delivery_with_disposition() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{
+        link::SettleError,
+        types::{
+            definitions::ReceiverSettleMode,
+            messaging::{Accepted, DeliveryState, Outcome},
+        },
+        Sender,
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        let delivery: Delivery<String> = receiver.recv().await.unwrap();
+        assert_eq!(delivery.body(), "hello");
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, receiver)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    // The receiving session only tracks the delivery-ids of the unsettled deliveries in
+    // `ReceiverSettleMode::Second`
+    let mut sender = Sender::builder()
+        .name("producer")
+        .target("q1")
+        .receiver_settle_mode(ReceiverSettleMode::Second)
+        .attach(&mut session)
+        .await
+        .unwrap();
+    let fut = sender.send_batchable("hello".to_string()).await.unwrap();
+    let delivery_tag = fut.delivery_tag().clone();
+    let (broker, _broker_session, _receiver) = remote.await.unwrap();
+
+    let unsettled = || async {
+        let clients: Vec<_> = broker.clients().await.collect();
+        clients[0].links[0].unsettled
+    };
+    assert_eq!(unsettled().await, 1);
+
+    let state = DeliveryState::Accepted(Accepted {});
+    sender
+        .settle_with_state(delivery_tag.clone(), state)
+        .await
+        .unwrap();
+    assert!(matches!(fut.await, Ok(Outcome::Accepted(_))));
+
+    // The remote receiver forgets the delivery once the disposition arrives
+    let mut remaining = unsettled().await;
+    for _ in 0..50 {
+        if remaining == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        remaining = unsettled().await;
+    }
+    assert_eq!(remaining, 0);
+
+    assert!(matches!(
+        sender.settle(delivery_tag).await,
+        Err(SettleError::DeliveryNotFound)
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn idempotent_sender_resends_with_the_same_sequence_numbers() {
+    use fe2o3_amqp::{
+        acceptor::{ListenerConnectionHandle, ListenerSessionHandle},
+        link::{IdempotentSender, ProducerStamp},
+    };
+
+    type Endpoints = (ListenerConnectionHandle, ListenerSessionHandle, Receiver);
+
+    async fn broker(
+        stream: tokio::io::DuplexStream,
+        count: usize,
+    ) -> (Vec<(String, u64)>, Endpoints) {
+        let (connection, mut session) = common::accept_session("broker", stream).await;
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        let mut received = Vec::new();
+        for _ in 0..count {
+            let delivery: Delivery<String> = receiver.recv().await.unwrap();
+            receiver.accept(&delivery).await.unwrap();
+            let stamp = ProducerStamp::from_message(delivery.message()).unwrap();
+            assert_eq!(stamp.producer_id, "producer-1");
+            received.push((delivery.into_body(), stamp.sequence));
+        }
+        // Keep the endpoints alive until the client goes away
+        (received, (connection, session, receiver))
+    }
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let first = tokio::spawn(broker(remote_stream, 1));
+
+    let (mut connection, mut session) = common::begin_session("producer", local_stream).await;
+    let sender = fe2o3_amqp::Sender::attach(&mut session, "orders", "q1")
+        .await
+        .unwrap();
+    let mut sender = IdempotentSender::with_next_sequence(sender, "producer-1", 10);
+    assert!(sender.send("a".to_string()).await.unwrap().is_accepted());
+
+    // The connection to the broker is lost
+    let (received, _endpoints) = first.await.unwrap();
+    assert_eq!(received, vec![("a".to_string(), 10)]);
+    let _ = session.end().await;
+    let _ = connection.close().await;
+
+    assert!(sender.send("b".to_string()).await.is_err());
+    assert!(sender.send("c".to_string()).await.is_err());
+    assert_eq!(sender.unconfirmed(), 2);
+    assert_eq!(sender.next_sequence(), 13);
+
+    // The broker comes back
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let second = tokio::spawn(broker(remote_stream, 2));
+    let (_connection, session) = common::begin_session("producer", local_stream).await;
+    let results = sender.reconnect(&session).await.unwrap();
+    let sequences: Vec<_> = results
+        .into_iter()
+        .map(|(sequence, result)| {
+            assert!(result.unwrap().is_accepted());
+            sequence
+        })
+        .collect();
+    assert_eq!(sequences, vec![11, 12]);
+    assert_eq!(sender.unconfirmed(), 0);
+
+    let (received, _endpoints) = second.await.unwrap();
+    assert_eq!(received, vec![("b".to_string(), 11), ("c".to_string(), 12)]);
+}
+
+#[cfg(feature = "debug-deliveries")]
+#[tokio::test(flavor = "multi_thread")]
+async fn delivery_traces_record_the_frames_of_a_delivery() {
+    use fe2o3_amqp::link::delivery_trace::DeliveryEventKind;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let broker = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        let delivery: Delivery<String> = receiver.recv().await.unwrap();
+        let tag = delivery.delivery_tag().clone();
+        let trace = receiver.delivery_trace(&tag).unwrap();
+        assert!(matches!(
+            trace.events[0].kind,
+            DeliveryEventKind::TransferReceived {
+                delivery_id: Some(_),
+                more: false,
+                ..
+            }
+        ));
+        assert_eq!(receiver.unsettled_delivery_traces().len(), 1);
+
+        receiver.accept(&delivery).await.unwrap();
+        let trace = receiver.delivery_trace(&tag).unwrap();
+        assert!(matches!(
+            trace.events[1].kind,
+            DeliveryEventKind::DispositionSent { settled: true, .. }
+        ));
+        assert!(trace.settled);
+        assert!(receiver.unsettled_delivery_traces().is_empty());
+        (tag, (connection, session, receiver))
+    });
+
+    let (mut connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut sender = fe2o3_amqp::Sender::attach(&mut session, "sender", "q1")
+        .await
+        .unwrap();
+    assert!(sender
+        .send("hello".to_string())
+        .await
+        .unwrap()
+        .is_accepted());
+
+    let (tag, _endpoints) = broker.await.unwrap();
+    let trace = sender.delivery_trace(&tag).unwrap();
+    assert_eq!(trace.events.len(), 2);
+    assert!(matches!(
+        trace.events[0].kind,
+        DeliveryEventKind::TransferSent {
+            settled: false,
+            more: false,
+            resume: false
+        }
+    ));
+    assert!(matches!(
+        trace.events[1].kind,
+        DeliveryEventKind::DispositionReceived { settled: true, .. }
+    ));
+    assert!(trace.settled);
+    assert!(trace.to_string().contains("DispositionReceived"));
+    assert!(sender.unsettled_delivery_traces().is_empty());
+
+    session.end().await.unwrap();
+    connection.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_settlements_are_reported_with_latency_stats() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use fe2o3_amqp::link::SlowSettlementAlert;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let broker = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        let fast: Delivery<String> = receiver.recv().await.unwrap();
+        receiver.accept(&fast).await.unwrap();
+        let slow: Delivery<String> = receiver.recv().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        receiver.accept(&slow).await.unwrap();
+        (connection, session, receiver)
+    });
+
+    let (mut connection, mut session) = common::begin_session("client", local_stream).await;
+    let alerts = std::sync::Arc::new(AtomicUsize::new(0));
+    let hook_alerts = alerts.clone();
+    let alert = SlowSettlementAlert::new(std::time::Duration::from_millis(50)).on_slow_settlement(
+        move |_| {
+            hook_alerts.fetch_add(1, Ordering::SeqCst);
+        },
+    );
+    let mut sender = fe2o3_amqp::Sender::builder()
+        .name("sender")
+        .target("q1")
+        .slow_settlement_alert(alert)
+        .attach(&mut session)
+        .await
+        .unwrap();
+    let mut slow_settlements = sender.subscribe_slow_settlements();
+    assert_eq!(sender.latency_stats().count, 0);
+
+    assert!(sender.send("fast".to_string()).await.unwrap().is_accepted());
+    assert!(sender.send("slow".to_string()).await.unwrap().is_accepted());
+
+    let stats = sender.latency_stats();
+    assert_eq!(stats.count, 2);
+    assert!(stats.min < std::time::Duration::from_millis(50));
+    assert!(stats.max >= std::time::Duration::from_millis(100));
+    assert!(stats.p50 <= stats.p99 && stats.p99 <= stats.max);
+
+    let slow = slow_settlements.try_recv().unwrap();
+    assert!(slow.latency >= std::time::Duration::from_millis(100));
+    assert!(slow_settlements.try_recv().is_err());
+    assert_eq!(alerts.load(Ordering::SeqCst), 1);
+
+    sender.reset_latency_stats();
+    assert_eq!(sender.latency_stats().count, 0);
+
+    let _endpoints = broker.await.unwrap();
+    session.end().await.unwrap();
+    connection.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn message_format_is_kept_on_every_transfer_of_a_delivery() {
+    use fe2o3_amqp::{
+        frames::amqp::{Frame, FrameBody},
+        link::{delivery::Sendable, message_format::message_format},
+        types::{messaging::Message, performatives::Flow, primitives::Binary},
+    };
+    use futures_util::{SinkExt, StreamExt};
+
+    let format = message_format(0x00_1234, 1);
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        let message = Message::builder()
+            .data(Binary::from(vec![7u8; 2000]))
+            .build();
+        let sendable = Sendable::builder()
+            .message(message)
+            .message_format(format)
+            .settled(true)
+            .build();
+        sender.send(sendable).await.unwrap();
+        (connection, session, sender)
+    });
+
+    let mut transport = common::begin_raw_session(local_stream, 512).await;
+    transport
+        .send(common::raw_receiver_attach("raw", 0))
+        .await
+        .unwrap();
+    loop {
+        match transport.next().await.unwrap().unwrap().body {
+            FrameBody::Attach(_) => break,
+            FrameBody::End(end) => panic!("Session is ended {:?}", end),
+            _ => {}
+        }
+    }
+    let flow = Flow {
+        next_incoming_id: Some(0),
+        incoming_window: 2048,
+        next_outgoing_id: 0,
+        outgoing_window: 2048,
+        handle: Some(0.into()),
+        delivery_count: Some(0),
+        link_credit: Some(10),
+        available: None,
+        drain: false,
+        echo: false,
+        properties: None,
+    };
+    transport
+        .send(Frame::new(0u16, FrameBody::Flow(flow)))
+        .await
+        .unwrap();
+
+    let mut transfers = Vec::new();
+    loop {
+        match transport.next().await.unwrap().unwrap().body {
+            FrameBody::Transfer { performative, .. } => {
+                let more = performative.more;
+                transfers.push(performative);
+                if !more {
+                    break;
+                }
+            }
+            FrameBody::Detach(detach) => panic!("Unexpected detach {:?}", detach),
+            FrameBody::End(end) => panic!("Session is ended {:?}", end),
+            _ => {}
+        }
+    }
+    assert!(transfers.len() > 1);
+    assert!(transfers
+        .iter()
+        .all(|transfer| transfer.message_format == Some(format)));
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pre_encoded_message_is_sent_without_serialization() {
+    use fe2o3_amqp::link::delivery::Sendable;
+
+    // An amqp-value section holding a str32 as it would be recorded from the wire
+    let text = "x".repeat(2000);
+    let mut payload = vec![0x00, 0x53, 0x77, 0xb1];
+    payload.extend_from_slice(&(text.len() as u32).to_be_bytes());
+    payload.extend_from_slice(text.as_bytes());
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        let sendable = Sendable::builder().bare_message_bytes(payload).build();
+        let outcome = sender.send_encoded(sendable).await.unwrap();
+        assert!(outcome.is_accepted());
+        (connection, session, sender)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .max_frame_size(512)
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::begin(&mut connection).await.unwrap();
+    let mut receiver = Receiver::attach(&mut session, "receiver", "q1")
+        .await
+        .unwrap();
+
+    // The payload is split into transfers and decoded as any other message
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    assert_eq!(delivery.body(), &text);
+    receiver.accept(&delivery).await.unwrap();
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn send_fails_once_the_deadline_elapses() {
+    use std::time::Duration;
+
+    use fe2o3_amqp::{link::SendError, Sendable, Sender};
+    use tokio::{sync::mpsc, time::Instant};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (ttl_tx, mut ttl_rx) = mpsc::unbounded_channel();
+
+    // The broker receives the messages but never settles them
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+        let mut deliveries = Vec::new();
+        for _ in 0..2 {
+            let delivery: Delivery<String> = receiver.recv().await.unwrap();
+            let ttl = delivery.message().header.as_ref().and_then(|h| h.ttl);
+            ttl_tx.send(ttl).unwrap();
+            deliveries.push(delivery);
+        }
+        (connection, session, receiver, deliveries)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut sender = Sender::attach(&mut session, "deadline", "q1")
+        .await
+        .unwrap();
+
+    let sendable = Sendable::builder()
+        .message("released".to_string())
+        .deadline(Instant::now() + Duration::from_millis(100))
+        .release_after_deadline(true)
+        .build();
+    let result = sender.send(sendable).await;
+    assert!(matches!(result, Err(SendError::DeadlineElapsed)));
+    let ttl = ttl_rx.recv().await.unwrap().unwrap();
+    assert!(ttl > 0 && ttl <= 100);
+    // The delivery is settled as released from the sender side
+    assert!(sender.unsettled_snapshot().is_empty());
+
+    let sendable = Sendable::builder()
+        .message("kept".to_string())
+        .deadline(Instant::now() + Duration::from_millis(50))
+        .build();
+    let fut = sender.send_batchable(sendable).await.unwrap();
+    assert!(matches!(fut.await, Err(SendError::DeadlineElapsed)));
+    assert!(ttl_rx.recv().await.unwrap().is_some());
+    assert_eq!(sender.unsettled_snapshot().len(), 1);
+
+    let _remote = remote.await.unwrap();
+}
@@ -0,0 +1,416 @@
+//! Tests beginning and accepting sessions
+
+#![cfg(all(feature = "acceptor", not(target_arch = "wasm32")))]
+
+use fe2o3_amqp::{
+    acceptor::{LinkAcceptor, SessionAcceptor},
+    link::delivery::Delivery,
+    Connection, Receiver, Session,
+};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_receiver_does_not_block_other_receivers_with_fair_dispatch() {
+    use fe2o3_amqp::{session::FairDispatch, types::definitions::SenderSettleMode};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        let (connection, mut session) = common::accept_session("broker", remote_stream).await;
+        let link_acceptor = LinkAcceptor::new();
+        let mut senders = Vec::new();
+        for _ in 0..2 {
+            senders.push(common::accept_sender(&mut session, &link_acceptor).await);
+        }
+        let mut fast = senders.pop().unwrap();
+        let mut slow = senders.pop().unwrap();
+        assert_eq!(slow.name(), "slow");
+
+        for i in 0..10 {
+            slow.send(format!("slow-{}", i)).await.unwrap();
+        }
+        fast.send("fast".to_string()).await.unwrap();
+        (connection, session, slow, fast)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("consumer")
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::builder()
+        .fair_dispatch(FairDispatch::default())
+        .begin(&mut connection)
+        .await
+        .unwrap();
+
+    let mut slow_builder = Receiver::builder()
+        .name("slow")
+        .source("q1")
+        .sender_settle_mode(SenderSettleMode::Settled);
+    slow_builder.buffer_size = 2;
+    let mut slow = slow_builder.attach(&mut session).await.unwrap();
+    let mut fast = Receiver::builder()
+        .name("fast")
+        .source("q2")
+        .sender_settle_mode(SenderSettleMode::Settled)
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    // The slow receiver has not taken any message yet
+    let delivery: Delivery<String> = fast.recv().await.unwrap();
+    assert_eq!(delivery.body(), "fast");
+
+    for i in 0..10 {
+        let delivery: Delivery<String> = slow.recv().await.unwrap();
+        assert_eq!(delivery.body(), &format!("slow-{}", i));
+    }
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn transfer_middleware_transforms_payloads() {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use bytes::Bytes;
+    use fe2o3_amqp::{
+        acceptor::ConnectionAcceptor,
+        session::{TransferMiddleware, TransferMiddlewareFuture},
+        types::performatives::Transfer,
+    };
+
+    /// Scrambles the payloads between the two sessions
+    struct Xor {
+        key: u8,
+        count: Arc<AtomicUsize>,
+    }
+
+    impl Xor {
+        fn apply(&self, payload: Bytes) -> Bytes {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            payload.iter().map(|b| b ^ self.key).collect()
+        }
+    }
+
+    impl TransferMiddleware for Xor {
+        fn on_incoming_transfer<'a>(
+            &'a self,
+            _transfer: &'a Transfer,
+            payload: Bytes,
+        ) -> TransferMiddlewareFuture<'a> {
+            Box::pin(async move { Ok(self.apply(payload)) })
+        }
+
+        fn on_outgoing_transfer<'a>(
+            &'a self,
+            _transfer: &'a Transfer,
+            payload: Bytes,
+        ) -> TransferMiddlewareFuture<'a> {
+            Box::pin(async move { Ok(self.apply(payload)) })
+        }
+    }
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let remote_count = Arc::new(AtomicUsize::new(0));
+    let local_count = Arc::new(AtomicUsize::new(0));
+
+    let count = remote_count.clone();
+    let remote = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::new("relay")
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        let mut session = SessionAcceptor::builder()
+            .transfer_middleware(Xor { key: 0x5a, count })
+            .build()
+            .accept(&mut connection)
+            .await
+            .unwrap();
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+        let outcome = sender.send("hello".to_string()).await.unwrap();
+        assert!(outcome.is_accepted());
+        (connection, session, sender)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("consumer")
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::builder()
+        .transfer_middleware(Xor {
+            key: 0x5a,
+            count: local_count.clone(),
+        })
+        .begin(&mut connection)
+        .await
+        .unwrap();
+    let mut receiver = Receiver::attach(&mut session, "receiver", "q1")
+        .await
+        .unwrap();
+    let delivery: Delivery<String> = receiver.recv().await.unwrap();
+    receiver.accept(&delivery).await.unwrap();
+    assert_eq!(delivery.body(), "hello");
+
+    let _endpoints = remote.await.unwrap();
+    assert_eq!(remote_count.load(Ordering::SeqCst), 1);
+    assert_eq!(local_count.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn remote_begin_is_exposed_on_both_ends() {
+    use fe2o3_amqp::acceptor::ConnectionAcceptor;
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::new("broker")
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        let mut session = SessionAcceptor::builder()
+            .handle_max(16u32)
+            .add_offered_capabilities("per-session-limits")
+            .build()
+            .accept(&mut connection)
+            .await
+            .unwrap();
+        assert_eq!(session.remote_begin().incoming_window, 512);
+        session.on_end().await.ok();
+        connection.on_close().await.ok();
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut session = Session::builder()
+        .incoming_window(512)
+        .begin(&mut connection)
+        .await
+        .unwrap();
+
+    let remote_begin = session.remote_begin();
+    assert_eq!(remote_begin.handle_max.0, 16);
+    assert!(remote_begin.offers_capability("per-session-limits"));
+
+    session.end().await.unwrap();
+    connection.close().await.unwrap();
+    remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn session_reports_sends_blocked_by_the_remote_incoming_window() {
+    use fe2o3_amqp::{acceptor::ConnectionAcceptor, Sender};
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (unblock_tx, unblock_rx) = tokio::sync::oneshot::channel();
+    let remote = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::new("broker")
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        let mut session = SessionAcceptor::builder()
+            .incoming_window(1)
+            .build()
+            .accept(&mut connection)
+            .await
+            .unwrap();
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+
+        // Any Flow from the receiving session re-opens its incoming-window
+        unblock_rx.await.unwrap();
+        receiver.set_credit(10).await.unwrap();
+        for expected in ["m0", "m1"] {
+            let delivery: Delivery<String> = receiver.recv().await.unwrap();
+            assert_eq!(delivery.body(), expected);
+            receiver.accept(&delivery).await.unwrap();
+        }
+        (connection, session, receiver)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut sender = Sender::attach(&mut session, "sender", "q1").await.unwrap();
+    assert!(!session.is_send_blocked());
+
+    let first = sender.send_batchable("m0".to_string()).await.unwrap();
+    let second = sender.send_batchable("m1".to_string()).await.unwrap();
+    let mut watch = session.watch_send_blocking();
+    let blocking = watch
+        .wait_for(|blocking| blocking.is_blocked)
+        .await
+        .unwrap();
+    assert_eq!(blocking.buffered_transfers, 1);
+    assert_eq!(blocking.blocked_count, 1);
+    assert!(blocking.blocked_since.is_some());
+    drop(blocking);
+    assert!(session.is_send_blocked());
+
+    unblock_tx.send(()).unwrap();
+    assert!(first.await.unwrap().is_accepted());
+    assert!(second.await.unwrap().is_accepted());
+    let blocking = session.send_blocking();
+    assert!(!blocking.is_blocked);
+    assert_eq!(blocking.buffered_transfers, 0);
+    assert_eq!(blocking.blocked_count, 1);
+    assert!(blocking.blocked_since.is_none());
+    assert!(blocking.blocked_total > std::time::Duration::ZERO);
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resumed_session_continues_the_delivery_ids() {
+    use fe2o3_amqp::{
+        acceptor::ConnectionAcceptor,
+        link::{LinkStateError, RecvError},
+        session::TransferIds,
+        Sender,
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let broker = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::new("broker")
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        let mut delivery_ids = Vec::new();
+        for _ in 0..2 {
+            let mut session = SessionAcceptor::new()
+                .accept(&mut connection)
+                .await
+                .unwrap();
+            let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+            for _ in 0..2 {
+                let delivery: Delivery<String> = receiver.recv().await.unwrap();
+                delivery_ids.push(*delivery.delivery_id());
+                receiver.accept(&delivery).await.unwrap();
+            }
+            assert!(matches!(
+                receiver.recv::<String>().await,
+                Err(RecvError::LinkStateError(LinkStateError::RemoteClosed))
+            ));
+            // The end is initiated by the client
+            assert!(matches!(
+                session.on_end().await,
+                Err(fe2o3_amqp::session::Error::RemoteEnded)
+            ));
+        }
+        (connection, delivery_ids)
+    });
+
+    let (mut connection, mut session) = common::begin_session("client", local_stream).await;
+    let mut sender = Sender::attach(&mut session, "sender-1", "q1")
+        .await
+        .unwrap();
+    sender.send("first".to_string()).await.unwrap();
+    sender.send("second".to_string()).await.unwrap();
+    sender.close().await.unwrap();
+    session.end().await.unwrap();
+    let ids = session.transfer_ids();
+    assert_eq!(
+        ids,
+        TransferIds {
+            next_outgoing_id: 2,
+            next_incoming_id: 0
+        }
+    );
+    assert_eq!(session.remote_continues_numbering(), None);
+
+    let mut session = Session::builder()
+        .resume_from(ids)
+        .begin(&mut connection)
+        .await
+        .unwrap();
+    // The broker does not send any transfer, so its numbering also starts where it left off
+    assert_eq!(session.remote_continues_numbering(), Some(true));
+    let mut sender = Sender::attach(&mut session, "sender-2", "q1")
+        .await
+        .unwrap();
+    sender.send("third".to_string()).await.unwrap();
+    sender.send("fourth".to_string()).await.unwrap();
+    sender.close().await.unwrap();
+    session.end().await.unwrap();
+    assert_eq!(session.transfer_ids().next_outgoing_id, 4);
+
+    let (_connection, delivery_ids) = broker.await.unwrap();
+    assert_eq!(delivery_ids, vec![0, 1, 2, 3]);
+    connection.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bulk_and_interactive_sessions_share_the_connection() {
+    use fe2o3_amqp::{
+        acceptor::ConnectionAcceptor,
+        connection::{QosClass, QosWeights},
+        Sender,
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::builder()
+            .container_id("broker")
+            .qos_weights(QosWeights::new(8, 1))
+            .build()
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        let mut endpoints = Vec::new();
+        for _ in 0..2 {
+            let mut session = SessionAcceptor::new()
+                .accept(&mut connection)
+                .await
+                .unwrap();
+            let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+            for _ in 0..16 {
+                let delivery: Delivery<String> = receiver.recv().await.unwrap();
+                receiver.accept(&delivery).await.unwrap();
+                received.push(delivery.into_body());
+            }
+            endpoints.push((session, receiver));
+        }
+        (connection, endpoints, received)
+    });
+
+    let mut connection = Connection::builder()
+        .container_id("client")
+        .qos_weights(QosWeights::new(4, 1))
+        .open_with_stream(local_stream)
+        .await
+        .unwrap();
+    let mut bulk = Session::builder()
+        .qos_class(QosClass::Bulk)
+        .begin(&mut connection)
+        .await
+        .unwrap();
+    let mut bulk_sender = Sender::attach(&mut bulk, "bulk", "q1").await.unwrap();
+    for i in 0..16 {
+        bulk_sender.send(format!("bulk-{}", i)).await.unwrap();
+    }
+    let mut interactive = Session::begin(&mut connection).await.unwrap();
+    let mut interactive_sender = Sender::attach(&mut interactive, "interactive", "q2")
+        .await
+        .unwrap();
+    for i in 0..16 {
+        interactive_sender
+            .send(format!("interactive-{}", i))
+            .await
+            .unwrap();
+    }
+
+    let (_connection, _endpoints, received) = remote.await.unwrap();
+    let expected: Vec<_> = (0..16)
+        .map(|i| format!("bulk-{}", i))
+        .chain((0..16).map(|i| format!("interactive-{}", i)))
+        .collect();
+    assert_eq!(received, expected);
+}
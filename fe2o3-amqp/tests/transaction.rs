@@ -0,0 +1,351 @@
+//! Tests transactional work against an accepting broker
+
+#![cfg(all(
+    feature = "acceptor",
+    feature = "transaction",
+    not(target_arch = "wasm32")
+))]
+
+use fe2o3_amqp::{
+    acceptor::{LinkAcceptor, SessionAcceptor},
+    link::delivery::Delivery,
+    Receiver,
+};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn transactional_acquisition_retires_deliveries_on_commit() {
+    use fe2o3_amqp::{
+        acceptor::ConnectionAcceptor,
+        link::receiver::CreditMode,
+        transaction::{coordinator::ControlLinkAcceptor, Controller, Transaction},
+        types::{definitions, messaging::Modified, messaging::Outcome},
+    };
+
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+
+    let remote = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::new("broker")
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        let mut session = SessionAcceptor::builder()
+            .control_link_acceptor(ControlLinkAcceptor::default())
+            .build()
+            .accept(&mut connection)
+            .await
+            .unwrap();
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+
+        let mut futs = Vec::new();
+        for body in ["accept", "reject", "release", "modify"] {
+            let fut = sender.send_batchable(body.to_string()).await.unwrap();
+            futs.push(fut);
+        }
+        let mut outcomes = Vec::new();
+        for fut in futs {
+            outcomes.push(fut.await.unwrap());
+        }
+        assert!(matches!(outcomes[0], Outcome::Accepted(_)));
+        assert!(matches!(outcomes[1], Outcome::Rejected(_)));
+        assert!(matches!(outcomes[2], Outcome::Released(_)));
+        match &outcomes[3] {
+            Outcome::Modified(modified) => assert_eq!(modified.delivery_failed, Some(true)),
+            other => panic!("Expecting a modified outcome, found {:?}", other),
+        }
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, sender)
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let controller = Controller::attach(&mut session, "controller")
+        .await
+        .unwrap();
+    let mut receiver = Receiver::builder()
+        .name("receiver")
+        .source("q1")
+        .credit_mode(CreditMode::Manual)
+        .attach(&mut session)
+        .await
+        .unwrap();
+
+    let txn = Transaction::declare(&controller, None).await.unwrap();
+    let mut acquisition = txn.acquire(&mut receiver, 4).await.unwrap();
+    let mut deliveries: Vec<Delivery<String>> = Vec::new();
+    for _ in 0..4 {
+        deliveries.push(acquisition.recv().await.unwrap());
+    }
+    assert_eq!(deliveries[0].body(), "accept");
+
+    acquisition.accept(&deliveries[0]).await.unwrap();
+    acquisition
+        .reject(
+            &deliveries[1],
+            definitions::Error::new(definitions::AmqpError::NotAllowed, None, None),
+        )
+        .await
+        .unwrap();
+    acquisition.release(&deliveries[2]).await.unwrap();
+    let modified = Modified {
+        delivery_failed: Some(true),
+        undeliverable_here: None,
+        message_annotations: None,
+    };
+    acquisition.modify(&deliveries[3], modified).await.unwrap();
+    acquisition.commit().await.unwrap();
+
+    let _endpoints = remote.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn txn_bridge_relays_batches_between_brokers() {
+    use fe2o3_amqp::{
+        acceptor::ConnectionAcceptor,
+        link::receiver::CreditMode,
+        transaction::{coordinator::ControlLinkAcceptor, Controller, TxnBridge},
+        types::messaging::Outcome,
+        Sender,
+    };
+
+    let (source_local, source_remote) = tokio::io::duplex(64 * 1024);
+    let (target_local, target_remote) = tokio::io::duplex(64 * 1024);
+
+    let source = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::new("source")
+            .accept(source_remote)
+            .await
+            .unwrap();
+        let mut session = SessionAcceptor::builder()
+            .control_link_acceptor(ControlLinkAcceptor::default())
+            .build()
+            .accept(&mut connection)
+            .await
+            .unwrap();
+        let mut sender = common::accept_sender(&mut session, &LinkAcceptor::new()).await;
+
+        let mut futs = Vec::new();
+        for body in ["a", "b", "c", "d"] {
+            futs.push(sender.send_batchable(body.to_string()).await.unwrap());
+        }
+        for fut in futs {
+            assert!(matches!(fut.await.unwrap(), Outcome::Accepted(_)));
+        }
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, sender)
+    });
+
+    let target = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::new("target")
+            .accept(target_remote)
+            .await
+            .unwrap();
+        let mut session = SessionAcceptor::builder()
+            .control_link_acceptor(ControlLinkAcceptor::default())
+            .build()
+            .accept(&mut connection)
+            .await
+            .unwrap();
+        let mut receiver = common::accept_receiver(&mut session, &LinkAcceptor::new()).await;
+
+        let mut bodies = Vec::new();
+        for _ in 0..4 {
+            let delivery: Delivery<String> = receiver.recv().await.unwrap();
+            receiver.accept(&delivery).await.unwrap();
+            bodies.push(delivery.into_body());
+        }
+        assert_eq!(bodies, ["a", "b", "c", "d"]);
+
+        // Keep the endpoints alive until the test finishes
+        (connection, session, receiver)
+    });
+
+    let (_source_connection, mut source_session) =
+        common::begin_session("relay", source_local).await;
+    let source_controller = Controller::attach(&mut source_session, "source-controller")
+        .await
+        .unwrap();
+    let mut receiver = Receiver::builder()
+        .name("relay-receiver")
+        .source("q1")
+        .credit_mode(CreditMode::Manual)
+        .attach(&mut source_session)
+        .await
+        .unwrap();
+
+    let (_target_connection, mut target_session) =
+        common::begin_session("relay", target_local).await;
+    let target_controller = Controller::attach(&mut target_session, "target-controller")
+        .await
+        .unwrap();
+    let mut sender = Sender::attach(&mut target_session, "relay-sender", "q2")
+        .await
+        .unwrap();
+
+    let bridge = TxnBridge::new(&source_controller, &target_controller, 2);
+    assert_eq!(bridge.batch_size(), 2);
+    bridge.relay(&mut receiver, &mut sender).await.unwrap();
+    bridge.relay(&mut receiver, &mut sender).await.unwrap();
+
+    let _source = source.await.unwrap();
+    let _target = target.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn discharge_in_doubt_is_resolved_on_a_new_connection() {
+    use fe2o3_amqp::{
+        acceptor::ConnectionAcceptor,
+        frames::amqp::{Frame, FrameBody},
+        transaction::{
+            coordinator::ControlLinkAcceptor, Controller, ControllerSendError, DischargeResolution,
+            Transaction, TransactionDischarge,
+        },
+        transport::Transport,
+        types::{
+            definitions::Role,
+            messaging::DeliveryState,
+            performatives::{Begin, Disposition, Flow, Open},
+            transaction::{Declared, TransactionId},
+        },
+    };
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // A coordinator that declares a transaction and drops the connection once the Discharge
+    // arrives
+    let (local_stream, mut remote_stream) = tokio::io::duplex(64 * 1024);
+    let lost = tokio::spawn(async move {
+        let header = *b"AMQP\x00\x01\x00\x00";
+        remote_stream.write_all(&header).await.unwrap();
+        let mut incoming_header = [0u8; 8];
+        remote_stream
+            .read_exact(&mut incoming_header)
+            .await
+            .unwrap();
+
+        let mut transport: Transport<_, Frame> = Transport::bind(remote_stream, 64 * 1024, None);
+        let mut transfers = 0;
+        while let Some(frame) = transport.next().await {
+            let reply = match frame.unwrap().body {
+                FrameBody::Open(_) => FrameBody::Open(Open {
+                    container_id: String::from("lost-broker"),
+                    hostname: None,
+                    max_frame_size: (64 * 1024).into(),
+                    channel_max: Default::default(),
+                    idle_time_out: None,
+                    outgoing_locales: None,
+                    incoming_locales: None,
+                    offered_capabilities: None,
+                    desired_capabilities: None,
+                    properties: None,
+                }),
+                FrameBody::Begin(_) => FrameBody::Begin(Begin {
+                    remote_channel: Some(0),
+                    next_outgoing_id: 0,
+                    incoming_window: 2048,
+                    outgoing_window: 2048,
+                    handle_max: Default::default(),
+                    offered_capabilities: None,
+                    desired_capabilities: None,
+                    properties: None,
+                }),
+                FrameBody::Attach(mut attach) => {
+                    attach.role = Role::Receiver;
+                    attach.initial_delivery_count = None;
+                    transport
+                        .send(Frame::new(0u16, FrameBody::Attach(attach)))
+                        .await
+                        .unwrap();
+                    FrameBody::Flow(Flow {
+                        next_incoming_id: Some(0),
+                        incoming_window: 2048,
+                        next_outgoing_id: 0,
+                        outgoing_window: 2048,
+                        handle: Some(0.into()),
+                        delivery_count: Some(0),
+                        link_credit: Some(10),
+                        available: None,
+                        drain: false,
+                        echo: false,
+                        properties: None,
+                    })
+                }
+                FrameBody::Transfer { performative, .. } if transfers == 0 => {
+                    transfers += 1;
+                    let declared = Declared {
+                        txn_id: TransactionId::from(*b"txn-1"),
+                    };
+                    FrameBody::Disposition(Disposition {
+                        role: Role::Receiver,
+                        first: performative.delivery_id.unwrap(),
+                        last: None,
+                        settled: true,
+                        state: Some(DeliveryState::Declared(declared)),
+                        batchable: false,
+                    })
+                }
+                // The outcome of the Discharge is lost with the connection
+                FrameBody::Transfer { .. } => return,
+                _ => continue,
+            };
+            transport.send(Frame::new(0u16, reply)).await.unwrap();
+        }
+    });
+
+    let (_connection, mut session) = common::begin_session("client", local_stream).await;
+    let controller = Controller::attach(&mut session, "controller")
+        .await
+        .unwrap();
+    let mut txn = Transaction::declare(&controller, None).await.unwrap();
+    match txn.discharge(false).await {
+        Err(ControllerSendError::DischargeInDoubt(in_doubt)) => {
+            assert_eq!(&in_doubt.txn_id.as_bytes(), b"txn-1");
+            assert!(!in_doubt.fail);
+        }
+        other => panic!("Expecting DischargeInDoubt, found {:?}", other),
+    }
+    assert!(txn.is_discharged());
+    lost.await.unwrap();
+
+    // The coordinator of the new connection does not know the transaction
+    let (local_stream, remote_stream) = tokio::io::duplex(64 * 1024);
+    let (done_tx, done_rx) = tokio::sync::oneshot::channel::<()>();
+    let remote = tokio::spawn(async move {
+        let mut connection = ConnectionAcceptor::new("broker")
+            .accept(remote_stream)
+            .await
+            .unwrap();
+        let session = SessionAcceptor::builder()
+            .control_link_acceptor(ControlLinkAcceptor::default())
+            .build()
+            .accept(&mut connection)
+            .await
+            .unwrap();
+        let _ = done_rx.await;
+        (connection, session)
+    });
+
+    let (_connection, session) = common::begin_session("client", local_stream).await;
+    let resolution = controller.recover_on_session(&session).await.unwrap();
+    match resolution {
+        Some(DischargeResolution::InDoubt(in_doubt)) => {
+            assert_eq!(&in_doubt.txn_id.as_bytes(), b"txn-1")
+        }
+        other => panic!("Expecting an unresolved discharge, found {:?}", other),
+    }
+    assert!(controller.in_doubt().is_none());
+
+    // The recovered control link is used for new transactions
+    let txn = Transaction::declare(&controller, None).await.unwrap();
+    txn.commit().await.unwrap();
+    assert!(controller
+        .recover_on_session(&session)
+        .await
+        .unwrap()
+        .is_none());
+
+    done_tx.send(()).unwrap();
+    let _endpoints = remote.await.unwrap();
+}
@@ -0,0 +1,128 @@
+#![allow(clippy::all)]
+
+//! Compares the allocations and the time of encoding and decoding the symbols of a typical
+//! performative with the inline `Symbol` against a `String` backed symbol, which is how `Symbol`
+//! was stored before.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hash::Hash,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use serde::{Deserialize, Serialize};
+use serde_amqp::{
+    primitives::{OrderedMap, Symbol, SymbolRef},
+    Value,
+};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// A symbol that is backed by a `String`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct StringSymbol(String);
+
+impl Serialize for StringSymbol {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SymbolRef(&self.0).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for StringSymbol {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Same as the previous `Symbol`, which allocates a `String` for every symbol
+        Symbol::deserialize(deserializer).map(|symbol| StringSymbol(symbol.into_inner()))
+    }
+}
+
+/// The symbols carried by an attach or an open frame
+#[derive(Debug, Serialize, Deserialize)]
+struct Performative<S: Ord + Hash> {
+    offered_capabilities: Vec<S>,
+    desired_capabilities: Vec<S>,
+    properties: OrderedMap<S, Value>,
+}
+
+const CAPABILITIES: [&str; 4] = [
+    "ANONYMOUS-RELAY",
+    "DELAYED_DELIVERY",
+    "SHARED-SUBS",
+    "amqp:link:redirect",
+];
+
+const PROPERTY_KEYS: [&str; 4] = ["product", "version", "platform", "x-opt-jms-dest"];
+
+fn performative<S: Ord + Hash>(make: impl Fn(&'static str) -> S) -> Performative<S> {
+    Performative {
+        offered_capabilities: CAPABILITIES.iter().map(|s| make(s)).collect(),
+        desired_capabilities: CAPABILITIES.iter().map(|s| make(s)).collect(),
+        properties: PROPERTY_KEYS
+            .iter()
+            .map(|k| (make(k), Value::Uint(1)))
+            .collect(),
+    }
+}
+
+fn allocations_of(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let inline = performative(Symbol::from);
+    let heap = performative(|s| StringSymbol(s.to_string()));
+    let buf = serde_amqp::to_vec(&inline).unwrap();
+    assert_eq!(buf, serde_amqp::to_vec(&heap).unwrap());
+
+    println!(
+        "allocations per construct: Symbol = {}, String = {}",
+        allocations_of(|| drop(black_box(performative(Symbol::from)))),
+        allocations_of(|| drop(black_box(performative(|s| StringSymbol(s.to_string())))))
+    );
+    println!(
+        "allocations per decode: Symbol = {}, String = {}",
+        allocations_of(|| {
+            let value: Performative<Symbol> = serde_amqp::from_slice(black_box(&buf)).unwrap();
+            drop(value)
+        }),
+        allocations_of(|| {
+            let value: Performative<StringSymbol> =
+                serde_amqp::from_slice(black_box(&buf)).unwrap();
+            drop(value)
+        })
+    );
+
+    c.bench_function("construct performative symbols Symbol", |b| {
+        b.iter(|| performative(Symbol::from))
+    });
+    c.bench_function("construct performative symbols String", |b| {
+        b.iter(|| performative(|s| StringSymbol(s.to_string())))
+    });
+    c.bench_function("deserialize performative symbols Symbol", |b| {
+        b.iter(|| serde_amqp::from_slice::<Performative<Symbol>>(black_box(&buf)).unwrap())
+    });
+    c.bench_function("deserialize performative symbols String", |b| {
+        b.iter(|| serde_amqp::from_slice::<Performative<StringSymbol>>(black_box(&buf)).unwrap())
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
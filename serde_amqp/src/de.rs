@@ -36,6 +36,66 @@ pub fn from_slice<'de, T: de::Deserialize<'de>>(slice: &'de [u8]) -> Result<T, E
     T::deserialize(&mut de)
 }
 
+/// Deserialize an instance of type T from an IO stream with the given [`DecodeLimits`]
+pub fn from_reader_with_limits<T: de::DeserializeOwned>(
+    reader: impl std::io::Read,
+    limits: DecodeLimits,
+) -> Result<T, Error> {
+    let reader = IoReader::new(reader);
+    let mut de = Deserializer::with_limits(reader, limits);
+    T::deserialize(&mut de)
+}
+
+/// Deserialize an instance of type T from a bytes slice with the given [`DecodeLimits`]
+pub fn from_slice_with_limits<'de, T: de::Deserialize<'de>>(
+    slice: &'de [u8],
+    limits: DecodeLimits,
+) -> Result<T, Error> {
+    let reader = SliceReader::new(slice);
+    let mut de = Deserializer::with_limits(reader, limits);
+    T::deserialize(&mut de)
+}
+
+/// Limits on nested compound types (list, map, array and described types) that are checked
+/// while deserializing
+///
+/// The limits guard against untrusted input that would otherwise overflow the stack with deeply
+/// nested values or allocate a large number of elements from a small number of bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DecodeLimits {
+    /// Maximum nesting depth of compound types. `None` means no limit
+    pub max_depth: Option<usize>,
+
+    /// Maximum number of elements in a single list or array, or the maximum number of keys and
+    /// values in a single map. `None` means no limit
+    pub max_collection_len: Option<usize>,
+}
+
+impl DecodeLimits {
+    /// Default maximum nesting depth
+    pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+    /// Default maximum number of elements in a single compound type
+    pub const DEFAULT_MAX_COLLECTION_LEN: usize = 1024 * 1024;
+
+    /// No limit on either the nesting depth or the number of elements
+    pub const fn unlimited() -> Self {
+        Self {
+            max_depth: None,
+            max_collection_len: None,
+        }
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: Some(Self::DEFAULT_MAX_DEPTH),
+            max_collection_len: Some(Self::DEFAULT_MAX_COLLECTION_LEN),
+        }
+    }
+}
+
 /// A structure that deserializes AMQP1.0 binary encoded values into rust types
 #[derive(Debug)]
 pub struct Deserializer<R> {
@@ -44,20 +104,57 @@ pub struct Deserializer<R> {
     enum_type: EnumType,
     struct_encoding: StructEncoding,
     elem_format_code: Option<EncodingCodes>,
+    limits: DecodeLimits,
+    depth: usize,
 }
 
 impl<'de, R: Read<'de>> Deserializer<R> {
     /// Creates a new AMQP1.0 (crate)deserializer
+    ///
+    /// No [`DecodeLimits`] are applied. Use [`Deserializer::with_limits`] when decoding
+    /// untrusted input.
     pub fn new(reader: R) -> Self {
+        Self::with_limits(reader, DecodeLimits::unlimited())
+    }
+
+    /// Creates a new AMQP1.0 (crate)deserializer that enforces the given [`DecodeLimits`]
+    pub fn with_limits(reader: R, limits: DecodeLimits) -> Self {
         Self {
             reader,
             new_type: Default::default(),
             enum_type: Default::default(),
             struct_encoding: StructEncoding::None,
             elem_format_code: None,
+            limits,
+            depth: 0,
+        }
+    }
+
+    /// Returns the limits enforced by the deserializer
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    fn check_collection_len(&self, count: usize) -> Result<(), Error> {
+        match self.limits.max_collection_len {
+            Some(max) if count > max => Err(Error::CollectionLengthLimitExceeded),
+            _ => Ok(()),
         }
     }
 
+    /// Deserialize a compound type one level deeper
+    fn nested<T>(&mut self, op: impl FnOnce(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        if let Some(max) = self.limits.max_depth {
+            if self.depth >= max {
+                return Err(Error::DepthLimitExceeded);
+            }
+        }
+        self.depth += 1;
+        let result = op(self);
+        self.depth -= 1;
+        result
+    }
+
     fn read_format_code(&mut self) -> Option<Result<EncodingCodes, Error>> {
         let code = self.reader.next();
         let code = code?;
@@ -350,22 +447,6 @@ impl<'de, R: Read<'de>> Deserializer<R> {
         }
     }
 
-    #[inline]
-    fn parse_symbol(&mut self) -> Result<String, Error> {
-        match self
-            .get_elem_code_or_read_format_code()
-            .ok_or_else(|| Error::unexpected_eof("parse_symbol"))??
-        {
-            EncodingCodes::Sym8 => self
-                .read_small_string()
-                .ok_or_else(|| Error::unexpected_eof("Expecting sym8"))?,
-            EncodingCodes::Sym32 => self
-                .read_string()
-                .ok_or_else(|| Error::unexpected_eof("Expecting sym32"))?,
-            _ => Err(Error::InvalidFormatCode),
-        }
-    }
-
     #[inline]
     fn parse_byte_buf(&mut self) -> Result<Vec<u8>, Error> {
         match self
@@ -705,10 +786,28 @@ where
     {
         match self.new_type {
             NewType::Symbol => {
-                // Leave symbol as visit_string because serde(untagged)
-                // on descriptor will visit String instead of str
+                // Visit the symbol as str so that a short symbol can be stored inline
+                // without an intermediate `String`
                 self.new_type = NewType::None;
-                visitor.visit_string(self.parse_symbol()?)
+                let len = match self
+                    .get_elem_code_or_read_format_code()
+                    .ok_or_else(|| Error::unexpected_eof("parse_symbol"))??
+                {
+                    EncodingCodes::Sym8 => self
+                        .reader
+                        .next()
+                        .ok_or_else(|| Error::unexpected_eof("Expecting sym8"))?
+                        as usize,
+                    EncodingCodes::Sym32 => {
+                        let len_bytes = self
+                            .reader
+                            .read_const_bytes()
+                            .ok_or_else(|| Error::unexpected_eof("Expecting sym32"))?;
+                        u32::from_be_bytes(len_bytes) as usize
+                    }
+                    _ => return Err(Error::InvalidFormatCode),
+                };
+                self.reader.forward_read_str(len, visitor)
             }
             _ => visitor.visit_string(self.parse_string()?),
         }
@@ -834,8 +933,6 @@ where
     {
         if name == SYMBOL {
             self.new_type = NewType::Symbol;
-            // Leave symbol as visit_string because serde(untagged)
-            // on descriptor will visit String instead of str
             self.deserialize_string(visitor)
         } else if name == SYMBOL_REF {
             self.new_type = NewType::SymbolRef;
@@ -890,7 +987,8 @@ where
                 let len = len - OFFSET_ARRAY8;
                 // let buf = self.reader.read_bytes(len)?;
 
-                visitor.visit_seq(ArrayAccess::new(self, len, count))
+                self.check_collection_len(count)?;
+                self.nested(|de| visitor.visit_seq(ArrayAccess::new(de, len, count)))
             }
             EncodingCodes::Array32 => {
                 // Read "header" bytes
@@ -915,12 +1013,13 @@ where
                 let len = len - OFFSET_ARRAY32;
                 // let buf = self.reader.read_bytes(len)?;
 
-                visitor.visit_seq(ArrayAccess::new(self, len, count))
+                self.check_collection_len(count)?;
+                self.nested(|de| visitor.visit_seq(ArrayAccess::new(de, len, count)))
             }
             EncodingCodes::List0 => {
                 let len = 0;
                 let count = 0;
-                visitor.visit_seq(ListAccess::new(self, len, count))
+                self.nested(|de| visitor.visit_seq(ListAccess::new(de, len, count)))
             }
             EncodingCodes::List8 => {
                 let len = self
@@ -937,7 +1036,8 @@ where
 
                 // Make sure there is no other element format code
                 self.elem_format_code = None;
-                visitor.visit_seq(ListAccess::new(self, len, count))
+                self.check_collection_len(count)?;
+                self.nested(|de| visitor.visit_seq(ListAccess::new(de, len, count)))
             }
             EncodingCodes::List32 => {
                 let len_bytes = self
@@ -956,7 +1056,8 @@ where
 
                 // Make sure there is no other element format code
                 self.elem_format_code = None;
-                visitor.visit_seq(ListAccess::new(self, len, count))
+                self.check_collection_len(count)?;
+                self.nested(|de| visitor.visit_seq(ListAccess::new(de, len, count)))
             }
             _ => Err(Error::InvalidFormatCode),
         }
@@ -1020,7 +1121,7 @@ where
             return Err(Error::SequenceLengthMismatch);
         }
 
-        visitor.visit_seq(ListAccess::new(self, size, count))
+        self.nested(|de| visitor.visit_seq(ListAccess::new(de, size, count)))
     }
 
     fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -1069,7 +1170,8 @@ where
 
         // // AMQP map count includes both key and value, should be halfed
         // let count = count / 2;
-        visitor.visit_map(MapAccess::new(self, size, count))
+        self.check_collection_len(count)?;
+        self.nested(|de| visitor.visit_map(MapAccess::new(de, size, count)))
     }
 
     fn deserialize_tuple_struct<V>(
@@ -1083,17 +1185,19 @@ where
     {
         if name == DESCRIBED_BASIC {
             self.struct_encoding = StructEncoding::DescribedBasic;
-            visitor.visit_seq(DescribedAccess::basic(self, len as u32))
+            self.nested(|de| visitor.visit_seq(DescribedAccess::basic(de, len as u32)))
         } else if name == DESCRIBED_LIST {
             self.struct_encoding = StructEncoding::DescribedList;
-            visitor.visit_seq(DescribedAccess::list(self))
+            self.nested(|de| visitor.visit_seq(DescribedAccess::list(de)))
         } else {
             match self
                 .get_elem_code_or_peek_byte()
                 .ok_or_else(|| Error::unexpected_eof("Expecting format code"))??
                 .try_into()?
             {
-                EncodingCodes::DescribedType => visitor.visit_seq(DescribedAccess::list(self)),
+                EncodingCodes::DescribedType => {
+                    self.nested(|de| visitor.visit_seq(DescribedAccess::list(de)))
+                }
                 _ => self.deserialize_tuple(len, visitor),
             }
         }
@@ -1112,13 +1216,13 @@ where
         let cur_encoding = self.struct_encoding.clone();
         let result = if name == DESCRIBED_BASIC {
             self.struct_encoding = StructEncoding::DescribedBasic;
-            visitor.visit_seq(DescribedAccess::basic(self, fields.len() as u32))
+            self.nested(|de| visitor.visit_seq(DescribedAccess::basic(de, fields.len() as u32)))
         } else if name == DESCRIBED_LIST {
             self.struct_encoding = StructEncoding::DescribedList;
-            visitor.visit_seq(DescribedAccess::list(self))
+            self.nested(|de| visitor.visit_seq(DescribedAccess::list(de)))
         } else if name == DESCRIBED_MAP {
             self.struct_encoding = StructEncoding::DescribedMap;
-            visitor.visit_map(DescribedAccess::map(self))
+            self.nested(|de| visitor.visit_map(DescribedAccess::map(de)))
         } else {
             self.struct_encoding = StructEncoding::None;
             match self
@@ -1130,7 +1234,9 @@ where
                     self.deserialize_tuple(fields.len(), visitor)
                 }
                 EncodingCodes::Map32 | EncodingCodes::Map8 => self.deserialize_map(visitor),
-                EncodingCodes::DescribedType => visitor.visit_seq(DescribedAccess::list(self)),
+                EncodingCodes::DescribedType => {
+                    self.nested(|de| visitor.visit_seq(DescribedAccess::list(de)))
+                }
                 _ => Err(Error::InvalidFormatCode),
             }
         };
@@ -2505,4 +2611,50 @@ mod tests {
         let buf = to_vec(&expected).unwrap();
         assert_eq_from_reader_vs_expected(&buf, expected);
     }
+
+    #[test]
+    fn test_deserialize_nested_value_with_depth_limit() {
+        use super::{from_slice_with_limits, DecodeLimits};
+        use crate::{ser::to_vec, Error, Value};
+
+        let mut value = Value::Null;
+        for _ in 0..10 {
+            value = Value::List(vec![value]);
+        }
+        let buf = to_vec(&value).unwrap();
+
+        let limits = DecodeLimits {
+            max_depth: Some(10),
+            max_collection_len: None,
+        };
+        let deserialized: Value = from_slice_with_limits(&buf, limits).unwrap();
+        assert_eq!(deserialized, value);
+
+        let limits = DecodeLimits {
+            max_depth: Some(9),
+            max_collection_len: None,
+        };
+        let result: Result<Value, _> = from_slice_with_limits(&buf, limits);
+        assert!(matches!(result, Err(Error::DepthLimitExceeded)));
+    }
+
+    #[test]
+    fn test_deserialize_array_with_collection_len_limit() {
+        use super::{from_reader_with_limits, DecodeLimits};
+        use crate::{Error, Value};
+
+        // An array of 200 null elements only takes a few bytes on the wire
+        let buf = [
+            EncodingCodes::Array8 as u8,
+            2,
+            200,
+            EncodingCodes::Null as u8,
+        ];
+        let limits = DecodeLimits {
+            max_depth: None,
+            max_collection_len: Some(100),
+        };
+        let result: Result<Value, _> = from_reader_with_limits(&buf[..], limits);
+        assert!(matches!(result, Err(Error::CollectionLengthLimitExceeded)));
+    }
 }
@@ -39,6 +39,16 @@ pub enum Error {
     /// Length is invalid
     #[error("Invalid length")]
     InvalidLength,
+
+    /// Nesting of compound types exceeds
+    /// [`DecodeLimits::max_depth`](crate::de::DecodeLimits::max_depth)
+    #[error("Nesting depth limit exceeded")]
+    DepthLimitExceeded,
+
+    /// Number of elements in a compound type exceeds
+    /// [`DecodeLimits::max_collection_len`](crate::de::DecodeLimits::max_collection_len)
+    #[error("Collection length limit exceeded")]
+    CollectionLengthLimitExceeded,
 }
 
 impl Error {
@@ -276,7 +276,9 @@ mod format;
 
 pub use serde;
 
-pub use de::{from_reader, from_slice};
+pub use de::{
+    from_reader, from_reader_with_limits, from_slice, from_slice_with_limits, DecodeLimits,
+};
 pub use error::Error;
 pub use ser::to_vec;
 pub use size_ser::serialized_size;
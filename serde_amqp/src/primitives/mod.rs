@@ -4,6 +4,7 @@ mod array;
 mod binary_ref;
 mod decimal;
 mod map;
+mod small_string;
 mod symbol;
 mod timestamp;
 mod uuid;
@@ -13,6 +14,7 @@ pub use crate::primitives::array::*;
 pub use crate::primitives::binary_ref::*;
 pub use crate::primitives::decimal::*;
 pub use crate::primitives::map::*;
+pub use crate::primitives::small_string::*;
 pub use crate::primitives::symbol::*;
 pub use crate::primitives::timestamp::*;
 pub use crate::primitives::uuid::*;
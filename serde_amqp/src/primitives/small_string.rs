@@ -0,0 +1,319 @@
+//! String that stores short values inline
+
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+
+use serde::{
+    de::{self, Visitor},
+    Serialize,
+};
+
+/// Number of bytes that a [`ShortString`] stores inline
+///
+/// This is the largest capacity with which a [`ShortString`] is still as large as a [`String`],
+/// because the inline variant has to fit next to the capacity of the heap variant, whose unused
+/// values are used as the discriminant.
+pub const SHORT_STRING_INLINE_CAPACITY: usize = 15;
+
+/// A [`SmallString`] that stores up to 15 bytes inline and is as large as a [`String`], which
+/// covers most of the symbols used by the protocol, such as error conditions and the keys of the
+/// properties maps
+pub type ShortString = SmallString<SHORT_STRING_INLINE_CAPACITY>;
+
+/// A UTF-8 string that stores values of up to `N` bytes inline and falls back to a heap
+/// allocated [`String`] for longer values
+///
+/// Creating a `SmallString` from a `&str` that fits inline does not allocate. The comparison,
+/// ordering and hashing are the same as the ones of `str`, so a `SmallString` can be looked up by
+/// a `&str` in a map.
+#[derive(Clone)]
+pub struct SmallString<const N: usize>(Repr<N>);
+
+#[derive(Clone)]
+enum Repr<const N: usize> {
+    /// The first `len` bytes of `buf` are valid UTF-8, which is checked once when the
+    /// `SmallString` is created. [`SmallString::new`] builds an empty one and
+    /// [`SmallString::from_str_inline`] copies all the bytes of a `str`, which are the only two
+    /// ways an inline value is built
+    Inline {
+        len: u8,
+        buf: [u8; N],
+    },
+    Heap(String),
+}
+
+impl<const N: usize> SmallString<N> {
+    /// Creates an empty string
+    pub const fn new() -> Self {
+        Self(Repr::Inline {
+            len: 0,
+            buf: [0; N],
+        })
+    }
+
+    /// Copies `s` into a new `SmallString`. This only allocates if `s` is longer than `N` bytes
+    pub fn from_str_inline(s: &str) -> Self {
+        // The length is stored as a `u8`
+        if s.len() <= N && s.len() <= u8::MAX as usize {
+            let mut buf = [0; N];
+            buf[..s.len()].copy_from_slice(s.as_bytes());
+            Self(Repr::Inline {
+                len: s.len() as u8,
+                buf,
+            })
+        } else {
+            Self(Repr::Heap(String::from(s)))
+        }
+    }
+
+    /// Returns the value as `str`
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            // SAFETY: The inline bytes are either empty or copied in full from a `str`, so the
+            // first `len` bytes are always valid UTF-8. See `Repr::Inline`
+            Repr::Inline { len, buf } => unsafe {
+                std::str::from_utf8_unchecked(buf.get_unchecked(..*len as usize))
+            },
+            Repr::Heap(s) => s.as_str(),
+        }
+    }
+
+    /// Whether the value is stored inline
+    pub fn is_inline(&self) -> bool {
+        matches!(self.0, Repr::Inline { .. })
+    }
+
+    /// Converts into a `String`. This allocates if the value is stored inline
+    pub fn into_string(self) -> String {
+        match self.0 {
+            Repr::Inline { .. } => String::from(self.as_str()),
+            Repr::Heap(s) => s,
+        }
+    }
+}
+
+impl<const N: usize> Default for SmallString<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for SmallString<N> {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> AsRef<str> for SmallString<N> {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> Borrow<str> for SmallString<N> {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> fmt::Debug for SmallString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<const N: usize> fmt::Display for SmallString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl<const N: usize> PartialEq for SmallString<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<const N: usize> Eq for SmallString<N> {}
+
+impl<const N: usize> PartialEq<str> for SmallString<N> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a, const N: usize> PartialEq<&'a str> for SmallString<N> {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl<const N: usize> PartialOrd for SmallString<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const N: usize> Ord for SmallString<N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+/// The `Hash` is exactly the same as `str`
+impl<const N: usize> Hash for SmallString<N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl<const N: usize> From<&str> for SmallString<N> {
+    fn from(value: &str) -> Self {
+        Self::from_str_inline(value)
+    }
+}
+
+impl<const N: usize> From<&String> for SmallString<N> {
+    fn from(value: &String) -> Self {
+        Self::from_str_inline(value)
+    }
+}
+
+/// A short `String` is moved inline and its allocation is freed, and a long one is kept as is
+impl<const N: usize> From<String> for SmallString<N> {
+    fn from(value: String) -> Self {
+        if value.len() <= N && value.len() <= u8::MAX as usize {
+            Self::from_str_inline(&value)
+        } else {
+            Self(Repr::Heap(value))
+        }
+    }
+}
+
+impl<const N: usize> From<SmallString<N>> for String {
+    fn from(value: SmallString<N>) -> Self {
+        value.into_string()
+    }
+}
+
+impl<const N: usize> Serialize for SmallString<N> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+struct SmallStringVisitor<const N: usize>;
+
+impl<'de, const N: usize> Visitor<'de> for SmallStringVisitor<N> {
+    type Value = SmallString<N>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(SmallString::from(v))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(SmallString::from(v))
+    }
+}
+
+impl<'de, const N: usize> de::Deserialize<'de> for SmallString<N> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(SmallStringVisitor::<N>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    use super::{ShortString, SmallString};
+
+    fn hash_of<T: Hash + ?Sized>(value: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn short_string_is_as_large_as_string() {
+        assert_eq!(
+            std::mem::size_of::<ShortString>(),
+            std::mem::size_of::<String>()
+        );
+        assert_eq!(
+            std::mem::size_of::<Option<ShortString>>(),
+            std::mem::size_of::<String>()
+        );
+    }
+
+    #[test]
+    fn short_values_are_stored_inline() {
+        let short = ShortString::from("amqp:not-found");
+        assert!(short.is_inline());
+        assert_eq!(short.as_str(), "amqp:not-found");
+
+        let long = ShortString::from("amqp:link:detach-forced");
+        assert!(!long.is_inline());
+        assert_eq!(long, "amqp:link:detach-forced");
+
+        let moved = SmallString::<4>::from(String::from("abcd"));
+        assert!(moved.is_inline());
+        assert_eq!(moved.into_string(), "abcd");
+    }
+
+    #[test]
+    fn ordering_and_hash_follow_str() {
+        let a = ShortString::from("a");
+        let b = SmallString::<0>::from("b");
+        assert!(a.as_str() < b.as_str());
+        assert_eq!(a.cmp(&ShortString::from("b")), std::cmp::Ordering::Less);
+        assert_eq!(hash_of(&a), hash_of("a"));
+        assert_eq!(hash_of(&b), hash_of("b"));
+    }
+
+    #[test]
+    fn multibyte_values_at_the_inline_capacity() {
+        // Five three-byte characters fill the 15 bytes exactly
+        let exact = "日本語の文";
+        assert_eq!(exact.len(), 15);
+        let inline = ShortString::from(exact);
+        assert!(inline.is_inline());
+        assert_eq!(inline.as_str(), exact);
+        assert_eq!(hash_of(&inline), hash_of(exact));
+
+        let moved = ShortString::from(String::from(exact));
+        assert!(moved.is_inline());
+        assert_eq!(moved, inline);
+
+        // A three-byte character that would straddle the end of the inline buffer
+        let straddling = "日本語のé語";
+        assert_eq!(straddling.len(), 17);
+        let heap = ShortString::from(straddling);
+        assert!(!heap.is_inline());
+        assert_eq!(heap.as_str(), straddling);
+    }
+}
@@ -1,7 +1,4 @@
-use std::{
-    borrow::Borrow,
-    ops::{Deref, DerefMut},
-};
+use std::{borrow::Borrow, ops::Deref};
 
 use serde::{
     de::{self, Visitor},
@@ -10,6 +7,8 @@ use serde::{
 
 use crate::__constants::{SYMBOL, SYMBOL_REF};
 
+use super::ShortString;
+
 /// Symbolic values from a constrained domain. This is similar to `Symbol` but
 /// takes a slice instead of `String`.
 ///
@@ -135,30 +134,32 @@ impl<'de> de::Deserialize<'de> for SymbolRef<'de> {
 /// to cache all the distinct values. Symbols are encoded as ASCII characters.
 ///
 /// Symbol should only contain ASCII characters. The implementation, however, wraps
-/// over a String. `AmqpNetLite` also wraps around a String, which in c# is utf-16.
+/// over a [`ShortString`], which stores symbols of up to 15 bytes inline so that most of the
+/// symbols in the performatives do not need a heap allocation. `AmqpNetLite` wraps around a String,
+/// which in c# is utf-16.
 #[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct Symbol(pub String);
+pub struct Symbol(pub ShortString);
 
 impl Symbol {
     /// Creates a new [`Symbol`]
-    pub fn new(val: impl Into<String>) -> Self {
+    pub fn new(val: impl Into<ShortString>) -> Self {
         Self(val.into())
     }
 
     /// Consume the wrapper into the inner string
     pub fn into_inner(self) -> String {
-        self.0
+        self.0.into_string()
     }
 
     /// Returns the inner value as str
     pub fn as_str(&self) -> &str {
-        &self.0[..]
+        self.0.as_str()
     }
 }
 
 impl From<String> for Symbol {
     fn from(val: String) -> Self {
-        Self(val)
+        Self(val.into())
     }
 }
 
@@ -168,6 +169,12 @@ impl From<&str> for Symbol {
     }
 }
 
+impl From<ShortString> for Symbol {
+    fn from(val: ShortString) -> Self {
+        Self(val)
+    }
+}
+
 impl<'a> From<SymbolRef<'a>> for Symbol {
     fn from(value: SymbolRef<'a>) -> Self {
         Self(value.0.into())
@@ -175,23 +182,17 @@ impl<'a> From<SymbolRef<'a>> for Symbol {
 }
 
 impl Deref for Symbol {
-    type Target = String;
+    type Target = str;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        self.0.as_str()
     }
 }
 
-impl DerefMut for Symbol {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
-    }
-}
-
-/// The `Ord` and `Hash` is exactly the same as wrapped `String`, which is the same as `&str`
+/// The `Ord` and `Hash` is exactly the same as wrapped `ShortString`, which is the same as `&str`
 impl Borrow<str> for Symbol {
     fn borrow(&self) -> &str {
-        &self.0[..]
+        self.0.as_str()
     }
 }
 
@@ -200,7 +201,7 @@ impl Serialize for Symbol {
     where
         S: serde::Serializer,
     {
-        serializer.serialize_newtype_struct(SYMBOL, &self.0)
+        serializer.serialize_newtype_struct(SYMBOL, self.0.as_str())
     }
 }
 
@@ -231,8 +232,8 @@ impl<'de> Visitor<'de> for SymbolVisitor {
     where
         D: serde::Deserializer<'de>,
     {
-        let val: String = de::Deserialize::deserialize(deserializer)?;
-        Ok(Symbol::new(val))
+        let val: ShortString = de::Deserialize::deserialize(deserializer)?;
+        Ok(Symbol(val))
     }
 }
 
@@ -273,6 +274,24 @@ mod tests {
         println!("{:?}", deserialized);
     }
 
+    #[test]
+    fn test_deserialize_short_symbol_inline() {
+        use crate::{from_reader, primitives::ShortString};
+
+        let short = Symbol::from("amqp:not-found");
+        let long = Symbol::from("apache.org:legacy-amqp-topic-binding:string");
+        let buf = to_vec(&(&short, &long)).unwrap();
+
+        let (de_short, de_long): (Symbol, Symbol) = from_slice(&buf).unwrap();
+        assert!(de_short.0.is_inline());
+        assert_eq!(de_short, short);
+        assert!(!de_long.0.is_inline());
+        assert_eq!(de_long, long);
+
+        let (de_short, _): (Symbol, Symbol) = from_reader(&buf[..]).unwrap();
+        assert_eq!(de_short.0, ShortString::from("amqp:not-found"));
+    }
+
     #[test]
     fn test_borrow_str() {
         use crate::value::Value;